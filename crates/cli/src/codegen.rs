@@ -0,0 +1,66 @@
+//! Generating declarations from an interface description.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::Context as _;
+use clap::{Args, ValueEnum};
+use externref::codegen::Interface;
+
+/// Side of the WASM module boundary to generate code for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum CodegenTarget {
+    /// `#[externref]` declarations of imports and exports for the WASM module.
+    #[default]
+    Guest,
+    /// Host-side traits for imported modules.
+    Host,
+}
+
+/// Generates `#[externref]` declarations for a WASM module or host-side traits
+/// from an interface description.
+#[derive(Debug, Args)]
+pub(crate) struct CodegenArgs {
+    /// Path to the interface description. The description is parsed as TOML if the file
+    /// has the `toml` extension, and as JSON otherwise.
+    interface: PathBuf,
+    /// Side of the WASM module boundary to generate code for.
+    #[arg(long, value_enum, default_value_t = CodegenTarget::Guest)]
+    target: CodegenTarget,
+    /// Path to the output Rust file. If not specified, the code will be emitted
+    /// to the standard output.
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+impl CodegenArgs {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let interface = self.read_interface()?;
+        let code = match self.target {
+            CodegenTarget::Guest => interface.guest_code(),
+            CodegenTarget::Host => interface.host_code(),
+        }
+        .context("failed generating code")?;
+
+        if let Some(output) = &self.output {
+            fs::write(output, code).with_context(|| {
+                format!("failed writing code to `{}`", output.to_string_lossy())
+            })?;
+        } else {
+            print!("{code}");
+        }
+        Ok(())
+    }
+
+    fn read_interface(&self) -> anyhow::Result<Interface> {
+        let path = &self.interface;
+        let raw = fs::read_to_string(path).with_context(|| {
+            format!("failed reading interface from `{}`", path.to_string_lossy())
+        })?;
+        let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+        if is_toml {
+            toml::from_str(&raw).context("failed parsing TOML interface")
+        } else {
+            serde_json::from_str(&raw).context("failed parsing JSON interface")
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Diffing module interfaces before and after processing.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::PathBuf,
+};
+
+use anyhow::Context as _;
+use clap::{Args, ValueEnum};
+use serde_json::json;
+use wasmparser::{
+    CompositeType, ExternalKind, FuncType, GlobalType, Parser, Payload, TableType, TypeRef,
+};
+
+use crate::ProcessingArgs;
+
+/// Output format of the interface diff.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum DiffFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// JSON object with `imports` and `exports` arrays.
+    Json,
+}
+
+/// Shows how imports and exports of a module change after processing without writing
+/// the processed module.
+#[derive(Debug, Args)]
+pub(crate) struct DiffArgs {
+    /// Path to the input WASM module.
+    input: PathBuf,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+    format: DiffFormat,
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+impl DiffArgs {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let module = fs::read(&self.input).with_context(|| {
+            format!(
+                "failed reading input module from `{}`",
+                self.input.to_string_lossy()
+            )
+        })?;
+        let processed = self
+            .processing
+            .create_processor()
+            .process_bytes(&module)
+            .context("failed processing module")?;
+
+        let original = ModuleInterface::parse(&module).context("failed parsing input module")?;
+        let processed =
+            ModuleInterface::parse(&processed).context("failed parsing processed module")?;
+        let diff = InterfaceDiff::new(&original, &processed);
+        match self.format {
+            DiffFormat::Text => print!("{diff}"),
+            DiffFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&diff.to_json())?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Imports and exports of a module together with their types, e.g. `func (i32) -> externref`.
+#[derive(Debug, Default)]
+struct ModuleInterface {
+    imports: BTreeMap<(String, String), String>,
+    exports: BTreeMap<String, String>,
+}
+
+impl ModuleInterface {
+    fn parse(module_bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut this = Self::default();
+        let mut types = vec![];
+        // Index spaces of the module (imported items come first).
+        let mut functions = vec![];
+        let mut tables = vec![];
+        let mut memory_count = 0_u32;
+        let mut globals = vec![];
+
+        for payload in Parser::new(0).parse_all(module_bytes) {
+            match payload? {
+                Payload::TypeSection(reader) => {
+                    for rec_group in reader {
+                        types.extend(rec_group?.into_types().map(|ty| {
+                            if let CompositeType::Func(ty) = &ty.composite_type {
+                                format_func_type(ty)
+                            } else {
+                                ty.to_string()
+                            }
+                        }));
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import?;
+                        let ty = match import.ty {
+                            TypeRef::Func(idx) => {
+                                let ty = types.get(idx as usize).context("invalid type index")?;
+                                functions.push(ty.clone());
+                                ty.clone()
+                            }
+                            TypeRef::Table(ty) => {
+                                tables.push(format_table_type(&ty));
+                                format_table_type(&ty)
+                            }
+                            TypeRef::Memory(_) => {
+                                memory_count += 1;
+                                "memory".to_owned()
+                            }
+                            TypeRef::Global(ty) => {
+                                globals.push(format_global_type(ty));
+                                format_global_type(ty)
+                            }
+                            TypeRef::Tag(_) => "tag".to_owned(),
+                        };
+                        let name = (import.module.to_owned(), import.name.to_owned());
+                        this.imports.insert(name, ty);
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for type_idx in reader {
+                        let ty = types.get(type_idx? as usize);
+                        functions.push(ty.context("invalid type index")?.clone());
+                    }
+                }
+                Payload::TableSection(reader) => {
+                    for table in reader {
+                        tables.push(format_table_type(&table?.ty));
+                    }
+                }
+                Payload::MemorySection(reader) => {
+                    memory_count += reader.count();
+                }
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        globals.push(format_global_type(global?.ty));
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export?;
+                        let idx = export.index as usize;
+                        let ty = match export.kind {
+                            ExternalKind::Func => functions.get(idx).cloned(),
+                            ExternalKind::Table => tables.get(idx).cloned(),
+                            ExternalKind::Memory => {
+                                (export.index < memory_count).then(|| "memory".to_owned())
+                            }
+                            ExternalKind::Global => globals.get(idx).cloned(),
+                            ExternalKind::Tag => Some("tag".to_owned()),
+                        };
+                        let ty = ty.with_context(|| {
+                            format!("invalid index for export `{}`", export.name)
+                        })?;
+                        this.exports.insert(export.name.to_owned(), ty);
+                    }
+                }
+                _ => { /* Other sections do not influence the interface */ }
+            }
+        }
+        Ok(this)
+    }
+}
+
+fn format_func_type(ty: &FuncType) -> String {
+    let params: Vec<_> = ty.params().iter().map(ToString::to_string).collect();
+    let results: Vec<_> = ty.results().iter().map(ToString::to_string).collect();
+    let results = if results.len() == 1 {
+        results[0].clone()
+    } else {
+        format!("({})", results.join(", "))
+    };
+    format!("func ({}) -> {results}", params.join(", "))
+}
+
+fn format_table_type(ty: &TableType) -> String {
+    format!("table {}", ty.element_type)
+}
+
+fn format_global_type(ty: GlobalType) -> String {
+    if ty.mutable {
+        format!("global mut {}", ty.content_type)
+    } else {
+        format!("global {}", ty.content_type)
+    }
+}
+
+/// Change of an import or export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change<'a> {
+    Added(&'a str),
+    Removed(&'a str),
+    Changed { old: &'a str, new: &'a str },
+}
+
+impl<'a> Change<'a> {
+    fn new(old: Option<&'a String>, new: Option<&'a String>) -> Option<Self> {
+        match (old, new) {
+            (None, Some(new)) => Some(Self::Added(new)),
+            (Some(old), None) => Some(Self::Removed(old)),
+            (Some(old), Some(new)) if old != new => Some(Self::Changed { old, new }),
+            _ => None,
+        }
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        match self {
+            Self::Added(new) => json!({ "change": "added", "new": new }),
+            Self::Removed(old) => json!({ "change": "removed", "old": old }),
+            Self::Changed { old, new } => json!({ "change": "changed", "old": old, "new": new }),
+        }
+    }
+}
+
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added(new) => write!(formatter, "+ {new}"),
+            Self::Removed(old) => write!(formatter, "- {old}"),
+            Self::Changed { old, new } => write!(formatter, "~ {old} => {new}"),
+        }
+    }
+}
+
+/// Changes in imports and exports of a module. Unchanged items are omitted.
+#[derive(Debug)]
+struct InterfaceDiff<'a> {
+    imports: Vec<(&'a (String, String), Change<'a>)>,
+    exports: Vec<(&'a String, Change<'a>)>,
+}
+
+impl<'a> InterfaceDiff<'a> {
+    fn new(original: &'a ModuleInterface, processed: &'a ModuleInterface) -> Self {
+        let import_names: BTreeSet<_> = original
+            .imports
+            .keys()
+            .chain(processed.imports.keys())
+            .collect();
+        let imports = import_names
+            .into_iter()
+            .filter_map(|name| {
+                let change = Change::new(original.imports.get(name), processed.imports.get(name));
+                Some((name, change?))
+            })
+            .collect();
+
+        let export_names: BTreeSet<_> = original
+            .exports
+            .keys()
+            .chain(processed.exports.keys())
+            .collect();
+        let exports = export_names
+            .into_iter()
+            .filter_map(|name| {
+                let change = Change::new(original.exports.get(name), processed.exports.get(name));
+                Some((name, change?))
+            })
+            .collect();
+
+        Self { imports, exports }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let imports = self.imports.iter().map(|((module, name), change)| {
+            let mut value = change.to_json();
+            value["module"] = module.as_str().into();
+            value["name"] = name.as_str().into();
+            value
+        });
+        let exports = self.exports.iter().map(|(name, change)| {
+            let mut value = change.to_json();
+            value["name"] = name.as_str().into();
+            value
+        });
+        json!({
+            "imports": imports.collect::<Vec<_>>(),
+            "exports": exports.collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl fmt::Display for InterfaceDiff<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(formatter, "Imports:")?;
+        if self.imports.is_empty() {
+            writeln!(formatter, "  (no changes)")?;
+        }
+        for ((module, name), change) in &self.imports {
+            writeln!(formatter, "  {module}::{name}: {change}")?;
+        }
+        writeln!(formatter, "Exports:")?;
+        if self.exports.is_empty() {
+            writeln!(formatter, "  (no changes)")?;
+        }
+        for (name, change) in &self.exports {
+            writeln!(formatter, "  {name}: {change}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Error reporting with exit codes and an optional machine-readable format.
+
+use std::{
+    fmt::{self, Write as _},
+    io,
+    process::ExitCode,
+};
+
+use clap::ValueEnum;
+use externref::processor;
+use miette::GraphicalReportHandler;
+use serde_json::json;
+
+/// Format of errors emitted to the standard error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ErrorFormat {
+    /// Human-readable error with the chain of causes. Processing errors are rendered
+    /// with an error code, help text and (if applicable) the offending function
+    /// in the WebAssembly text format.
+    #[default]
+    Text,
+    /// Single-line JSON object with the error kind, exit code, message, and the code
+    /// and location of the processing error (if applicable).
+    Json,
+}
+
+/// Category of an error determining the exit code of the CLI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorKind {
+    /// Error not falling into other categories, e.g. a bug in the processor
+    /// producing an invalid module.
+    Other,
+    /// Input module is malformed or cannot be processed as is.
+    MalformedModule,
+    /// Input module was transformed by external tools (e.g., `wasm-opt` or asyncify)
+    /// before processing; these tools should run after the processor.
+    ToolOrdering,
+    /// I/O error reading or writing a file.
+    Io,
+}
+
+impl ErrorKind {
+    fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::MalformedModule => 2,
+            Self::ToolOrdering => 3,
+            Self::Io => 4,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::MalformedModule => "malformed_module",
+            Self::ToolOrdering => "tool_ordering",
+            Self::Io => "io",
+        }
+    }
+
+    /// Classifies an error by the first recognized error in its chain of causes.
+    pub(crate) fn classify(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<BatchError>() {
+                return err.kind;
+            } else if let Some(err) = cause.downcast_ref::<processor::Error>() {
+                return Self::from_processor_error(err);
+            } else if let Some(report) = cause.downcast_ref::<processor::ErrorReport>() {
+                return report
+                    .errors()
+                    .next()
+                    .map_or(Self::Other, Self::from_processor_error);
+            } else if cause.is::<io::Error>() {
+                return Self::Io;
+            } else if cause.is::<wat::Error>() {
+                return Self::MalformedModule;
+            }
+        }
+        Self::Other
+    }
+
+    fn from_processor_error(err: &processor::Error) -> Self {
+        use processor::Error;
+
+        match err {
+            Error::IncorrectGuard { .. }
+            | Error::UnexpectedCall { .. }
+            | Error::Asyncified { .. } => Self::ToolOrdering,
+            Error::Io { .. } => Self::Io,
+            Error::File { error, .. } => Self::from_processor_error(error),
+            Error::Multiple(errors) => errors
+                .first()
+                .map_or(Self::Other, Self::from_processor_error),
+            Error::NotProcessed | Error::PostOptimization(_) | Error::Hook(_) => Self::Other,
+            _ => Self::MalformedModule,
+        }
+    }
+}
+
+/// Error processing a batch of modules. Individual errors are reported as they occur,
+/// so this error only summarizes them.
+#[derive(Debug)]
+pub(crate) struct BatchError {
+    pub failed_count: usize,
+    pub total_count: usize,
+    /// Kind of the first encountered error.
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "failed processing {} of {} module(s)",
+            self.failed_count, self.total_count
+        )
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// Finds the processing error in the chain of causes, if any. For multiple errors,
+/// the first error is returned.
+fn find_processor_error(err: &anyhow::Error) -> Option<&processor::Error> {
+    let processor_err = err.chain().find_map(|cause| {
+        if let Some(report) = cause.downcast_ref::<processor::ErrorReport>() {
+            report.errors().next()
+        } else {
+            cause.downcast_ref::<processor::Error>()
+        }
+    })?;
+    Some(processor_err.errors().first().unwrap_or(processor_err))
+}
+
+/// Renders the error with a rich diagnostic for the processing error, preceded by the contexts
+/// wrapping it.
+fn render_report(err: &anyhow::Error, report: &processor::ErrorReport) -> String {
+    let mut output = String::new();
+    let contexts = err
+        .chain()
+        .take_while(|cause| !cause.is::<processor::ErrorReport>());
+    for (idx, context) in contexts.enumerate() {
+        let prefix = if idx == 0 { "Error: " } else { "Caused by: " };
+        writeln!(output, "{prefix}{context}").unwrap();
+    }
+    output.push('\n');
+    GraphicalReportHandler::new()
+        .render_report(&mut output, report)
+        .unwrap();
+    output
+}
+
+/// Reports the error to the standard error in the specified format and returns the exit code.
+pub(crate) fn report(err: &anyhow::Error, format: ErrorFormat) -> ExitCode {
+    let kind = ErrorKind::classify(err);
+    match format {
+        ErrorFormat::Text => {
+            let report = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<processor::ErrorReport>());
+            if let Some(report) = report {
+                eprint!("{}", render_report(err, report));
+            } else {
+                // Mirrors the output of returning `anyhow::Result` from `main()`.
+                eprintln!("Error: {err:?}");
+            }
+        }
+        ErrorFormat::Json => {
+            let processor_err = find_processor_error(err);
+            let report = json!({
+                "kind": kind.as_str(),
+                "exit_code": kind.exit_code(),
+                "code": processor_err.map(processor::Error::code),
+                "message": format!("{err:#}"),
+                "function": processor_err.and_then(processor::Error::function_name),
+                "code_offset": processor_err.and_then(processor::Error::code_offset),
+            });
+            eprintln!("{report}");
+        }
+    }
+    ExitCode::from(kind.exit_code())
+}
@@ -6,15 +6,35 @@
 #![allow(clippy::must_use_candidate, clippy::module_name_repetitions)]
 
 use std::{
+    collections::HashMap,
     fs,
-    io::{self, Read as _, Write as _},
-    path::PathBuf,
+    io::{self, IsTerminal as _, Read as _, Write as _},
+    path::{Path, PathBuf},
+    process::ExitCode,
     str::FromStr,
 };
 
-use anyhow::{anyhow, ensure, Context};
-use clap::Parser;
-use externref::processor::Processor;
+use anyhow::{anyhow, bail, ensure, Context};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use externref::{
+    processor::{
+        write_atomically, DropReentrancy, DropSlotValue, GcMode, ImportShimVisibility,
+        InterfaceFormat, Partitioning, PostOptimizer, Processor, SectionOptions,
+    },
+    Function,
+};
+
+use crate::{
+    codegen::CodegenArgs,
+    diff::DiffArgs,
+    error::{BatchError, ErrorFormat, ErrorKind},
+};
+
+mod codegen;
+mod diff;
+mod error;
+#[cfg(feature = "wasmtime")]
+mod verify;
 
 #[derive(Debug, Clone)]
 struct ModuleAndName {
@@ -41,21 +61,211 @@ impl FromStr for ModuleAndName {
     }
 }
 
-/// CLI for transforming WASM modules with `externref` shims produced with the help
-/// of the `externref` crate.
-#[derive(Debug, Parser)]
-struct Cli {
-    /// Path to the input WASM module.
-    /// If set to `-`, the module will be read from the standard input.
-    input: PathBuf,
-    /// Path to the output WASM module. If not specified, the module will be emitted
-    /// to the standard output.
-    #[arg(long, short = 'o')]
-    output: Option<PathBuf>,
+/// Renaming of an item, specified in the `old=new` format.
+#[derive(Debug, Clone)]
+struct Rename<T> {
+    from: T,
+    to: T,
+}
+
+impl<T> FromStr for Rename<T>
+where
+    T: FromStr<Err = anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("renaming must be specified in the `old=new` format"))?;
+        Ok(Self {
+            from: from.parse().context("invalid old name")?,
+            to: to.parse().context("invalid new name")?,
+        })
+    }
+}
+
+/// Additional custom section with function declarations, specified in the `name`
+/// or `name=import_module` format.
+#[derive(Debug, Clone)]
+struct ExtraSection {
+    name: String,
+    import_module: Option<String>,
+}
+
+impl FromStr for ExtraSection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, import_module) = match s.split_once('=') {
+            Some((name, module)) => (name, Some(module)),
+            None => (s, None),
+        };
+        ensure!(
+            name.starts_with(Function::CUSTOM_SECTION_NAME),
+            "section name must start with `{}`",
+            Function::CUSTOM_SECTION_NAME
+        );
+        if let Some(module) = import_module {
+            ensure!(!module.is_empty(), "import module cannot be empty");
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            import_module: import_module.map(str::to_owned),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ExportName(String);
+
+impl FromStr for ExportName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ensure!(!s.is_empty(), "name cannot be empty");
+        Ok(Self(s.to_owned()))
+    }
+}
+
+/// Format of the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ManifestFormat {
+    /// Pretty-printed JSON.
+    Json,
+    /// WIT-like world describing imports and exports of the processed module.
+    Wit,
+}
+
+impl From<ManifestFormat> for InterfaceFormat {
+    fn from(format: ManifestFormat) -> Self {
+        match format {
+            ManifestFormat::Json => Self::Json,
+            ManifestFormat::Wit => Self::Wit,
+        }
+    }
+}
+
+/// Format of input / output modules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ModuleFormat {
+    /// WASM binary format.
+    #[default]
+    Wasm,
+    /// WebAssembly text format.
+    Wat,
+}
+
+/// Partitioning of the `externref`s table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum TablePartitioning {
+    /// All references are placed into a single table.
+    #[default]
+    Shared,
+    /// Each resource kind gets a dedicated table exported as `{table}::{kind}`.
+    PerKind,
+}
+
+impl From<TablePartitioning> for Partitioning {
+    fn from(partitioning: TablePartitioning) -> Self {
+        match partitioning {
+            TablePartitioning::Shared => Self::Shared,
+            TablePartitioning::PerKind => Self::PerKind,
+        }
+    }
+}
+
+/// Behavior on reference drops while the drop hook is running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum DropReentrancyPolicy {
+    /// Reentrant drops are not checked.
+    #[default]
+    Allow,
+    /// A reentrant drop traps.
+    Trap,
+    /// A reentrant drop frees the table slot without calling the drop hook.
+    Skip,
+    /// A reentrant drop frees the table slot and defers the drop hook call until
+    /// the outermost hook call returns.
+    Queue,
+}
+
+impl From<DropReentrancyPolicy> for DropReentrancy {
+    fn from(policy: DropReentrancyPolicy) -> Self {
+        match policy {
+            DropReentrancyPolicy::Allow => Self::Allow,
+            DropReentrancyPolicy::Trap => Self::Trap,
+            DropReentrancyPolicy::Skip => Self::Skip,
+            DropReentrancyPolicy::Queue => Self::Queue,
+        }
+    }
+}
+
+/// Preset bundling processing options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Preset {
+    /// Retains function and local names and unused functions, exports resource metrics,
+    /// and validates the processed module.
+    Debug,
+    /// Retains function and local names and removes unused functions; skips validation.
+    Release,
+    /// Strips function and local names, removes unused functions, and optimizes the processed
+    /// module with `wasm-opt`; skips validation.
+    Size,
+}
+
+impl Preset {
+    /// Args supplied to `wasm-opt` when optimizing processed modules.
+    const WASM_OPT_ARGS: &'static [&'static str] = &[
+        "-Os",
+        "--enable-mutable-globals",
+        "--enable-reference-types",
+        "--enable-multivalue",
+    ];
+
+    fn strip_names(self) -> bool {
+        matches!(self, Self::Size)
+    }
+
+    fn validate(self) -> bool {
+        matches!(self, Self::Debug)
+    }
+
+    fn skip_gc(self) -> bool {
+        matches!(self, Self::Debug)
+    }
+
+    fn resource_metrics(self) -> bool {
+        matches!(self, Self::Debug)
+    }
+
+    fn optimize(self) -> bool {
+        matches!(self, Self::Size)
+    }
+}
+
+/// Options influencing module processing.
+#[derive(Debug, Args)]
+#[allow(clippy::struct_excessive_bools)] // flags are independent processing options
+struct ProcessingArgs {
     /// Name of the exported `externref`s table where refs obtained from the host
     /// are placed.
     #[arg(long = "table", default_value = "externrefs")]
     export_table: String,
+    /// Name of the exported table backing resource tables. The table is only added
+    /// if the module uses resource tables.
+    #[arg(long = "resource-table", default_value = "resources")]
+    export_resource_table: String,
+    /// Name of the exported mutable global backing resource globals. The global is only added
+    /// if the module uses resource globals.
+    #[arg(long = "resource-global", default_value = "global_resource")]
+    export_resource_global: String,
+    /// Partitioning of the `externref`s table by resource kind.
+    ///
+    /// With per-kind partitioning, references of unknown kind remain in the table specified
+    /// by `--table`, and reference IDs encode both the table and the slot index.
+    #[arg(long = "table-partitioning", value_enum, default_value_t = TablePartitioning::Shared)]
+    table_partitioning: TablePartitioning,
     /// Function to notify the host about dropped `externref`s specified
     /// in the `module::name` format.
     ///
@@ -63,6 +273,308 @@ struct Cli {
     /// and will be called immediately before dropping each reference.
     #[arg(long = "drop-fn")]
     drop_fn: Option<ModuleAndName>,
+    /// Passes the resource kind ID to the drop hook specified by `--drop-fn`.
+    ///
+    /// The hook will have a signature `(externref, i32) -> ()`. Kind IDs are assigned per
+    /// resource type in the alphabetical order of type names; the mapping is included
+    /// into the manifest.
+    #[arg(long = "drop-fn-with-kind", requires = "drop_fn")]
+    drop_fn_with_kind: bool,
+    /// Behavior on reference drops while the drop hook specified by `--drop-fn` is running,
+    /// e.g. if the hook calls back into the module, which drops another reference.
+    #[arg(
+        long = "drop-reentrancy",
+        value_enum,
+        default_value_t = DropReentrancyPolicy::Allow,
+        requires = "drop_fn"
+    )]
+    drop_reentrancy: DropReentrancyPolicy,
+    /// Module of surrogate imports replaced by the processor. Should match the module
+    /// the `externref` crate was compiled with (set via the `EXTERNREF_SURROGATE_MODULE`
+    /// env variable), e.g. if the host provides a real module named `externref`.
+    #[arg(long = "surrogate-module", default_value = "externref")]
+    surrogate_module: String,
+    /// Surrogate guard import placed by the `externref` macro in wrappers of imported functions,
+    /// specified in the `module::name` format. Should be used together with
+    /// `#[externref(surrogate_module = "..")]`. By default, the guard is imported as `guard`
+    /// from the surrogate module.
+    #[arg(long = "guard-import")]
+    guard_import: Option<ModuleAndName>,
+    /// Immutable `externref` global to write into dropped table slots instead of null,
+    /// specified in the `module::name` format.
+    ///
+    /// This global will be added as an import. Tombstones allow the host to distinguish
+    /// never used table slots from used and freed ones.
+    #[arg(long = "tombstone")]
+    tombstone: Option<ModuleAndName>,
+    /// Function selecting slots of the `externref`s table for inserted references,
+    /// specified in the `module::name` format.
+    ///
+    /// This function will be added as an import with a signature `() -> i32`. It should return
+    /// the index of a free slot, or a negative value to use the built-in free slot search.
+    #[arg(long = "slot-allocator")]
+    slot_allocator: Option<ModuleAndName>,
+    /// Number of low slots in the `externref`s table reserved for the host.
+    ///
+    /// The table will initially contain this number of null slots, which the module never uses
+    /// for inserted references. The host can place well-known references into these slots.
+    #[arg(
+        long = "reserved-slots",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u32).range(..=i64::from(i32::MAX))
+    )]
+    reserved_slots: u32,
+    /// Export `i32` globals with the number of live references and its maximum value
+    /// (`externref_live_count` and `externref_peak_count`, respectively).
+    /// Can be specified as `--resource-metrics=false` to override the preset.
+    #[arg(long = "resource-metrics", num_args = 0..=1, default_missing_value = "true")]
+    resource_metrics: Option<bool>,
+    /// Export functions helping the host to snapshot and restore the `externref`s table
+    /// (`externref_snapshot_len` and `externref_restore_prepare`, respectively).
+    #[arg(long = "snapshot-helpers")]
+    snapshot_helpers: bool,
+    /// Function to notify the host about references moved by table compaction, specified
+    /// in the `module::name` format. Enables the `externref_compact` export.
+    ///
+    /// This function will be added as an import with a signature `(i32, i32) -> ()` and will be
+    /// called with the old and the new ID of each moved reference. IDs held by the module
+    /// are not updated, so the host should only compact the table when the module opts in.
+    #[arg(long = "compaction-fn")]
+    compaction_fn: Option<ModuleAndName>,
+    /// Export functions replacing the surrogate `insert`, `get` and `drop` imports
+    /// (`externref_insert`, `externref_get` and `externref_drop`, respectively),
+    /// so that the host can call them directly.
+    #[arg(long = "export-shims")]
+    export_shims: bool,
+    /// Function to notify the host about leaked `externref`s specified
+    /// in the `module::name` format.
+    ///
+    /// This function will be added as an import with a signature `(i32) -> ()` and will be called
+    /// with the number of live references after each call to the exports
+    /// matching `--leak-check-exports`, provided that there are live references.
+    #[arg(long = "leak-fn")]
+    leak_fn: Option<ModuleAndName>,
+    /// Glob pattern for names of exports checked for leaked references
+    /// (`*` matches any sequence of chars, `?` matches a single char).
+    #[arg(long = "leak-check-exports", default_value = "*", requires = "leak_fn")]
+    leak_check_exports: String,
+    /// Name of the custom section with function declarations processed with the specified
+    /// options. Should match `#[externref(section = "..")]` in the processed code.
+    #[arg(long = "section", default_value = Function::CUSTOM_SECTION_NAME)]
+    section_name: String,
+    /// Additional custom section with function declarations processed in the same pass,
+    /// specified in the `name` or `name=import_module` format. Can be specified multiple times.
+    ///
+    /// If the import module is specified, functions imported in the section declarations
+    /// are renamed to be imported from this module.
+    #[arg(long = "add-section")]
+    extra_sections: Vec<ExtraSection>,
+    /// Import to rename specified in the `old_module::old_name=new_module::new_name` format.
+    /// Can be specified multiple times.
+    ///
+    /// Renaming is applied after patching functions.
+    #[arg(long = "rename-import")]
+    import_renames: Vec<Rename<ModuleAndName>>,
+    /// Export to rename specified in the `old_name=new_name` format.
+    /// Can be specified multiple times.
+    #[arg(long = "rename-export")]
+    export_renames: Vec<Rename<ExportName>>,
+    /// Process the module even if it was already processed.
+    #[arg(long)]
+    force: bool,
+    /// Attempt to patch all functions and report all errors at once, rather than
+    /// stopping on the first error.
+    #[arg(long)]
+    continue_on_error: bool,
+}
+
+impl ProcessingArgs {
+    fn create_processor(&self) -> Processor<'_> {
+        let mut processor = Processor::default();
+        processor.set_ref_table(self.export_table.as_str());
+        processor.set_resource_table(self.export_resource_table.as_str());
+        processor.set_resource_global(self.export_resource_global.as_str());
+        processor.set_table_partitioning(self.table_partitioning.into());
+        if let Some(drop_fn) = &self.drop_fn {
+            if self.drop_fn_with_kind {
+                processor.set_drop_fn_with_kind(&drop_fn.module, &drop_fn.name);
+            } else {
+                processor.set_drop_fn(&drop_fn.module, &drop_fn.name);
+            }
+            processor.set_drop_reentrancy(self.drop_reentrancy.into());
+        }
+        processor.set_surrogate_module(&self.surrogate_module);
+        if let Some(guard_import) = &self.guard_import {
+            processor.set_guard_import(&guard_import.module, &guard_import.name);
+        }
+        if let Some(tombstone) = &self.tombstone {
+            processor.set_drop_slot_value(DropSlotValue::Tombstone {
+                module: &tombstone.module,
+                name: &tombstone.name,
+            });
+        }
+        if let Some(allocator) = &self.slot_allocator {
+            processor.set_slot_allocator(&allocator.module, &allocator.name);
+        }
+        if self.reserved_slots > 0 {
+            processor.set_reserved_slots(self.reserved_slots);
+        }
+        if self.resource_metrics == Some(true) {
+            processor.enable_resource_metrics();
+        }
+        if self.snapshot_helpers {
+            processor.enable_snapshot_helpers();
+        }
+        if let Some(compaction_fn) = &self.compaction_fn {
+            processor.enable_compaction(&compaction_fn.module, &compaction_fn.name);
+        }
+        if self.export_shims {
+            processor.set_import_shim_visibility(ImportShimVisibility::EXPORTED);
+        }
+        if let Some(leak_fn) = &self.leak_fn {
+            processor.set_leak_check(&leak_fn.module, &leak_fn.name, &self.leak_check_exports);
+        }
+        processor.set_section_name(&self.section_name);
+        for section in &self.extra_sections {
+            let mut options = SectionOptions::default();
+            if let Some(module) = &section.import_module {
+                options = options.with_import_module(module);
+            }
+            processor.add_section(&section.name, options);
+        }
+        for Rename { from, to } in &self.import_renames {
+            processor.rename_import((&from.module, &from.name), (&to.module, &to.name));
+        }
+        for Rename { from, to } in &self.export_renames {
+            processor.rename_export(&from.0, &to.0);
+        }
+
+        processor.set_force(self.force);
+        processor.set_continue_on_error(self.continue_on_error);
+        processor
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Shows how imports and exports of a module will change after processing, without writing
+    /// the processed module.
+    Diff(Box<DiffArgs>),
+    /// Generates `#[externref]` declarations for a WASM module or host-side traits
+    /// from an interface description.
+    Codegen(CodegenArgs),
+}
+
+/// CLI for transforming WASM modules with `externref` shims produced with the help
+/// of the `externref` crate.
+///
+/// Exit codes: 1 for unclassified errors, 2 for malformed input modules, 3 for modules
+/// transformed by external tools (e.g., `wasm-opt`) before processing, 4 for I/O errors.
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Paths to the input WASM modules. Paths may contain glob patterns (e.g.,
+    /// `target/wasm32-unknown-unknown/release/*.wasm`), which are expanded by the CLI
+    /// if not expanded by the shell. If set to `-`, the module will be read
+    /// from the standard input. If no inputs are specified and the standard input
+    /// is not a terminal, the module is read from the standard input as well, so that the CLI
+    /// can be used as a filter in pipelines.
+    ///
+    /// Processing multiple modules requires `--out-dir`.
+    inputs: Vec<PathBuf>,
+    /// Path to the output WASM module. If not specified, the module will be emitted
+    /// to the standard output.
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+    /// Directory to write processed modules to. Each module is written to a file with
+    /// the same name as the input module; inputs whose outputs would have the same name
+    /// are rejected. The directory is created if necessary.
+    #[arg(long, conflicts_with_all = ["output", "manifest", "emit_js_glue", "emit_node_glue"])]
+    out_dir: Option<PathBuf>,
+    /// Format of the input modules.
+    #[arg(long, value_enum, default_value_t = ModuleFormat::Wasm)]
+    input_format: ModuleFormat,
+    /// Format of the processed modules. With the `wat` format and `--out-dir`, processed modules
+    /// are written to files with the `wat` extension.
+    #[arg(long, value_enum, default_value_t = ModuleFormat::Wasm)]
+    output_format: ModuleFormat,
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// Path to write the manifest describing the reference interface of the processed module
+    /// (imports / exports with `externref` positions and WASM types, the `externref`s table,
+    /// drop hook etc.).
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Format of the manifest.
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json, requires = "manifest")]
+    manifest_format: ManifestFormat,
+    /// Path to write an ES module with JS glue for browser hosts not using `wasm-bindgen`.
+    /// The glue instantiates the processed module, implements hooks (e.g., forwards the drop hook
+    /// to a callback) and provides typed wrappers for exported functions.
+    #[arg(long, value_name = "PATH")]
+    emit_js_glue: Option<PathBuf>,
+    /// Path to write an ES module with JS glue for Node.js hosts, optionally using WASI.
+    /// In addition to the browser glue functionality, it loads the module from a file path,
+    /// initializes it with the provided WASI instance, and allows inspecting the `externref`s table.
+    #[arg(long, value_name = "PATH")]
+    emit_node_glue: Option<PathBuf>,
+    /// Preset selecting a combination of the options below. Options specified explicitly
+    /// override the preset values.
+    #[arg(long, value_enum, default_value_t = Preset::Release)]
+    preset: Preset,
+    /// Strip function and local names from the processed module.
+    /// Can be specified as `--strip-names=false` to override the preset.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    strip_names: Option<bool>,
+    /// Glob pattern for names of custom sections to strip from the processed module
+    /// (`*` matches any sequence of chars, `?` matches a single char), e.g. `producers`.
+    /// Can be specified multiple times.
+    ///
+    /// Stripping is performed together with processing, so there is no need to run
+    /// `wasm-strip` afterwards.
+    #[arg(long = "strip-custom-sections", value_name = "GLOB")]
+    stripped_sections: Vec<String>,
+    /// Skip garbage collection of unused items (functions, globals etc.) in the processed module.
+    /// Surrogate functions replaced during processing are still removed.
+    /// Can be specified as `--skip-gc=false` to override the preset.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        conflicts_with = "gc_keep"
+    )]
+    skip_gc: Option<bool>,
+    /// Glob pattern for names of local functions retained by garbage collection even if they are
+    /// unused, e.g. `helper_*`. Can be specified multiple times.
+    #[arg(long, value_name = "GLOB")]
+    gc_keep: Vec<String>,
+    /// Optimize the processed module with `wasm-opt -Os` (`wasm-opt` must be available in `PATH`).
+    /// Can be specified as `--optimize=false` to override the preset.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    optimize: Option<bool>,
+    /// Validate the processed module.
+    /// Can be specified as `--validate=false` to override the preset.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    validate: Option<bool>,
+    /// Output modules that contain neither `externref` declarations nor the processing marker
+    /// as is, without full parsing. Unlike processed modules, such modules do not get
+    /// the processing marker. Only applies if no processing options are specified,
+    /// since options may modify modules without declarations (e.g., rename imports).
+    #[arg(long)]
+    pass_through: bool,
+    /// Smoke-test the processed module by instantiating it in the specified embedded runtime
+    /// with stubbed imports (function imports return zeros / nulls). Checks that the module
+    /// instantiates and exports the `externref`s table if it uses `externref`s.
+    #[cfg(feature = "wasmtime")]
+    #[arg(long, value_enum, value_name = "RUNTIME")]
+    verify_runtime: Option<verify::Runtime>,
+    /// Format of errors emitted to the standard error. The `json` format outputs a single-line
+    /// JSON object with the error kind, exit code, message, and the function name and
+    /// bytecode offset for errors tied to a specific location.
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
 }
 
 impl Cli {
@@ -81,22 +593,124 @@ impl Cli {
         #[cfg(feature = "tracing")]
         Self::configure_tracing();
 
-        let module = self.read_input_module().with_context(|| {
+        match &self.command {
+            Some(Command::Diff(args)) => return args.run(),
+            Some(Command::Codegen(args)) => return args.run(),
+            None => { /* process modules */ }
+        }
+        let inputs = self.expand_inputs()?;
+        let processor = self.create_processor();
+        if let Some(out_dir) = &self.out_dir {
+            self.process_batch(&processor, &inputs, out_dir)
+        } else {
+            ensure!(
+                inputs.len() == 1,
+                "processing multiple modules requires specifying `--out-dir`"
+            );
+            self.process_single(&processor, &inputs[0])
+        }
+    }
+
+    /// Expands glob patterns in inputs that do not correspond to existing files.
+    fn expand_inputs(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if self.inputs.is_empty() {
+            ensure!(
+                !io::stdin().is_terminal(),
+                "no input modules specified; specify `-` to read the module from the standard input"
+            );
+            return Ok(vec![PathBuf::from("-")]);
+        }
+
+        let mut inputs = Vec::with_capacity(self.inputs.len());
+        for input in &self.inputs {
+            let pattern = input.to_string_lossy();
+            let is_pattern = pattern.contains(['*', '?', '[']);
+            if !is_pattern || input.exists() {
+                inputs.push(input.clone());
+                continue;
+            }
+
+            let paths = glob::glob(&pattern)
+                .with_context(|| format!("invalid glob pattern `{pattern}`"))?;
+            let len_before = inputs.len();
+            for path in paths {
+                let path = path.with_context(|| format!("failed expanding `{pattern}`"))?;
+                if path.is_file() {
+                    inputs.push(path);
+                }
+            }
+            ensure!(
+                inputs.len() > len_before,
+                "glob pattern `{pattern}` does not match any files"
+            );
+        }
+        Ok(inputs)
+    }
+
+    fn create_processor(&self) -> Processor<'_> {
+        let mut processor = self.processing.create_processor();
+        let strip_names = self.strip_names.unwrap_or(self.preset.strip_names());
+        processor.set_strip_names(strip_names);
+        processor.strip_custom_sections(self.stripped_sections.iter().map(String::as_str));
+        if !self.gc_keep.is_empty() {
+            processor.set_gc(GcMode::KeepNamed(
+                self.gc_keep.iter().map(String::as_str).collect(),
+            ));
+        } else if self.skip_gc.unwrap_or(self.preset.skip_gc()) {
+            processor.set_gc(GcMode::Skip);
+        }
+        if self.processing.resource_metrics.is_none() && self.preset.resource_metrics() {
+            processor.enable_resource_metrics();
+        }
+        if self.optimize.unwrap_or(self.preset.optimize()) {
+            processor.set_post_optimizer(PostOptimizer::WasmOpt {
+                args: Preset::WASM_OPT_ARGS.to_vec(),
+            });
+        }
+        processor
+    }
+
+    fn process_single(&self, processor: &Processor<'_>, input: &Path) -> anyhow::Result<()> {
+        let module = Self::read_input_module(input).with_context(|| {
             format!(
                 "failed reading input module from `{}`",
-                self.input.to_string_lossy()
+                input.to_string_lossy()
             )
         })?;
+        let module = self.parse_input_module(module)?;
 
-        let mut processor = Processor::default();
-        processor.set_ref_table(self.export_table.as_str());
-        if let Some(drop_fn) = &self.drop_fn {
-            processor.set_drop_fn(&drop_fn.module, &drop_fn.name);
+        if let Some(path) = &self.manifest {
+            let mut manifest = processor
+                .emit_interface(&module, self.manifest_format.into())
+                .context("failed creating manifest")?;
+            if !manifest.ends_with('\n') {
+                manifest.push('\n');
+            }
+            fs::write(path, manifest).with_context(|| {
+                format!("failed writing manifest to `{}`", path.to_string_lossy())
+            })?;
+        }
+        if let Some(path) = &self.emit_js_glue {
+            let glue = processor
+                .emit_interface(&module, InterfaceFormat::JsGlue)
+                .context("failed creating JS glue")?;
+            fs::write(path, glue).with_context(|| {
+                format!("failed writing JS glue to `{}`", path.to_string_lossy())
+            })?;
+        }
+        if let Some(path) = &self.emit_node_glue {
+            let glue = processor
+                .emit_interface(&module, InterfaceFormat::NodeGlue)
+                .context("failed creating Node.js glue")?;
+            fs::write(path, glue).with_context(|| {
+                format!(
+                    "failed writing Node.js glue to `{}`",
+                    path.to_string_lossy()
+                )
+            })?;
         }
-        let processed = processor
-            .process_bytes(&module)
-            .context("failed processing module")?;
 
+        let processed = self.process_module(processor, &module)?;
         self.write_output_module(&processed).with_context(|| {
             if let Some(path) = &self.output {
                 format!("failed writing module to file `{}`", path.to_string_lossy())
@@ -106,20 +720,175 @@ impl Cli {
         })
     }
 
-    fn read_input_module(&self) -> anyhow::Result<Vec<u8>> {
-        let bytes = if self.input.as_os_str() == "-" {
+    /// Processes each of the `inputs` and writes a summary to the stderr. Failing to process
+    /// a module does not prevent processing the remaining modules.
+    fn process_batch(
+        &self,
+        processor: &Processor<'_>,
+        inputs: &[PathBuf],
+        out_dir: &Path,
+    ) -> anyhow::Result<()> {
+        fs::create_dir_all(out_dir).with_context(|| {
+            format!(
+                "failed creating output directory `{}`",
+                out_dir.to_string_lossy()
+            )
+        })?;
+
+        // Determine all output paths up front, so that outputs of inputs with the same file name
+        // (e.g., `a/module.wasm` and `b/module.wasm`) do not silently overwrite each other.
+        let mut outputs = HashMap::<PathBuf, &Path>::with_capacity(inputs.len());
+        let mut output_paths = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let output = self
+                .output_path(input, out_dir)
+                .with_context(|| format!("invalid input `{}`", input.to_string_lossy()))?;
+            if let Some(prev_input) = outputs.insert(output.clone(), input) {
+                bail!(
+                    "inputs `{}` and `{}` would be written to the same output `{}`",
+                    prev_input.to_string_lossy(),
+                    input.to_string_lossy(),
+                    output.to_string_lossy()
+                );
+            }
+            output_paths.push(output);
+        }
+
+        let mut failed_count = 0;
+        let mut first_error_kind = None;
+        for (input, output) in inputs.iter().zip(output_paths) {
+            match self.process_file(processor, input, output) {
+                Ok((output, original_len, processed_len)) => {
+                    eprintln!(
+                        "{} -> {}: OK ({original_len} -> {processed_len} bytes)",
+                        input.to_string_lossy(),
+                        output.to_string_lossy()
+                    );
+                }
+                Err(err) => {
+                    eprintln!("{}: FAILED: {err:#}", input.to_string_lossy());
+                    failed_count += 1;
+                    first_error_kind.get_or_insert_with(|| ErrorKind::classify(&err));
+                }
+            }
+        }
+
+        if let Some(kind) = first_error_kind {
+            return Err(BatchError {
+                failed_count,
+                total_count: inputs.len(),
+                kind,
+            }
+            .into());
+        }
+        eprintln!("processed {} module(s)", inputs.len());
+        Ok(())
+    }
+
+    /// Returns the path in `out_dir` to write the processed `input` module to.
+    fn output_path(&self, input: &Path, out_dir: &Path) -> anyhow::Result<PathBuf> {
+        let file_name = input
+            .file_name()
+            .filter(|_| input.as_os_str() != "-")
+            .ok_or_else(|| anyhow!("input must be a file"))?;
+        let mut output = out_dir.join(file_name);
+        if self.output_format == ModuleFormat::Wat {
+            output.set_extension("wat");
+        }
+        Ok(output)
+    }
+
+    /// Returns the output path, and lengths of the original and processed modules.
+    fn process_file(
+        &self,
+        processor: &Processor<'_>,
+        input: &Path,
+        output: PathBuf,
+    ) -> anyhow::Result<(PathBuf, usize, usize)> {
+        let module = fs::read(input).context("failed reading input module")?;
+        let module = self.parse_input_module(module)?;
+        let processed = self.process_module(processor, &module)?;
+        write_atomically(&output, &processed)
+            .with_context(|| format!("failed writing module to `{}`", output.to_string_lossy()))?;
+        Ok((output, module.len(), processed.len()))
+    }
+
+    fn process_module(&self, processor: &Processor<'_>, module: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let processed = if self.can_pass_through(processor, module) {
+            module.to_vec()
+        } else {
+            let processed = if Self::has_default_options(processor) {
+                externref::processor::process_bytes(module)
+            } else {
+                processor.process_bytes(module)
+            };
+            processed
+                .map_err(|err| err.into_report(module))
+                .context("failed processing module")?
+        };
+        if self.validate.unwrap_or(self.preset.validate()) {
+            wasmparser::Validator::new()
+                .validate_all(&processed)
+                .context("processed module is invalid")?;
+        }
+        #[cfg(feature = "wasmtime")]
+        if let Some(runtime) = self.verify_runtime {
+            runtime
+                .verify(&processed, &self.processing.export_table)
+                .context("processed module failed runtime verification")?;
+        }
+        Ok(match self.output_format {
+            ModuleFormat::Wasm => processed,
+            ModuleFormat::Wat => wasmprinter::print_bytes(&processed)
+                .context("failed printing processed module")?
+                .into_bytes(),
+        })
+    }
+
+    /// Checks whether the `processor` has the default options, in which case modules
+    /// are processed via [`externref::processor::process_bytes()`].
+    fn has_default_options(processor: &Processor<'_>) -> bool {
+        // `Processor` cannot implement `PartialEq` since it may contain hooks, but its `Debug`
+        // representation covers all options.
+        format!("{processor:?}") == format!("{:?}", Processor::default())
+    }
+
+    /// Checks whether the module can be output as is, i.e., pass-through is enabled, the processor
+    /// has the default options, and the module contains neither `externref` declarations
+    /// nor the processing marker. Only section headers are scanned, which is much faster
+    /// than processing.
+    fn can_pass_through(&self, processor: &Processor<'_>, module: &[u8]) -> bool {
+        // If the module cannot be scanned, it is processed in order to get a proper error.
+        self.pass_through
+            && Self::has_default_options(processor)
+            && matches!(has_externref_sections(module), Ok(false))
+    }
+
+    /// Converts the input module to the WASM binary format if necessary.
+    fn parse_input_module(&self, module: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(match self.input_format {
+            ModuleFormat::Wasm => module,
+            ModuleFormat::Wat => {
+                let text = String::from_utf8(module).context("input module is not UTF-8")?;
+                wat::parse_str(&text).context("failed parsing input module")?
+            }
+        })
+    }
+
+    fn read_input_module(input: &Path) -> anyhow::Result<Vec<u8>> {
+        let bytes = if input.as_os_str() == "-" {
             let mut buffer = Vec::with_capacity(1_024);
             io::stdin().read_to_end(&mut buffer)?;
             buffer
         } else {
-            fs::read(&self.input)?
+            fs::read(input)?
         };
         Ok(bytes)
     }
 
     fn write_output_module(&self, bytes: &[u8]) -> anyhow::Result<()> {
         if let Some(path) = &self.output {
-            fs::write(path, bytes)?;
+            write_atomically(path, bytes)?;
         } else {
             io::stdout().lock().write_all(bytes)?;
         }
@@ -127,6 +896,41 @@ impl Cli {
     }
 }
 
-fn main() -> anyhow::Result<()> {
-    Cli::parse().run()
+/// Scans section headers of a WASM module to find `externref` declarations
+/// or the processing marker. Function bodies are skipped without parsing.
+fn has_externref_sections(bytes: &[u8]) -> Result<bool, wasmparser::BinaryReaderError> {
+    use wasmparser::{Chunk, Parser, Payload};
+
+    let mut parser = Parser::new(0);
+    let mut data = bytes;
+    loop {
+        let Chunk::Parsed { consumed, payload } = parser.parse(data, true)? else {
+            // Cannot happen since all data is available.
+            return Ok(true);
+        };
+        data = &data[consumed..];
+        match payload {
+            // Sections with declarations may have custom names starting with the default name;
+            // the processing marker section is covered as well.
+            Payload::CustomSection(section)
+                if section.name().starts_with(Function::CUSTOM_SECTION_NAME) =>
+            {
+                return Ok(true);
+            }
+            Payload::CodeSectionStart { size, .. } => {
+                parser.skip_section();
+                data = &data[size as usize..];
+            }
+            Payload::End(_) => return Ok(false),
+            _ => { /* skip other payloads */ }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => error::report(&err, cli.error_format),
+    }
 }
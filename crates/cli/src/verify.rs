@@ -0,0 +1,99 @@
+//! Smoke-testing processed modules in an embedded WASM runtime.
+
+use anyhow::{anyhow, ensure, Context as _};
+use clap::ValueEnum;
+use wasmtime::{Engine, ExternType, Global, Linker, Memory, Module, Store, Table, Val, ValType};
+
+/// Runtime used to verify processed modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Runtime {
+    /// Embedded `wasmtime` runtime.
+    Wasmtime,
+}
+
+impl Runtime {
+    /// Instantiates the processed `module` with stubbed imports, and checks that the `externref`s
+    /// table is exported if the module interface uses `externref`s.
+    pub(crate) fn verify(self, module: &[u8], ref_table: &str) -> anyhow::Result<()> {
+        match self {
+            Self::Wasmtime => verify_with_wasmtime(module, ref_table),
+        }
+    }
+}
+
+fn verify_with_wasmtime(module: &[u8], ref_table: &str) -> anyhow::Result<()> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, module).context("failed compiling module")?;
+    let mut store = Store::new(&engine, ());
+    let mut linker = Linker::new(&engine);
+    // Function imports return zeros / nulls; other imports are created with default values.
+    linker.define_unknown_imports_as_default_values(&module)?;
+    for import in module.imports() {
+        let (module_name, name) = (import.module(), import.name());
+        match import.ty() {
+            ExternType::Func(_) => { /* already defined */ }
+            ExternType::Global(ty) => {
+                let value = default_value(ty.content())
+                    .ok_or_else(|| anyhow!("cannot stub global `{module_name}::{name}`"))?;
+                let global = Global::new(&mut store, ty, value)?;
+                linker.define(&store, module_name, name, global)?;
+            }
+            ExternType::Table(ty) => {
+                let init = default_value(&ValType::Ref(ty.element().clone()))
+                    .and_then(Val::ref_)
+                    .ok_or_else(|| anyhow!("cannot stub table `{module_name}::{name}`"))?;
+                let table = Table::new(&mut store, ty, init)?;
+                linker.define(&store, module_name, name, table)?;
+            }
+            ExternType::Memory(ty) => {
+                let memory = Memory::new(&mut store, ty)?;
+                linker.define(&store, module_name, name, memory)?;
+            }
+        }
+    }
+    linker
+        .instantiate(&mut store, &module)
+        .context("failed instantiating module")?;
+
+    let uses_externrefs = module
+        .imports()
+        .map(|import| import.ty())
+        .chain(module.exports().map(|export| export.ty()))
+        .any(|ty| match ty {
+            ExternType::Func(ty) => ty.params().chain(ty.results()).any(is_externref),
+            _ => false,
+        });
+    if uses_externrefs {
+        let partition_prefix = format!("{ref_table}::");
+        let has_table = module.exports().any(|export| {
+            let name = export.name();
+            let is_ref_table = name == ref_table || name.starts_with(&partition_prefix);
+            is_ref_table
+                && matches!(
+                    export.ty(),
+                    ExternType::Table(ty) if ty.element().heap_type().is_extern()
+                )
+        });
+        ensure!(
+            has_table,
+            "module uses `externref`s, but does not export the `{ref_table}` table"
+        );
+    }
+    Ok(())
+}
+
+fn is_externref(ty: ValType) -> bool {
+    matches!(ty, ValType::Ref(ty) if ty.heap_type().is_extern())
+}
+
+fn default_value(ty: &ValType) -> Option<Val> {
+    Some(match ty {
+        ValType::I32 => Val::I32(0),
+        ValType::I64 => Val::I64(0),
+        ValType::F32 => Val::F32(0),
+        ValType::F64 => Val::F64(0),
+        ValType::V128 => Val::V128(0_u128.into()),
+        ValType::Ref(ty) if ty.is_nullable() => Val::null_ref(ty.heap_type()),
+        ValType::Ref(_) => return None,
+    })
+}
@@ -7,7 +7,6 @@ use term_transcript::{
     test::TestConfig,
     ExitStatus, PtyCommand, ShellOptions,
 };
-#[cfg(feature = "tracing")]
 use test_casing::{decorate, decorators::Retry};
 
 fn template() -> Template {
@@ -50,6 +49,14 @@ fn error_processing_module() {
     );
 }
 
+#[test]
+fn error_processing_module_as_json() {
+    test_config().test(
+        "tests/snapshots/error-json.svg",
+        ["externref --error-format json -o /dev/null tests/integration.rs"],
+    );
+}
+
 #[test]
 fn error_specifying_drop_fn() {
     test_config().test(
@@ -57,3 +64,80 @@ fn error_specifying_drop_fn() {
         ["externref --drop-fn test_drop -o /dev/null tests/test.wasm"],
     );
 }
+
+#[test]
+#[decorate(Retry::times(3))] // sometimes, the captured output includes `>` from the input
+fn batch_processing() {
+    test_config().test(
+        "tests/snapshots/batch.svg",
+        [
+            "externref --drop-fn test::drop --out-dir /tmp/externref-batch \\\n  \
+            tests/test.wasm tests/integration.rs",
+        ],
+    );
+}
+
+#[test]
+#[decorate(Retry::times(3))]
+fn error_batch_processing_with_colliding_outputs() {
+    test_config().test(
+        "tests/snapshots/error-batch-collision.svg",
+        [
+            "externref --drop-fn test::drop --out-dir /tmp/externref-collision \\\n  \
+            tests/test.wasm ../cli/tests/test.wasm",
+        ],
+    );
+}
+
+#[test]
+fn processing_in_pipeline() {
+    test_config().test(
+        "tests/snapshots/pipeline.svg",
+        ["externref --drop-fn test::drop < tests/test.wasm | wc -c"],
+    );
+}
+
+#[test]
+#[decorate(Retry::times(3))]
+fn passing_through_modules_without_declarations() {
+    test_config().test(
+        "tests/snapshots/pass-through.svg",
+        [
+            "echo '(module)' | externref --input-format wat | wc -c",
+            "echo '(module)' | externref --input-format wat --pass-through | wc -c",
+        ],
+    );
+}
+
+#[test]
+fn diffing_interface() {
+    test_config().test(
+        "tests/snapshots/diff.svg",
+        ["externref diff --drop-fn test::drop tests/test.wasm"],
+    );
+}
+
+#[test]
+fn generating_code() {
+    test_config().test(
+        "tests/snapshots/codegen.svg",
+        [
+            "externref codegen tests/interface.toml",
+            "externref codegen --target host tests/interface.toml",
+        ],
+    );
+}
+
+#[cfg(feature = "wasmtime")]
+#[test]
+fn verifying_module_in_runtime() {
+    test_config().test(
+        "tests/snapshots/verify-runtime.svg",
+        [
+            "externref --drop-fn test::drop --verify-runtime wasmtime \\\n  \
+            -o /dev/null tests/test.wasm && echo OK",
+            "echo '(module (func (export \"test\") (param externref)))' | \\\n  \
+            externref --input-format wat --verify-runtime wasmtime -o /dev/null",
+        ],
+    );
+}
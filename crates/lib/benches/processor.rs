@@ -0,0 +1,129 @@
+//! Benchmarks for processing WASM modules.
+
+use std::{fmt::Write as _, fs};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use externref::{processor::Processor, BitSlice, Function, FunctionKind, TypeNames};
+use walrus::{Module, RawCustomSection};
+
+const ARENA_ALLOC: Function<'static> = Function {
+    kind: FunctionKind::Import("arena"),
+    name: "alloc",
+    externrefs: BitSlice::builder::<1>(3)
+        .with_set_bit(0)
+        .with_set_bit(2)
+        .build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+const ARENA_ALLOC_BYTES: [u8; ARENA_ALLOC.custom_section_len()] = ARENA_ALLOC.custom_section();
+
+const TEST: Function<'static> = Function {
+    kind: FunctionKind::Export,
+    name: "test",
+    externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+const TEST_BYTES: [u8; TEST.custom_section_len()] = TEST.custom_section();
+
+/// Function that does not deal with `externref`s; it should be skipped by the processor.
+fn write_plain_function(module: &mut String, idx: usize, body_len: usize) {
+    writeln!(
+        module,
+        "  (func (export \"plain{idx}\") (param i32) (result i32)"
+    )
+    .unwrap();
+    writeln!(module, "    (local.get 0)").unwrap();
+    for i in 0..body_len {
+        writeln!(
+            module,
+            "    (i32.add (i32.const {i})) (i32.mul (i32.const 3))"
+        )
+        .unwrap();
+    }
+    writeln!(module, "  )").unwrap();
+}
+
+/// Function calling an import returning a ref; it has to be visited by the processor.
+fn write_ref_function(module: &mut String, idx: usize, body_len: usize) {
+    writeln!(module, "  (func (export \"ref{idx}\") (param $arena i32)").unwrap();
+    for i in 0..body_len {
+        writeln!(
+            module,
+            "    (call $drop_ref (call $insert_ref \
+             (call $alloc (call $get_ref (local.get $arena)) (i32.const {i})) (i32.const -1)))"
+        )
+        .unwrap();
+    }
+    writeln!(module, "  )").unwrap();
+}
+
+/// Generates a module based on `tests/modules/simple.wast` with additional functions.
+fn generate_module(plain_fns: usize, ref_fns: usize, body_len: usize) -> Vec<u8> {
+    let base = fs::read_to_string("tests/modules/simple.wast").unwrap();
+    let base = base.trim_end().strip_suffix(')').unwrap();
+    // Modules produced by the `externref` macro import the guard function,
+    // which requires an additional pass over functions mentioning it.
+    let mut module = base.replacen(
+        "(module",
+        "(module\n  (import \"externref\" \"guard\" (func $guard))",
+        1,
+    );
+    for idx in 0..plain_fns {
+        write_plain_function(&mut module, idx, body_len);
+    }
+    for idx in 0..ref_fns {
+        write_ref_function(&mut module, idx, body_len);
+    }
+    module.push(')');
+
+    let module = wat::parse_str(&module).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: [ARENA_ALLOC_BYTES.as_slice(), &TEST_BYTES].concat(),
+    });
+    module.emit_wasm()
+}
+
+fn processing_modules(criterion: &mut Criterion) {
+    let modules = [
+        ("small", generate_module(0, 0, 0)),
+        ("large", generate_module(50, 50, 500)),
+        ("many_functions", generate_module(2_000, 20, 10)),
+    ];
+
+    let mut group = criterion.benchmark_group("process_bytes");
+    for (name, module) in &modules {
+        group.throughput(Throughput::Bytes(module.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            module,
+            |bencher, module| {
+                let processor = Processor::default();
+                bencher.iter(|| processor.process_bytes(module).unwrap());
+            },
+        );
+    }
+    group.finish();
+
+    let mut group = criterion.benchmark_group("verify");
+    for (name, module) in &modules {
+        group.throughput(Throughput::Bytes(module.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            module,
+            |bencher, module| {
+                let processor = Processor::default();
+                bencher.iter(|| processor.verify(module).unwrap());
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, processing_modules);
+criterion_main!(benches);
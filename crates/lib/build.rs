@@ -0,0 +1,47 @@
+//! Generates the declarations depending on the module of surrogate imports, which can be changed
+//! via the `EXTERNREF_SURROGATE_MODULE` env variable.
+
+use std::{
+    env::{self, VarError},
+    fs,
+    path::PathBuf,
+};
+
+const MODULE_VAR: &str = "EXTERNREF_SURROGATE_MODULE";
+const DEFAULT_MODULE: &str = "externref";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed={MODULE_VAR}");
+    let module = match env::var(MODULE_VAR) {
+        Ok(module) => module,
+        Err(VarError::NotPresent) => DEFAULT_MODULE.to_owned(),
+        Err(VarError::NotUnicode(_)) => panic!("`{MODULE_VAR}` is not a valid UTF-8 string"),
+    };
+    assert!(!module.is_empty(), "`{MODULE_VAR}` must not be empty");
+
+    let code = format!(
+        r#"/// Module of surrogate imports used by this crate (e.g., for creating and dropping [`Resource`]s).
+///
+/// By default, the module is `externref`. It can be changed by setting
+/// the `EXTERNREF_SURROGATE_MODULE` env variable when compiling the crate, e.g. if the host
+/// provides a real module named `externref`. The processor must be configured to use the same
+/// module via `Processor::set_surrogate_module()`.
+pub const SURROGATE_MODULE: &str = {module:?};
+
+/// Declares foreign functions imported from the [`SURROGATE_MODULE`].
+#[doc(hidden)] // only used by the crate itself and by the macros it exports
+#[macro_export]
+macro_rules! __surrogate_imports {{
+    ($($item:tt)*) => {{
+        #[link(wasm_import_module = {module:?})]
+        extern "C" {{
+            $($item)*
+        }}
+    }};
+}}
+"#
+    );
+    let out_dir = env::var_os("OUT_DIR").expect("`OUT_DIR` is not set");
+    let out_path = PathBuf::from(out_dir).join("surrogate.rs");
+    fs::write(out_path, code).expect("failed writing surrogate declarations");
+}
@@ -0,0 +1,333 @@
+//! Fixed-capacity arena for storing resources without heap allocations.
+
+use core::{fmt, hash, iter, marker::PhantomData, slice};
+
+use crate::Resource;
+
+/// Typed key of a resource stored in a [`ResourceArena`].
+///
+/// Slots are reused after a resource is removed from the arena, so a key of a removed resource
+/// may refer to another resource inserted later.
+pub struct ResourceSlot<T> {
+    idx: usize,
+    _ty: PhantomData<fn(T)>,
+}
+
+impl<T> fmt::Debug for ResourceSlot<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_tuple("ResourceSlot")
+            .field(&self.idx)
+            .finish()
+    }
+}
+
+impl<T> Clone for ResourceSlot<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ResourceSlot<T> {}
+
+impl<T> PartialEq for ResourceSlot<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+
+impl<T> Eq for ResourceSlot<T> {}
+
+impl<T> hash::Hash for ResourceSlot<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.idx.hash(state);
+    }
+}
+
+impl<T> ResourceSlot<T> {
+    const fn new(idx: usize) -> Self {
+        Self {
+            idx,
+            _ty: PhantomData,
+        }
+    }
+
+    /// Returns the 0-based index of this slot in the arena.
+    pub const fn index(self) -> usize {
+        self.idx
+    }
+}
+
+/// Fixed-capacity arena of [`Resource`]s with typed keys.
+///
+/// The arena is an array of `Option<Resource<T>>` that can be created in const contexts,
+/// so it can be placed in static storage without the `std` feature or heap allocations,
+/// e.g. to keep resources obtained from the host between calls into the module.
+/// Resources remaining in the arena are dropped together with it.
+///
+/// # Examples
+///
+/// ```
+/// # use std::sync::Mutex;
+/// # use externref::{externref, Resource, ResourceArena, ResourceSlot};
+/// pub struct Sender(());
+///
+/// static SENDERS: Mutex<ResourceArena<Sender, 4>> = Mutex::new(ResourceArena::new());
+///
+/// #[externref]
+/// #[link(wasm_import_module = "test")]
+/// extern "C" {
+///     fn send(sender: &Resource<Sender>, message: &[u8]);
+/// }
+///
+/// #[externref]
+/// pub extern "C" fn register_sender(sender: Resource<Sender>) -> i32 {
+///     match SENDERS.lock().unwrap().insert(sender) {
+///         Ok(slot) => slot.index() as i32,
+///         Err(_) => -1, // the arena is full; the sender is dropped
+///     }
+/// }
+///
+/// fn broadcast(message: &[u8]) {
+///     for (_, sender) in SENDERS.lock().unwrap().iter() {
+///         unsafe { send(sender, message) };
+///     }
+/// }
+///
+/// fn unregister_all() {
+///     // Drops all senders.
+///     SENDERS.lock().unwrap().clear();
+/// }
+/// ```
+pub struct ResourceArena<T, const N: usize> {
+    slots: [Option<Resource<T>>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> fmt::Debug for ResourceArena<T, N> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ResourceArena")
+            .field("len", &self.len)
+            .field("capacity", &N)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, const N: usize> Default for ResourceArena<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> ResourceArena<T, N> {
+    const EMPTY_SLOT: Option<Resource<T>> = None;
+
+    /// Creates an empty arena.
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY_SLOT; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the maximum number of resources in this arena.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of resources in this arena.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks whether this arena is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts a resource into the first free slot of this arena.
+    ///
+    /// # Errors
+    ///
+    /// Returns the resource back if the arena is full.
+    pub fn insert(&mut self, resource: Resource<T>) -> Result<ResourceSlot<T>, Resource<T>> {
+        let Some(idx) = self.slots.iter().position(Option::is_none) else {
+            return Err(resource);
+        };
+        self.slots[idx] = Some(resource);
+        self.len += 1;
+        Ok(ResourceSlot::new(idx))
+    }
+
+    /// Gets a reference to the resource in the specified slot.
+    pub fn get(&self, slot: ResourceSlot<T>) -> Option<&Resource<T>> {
+        self.slots.get(slot.idx)?.as_ref()
+    }
+
+    /// Gets a mutable reference to the resource in the specified slot.
+    pub fn get_mut(&mut self, slot: ResourceSlot<T>) -> Option<&mut Resource<T>> {
+        self.slots.get_mut(slot.idx)?.as_mut()
+    }
+
+    /// Removes the resource from the specified slot and returns it. The slot can then be reused
+    /// for other resources.
+    pub fn remove(&mut self, slot: ResourceSlot<T>) -> Option<Resource<T>> {
+        let resource = self.slots.get_mut(slot.idx)?.take()?;
+        self.len -= 1;
+        Some(resource)
+    }
+
+    /// Iterates over resources in this arena together with their slots, in the slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceSlot<T>, &Resource<T>)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(idx, resource)| {
+            resource
+                .as_ref()
+                .map(|resource| (ResourceSlot::new(idx), resource))
+        })
+    }
+
+    /// Iterates over mutable references to resources in this arena together with their slots,
+    /// in the slot order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ResourceSlot<T>, &mut Resource<T>)> + '_ {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, resource)| {
+                resource
+                    .as_mut()
+                    .map(|resource| (ResourceSlot::new(idx), resource))
+            })
+    }
+
+    /// Removes all resources from this arena, returning them together with their slots
+    /// in the slot order. Resources not consumed from the returned iterator are dropped
+    /// once it is dropped.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        Drain {
+            inner: self.slots.iter_mut().enumerate(),
+            len: &mut self.len,
+        }
+    }
+
+    /// Removes and drops all resources in this arena.
+    pub fn clear(&mut self) {
+        self.drain().for_each(drop);
+    }
+}
+
+/// Draining iterator over resources in a [`ResourceArena`] returned by
+/// [`ResourceArena::drain()`].
+pub struct Drain<'a, T> {
+    inner: iter::Enumerate<slice::IterMut<'a, Option<Resource<T>>>>,
+    /// Length of the arena, which is decremented as resources are taken, so that it remains
+    /// correct even if the iterator is leaked.
+    len: &'a mut usize,
+}
+
+impl<T> fmt::Debug for Drain<'_, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("Drain").finish_non_exhaustive()
+    }
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = (ResourceSlot<T>, Resource<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.find_map(|(idx, resource)| {
+            resource
+                .take()
+                .map(|resource| (ResourceSlot::new(idx), resource))
+        })?;
+        *self.len -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (*self.len, Some(*self.len))
+    }
+}
+
+impl<T> ExactSizeIterator for Drain<'_, T> {}
+
+impl<T> iter::FusedIterator for Drain<'_, T> {}
+
+/// Drops resources not consumed from the iterator.
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+    use crate::alloc::Vec;
+
+    #[test]
+    fn arena_basics() {
+        let mut arena = ResourceArena::<(), 2>::new();
+        assert!(arena.is_empty());
+        let first = arena.insert(Resource::from_id(5)).unwrap();
+        let second = arena.insert(Resource::from_id(7)).unwrap();
+        assert_eq!((first.index(), second.index()), (0, 1));
+        assert_eq!(arena.len(), 2);
+        let overflow = arena.insert(Resource::from_id(9)).unwrap_err();
+        assert_eq!(overflow.id(), 9);
+
+        assert_eq!(arena.get(second).unwrap().id(), 7);
+        let removed = arena.remove(first).unwrap();
+        assert_eq!(removed.id(), 5);
+        assert!(arena.get(first).is_none());
+        assert!(arena.remove(first).is_none());
+        assert_eq!(arena.len(), 1);
+
+        let reused = arena.insert(removed).unwrap();
+        assert_eq!(reused, first);
+        let ids: Vec<_> = arena
+            .iter()
+            .map(|(slot, resource)| (slot.index(), resource.id()))
+            .collect();
+        assert_eq!(ids, [(0, 5), (1, 7)]);
+    }
+
+    #[test]
+    fn draining_arena() {
+        let mut arena = ResourceArena::<(), 3>::new();
+        arena.insert(Resource::from_id(1)).unwrap();
+        let slot = arena.insert(Resource::from_id(2)).unwrap();
+        arena.insert(Resource::from_id(3)).unwrap();
+        arena.remove(slot).unwrap();
+
+        let mut drain = arena.drain();
+        let (slot, resource) = drain.next().unwrap();
+        assert_eq!((slot.index(), resource.id()), (0, 1));
+        drop(drain);
+        assert!(arena.is_empty());
+        assert_eq!(arena.iter().count(), 0);
+        assert!(arena.insert(resource).is_ok());
+    }
+
+    #[test]
+    fn leaking_drain() {
+        let mut arena = ResourceArena::<(), 3>::new();
+        arena.insert(Resource::from_id(1)).unwrap();
+        let slot = arena.insert(Resource::from_id(2)).unwrap();
+
+        let mut drain = arena.drain();
+        assert_eq!(drain.len(), 2);
+        let (_, resource) = drain.next().unwrap();
+        assert_eq!(drain.len(), 1);
+        drop(resource);
+        mem::forget(drain);
+
+        // The resource not taken from the leaked iterator remains in the arena.
+        assert_eq!(arena.len(), 1);
+        let resource = arena.remove(slot).unwrap();
+        assert_eq!(resource.id(), 2);
+        assert!(arena.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! Per-type cache of singleton resources.
+
+use core::{
+    any::TypeId,
+    cell::UnsafeCell,
+    hint, mem, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    alloc::{Box, Vec},
+    Resource,
+};
+
+/// Cached resources in the order of their insertion. The cache is process-wide; the lock
+/// only makes it memory-safe, but does not make cached resources valid in other threads.
+struct ResourceCache {
+    locked: AtomicBool,
+    entries: UnsafeCell<Vec<(TypeId, &'static Resource<()>)>>,
+}
+
+// SAFETY: access to `entries` is synchronized via `locked`.
+unsafe impl Sync for ResourceCache {}
+
+impl ResourceCache {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            entries: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Provides exclusive access to the cache entries. `action` must not access the cache
+    /// or drop resources (since this can call into the guest drop hook, which may access
+    /// the cache).
+    fn with_entries<R>(
+        &self,
+        action: impl FnOnce(&mut Vec<(TypeId, &'static Resource<()>)>) -> R,
+    ) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        // SAFETY: the lock is held, so no other references to `entries` exist.
+        let output = action(unsafe { &mut *self.entries.get() });
+        self.locked.store(false, Ordering::Release);
+        output
+    }
+
+    fn get(&self, type_id: TypeId) -> Option<&'static Resource<()>> {
+        self.with_entries(|entries| {
+            entries
+                .iter()
+                .find_map(|(id, resource)| (*id == type_id).then_some(*resource))
+        })
+    }
+
+    /// Inserts a resource unless the cache already contains a resource for `type_id`
+    /// (e.g., if it was inserted by the initializer). In the latter case, the provided resource
+    /// is returned so that it can be dropped outside the lock.
+    fn insert(
+        &self,
+        type_id: TypeId,
+        resource: Resource<()>,
+    ) -> (&'static Resource<()>, Option<Resource<()>>) {
+        self.with_entries(|entries| {
+            if let Some((_, cached)) = entries.iter().find(|(id, _)| *id == type_id) {
+                (*cached, Some(resource))
+            } else {
+                let cached: &'static Resource<()> = Box::leak(Box::new(resource));
+                entries.push((type_id, cached));
+                (cached, None)
+            }
+        })
+    }
+
+    fn take(&self) -> Vec<(TypeId, &'static Resource<()>)> {
+        self.with_entries(mem::take)
+    }
+}
+
+static CACHE: ResourceCache = ResourceCache::new();
+
+impl<T: 'static> Resource<T> {
+    /// Returns the resource cached for the type `T`, initializing it with `init` on the first call.
+    /// This is useful for singleton resources, such as a context handle returned by a host import.
+    ///
+    /// The cache is intended for single-threaded modules only. It is shared by all threads,
+    /// while `externref`s tables are per-thread (see [`SharedResource`](crate::SharedResource)),
+    /// so a resource cached in one thread refers to a wrong reference when used in another one.
+    ///
+    /// `init` is called without holding any locks, so it may access the cache itself
+    /// (e.g., to obtain another cached resource). Cached resources are never dropped
+    /// automatically; the host can drop them by calling the `__externref_teardown` export
+    /// (enabled by the `cache-teardown` crate feature) before discarding the module instance,
+    /// or the guest can call [`Resource::drop_cached()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use externref::{externref, Resource};
+    /// pub struct Context(());
+    ///
+    /// #[externref]
+    /// #[link(wasm_import_module = "test")]
+    /// extern "C" {
+    ///     fn create_context() -> Resource<Context>;
+    ///     fn log(context: &Resource<Context>, message: &str);
+    /// }
+    ///
+    /// fn log_with_context(message: &str) {
+    ///     let context = Resource::cached(|| unsafe { create_context() });
+    ///     unsafe { log(context, message) };
+    /// }
+    /// ```
+    pub fn cached(init: impl FnOnce() -> Self) -> &'static Self {
+        let type_id = TypeId::of::<T>();
+        let cached = if let Some(cached) = CACHE.get(type_id) {
+            cached
+        } else {
+            let (cached, redundant) = CACHE.insert(type_id, init().upcast());
+            drop(redundant);
+            cached
+        };
+
+        let ptr = ptr::from_ref(cached).cast::<Self>();
+        // SAFETY: the resource was inserted for `T`, and all resource types have identical
+        // layout (see `Resource::upcast_ref()`).
+        unsafe { &*ptr }
+    }
+}
+
+impl Resource<()> {
+    /// Drops all resources [cached](Resource::cached()) by the module in the reverse order
+    /// of their caching, so that resources cached later (which can depend on earlier ones)
+    /// are dropped first. This is what the `__externref_teardown` export (enabled
+    /// by the `cache-teardown` crate feature) calls.
+    ///
+    /// # Safety
+    ///
+    /// References to cached resources obtained before the call become dangling: they must
+    /// not be used, since their slots in the `externref`s table may be reused by other references.
+    /// The cache must not have been cleared via [`drop_all()`](crate::drop_all()) beforehand;
+    /// otherwise, cached references will be dropped twice.
+    pub unsafe fn drop_cached() {
+        for (_, cached) in CACHE.take().into_iter().rev() {
+            // The memory of the cached resource is leaked, so that existing `&'static`
+            // references remain valid (albeit stale).
+            drop(ptr::read(cached));
+        }
+    }
+}
+
+/// Drops cached resources. Intended to be called by the host before discarding the module instance.
+#[cfg(all(target_arch = "wasm32", feature = "cache-teardown"))]
+#[export_name = "__externref_teardown"]
+extern "C" fn teardown() {
+    unsafe { Resource::drop_cached() };
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn caching_resources() {
+        struct First;
+        struct Second;
+
+        static INIT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let first = Resource::<First>::cached(|| {
+            INIT_COUNT.fetch_add(1, Ordering::Relaxed);
+            Resource::from_id(1)
+        });
+        assert_eq!(first.id(), 1);
+        let first_again = Resource::<First>::cached(|| unreachable!());
+        assert!(ptr::eq(first, first_again));
+
+        // Initializers can access the cache.
+        let second = Resource::<Second>::cached(|| {
+            let first = Resource::<First>::cached(|| unreachable!());
+            Resource::from_id(first.id() + 1)
+        });
+        assert_eq!(second.id(), 2);
+        assert_eq!(INIT_COUNT.load(Ordering::Relaxed), 1);
+    }
+}
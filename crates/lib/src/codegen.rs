@@ -0,0 +1,729 @@
+//! Generating declarations from an interface description.
+//!
+//! An [`Interface`] lists functions imported / exported by a WASM module together with
+//! their argument and return types. It is usually deserialized from a JSON or TOML file
+//! (e.g., by the `externref` CLI). Types are specified in the Rust syntax; besides
+//! primitive WASM-compatible types (`i32`, `u32`, `i64`, `u64`, `f32`, `f64`, `isize`, `usize`),
+//! resources in all forms supported by the [`externref` macro](macro@crate::externref)
+//! can be used: `Resource<T>`, `&Resource<T>`, `Option<Resource<T>>` and `Option<&Resource<T>>`.
+//! `T` is the resource kind; if omitted (i.e., in `Resource`), `()` is used.
+//!
+//! Based on the interface, the following code can be generated:
+//!
+//! - [Guest code](Interface::guest_code()) for the WASM module: marker types for resource kinds,
+//!   `extern "C"` blocks with imports, and an `Exports` trait together with
+//!   the `export_interface!` macro generating exported functions from a trait implementation.
+//!   All functions are annotated with the `#[externref]` attribute.
+//! - [Host code](Interface::host_code()): a trait for each imported module, which host
+//!   implementations of imports can implement. The trait is runtime-agnostic;
+//!   `externref`s are represented by an associated type.
+//!
+//! # Examples
+//!
+//! ```
+//! use externref::codegen::Interface;
+//!
+//! let interface: Interface = serde_json::from_str(r#"{
+//!     "imports": [{
+//!         "module": "arena",
+//!         "functions": [{
+//!             "name": "alloc",
+//!             "params": [
+//!                 { "name": "arena", "type": "&Resource<Arena>" },
+//!                 { "name": "capacity", "type": "usize" }
+//!             ],
+//!             "result": "Option<Resource<Bytes>>"
+//!         }]
+//!     }],
+//!     "exports": [{
+//!         "name": "test",
+//!         "params": [{ "name": "arena", "type": "Resource<Arena>" }]
+//!     }]
+//! }"#)?;
+//!
+//! let guest_code = interface.guest_code()?;
+//! assert!(guest_code.contains(
+//!     "pub fn alloc(arena: &Resource<Arena>, capacity: usize) -> Option<Resource<Bytes>>;"
+//! ));
+//! let host_code = interface.host_code()?;
+//! assert!(host_code.contains("pub trait ArenaImports {"));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    fmt::Write as _,
+    str::FromStr,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Header prepended to all generated code.
+const HEADER: &str = "// Generated by `externref` codegen; do not edit.\n";
+
+/// Errors that can occur when generating code from an [`Interface`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Type that cannot be parsed or is not supported.
+    InvalidType(String),
+    /// Name that is not a valid Rust identifier.
+    InvalidIdent(String),
+    /// Function declared multiple times in the same module.
+    DuplicateFunction {
+        /// Imported module name, or `None` for exports.
+        module: Option<String>,
+        /// Function name.
+        name: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidType(ty) => write!(
+                formatter,
+                "unsupported type `{ty}`; expected a primitive type or a resource, \
+                 e.g. `&Resource<T>` or `Option<Resource<T>>`"
+            ),
+            Self::InvalidIdent(ident) => {
+                write!(formatter, "`{ident}` is not a valid Rust identifier")
+            }
+            Self::DuplicateFunction {
+                module: Some(module),
+                name,
+            } => write!(
+                formatter,
+                "function `{name}` is imported from module `{module}` multiple times"
+            ),
+            Self::DuplicateFunction { module: None, name } => {
+                write!(formatter, "function `{name}` is exported multiple times")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Primitive WASM-compatible type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PrimitiveType {
+    /// `i32`
+    I32,
+    /// `u32`
+    U32,
+    /// `i64`
+    I64,
+    /// `u64`
+    U64,
+    /// `f32`
+    F32,
+    /// `f64`
+    F64,
+    /// `isize`; corresponds to `i32` on the host.
+    Isize,
+    /// `usize`; corresponds to `u32` on the host.
+    Usize,
+}
+
+impl PrimitiveType {
+    const ALL: [Self; 8] = [
+        Self::I32,
+        Self::U32,
+        Self::I64,
+        Self::U64,
+        Self::F32,
+        Self::F64,
+        Self::Isize,
+        Self::Usize,
+    ];
+
+    fn guest_name(self) -> &'static str {
+        match self {
+            Self::I32 => "i32",
+            Self::U32 => "u32",
+            Self::I64 => "i64",
+            Self::U64 => "u64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::Isize => "isize",
+            Self::Usize => "usize",
+        }
+    }
+
+    fn host_name(self) -> &'static str {
+        match self {
+            Self::Isize => "i32",
+            Self::Usize => "u32",
+            _ => self.guest_name(),
+        }
+    }
+}
+
+/// Type of a function arg or return value in an [`Interface`].
+///
+/// Types are (de)serialized as strings in the Rust syntax, e.g. `usize`
+/// or `Option<&Resource<Sender>>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+#[non_exhaustive]
+pub enum ValueType {
+    /// Primitive type.
+    Primitive(PrimitiveType),
+    /// Resource, i.e. an `externref` on the host side.
+    Resource {
+        /// Resource kind, i.e. the type param of `Resource<_>`. `None` corresponds to `()`.
+        kind: Option<String>,
+        /// Is the resource borrowed (`&Resource<_>`)?
+        borrowed: bool,
+        /// Is the resource wrapped in `Option<_>`?
+        nullable: bool,
+    },
+}
+
+impl ValueType {
+    fn resource_kind(&self) -> Option<&str> {
+        match self {
+            Self::Resource {
+                kind: Some(kind), ..
+            } => Some(kind),
+            _ => None,
+        }
+    }
+
+    fn write_host_type(&self, buffer: &mut String) {
+        match self {
+            Self::Primitive(ty) => buffer.push_str(ty.host_name()),
+            Self::Resource { nullable: true, .. } => buffer.push_str("Option<Self::Ref>"),
+            Self::Resource { .. } => buffer.push_str("Self::Ref"),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Primitive(ty) => formatter.write_str(ty.guest_name()),
+            Self::Resource {
+                kind,
+                borrowed,
+                nullable,
+            } => {
+                let kind = kind.as_deref().unwrap_or("()");
+                let borrow = if *borrowed { "&" } else { "" };
+                if *nullable {
+                    write!(formatter, "Option<{borrow}Resource<{kind}>>")
+                } else {
+                    write!(formatter, "{borrow}Resource<{kind}>")
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for ValueType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_type = || Error::InvalidType(s.to_owned());
+        let ty: String = s.chars().filter(|ch| !ch.is_whitespace()).collect();
+
+        if let Some(primitive) = PrimitiveType::ALL
+            .into_iter()
+            .find(|primitive| primitive.guest_name() == ty)
+        {
+            return Ok(Self::Primitive(primitive));
+        }
+
+        let (ty, nullable) = match ty.strip_prefix("Option<") {
+            Some(inner) => (inner.strip_suffix('>').ok_or_else(invalid_type)?, true),
+            None => (ty.as_str(), false),
+        };
+        let (ty, borrowed) = match ty.strip_prefix('&') {
+            Some(inner) => (inner, true),
+            None => (ty, false),
+        };
+        let kind = if ty == "Resource" {
+            None
+        } else {
+            let kind = ty
+                .strip_prefix("Resource<")
+                .and_then(|ty| ty.strip_suffix('>'))
+                .ok_or_else(invalid_type)?;
+            if kind == "()" {
+                None
+            } else if is_ident(kind) {
+                Some(kind.to_owned())
+            } else {
+                return Err(invalid_type());
+            }
+        };
+        Ok(Self::Resource {
+            kind,
+            borrowed,
+            nullable,
+        })
+    }
+}
+
+impl TryFrom<String> for ValueType {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ValueType> for String {
+    fn from(value: ValueType) -> Self {
+        value.to_string()
+    }
+}
+
+/// Named function arg in an [`Interface`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Param {
+    /// Arg name. Must be a valid Rust identifier.
+    pub name: String,
+    /// Arg type.
+    #[serde(rename = "type")]
+    pub ty: ValueType,
+}
+
+/// Function declaration in an [`Interface`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionDecl {
+    /// Function name. Must be a valid Rust identifier.
+    pub name: String,
+    /// Function args.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+    /// Return type, or `None` if the function does not return a value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<ValueType>,
+}
+
+impl FunctionDecl {
+    fn resource_kinds(&self) -> impl Iterator<Item = &str> + '_ {
+        let params = self.params.iter().map(|param| &param.ty);
+        params
+            .chain(self.result.as_ref())
+            .filter_map(ValueType::resource_kind)
+    }
+
+    fn validate(&self, module: Option<&str>, names: &mut HashSet<String>) -> Result<(), Error> {
+        check_ident(&self.name)?;
+        for param in &self.params {
+            check_ident(&param.name)?;
+        }
+        if !names.insert(self.name.clone()) {
+            return Err(Error::DuplicateFunction {
+                module: module.map(str::to_owned),
+                name: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn write_guest_signature(&self, buffer: &mut String) {
+        write!(buffer, "fn {}(", self.name).unwrap();
+        for (i, param) in self.params.iter().enumerate() {
+            let comma = if i + 1 == self.params.len() { "" } else { ", " };
+            write!(buffer, "{}: {}{comma}", param.name, param.ty).unwrap();
+        }
+        buffer.push(')');
+        if let Some(result) = &self.result {
+            write!(buffer, " -> {result}").unwrap();
+        }
+    }
+
+    fn write_host_signature(&self, buffer: &mut String) {
+        write!(buffer, "fn {}(&mut self", self.name).unwrap();
+        for param in &self.params {
+            write!(buffer, ", {}: ", param.name).unwrap();
+            param.ty.write_host_type(buffer);
+        }
+        buffer.push(')');
+        if let Some(result) = &self.result {
+            buffer.push_str(" -> ");
+            result.write_host_type(buffer);
+        }
+    }
+
+    fn write_export(&self, buffer: &mut String) {
+        buffer.push_str("        #[externref]\n");
+        writeln!(buffer, "        #[export_name = \"{}\"]", self.name).unwrap();
+        let mut signature = String::new();
+        self.write_guest_signature(&mut signature);
+        writeln!(buffer, "        pub extern \"C\" {signature} {{").unwrap();
+        let args: Vec<_> = self.params.iter().map(|param| &*param.name).collect();
+        writeln!(
+            buffer,
+            "            <$ty as Exports>::{}({})",
+            self.name,
+            args.join(", ")
+        )
+        .unwrap();
+        buffer.push_str("        }\n");
+    }
+}
+
+/// Functions imported from a single WASM module.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportModule {
+    /// Name of the imported module.
+    pub module: String,
+    /// Imported functions.
+    #[serde(default)]
+    pub functions: Vec<FunctionDecl>,
+}
+
+impl ImportModule {
+    /// Converts the module name to a host trait name, e.g. `test_arena` to `TestArenaImports`.
+    fn trait_name(&self) -> Result<String, Error> {
+        let mut name = String::new();
+        for part in self.module.split(|ch: char| !ch.is_ascii_alphanumeric()) {
+            let mut chars = part.chars();
+            if let Some(first) = chars.next() {
+                name.push(first.to_ascii_uppercase());
+                name.extend(chars);
+            }
+        }
+        name.push_str("Imports");
+        if is_ident(&name) {
+            Ok(name)
+        } else {
+            Err(Error::InvalidIdent(name))
+        }
+    }
+}
+
+/// Description of functions imported and exported by a WASM module.
+///
+/// See the [module docs](self) for the description format and an example of usage.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interface {
+    /// Imported functions grouped by the module.
+    #[serde(default)]
+    pub imports: Vec<ImportModule>,
+    /// Exported functions.
+    #[serde(default)]
+    pub exports: Vec<FunctionDecl>,
+}
+
+impl Interface {
+    /// Checks that the interface can be used for code generation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a function or an arg name is not a valid Rust identifier,
+    /// or a function is declared multiple times in the same module.
+    pub fn validate(&self) -> Result<(), Error> {
+        for import in &self.imports {
+            import.trait_name()?;
+            let mut names = HashSet::new();
+            for function in &import.functions {
+                function.validate(Some(&import.module), &mut names)?;
+            }
+        }
+        let mut names = HashSet::new();
+        for function in &self.exports {
+            function.validate(None, &mut names)?;
+        }
+        Ok(())
+    }
+
+    fn all_functions(&self) -> impl Iterator<Item = &FunctionDecl> + '_ {
+        let imports = self.imports.iter().flat_map(|import| &import.functions);
+        imports.chain(&self.exports)
+    }
+
+    /// Generates code for the WASM module. The code requires the `macro` feature
+    /// of the `externref` crate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interface is [invalid](Self::validate()).
+    pub fn guest_code(&self) -> Result<String, Error> {
+        self.validate()?;
+
+        let mut buffer = String::from(HEADER);
+        buffer.push_str("\nuse externref::{externref, Resource};\n");
+
+        let kinds: BTreeSet<_> = self
+            .all_functions()
+            .flat_map(FunctionDecl::resource_kinds)
+            .collect();
+        for kind in kinds {
+            writeln!(buffer, "\n/// `{kind}` resource kind.").unwrap();
+            writeln!(buffer, "pub struct {kind}(());").unwrap();
+        }
+
+        for import in &self.imports {
+            buffer.push_str("\n#[externref]\n");
+            writeln!(
+                buffer,
+                "#[link(wasm_import_module = \"{}\")]",
+                import.module
+            )
+            .unwrap();
+            buffer.push_str("extern \"C\" {\n");
+            for function in &import.functions {
+                buffer.push_str("    pub ");
+                function.write_guest_signature(&mut buffer);
+                buffer.push_str(";\n");
+            }
+            buffer.push_str("}\n");
+        }
+
+        if !self.exports.is_empty() {
+            buffer.push_str("\n/// Functions exported from the module.\n");
+            buffer.push_str("pub trait Exports {\n");
+            for function in &self.exports {
+                buffer.push_str("    ");
+                function.write_guest_signature(&mut buffer);
+                buffer.push_str(";\n");
+            }
+            buffer.push_str("}\n");
+
+            buffer.push_str(
+                "\n/// Exports functions implemented by the specified type via the `Exports` trait.\n\
+                 /// Must be invoked in the module containing the generated code.\n\
+                 #[allow(unused_macros)]\n\
+                 macro_rules! export_interface {\n    \
+                     ($ty:ty) => {\n",
+            );
+            for (i, function) in self.exports.iter().enumerate() {
+                if i > 0 {
+                    buffer.push('\n');
+                }
+                function.write_export(&mut buffer);
+            }
+            buffer.push_str("    };\n}\n");
+        }
+        Ok(buffer)
+    }
+
+    /// Generates host-side traits for imported modules.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interface is [invalid](Self::validate()).
+    pub fn host_code(&self) -> Result<String, Error> {
+        self.validate()?;
+
+        let mut buffer = String::from(HEADER);
+        for import in &self.imports {
+            let trait_name = import.trait_name()?;
+            writeln!(
+                buffer,
+                "\n/// Host-side implementation of functions imported from the `{}` module.",
+                import.module
+            )
+            .unwrap();
+            writeln!(buffer, "pub trait {trait_name} {{").unwrap();
+            buffer.push_str("    /// Host representation of `externref`s.\n");
+            buffer.push_str("    type Ref;\n");
+            for function in &import.functions {
+                buffer.push('\n');
+                writeln!(
+                    buffer,
+                    "    /// Implements the `{}::{}` import.",
+                    import.module, function.name
+                )
+                .unwrap();
+                buffer.push_str("    ");
+                function.write_host_signature(&mut buffer);
+                buffer.push_str(";\n");
+            }
+            buffer.push_str("}\n");
+        }
+        Ok(buffer)
+    }
+}
+
+/// Strict and reserved Rust keywords that cannot be used as identifiers.
+const KEYWORDS: &[&str] = &[
+    "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    let starts_correctly = chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_');
+    starts_correctly
+        && s != "_"
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        && !KEYWORDS.contains(&s)
+}
+
+fn check_ident(s: &str) -> Result<(), Error> {
+    if is_ident(s) {
+        Ok(())
+    } else {
+        Err(Error::InvalidIdent(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_interface() -> Interface {
+        serde_json::from_value(serde_json::json!({
+            "imports": [{
+                "module": "test_arena",
+                "functions": [{
+                    "name": "alloc",
+                    "params": [
+                        { "name": "arena", "type": "&Resource<Arena>" },
+                        { "name": "capacity", "type": "usize" },
+                    ],
+                    "result": "Option<Resource<Bytes>>",
+                }, {
+                    "name": "inspect",
+                    "params": [{ "name": "value", "type": "Option<&Resource>" }],
+                }],
+            }],
+            "exports": [{
+                "name": "test",
+                "params": [{ "name": "arena", "type": "Resource<Arena>" }],
+                "result": "i64",
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parsing_types() {
+        let ty: ValueType = "usize".parse().unwrap();
+        assert_eq!(ty, ValueType::Primitive(PrimitiveType::Usize));
+
+        let ty: ValueType = "Option< &Resource<Sender> >".parse().unwrap();
+        assert_eq!(
+            ty,
+            ValueType::Resource {
+                kind: Some("Sender".to_owned()),
+                borrowed: true,
+                nullable: true,
+            }
+        );
+        assert_eq!(ty.to_string(), "Option<&Resource<Sender>>");
+
+        let ty: ValueType = "Resource<()>".parse().unwrap();
+        assert_eq!(ty.to_string(), "Resource<()>");
+
+        for invalid_ty in [
+            "u8",
+            "&usize",
+            "Option<usize>",
+            "Resource<a::B>",
+            "Box<Resource>",
+        ] {
+            let err = invalid_ty.parse::<ValueType>().unwrap_err();
+            assert_eq!(err, Error::InvalidType(invalid_ty.to_owned()));
+        }
+    }
+
+    #[test]
+    fn generating_guest_code() {
+        let code = sample_interface().guest_code().unwrap();
+        let expected = r#"// Generated by `externref` codegen; do not edit.
+
+use externref::{externref, Resource};
+
+/// `Arena` resource kind.
+pub struct Arena(());
+
+/// `Bytes` resource kind.
+pub struct Bytes(());
+
+#[externref]
+#[link(wasm_import_module = "test_arena")]
+extern "C" {
+    pub fn alloc(arena: &Resource<Arena>, capacity: usize) -> Option<Resource<Bytes>>;
+    pub fn inspect(value: Option<&Resource<()>>);
+}
+
+/// Functions exported from the module.
+pub trait Exports {
+    fn test(arena: Resource<Arena>) -> i64;
+}
+
+/// Exports functions implemented by the specified type via the `Exports` trait.
+/// Must be invoked in the module containing the generated code.
+#[allow(unused_macros)]
+macro_rules! export_interface {
+    ($ty:ty) => {
+        #[externref]
+        #[export_name = "test"]
+        pub extern "C" fn test(arena: Resource<Arena>) -> i64 {
+            <$ty as Exports>::test(arena)
+        }
+    };
+}
+"#;
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn generating_host_code() {
+        let code = sample_interface().host_code().unwrap();
+        let expected = r"// Generated by `externref` codegen; do not edit.
+
+/// Host-side implementation of functions imported from the `test_arena` module.
+pub trait TestArenaImports {
+    /// Host representation of `externref`s.
+    type Ref;
+
+    /// Implements the `test_arena::alloc` import.
+    fn alloc(&mut self, arena: Self::Ref, capacity: u32) -> Option<Self::Ref>;
+
+    /// Implements the `test_arena::inspect` import.
+    fn inspect(&mut self, value: Option<Self::Ref>);
+}
+";
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn invalid_interfaces() {
+        let mut interface = sample_interface();
+        interface.exports.push(interface.exports[0].clone());
+        let err = interface.validate().unwrap_err();
+        assert_eq!(
+            err,
+            Error::DuplicateFunction {
+                module: None,
+                name: "test".to_owned(),
+            }
+        );
+
+        let mut interface = sample_interface();
+        interface.imports[0].functions[0].name = "1st".to_owned();
+        let err = interface.guest_code().unwrap_err();
+        assert_eq!(err, Error::InvalidIdent("1st".to_owned()));
+
+        let mut interface = sample_interface();
+        interface.exports[0].params[0].name = "self".to_owned();
+        let err = interface.guest_code().unwrap_err();
+        assert_eq!(err, Error::InvalidIdent("self".to_owned()));
+
+        let mut interface = sample_interface();
+        interface.imports[0].module = "1".to_owned();
+        let err = interface.host_code().unwrap_err();
+        assert_eq!(err, Error::InvalidIdent("1Imports".to_owned()));
+
+        let err = serde_json::from_str::<Param>(r#"{ "name": "x", "type": "u8" }"#).unwrap_err();
+        assert!(err.to_string().contains("unsupported type `u8`"), "{err}");
+    }
+}
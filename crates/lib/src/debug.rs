@@ -0,0 +1,120 @@
+//! Tracking creation sites of live [`Resource`]s for leak hunting.
+//!
+//! If the `debug-tracking` crate feature is enabled, each non-null `Resource` obtained
+//! from the host remembers where it was created, until it is dropped. The creation site
+//! is the declaration of the imported function returning the resource, or of the exported
+//! function arg receiving it (for resources obtained via [`ResourceTable::get()`],
+//! [`GlobalResource::get()`] or [`SharedResource::to_resource()`], it's the caller
+//! of the corresponding method). Live resources can be listed via [`dump_live_resources()`].
+//!
+//! Converting a resource to a [`HostHandle`] and back retains the creation site, as well as
+//! [upcasting](Resource::upcast()) / downcasting. If the feature is disabled, this module
+//! is not available, and resources do not track anything.
+//!
+//! [`ResourceTable::get()`]: crate::ResourceTable::get()
+//! [`GlobalResource::get()`]: crate::GlobalResource::get()
+//! [`SharedResource::to_resource()`]: crate::SharedResource::to_resource()
+//! [`HostHandle`]: crate::HostHandle
+//!
+//! # Examples
+//!
+//! ```
+//! use externref::debug;
+//!
+//! // Check for leaked resources, e.g. at the end of an exported function.
+//! for resource in debug::dump_live_resources() {
+//!     eprintln!("{resource}");
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::Range,
+    panic::Location,
+    sync::{Mutex, PoisonError},
+    vec::Vec,
+};
+
+#[cfg(doc)]
+use crate::Resource;
+
+/// Creation sites of live resources keyed by the resource ID.
+static REGISTRY: Mutex<BTreeMap<usize, &'static Location<'static>>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn record(id: usize, location: &'static Location<'static>) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry.insert(id, location);
+}
+
+pub(crate) fn remove(id: usize) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry.remove(&id);
+}
+
+pub(crate) fn remove_range(ids: Range<usize>) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry.retain(|id, _| !ids.contains(id));
+}
+
+/// Information about a live [`Resource`] returned by [`dump_live_resources()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LiveResource {
+    /// Resource ID, i.e., the index of the resource in the `externref`s table.
+    pub id: usize,
+    /// Location where the resource was created.
+    pub location: &'static Location<'static>,
+}
+
+impl fmt::Display for LiveResource {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "resource #{} created at {}",
+            self.id, self.location
+        )
+    }
+}
+
+/// Returns all live resources tracked in this module instance, ordered by ID.
+pub fn dump_live_resources() -> Vec<LiveResource> {
+    let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry
+        .iter()
+        .map(|(&id, &location)| LiveResource { id, location })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExternRef, Resource};
+
+    #[test]
+    fn tracking_resources() {
+        let resource = unsafe { Resource::<()>::new_non_null(ExternRef(1_000), || -1) };
+        let line = line!() - 1;
+        let null_resource = unsafe { Resource::<()>::new_nullable(ExternRef(usize::MAX), || -1) };
+
+        let live_resources = dump_live_resources();
+        let live_resource = live_resources.iter().find(|res| res.id == 1_000).unwrap();
+        assert_eq!(live_resource.location.file(), file!());
+        assert_eq!(live_resource.location.line(), line);
+        assert!(live_resources.iter().all(|res| res.id != usize::MAX));
+        let display = live_resource.to_string();
+        assert!(
+            display.starts_with("resource #1000 created at "),
+            "{display}"
+        );
+
+        let handle = resource.upcast().into_host_handle();
+        let resource = Resource::from_host_handle(handle);
+        let live_resources = dump_live_resources();
+        assert!(live_resources.contains(live_resource));
+
+        drop((resource, null_resource));
+        let live_resources = dump_live_resources();
+        assert!(live_resources.iter().all(|res| res.id != 1_000));
+    }
+}
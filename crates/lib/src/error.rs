@@ -12,6 +12,17 @@ pub enum ReadErrorKind {
     UnexpectedEof,
     /// Error parsing
     Utf8(Utf8Error),
+    /// Unsupported version of a custom section entry. This can occur if the module was compiled
+    /// with a newer version of the `externref` crate than the one used for processing.
+    UnsupportedVersion(u32),
+    /// Length prefix of a string or a bit slice exceeds the maximum supported value.
+    /// Such lengths cannot be produced by this crate and indicate a malformed custom section.
+    OversizedLength {
+        /// Length read from the custom section.
+        len: u32,
+        /// Maximum supported length.
+        max: usize,
+    },
 }
 
 impl fmt::Display for ReadErrorKind {
@@ -19,6 +30,15 @@ impl fmt::Display for ReadErrorKind {
         match self {
             Self::UnexpectedEof => formatter.write_str("reached end of input"),
             Self::Utf8(err) => write!(formatter, "{err}"),
+            Self::UnsupportedVersion(version) => write!(
+                formatter,
+                "unsupported version {version}; the latest supported version is {}",
+                crate::Function::CUSTOM_SECTION_VERSION
+            ),
+            Self::OversizedLength { len, max } => write!(
+                formatter,
+                "length {len} exceeds the maximum supported value {max}"
+            ),
         }
     }
 }
@@ -42,6 +62,13 @@ pub struct ReadError {
     context: String,
 }
 
+impl ReadError {
+    /// Returns the kind of this error.
+    pub fn kind(&self) -> &ReadErrorKind {
+        &self.kind
+    }
+}
+
 impl fmt::Display for ReadError {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(formatter, "failed reading {}: {}", self.context, self.kind)
@@ -53,7 +80,42 @@ impl std::error::Error for ReadError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match &self.kind {
             ReadErrorKind::Utf8(err) => Some(err),
-            ReadErrorKind::UnexpectedEof => None,
+            ReadErrorKind::UnexpectedEof
+            | ReadErrorKind::UnsupportedVersion(_)
+            | ReadErrorKind::OversizedLength { .. } => None,
         }
     }
 }
+
+/// Error returned by [`Resource::lend()`] if the lent resource was invalidated by the host,
+/// i.e., its slot in the `externref`s table was nulled.
+///
+/// [`Resource::lend()`]: crate::Resource::lend()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LendError {
+    slot: usize,
+}
+
+impl LendError {
+    pub(crate) fn new(slot: usize) -> Self {
+        Self { slot }
+    }
+
+    /// Returns the 0-based index of the invalidated slot in the `externref`s table.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+}
+
+impl fmt::Display for LendError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "resource in slot #{} was invalidated by the host while lent",
+            self.slot
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LendError {}
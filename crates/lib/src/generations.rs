@@ -0,0 +1,142 @@
+//! Generation counters for detecting use of stale [`Resource`]s.
+//!
+//! Each resource ID (i.e., a slot in the `externref`s table) has a generation counter,
+//! which is incremented each time the reference in the slot is dropped by the module
+//! (including [`drop_all()`](crate::drop_all()) and [`Resource::drop_range()`]).
+//! A resource remembers the generation of its slot on creation and checks it each time
+//! it is passed to the host, dropped or converted into a [`HostHandle`](crate::HostHandle).
+//!
+//! WASM tables cannot contain `i32`s, so the counters are stored in linear memory on the guest
+//! side. The counters are thread-local, since each thread of a multi-threaded WASM app
+//! is a separate module instance with its own `externref`s table.
+
+use std::{cell::RefCell, vec::Vec};
+
+#[cfg(doc)]
+use crate::Resource;
+
+std::thread_local! {
+    /// Generations indexed by the resource ID. IDs outside the vector have the zero generation.
+    static GENERATIONS: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the current generation for the specified ID, making sure that the ID is tracked.
+pub(crate) fn record(id: usize) -> u32 {
+    GENERATIONS.with(|generations| {
+        let mut generations = generations.borrow_mut();
+        if generations.len() <= id {
+            generations.resize(id + 1, 0);
+        }
+        generations[id]
+    })
+}
+
+/// Returns the current generation for the specified ID without tracking it.
+pub(crate) fn current(id: usize) -> u32 {
+    GENERATIONS.with(|generations| generations.borrow().get(id).copied().unwrap_or(0))
+}
+
+/// Checks that a resource with the specified ID and generation is not stale.
+///
+/// # Panics
+///
+/// Panics if the resource is stale.
+#[track_caller]
+pub(crate) fn check(id: usize, generation: u32) {
+    let current = current(id);
+    assert!(
+        current == generation,
+        "stale resource #{id} (generation {generation}, current generation {current}): \
+         the slot was reused after the resource was dropped"
+    );
+}
+
+/// Marks the reference with the specified ID as dropped.
+pub(crate) fn bump(id: usize) {
+    GENERATIONS.with(|generations| {
+        if let Some(generation) = generations.borrow_mut().get_mut(id) {
+            *generation = generation.wrapping_add(1);
+        }
+    });
+}
+
+/// Marks references with IDs in the specified range as dropped.
+pub(crate) fn bump_range(start: usize, len: usize) {
+    GENERATIONS.with(|generations| {
+        let mut generations = generations.borrow_mut();
+        let start = start.min(generations.len());
+        let end = start.saturating_add(len).min(generations.len());
+        for generation in &mut generations[start..end] {
+            *generation = generation.wrapping_add(1);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{mem, ptr};
+
+    use super::*;
+    use crate::{drop_all, ExternRef, HostHandle, Resource};
+
+    /// Creates a bitwise copy of a resource, similar to one leaked via a `HostHandle`
+    /// restored twice.
+    fn leaked_copy<T>(resource: &Resource<T>) -> Resource<T> {
+        unsafe { ptr::read(resource) }
+    }
+
+    #[test]
+    fn generations_are_retained_by_conversions() {
+        let resource = Resource::<()>::from_id(5);
+        assert_eq!(resource.generation, 0);
+        drop(resource);
+        assert_eq!(current(5), 1);
+
+        let resource = Resource::<()>::from_id(5);
+        assert_eq!(resource.generation, 1);
+        let resource = unsafe { resource.downcast_unchecked::<ExternRef>() }.upcast();
+        assert_eq!(resource.generation, 1);
+        let handle = resource.into_host_handle();
+        let resource = Resource::from_host_handle(handle);
+        assert_eq!(resource.generation, 1);
+        assert_eq!(resource.checked_id(), 5);
+
+        mem::forget(resource);
+        unsafe { Resource::drop_range(4, 2) };
+        assert_eq!(current(5), 2);
+        unsafe { drop_all() };
+        assert_eq!(current(5), 3);
+        // Untracked IDs are not affected.
+        assert_eq!(current(1_000), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "stale resource #7 (generation 0, current generation 1)")]
+    fn using_stale_resource() {
+        let resource = Resource::<()>::from_id(7);
+        let stale = mem::ManuallyDrop::new(leaked_copy(&resource));
+        drop(resource);
+        let reused = mem::ManuallyDrop::new(Resource::<()>::from_id(7));
+        unsafe { Resource::raw(Some(&reused)) };
+        unsafe { Resource::raw(Some(&stale)) };
+    }
+
+    #[test]
+    #[should_panic(expected = "stale resource #3")]
+    fn dropping_stale_resource() {
+        let resource = Resource::<()>::from_id(3);
+        let stale = leaked_copy(&resource);
+        drop(resource);
+        drop(stale);
+    }
+
+    #[test]
+    fn null_resources_are_not_checked() {
+        let null = Resource::<()>::null();
+        assert_eq!(null.checked_id(), usize::MAX);
+        let handle = unsafe { HostHandle::<()>::from_index(9) };
+        let resource = Resource::from_host_handle(handle);
+        // The ID was never tracked, so its generation is zero.
+        assert_eq!(resource.generation, 0);
+    }
+}
@@ -0,0 +1,96 @@
+//! Global resource exposed to the host as a mutable `externref` global.
+
+use core::{fmt, marker::PhantomData};
+
+use crate::Resource;
+
+#[cfg(target_arch = "wasm32")]
+__surrogate_imports! {
+    #[link_name = "global_get"]
+    fn global_get() -> usize;
+    #[link_name = "global_set"]
+    fn global_set(id: usize);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn global_get() -> usize {
+    usize::MAX
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn global_set(_id: usize) {
+    // Do nothing
+}
+
+/// Resource stored in a mutable global with the `externref` type, e.g. a "current context"
+/// reference set by the host before calling into the module.
+///
+/// The [processor](crate::processor) adds the global to the module and exports it
+/// (by default, as `"global_resource"`), so that the host can read and write it directly.
+/// The global is unique per module, so all `GlobalResource` instances refer to the same global;
+/// the type param is only a hint on the resource type, similar to [`ResourceTable`](crate::ResourceTable).
+///
+/// # Examples
+///
+/// ```
+/// # use externref::{externref, GlobalResource, Resource};
+/// pub struct Context(());
+///
+/// static CONTEXT: GlobalResource<Context> = GlobalResource::new();
+///
+/// #[externref]
+/// #[link(wasm_import_module = "test")]
+/// extern "C" {
+///     fn log(context: &Resource<Context>, message: &str);
+/// }
+///
+/// fn log_with_context(message: &str) {
+///     if let Some(context) = CONTEXT.get() {
+///         unsafe { log(&context, message) };
+///     }
+/// }
+/// ```
+pub struct GlobalResource<T> {
+    _ty: PhantomData<fn(T)>,
+}
+
+impl<T> fmt::Debug for GlobalResource<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("GlobalResource").finish()
+    }
+}
+
+impl<T> Default for GlobalResource<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> GlobalResource<T> {
+    /// Creates a handle to the global.
+    pub const fn new() -> Self {
+        Self { _ty: PhantomData }
+    }
+
+    /// Gets the resource stored in the global. Returns `None` if the global is null
+    /// (e.g., if it was never set).
+    ///
+    /// The returned resource is a new copy of the reference in the global; dropping it
+    /// does not affect the global.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn get(&self) -> Option<Resource<T>> {
+        let id = unsafe { global_get() };
+        if id == usize::MAX {
+            None
+        } else {
+            Some(Resource::from_id(id))
+        }
+    }
+
+    /// Stores a copy of the `resource` reference in the global, or nulls the global
+    /// if `resource` is `None`. The resource itself remains usable and should be dropped as usual.
+    pub fn set(&self, resource: Option<&Resource<T>>) {
+        let id = resource.map_or(usize::MAX, Resource::id);
+        unsafe { global_set(id) };
+    }
+}
@@ -0,0 +1,595 @@
+//! Host-side utilities for inspecting the `externref`s table of a processed WASM module.
+//!
+//! The utilities are runtime-agnostic: a [`TableSnapshot`] is built from the table slots
+//! mapped to host-defined *kinds* of references (e.g., type names of the data behind
+//! the references). Null slots are represented as `None`.
+//!
+//! Host data behind references can implement the [`HostResource`] trait (which can be derived
+//! if the `macro` crate feature is enabled). A [`ResourceRegistry`] of such types provides
+//! glue for table snapshots and the drop hook independently of the WASM runtime.
+//!
+//! Additionally, [`SharedRegistry`] can back the host imports used by
+//! [`SharedResource`](crate::SharedResource)s.
+//!
+//! # Examples
+//!
+//! ```
+//! use externref::host::{SlotChange, TableSnapshot};
+//!
+//! // In real tests, snapshots are built from an instantiated WASM module,
+//! // e.g. by mapping each table element to the type of the referenced host data.
+//! let before = TableSnapshot::new([Some("sender"), None]);
+//! let after = TableSnapshot::new([Some("sender"), Some("bytes"), Some("bytes")]);
+//!
+//! let diff = TableSnapshot::diff(&before, &after);
+//! assert_eq!(diff.len(), 2);
+//! assert_eq!(diff.changes()[0], (1, SlotChange::Added { kind: &"bytes" }));
+//! println!("{diff}");
+//!
+//! // Snapshots can be compared with human-readable panic messages.
+//! after.assert_matches(&TableSnapshot::new([Some("sender"), Some("bytes"), Some("bytes")]));
+//! ```
+
+use std::{
+    any::{Any, TypeId},
+    fmt,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// Derives [`HostResource`] for a type.
+///
+/// By default, the kind name is the name of the type. It can be overridden via
+/// `#[host_resource(kind = "name")]`, e.g. to match the name of the resource type
+/// used in the WASM module. Additionally, the following attributes are supported:
+///
+/// - `#[host_resource(on_drop = "path::to::function")]` specifies a function called
+///   from [`HostResource::on_drop()`]. The function must accept `&Self`.
+/// - `#[host_resource(crate = "path::to::externref")]` specifies the path to the `externref` crate.
+///
+/// # Examples
+///
+/// ```
+/// use externref::host::HostResource;
+///
+/// #[derive(HostResource)]
+/// #[host_resource(kind = "Sender", on_drop = "close")]
+/// struct HostSender {
+///     channel: String,
+/// }
+///
+/// fn close(sender: &HostSender) {
+///     println!("closing {}", sender.channel);
+/// }
+///
+/// assert_eq!(HostSender::KIND, "Sender");
+/// ```
+#[cfg(feature = "macro")]
+#[cfg_attr(docsrs, doc(cfg(feature = "macro")))]
+pub use externref_macro::HostResource;
+
+/// Snapshot of the `externref`s table with each slot mapped to a host-defined kind
+/// of the reference. Null slots are represented as `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSnapshot<K> {
+    slots: Vec<Option<K>>,
+}
+
+impl<K> TableSnapshot<K> {
+    /// Creates a snapshot from the provided slots.
+    pub fn new(slots: impl IntoIterator<Item = Option<K>>) -> Self {
+        Self {
+            slots: slots.into_iter().collect(),
+        }
+    }
+
+    /// Returns the slots in this snapshot.
+    pub fn slots(&self) -> &[Option<K>] {
+        &self.slots
+    }
+
+    /// Returns the number of slots (i.e., the table size) in this snapshot.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Checks whether this snapshot has no slots.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the number of live (non-null) slots in this snapshot.
+    pub fn live_count(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+}
+
+impl<K: PartialEq> TableSnapshot<K> {
+    /// Computes the difference between two snapshots. Slots beyond the size of a snapshot
+    /// are considered to be null.
+    pub fn diff<'a>(before: &'a Self, after: &'a Self) -> TableDiff<'a, K> {
+        let len = before.len().max(after.len());
+        let changes = (0..len).filter_map(|idx| {
+            let old = before.slots.get(idx).and_then(Option::as_ref);
+            let new = after.slots.get(idx).and_then(Option::as_ref);
+            let change = match (old, new) {
+                (None, Some(kind)) => SlotChange::Added { kind },
+                (Some(kind), None) => SlotChange::Removed { kind },
+                (Some(from), Some(to)) if from != to => SlotChange::Changed { from, to },
+                _ => return None,
+            };
+            Some((idx, change))
+        });
+
+        TableDiff {
+            before_len: before.len(),
+            after_len: after.len(),
+            changes: changes.collect(),
+        }
+    }
+}
+
+impl<K: PartialEq + fmt::Debug> TableSnapshot<K> {
+    /// Asserts that this snapshot matches the `expected` one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the snapshots differ. The panic message contains the human-readable diff
+    /// between the snapshots.
+    #[track_caller]
+    pub fn assert_matches(&self, expected: &Self) {
+        if self != expected {
+            let diff = Self::diff(expected, self);
+            panic!("table snapshot differs from the expected one\n{diff}\nactual: {self}");
+        }
+    }
+}
+
+impl<K: fmt::Debug> fmt::Display for TableSnapshot<K> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("[")?;
+        for (idx, slot) in self.slots.iter().enumerate() {
+            if idx > 0 {
+                formatter.write_str(", ")?;
+            }
+            match slot {
+                Some(kind) => write!(formatter, "{idx}: {kind:?}")?,
+                None => write!(formatter, "{idx}: null")?,
+            }
+        }
+        formatter.write_str("]")
+    }
+}
+
+/// Change of a single slot in a [`TableDiff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotChange<'a, K> {
+    /// A reference was added to a previously null slot.
+    Added {
+        /// Kind of the added reference.
+        kind: &'a K,
+    },
+    /// A reference was removed from the slot.
+    Removed {
+        /// Kind of the removed reference.
+        kind: &'a K,
+    },
+    /// A reference in the slot was replaced with a reference of another kind.
+    Changed {
+        /// Kind of the previous reference.
+        from: &'a K,
+        /// Kind of the new reference.
+        to: &'a K,
+    },
+}
+
+impl<K: fmt::Debug> fmt::Display for SlotChange<'_, K> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added { kind } => write!(formatter, "added {kind:?}"),
+            Self::Removed { kind } => write!(formatter, "removed {kind:?}"),
+            Self::Changed { from, to } => write!(formatter, "changed {from:?} -> {to:?}"),
+        }
+    }
+}
+
+/// Difference between two [`TableSnapshot`]s returned by [`TableSnapshot::diff()`].
+#[derive(Debug, Clone)]
+pub struct TableDiff<'a, K> {
+    before_len: usize,
+    after_len: usize,
+    changes: Vec<(usize, SlotChange<'a, K>)>,
+}
+
+impl<'a, K> TableDiff<'a, K> {
+    /// Returns slot changes together with 0-based slot indices, ordered by the index.
+    pub fn changes(&self) -> &[(usize, SlotChange<'a, K>)] {
+        &self.changes
+    }
+
+    /// Returns the number of changed slots.
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Checks whether the snapshots have the same set of live slots and the same table size.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.before_len == self.after_len
+    }
+}
+
+impl<K: fmt::Debug> fmt::Display for TableDiff<'_, K> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.before_len != self.after_len {
+            writeln!(
+                formatter,
+                "table size: {} -> {}",
+                self.before_len, self.after_len
+            )?;
+        }
+        if self.changes.is_empty() {
+            return formatter.write_str("no slot changes");
+        }
+        for (i, (idx, change)) in self.changes.iter().enumerate() {
+            if i > 0 {
+                writeln!(formatter)?;
+            }
+            write!(formatter, "slot #{idx}: {change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Host data behind references passed to a WASM module.
+///
+/// The trait is runtime-agnostic; it should be used together with a [`ResourceRegistry`]
+/// which dispatches type-erased data obtained from the runtime (e.g., via `wasmtime::ExternRef::data()`)
+/// to the implementations. The trait can be derived if the `macro` crate feature is enabled.
+pub trait HostResource: Any {
+    /// Name of the resource kind. To be matched with the kinds assigned by the processor,
+    /// this should be equal to the name of the resource type in the module (e.g., `Sender`
+    /// for `Resource<Sender>`), or to the table partition specified via `#[externref(table = ..)]`.
+    const KIND: &'static str;
+
+    /// Returns the ID of this resource kind among the resource `kinds` of a processed module,
+    /// e.g. ones listed in `Manifest::resource_kinds`. This ID is passed to the drop hook
+    /// with kinds. Returns `None` if the kind is not used by the module.
+    fn kind_id(kinds: &[impl AsRef<str>]) -> Option<i32>
+    where
+        Self: Sized,
+    {
+        let idx = kinds.iter().position(|kind| kind.as_ref() == Self::KIND)?;
+        i32::try_from(idx).ok()
+    }
+
+    /// Called when the WASM module notifies the host that it has dropped a reference
+    /// to this resource (e.g., from the drop hook). No-op by default.
+    fn on_drop(&self) {
+        // Do nothing
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ResourceEntry {
+    type_id: TypeId,
+    kind: &'static str,
+    on_drop: fn(&dyn Any),
+}
+
+impl fmt::Debug for ResourceEntry {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ResourceEntry")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registry of [`HostResource`] types dispatching type-erased host data to them.
+///
+/// # Examples
+///
+/// ```
+/// # use std::any::Any;
+/// use externref::host::{HostResource, ResourceRegistry, TableSnapshot};
+///
+/// struct Sender;
+///
+/// impl HostResource for Sender {
+///     const KIND: &'static str = "Sender";
+/// }
+///
+/// let mut registry = ResourceRegistry::default();
+/// registry.register::<Sender>();
+/// // In real code, slots are read from the `externrefs` table of a WASM instance.
+/// let slots: [Option<&dyn Any>; 3] = [Some(&Sender), None, Some(&42_u32)];
+/// let snapshot = registry.snapshot(slots);
+/// assert_eq!(
+///     snapshot,
+///     TableSnapshot::new([Some("Sender"), None, Some(ResourceRegistry::UNKNOWN_KIND)])
+/// );
+/// // Called from the drop hook.
+/// assert!(registry.notify_drop(&Sender));
+/// ```
+#[derive(Debug, Default)]
+pub struct ResourceRegistry {
+    entries: Vec<ResourceEntry>,
+}
+
+impl ResourceRegistry {
+    /// Kind reported for data not registered in the registry.
+    pub const UNKNOWN_KIND: &'static str = "(unknown)";
+
+    /// Registers the resource type. Registering a type repeatedly is a no-op.
+    pub fn register<T: HostResource>(&mut self) -> &mut Self {
+        let type_id = TypeId::of::<T>();
+        if !self.entries.iter().any(|entry| entry.type_id == type_id) {
+            self.entries.push(ResourceEntry {
+                type_id,
+                kind: T::KIND,
+                on_drop: |data| {
+                    if let Some(data) = data.downcast_ref::<T>() {
+                        data.on_drop();
+                    }
+                },
+            });
+        }
+        self
+    }
+
+    fn entry(&self, data: &dyn Any) -> Option<&ResourceEntry> {
+        let type_id = data.type_id();
+        self.entries.iter().find(|entry| entry.type_id == type_id)
+    }
+
+    /// Returns the kind of the provided data, or `None` if the data type is not registered.
+    pub fn kind_of(&self, data: &dyn Any) -> Option<&'static str> {
+        self.entry(data).map(|entry| entry.kind)
+    }
+
+    /// Creates a table snapshot from the data behind the table slots. Data of unregistered types
+    /// is mapped to [`Self::UNKNOWN_KIND`].
+    pub fn snapshot<'a>(
+        &self,
+        slots: impl IntoIterator<Item = Option<&'a dyn Any>>,
+    ) -> TableSnapshot<&'static str> {
+        let slots = slots
+            .into_iter()
+            .map(|data| Some(self.kind_of(data?).unwrap_or(Self::UNKNOWN_KIND)));
+        TableSnapshot::new(slots)
+    }
+
+    /// Validates a [`HostHandle`](crate::HostHandle) index deserialized by the module.
+    /// This is intended to implement the `validate_handle` import from the
+    /// [`HostHandle::SERDE_MODULE_NAME`](crate::HostHandle::SERDE_MODULE_NAME) module;
+    /// `data` is the host data behind the table slot with the index, or `None` if the slot
+    /// is null or out of bounds. Returns 1 if the slot contains data of a registered type,
+    /// and 0 otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::any::Any;
+    /// # use externref::host::{HostResource, ResourceRegistry};
+    /// struct Sender;
+    ///
+    /// impl HostResource for Sender {
+    ///     const KIND: &'static str = "Sender";
+    /// }
+    ///
+    /// let mut registry = ResourceRegistry::default();
+    /// registry.register::<Sender>();
+    /// // In real code, slots are read from the `externrefs` table of a WASM instance.
+    /// let slots: [Option<&dyn Any>; 2] = [Some(&Sender), None];
+    /// let validate_handle = |index: u32| {
+    ///     let data = slots.get(index as usize).copied().flatten();
+    ///     registry.validate_handle(data)
+    /// };
+    /// assert_eq!(validate_handle(0), 1);
+    /// assert_eq!(validate_handle(1), 0);
+    /// assert_eq!(validate_handle(2), 0);
+    /// ```
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub fn validate_handle(&self, data: Option<&dyn Any>) -> i32 {
+        i32::from(data.is_some_and(|data| self.entry(data).is_some()))
+    }
+
+    /// Calls [`HostResource::on_drop()`] for the provided data. Returns `false` if the data type
+    /// is not registered.
+    pub fn notify_drop(&self, data: &dyn Any) -> bool {
+        if let Some(entry) = self.entry(data) {
+            (entry.on_drop)(data);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Thread-safe registry of host data behind [`SharedResource`](crate::SharedResource)s.
+/// The registry maps handles passed to the module to the data, e.g., the data behind `externref`s
+/// or the `externref`s themselves if the runtime allows sharing them among instances.
+///
+/// Handles of removed values are reused.
+///
+/// # Examples
+///
+/// ```
+/// use externref::host::SharedRegistry;
+///
+/// let registry = SharedRegistry::default();
+/// // Called from the `externref_shared::share` import.
+/// let handle = registry.insert("sender");
+/// // Called from the `externref_shared::get` import.
+/// assert_eq!(registry.get(handle), Some("sender"));
+/// // Called from the `externref_shared::drop` import.
+/// assert_eq!(registry.remove(handle), Some("sender"));
+/// assert!(registry.is_empty());
+/// ```
+#[derive(Debug)]
+pub struct SharedRegistry<T> {
+    slots: Mutex<Vec<Option<T>>>,
+}
+
+impl<T> Default for SharedRegistry<T> {
+    fn default() -> Self {
+        Self {
+            slots: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl<T> SharedRegistry<T> {
+    // Registry operations cannot panic midway, so the slots are consistent even if
+    // the mutex is poisoned.
+    fn lock(&self) -> MutexGuard<'_, Vec<Option<T>>> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Inserts the value into the registry and returns its handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values exceeds `u32::MAX`.
+    pub fn insert(&self, value: T) -> u32 {
+        let mut slots = self.lock();
+        let idx = if let Some(idx) = slots.iter().position(Option::is_none) {
+            slots[idx] = Some(value);
+            idx
+        } else {
+            slots.push(Some(value));
+            slots.len() - 1
+        };
+        u32::try_from(idx).expect("too many shared values")
+    }
+
+    /// Gets a copy of the value with the specified handle.
+    pub fn get(&self, handle: u32) -> Option<T>
+    where
+        T: Clone,
+    {
+        let slots = self.lock();
+        slots.get(handle as usize)?.clone()
+    }
+
+    /// Removes the value with the specified handle from the registry.
+    pub fn remove(&self, handle: u32) -> Option<T> {
+        let mut slots = self.lock();
+        slots.get_mut(handle as usize)?.take()
+    }
+
+    /// Returns the number of values in the registry.
+    pub fn len(&self) -> usize {
+        let slots = self.lock();
+        slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Checks whether the registry is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffing_snapshots() {
+        let before = TableSnapshot::new([Some("sender"), Some("bytes"), Some("bytes")]);
+        let after = TableSnapshot::new([Some("sender"), None, Some("sender"), Some("bytes")]);
+        let diff = TableSnapshot::diff(&before, &after);
+
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff.changes(),
+            [
+                (1, SlotChange::Removed { kind: &"bytes" }),
+                (
+                    2,
+                    SlotChange::Changed {
+                        from: &"bytes",
+                        to: &"sender"
+                    }
+                ),
+                (3, SlotChange::Added { kind: &"bytes" }),
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "table size: 3 -> 4\n\
+             slot #1: removed \"bytes\"\n\
+             slot #2: changed \"bytes\" -> \"sender\"\n\
+             slot #3: added \"bytes\""
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct Counter(std::cell::Cell<usize>);
+
+    impl HostResource for Counter {
+        const KIND: &'static str = "Counter";
+
+        fn on_drop(&self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn registering_host_resources() {
+        let mut registry = ResourceRegistry::default();
+        registry.register::<Counter>().register::<Counter>();
+        assert_eq!(registry.entries.len(), 1);
+
+        let counter = Counter::default();
+        assert_eq!(registry.kind_of(&counter), Some("Counter"));
+        assert_eq!(registry.kind_of(&"test"), None);
+        assert!(registry.notify_drop(&counter));
+        assert!(registry.notify_drop(&counter));
+        assert!(!registry.notify_drop(&"test"));
+        assert_eq!(counter.0.get(), 2);
+
+        assert_eq!(Counter::kind_id(&["Bytes", "Counter"]), Some(1));
+        assert_eq!(Counter::kind_id(&["Bytes"]), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn validating_handles() {
+        let mut registry = ResourceRegistry::default();
+        registry.register::<Counter>();
+        assert_eq!(registry.validate_handle(Some(&Counter::default())), 1);
+        assert_eq!(registry.validate_handle(Some(&"test")), 0);
+        assert_eq!(registry.validate_handle(None), 0);
+    }
+
+    #[test]
+    fn diffing_same_snapshots() {
+        let snapshot = TableSnapshot::new([Some(1), None]);
+        let diff = TableSnapshot::diff(&snapshot, &snapshot);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no slot changes");
+    }
+
+    #[test]
+    #[should_panic(expected = "slot #1: removed")]
+    fn asserting_snapshots() {
+        let snapshot = TableSnapshot::new([Some(1), None]);
+        snapshot.assert_matches(&TableSnapshot::new([Some(1), Some(2)]));
+    }
+
+    #[test]
+    fn reusing_shared_handles() {
+        let registry = SharedRegistry::default();
+        let first = registry.insert("first");
+        let second = registry.insert("second");
+        assert_ne!(first, second);
+        assert_eq!(registry.len(), 2);
+
+        assert_eq!(registry.remove(first), Some("first"));
+        assert_eq!(registry.remove(first), None);
+        assert_eq!(registry.get(first), None);
+        assert_eq!(registry.insert("third"), first);
+        assert_eq!(registry.get(second), Some("second"));
+    }
+}
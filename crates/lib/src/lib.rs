@@ -34,9 +34,15 @@
 //!
 //! 1. Use [`Resource`]s as arguments / return results for imported and/or exported functions
 //!    in a WASM module in place of `externref`s . Reference args (including mutable references)
-//!    and the `Option<_>` wrapper are supported as well.
+//!    and the `Option<_>` wrapper are supported as well. Exported functions may also return
+//!    `Result<Resource<_>, E>` where `E: Into<i32>`; such functions will return
+//!    an `(externref, i32)` pair with the second value being the error code (0 on success).
+//!    Similarly, exported functions returning `Option<Resource<_>>` and marked
+//!    with `#[resource(nullable = "flag")]` return an `(externref, i32)` pair with the second
+//!    value signalling whether the resource is present.
 //! 2. Add the `#[externref]` proc macro on the imported / exported functions.
-//! 3. Post-process the generated WASM module with the [`processor`].
+//! 3. Post-process the generated WASM module with the [`processor`]. For the default
+//!    processing options, use [`processor::process_bytes()`].
 //!
 //! `Resource`s support primitive downcasting and upcasting with `Resource<()>` signalling
 //! a generic resource. Downcasting is *unchecked*; it is up to the `Resource` users to
@@ -54,7 +60,7 @@
 //! is recorded in a WASM custom section.
 //!
 //! To handle `usize` (~`i32` in WASM) <-> `externref` conversions, managing resources is performed
-//! using 3 function imports from a surrogate module:
+//! using 3 function imports from a surrogate module ([`SURROGATE_MODULE`]):
 //!
 //! - Creating a `Resource` ("real" signature `fn(externref) -> usize`) stores a reference
 //!   into an `externref` table and returns the table index. The index is what is actually
@@ -105,14 +111,127 @@
 //!
 //! *(Off by default)*
 //!
-//! Enables WASM module processing via the [`processor`] module. Requires the `std` feature.
+//! Enables WASM module processing via the [`processor`] module.
+//!
+//! This feature does not enable `std` by itself. Without the `std` feature, file processing,
+//! [`PostOptimizer`](processor::PostOptimizer)s (which run external tools),
+//! [`Transform`](processor::Transform) and the [`Error`](std::error::Error) implementation
+//! for processing errors are not available.
+//!
+//! Processing still cannot be performed on `no_std` targets: the processor is built on top
+//! of [`walrus`](https://docs.rs/walrus/), which requires the standard library. If processing
+//! needs to be sandboxed, consider compiling it for a WASI target (e.g., `wasm32-wasip1`),
+//! which provides `std`.
+//!
+//! ## `host`
+//!
+//! *(Off by default)*
+//!
+//! Enables host-side utilities for testing processed modules via the [`host`] module,
+//! such as snapshotting and diffing the `externref`s table. Requires the `std` feature.
+//!
+//! ## `testing`
+//!
+//! *(Off by default)*
+//!
+//! Enables a mock host for executing processed modules in tests via the [`testing`] module.
+//! Requires the `processor` feature.
+//!
+//! ## `serde`
+//!
+//! *(Off by default)*
+//!
+//! Implements [`Serialize`](::serde::Serialize) / [`Deserialize`](::serde::Deserialize)
+//! for [`HostHandle`]s, e.g. to persist module state referencing host objects.
+//! See the `HostHandle` docs for details.
+//!
+//! ## `dwarf`
+//!
+//! *(Off by default)*
+//!
+//! Resolves source locations for [processing](processor) errors caused by misplaced
+//! instructions from DWARF debug info in the processed module. Requires the `processor` feature.
+//!
+//! ## `miette`
+//!
+//! *(Off by default)*
+//!
+//! Implements [`miette::Diagnostic`](https://docs.rs/miette/) for [processing errors](processor::Error),
+//! providing error codes and help text. [`Error::into_report()`](processor::Error::into_report())
+//! additionally renders the offending function in the WebAssembly text format with the offending
+//! instruction labeled. Requires the `processor` feature.
+//!
+//! ## `wat`
+//!
+//! *(Off by default)*
+//!
+//! Enables processing modules in the WebAssembly text format via
+//! [`Processor::process_wat()`](processor::Processor::process_wat()).
+//! Requires the `processor` feature.
+//!
+//! ## `capi`
+//!
+//! *(Off by default)*
+//!
+//! Provides a stable C ABI for processing modules from non-Rust build systems
+//! via the [`processor::capi`] module. Requires the `processor` feature.
+//!
+//! ## `test-util`
+//!
+//! *(Off by default)*
+//!
+//! Enables generating random modules for fuzzing the processor and checking invariants
+//! of the generated table functions via the [`processor::test_util`] module.
+//! Requires the `processor` feature.
+//!
+//! ## `test-fixtures`
+//!
+//! *(Off by default)*
+//!
+//! Exposes the corpus of WAT modules used in processor tests via the [`processor::fixtures`]
+//! module, and golden-output testing helpers via the [`processor::golden`] module,
+//! so that forks of the processor and [hooks](processor::ProcessingHook) can be regression-tested
+//! against the same corpus. Requires the `wat` feature.
+//!
+//! ## `codegen`
+//!
+//! *(Off by default)*
+//!
+//! Enables generating `#[externref]` declarations and host-side traits from an interface
+//! description via the [`codegen`] module. Requires the `std` feature.
+//!
+//! ## `debug-tracking`
+//!
+//! *(Off by default)*
+//!
+//! Records creation sites of live [`Resource`]s, which can be listed via the [`debug`] module
+//! to hunt down resource leaks. Requires the `std` feature. Has no overhead if disabled.
+//!
+//! ## `cache-teardown`
+//!
+//! *(Off by default)*
+//!
+//! Exports the `__externref_teardown` function from the WASM module, which drops resources
+//! [cached](Resource::cached()) by the module. The host can call it before discarding
+//! the module instance.
+//!
+//! ## `generations`
+//!
+//! *(Off by default)*
+//!
+//! Tracks a generation counter for each slot of the `externref`s table, which is incremented
+//! when the reference in the slot is dropped. Each [`Resource`] stores the generation
+//! of its slot in addition to the slot index, and panics if it is used (passed to the host,
+//! dropped or converted into a [`HostHandle`]) after the slot was freed, e.g. if it was not
+//! forgotten after [`drop_all()`]. Requires the `std` feature. If disabled, resources
+//! are pointer-sized, and no checks are performed.
 //!
 //! ## `tracing`
 //!
 //! *(Off by default)*
 //!
 //! Enables tracing during [module processing](processor) with the [`tracing`] facade.
-//! Tracing events / spans mostly use `INFO` and `DEBUG` levels.
+//! Tracing events / spans mostly use `INFO` and `DEBUG` levels. Requires the `std` feature.
 //!
 //! [`tracing`]: https://docs.rs/tracing/
 //!
@@ -173,35 +292,86 @@
     clippy::inline_always
 )]
 
-use core::{alloc::Layout, fmt, marker::PhantomData, mem, ptr};
+use core::{
+    alloc::Layout,
+    fmt,
+    marker::PhantomData,
+    mem,
+    num::NonZeroUsize,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
 
 #[cfg(feature = "macro")]
 #[cfg_attr(docsrs, doc(cfg(feature = "macro")))]
 pub use externref_macro::externref;
 
 pub use crate::{
-    error::{ReadError, ReadErrorKind},
-    signature::{BitSlice, BitSliceBuilder, Function, FunctionKind},
+    arena::{Drain, ResourceArena, ResourceSlot},
+    error::{LendError, ReadError, ReadErrorKind},
+    global::GlobalResource,
+    shared::SharedResource,
+    signature::{BitSlice, BitSliceBuf, BitSliceBuilder, Function, FunctionKind, TypeNames},
+    table::ResourceTable,
 };
 
+// Defines `SURROGATE_MODULE` and the `__surrogate_imports!` macro; see the build script.
+// Must be included before the modules using the macro.
+include!(concat!(env!("OUT_DIR"), "/surrogate.rs"));
+
+mod arena;
+mod cache;
+#[cfg(feature = "codegen")]
+#[cfg_attr(docsrs, doc(cfg(feature = "codegen")))]
+pub mod codegen;
+#[cfg(feature = "debug-tracking")]
+#[cfg_attr(docsrs, doc(cfg(feature = "debug-tracking")))]
+pub mod debug;
 mod error;
+#[cfg(feature = "generations")]
+mod generations;
+mod global;
+#[cfg(feature = "host")]
+#[cfg_attr(docsrs, doc(cfg(feature = "host")))]
+pub mod host;
 #[cfg(feature = "processor")]
 #[cfg_attr(docsrs, doc(cfg(feature = "processor")))]
 pub mod processor;
+#[cfg(feature = "serde")]
+mod serde_impls;
+mod shared;
 mod signature;
+mod table;
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod testing;
+pub mod wrappers;
 
 // Polyfill for `alloc` types.
 mod alloc {
     #[cfg(not(feature = "std"))]
     extern crate alloc as std;
 
-    pub(crate) use std::{format, string::String};
+    #[cfg(all(feature = "processor", feature = "std"))]
+    pub(crate) use std::collections::{hash_map, HashMap, HashSet};
+    #[cfg(feature = "processor")]
+    pub(crate) use std::{
+        borrow::{Cow, ToOwned},
+        collections::{BTreeMap, BTreeSet},
+        string::ToString,
+    };
+    pub(crate) use std::{boxed::Box, format, string::String, vec, vec::Vec};
+
+    #[cfg(all(feature = "processor", not(feature = "std")))]
+    pub(crate) use hashbrown::{hash_map, HashMap, HashSet};
 }
 
 /// `externref` surrogate.
 ///
 /// The post-processing logic replaces variables of this type with real `externref`s.
-#[doc(hidden)] // should only be used by macro-generated code
+/// Values of this type cannot be created or cloned by the user code; they are only obtained
+/// from the host (as args of exported functions or return values of imported functions)
+/// or via the [`wrappers`] module.
 #[repr(transparent)]
 pub struct ExternRef(usize);
 
@@ -221,8 +391,7 @@ impl ExternRef {
     #[inline(always)]
     pub unsafe fn guard() {
         #[cfg(target_arch = "wasm32")]
-        #[link(wasm_import_module = "externref")]
-        extern "C" {
+        __surrogate_imports! {
             #[link_name = "guard"]
             fn guard();
         }
@@ -230,11 +399,32 @@ impl ExternRef {
         #[cfg(target_arch = "wasm32")]
         guard();
     }
+
+    /// Sets the error code for a fallible exported function (i.e., one returning
+    /// `Result<Resource<_>, E>`), or the presence flag for a function returning
+    /// `Option<Resource<_>>` marked with `#[resource(nullable = "flag")]`. The processor places
+    /// this code into the second return value of the function.
+    ///
+    /// # Safety
+    ///
+    /// This method should only be called by the code generated by the `externref` macro.
+    #[inline(always)]
+    pub unsafe fn set_error_code(code: i32) {
+        #[cfg(target_arch = "wasm32")]
+        __surrogate_imports! {
+            #[link_name = "set_error_code"]
+            fn set_error_code(code: i32);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        set_error_code(code);
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = code;
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
-#[link(wasm_import_module = "externref")]
-extern "C" {
+__surrogate_imports! {
     #[link_name = "get"]
     fn get_externref(id: usize) -> ExternRef;
 }
@@ -245,31 +435,158 @@ unsafe fn get_externref(id: usize) -> ExternRef {
 }
 
 #[cfg(target_arch = "wasm32")]
-#[link(wasm_import_module = "externref")]
-extern "C" {
+__surrogate_imports! {
     #[link_name = "insert"]
-    fn insert_externref(id: ExternRef) -> usize;
+    fn insert_externref(id: ExternRef, kind: i32) -> usize;
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 #[allow(clippy::needless_pass_by_value)]
-unsafe fn insert_externref(id: ExternRef) -> usize {
+unsafe fn insert_externref(id: ExternRef, _kind: i32) -> usize {
     id.0
 }
 
+#[cfg(target_arch = "wasm32")]
+__surrogate_imports! {
+    #[link_name = "is_live"]
+    fn is_live_externref(id: usize) -> i32;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn is_live_externref(_id: usize) -> i32 {
+    1
+}
+
+/// Produces a closure returning the kind ID of the resource type with the specified name.
+/// Kind IDs are assigned by the [`processor`] based on the names of surrogate imports.
+///
+/// The produced closure should be passed to the `lift*()` functions from the [`wrappers`] module.
+/// Outside WASM, the closure returns -1.
+///
+/// # Examples
+///
+/// ```
+/// # use externref::resource_kind;
+/// let kind = resource_kind!("Sender");
+/// # #[cfg(not(target_arch = "wasm32"))]
+/// assert_eq!(kind(), -1);
+/// ```
+#[macro_export]
+macro_rules! resource_kind {
+    ($name:tt) => {{
+        #[cfg(target_arch = "wasm32")]
+        $crate::__surrogate_imports! {
+            #[link_name = concat!("kind::", $name)]
+            fn resource_kind() -> i32;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        unsafe fn resource_kind() -> i32 {
+            -1
+        }
+
+        || unsafe { resource_kind() }
+    }};
+}
+
+/// ID of a [`Resource`] packed so that `Option<Resource<_>>` has the same size as `Resource<_>`.
+///
+/// The ID is stored offset by 2 (with wraparound), so that the null ID (`usize::MAX`) is stored
+/// as 1, and the niche (0) corresponds to the ID `usize::MAX - 1`. The latter is never a valid
+/// index in the `externref`s table, since the table cannot contain `usize::MAX` references.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+struct PackedId(NonZeroUsize);
+
+impl fmt::Debug for PackedId {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.get(), formatter)
+    }
+}
+
+impl PackedId {
+    /// Packs the specified ID.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id == usize::MAX - 1`.
+    #[inline(always)]
+    const fn new(id: usize) -> Self {
+        match NonZeroUsize::new(id.wrapping_add(2)) {
+            Some(packed) => Self(packed),
+            None => panic!("`externref`s table overflow"),
+        }
+    }
+
+    #[inline(always)]
+    const fn get(self) -> usize {
+        self.0.get().wrapping_sub(2)
+    }
+}
+
 /// Host resource exposed to WASM.
 ///
 /// Internally, a resource is just an index into the `externref`s table; thus, it is completely
 /// valid to store `Resource`s on heap (in a `Vec`, thread-local storage, etc.). The type param
-/// can be used for type safety.
+/// can be used for type safety. The index is packed so that `Option<Resource<T>>` has the same
+/// size as `Resource<T>` (i.e., `usize`). If the `generations` crate feature is enabled,
+/// resources additionally store the generation of their table slot to detect stale resources.
+///
+/// A resource may be [null](Self::null()), i.e., not refer to any host object. This is useful
+/// to represent "no resource" where `Option<Resource<T>>` is inconvenient (e.g., in fixed-size
+/// arrays). Null resources are passed to the host as null `externref`s. Nullable args
+/// of exported functions processed by the [`externref`](macro@externref) macro must be marked
+/// with `#[resource(nullable)]`; otherwise, passing a null `externref` will panic.
+///
+/// # Typestates
+///
+/// The optional `S` type param encodes the state of the resource, which allows expressing
+/// the host object lifecycle in the type system. State transitions are performed
+/// by host imports consuming the resource in one state and returning it in another one.
+/// The state is purely phantom: resources in all states share the same resource kind (i.e.,
+/// the last path segment of `T`) and the same representation. Resource containers
+/// (e.g., [`ResourceTable`] or [`HostHandle`]) only support resources in the default `()` state.
+///
+/// ```
+/// # use externref::{externref, Resource};
+/// pub struct File(());
+/// pub struct Open;
+/// pub struct Closed;
+///
+/// #[externref]
+/// #[link(wasm_import_module = "fs")]
+/// extern "C" {
+///     fn open_file(path_ptr: *const u8, path_len: usize) -> Resource<File, Open>;
+///     fn read_byte(file: &Resource<File, Open>) -> i32;
+///     fn close_file(file: Resource<File, Open>) -> Resource<File, Closed>;
+/// }
+///
+/// fn read_first_byte(path: &str) -> (i32, Resource<File, Closed>) {
+///     let file = unsafe { open_file(path.as_ptr(), path.len()) };
+///     let byte = unsafe { read_byte(&file) };
+///     (byte, unsafe { close_file(file) })
+/// }
+/// ```
 #[derive(Debug)]
 #[repr(C)]
-pub struct Resource<T> {
-    id: usize,
-    _ty: PhantomData<fn(T)>,
+pub struct Resource<T, S = ()> {
+    id: PackedId,
+    /// Generation of the slot at the resource creation.
+    #[cfg(feature = "generations")]
+    generation: u32,
+    _ty: PhantomData<fn(T, S)>,
 }
 
-impl<T> Resource<T> {
+#[cfg(not(feature = "generations"))]
+const _: () = assert!(
+    mem::size_of::<Option<Resource<()>>>() == mem::size_of::<usize>(),
+    "`Option<Resource<_>>` must be pointer-sized"
+);
+
+impl<T, S> Resource<T, S> {
+    /// ID of null resources.
+    const NULL_ID: usize = usize::MAX;
+
     /// Creates a new resource converting it from.
     ///
     /// # Safety
@@ -277,34 +594,166 @@ impl<T> Resource<T> {
     /// This method must be called with an `externref` obtained from the host (as a return
     /// type for an imported function or an argument for an exported function); it is not
     /// a "real" `usize`. The proper use is ensured by the [`externref`] macro.
+    ///
+    /// `kind` returns the resource kind ID, which is passed to the `externref`s table
+    /// together with the reference.
     #[doc(hidden)] // should only be used by macro-generated code
     #[inline(always)]
-    pub unsafe fn new(id: ExternRef) -> Option<Self> {
-        let id = insert_externref(id);
-        if id == usize::MAX {
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub unsafe fn new(id: ExternRef, kind: impl FnOnce() -> i32) -> Option<Self> {
+        let id = insert_externref(id, kind());
+        if id == Self::NULL_ID {
             None
         } else {
-            Some(Self {
-                id,
-                _ty: PhantomData,
-            })
+            Some(Self::from_id(id))
         }
     }
 
     #[doc(hidden)] // should only be used by macro-generated code
     #[inline(always)]
-    pub unsafe fn new_non_null(id: ExternRef) -> Self {
-        let id = insert_externref(id);
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub unsafe fn new_non_null(id: ExternRef, kind: impl FnOnce() -> i32) -> Self {
+        let id = insert_externref(id, kind());
         assert!(
-            id != usize::MAX,
+            id != Self::NULL_ID,
             "Passed null `externref` as non-nullable arg"
         );
+        Self::from_id(id)
+    }
+
+    /// Same as [`Self::new()`], but represents a null `externref` as a [null](Self::null())
+    /// resource.
+    #[doc(hidden)] // should only be used by macro-generated code
+    #[inline(always)]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub unsafe fn new_nullable(id: ExternRef, kind: impl FnOnce() -> i32) -> Self {
+        Self::from_id(insert_externref(id, kind()))
+    }
+
+    /// Wraps a freshly inserted reference. With the `debug-tracking` feature, records
+    /// the creation site of the resource.
+    #[inline(always)]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub(crate) fn from_id(id: usize) -> Self {
+        #[cfg(feature = "debug-tracking")]
+        if id != Self::NULL_ID {
+            debug::record(id, core::panic::Location::caller());
+        }
         Self {
-            id,
+            id: PackedId::new(id),
+            #[cfg(feature = "generations")]
+            generation: if id == Self::NULL_ID {
+                0
+            } else {
+                generations::record(id)
+            },
             _ty: PhantomData,
         }
     }
 
+    /// Creates a null resource, which does not refer to any host object.
+    pub const fn null() -> Self {
+        Self {
+            id: PackedId::new(Self::NULL_ID),
+            #[cfg(feature = "generations")]
+            generation: 0,
+            _ty: PhantomData,
+        }
+    }
+
+    /// Refers to a well-known reference placed by the host into a reserved slot
+    /// of the `externref`s table (see `Processor::set_reserved_slots()` in the [`processor`]
+    /// module). Unlike resources obtained from the host, this does not insert the reference
+    /// into the table.
+    ///
+    /// The returned resource is wrapped in [`ManuallyDrop`](mem::ManuallyDrop), since reserved
+    /// slots are owned by the host; dropping the resource would remove the reference
+    /// from the table. For the same reason, the resource should only be passed to the host
+    /// by reference.
+    ///
+    /// # Safety
+    ///
+    /// `id` must be less than the number of reserved slots, and the host must place a reference
+    /// to an object of type `T` into the slot. If the table is partitioned by resource kind,
+    /// `id` must encode the partition as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use externref::{externref, Resource};
+    /// pub struct Stream(());
+    ///
+    /// #[externref]
+    /// #[link(wasm_import_module = "io")]
+    /// extern "C" {
+    ///     fn write(stream: &Resource<Stream>, ptr: *const u8, len: usize);
+    /// }
+    ///
+    /// /// Slot reserved by the host for the standard output stream.
+    /// const STDOUT: usize = 1;
+    ///
+    /// fn print(s: &str) {
+    ///     let stdout = unsafe { Resource::<Stream>::well_known(STDOUT) };
+    ///     unsafe { write(&stdout, s.as_ptr(), s.len()) };
+    /// }
+    /// ```
+    pub unsafe fn well_known(id: usize) -> mem::ManuallyDrop<Self> {
+        mem::ManuallyDrop::new(Self {
+            id: PackedId::new(id),
+            #[cfg(feature = "generations")]
+            generation: generations::current(id),
+            _ty: PhantomData,
+        })
+    }
+
+    /// Checks whether this resource is [null](Self::null()).
+    pub const fn is_null(&self) -> bool {
+        self.id.get() == Self::NULL_ID
+    }
+
+    #[inline(always)]
+    pub(crate) const fn id(&self) -> usize {
+        self.id.get()
+    }
+
+    /// Returns the ID of this resource to pass it to the host. With the `generations` feature,
+    /// checks that the resource is not stale.
+    #[inline(always)]
+    #[cfg_attr(feature = "generations", track_caller)]
+    pub(crate) fn checked_id(&self) -> usize {
+        let id = self.id();
+        #[cfg(feature = "generations")]
+        if id != Self::NULL_ID {
+            generations::check(id, self.generation);
+        }
+        id
+    }
+
+    /// Returns the identity of this resource, or `None` if the resource is [null](Self::null()).
+    /// The identity is stable while the resource is alive and is shared by all resources
+    /// referring to the same slot in the `externref`s table (e.g., after [upcasting](Self::upcast())).
+    /// See [`ResourceId`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use externref::{HostHandle, Resource};
+    /// # let handle = unsafe { HostHandle::<()>::from_index(3) };
+    /// let resource: Resource<()> = // ...
+    /// #    Resource::from_host_handle(handle);
+    /// let id = resource.ptr_id().unwrap();
+    /// assert_eq!(id.index(), 3);
+    /// assert_eq!(id.to_string(), "#3");
+    /// assert!(Resource::<()>::null().ptr_id().is_none());
+    /// ```
+    pub const fn ptr_id(&self) -> Option<ResourceId> {
+        if self.is_null() {
+            None
+        } else {
+            Some(ResourceId(self.id()))
+        }
+    }
+
     /// Obtains an `externref` from this resource.
     ///
     /// # Safety
@@ -316,35 +765,104 @@ impl<T> Resource<T> {
     #[inline(always)]
     pub unsafe fn raw(this: Option<&Self>) -> ExternRef {
         get_externref(match this {
-            None => usize::MAX,
-            Some(resource) => resource.id,
+            None => Self::NULL_ID,
+            Some(resource) => resource.checked_id(),
         })
     }
 
     /// Obtains an `externref` from this resource and drops the resource.
     #[doc(hidden)] // should only be used by macro-generated code
     #[inline(always)]
-    #[allow(clippy::needless_pass_by_value)]
     pub unsafe fn take_raw(this: Option<Self>) -> ExternRef {
-        get_externref(match this {
-            None => usize::MAX,
-            Some(resource) => resource.id,
-        })
+        let raw = get_externref(match &this {
+            None => Self::NULL_ID,
+            Some(resource) => resource.checked_id(),
+        });
+        // The resource must be dropped *after* obtaining the reference; otherwise,
+        // the reference will be removed from the table beforehand.
+        drop(this);
+        raw
+    }
+
+    /// Lends this resource to the host for the duration of `f` (which would typically
+    /// call a host import, passing the resource via the provided [`LoanToken`]).
+    /// Once `f` returns, checks that the resource is still live, i.e., that the host
+    /// has not invalidated it by nulling its slot in the `externref`s table.
+    ///
+    /// The slot is *not* marked as borrowed while `f` runs. The host can null or overwrite slots
+    /// directly via the exported `externref`s table, bypassing any guest-side instrumentation
+    /// (the [drop reentrancy](crate::processor::DropReentrancy) guard only covers calls
+    /// to the drop hook). Thus, invalidation is only detected after `f` returns, and it is
+    /// not detected at all if the host overwrites the slot with another non-null reference
+    /// (e.g., a tombstone) rather than nulling it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the resource was invalidated. In this case, the slot of the resource
+    /// may be reused for another reference, so the resource should not be used afterwards;
+    /// consider [forgetting](mem::forget()) it instead of dropping.
+    /// [Null](Self::null()) resources cannot be invalidated, so lending them always succeeds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use externref::{externref, Resource};
+    /// pub struct Bytes(());
+    ///
+    /// #[externref]
+    /// #[link(wasm_import_module = "test")]
+    /// extern "C" {
+    ///     fn process_bytes(bytes: &Resource<Bytes>) -> usize;
+    /// }
+    ///
+    /// fn process(bytes: Resource<Bytes>) -> Option<usize> {
+    ///     match bytes.lend(|token| unsafe { process_bytes(token.resource()) }) {
+    ///         Ok(len) => Some(len),
+    ///         Err(_) => {
+    ///             core::mem::forget(bytes);
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn lend<R>(&self, f: impl FnOnce(LoanToken<'_, T, S>) -> R) -> Result<R, LendError> {
+        let output = f(LoanToken { resource: self });
+        if self.is_null() {
+            return Ok(output);
+        }
+        let is_live = unsafe { is_live_externref(self.checked_id()) };
+        if is_live == 0 {
+            Err(LendError::new(self.id()))
+        } else {
+            Ok(output)
+        }
     }
 
     /// Upcasts this resource to a generic resource.
     pub fn upcast(self) -> Resource<()> {
-        Resource {
-            id: self.leak_id(),
-            _ty: PhantomData,
-        }
+        self.cast()
+    }
+
+    /// Changes the state of this resource without calling the host.
+    ///
+    /// # Safety
+    ///
+    /// No checks are performed that the host object is actually in the `S2` state. It is up
+    /// to the caller to ensure this (e.g., if the state transition is known to occur implicitly
+    /// on the host side).
+    pub unsafe fn with_state_unchecked<S2>(self) -> Resource<T, S2> {
+        self.cast()
     }
 
     #[inline]
-    fn leak_id(self) -> usize {
-        let id = self.id;
-        mem::forget(self);
-        id
+    fn cast<T2, S2>(self) -> Resource<T2, S2> {
+        let this = mem::ManuallyDrop::new(self);
+        Resource {
+            id: this.id,
+            #[cfg(feature = "generations")]
+            generation: this.generation,
+            _ty: PhantomData,
+        }
     }
 
     /// Upcasts a reference to this resource to a generic resource reference.
@@ -360,7 +878,252 @@ impl<T> Resource<T> {
     }
 }
 
+impl<T> Resource<T> {
+    /// Converts this resource into an opaque [`HostHandle`], e.g., to pass it to the host
+    /// as a numeric context or to persist it in linear memory. The reference remains
+    /// in the `externref`s table until the handle is converted back via [`Self::from_host_handle()`].
+    #[cfg_attr(feature = "generations", track_caller)]
+    pub fn into_host_handle(self) -> HostHandle<T> {
+        let this = mem::ManuallyDrop::new(self);
+        HostHandle {
+            index: this.checked_id(),
+            _ty: PhantomData,
+        }
+    }
+
+    /// Restores a resource from a [`HostHandle`] previously obtained
+    /// via [`Self::into_host_handle()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle index is `usize::MAX - 1`, which is never a valid index
+    /// in the `externref`s table. Such a handle can only be created from an index supplied
+    /// by the host (e.g., as an arg of an exported function); handles deserialized
+    /// via `serde` are checked not to have this index.
+    pub fn from_host_handle(handle: HostHandle<T>) -> Self {
+        let id = handle.into_index();
+        Self {
+            id: PackedId::new(id),
+            #[cfg(feature = "generations")]
+            generation: generations::current(id),
+            _ty: PhantomData,
+        }
+    }
+}
+
+/// Token providing access to a [`Resource`] lent to the host via [`Resource::lend()`].
+#[derive(Debug)]
+pub struct LoanToken<'a, T, S = ()> {
+    resource: &'a Resource<T, S>,
+}
+
+impl<'a, T, S> LoanToken<'a, T, S> {
+    /// Returns the lent resource.
+    pub fn resource(&self) -> &'a Resource<T, S> {
+        self.resource
+    }
+}
+
+/// Identity of a non-null [`Resource`] obtained via [`Resource::ptr_id()`].
+///
+/// The identity is the index of the resource in the `externref`s table, i.e., the same value
+/// the host observes (e.g., as the slot index in `host::ResourceRegistry` or
+/// as a [`HostHandle::index()`]). Thus, identities can be logged and correlated across
+/// the guest and the host; they are displayed as `#{index}`. Identities are unique among
+/// live resources, but may be reused once a resource is dropped, so they should not be retained
+/// beyond the resource lifetime (e.g., as keys in a long-lived map).
+///
+/// If the `serde` crate feature is enabled, identities implement `Serialize` / `Deserialize`
+/// as the table index. Unlike [`HostHandle`]s, deserialized identities are not validated,
+/// since they do not own the resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResourceId(usize);
+
+impl ResourceId {
+    /// Creates an identity from the 0-based index in the `externref`s table, e.g.,
+    /// to correlate host-side references with [`Resource::ptr_id()`] values.
+    pub const fn from_index(index: usize) -> Self {
+        Self(index)
+    }
+
+    /// Returns the 0-based index of the resource in the `externref`s table.
+    pub const fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for ResourceId {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "#{}", self.0)
+    }
+}
+
+/// Opaque handle to a [`Resource`] represented by the index of the resource
+/// in the `externref`s table. Created via [`Resource::into_host_handle()`].
+///
+/// Unlike `Resource`s, handles can be passed to the host as plain integers (`i32` on `wasm32`),
+/// e.g., as a context for callbacks. If a `HostHandle` is used as an arg
+/// of an imported / exported function processed by the [`externref`](macro@externref) macro,
+/// this is recorded in the function declaration, so that the host knows that the value
+/// is an index in the `externref`s table. (If the table is partitioned by resource kind
+/// via `Processor::set_table_partitioning()`, the index also encodes the table partition.)
+///
+/// A handle owns the resource; dropping the handle without converting it back
+/// via [`Resource::from_host_handle()`] leaks the reference.
+///
+/// # Serialization
+///
+/// If the `serde` crate feature is enabled, handles implement `Serialize` / `Deserialize`
+/// as their table index. This allows persisting module state (e.g., as a snapshot
+/// of the relevant data structures) that references live host objects; the host is responsible
+/// for restoring the `externref`s table so that the indices remain valid.
+///
+/// Since a deserialized index can be arbitrary, it is re-validated by the host
+/// on deserialization via an import from the [`Self::SERDE_MODULE_NAME`] module
+/// with the `(i32) -> i32` signature (`(i64) -> i32` on `wasm64`) named `"validate_handle"`.
+/// The import should return 0 if the index does not refer to a live reference, in which case
+/// deserialization fails. On the host side, the import can be implemented using
+/// `host::ResourceRegistry::validate_handle()`. Like other imports, the import
+/// can be renamed during processing via `Processor::rename_import()`.
+///
+/// A deserialized handle owns the resource just like the original one. Thus, deserialization
+/// is meant to restore state in a new module instance (or after the original handle was
+/// consumed); otherwise, the reference may be dropped twice.
+///
+/// # Examples
+///
+/// ```
+/// # use externref::{externref, HostHandle, Resource};
+/// pub struct Sender(());
+///
+/// #[externref]
+/// #[link(wasm_import_module = "test")]
+/// extern "C" {
+///     /// Registers a callback; the handle will be passed back to `on_message()`.
+///     fn register_callback(context: HostHandle<Sender>);
+/// }
+///
+/// #[externref]
+/// pub extern "C" fn on_message(context: HostHandle<Sender>) {
+///     let sender = Resource::from_host_handle(context);
+///     // Use `sender`...
+/// }
+///
+/// fn register(sender: Resource<Sender>) {
+///     unsafe { register_callback(sender.into_host_handle()) };
+/// }
+/// ```
+#[must_use = "dropping a handle leaks the resource"]
+#[repr(transparent)]
+pub struct HostHandle<T> {
+    index: usize,
+    _ty: PhantomData<fn(T)>,
+}
+
+impl<T> fmt::Debug for HostHandle<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_tuple("HostHandle")
+            .field(&self.index)
+            .finish()
+    }
+}
+
+impl<T> HostHandle<T> {
+    /// Name of the module with the host import validating deserialized handles.
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub const SERDE_MODULE_NAME: &'static str = "externref_serde";
+
+    /// Returns the 0-based index of the resource in the `externref`s table.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Converts this handle into the table index, e.g., to persist it in linear memory.
+    /// The handle can be restored via [`Self::from_index()`].
+    pub fn into_index(self) -> usize {
+        self.index
+    }
+
+    /// Restores a handle from the table index obtained via [`Self::into_index()`].
+    ///
+    /// # Safety
+    ///
+    /// The index must be obtained from a handle of the same resource type, and must
+    /// be restored at most once.
+    pub unsafe fn from_index(index: usize) -> Self {
+        Self {
+            index,
+            _ty: PhantomData,
+        }
+    }
+}
+
 impl Resource<()> {
+    /// Registers a guest callback invoked each time a non-null resource of any type is dropped,
+    /// e.g., to clear caches keyed by the resource. The callback receives the 0-based index
+    /// of the resource in the `externref`s table (same as [`HostHandle::index()`]) and is called
+    /// before the reference is removed from the table, so the index is not yet reused.
+    ///
+    /// The callback is shared by all resources in the module; registering a callback replaces
+    /// the previously registered one, which is returned. Resources converted
+    /// into [`HostHandle`]s or upcast / downcast are not dropped and thus do not trigger
+    /// the callback.
+    ///
+    /// The callback is called via a function pointer, i.e., it is an entry in the WASM function
+    /// table with the `(i32) -> ()` signature. Thus, table entries declared via
+    /// the [`externref`](macro@externref) macro must not have the same raw signature
+    /// (e.g., `extern "C" fn(Resource<T>)`); otherwise, the [processor](crate::processor)
+    /// will be unable to distinguish them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use core::sync::atomic::{AtomicUsize, Ordering};
+    /// # use externref::Resource;
+    /// static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// fn on_drop(_index: usize) {
+    ///     DROPPED.fetch_add(1, Ordering::Relaxed);
+    /// }
+    ///
+    /// let prev_hook = Resource::on_drop(on_drop);
+    /// assert!(prev_hook.is_none());
+    /// ```
+    pub fn on_drop(hook: fn(usize)) -> Option<fn(usize)> {
+        drop_hook_from_ptr(DROP_HOOK.swap(hook as *mut (), Ordering::AcqRel))
+    }
+
+    /// Drops all references with IDs (i.e., [`HostHandle::index()`]es) in the `start..start + len`
+    /// range, e.g. to clean up resources allocated in an arena. Free slots in the range
+    /// are skipped. Like with [`drop_all()`], the host drop hook is invoked for each dropped
+    /// reference, but the [guest callback](Self::on_drop()) is not.
+    ///
+    /// # Safety
+    ///
+    /// All [`Resource`]s and [`HostHandle`]s referring to the dropped references become dangling:
+    /// they must not be used and must be [forgotten](mem::forget()) rather than dropped,
+    /// since their slots in the `externref`s table may be reused by other references.
+    pub unsafe fn drop_range(start: usize, len: usize) {
+        #[cfg(target_arch = "wasm32")]
+        __surrogate_imports! {
+            #[link_name = "drop_range"]
+            fn drop_externref_range(start: usize, len: usize);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        unsafe fn drop_externref_range(_start: usize, _len: usize) {
+            // Do nothing
+        }
+
+        #[cfg(feature = "debug-tracking")]
+        debug::remove_range(start..start.saturating_add(len));
+        #[cfg(feature = "generations")]
+        generations::bump_range(start, len);
+        drop_externref_range(start, len);
+    }
+
     /// Downcasts this generic resource to a specific type.
     ///
     /// # Safety
@@ -369,20 +1132,67 @@ impl Resource<()> {
     /// by `Resource<T>`. It is up to the caller to check this beforehand (e.g., by calling
     /// a WASM import taking `&Resource<()>` and returning an app-specific resource kind).
     pub unsafe fn downcast_unchecked<T>(self) -> Resource<T> {
-        Resource {
-            id: self.leak_id(),
-            _ty: PhantomData,
-        }
+        self.cast()
+    }
+}
+
+/// Guest callback invoked when a non-null [`Resource`] is dropped; registered via
+/// [`Resource::on_drop()`]. Stored as a type-erased pointer since function pointers cannot
+/// be used with atomics directly.
+static DROP_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+fn drop_hook_from_ptr(hook: *mut ()) -> Option<fn(usize)> {
+    if hook.is_null() {
+        None
+    } else {
+        // SAFETY: non-null values are only stored by `Resource::on_drop()`, which stores
+        // valid function pointers.
+        Some(unsafe { mem::transmute::<*mut (), fn(usize)>(hook) })
     }
 }
 
-/// Drops the `externref` associated with this resource.
-impl<T> Drop for Resource<T> {
+/// Drops all references in the `externref`s table, invoking the host drop hook
+/// (see `Processor::set_drop_fn()` in the [`processor`] module) for each of them. This is
+/// a cheap way to reset all host references held by the module, e.g. between requests handled
+/// by a long-lived module instance. The [guest callback](Resource::on_drop()) is not invoked.
+///
+/// References stored in [`ResourceTable`]s, [`GlobalResource`]s and [`SharedResource`]s
+/// are not affected.
+///
+/// # Safety
+///
+/// All [`Resource`]s and [`HostHandle`]s in the module become dangling: they must not be used
+/// and must be [forgotten](mem::forget()) rather than dropped, since their slots
+/// in the `externref`s table may be reused by other references.
+pub unsafe fn drop_all() {
+    #[cfg(target_arch = "wasm32")]
+    __surrogate_imports! {
+        #[link_name = "drop_all"]
+        fn drop_all_externrefs();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    unsafe fn drop_all_externrefs() {
+        // Do nothing
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    debug::remove_range(0..usize::MAX);
+    #[cfg(feature = "generations")]
+    generations::bump_range(0, usize::MAX);
+    drop_all_externrefs();
+}
+
+/// Drops the `externref` associated with this resource. Dropping a [null](Resource::null())
+/// resource is a no-op.
+///
+/// If a drop hook is registered via [`Resource::on_drop()`], it is invoked before
+/// the reference is removed from the `externref`s table.
+impl<T, S> Drop for Resource<T, S> {
     #[inline(always)]
     fn drop(&mut self) {
         #[cfg(target_arch = "wasm32")]
-        #[link(wasm_import_module = "externref")]
-        extern "C" {
+        __surrogate_imports! {
             #[link_name = "drop"]
             fn drop_externref(id: usize);
         }
@@ -392,9 +1202,40 @@ impl<T> Drop for Resource<T> {
             // Do nothing
         }
 
-        unsafe { drop_externref(self.id) };
+        if !self.is_null() {
+            let id = self.checked_id();
+            if let Some(hook) = drop_hook_from_ptr(DROP_HOOK.load(Ordering::Acquire)) {
+                hook(id);
+            }
+            #[cfg(feature = "debug-tracking")]
+            debug::remove(id);
+            #[cfg(feature = "generations")]
+            generations::bump(id);
+            unsafe { drop_externref(id) };
+        }
     }
 }
 
 #[cfg(doctest)]
 doc_comment::doctest!("../README.md");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::format;
+
+    #[test]
+    fn packing_resource_ids() {
+        for id in [0, 1, 1_000, usize::MAX - 2, usize::MAX] {
+            assert_eq!(PackedId::new(id).get(), id);
+        }
+        assert_eq!(PackedId::new(Resource::<()>::NULL_ID).0.get(), 1);
+        assert_eq!(format!("{:?}", PackedId::new(42)), "42");
+    }
+
+    #[test]
+    #[should_panic(expected = "table overflow")]
+    fn packing_invalid_resource_id() {
+        PackedId::new(usize::MAX - 1);
+    }
+}
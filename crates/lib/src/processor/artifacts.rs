@@ -0,0 +1,50 @@
+//! IDs of functions and tables injected by the processor.
+
+use walrus::{FunctionId, Module, TableId};
+
+use crate::alloc::Vec;
+
+/// IDs of functions and tables injected by the [`Processor`](super::Processor) into a module.
+/// Returned by [`Processor::process()`](super::Processor::process()) and can be used
+/// to further instrument the processed module without rediscovering the injected items.
+///
+/// Items are only injected if the module uses them; e.g., if the module never
+/// drops references, there is no drop function. Items removed as unused after processing
+/// are not reported either.
+///
+/// Reference IDs in the signatures of the reported functions are always `i32`s,
+/// regardless of the [index type](super::Processor::set_index_type()) of surrogate imports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProcessingArtifacts {
+    /// Function inserting a reference into the `externref`s table. Has the
+    /// `(externref, i32) -> i32` signature, where the second arg is the resource kind ID
+    /// (-1 if the kind is unknown) and the result is the reference ID.
+    pub insert_fn: Option<FunctionId>,
+    /// Function getting a reference by its ID. Has the `(i32) -> externref` signature.
+    pub get_fn: Option<FunctionId>,
+    /// Function dropping a reference by its ID. Has the `(i32) -> ()` signature.
+    pub drop_fn: Option<FunctionId>,
+    /// Table with `externref`s. If the table is [partitioned](super::Partitioning::PerKind),
+    /// this is the table with references of unknown kind.
+    pub ref_table: Option<TableId>,
+    /// Tables with references of each resource kind, ordered by the kind ID. Only non-empty
+    /// if the table is [partitioned](super::Partitioning::PerKind).
+    pub kind_tables: Vec<TableId>,
+}
+
+impl ProcessingArtifacts {
+    /// Forgets about items that are no longer present in the `module`.
+    pub(crate) fn retain_live(&mut self, module: &Module) {
+        let has_fn =
+            |fn_id: &FunctionId| module.funcs.iter().any(|function| function.id() == *fn_id);
+        let has_table =
+            |table_id: &TableId| module.tables.iter().any(|table| table.id() == *table_id);
+
+        self.insert_fn = self.insert_fn.filter(has_fn);
+        self.get_fn = self.get_fn.filter(has_fn);
+        self.drop_fn = self.drop_fn.filter(has_fn);
+        self.ref_table = self.ref_table.filter(has_table);
+        self.kind_tables.retain(has_table);
+    }
+}
@@ -0,0 +1,129 @@
+//! Stable C ABI for processing modules from non-Rust build systems.
+//!
+//! The ABI consists of two functions:
+//!
+//! ```c
+//! int32_t externref_process_bytes(
+//!     const uint8_t *input, size_t input_len,
+//!     uint8_t **output, size_t *output_len
+//! );
+//! void externref_free_bytes(uint8_t *bytes, size_t len);
+//! ```
+//!
+//! [`externref_process_bytes()`] processes the module with the default [`Processor`] options,
+//! similar to [`process_bytes()`].
+//! On success, it returns [`EXTERNREF_OK`] and writes the processed module to `output`;
+//! otherwise, it returns a non-zero status code and writes a UTF-8 error message
+//! (not null-terminated) to `output`. In both cases, the output must be freed with
+//! [`externref_free_bytes()`].
+//!
+//! To link the ABI, build the crate as a C library, e.g. with
+//! `cargo rustc -p externref --release --features capi --crate-type cdylib`
+//! (or `--crate-type staticlib` for a static library).
+//!
+//! [`Processor`]: super::Processor
+
+use std::{panic, ptr, slice};
+
+use super::process_bytes;
+
+/// Status code returned by [`externref_process_bytes()`] if processing succeeded.
+pub const EXTERNREF_OK: i32 = 0;
+/// Status code returned by [`externref_process_bytes()`] if processing failed.
+pub const EXTERNREF_ERROR: i32 = 1;
+/// Status code returned by [`externref_process_bytes()`] if processing panicked.
+pub const EXTERNREF_PANIC: i32 = 2;
+/// Status code returned by [`externref_process_bytes()`] if any of the supplied pointers is null.
+/// In this case, nothing is written to the output.
+pub const EXTERNREF_INVALID_ARGS: i32 = -1;
+
+/// Processes a WASM module with the default [`Processor`](super::Processor) options.
+/// See the [module docs](self) for the description of args and return values.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes. `output` and `output_len` must be
+/// valid for writes. The bytes written to `output` must be freed with [`externref_free_bytes()`].
+#[no_mangle]
+pub unsafe extern "C" fn externref_process_bytes(
+    input: *const u8,
+    input_len: usize,
+    output: *mut *mut u8,
+    output_len: *mut usize,
+) -> i32 {
+    if input.is_null() || output.is_null() || output_len.is_null() {
+        return EXTERNREF_INVALID_ARGS;
+    }
+    // SAFETY: guaranteed by the caller
+    let input = unsafe { slice::from_raw_parts(input, input_len) };
+    let result = panic::catch_unwind(|| process_bytes(input));
+    let (status, bytes) = match result {
+        Ok(Ok(processed)) => (EXTERNREF_OK, processed),
+        Ok(Err(err)) => (EXTERNREF_ERROR, err.to_string().into_bytes()),
+        Err(_) => (EXTERNREF_PANIC, b"processing panicked".to_vec()),
+    };
+
+    let len = bytes.len();
+    let bytes = Box::into_raw(bytes.into_boxed_slice());
+    // SAFETY: guaranteed by the caller
+    unsafe {
+        *output_len = len;
+        *output = bytes.cast();
+    }
+    status
+}
+
+/// Frees bytes written by [`externref_process_bytes()`]. Does nothing if `bytes` is null.
+///
+/// # Safety
+///
+/// `bytes` and `len` must be the output of [`externref_process_bytes()`] that was not freed
+/// before.
+#[no_mangle]
+pub unsafe extern "C" fn externref_free_bytes(bytes: *mut u8, len: usize) {
+    if !bytes.is_null() {
+        // SAFETY: guaranteed by the caller
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(bytes, len)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(input: &[u8]) -> (i32, Vec<u8>) {
+        let mut output = ptr::null_mut();
+        let mut output_len = 0;
+        let status = unsafe {
+            externref_process_bytes(input.as_ptr(), input.len(), &mut output, &mut output_len)
+        };
+        let bytes = unsafe { slice::from_raw_parts(output, output_len) }.to_vec();
+        unsafe { externref_free_bytes(output, output_len) };
+        (status, bytes)
+    }
+
+    #[test]
+    fn processing_module_via_capi() {
+        let module = b"\0asm\x01\0\0\0";
+        let (status, processed) = process(module);
+        assert_eq!(status, EXTERNREF_OK);
+        assert_eq!(processed, process_bytes(module).unwrap());
+    }
+
+    #[test]
+    fn processing_error_via_capi() {
+        let (status, message) = process(b"not a module");
+        assert_eq!(status, EXTERNREF_ERROR);
+        let message = String::from_utf8(message).unwrap();
+        assert!(message.contains("failed reading WASM module"), "{message}");
+    }
+
+    #[test]
+    fn invalid_args_for_capi() {
+        let mut output_len = 0;
+        let status =
+            unsafe { externref_process_bytes(ptr::null(), 0, ptr::null_mut(), &mut output_len) };
+        assert_eq!(status, EXTERNREF_INVALID_ARGS);
+        unsafe { externref_free_bytes(ptr::null_mut(), 0) };
+    }
+}
@@ -0,0 +1,158 @@
+//! Resolving WASM bytecode offsets to instruction locations using the original module code
+//! and DWARF debug info.
+
+use walrus::{LocalFunction, ModuleDebugData};
+
+use super::{CodeLocation, SourceLocation};
+
+/// Resolves the location of the instruction at the specified WASM bytecode `offset`
+/// in `local_fn`. Returns `None` if the function is not present in the original module
+/// or the offset does not correspond to an instruction.
+pub(crate) fn resolve_location(
+    debug: &ModuleDebugData,
+    local_fn: &LocalFunction,
+    offset: u32,
+) -> Option<CodeLocation> {
+    // `instruction_mapping` is ordered by the instruction address.
+    let (instr_index, &(address, _)) = local_fn
+        .instruction_mapping
+        .iter()
+        .enumerate()
+        .find(|(_, (_, location))| !location.is_default() && location.data() == offset)?;
+    let location = CodeLocation {
+        instr_index,
+        source: source_location(debug, address as u64),
+    };
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(offset, %location, "resolved code location");
+    Some(location)
+}
+
+#[cfg(not(feature = "dwarf"))]
+fn source_location(_debug: &ModuleDebugData, _address: u64) -> Option<SourceLocation> {
+    None
+}
+
+/// Looks up the source location for the code section-relative `address` in the DWARF line
+/// programs.
+#[cfg(feature = "dwarf")]
+fn source_location(debug: &ModuleDebugData, address: u64) -> Option<SourceLocation> {
+    use std::path::Path;
+
+    use gimli::{ColumnType, EndianSlice, LittleEndian};
+
+    let dwarf = debug
+        .dwarf
+        .borrow(|section| EndianSlice::new(section, LittleEndian));
+    let mut units = dwarf.units();
+    while let Ok(Some(header)) = units.next() {
+        let Ok(unit) = dwarf.unit(header) else {
+            continue;
+        };
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+
+        let mut rows = program.rows();
+        let mut prev_row = None;
+        while let Ok(Some((header, row))) = rows.next_row() {
+            let row_matches = prev_row.filter(|prev: &gimli::LineRow| {
+                prev.address() <= address && address < row.address()
+            });
+            if let Some(matching_row) = row_matches {
+                let file = matching_row.file(header)?;
+                let name = dwarf.attr_string(&unit, file.path_name()).ok()?;
+                let mut path = name.to_string_lossy().into_owned();
+                let dir = file
+                    .directory(header)
+                    .and_then(|dir| dwarf.attr_string(&unit, dir).ok());
+                if let Some(dir) = dir {
+                    let dir = dir.to_string_lossy();
+                    path = Path::new(dir.as_ref()).join(path).display().to_string();
+                }
+
+                return Some(SourceLocation {
+                    file: path,
+                    line: matching_row.line().map(Into::into),
+                    column: match matching_row.column() {
+                        ColumnType::LeftEdge => None,
+                        ColumnType::Column(column) => Some(column.into()),
+                    },
+                });
+            }
+            prev_row = (!row.end_sequence()).then_some(*row);
+        }
+    }
+    None
+}
+
+#[cfg(all(test, feature = "dwarf"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use gimli::{
+        write::{self, EndianVec, LineProgram, LineString, Sections, Unit},
+        Encoding, Format, LineEncoding, LittleEndian,
+    };
+
+    use super::*;
+
+    fn create_debug_data() -> ModuleDebugData {
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: 4,
+        };
+        let mut program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::String(b"/work".to_vec()),
+            LineString::String(b"lib.rs".to_vec()),
+            None,
+        );
+        let dir_id = program.add_directory(LineString::String(b"/src".to_vec()));
+        let file_id = program.add_file(LineString::String(b"lib.rs".to_vec()), dir_id, None);
+        program.begin_sequence(Some(write::Address::Constant(0x10)));
+        for (offset, line) in [(0, 3), (8, 42)] {
+            let row = program.row();
+            row.address_offset = offset;
+            row.file = file_id;
+            row.line = line;
+            row.column = 5;
+            program.generate_row();
+        }
+        program.end_sequence(0x20);
+
+        let mut dwarf = write::Dwarf::default();
+        dwarf.units.add(Unit::new(encoding, program));
+        let mut sections = Sections::new(EndianVec::new(LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+
+        let mut section_data = HashMap::new();
+        sections
+            .for_each(|id, data| {
+                section_data.insert(id, data.slice().to_vec());
+                Ok::<_, gimli::Error>(())
+            })
+            .unwrap();
+
+        let dwarf = gimli::Dwarf::load(|id| {
+            Ok::<_, gimli::Error>(section_data.remove(&id).unwrap_or_default())
+        })
+        .unwrap();
+        ModuleDebugData { dwarf }
+    }
+
+    #[test]
+    fn resolving_source_locations() {
+        let debug = create_debug_data();
+        let location = source_location(&debug, 0x12).unwrap();
+        assert_eq!(location.to_string(), "/src/lib.rs:3:5");
+        let location = source_location(&debug, 0x1a).unwrap();
+        assert_eq!(location.to_string(), "/src/lib.rs:42:5");
+
+        assert!(source_location(&debug, 0x8).is_none());
+        assert!(source_location(&debug, 0x30).is_none());
+    }
+}
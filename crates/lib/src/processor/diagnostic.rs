@@ -0,0 +1,309 @@
+//! Rich diagnostics for processing errors based on `miette`.
+
+use std::{error, fmt, iter};
+
+use miette::{Diagnostic, LabeledSpan, NamedSource, SourceCode, SourceSpan};
+
+use super::Error;
+
+impl Diagnostic for Error {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(Self::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Self::help(self).map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+}
+
+/// Processing [`Error`] together with the WAT representation of the function the error
+/// points to (if any), with the offending instruction labeled. Created via
+/// [`Error::into_report()`].
+///
+/// The report implements [`Diagnostic`], so it can be rendered by `miette` handlers, e.g.
+/// by converting it to `miette::Report`. [Multiple errors](Error::Multiple) are rendered
+/// as related diagnostics, each with its own labeled function.
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "miette")))]
+pub struct ErrorReport {
+    inner: ReportInner,
+}
+
+#[derive(Debug)]
+enum ReportInner {
+    Single {
+        error: Error,
+        source: Option<NamedSource<String>>,
+        label: Option<LabeledSpan>,
+    },
+    Multiple(Vec<ErrorReport>),
+}
+
+impl Error {
+    /// Converts this error into a [report](ErrorReport) with a labeled span in the WAT
+    /// representation of the offending function. `module_bytes` must be the module
+    /// passed to the processor; if the module cannot be rendered, or if the error does not point
+    /// to an instruction, the report has no source code.
+    #[cfg_attr(docsrs, doc(cfg(feature = "miette")))]
+    pub fn into_report(self, module_bytes: &[u8]) -> ErrorReport {
+        let mut wat = String::new();
+        let lines: Vec<_> = wasmprinter::Config::new()
+            .offsets_and_lines(module_bytes, &mut wat)
+            .map(Iterator::collect)
+            .unwrap_or_default();
+        self.into_report_with_lines(&lines)
+    }
+
+    fn into_report_with_lines(self, lines: &[(Option<usize>, &str)]) -> ErrorReport {
+        let inner = match self {
+            Self::Multiple(errors) => ReportInner::Multiple(
+                errors
+                    .into_iter()
+                    .map(|err| err.into_report_with_lines(lines))
+                    .collect(),
+            ),
+            error => {
+                let function = error
+                    .code_offset()
+                    .and_then(|offset| render_function(lines, offset as usize));
+                let (source, label) = match function {
+                    Some((text, span)) => {
+                        let name = error.function_name().unwrap_or("(unnamed function)");
+                        let source = NamedSource::new(format!("{name}.wat"), text);
+                        let label = LabeledSpan::new_with_span(Some(label_text(&error)), span);
+                        (Some(source), Some(label))
+                    }
+                    None => (None, None),
+                };
+                ReportInner::Single {
+                    error,
+                    source,
+                    label,
+                }
+            }
+        };
+        ErrorReport { inner }
+    }
+}
+
+fn label_text(error: &Error) -> String {
+    let text = match error {
+        Error::IncorrectGuard { .. } => "incorrectly placed guard",
+        Error::UnexpectedCall { .. } => "unexpected call",
+        _ => "here",
+    };
+    match error
+        .code_location()
+        .and_then(|location| location.source.as_ref())
+    {
+        Some(source) => format!("{text} ({source})"),
+        None => text.to_owned(),
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Renders the function containing the instruction at the specified bytecode offset.
+/// Returns the function text with the common indentation removed and the span
+/// of the instruction in it.
+fn render_function(lines: &[(Option<usize>, &str)], offset: usize) -> Option<(String, SourceSpan)> {
+    let target = lines
+        .iter()
+        .position(|(line_offset, _)| *line_offset == Some(offset))?;
+    let start = lines[..target]
+        .iter()
+        .rposition(|(_, line)| line.trim_start().starts_with("(func"))?;
+    let indent = indentation(lines[start].1);
+    let end = lines[start + 1..]
+        .iter()
+        .position(|(_, line)| indentation(line) <= indent)
+        .map_or(lines.len(), |pos| start + 1 + pos);
+
+    let mut text = String::new();
+    let mut span = None;
+    for (idx, (_, line)) in lines.iter().enumerate().take(end).skip(start) {
+        let line = line.get(indent..).unwrap_or(line);
+        if idx == target {
+            let mut content = line.trim();
+            if idx + 1 == end {
+                // Exclude the closing paren of the function.
+                content = content.strip_suffix(')').unwrap_or(content);
+            }
+            let content_start = text.len() + indentation(line);
+            span = Some(SourceSpan::from((content_start, content.len())));
+        }
+        text.push_str(line);
+    }
+    text.truncate(text.trim_end().len());
+    Some((text, span?))
+}
+
+impl ErrorReport {
+    /// Iterates over the errors in this report: a single error, or all errors
+    /// if the report was created from [`Error::Multiple`].
+    pub fn errors(&self) -> impl Iterator<Item = &Error> + '_ {
+        let iter: Box<dyn Iterator<Item = &Error>> = match &self.inner {
+            ReportInner::Single { error, .. } => Box::new(iter::once(error)),
+            ReportInner::Multiple(reports) => Box::new(reports.iter().flat_map(Self::errors)),
+        };
+        iter
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            ReportInner::Single { error, .. } => fmt::Display::fmt(error, formatter),
+            ReportInner::Multiple(reports) => write!(
+                formatter,
+                "{} errors occurred during processing",
+                reports.len()
+            ),
+        }
+    }
+}
+
+impl error::Error for ErrorReport {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.inner {
+            ReportInner::Single { error, .. } => error.source(),
+            ReportInner::Multiple(_) => None,
+        }
+    }
+}
+
+impl Diagnostic for ErrorReport {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        match &self.inner {
+            ReportInner::Single { error, .. } => Diagnostic::code(error),
+            ReportInner::Multiple(_) => Some(Box::new("externref::multiple")),
+        }
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        match &self.inner {
+            ReportInner::Single { error, .. } => Diagnostic::help(error),
+            ReportInner::Multiple(_) => None,
+        }
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        match &self.inner {
+            ReportInner::Single { source, .. } => {
+                source.as_ref().map(|source| source as &dyn SourceCode)
+            }
+            ReportInner::Multiple(_) => None,
+        }
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        match &self.inner {
+            ReportInner::Single {
+                label: Some(label), ..
+            } => Some(Box::new(iter::once(label.clone()))),
+            _ => None,
+        }
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        match &self.inner {
+            ReportInner::Multiple(reports) => Some(Box::new(
+                reports.iter().map(|report| report as &dyn Diagnostic),
+            )),
+            ReportInner::Single { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use walrus::{Module, RawCustomSection};
+
+    use super::*;
+    use crate::{processor::Processor, BitSlice, Function, FunctionKind, TypeNames};
+
+    const MODULE: &str = r#"
+        (module
+            (import "externref" "guard" (func $guard))
+
+            (func $test (export "test") (param $ref i32)
+                (drop (local.get $ref))
+                (call $guard)
+            )
+        )
+    "#;
+
+    const TEST: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "test",
+        externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const TEST_BYTES: [u8; TEST.custom_section_len()] = TEST.custom_section();
+
+    fn module_bytes() -> Vec<u8> {
+        let module = wat::parse_str(MODULE).unwrap();
+        let mut module = Module::from_buffer(&module).unwrap();
+        module.customs.add(RawCustomSection {
+            name: Function::CUSTOM_SECTION_NAME.to_owned(),
+            data: TEST_BYTES.to_vec(),
+        });
+        module.emit_wasm()
+    }
+
+    #[test]
+    fn report_for_incorrect_guard() {
+        let module_bytes = module_bytes();
+        let err = Processor::default()
+            .process_bytes(&module_bytes)
+            .unwrap_err();
+        assert_eq!(err.code(), "externref::incorrect_guard");
+        assert_eq!(err.function_name(), Some("test"));
+        assert!(err.code_offset().is_some());
+
+        let report = err.into_report(&module_bytes);
+        assert_eq!(report.errors().count(), 1);
+        let code = Diagnostic::code(&report).unwrap().to_string();
+        assert_eq!(code, "externref::incorrect_guard");
+        let help = Diagnostic::help(&report).unwrap().to_string();
+        assert!(help.contains("wasm-opt"), "{help}");
+
+        let source = report.source_code().unwrap();
+        let labels: Vec<_> = report.labels().unwrap().collect();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].label(), Some("incorrectly placed guard"));
+        let span = source.read_span(labels[0].inner(), 0, 0).unwrap();
+        let span = String::from_utf8_lossy(span.data());
+        assert_eq!(span, "call $guard");
+
+        let function = source.read_span(&(0, 0).into(), 0, 100).unwrap();
+        let function = String::from_utf8_lossy(function.data());
+        assert!(function.starts_with("(func $test"), "{function}");
+        assert!(!function.contains("(import"), "{function}");
+    }
+
+    #[test]
+    fn report_for_multiple_errors() {
+        let module_bytes = module_bytes();
+        let err = Processor::default()
+            .process_bytes(&module_bytes)
+            .unwrap_err();
+        let err = Error::Multiple(vec![err, Error::NotProcessed]);
+        let report = err.into_report(&module_bytes);
+        assert_eq!(report.to_string(), "2 errors occurred during processing");
+        let codes: Vec<_> = report.errors().map(Error::code).collect();
+        assert_eq!(
+            codes,
+            ["externref::incorrect_guard", "externref::not_processed"]
+        );
+
+        let related: Vec<_> = report.related().unwrap().collect();
+        assert_eq!(related.len(), 2);
+        assert!(related[0].source_code().is_some());
+        assert!(related[1].source_code().is_none());
+    }
+}
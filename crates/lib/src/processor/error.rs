@@ -1,8 +1,14 @@
 //! Processing errors.
 
-use std::{error, fmt};
+use core::{fmt, slice};
+#[cfg(feature = "std")]
+use std::{io, path::PathBuf};
 
-use crate::ReadError;
+use super::IndexType;
+use crate::{
+    alloc::{format, vec, String, ToOwned, Vec},
+    ReadError,
+};
 
 /// Location of a `Resource`: a function argument or a return type.
 #[derive(Debug)]
@@ -22,6 +28,53 @@ impl fmt::Display for Location {
     }
 }
 
+/// Location of an instruction in the original WASM module resolved from its WASM bytecode offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CodeLocation {
+    /// Zero-based index of the instruction in the function body (i.e., in the order
+    /// the instructions are listed in the text format).
+    pub instr_index: usize,
+    /// Source location of the instruction resolved using DWARF debug info. Only resolved
+    /// if the `dwarf` crate feature is enabled and the module contains debug info.
+    pub source: Option<SourceLocation>,
+}
+
+impl fmt::Display for CodeLocation {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "instruction #{}", self.instr_index)?;
+        if let Some(source) = &self.source {
+            write!(formatter, ", {source}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Source code location resolved from DWARF debug info.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SourceLocation {
+    /// Path to the source file.
+    pub file: String,
+    /// One-based line number.
+    pub line: Option<u64>,
+    /// One-based column number.
+    pub column: Option<u64>,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.file)?;
+        if let Some(line) = self.line {
+            write!(formatter, ":{line}")?;
+            if let Some(column) = self.column {
+                write!(formatter, ":{column}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Errors that can occur when [processing] a WASM module.
 ///
 /// [processing]: super::Processor::process()
@@ -40,10 +93,71 @@ pub enum Error {
         /// Name of the function.
         name: String,
     },
+    /// Several declarations of the same function in the custom section disagree
+    /// on the function signature. This can happen when linking objects compiled
+    /// with different declarations of the function, if an imported function is re-exported
+    /// and the import and export declarations disagree, or if a function exported
+    /// under several names has disagreeing declarations for its aliases.
+    ConflictingDeclarations {
+        /// Name of the module; `None` for exported functions.
+        module: Option<String>,
+        /// Name of the function.
+        name: String,
+    },
+    /// Imported function declared with a surrogate module (set via
+    /// `#[externref(surrogate_module = ..)]`) different from the module
+    /// of the [configured guard import](super::Processor::set_guard_import()).
+    SurrogateModuleMismatch {
+        /// Name of the module.
+        module: String,
+        /// Name of the function.
+        name: String,
+        /// Surrogate module recorded in the function declaration.
+        declared: String,
+        /// Module of the guard import expected by the processor.
+        expected: String,
+    },
+    /// Module contains a custom section with function declarations (i.e., one with the name
+    /// starting with [`Function::CUSTOM_SECTION_NAME`](crate::Function::CUSTOM_SECTION_NAME))
+    /// that is not configured in the processor via [`Processor::set_section_name()`]
+    /// or [`Processor::add_section()`].
+    ///
+    /// [`Processor::set_section_name()`]: super::Processor::set_section_name()
+    /// [`Processor::add_section()`]: super::Processor::add_section()
+    UnknownSection {
+        /// Name of the custom section.
+        name: String,
+    },
     /// Missing exported function with the enclosed name.
     NoExport(String),
     /// Unexpected type of an export (expected a function).
     UnexpectedExportType(String),
+    /// Export [renamed](super::Processor::rename_export()) to the enclosed name
+    /// conflicts with an existing export with the same name.
+    DuplicateExport(String),
+    /// Function with the enclosed name declared as a [table entry](crate::FunctionKind::TableEntry)
+    /// is missing from the module, or is not placed in a function table. The function is looked up
+    /// in the `name` custom section and, if it is not found there, among the module exports.
+    NoTableEntry(String),
+    /// Function declared as a [table entry](crate::FunctionKind::TableEntry) has the same WASM
+    /// signature as another function in a function table, but the functions differ
+    /// in [`Resource`](crate::Resource) args / return types. Thus, `call_indirect` instructions
+    /// with this signature cannot be patched unambiguously.
+    AmbiguousTableEntry {
+        /// Name of the declared function.
+        name: String,
+        /// Name of the other function in the table.
+        other: String,
+    },
+    /// Function declared as the [start function](crate::FunctionKind::Start) conflicts
+    /// with the start function already present in the module, or with another declared
+    /// start function.
+    ConflictingStart {
+        /// Name of the declared function.
+        name: String,
+        /// Name of the other start function.
+        other: String,
+    },
     /// Imported or exported function has unexpected arity.
     UnexpectedArity {
         /// Name of the module; `None` for exported functions.
@@ -63,9 +177,38 @@ pub enum Error {
         name: String,
         /// Location of an argument / return type in the function.
         location: Location,
-        /// Actual type of the function (the expected type is always `i32`).
+        /// Rust type name of the resource at the location, if recorded
+        /// (see [`TypeNames`](crate::TypeNames)).
+        resource_type: Option<String>,
+        /// Expected type: `i32` or `i64` depending on the [index type](super::IndexType).
+        expected_type: walrus::ValType,
+        /// Actual type of the function.
         real_type: walrus::ValType,
     },
+    /// Signature of an import from the surrogate module does not match
+    /// the [index type](super::Processor::set_index_type()) of reference IDs. This usually means
+    /// that the module was compiled for another target than the processor is configured for
+    /// (e.g., for `wasm64` with the default 32-bit index type).
+    UnexpectedIndexType {
+        /// Module of the surrogate import.
+        module: String,
+        /// Name of the surrogate import.
+        name: String,
+        /// Index type configured in the processor.
+        index_type: IndexType,
+    },
+    /// Functions declared with [`Resource`](crate::Resource)s cannot be patched since the surrogate
+    /// imports converting resources to and from `externref`s are missing from the module.
+    /// This can happen if all uses of `Resource` methods were removed by LTO, while
+    /// the declarations remained.
+    OrphanedDeclarations {
+        /// Orphaned functions as `(module, name)` tuples; the module is `None` for exported functions.
+        functions: Vec<(Option<String>, String)>,
+        /// Surrogate module (`externref` by default).
+        module: String,
+        /// Names of the missing imports from the surrogate [`module`](Self::OrphanedDeclarations::module).
+        missing_imports: Vec<String>,
+    },
 
     /// Incorrectly placed `externref` guard. This is caused by processing the WASM module
     /// with external tools (e.g., `wasm-opt`) before using this processor.
@@ -74,6 +217,8 @@ pub enum Error {
         function_name: Option<String>,
         /// WASM bytecode offset of the offending guard.
         code_offset: Option<u32>,
+        /// Location of the offending guard resolved from [`Self::IncorrectGuard::code_offset`].
+        code_location: Option<CodeLocation>,
     },
     /// Unexpected call to a function returning `externref`. Such calls should be confined
     /// in order for the processor to work properly. Like with [`Self::IncorrectGuard`],
@@ -83,10 +228,204 @@ pub enum Error {
         function_name: Option<String>,
         /// WASM bytecode offset of the offending call.
         code_offset: Option<u32>,
+        /// Location of the offending call resolved from [`Self::UnexpectedCall::code_offset`].
+        code_location: Option<CodeLocation>,
+    },
+
+    /// The module was already processed, i.e., it contains the [marker section].
+    /// Processing can be forced via [`Processor::set_force()`].
+    ///
+    /// [marker section]: super::Processor::MARKER_SECTION_NAME
+    /// [`Processor::set_force()`]: super::Processor::set_force()
+    AlreadyProcessed {
+        /// Version of the `externref` crate that processed the module, as recorded
+        /// in the marker section.
+        version: String,
+    },
+    /// The module was transformed by asyncify, which must run *after* the processor.
+    /// See [`Processor::set_asyncify_prefixes()`] for details.
+    ///
+    /// [`Processor::set_asyncify_prefixes()`]: super::Processor::set_asyncify_prefixes()
+    Asyncified {
+        /// Name of the function signalling the asyncify transform.
+        function_name: String,
+    },
+    /// The module passed to a [`PostOptimizer`](super::PostOptimizer) was not processed.
+    /// Optimizing unprocessed modules can break subsequent processing.
+    NotProcessed,
+    /// Error running a [`PostOptimizer`](super::PostOptimizer).
+    PostOptimization(anyhow::Error),
+    /// Error returned by a [`ProcessingHook`](super::ProcessingHook) or by the closure
+    /// passed to [`Processor::process_with()`](super::Processor::process_with()).
+    Hook(anyhow::Error),
+
+    /// I/O error reading or writing a file in [`Processor::process_file()`].
+    ///
+    /// [`Processor::process_file()`]: super::Processor::process_file()
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    Io {
+        /// Path to the file.
+        path: PathBuf,
+        /// Underlying I/O error.
+        error: io::Error,
+    },
+    /// Error processing a module read from a file in [`Processor::process_file()`].
+    ///
+    /// [`Processor::process_file()`]: super::Processor::process_file()
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    File {
+        /// Path to the input file.
+        path: PathBuf,
+        /// Processing error.
+        error: Box<Self>,
     },
+    /// Multiple errors collected if [continuing on error] is enabled. Errors are listed
+    /// in the order they were encountered; the vector always contains at least 2 errors.
+    ///
+    /// [continuing on error]: super::Processor::set_continue_on_error()
+    Multiple(Vec<Self>),
+}
+
+impl Error {
+    /// Returns a stable machine-readable code of this error, e.g. `externref::incorrect_guard`.
+    /// For [`Self::File`], returns the code of the wrapped error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Read(_) => "externref::read",
+            Self::Wasm(_) => "externref::wasm",
+            Self::UnexpectedImportType { .. } => "externref::unexpected_import_type",
+            Self::ConflictingDeclarations { .. } => "externref::conflicting_declarations",
+            Self::SurrogateModuleMismatch { .. } => "externref::surrogate_module_mismatch",
+            Self::UnknownSection { .. } => "externref::unknown_section",
+            Self::NoExport(_) => "externref::no_export",
+            Self::UnexpectedExportType(_) => "externref::unexpected_export_type",
+            Self::DuplicateExport(_) => "externref::duplicate_export",
+            Self::NoTableEntry(_) => "externref::no_table_entry",
+            Self::AmbiguousTableEntry { .. } => "externref::ambiguous_table_entry",
+            Self::ConflictingStart { .. } => "externref::conflicting_start",
+            Self::UnexpectedArity { .. } => "externref::unexpected_arity",
+            Self::UnexpectedType { .. } => "externref::unexpected_type",
+            Self::UnexpectedIndexType { .. } => "externref::unexpected_index_type",
+            Self::OrphanedDeclarations { .. } => "externref::orphaned_declarations",
+            Self::IncorrectGuard { .. } => "externref::incorrect_guard",
+            Self::UnexpectedCall { .. } => "externref::unexpected_call",
+            Self::AlreadyProcessed { .. } => "externref::already_processed",
+            Self::Asyncified { .. } => "externref::asyncified",
+            Self::NotProcessed => "externref::not_processed",
+            Self::PostOptimization(_) => "externref::post_optimization",
+            Self::Hook(_) => "externref::hook",
+            #[cfg(feature = "std")]
+            Self::Io { .. } => "externref::io",
+            #[cfg(feature = "std")]
+            Self::File { error, .. } => error.code(),
+            Self::Multiple(_) => "externref::multiple",
+        }
+    }
+
+    /// Returns a suggestion on fixing this error, if any.
+    pub fn help(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::IncorrectGuard { .. } | Self::UnexpectedCall { .. } => {
+                "run WASM manipulation tools such as `wasm-opt` *after* the externref processor, \
+                 or use `Processor::set_post_optimizer()`"
+            }
+            Self::Asyncified { .. } => "run asyncify (`wasm-opt --asyncify`) *after* the externref processor",
+            Self::SurrogateModuleMismatch { .. } => {
+                "set the guard import via `Processor::set_guard_import()` to match the surrogate module"
+            }
+            Self::UnexpectedIndexType { .. } => {
+                "set the index type matching the module target via `Processor::set_index_type()` \
+                 (e.g., `IndexType::I64` for `wasm64` modules)"
+            }
+            Self::UnknownSection { .. } => "add the section via `Processor::add_section()`",
+            Self::NoTableEntry(_) => {
+                "do not strip the `name` custom section before processing, or export the entry \
+                 under its name (e.g., via `#[externref(indirect_export)]`)"
+            }
+            Self::OrphanedDeclarations { .. } => {
+                "ensure that the module is linked without removing `externref` surrogate imports \
+                 still referenced by function declarations"
+            }
+            Self::AlreadyProcessed { .. } => {
+                "enable forced processing via `Processor::set_force()` to process the module again"
+            }
+            Self::NotProcessed => "run the externref processor before the post-optimizer",
+            #[cfg(feature = "std")]
+            Self::File { error, .. } => return error.help(),
+            _ => return None,
+        })
+    }
+
+    /// Returns the name of the function associated with this error, such as the function
+    /// containing an [incorrectly placed guard](Self::IncorrectGuard).
+    pub fn function_name(&self) -> Option<&str> {
+        match self {
+            Self::IncorrectGuard { function_name, .. }
+            | Self::UnexpectedCall { function_name, .. } => function_name.as_deref(),
+            Self::Asyncified { function_name } => Some(function_name),
+            #[cfg(feature = "std")]
+            Self::File { error, .. } => error.function_name(),
+            _ => None,
+        }
+    }
+
+    /// Returns the WASM bytecode offset of the instruction associated with this error, if any.
+    pub fn code_offset(&self) -> Option<u32> {
+        match self {
+            Self::IncorrectGuard { code_offset, .. } | Self::UnexpectedCall { code_offset, .. } => {
+                *code_offset
+            }
+            #[cfg(feature = "std")]
+            Self::File { error, .. } => error.code_offset(),
+            _ => None,
+        }
+    }
+
+    /// Returns the resolved location of the instruction associated with this error, if any.
+    pub fn code_location(&self) -> Option<&CodeLocation> {
+        match self {
+            Self::IncorrectGuard { code_location, .. }
+            | Self::UnexpectedCall { code_location, .. } => code_location.as_ref(),
+            #[cfg(feature = "std")]
+            Self::File { error, .. } => error.code_location(),
+            _ => None,
+        }
+    }
+
+    /// Returns the errors wrapped by this error: all errors for [`Self::Multiple`],
+    /// or a single-element slice with this error otherwise.
+    pub fn errors(&self) -> &[Self] {
+        match self {
+            Self::Multiple(errors) => errors,
+            _ => slice::from_ref(self),
+        }
+    }
+}
+
+fn module_descr(module: Option<&str>) -> String {
+    module.map_or_else(String::new, |module| format!(" imported from `{module}`"))
+}
+
+fn code_location(
+    function_name: Option<&str>,
+    code_offset: Option<u32>,
+    code_location: Option<&CodeLocation>,
+) -> String {
+    let function_name = function_name.unwrap_or("(unnamed function)");
+    let mut location = code_offset.map_or_else(
+        || function_name.to_owned(),
+        |offset| format!("{function_name} at {offset}"),
+    );
+    if let Some(code_location) = code_location {
+        location = format!("{location} ({code_location})");
+    }
+    location
 }
 
 impl fmt::Display for Error {
+    #[allow(clippy::too_many_lines)] // a simple `match` on all variants
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         const EXTERNAL_TOOL_TIP: &str = "This can be caused by an external WASM manipulation tool \
             such as `wasm-opt`. Please run such tools *after* the externref processor.";
@@ -102,6 +441,36 @@ impl fmt::Display for Error {
                 )
             }
 
+            Self::ConflictingDeclarations { module, name } => {
+                let module_descr = module_descr(module.as_deref());
+                write!(
+                    formatter,
+                    "conflicting declarations for function `{name}`{module_descr}"
+                )
+            }
+
+            Self::SurrogateModuleMismatch {
+                module,
+                name,
+                declared,
+                expected,
+            } => {
+                write!(
+                    formatter,
+                    "imported function `{module}::{name}` is declared with surrogate module \
+                     `{declared}`, while the processor expects the guard import from `{expected}`; \
+                     set the guard import via `Processor::set_guard_import()` to match"
+                )
+            }
+
+            Self::UnknownSection { name } => {
+                write!(
+                    formatter,
+                    "module contains function declarations in custom section `{name}` \
+                     not configured in the processor; add it via `Processor::add_section()`"
+                )
+            }
+
             Self::NoExport(name) => {
                 write!(formatter, "missing exported function `{name}`")
             }
@@ -111,6 +480,33 @@ impl fmt::Display for Error {
                     "unexpected type of export `{name}`; expected a function"
                 )
             }
+            Self::DuplicateExport(name) => {
+                write!(
+                    formatter,
+                    "renamed export `{name}` conflicts with an existing export"
+                )
+            }
+            Self::NoTableEntry(name) => {
+                write!(
+                    formatter,
+                    "missing function table entry `{name}`; note that table entries are resolved \
+                     using the `name` custom section or module exports"
+                )
+            }
+            Self::AmbiguousTableEntry { name, other } => {
+                write!(
+                    formatter,
+                    "function table entry `{name}` has the same signature as `{other}`, \
+                     but different resource args / return types; indirect calls \
+                     with this signature cannot be patched"
+                )
+            }
+            Self::ConflictingStart { name, other } => {
+                write!(
+                    formatter,
+                    "declared start function `{name}` conflicts with start function `{other}`"
+                )
+            }
 
             Self::UnexpectedArity {
                 module,
@@ -118,9 +514,7 @@ impl fmt::Display for Error {
                 expected_arity,
                 real_arity,
             } => {
-                let module_descr = module
-                    .as_ref()
-                    .map_or_else(String::new, |module| format!(" imported from `{module}`"));
+                let module_descr = module_descr(module.as_deref());
                 write!(
                     formatter,
                     "unexpected arity for function `{name}`{module_descr}: \
@@ -131,50 +525,173 @@ impl fmt::Display for Error {
                 module,
                 name,
                 location,
+                resource_type,
+                expected_type,
                 real_type,
             } => {
-                let module_descr = module
+                let module_descr = module_descr(module.as_deref());
+                let resource_descr = resource_type
                     .as_ref()
-                    .map_or_else(String::new, |module| format!(" imported from `{module}`"));
+                    .map_or_else(String::new, |ty| format!(" (`Resource<{ty}>`)"));
+                write!(
+                    formatter,
+                    "{location}{resource_descr} of function `{name}`{module_descr} has \
+                     unexpected type; expected {expected_type}, got {real_type}"
+                )
+            }
+            Self::UnexpectedIndexType {
+                module,
+                name,
+                index_type,
+            } => {
+                let id_type = match index_type {
+                    IndexType::I32 => "i32",
+                    IndexType::I64 => "i64",
+                };
                 write!(
                     formatter,
-                    "{location} of function `{name}`{module_descr} has unexpected type; \
-                     expected `i32`, got {real_type}"
+                    "signature of surrogate import `{module}::{name}` does not match \
+                     the configured index type; expected `{id_type}` reference IDs"
+                )
+            }
+            Self::OrphanedDeclarations {
+                functions,
+                module,
+                missing_imports,
+            } => {
+                let functions: Vec<_> = functions
+                    .iter()
+                    .map(|(module, name)| format!("`{name}`{}", module_descr(module.as_deref())))
+                    .collect();
+                let missing_imports: Vec<_> = missing_imports
+                    .iter()
+                    .map(|name| format!("`{module}::{name}`"))
+                    .collect();
+                write!(
+                    formatter,
+                    "functions {} are declared with resources, but the module lacks \
+                     surrogate imports {} needed to patch them; this can happen if all uses \
+                     of `Resource` methods were removed by LTO",
+                    functions.join(", "),
+                    missing_imports.join(", ")
                 )
             }
 
             Self::IncorrectGuard {
                 function_name,
                 code_offset,
+                code_location: resolved_location,
             } => {
-                let function_name = function_name
-                    .as_ref()
-                    .map_or("(unnamed function)", String::as_str);
-                let code_offset = code_offset
-                    .as_ref()
-                    .map_or_else(String::new, |offset| format!(" at {offset}"));
+                let location = code_location(
+                    function_name.as_deref(),
+                    *code_offset,
+                    resolved_location.as_ref(),
+                );
                 write!(
                     formatter,
-                    "incorrectly placed externref guard in {function_name}{code_offset}. \
-                     {EXTERNAL_TOOL_TIP}"
+                    "incorrectly placed externref guard in {location}. {EXTERNAL_TOOL_TIP}"
                 )
             }
             Self::UnexpectedCall {
                 function_name,
                 code_offset,
+                code_location: resolved_location,
             } => {
-                let function_name = function_name
-                    .as_ref()
-                    .map_or("(unnamed function)", String::as_str);
-                let code_offset = code_offset
-                    .as_ref()
-                    .map_or_else(String::new, |offset| format!(" at {offset}"));
+                let location = code_location(
+                    function_name.as_deref(),
+                    *code_offset,
+                    resolved_location.as_ref(),
+                );
                 write!(
                     formatter,
                     "unexpected call to an `externref`-returning function \
-                     in {function_name}{code_offset}. {EXTERNAL_TOOL_TIP}"
+                     in {location}. {EXTERNAL_TOOL_TIP}"
+                )
+            }
+
+            Self::AlreadyProcessed { version } => {
+                write!(
+                    formatter,
+                    "module was already processed (by externref v{version}); \
+                     use forced processing to process it again"
+                )
+            }
+            Self::Asyncified { function_name } => {
+                write!(
+                    formatter,
+                    "module was transformed by asyncify (found function `{function_name}`); \
+                     asyncify must run *after* the externref processor"
+                )
+            }
+            Self::NotProcessed => {
+                write!(
+                    formatter,
+                    "module was not processed by the externref processor. {EXTERNAL_TOOL_TIP}"
                 )
             }
+            Self::PostOptimization(err) => write!(formatter, "failed optimizing module: {err}"),
+            Self::Hook(err) => write!(formatter, "processing hook failed: {err}"),
+
+            #[cfg(feature = "std")]
+            Self::Io { path, error } => {
+                write!(formatter, "I/O error on `{}`: {error}", path.display())
+            }
+            #[cfg(feature = "std")]
+            Self::File { path, error } => {
+                write!(formatter, "failed processing `{}`: {error}", path.display())
+            }
+            Self::Multiple(errors) => {
+                write!(
+                    formatter,
+                    "{} errors occurred during processing:",
+                    errors.len()
+                )?;
+                for (idx, err) in errors.iter().enumerate() {
+                    write!(formatter, "\n{}. {err}", idx + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Handler of errors related to individual functions. Depending on the processor configuration,
+/// either fails on the first error, or collects all errors.
+#[derive(Debug)]
+pub(crate) struct ErrorCollector {
+    continue_on_error: bool,
+    errors: Vec<Error>,
+}
+
+impl ErrorCollector {
+    pub fn new(continue_on_error: bool) -> Self {
+        Self {
+            continue_on_error,
+            errors: vec![],
+        }
+    }
+
+    /// Handles the result of a fallible operation. Returns `Ok(None)` if the error was collected.
+    pub fn check<T>(&mut self, result: Result<T, Error>) -> Result<Option<T>, Error> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self.continue_on_error => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%err, "collected processing error");
+                self.errors.push(err);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns collected errors, if any.
+    pub fn finish(self) -> Result<(), Error> {
+        let mut errors = self.errors;
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::Multiple(errors)),
         }
     }
 }
@@ -185,11 +702,14 @@ impl From<ReadError> for Error {
     }
 }
 
-impl error::Error for Error {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::Read(err) => Some(err),
-            Self::Wasm(err) => Some(err.as_ref()),
+            Self::Wasm(err) | Self::PostOptimization(err) | Self::Hook(err) => Some(err.as_ref()),
+            Self::Io { error, .. } => Some(error),
+            Self::File { error, .. } => Some(error.as_ref()),
             _ => None,
         }
     }
@@ -0,0 +1,175 @@
+//! Corpus of WAT modules used in the processor tests.
+//!
+//! Each [`Fixture`] consists of a module in the WebAssembly text format and the [`Function`]
+//! declarations the module should be processed with. Fixtures can be processed with
+//! the [`golden`](super::golden) helpers, e.g. to regression-test forks of the processor
+//! or [hooks](super::ProcessingHook) against the same corpus as the crate itself.
+//!
+//! Besides the basic modules, the corpus contains modules with different placements
+//! of the surrogate `guard` calls, as emitted by different rustc versions and optimization
+//! settings ([`GUARD_LEADING`], [`GUARD_AFTER_STACK_FRAME`], [`GUARD_INLINED`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use externref::processor::{fixtures, process_bytes};
+//!
+//! for fixture in fixtures::ALL {
+//!     process_bytes(&fixture.module_bytes())?;
+//! }
+//! # Ok::<_, externref::processor::Error>(())
+//! ```
+
+use std::fmt::Write as _;
+
+use crate::{BitSlice, Function, FunctionKind, TypeNames};
+
+/// Declaration of the imported `arena::alloc` function used by all fixtures.
+/// Corresponds to `fn alloc(arena: &Resource<Arena>, cap: usize) -> Option<Resource<Bytes>>`.
+pub const ARENA_ALLOC: Function<'static> = Function {
+    kind: FunctionKind::Import("arena"),
+    name: "alloc",
+    externrefs: BitSlice::builder::<1>(3)
+        .with_set_bit(0)
+        .with_set_bit(2)
+        .build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+
+/// Declaration of the exported `test` function used by all fixtures.
+/// Corresponds to `fn test(arena: &Resource<Arena>)`.
+pub const TEST: Function<'static> = Function {
+    kind: FunctionKind::Export,
+    name: "test",
+    externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+
+const BASIC_DECLARATIONS: &[Function<'static>] = &[ARENA_ALLOC, TEST];
+
+/// WAT module used as a processor test fixture.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Fixture {
+    /// Name of the fixture, e.g. `simple`. Names are unique within the corpus.
+    pub name: &'static str,
+    /// Module in the WebAssembly text format. The module does not contain function declarations.
+    pub wat: &'static str,
+    /// Function declarations to process the module with.
+    pub declarations: &'static [Function<'static>],
+}
+
+impl Fixture {
+    const fn basic(name: &'static str, wat: &'static str) -> Self {
+        Self {
+            name,
+            wat,
+            declarations: BASIC_DECLARATIONS,
+        }
+    }
+
+    /// Returns the module text with [declarations](Self::declarations) added
+    /// as the custom section annotation. The returned text can be processed via
+    /// [`Processor::process_wat()`](super::Processor::process_wat()).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the module text does not end with a closing paren. This never happens
+    /// for fixtures in the corpus.
+    pub fn annotated_wat(&self) -> String {
+        let mut section = vec![];
+        for declaration in self.declarations {
+            declaration.write_to_vec(&mut section);
+        }
+        let mut escaped = String::with_capacity(section.len() * 3);
+        for byte in section {
+            write!(escaped, "\\{byte:02x}").unwrap();
+        }
+
+        let wat = self.wat.trim_end();
+        let wat = wat
+            .strip_suffix(')')
+            .expect("module text must end with `)`");
+        format!(
+            "{wat}\n  (@custom \"{}\" \"{escaped}\")\n)\n",
+            Function::CUSTOM_SECTION_NAME
+        )
+    }
+
+    /// Returns the binary module with [declarations](Self::declarations) placed
+    /// in the custom section.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the module text cannot be parsed. This never happens for fixtures
+    /// in the corpus.
+    pub fn module_bytes(&self) -> Vec<u8> {
+        wat::parse_str(self.annotated_wat()).expect("cannot parse fixture module")
+    }
+}
+
+/// Module with an exported function calling an imported function, with all calls
+/// to surrogate imports inlined.
+pub const SIMPLE: Fixture =
+    Fixture::basic("simple", include_str!("../../tests/modules/simple.wast"));
+
+/// Same as [`SIMPLE`], but with some `externref`s assigned to locals rather than inlined,
+/// and with a guarded internal function.
+pub const SIMPLE_NO_INLINE: Fixture = Fixture::basic(
+    "simple-no-inline",
+    include_str!("../../tests/modules/simple-no-inline.wast"),
+);
+
+/// Same as [`SIMPLE`], but with resource kinds tracked by the `externref` macro.
+pub const SIMPLE_KINDS: Fixture = Fixture::basic(
+    "simple-kinds",
+    include_str!("../../tests/modules/simple-kinds.wast"),
+);
+
+/// Same as [`SIMPLE`], but with resources pushed to the resource table.
+pub const SIMPLE_TABLE: Fixture = Fixture::basic(
+    "simple-table",
+    include_str!("../../tests/modules/simple-table.wast"),
+);
+
+/// Same as [`SIMPLE`], but with a resource stored in the resource global.
+pub const SIMPLE_GLOBAL: Fixture = Fixture::basic(
+    "simple-global",
+    include_str!("../../tests/modules/simple-global.wast"),
+);
+
+/// Module with a wrapper of an imported function starting with the guard call.
+pub const GUARD_LEADING: Fixture = Fixture::basic(
+    "guard-leading",
+    include_str!("../../tests/modules/guard-leading.wast"),
+);
+
+/// Module with a wrapper of an imported function calling the guard after allocating
+/// a shadow stack frame.
+pub const GUARD_AFTER_STACK_FRAME: Fixture = Fixture::basic(
+    "guard-after-stack-frame",
+    include_str!("../../tests/modules/guard-after-stack-frame.wast"),
+);
+
+/// Module with a wrapper of an imported function inlined into the exported function,
+/// so that the guard call starts the exported function.
+pub const GUARD_INLINED: Fixture = Fixture::basic(
+    "guard-inlined",
+    include_str!("../../tests/modules/guard-inlined.wast"),
+);
+
+/// All fixtures in the corpus.
+pub const ALL: &[Fixture] = &[
+    SIMPLE,
+    SIMPLE_NO_INLINE,
+    SIMPLE_KINDS,
+    SIMPLE_TABLE,
+    SIMPLE_GLOBAL,
+    GUARD_LEADING,
+    GUARD_AFTER_STACK_FRAME,
+    GUARD_INLINED,
+];
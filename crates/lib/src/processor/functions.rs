@@ -1,183 +1,2481 @@
 //! Patched functions for working with `externref`s.
 
-use std::{
-    cmp,
-    collections::{HashMap, HashSet},
-};
+use core::{cmp, iter, mem};
 
 use walrus::{
-    ir::{self, BinaryOp},
-    Function, FunctionBuilder, FunctionId, FunctionKind as WasmFunctionKind, ImportKind,
-    InstrLocId, InstrSeqBuilder, LocalFunction, LocalId, Module, ModuleImports, RefType, TableId,
+    ir::{self, BinaryOp, UnaryOp},
+    ConstExpr, ElementItems, ElementKind, Function, FunctionBuilder, FunctionId,
+    FunctionKind as WasmFunctionKind, GlobalId, ImportKind, InstrLocId, InstrSeqBuilder,
+    LocalFunction, LocalId, Module, ModuleDebugData, ModuleImports, RefType, TableId, TypeId,
     ValType,
 };
 
-use super::{Error, Processor, EXTERNREF};
+use super::{
+    debug_info::resolve_location,
+    error::ErrorCollector,
+    scan::{FunctionRefs, FunctionScan},
+    DropReentrancy, DropSlotValue, Error, ImportName, ImportShimVisibility, IndexType,
+    Partitioning, ProcessingArtifacts, Processor, EXTERNREF,
+};
+use crate::alloc::{format, vec, HashMap, String, ToOwned, Vec};
 
 #[derive(Debug)]
 pub(crate) struct ExternrefImports {
     insert: Option<FunctionId>,
     get: Option<FunctionId>,
     drop: Option<FunctionId>,
+    drop_all: Option<FunctionId>,
+    drop_range: Option<FunctionId>,
     guard: Option<FunctionId>,
+    set_error_code: Option<FunctionId>,
+    is_live: Option<FunctionId>,
+    table_push: Option<FunctionId>,
+    table_get: Option<FunctionId>,
+    table_len: Option<FunctionId>,
+    table_clear: Option<FunctionId>,
+    global_get: Option<FunctionId>,
+    global_set: Option<FunctionId>,
+    share: Option<FunctionId>,
+    get_shared: Option<FunctionId>,
+    drop_shared: Option<FunctionId>,
+    /// Imports returning resource kind IDs, sorted by the kind name.
+    kinds: Vec<(String, FunctionId)>,
+}
+
+impl ExternrefImports {
+    /// Default module of surrogate imports.
+    pub const MODULE_NAME: &'static str = "externref";
+    /// Prefix of names of imports returning resource kind IDs.
+    pub const KIND_PREFIX: &'static str = "kind::";
+    /// Names of imports used by [`ResourceTable`](crate::ResourceTable)s.
+    pub const RESOURCE_TABLE_FNS: [&'static str; 4] =
+        ["table_push", "table_get", "table_len", "table_clear"];
+    /// Names of imports used by [`GlobalResource`](crate::GlobalResource)s.
+    pub const RESOURCE_GLOBAL_FNS: [&'static str; 2] = ["global_get", "global_set"];
+    /// Names of imports used by [`SharedResource`](crate::SharedResource)s.
+    pub const SHARED_FNS: [&'static str; 3] = ["share", "get_shared", "drop_shared"];
+    /// Module of host imports called by the functions replacing [`Self::SHARED_FNS`].
+    pub const SHARED_MODULE_NAME: &'static str = "externref_shared";
+
+    pub fn new(
+        imports: &mut ModuleImports,
+        module: &str,
+        guard_import: (&str, &str),
+    ) -> Result<Self, Error> {
+        let (guard_module, guard_name) = guard_import;
+        let take_import =
+            |imports: &mut ModuleImports, name: &str| Self::take_import(imports, module, name);
+        Ok(Self {
+            insert: take_import(imports, "insert")?,
+            get: take_import(imports, "get")?,
+            drop: take_import(imports, "drop")?,
+            drop_all: take_import(imports, "drop_all")?,
+            drop_range: take_import(imports, "drop_range")?,
+            guard: Self::take_import(imports, guard_module, guard_name)?,
+            set_error_code: take_import(imports, "set_error_code")?,
+            is_live: take_import(imports, "is_live")?,
+            table_push: take_import(imports, Self::RESOURCE_TABLE_FNS[0])?,
+            table_get: take_import(imports, Self::RESOURCE_TABLE_FNS[1])?,
+            table_len: take_import(imports, Self::RESOURCE_TABLE_FNS[2])?,
+            table_clear: take_import(imports, Self::RESOURCE_TABLE_FNS[3])?,
+            global_get: take_import(imports, Self::RESOURCE_GLOBAL_FNS[0])?,
+            global_set: take_import(imports, Self::RESOURCE_GLOBAL_FNS[1])?,
+            share: take_import(imports, Self::SHARED_FNS[0])?,
+            get_shared: take_import(imports, Self::SHARED_FNS[1])?,
+            drop_shared: take_import(imports, Self::SHARED_FNS[2])?,
+            kinds: Self::take_kind_imports(imports, module)?,
+        })
+    }
+
+    fn take_kind_imports(
+        imports: &mut ModuleImports,
+        module: &str,
+    ) -> Result<Vec<(String, FunctionId)>, Error> {
+        let kind_imports = imports.iter().filter_map(|import| {
+            if import.module != module {
+                return None;
+            }
+            let kind_name = import.name.strip_prefix(Self::KIND_PREFIX)?;
+            Some((import.id(), kind_name.to_owned()))
+        });
+        let kind_imports: Vec<_> = kind_imports.collect();
+
+        let mut kinds = Vec::with_capacity(kind_imports.len());
+        for (import_id, kind_name) in kind_imports {
+            let ImportKind::Function(fn_id) = imports.get(import_id).kind else {
+                return Err(Error::UnexpectedImportType {
+                    module: module.to_owned(),
+                    name: imports.get(import_id).name.clone(),
+                });
+            };
+            imports.delete(import_id);
+            kinds.push((kind_name, fn_id));
+        }
+        kinds.sort_unstable();
+        Ok(kinds)
+    }
+
+    fn take_import(
+        imports: &mut ModuleImports,
+        module: &str,
+        name: &str,
+    ) -> Result<Option<FunctionId>, Error> {
+        let fn_id = imports
+            .find(module, name)
+            .map(|import_id| match imports.get(import_id).kind {
+                ImportKind::Function(fn_id) => {
+                    imports.delete(import_id);
+                    Ok(fn_id)
+                }
+                _ => Err(Error::UnexpectedImportType {
+                    module: module.to_owned(),
+                    name: name.to_owned(),
+                }),
+            });
+        fn_id.transpose()
+    }
+
+    /// Checks that the surrogate imports adapted to the index type have the matching signatures.
+    /// Otherwise, replacing the imports would produce an invalid module.
+    pub fn check_signatures(
+        &self,
+        module: &Module,
+        index_type: IndexType,
+        surrogate_module: &str,
+    ) -> Result<(), Error> {
+        type Signature<'a> = (&'a [ValType], &'a [ValType]);
+
+        let id = index_type.val_type();
+        let expected_signatures: [(&str, Option<FunctionId>, &[Signature<'_>]); 5] = [
+            // Modules compiled with older crate versions do not pass the resource kind.
+            (
+                "insert",
+                self.insert,
+                &[(&[id, ValType::I32], &[id]), (&[id], &[id])],
+            ),
+            ("get", self.get, &[(&[id], &[id])]),
+            ("drop", self.drop, &[(&[id], &[])]),
+            ("drop_range", self.drop_range, &[(&[id, id], &[])]),
+            ("is_live", self.is_live, &[(&[id], &[ValType::I32])]),
+        ];
+
+        for (name, fn_id, signatures) in expected_signatures {
+            let Some(fn_id) = fn_id else {
+                continue;
+            };
+            let (params, results) = module.types.params_results(module.funcs.get(fn_id).ty());
+            if !signatures.contains(&(params, results)) {
+                return Err(Error::UnexpectedIndexType {
+                    module: surrogate_module.to_owned(),
+                    name: name.to_owned(),
+                    index_type,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns names and IDs of the imports that are replaced with local functions.
+    fn replaced_imports(&self) -> impl Iterator<Item = (String, FunctionId)> + '_ {
+        let named_imports = [
+            ("insert", self.insert),
+            ("get", self.get),
+            ("drop", self.drop),
+            ("drop_all", self.drop_all),
+            ("drop_range", self.drop_range),
+            ("set_error_code", self.set_error_code),
+            ("is_live", self.is_live),
+            (Self::RESOURCE_TABLE_FNS[0], self.table_push),
+            (Self::RESOURCE_TABLE_FNS[1], self.table_get),
+            (Self::RESOURCE_TABLE_FNS[2], self.table_len),
+            (Self::RESOURCE_TABLE_FNS[3], self.table_clear),
+            (Self::RESOURCE_GLOBAL_FNS[0], self.global_get),
+            (Self::RESOURCE_GLOBAL_FNS[1], self.global_set),
+            (Self::SHARED_FNS[0], self.share),
+            (Self::SHARED_FNS[1], self.get_shared),
+            (Self::SHARED_FNS[2], self.drop_shared),
+        ];
+        let named_imports = named_imports
+            .into_iter()
+            .filter_map(|(name, fn_id)| Some((name.to_owned(), fn_id?)));
+        let kind_imports = self
+            .kinds
+            .iter()
+            .map(|(name, fn_id)| (format!("{}{name}", Self::KIND_PREFIX), *fn_id));
+        named_imports.chain(kind_imports)
+    }
+
+    /// Checks whether the module uses the [resource table](crate::ResourceTable).
+    fn uses_resource_table(&self) -> bool {
+        self.table_push.is_some()
+            || self.table_get.is_some()
+            || self.table_len.is_some()
+            || self.table_clear.is_some()
+    }
+
+    /// Checks whether the module uses the [resource global](crate::GlobalResource).
+    fn uses_resource_global(&self) -> bool {
+        self.global_get.is_some() || self.global_set.is_some()
+    }
+}
+
+/// Tables and globals used by the patched functions for a single partition
+/// of the `externref`s table.
+#[derive(Debug, Clone, Copy)]
+struct RefTables {
+    /// Table with `externref`s. This table is (optionally) exported.
+    refs: TableId,
+    /// Shadow table tracking occupied slots in `refs`. Only present if dropped slots
+    /// are filled with tombstones, since WASM cannot compare `externref`s.
+    occupancy: Option<TableId>,
+    /// Global containing the tombstone reference.
+    tombstone: Option<GlobalId>,
+    /// Imported function selecting a slot for an inserted reference.
+    slot_allocator: Option<FunctionId>,
+    /// Resource kinds of slots in `refs`. Only present if the drop hook receives
+    /// the resource kind.
+    kinds: Option<KindSource>,
+    /// Globals with resource metrics. Only present if metrics are enabled and are not
+    /// updated by the dispatching functions (see [`Partitions`]).
+    metrics: Option<ResourceMetrics>,
+    /// Number of low slots reserved for the host. These slots are never allocated, dropped
+    /// or moved by the patched functions.
+    reserved: i32,
+}
+
+/// Source of resource kinds for the drop hook.
+#[derive(Debug, Clone, Copy)]
+enum KindSource {
+    /// Kinds are tracked for each slot.
+    Tables(KindTables),
+    /// All references in the table have the same kind, which is the case for per-kind partitions.
+    Fixed(i32),
+}
+
+/// Tables tracking resource kinds. Since WASM tables cannot contain `i32`s, kinds are encoded
+/// as references to marker functions returning the kind ID.
+#[derive(Debug, Clone, Copy)]
+struct KindTables {
+    /// Kind markers for each slot in the `externref`s table; null for slots with unknown kind.
+    slots: TableId,
+    /// Immutable table with kind markers indexed by the kind ID.
+    markers: TableId,
+    /// Type of marker functions, `() -> i32`.
+    marker_ty: TypeId,
+}
+
+impl KindTables {
+    fn new(module: &mut Module, marker_fns: &[FunctionId]) -> Self {
+        let slots = module.tables.add_local(false, 0, None, RefType::Funcref);
+        Self {
+            slots,
+            markers: add_fn_table(module, marker_fns),
+            marker_ty: module.types.add(&[], &[ValType::I32]),
+        }
+    }
+}
+
+/// Adds an immutable table containing references to the specified functions.
+fn add_fn_table(module: &mut Module, fns: &[FunctionId]) -> TableId {
+    let fn_count = fns.len() as u64;
+    let table = module
+        .tables
+        .add_local(false, fn_count, Some(fn_count), RefType::Funcref);
+    let elements_id = module.elements.add(
+        ElementKind::Active {
+            table,
+            offset: ConstExpr::Value(ir::Value::I32(0)),
+        },
+        ElementItems::Functions(fns.to_vec()),
+    );
+    // Link the segment to the table; otherwise, it will be removed during GC.
+    module
+        .tables
+        .get_mut(table)
+        .elem_segments
+        .insert(elements_id);
+    table
+}
+
+/// Exported mutable `i32` globals tracking the number of live references.
+#[derive(Debug, Clone, Copy)]
+struct ResourceMetrics {
+    /// Current number of live references.
+    live_count: GlobalId,
+    /// Maximum number of simultaneously live references.
+    peak_count: GlobalId,
+}
+
+impl ResourceMetrics {
+    fn new(module: &mut Module) -> Self {
+        let [live_count, peak_count] = [Processor::LIVE_COUNT_EXPORT, Processor::PEAK_COUNT_EXPORT]
+            .map(|name| {
+                let global = module.globals.add_local(
+                    ValType::I32,
+                    true,
+                    false,
+                    ConstExpr::Value(ir::Value::I32(0)),
+                );
+                module.exports.add(name, global);
+                global
+            });
+        Self {
+            live_count,
+            peak_count,
+        }
+    }
+
+    // Implements the following logic:
+    //
+    // ```
+    // live_count += 1;
+    // if live_count > peak_count {
+    //     peak_count = live_count;
+    // }
+    // ```
+    fn increment(self, builder: &mut InstrSeqBuilder<'_>) {
+        builder
+            .global_get(self.live_count)
+            .i32_const(1)
+            .binop(BinaryOp::I32Add)
+            .global_set(self.live_count)
+            .global_get(self.live_count)
+            .global_get(self.peak_count)
+            .binop(BinaryOp::I32GtU)
+            .if_else(
+                None,
+                |new_peak| {
+                    new_peak
+                        .global_get(self.live_count)
+                        .global_set(self.peak_count);
+                },
+                |_| {},
+            );
+    }
+
+    fn decrement(self, builder: &mut InstrSeqBuilder<'_>) {
+        builder
+            .global_get(self.live_count)
+            .i32_const(1)
+            .binop(BinaryOp::I32Sub)
+            .global_set(self.live_count);
+    }
+}
+
+/// Drop hook imported from the host.
+#[derive(Debug, Clone, Copy)]
+struct DropHook {
+    fn_id: FunctionId,
+    guard: Option<DropGuard>,
+}
+
+/// Reentrancy guard for the drop hook; see [`DropReentrancy`].
+#[derive(Debug, Clone, Copy)]
+struct DropGuard {
+    /// Global set while the drop hook is running.
+    active: GlobalId,
+    policy: DropReentrancy,
+    /// Only present for [`DropReentrancy::Queue`].
+    queue: Option<DropQueue>,
+}
+
+impl DropGuard {
+    fn new(
+        module: &mut Module,
+        processor: &Processor<'_>,
+        kind_fns: &[FunctionId],
+    ) -> Option<Self> {
+        let policy = processor.drop_reentrancy;
+        if policy == DropReentrancy::Allow {
+            return None;
+        }
+        let active = module.globals.add_local(
+            ValType::I32,
+            true,
+            false,
+            ConstExpr::Value(ir::Value::I32(0)),
+        );
+        let queue = (policy == DropReentrancy::Queue).then(|| DropQueue {
+            refs: module.tables.add_local(false, 0, None, RefType::Externref),
+            kinds: processor
+                .drop_fn_with_kind
+                .then(|| KindTables::new(module, kind_fns)),
+            len: module.globals.add_local(
+                ValType::I32,
+                true,
+                false,
+                ConstExpr::Value(ir::Value::I32(0)),
+            ),
+        });
+        Some(Self {
+            active,
+            policy,
+            queue,
+        })
+    }
+}
+
+/// Stack of references with deferred drop hook calls.
+#[derive(Debug, Clone, Copy)]
+struct DropQueue {
+    refs: TableId,
+    /// Kinds of deferred references. Only present if the drop hook receives the resource kind.
+    kinds: Option<KindTables>,
+    /// Global containing the number of deferred references.
+    len: GlobalId,
+}
+
+/// Table backing [`ResourceTable`](crate::ResourceTable)s together with its length.
+#[derive(Debug, Clone, Copy)]
+struct ResourceTable {
+    table: TableId,
+    /// Global containing the number of pushed elements.
+    len: GlobalId,
+}
+
+impl ResourceTable {
+    fn new(module: &mut Module, processor: &Processor<'_>) -> Self {
+        let table = module.tables.add_local(false, 0, None, RefType::Externref);
+        if let Some(table_name) = processor.resource_table_name {
+            module.exports.add(table_name, table);
+        }
+        let len = module.globals.add_local(
+            ValType::I32,
+            true,
+            false,
+            ConstExpr::Value(ir::Value::I32(0)),
+        );
+        Self { table, len }
+    }
+}
+
+impl RefTables {
+    fn new(
+        module: &mut Module,
+        export_name: Option<&str>,
+        (tombstone, slot_allocator): (Option<GlobalId>, Option<FunctionId>),
+        kinds: Option<KindSource>,
+        metrics: Option<ResourceMetrics>,
+        reserved: u32,
+    ) -> Self {
+        let initial_size = u64::from(reserved);
+        let refs = module
+            .tables
+            .add_local(false, initial_size, None, RefType::Externref);
+        if let Some(export_name) = export_name {
+            module.exports.add(export_name, refs);
+        }
+        let occupancy = tombstone.is_some().then(|| {
+            module
+                .tables
+                .add_local(false, initial_size, None, RefType::Externref)
+        });
+        Self {
+            refs,
+            occupancy,
+            tombstone,
+            slot_allocator,
+            kinds,
+            metrics,
+            reserved: i32::try_from(reserved).expect("too many reserved slots"),
+        }
+    }
+
+    /// Returns the table that should be scanned for free slots.
+    fn scanned(&self) -> TableId {
+        self.occupancy.unwrap_or(self.refs)
+    }
+
+    /// Pushes a non-zero value if the slot index in the `idx` local exceeds the first
+    /// non-reserved slot, i.e., if a downward scan of the table should continue.
+    fn push_is_above_reserved(self, builder: &mut InstrSeqBuilder<'_>, idx: LocalId) {
+        builder.local_get(idx);
+        if self.reserved > 0 {
+            builder.i32_const(self.reserved).binop(BinaryOp::I32GtU);
+        }
+    }
+}
+
+impl IndexType {
+    pub(super) fn val_type(self) -> ValType {
+        match self {
+            Self::I32 => ValType::I32,
+            Self::I64 => ValType::I64,
+        }
+    }
+
+    /// Pushes the ID from the `id` local onto the stack, converting it to an `i32` table index.
+    /// Traps if the ID does not fit into an `i32`, so that out-of-range IDs are not wrapped
+    /// to valid indices.
+    fn push_as_i32(self, builder: &mut InstrSeqBuilder<'_>, id: LocalId) {
+        match self {
+            Self::I32 => {
+                builder.local_get(id);
+            }
+            Self::I64 => {
+                // The conversion is lossless iff sign-extending the wrapped value
+                // produces the original ID; this covers the `-1` sentinel.
+                builder
+                    .local_get(id)
+                    .unop(UnaryOp::I32WrapI64)
+                    .unop(UnaryOp::I64ExtendSI32)
+                    .local_get(id)
+                    .binop(BinaryOp::I64Ne)
+                    .if_else(
+                        None,
+                        |out_of_range| {
+                            out_of_range.unreachable();
+                        },
+                        |_| {},
+                    )
+                    .local_get(id)
+                    .unop(UnaryOp::I32WrapI64);
+            }
+        }
+    }
+
+    /// Converts the `i32` table index on top of the stack into an ID. The `-1` sentinel
+    /// is mapped to `-1`.
+    fn convert_from_i32(self, builder: &mut InstrSeqBuilder<'_>) {
+        match self {
+            Self::I32 => { /* no conversion necessary */ }
+            Self::I64 => {
+                builder.unop(UnaryOp::I64ExtendSI32);
+            }
+        }
+    }
+
+    /// Adds a function adapting the signature of the specified function to this index type.
+    /// `index_params` are indices of ID params; if `index_result` is set, the (only) result
+    /// of the function is an ID as well. Returns `fn_id` if no adaptation is required.
+    fn adapt_fn(
+        self,
+        module: &mut Module,
+        fn_id: FunctionId,
+        index_params: &[usize],
+        index_result: bool,
+    ) -> FunctionId {
+        if self == Self::I32 {
+            return fn_id;
+        }
+
+        let (params, results) = module.types.params_results(module.funcs.get(fn_id).ty());
+        let mut params = params.to_vec();
+        let mut results = results.to_vec();
+        for &idx in index_params {
+            params[idx] = self.val_type();
+        }
+        if index_result {
+            results[0] = self.val_type();
+        }
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        if let Some(name) = &module.funcs.get(fn_id).name {
+            builder.name(format!("{name}_i64"));
+        }
+        let args: Vec<_> = params.iter().map(|&ty| module.locals.add(ty)).collect();
+        let mut instr_builder = builder.func_body();
+        for (idx, &arg) in args.iter().enumerate() {
+            if index_params.contains(&idx) {
+                self.push_as_i32(&mut instr_builder, arg);
+            } else {
+                instr_builder.local_get(arg);
+            }
+        }
+        instr_builder.call(fn_id);
+        if index_result {
+            self.convert_from_i32(&mut instr_builder);
+        }
+        builder.finish(args, &mut module.funcs)
+    }
+}
+
+/// Partitions of the `externref`s table; see [`Partitioning`].
+#[derive(Debug)]
+struct Partitions {
+    /// Partition 0 contains references of unknown kind; partition `k + 1` contains references
+    /// of kind `k`. If the table is not partitioned, there is a single partition.
+    tables: Vec<RefTables>,
+    /// Globals with resource metrics updated by the dispatching functions. Only present
+    /// if metrics are enabled and there are multiple partitions.
+    metrics: Option<ResourceMetrics>,
+}
+
+impl Partitions {
+    fn new(
+        module: &mut Module,
+        processor: &Processor<'_>,
+        kinds: &[(String, FunctionId)],
+        kind_fns: &[FunctionId],
+    ) -> Self {
+        let tombstone = match processor.drop_slot_value {
+            DropSlotValue::Null => None,
+            DropSlotValue::Tombstone {
+                module: module_name,
+                name,
+            } => {
+                let global = module.add_import_global(module_name, name, EXTERNREF, false, false);
+                Some(global.0)
+            }
+        };
+        let slot_allocator = processor.slot_allocator.map(|(module_name, name)| {
+            let ty = module.types.add(&[], &[ValType::I32]);
+            module.add_import_func(module_name, name, ty).0
+        });
+        let imports = (tombstone, slot_allocator);
+        let metrics = processor
+            .resource_metrics
+            .then(|| ResourceMetrics::new(module));
+
+        // If there are no resource kinds, partitioning is meaningless.
+        if processor.partitioning == Partitioning::Shared || kinds.is_empty() {
+            let kinds = processor
+                .drop_fn_with_kind
+                .then(|| KindSource::Tables(KindTables::new(module, kind_fns)));
+            let tables = RefTables::new(
+                module,
+                processor.table_name,
+                imports,
+                kinds,
+                metrics,
+                processor.reserved_slots,
+            );
+            return Self {
+                tables: vec![tables],
+                metrics: None,
+            };
+        }
+
+        let kind_names = iter::once(None).chain(kinds.iter().map(|(name, _)| Some(name)));
+        let tables = kind_names.enumerate().map(|(partition, kind_name)| {
+            let export_name = processor.table_name.map(|table_name| match kind_name {
+                Some(kind_name) => format!("{table_name}::{kind_name}"),
+                None => table_name.to_owned(),
+            });
+            let kind_id = i32::try_from(partition).expect("too many resource kinds") - 1;
+            let kinds = processor
+                .drop_fn_with_kind
+                .then_some(KindSource::Fixed(kind_id));
+            RefTables::new(
+                module,
+                export_name.as_deref(),
+                imports,
+                kinds,
+                None,
+                processor.reserved_slots,
+            )
+        });
+        Self {
+            tables: tables.collect(),
+            metrics,
+        }
+    }
+
+    /// Returns the only partition if the table is not partitioned.
+    fn single(&self) -> Option<RefTables> {
+        match self.tables.as_slice() {
+            [tables] => Some(*tables),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    // ^ The number of partitions is bounded by the number of resource kinds, which fits into `i32`
+    fn count(&self) -> i32 {
+        self.tables.len() as i32
+    }
+
+    /// Creates a function for each partition and an immutable table with references
+    /// to these functions, which is used by the dispatching function.
+    /// Returns the table together with the type of partition functions.
+    fn add_fns(
+        &self,
+        module: &mut Module,
+        mut patch_fn: impl FnMut(&mut Module, RefTables) -> FunctionId,
+    ) -> (TableId, TypeId) {
+        let fns: Vec<_> = self
+            .tables
+            .iter()
+            .enumerate()
+            .map(|(partition, &tables)| {
+                let fn_id = patch_fn(module, tables);
+                let function = module.funcs.get_mut(fn_id);
+                if let Some(name) = &mut function.name {
+                    *name = format!("{name}_{partition}");
+                }
+                fn_id
+            })
+            .collect();
+        let fn_ty = module.funcs.get(fns[0]).ty();
+        (add_fn_table(module, &fns), fn_ty)
+    }
+
+    // Pushes the slot index and the partition index encoded in the reference ID onto the stack,
+    // and calls the partition function.
+    fn dispatch(
+        &self,
+        builder: &mut InstrSeqBuilder<'_>,
+        id: LocalId,
+        (fn_table, fn_ty): (TableId, TypeId),
+    ) {
+        builder
+            .local_get(id)
+            .i32_const(self.count())
+            .binop(BinaryOp::I32DivU)
+            .local_get(id)
+            .i32_const(self.count())
+            .binop(BinaryOp::I32RemU)
+            .call_indirect(fn_ty, fn_table);
+    }
 }
 
-impl ExternrefImports {
-    const MODULE_NAME: &'static str = "externref";
+/// Way to read a reference from the `externref`s table by its ID.
+#[derive(Debug, Clone, Copy)]
+enum RefAccess {
+    /// Direct access to the table.
+    Table(TableId),
+    /// Calling a function with the `(i32) -> externref` signature.
+    Function(FunctionId),
+}
+
+impl RefAccess {
+    fn push_ref(self, builder: &mut InstrSeqBuilder<'_>, id: LocalId) {
+        builder.local_get(id);
+        match self {
+            Self::Table(table_id) => builder.table_get(table_id),
+            Self::Function(fn_id) => builder.call(fn_id),
+        };
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PatchedFunctions {
+    fn_mapping: HashMap<FunctionId, FunctionId>,
+    get_ref_id: Option<FunctionId>,
+    guard_id: Option<FunctionId>,
+    error_code_id: Option<GlobalId>,
+    /// Names of replaced surrogate imports together with IDs of the replacing functions.
+    surrogates: Vec<(String, FunctionId)>,
+    check_leaks_id: Option<FunctionId>,
+    artifacts: ProcessingArtifacts,
+}
+
+impl PatchedFunctions {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", name = "patch_imports", skip_all)
+    )]
+    #[allow(clippy::too_many_lines)] // straightforward patching of surrogate imports one by one
+    pub fn new(module: &mut Module, imports: &ExternrefImports, processor: &Processor<'_>) -> Self {
+        let mut fn_mapping = HashMap::with_capacity(3 + imports.kinds.len());
+        let mut kind_fns = Vec::with_capacity(imports.kinds.len());
+        for (kind_id, (kind_name, fn_id)) in imports.kinds.iter().enumerate() {
+            module.funcs.delete(*fn_id);
+            let kind_fn_id = Self::patch_kind_fn(module, kind_name, kind_id);
+            fn_mapping.insert(*fn_id, kind_fn_id);
+            kind_fns.push(kind_fn_id);
+        }
+
+        let partitions = Partitions::new(module, processor, &imports.kinds, &kind_fns);
+        let check_leaks_id = processor
+            .leak_check
+            .map(|leak_check| Self::patch_check_leaks_fn(module, &partitions, leak_check.hook));
+        if processor.snapshot_helpers {
+            let snapshot_len_id = Self::patch_snapshot_len_fn(module, &partitions);
+            module
+                .exports
+                .add(Processor::SNAPSHOT_LEN_EXPORT, snapshot_len_id);
+            let restore_prepare_id = Self::patch_restore_prepare_fn(module, &partitions);
+            module
+                .exports
+                .add(Processor::RESTORE_PREPARE_EXPORT, restore_prepare_id);
+        }
+        if let Some((module_name, name)) = processor.compaction_hook {
+            let ty = module.types.add(&[ValType::I32, ValType::I32], &[]);
+            let move_fn_id = module.add_import_func(module_name, name, ty).0;
+            let compact_id = Self::patch_compact(module, &partitions, move_fn_id);
+            module.exports.add(Processor::COMPACT_EXPORT, compact_id);
+        }
+        let mut get_ref_id = None;
+        let exports_shims = processor.shim_visibility != ImportShimVisibility::Internal;
+        // The insertion function is used by `ResourceTable::get()`, `GlobalResource::get()`
+        // and `SharedResource::to_resource()` as well.
+        let insert_fn_id = (exports_shims
+            || imports.insert.is_some()
+            || imports.table_get.is_some()
+            || imports.global_get.is_some()
+            || imports.get_shared.is_some())
+        .then(|| Self::patch_insert(module, &partitions));
+
+        if let (Some(fn_id), Some(insert_fn_id)) = (imports.insert, insert_fn_id) {
+            // Modules compiled with older crate versions do not pass the resource kind.
+            let import_ty = module.types.get(module.funcs.get(fn_id).ty());
+            let passes_kind = import_ty.params().len() > 1;
+            module.funcs.delete(fn_id);
+            let patched_fn_id = if passes_kind {
+                insert_fn_id
+            } else {
+                Self::patch_insert_without_kind_fn(module, insert_fn_id)
+            };
+            let patched_fn_id = processor
+                .index_type
+                .adapt_fn(module, patched_fn_id, &[], true);
+            fn_mapping.insert(fn_id, patched_fn_id);
+        }
+
+        if let Some(fn_id) = imports.get {
+            module.funcs.delete(fn_id);
+            let patched_fn_id = Self::patch_get(module, &partitions);
+            let adapted_fn_id = processor
+                .index_type
+                .adapt_fn(module, patched_fn_id, &[0], false);
+            fn_mapping.insert(fn_id, adapted_fn_id);
+            get_ref_id = Some(patched_fn_id);
+        }
+
+        let mut drop_ref_id = None;
+        // The drop function is used by bulk drop functions as well.
+        if exports_shims
+            || imports.drop.is_some()
+            || imports.drop_all.is_some()
+            || imports.drop_range.is_some()
+        {
+            let drop_hook = processor.drop_fn_name.map(|(module_name, name)| {
+                let ty = if processor.drop_fn_with_kind {
+                    module.types.add(&[EXTERNREF, ValType::I32], &[])
+                } else {
+                    module.types.add(&[EXTERNREF], &[])
+                };
+                DropHook {
+                    fn_id: module.add_import_func(module_name, name, ty).0,
+                    guard: DropGuard::new(module, processor, &kind_fns),
+                }
+            });
+            let patched_fn_id = Self::patch_drop(module, &partitions, drop_hook);
+            if let Some(fn_id) = imports.drop {
+                module.funcs.delete(fn_id);
+                let adapted_fn_id =
+                    processor
+                        .index_type
+                        .adapt_fn(module, patched_fn_id, &[0], false);
+                fn_mapping.insert(fn_id, adapted_fn_id);
+            }
+            drop_ref_id = Some(patched_fn_id);
+        }
+        if let (Some(fn_id), Some(drop_ref_id)) = (imports.drop_all, drop_ref_id) {
+            module.funcs.delete(fn_id);
+            let patched_fn_id = Self::patch_drop_all_fn(module, &partitions, drop_ref_id);
+            fn_mapping.insert(fn_id, patched_fn_id);
+        }
+        if let (Some(fn_id), Some(drop_ref_id)) = (imports.drop_range, drop_ref_id) {
+            module.funcs.delete(fn_id);
+            let is_occupied_id = Self::patch_is_live(module, &partitions, true);
+            let patched_fn_id = Self::patch_drop_range_fn(module, is_occupied_id, drop_ref_id);
+            let adapted_fn_id =
+                processor
+                    .index_type
+                    .adapt_fn(module, patched_fn_id, &[0, 1], false);
+            fn_mapping.insert(fn_id, adapted_fn_id);
+        }
+
+        let mut error_code_id = None;
+        if let Some(fn_id) = imports.set_error_code {
+            module.funcs.delete(fn_id);
+            let global_id = module.globals.add_local(
+                ValType::I32,
+                true,
+                false,
+                ConstExpr::Value(ir::Value::I32(0)),
+            );
+            fn_mapping.insert(fn_id, Self::patch_set_error_code_fn(module, global_id));
+            error_code_id = Some(global_id);
+        }
+
+        if let Some(fn_id) = imports.is_live {
+            module.funcs.delete(fn_id);
+            let patched_fn_id = Self::patch_is_live(module, &partitions, false);
+            let adapted_fn_id = processor
+                .index_type
+                .adapt_fn(module, patched_fn_id, &[0], false);
+            fn_mapping.insert(fn_id, adapted_fn_id);
+        }
+
+        // Unlike `get_ref_id`, may be used by the processor only (by `ResourceTable` functions).
+        let mut get_fn_id = get_ref_id;
+        if imports.uses_resource_table() {
+            let resource_table = ResourceTable::new(module, processor);
+            let ref_access = match partitions.single() {
+                Some(tables) => RefAccess::Table(tables.refs),
+                None => RefAccess::Function(
+                    *get_fn_id.get_or_insert_with(|| Self::patch_get(module, &partitions)),
+                ),
+            };
+            Self::patch_resource_table_fns(
+                module,
+                imports,
+                ref_access,
+                resource_table,
+                insert_fn_id,
+                &mut fn_mapping,
+            );
+        }
+
+        if imports.uses_resource_global() {
+            let global_id = module.globals.add_local(
+                EXTERNREF,
+                true,
+                false,
+                ConstExpr::RefNull(RefType::Externref),
+            );
+            if let Some(global_name) = processor.resource_global_name {
+                module.exports.add(global_name, global_id);
+            }
+
+            if let (Some(fn_id), Some(insert_fn_id)) = (imports.global_get, insert_fn_id) {
+                module.funcs.delete(fn_id);
+                let patched_fn_id = Self::patch_global_get_fn(module, global_id, insert_fn_id);
+                fn_mapping.insert(fn_id, patched_fn_id);
+            }
+            if let Some(fn_id) = imports.global_set {
+                // The getter handles null references (i.e., the -1 ID).
+                let get_fn_id =
+                    *get_fn_id.get_or_insert_with(|| Self::patch_get(module, &partitions));
+                module.funcs.delete(fn_id);
+                let patched_fn_id = Self::patch_global_set_fn(module, global_id, get_fn_id);
+                fn_mapping.insert(fn_id, patched_fn_id);
+            }
+        }
+
+        if let Some(fn_id) = imports.share {
+            let ref_access = match partitions.single() {
+                Some(tables) => RefAccess::Table(tables.refs),
+                None => RefAccess::Function(
+                    *get_fn_id.get_or_insert_with(|| Self::patch_get(module, &partitions)),
+                ),
+            };
+            module.funcs.delete(fn_id);
+            fn_mapping.insert(fn_id, Self::patch_share_fn(module, ref_access));
+        }
+        if let (Some(fn_id), Some(insert_fn_id)) = (imports.get_shared, insert_fn_id) {
+            module.funcs.delete(fn_id);
+            fn_mapping.insert(fn_id, Self::patch_get_shared_fn(module, insert_fn_id));
+        }
+        if let Some(fn_id) = imports.drop_shared {
+            // The host import has the same signature, so it is called directly.
+            module.funcs.delete(fn_id);
+            let ty = module.types.add(&[ValType::I32], &[]);
+            let host_fn_id = module
+                .add_import_func(ExternrefImports::SHARED_MODULE_NAME, "drop", ty)
+                .0;
+            fn_mapping.insert(fn_id, host_fn_id);
+        }
+
+        if let ImportShimVisibility::Exported { insert, get, drop } = processor.shim_visibility {
+            // All functions are created above if shims are exported.
+            let get_fn_id = *get_fn_id.get_or_insert_with(|| Self::patch_get(module, &partitions));
+            module.exports.add(insert, insert_fn_id.unwrap());
+            module.exports.add(get, get_fn_id);
+            module.exports.add(drop, drop_ref_id.unwrap());
+        }
+
+        let surrogates = imports
+            .replaced_imports()
+            .filter_map(|(name, fn_id)| Some((name, *fn_mapping.get(&fn_id)?)))
+            .collect();
+        let artifacts = ProcessingArtifacts {
+            insert_fn: insert_fn_id,
+            get_fn: get_fn_id,
+            drop_fn: drop_ref_id,
+            ref_table: partitions.tables.first().map(|tables| tables.refs),
+            kind_tables: partitions
+                .tables
+                .iter()
+                .skip(1)
+                .map(|tables| tables.refs)
+                .collect(),
+        };
+        Self {
+            fn_mapping,
+            get_ref_id,
+            guard_id: imports.guard,
+            error_code_id,
+            surrogates,
+            check_leaks_id,
+            artifacts,
+        }
+    }
+
+    fn patch_insert(module: &mut Module, partitions: &Partitions) -> FunctionId {
+        if let Some(tables) = partitions.single() {
+            return Self::patch_insert_fn(module, tables);
+        }
+
+        // We want to implement the following logic:
+        //
+        // ```
+        // let partition = if (kind as u32) < kind_count { kind + 1 } else { 0 };
+        // let slot = partition_inserts[partition](value, kind);
+        // if slot == -1 {
+        //     return -1;
+        // }
+        // slot * partition_count + partition
+        // ```
+        let partition_fns = partitions.add_fns(module, Self::patch_insert_fn);
+        let count = partitions.count();
+        let mut builder = FunctionBuilder::new(
+            &mut module.types,
+            &[EXTERNREF, ValType::I32],
+            &[ValType::I32],
+        );
+        builder.name("externref_insert".to_owned());
+        let value = module.locals.add(EXTERNREF);
+        let kind = module.locals.add(ValType::I32);
+        let partition = module.locals.add(ValType::I32);
+        let slot = module.locals.add(ValType::I32);
+        let (fn_table, fn_ty) = partition_fns;
+        builder
+            .func_body()
+            .local_get(kind)
+            .i32_const(count - 1)
+            .binop(BinaryOp::I32LtU)
+            .if_else(
+                ValType::I32,
+                |known_kind| {
+                    known_kind
+                        .local_get(kind)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add);
+                },
+                |unknown_kind| {
+                    unknown_kind.i32_const(0);
+                },
+            )
+            .local_set(partition)
+            .local_get(value)
+            .local_get(kind)
+            .local_get(partition)
+            .call_indirect(fn_ty, fn_table)
+            .local_tee(slot)
+            .i32_const(-1)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |value_is_null| {
+                    value_is_null.i32_const(-1).return_();
+                },
+                |_| {},
+            );
+        let mut instr_builder = builder.func_body();
+        if let Some(metrics) = partitions.metrics {
+            metrics.increment(&mut instr_builder);
+        }
+        instr_builder
+            .local_get(slot)
+            .i32_const(count)
+            .binop(BinaryOp::I32Mul)
+            .local_get(partition)
+            .binop(BinaryOp::I32Add);
+        builder.finish(vec![value, kind], &mut module.funcs)
+    }
+
+    /// Inserts a reference with an unknown kind.
+    fn patch_insert_without_kind_fn(module: &mut Module, insert_fn_id: FunctionId) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[EXTERNREF], &[ValType::I32]);
+        builder.name("externref_insert_without_kind".to_owned());
+        let value = module.locals.add(EXTERNREF);
+        builder
+            .func_body()
+            .local_get(value)
+            .i32_const(-1)
+            .call(insert_fn_id);
+        builder.finish(vec![value], &mut module.funcs)
+    }
+
+    fn patch_get(module: &mut Module, partitions: &Partitions) -> FunctionId {
+        if let Some(tables) = partitions.single() {
+            return Self::patch_get_fn(module, tables.refs);
+        }
+
+        let partition_fns = partitions.add_fns(module, |module, tables| {
+            Self::patch_get_fn(module, tables.refs)
+        });
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[EXTERNREF]);
+        builder.name("externref_get".to_owned());
+        let id = module.locals.add(ValType::I32);
+        builder
+            .func_body()
+            .local_get(id)
+            .i32_const(-1)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                EXTERNREF,
+                |null_requested| {
+                    null_requested.ref_null(RefType::Externref);
+                },
+                |elem_requested| {
+                    partitions.dispatch(elem_requested, id, partition_fns);
+                },
+            );
+        builder.finish(vec![id], &mut module.funcs)
+    }
+
+    fn patch_drop(
+        module: &mut Module,
+        partitions: &Partitions,
+        drop_hook: Option<DropHook>,
+    ) -> FunctionId {
+        if let Some(tables) = partitions.single() {
+            return Self::patch_drop_fn(module, tables, drop_hook);
+        }
+
+        let partition_fns = partitions.add_fns(module, |module, tables| {
+            Self::patch_drop_fn(module, tables, drop_hook)
+        });
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        builder.name("externref_drop".to_owned());
+        let id = module.locals.add(ValType::I32);
+        let mut instr_builder = builder.func_body();
+        partitions.dispatch(&mut instr_builder, id, partition_fns);
+        if let Some(metrics) = partitions.metrics {
+            metrics.decrement(&mut instr_builder);
+        }
+        builder.finish(vec![id], &mut module.funcs)
+    }
+
+    /// Patches a function checking whether the slot with the specified ID is live. If `scanned`
+    /// is set, the check is performed in the [scanned table](RefTables::scanned()), i.e.,
+    /// slots filled with tombstones are considered free. Otherwise, a live slot must be non-null
+    /// both in the occupancy table (if any) and in the `externref`s table, so that dropped slots
+    /// filled with tombstones and slots nulled by the host are not live.
+    fn patch_is_live(module: &mut Module, partitions: &Partitions, scanned: bool) -> FunctionId {
+        let name = if scanned {
+            "externref_is_occupied"
+        } else {
+            "externref_is_live"
+        };
+        let checked_tables = |tables: RefTables| -> Vec<_> {
+            if scanned {
+                vec![tables.scanned()]
+            } else {
+                iter::once(tables.refs).chain(tables.occupancy).collect()
+            }
+        };
+        if let Some(tables) = partitions.single() {
+            return Self::patch_is_live_fn(module, &checked_tables(tables), name);
+        }
+
+        let partition_fns = partitions.add_fns(module, |module, tables| {
+            Self::patch_is_live_fn(module, &checked_tables(tables), name)
+        });
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        builder.name(name.to_owned());
+        let id = module.locals.add(ValType::I32);
+        partitions.dispatch(&mut builder.func_body(), id, partition_fns);
+        builder.finish(vec![id], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // if value == NULL {
+    //     return -1;
+    // }
+    // let table_len = externrefs_table.len();
+    // let mut free_idx;
+    // if table_len > 0 {
+    //     free_idx -= 1;
+    //     loop {
+    //         if externrefs_table[free_idx] == NULL {
+    //             break;
+    //         } else if free_idx == 0 {
+    //             free_idx = table_len;
+    //             break;
+    //         } else {
+    //             free_idx -= 1;
+    //         }
+    //     }
+    // } else {
+    //     free_idx = 0;
+    // };
+    // if free_idx == table_len {
+    //     externrefs_table.grow(1, value);
+    // } else {
+    //     externrefs_table[free_idx] = value;
+    // }
+    // free_idx
+    // ```
+    //
+    // If dropped slots are filled with tombstones, the free slot search is performed
+    // in the occupancy table, and both tables are updated with the inserted value.
+    // If resource kinds are tracked per slot, the kind of the inserted value is recorded as well.
+    // If a slot allocator is configured, the slot selected by it is tried first
+    // (see `Self::call_slot_allocator()`).
+    fn patch_insert_fn(module: &mut Module, tables: RefTables) -> FunctionId {
+        let mut builder = FunctionBuilder::new(
+            &mut module.types,
+            &[EXTERNREF, ValType::I32],
+            &[ValType::I32],
+        );
+        builder.name("externref_insert".to_owned());
+        let value = module.locals.add(EXTERNREF);
+        let kind = module.locals.add(ValType::I32);
+        let free_idx = module.locals.add(ValType::I32);
+        let scanned_id = tables.scanned();
+        let table_ids: Vec<_> = iter::once(tables.refs).chain(tables.occupancy).collect();
+        builder.func_body().local_get(value).ref_is_null().if_else(
+            None,
+            |value_is_null| {
+                value_is_null.i32_const(-1).return_();
+            },
+            |_| {},
+        );
+        let place_value = |builder: &mut InstrSeqBuilder<'_>| {
+            builder.table_size(scanned_id);
+            if tables.reserved > 0 {
+                builder.i32_const(tables.reserved).binop(BinaryOp::I32GtU);
+            }
+            builder
+                .if_else(
+                    None,
+                    |table_is_not_empty| {
+                        table_is_not_empty
+                            .table_size(scanned_id)
+                            .i32_const(1)
+                            .binop(BinaryOp::I32Sub)
+                            .local_set(free_idx)
+                            .block(None, |loop_wrapper| {
+                                Self::create_loop(loop_wrapper, tables, free_idx);
+                            });
+                    },
+                    |table_is_empty| {
+                        if tables.reserved > 0 {
+                            // The table only contains reserved slots.
+                            table_is_empty.table_size(scanned_id).local_set(free_idx);
+                        } else if tables.slot_allocator.is_some() {
+                            // `free_idx` is overwritten by the allocator call.
+                            table_is_empty.i32_const(0).local_set(free_idx);
+                        }
+                    },
+                )
+                .local_get(free_idx)
+                .table_size(scanned_id)
+                .binop(BinaryOp::I32Eq)
+                .if_else(
+                    None,
+                    |growth_required| {
+                        for &table_id in &table_ids {
+                            growth_required
+                                .local_get(value)
+                                .i32_const(1)
+                                .table_grow(table_id)
+                                .i32_const(-1)
+                                .binop(BinaryOp::I32Eq)
+                                .if_else(
+                                    None,
+                                    |growth_failed| {
+                                        growth_failed.unreachable();
+                                    },
+                                    |_| {},
+                                );
+                        }
+                    },
+                    |growth_not_required| {
+                        for &table_id in &table_ids {
+                            growth_not_required
+                                .local_get(free_idx)
+                                .local_get(value)
+                                .table_set(table_id);
+                        }
+                    },
+                );
+        };
+        if let Some(allocator_id) = tables.slot_allocator {
+            builder.func_body().block(None, |placed| {
+                let locals = (value, free_idx);
+                Self::call_slot_allocator(
+                    placed,
+                    allocator_id,
+                    &table_ids,
+                    tables.reserved,
+                    locals,
+                );
+                place_value(placed);
+            });
+        } else {
+            place_value(&mut builder.func_body());
+        }
+        let mut instr_builder = builder.func_body();
+        if let Some(KindSource::Tables(kinds)) = tables.kinds {
+            Self::store_kind(&mut instr_builder, kinds, free_idx, kind);
+        }
+        if let Some(metrics) = tables.metrics {
+            metrics.increment(&mut instr_builder);
+        }
+        instr_builder.local_get(free_idx);
+        builder.finish(vec![value, kind], &mut module.funcs)
+    }
+
+    // Implements the following logic, where `'placed` is the block enclosing the built-in
+    // free slot search:
+    //
+    // ```
+    // free_idx = alloc_slot();
+    // if free_idx >= reserved && (free_idx >= table_len || externrefs_table[free_idx] == NULL) {
+    //     if free_idx >= table_len {
+    //         externrefs_table.grow(free_idx + 1 - table_len, NULL);
+    //     }
+    //     externrefs_table[free_idx] = value;
+    //     break 'placed;
+    // }
+    // ```
+    //
+    // That is, negative, reserved and occupied slots returned by the allocator are ignored.
+    // `table_ids` are the tables to update; the first table is the `externref`s table,
+    // and the last one is the table scanned for free slots.
+    fn call_slot_allocator(
+        builder: &mut InstrSeqBuilder<'_>,
+        allocator_id: FunctionId,
+        table_ids: &[TableId],
+        reserved: i32,
+        (value, free_idx): (LocalId, LocalId),
+    ) {
+        let placed_id = builder.id();
+        let scanned_id = *table_ids.last().unwrap();
+        builder
+            .call(allocator_id)
+            .local_tee(free_idx)
+            .i32_const(reserved)
+            .binop(BinaryOp::I32GeS)
+            .if_else(
+                None,
+                |is_non_negative| {
+                    is_non_negative
+                        .local_get(free_idx)
+                        .table_size(scanned_id)
+                        .binop(BinaryOp::I32LtU)
+                        .if_else(
+                            ValType::I32,
+                            |in_bounds| {
+                                in_bounds
+                                    .local_get(free_idx)
+                                    .table_get(scanned_id)
+                                    .ref_is_null();
+                            },
+                            |out_of_bounds| {
+                                out_of_bounds.i32_const(1);
+                            },
+                        )
+                        .if_else(
+                            None,
+                            |is_free| {
+                                for &table_id in table_ids {
+                                    Self::grow_to_fit(is_free, table_id, free_idx);
+                                }
+                                for &table_id in table_ids {
+                                    is_free
+                                        .local_get(free_idx)
+                                        .local_get(value)
+                                        .table_set(table_id);
+                                }
+                                is_free.br(placed_id);
+                            },
+                            |_| {},
+                        );
+                },
+                |_| {},
+            );
+    }
+
+    /// Grows the `externref` table with nulls so that it contains the `idx` slot.
+    fn grow_to_fit(builder: &mut InstrSeqBuilder<'_>, table_id: TableId, idx: LocalId) {
+        builder
+            .local_get(idx)
+            .table_size(table_id)
+            .binop(BinaryOp::I32GeU)
+            .if_else(
+                None,
+                |growth_required| {
+                    growth_required
+                        .ref_null(RefType::Externref)
+                        .local_get(idx)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .table_size(table_id)
+                        .binop(BinaryOp::I32Sub)
+                        .table_grow(table_id)
+                        .i32_const(-1)
+                        .binop(BinaryOp::I32Eq)
+                        .if_else(
+                            None,
+                            |growth_failed| {
+                                growth_failed.unreachable();
+                            },
+                            |_| {},
+                        );
+                },
+                |_| {},
+            );
+    }
+
+    fn create_loop(builder: &mut InstrSeqBuilder<'_>, tables: RefTables, free_idx: LocalId) {
+        let table_id = tables.scanned();
+        let break_id = builder.id();
+        builder.loop_(None, |idx_loop| {
+            let loop_id = idx_loop.id();
+            idx_loop
+                .local_get(free_idx)
+                .table_get(table_id)
+                .ref_is_null()
+                .if_else(
+                    None,
+                    |is_null| {
+                        is_null.br(break_id);
+                    },
+                    |is_not_null| {
+                        tables.push_is_above_reserved(is_not_null, free_idx);
+                        is_not_null.if_else(
+                            None,
+                            |has_more_slots| {
+                                has_more_slots
+                                    .local_get(free_idx)
+                                    .i32_const(1)
+                                    .binop(BinaryOp::I32Sub)
+                                    .local_set(free_idx)
+                                    .br(loop_id);
+                            },
+                            |no_more_slots| {
+                                no_more_slots
+                                    .table_size(table_id)
+                                    .local_set(free_idx)
+                                    .br(break_id);
+                            },
+                        );
+                    },
+                );
+        });
+    }
+
+    fn patch_get_fn(module: &mut Module, table_id: TableId) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[EXTERNREF]);
+        builder.name("externref_get".to_owned());
+        let idx = module.locals.add(ValType::I32);
+        builder
+            .func_body()
+            .local_get(idx)
+            .i32_const(-1)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                EXTERNREF,
+                |null_requested| {
+                    null_requested.ref_null(RefType::Externref);
+                },
+                |elem_requested| {
+                    elem_requested.local_get(idx).table_get(table_id);
+                },
+            );
+        builder.finish(vec![idx], &mut module.funcs)
+    }
+
+    fn patch_drop_fn(
+        module: &mut Module,
+        tables: RefTables,
+        drop_hook: Option<DropHook>,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        builder.name("externref_drop".to_owned());
+        let idx = module.locals.add(ValType::I32);
+        // Locals for the position in the drop queue and the kind of the deferred reference.
+        let queue_locals = (
+            module.locals.add(ValType::I32),
+            module.locals.add(ValType::I32),
+        );
+
+        let mut instr_builder = builder.func_body();
+        if let Some(hook) = drop_hook {
+            if let Some(guard) = hook.guard {
+                instr_builder.global_get(guard.active).if_else(
+                    None,
+                    |reentrant| {
+                        Self::drop_reentrant(reentrant, tables, idx, guard, queue_locals);
+                    },
+                    |_| {},
+                );
+                instr_builder.i32_const(1).global_set(guard.active);
+            }
+            instr_builder.local_get(idx).table_get(tables.refs);
+            Self::push_drop_kind(&mut instr_builder, tables.kinds, idx);
+            instr_builder.call(hook.fn_id);
+            if let Some(guard) = hook.guard {
+                if let Some(queue) = guard.queue {
+                    Self::drain_drop_queue(&mut instr_builder, queue, hook.fn_id, queue_locals.0);
+                }
+                instr_builder.i32_const(0).global_set(guard.active);
+            }
+        }
+        Self::free_slot(&mut instr_builder, tables, idx);
+        builder.finish(vec![idx], &mut module.funcs)
+    }
+
+    /// Pushes the kind of the reference in the slot with the specified index onto the stack
+    /// if the drop hook receives the resource kind.
+    fn push_drop_kind(builder: &mut InstrSeqBuilder<'_>, kinds: Option<KindSource>, idx: LocalId) {
+        match kinds {
+            Some(KindSource::Tables(kinds)) => {
+                Self::push_kind(builder, kinds, idx);
+            }
+            Some(KindSource::Fixed(kind_id)) => {
+                builder.i32_const(kind_id);
+            }
+            None => { /* the drop hook does not receive the kind */ }
+        }
+    }
+
+    fn free_slot(builder: &mut InstrSeqBuilder<'_>, tables: RefTables, idx: LocalId) {
+        builder.local_get(idx);
+        if let Some(tombstone) = tables.tombstone {
+            builder.global_get(tombstone);
+        } else {
+            builder.ref_null(RefType::Externref);
+        }
+        builder.table_set(tables.refs);
+        if let Some(occupancy) = tables.occupancy {
+            builder
+                .local_get(idx)
+                .ref_null(RefType::Externref)
+                .table_set(occupancy);
+        }
+        if let Some(metrics) = tables.metrics {
+            metrics.decrement(builder);
+        }
+    }
+
+    /// Handles a drop while the drop hook is running according to the guard policy.
+    fn drop_reentrant(
+        builder: &mut InstrSeqBuilder<'_>,
+        tables: RefTables,
+        idx: LocalId,
+        guard: DropGuard,
+        (pos, kind): (LocalId, LocalId),
+    ) {
+        if guard.policy == DropReentrancy::Trap {
+            builder.unreachable();
+            return;
+        }
+
+        if let Some(queue) = guard.queue {
+            // We want to implement the following logic:
+            //
+            // ```
+            // let pos = queue_len;
+            // if pos >= queue.len() {
+            //     queue.grow(1, NULL);
+            // }
+            // queue[pos] = refs[idx];
+            // queue_kinds[pos] = kind(idx);
+            // queue_len = pos + 1;
+            // ```
+            builder
+                .global_get(queue.len)
+                .local_set(pos)
+                .local_get(pos)
+                .table_size(queue.refs)
+                .binop(BinaryOp::I32GeU)
+                .if_else(
+                    None,
+                    |growth_required| {
+                        growth_required
+                            .ref_null(RefType::Externref)
+                            .i32_const(1)
+                            .table_grow(queue.refs)
+                            .i32_const(-1)
+                            .binop(BinaryOp::I32Eq)
+                            .if_else(
+                                None,
+                                |growth_failed| {
+                                    growth_failed.unreachable();
+                                },
+                                |_| {},
+                            );
+                    },
+                    |_| {},
+                )
+                .local_get(pos)
+                .local_get(idx)
+                .table_get(tables.refs)
+                .table_set(queue.refs);
+            if let Some(queue_kinds) = queue.kinds {
+                Self::push_drop_kind(builder, tables.kinds, idx);
+                builder.local_set(kind);
+                Self::store_kind(builder, queue_kinds, pos, kind);
+            }
+            builder
+                .local_get(pos)
+                .i32_const(1)
+                .binop(BinaryOp::I32Add)
+                .global_set(queue.len);
+        }
+        Self::free_slot(builder, tables, idx);
+        builder.return_();
+    }
+
+    // Implements the following logic:
+    //
+    // ```
+    // while queue_len > 0 {
+    //     queue_len -= 1;
+    //     let pos = queue_len;
+    //     let (ref, kind) = (queue[pos], queue_kinds[pos]);
+    //     queue[pos] = NULL;
+    //     drop_hook(ref, kind);
+    // }
+    // ```
+    fn drain_drop_queue(
+        builder: &mut InstrSeqBuilder<'_>,
+        queue: DropQueue,
+        drop_fn_id: FunctionId,
+        pos: LocalId,
+    ) {
+        builder.block(None, |drain| {
+            let break_id = drain.id();
+            drain.loop_(None, |drain_loop| {
+                let loop_id = drain_loop.id();
+                drain_loop
+                    .global_get(queue.len)
+                    .unop(UnaryOp::I32Eqz)
+                    .br_if(break_id)
+                    .global_get(queue.len)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Sub)
+                    .local_tee(pos)
+                    .global_set(queue.len)
+                    .local_get(pos)
+                    .table_get(queue.refs);
+                if let Some(kinds) = queue.kinds {
+                    Self::push_kind(drain_loop, kinds, pos);
+                }
+                // The slot must be cleared before calling the hook since the hook may defer
+                // more drops, which will reuse the slot.
+                drain_loop
+                    .local_get(pos)
+                    .ref_null(RefType::Externref)
+                    .table_set(queue.refs)
+                    .call(drop_fn_id)
+                    .br(loop_id);
+            });
+        });
+    }
+
+    // Implements the following logic:
+    //
+    // ```
+    // for (partition, table) in scanned_tables.enumerate() {
+    //     let mut idx = table.len();
+    //     while idx > 0 {
+    //         idx -= 1;
+    //         if table[idx] != NULL {
+    //             drop_ref(idx * partition_count + partition);
+    //         }
+    //     }
+    // }
+    // ```
+    fn patch_drop_all_fn(
+        module: &mut Module,
+        partitions: &Partitions,
+        drop_ref_id: FunctionId,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.name("externref_drop_all".to_owned());
+        let idx = module.locals.add(ValType::I32);
+        let mut instr_builder = builder.func_body();
+        for (partition, tables) in (0..partitions.count()).zip(&partitions.tables) {
+            let scanned_id = tables.scanned();
+            instr_builder
+                .table_size(scanned_id)
+                .local_set(idx)
+                .block(None, |loop_wrapper| {
+                    let break_id = loop_wrapper.id();
+                    loop_wrapper.loop_(None, |idx_loop| {
+                        let loop_id = idx_loop.id();
+                        tables.push_is_above_reserved(idx_loop, idx);
+                        idx_loop
+                            .unop(UnaryOp::I32Eqz)
+                            .br_if(break_id)
+                            .local_get(idx)
+                            .i32_const(1)
+                            .binop(BinaryOp::I32Sub)
+                            .local_tee(idx)
+                            .table_get(scanned_id)
+                            .ref_is_null()
+                            .if_else(
+                                None,
+                                |_| {},
+                                |is_live| {
+                                    is_live
+                                        .local_get(idx)
+                                        .i32_const(partitions.count())
+                                        .binop(BinaryOp::I32Mul)
+                                        .i32_const(partition)
+                                        .binop(BinaryOp::I32Add)
+                                        .call(drop_ref_id);
+                                },
+                            )
+                            .br(loop_id);
+                    });
+                });
+        }
+        builder.finish(vec![], &mut module.funcs)
+    }
+
+    // Implements the following logic:
+    //
+    // ```
+    // let mut end = start + len;
+    // if end < start {
+    //     end = u32::MAX; // overflow
+    // }
+    // while start < end {
+    //     if is_occupied(start) {
+    //         drop_ref(start);
+    //     }
+    //     start += 1;
+    // }
+    // ```
+    fn patch_drop_range_fn(
+        module: &mut Module,
+        is_occupied_id: FunctionId,
+        drop_ref_id: FunctionId,
+    ) -> FunctionId {
+        let mut builder =
+            FunctionBuilder::new(&mut module.types, &[ValType::I32, ValType::I32], &[]);
+        builder.name("externref_drop_range".to_owned());
+        let id = module.locals.add(ValType::I32);
+        let len = module.locals.add(ValType::I32);
+        let end = module.locals.add(ValType::I32);
+        builder
+            .func_body()
+            .local_get(id)
+            .local_get(len)
+            .binop(BinaryOp::I32Add)
+            .local_tee(end)
+            .local_get(id)
+            .binop(BinaryOp::I32LtU)
+            .if_else(
+                None,
+                |overflow| {
+                    overflow.i32_const(-1).local_set(end);
+                },
+                |_| {},
+            )
+            .block(None, |loop_wrapper| {
+                let break_id = loop_wrapper.id();
+                loop_wrapper.loop_(None, |id_loop| {
+                    let loop_id = id_loop.id();
+                    id_loop
+                        .local_get(id)
+                        .local_get(end)
+                        .binop(BinaryOp::I32GeU)
+                        .br_if(break_id)
+                        .local_get(id)
+                        .call(is_occupied_id)
+                        .if_else(
+                            None,
+                            |is_occupied| {
+                                is_occupied.local_get(id).call(drop_ref_id);
+                            },
+                            |_| {},
+                        )
+                        .local_get(id)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .local_set(id)
+                        .br(loop_id);
+                });
+            });
+        builder.finish(vec![id, len], &mut module.funcs)
+    }
+
+    // Pushes the kind ID of the slot with the specified index onto the stack,
+    // or -1 if the kind is unknown.
+    fn push_kind(builder: &mut InstrSeqBuilder<'_>, kinds: KindTables, idx: LocalId) {
+        builder
+            .local_get(idx)
+            .table_size(kinds.slots)
+            .binop(BinaryOp::I32LtU)
+            .if_else(
+                ValType::I32,
+                |in_bounds| {
+                    in_bounds
+                        .local_get(idx)
+                        .table_get(kinds.slots)
+                        .ref_is_null()
+                        .if_else(
+                            ValType::I32,
+                            |unknown_kind| {
+                                unknown_kind.i32_const(-1);
+                            },
+                            |known_kind| {
+                                known_kind
+                                    .local_get(idx)
+                                    .call_indirect(kinds.marker_ty, kinds.slots);
+                            },
+                        );
+                },
+                |out_of_bounds| {
+                    out_of_bounds.i32_const(-1);
+                },
+            );
+    }
+
+    fn patch_kind_fn(module: &mut Module, kind_name: &str, kind_id: usize) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        builder.name(format!("externref_kind_{kind_name}"));
+        let kind_id = i32::try_from(kind_id).expect("too many resource kinds");
+        builder.func_body().i32_const(kind_id);
+        builder.finish(vec![], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // if idx >= kind_slots.len() {
+    //     kind_slots.grow(idx + 1 - kind_slots.len(), NULL);
+    // }
+    // kind_slots[idx] = if (kind as u32) < kind_markers.len() {
+    //     kind_markers[kind]
+    // } else {
+    //     NULL // the kind of the reference is unknown
+    // };
+    // ```
+    fn store_kind(
+        builder: &mut InstrSeqBuilder<'_>,
+        kinds: KindTables,
+        idx: LocalId,
+        kind: LocalId,
+    ) {
+        builder
+            .local_get(idx)
+            .table_size(kinds.slots)
+            .binop(BinaryOp::I32GeU)
+            .if_else(
+                None,
+                |growth_required| {
+                    growth_required
+                        .ref_null(RefType::Funcref)
+                        .local_get(idx)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .table_size(kinds.slots)
+                        .binop(BinaryOp::I32Sub)
+                        .table_grow(kinds.slots)
+                        .i32_const(-1)
+                        .binop(BinaryOp::I32Eq)
+                        .if_else(
+                            None,
+                            |growth_failed| {
+                                growth_failed.unreachable();
+                            },
+                            |_| {},
+                        );
+                },
+                |_| {},
+            )
+            .local_get(idx)
+            .local_get(kind)
+            .table_size(kinds.markers)
+            .binop(BinaryOp::I32LtU)
+            .if_else(
+                ValType::Ref(RefType::Funcref),
+                |known_kind| {
+                    known_kind.local_get(kind).table_get(kinds.markers);
+                },
+                |unknown_kind| {
+                    unknown_kind.ref_null(RefType::Funcref);
+                },
+            )
+            .table_set(kinds.slots);
+    }
+
+    fn patch_resource_table_fns(
+        module: &mut Module,
+        imports: &ExternrefImports,
+        ref_access: RefAccess,
+        resource_table: ResourceTable,
+        insert_fn_id: Option<FunctionId>,
+        fn_mapping: &mut HashMap<FunctionId, FunctionId>,
+    ) {
+        if let Some(fn_id) = imports.table_push {
+            module.funcs.delete(fn_id);
+            let patched_fn_id = Self::patch_table_push_fn(module, ref_access, resource_table);
+            fn_mapping.insert(fn_id, patched_fn_id);
+        }
+        if let (Some(fn_id), Some(insert_fn_id)) = (imports.table_get, insert_fn_id) {
+            module.funcs.delete(fn_id);
+            let patched_fn_id = Self::patch_table_get_fn(module, resource_table, insert_fn_id);
+            fn_mapping.insert(fn_id, patched_fn_id);
+        }
+        if let Some(fn_id) = imports.table_len {
+            module.funcs.delete(fn_id);
+            let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+            builder.name("externref_table_len".to_owned());
+            builder.func_body().global_get(resource_table.len);
+            fn_mapping.insert(fn_id, builder.finish(vec![], &mut module.funcs));
+        }
+        if let Some(fn_id) = imports.table_clear {
+            module.funcs.delete(fn_id);
+            fn_mapping.insert(fn_id, Self::patch_table_clear_fn(module, resource_table));
+        }
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // while len > 0 {
+    //     len -= 1;
+    //     resource_table[len] = NULL;
+    // }
+    // ```
+    //
+    // `table.fill` is not used since it is not supported by some tools (e.g., older `wasm-opt`).
+    fn patch_table_clear_fn(module: &mut Module, resource_table: ResourceTable) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.name("externref_table_clear".to_owned());
+        let ResourceTable { table, len } = resource_table;
+        builder.func_body().block(None, |loop_wrapper| {
+            let break_id = loop_wrapper.id();
+            loop_wrapper.loop_(None, |clear_loop| {
+                let loop_id = clear_loop.id();
+                clear_loop
+                    .global_get(len)
+                    .unop(UnaryOp::I32Eqz)
+                    .br_if(break_id)
+                    .global_get(len)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Sub)
+                    .global_set(len)
+                    .global_get(len)
+                    .ref_null(RefType::Externref)
+                    .table_set(table)
+                    .br(loop_id);
+            });
+        });
+        builder.finish(vec![], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // let value = externrefs_table[id];
+    // if len == resource_table.len() {
+    //     resource_table.grow(1, value);
+    // } else {
+    //     resource_table[len] = value;
+    // }
+    // len += 1;
+    // len - 1
+    // ```
+    fn patch_table_push_fn(
+        module: &mut Module,
+        ref_access: RefAccess,
+        resource_table: ResourceTable,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        builder.name("externref_table_push".to_owned());
+        let id = module.locals.add(ValType::I32);
+        let ResourceTable { table, len } = resource_table;
+        builder
+            .func_body()
+            .global_get(len)
+            .table_size(table)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |growth_required| {
+                    ref_access.push_ref(growth_required, id);
+                    growth_required
+                        .i32_const(1)
+                        .table_grow(table)
+                        .i32_const(-1)
+                        .binop(BinaryOp::I32Eq)
+                        .if_else(
+                            None,
+                            |growth_failed| {
+                                growth_failed.unreachable();
+                            },
+                            |_| {},
+                        );
+                },
+                |growth_not_required| {
+                    growth_not_required.global_get(len);
+                    ref_access.push_ref(growth_not_required, id);
+                    growth_not_required.table_set(table);
+                },
+            )
+            .global_get(len)
+            .global_get(len)
+            .i32_const(1)
+            .binop(BinaryOp::I32Add)
+            .global_set(len);
+        builder.finish(vec![id], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // externref_insert(resource_global, -1) // the kind of the reference is unknown
+    // ```
+    fn patch_global_get_fn(
+        module: &mut Module,
+        global_id: GlobalId,
+        insert_fn_id: FunctionId,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        builder.name("externref_global_get".to_owned());
+        builder
+            .func_body()
+            .global_get(global_id)
+            .i32_const(-1)
+            .call(insert_fn_id);
+        builder.finish(vec![], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // resource_global = externref_get(id);
+    // ```
+    fn patch_global_set_fn(
+        module: &mut Module,
+        global_id: GlobalId,
+        get_fn_id: FunctionId,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        builder.name("externref_global_set".to_owned());
+        let id = module.locals.add(ValType::I32);
+        builder
+            .func_body()
+            .local_get(id)
+            .call(get_fn_id)
+            .global_set(global_id);
+        builder.finish(vec![id], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // externref_shared::share(externrefs_table[id])
+    // ```
+    fn patch_share_fn(module: &mut Module, ref_access: RefAccess) -> FunctionId {
+        let host_ty = module.types.add(&[EXTERNREF], &[ValType::I32]);
+        let host_fn_id = module
+            .add_import_func(ExternrefImports::SHARED_MODULE_NAME, "share", host_ty)
+            .0;
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        builder.name("externref_share".to_owned());
+        let id = module.locals.add(ValType::I32);
+        let mut instr_builder = builder.func_body();
+        ref_access.push_ref(&mut instr_builder, id);
+        instr_builder.call(host_fn_id);
+        builder.finish(vec![id], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // externref_insert(externref_shared::get(handle), -1) // the kind of the reference is unknown
+    // ```
+    fn patch_get_shared_fn(module: &mut Module, insert_fn_id: FunctionId) -> FunctionId {
+        let host_ty = module.types.add(&[ValType::I32], &[EXTERNREF]);
+        let host_fn_id = module
+            .add_import_func(ExternrefImports::SHARED_MODULE_NAME, "get", host_ty)
+            .0;
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        builder.name("externref_get_shared".to_owned());
+        let handle = module.locals.add(ValType::I32);
+        builder
+            .func_body()
+            .local_get(handle)
+            .call(host_fn_id)
+            .i32_const(-1)
+            .call(insert_fn_id);
+        builder.finish(vec![handle], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // if idx >= len {
+    //     return -1;
+    // }
+    // externref_insert(resource_table[idx], -1) // the kind of the reference is unknown
+    // ```
+    fn patch_table_get_fn(
+        module: &mut Module,
+        resource_table: ResourceTable,
+        insert_fn_id: FunctionId,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        builder.name("externref_table_get".to_owned());
+        let idx = module.locals.add(ValType::I32);
+        builder
+            .func_body()
+            .local_get(idx)
+            .global_get(resource_table.len)
+            .binop(BinaryOp::I32GeU)
+            .if_else(
+                None,
+                |out_of_bounds| {
+                    out_of_bounds.i32_const(-1).return_();
+                },
+                |_| {},
+            )
+            .local_get(idx)
+            .table_get(resource_table.table)
+            .i32_const(-1)
+            .call(insert_fn_id);
+        builder.finish(vec![idx], &mut module.funcs)
+    }
+
+    // We want to implement the following logic:
+    //
+    // ```
+    // let mut idx = externrefs_table.len();
+    // let mut count = 0;
+    // while idx > 0 {
+    //     idx -= 1;
+    //     if externrefs_table[idx] != NULL {
+    //         count += 1;
+    //     }
+    // }
+    // if count != 0 {
+    //     on_leak(count);
+    // }
+    // ```
+    //
+    // If dropped slots are filled with tombstones, the occupancy table is scanned instead.
+    // If the table is partitioned, all partitions are scanned.
+    fn patch_check_leaks_fn(
+        module: &mut Module,
+        partitions: &Partitions,
+        (module_name, name): ImportName<'_>,
+    ) -> FunctionId {
+        let hook_ty = module.types.add(&[ValType::I32], &[]);
+        let hook_id = module.add_import_func(module_name, name, hook_ty).0;
 
-    pub fn new(imports: &mut ModuleImports) -> Result<Self, Error> {
-        Ok(Self {
-            insert: Self::take_import(imports, "insert")?,
-            get: Self::take_import(imports, "get")?,
-            drop: Self::take_import(imports, "drop")?,
-            guard: Self::take_import(imports, "guard")?,
-        })
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.name("externref_check_leaks".to_owned());
+        let idx = module.locals.add(ValType::I32);
+        let count = module.locals.add(ValType::I32);
+        let mut instr_builder = builder.func_body();
+        for tables in &partitions.tables {
+            let scanned_id = tables.scanned();
+            instr_builder
+                .table_size(scanned_id)
+                .local_set(idx)
+                .block(None, |loop_wrapper| {
+                    let break_id = loop_wrapper.id();
+                    loop_wrapper.loop_(None, |idx_loop| {
+                        let loop_id = idx_loop.id();
+                        tables.push_is_above_reserved(idx_loop, idx);
+                        idx_loop
+                            .unop(UnaryOp::I32Eqz)
+                            .br_if(break_id)
+                            .local_get(idx)
+                            .i32_const(1)
+                            .binop(BinaryOp::I32Sub)
+                            .local_tee(idx)
+                            .table_get(scanned_id)
+                            .ref_is_null()
+                            .if_else(
+                                None,
+                                |_| {},
+                                |is_live| {
+                                    is_live
+                                        .local_get(count)
+                                        .i32_const(1)
+                                        .binop(BinaryOp::I32Add)
+                                        .local_set(count);
+                                },
+                            )
+                            .br(loop_id);
+                    });
+                });
+        }
+        instr_builder.local_get(count).if_else(
+            None,
+            |has_leaks| {
+                has_leaks.local_get(count).call(hook_id);
+            },
+            |_| {},
+        );
+        builder.finish(vec![], &mut module.funcs)
     }
 
-    fn take_import(imports: &mut ModuleImports, name: &str) -> Result<Option<FunctionId>, Error> {
-        let fn_id = imports.find(Self::MODULE_NAME, name).map(|import_id| {
-            match imports.get(import_id).kind {
-                ImportKind::Function(fn_id) => {
-                    imports.delete(import_id);
-                    Ok(fn_id)
-                }
-                _ => Err(Error::UnexpectedImportType {
-                    module: Self::MODULE_NAME.to_owned(),
-                    name: name.to_owned(),
-                }),
-            }
-        });
-        fn_id.transpose()
+    // Implements the following logic:
+    //
+    // ```
+    // max(externrefs_table.len() for each partition)
+    // ```
+    fn patch_snapshot_len_fn(module: &mut Module, partitions: &Partitions) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        builder.name("externref_snapshot_len".to_owned());
+        let len = module.locals.add(ValType::I32);
+        let mut instr_builder = builder.func_body();
+        for tables in &partitions.tables {
+            instr_builder
+                .table_size(tables.refs)
+                .local_get(len)
+                .binop(BinaryOp::I32GtU)
+                .if_else(
+                    None,
+                    |is_longer| {
+                        is_longer.table_size(tables.refs).local_set(len);
+                    },
+                    |_| {},
+                );
+        }
+        instr_builder.local_get(len);
+        builder.finish(vec![], &mut module.funcs)
     }
-}
-
-#[derive(Debug)]
-pub(crate) struct PatchedFunctions {
-    fn_mapping: HashMap<FunctionId, FunctionId>,
-    get_ref_id: Option<FunctionId>,
-    guard_id: Option<FunctionId>,
-}
 
-impl PatchedFunctions {
-    #[cfg_attr(
-        feature = "tracing",
-        tracing::instrument(level = "debug", name = "patch_imports", skip_all)
-    )]
-    pub fn new(module: &mut Module, imports: &ExternrefImports, processor: &Processor<'_>) -> Self {
-        let table_id = module.tables.add_local(false, 0, None, RefType::Externref);
-        if let Some(table_name) = processor.table_name {
-            module.exports.add(table_name, table_id);
+    fn patch_compact(
+        module: &mut Module,
+        partitions: &Partitions,
+        move_fn_id: FunctionId,
+    ) -> FunctionId {
+        if let Some(tables) = partitions.single() {
+            return Self::patch_compact_fn(module, tables, (0, 1), move_fn_id);
         }
 
-        let mut fn_mapping = HashMap::with_capacity(3);
-        let mut get_ref_id = None;
-
-        if let Some(fn_id) = imports.insert {
-            #[cfg(feature = "tracing")]
-            tracing::debug!(name = "externref::insert", "replaced import");
-
-            module.funcs.delete(fn_id);
-            fn_mapping.insert(fn_id, Self::patch_insert_fn(module, table_id));
+        let count = partitions.count();
+        let partition_fns: Vec<_> = (0..count)
+            .zip(&partitions.tables)
+            .map(|(partition, &tables)| {
+                let fn_id = Self::patch_compact_fn(module, tables, (partition, count), move_fn_id);
+                module.funcs.get_mut(fn_id).name = Some(format!("externref_compact_{partition}"));
+                fn_id
+            })
+            .collect();
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        builder.name("externref_compact".to_owned());
+        let mut instr_builder = builder.func_body();
+        instr_builder.i32_const(0);
+        for fn_id in partition_fns {
+            instr_builder.call(fn_id).binop(BinaryOp::I32Add);
         }
+        builder.finish(vec![], &mut module.funcs)
+    }
 
-        if let Some(fn_id) = imports.get {
-            #[cfg(feature = "tracing")]
-            tracing::debug!(name = "externref::get", "replaced import");
-
-            module.funcs.delete(fn_id);
-            let patched_fn_id = Self::patch_get_fn(module, table_id);
-            fn_mapping.insert(fn_id, patched_fn_id);
-            get_ref_id = Some(patched_fn_id);
+    // Implements the following logic, where `id(slot)` is `slot * count + partition`:
+    //
+    // ```
+    // let (mut lo, mut hi, mut moved) = (reserved, externrefs_table.len() - 1, 0);
+    // while lo < hi {
+    //     if externrefs_table[lo] != NULL {
+    //         lo += 1;
+    //     } else if externrefs_table[hi] == NULL {
+    //         hi -= 1;
+    //     } else {
+    //         externrefs_table[lo] = externrefs_table[hi];
+    //         externrefs_table[hi] = NULL; // or the tombstone
+    //         move_hook(id(hi), id(lo));
+    //         moved += 1;
+    //         lo += 1;
+    //         hi -= 1;
+    //     }
+    // }
+    // moved
+    // ```
+    //
+    // Occupancy is checked using the scanned table; the occupancy and kind tables are updated
+    // together with the `externref`s table.
+    fn patch_compact_fn(
+        module: &mut Module,
+        tables: RefTables,
+        (partition, count): (i32, i32),
+        move_fn_id: FunctionId,
+    ) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        builder.name("externref_compact".to_owned());
+        let lo = module.locals.add(ValType::I32);
+        let hi = module.locals.add(ValType::I32);
+        let moved = module.locals.add(ValType::I32);
+        let scanned_id = tables.scanned();
+        let push_id = |builder: &mut InstrSeqBuilder<'_>, slot: LocalId| {
+            builder.local_get(slot);
+            if count > 1 {
+                builder
+                    .i32_const(count)
+                    .binop(BinaryOp::I32Mul)
+                    .i32_const(partition)
+                    .binop(BinaryOp::I32Add);
+            }
+        };
+        let mut instr_builder = builder.func_body();
+        if tables.reserved > 0 {
+            instr_builder.i32_const(tables.reserved).local_set(lo);
         }
+        instr_builder
+            .table_size(scanned_id)
+            .i32_const(1)
+            .binop(BinaryOp::I32Sub)
+            .local_set(hi)
+            .block(None, |done| {
+                let done_id = done.id();
+                done.loop_(None, |compact_loop| {
+                    let loop_id = compact_loop.id();
+                    compact_loop
+                        .local_get(lo)
+                        .local_get(hi)
+                        .binop(BinaryOp::I32GeS)
+                        .br_if(done_id)
+                        .local_get(lo)
+                        .table_get(scanned_id)
+                        .ref_is_null()
+                        .unop(UnaryOp::I32Eqz)
+                        .if_else(
+                            None,
+                            |lo_is_occupied| {
+                                lo_is_occupied
+                                    .local_get(lo)
+                                    .i32_const(1)
+                                    .binop(BinaryOp::I32Add)
+                                    .local_set(lo)
+                                    .br(loop_id);
+                            },
+                            |_| {},
+                        )
+                        .local_get(hi)
+                        .table_get(scanned_id)
+                        .ref_is_null()
+                        .if_else(
+                            None,
+                            |hi_is_free| {
+                                hi_is_free
+                                    .local_get(hi)
+                                    .i32_const(1)
+                                    .binop(BinaryOp::I32Sub)
+                                    .local_set(hi)
+                                    .br(loop_id);
+                            },
+                            |_| {},
+                        );
 
-        if let Some(fn_id) = imports.drop {
-            #[cfg(feature = "tracing")]
-            tracing::debug!(name = "externref::drop", "replaced import");
+                    Self::move_slot(compact_loop, tables, hi, lo);
+                    push_id(compact_loop, hi);
+                    push_id(compact_loop, lo);
+                    compact_loop
+                        .call(move_fn_id)
+                        .local_get(moved)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .local_set(moved)
+                        .local_get(lo)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Add)
+                        .local_set(lo)
+                        .local_get(hi)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Sub)
+                        .local_set(hi)
+                        .br(loop_id);
+                });
+            })
+            .local_get(moved);
+        builder.finish(vec![], &mut module.funcs)
+    }
 
-            module.funcs.delete(fn_id);
-            let drop_fn_id = processor.drop_fn_name.map(|(module_name, name)| {
-                let ty = module.types.add(&[EXTERNREF], &[]);
-                module.add_import_func(module_name, name, ty).0
-            });
-            fn_mapping.insert(fn_id, Self::patch_drop_fn(module, table_id, drop_fn_id));
+    /// Moves the reference (together with the associated occupancy and kind) from the `from` slot
+    /// to the `to` slot, marking the `from` slot as free.
+    fn move_slot(builder: &mut InstrSeqBuilder<'_>, tables: RefTables, from: LocalId, to: LocalId) {
+        let occupancy = tables
+            .occupancy
+            .map(|table_id| (table_id, RefType::Externref));
+        let kinds = match tables.kinds {
+            Some(KindSource::Tables(kinds)) => Some((kinds.slots, RefType::Funcref)),
+            _ => None,
+        };
+        let table_ids = iter::once((tables.refs, RefType::Externref))
+            .chain(occupancy)
+            .chain(kinds);
+        for (table_id, ref_ty) in table_ids {
+            builder
+                .local_get(to)
+                .local_get(from)
+                .table_get(table_id)
+                .table_set(table_id)
+                .local_get(from);
+            match tables.tombstone {
+                Some(tombstone) if table_id == tables.refs => builder.global_get(tombstone),
+                _ => builder.ref_null(ref_ty),
+            };
+            builder.table_set(table_id);
         }
+    }
 
-        Self {
-            fn_mapping,
-            get_ref_id,
-            guard_id: imports.guard,
+    // Grows all tables of each partition to at least `len` slots and clears them, so that
+    // the host can write saved references back into the `externref`s tables. If dropped slots
+    // are filled with tombstones, the first `len` slots are marked as occupied, since WASM
+    // cannot determine which of the restored slots are free.
+    fn patch_restore_prepare_fn(module: &mut Module, partitions: &Partitions) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        builder.name("externref_restore_prepare".to_owned());
+        let len = module.locals.add(ValType::I32);
+        let mut instr_builder = builder.func_body();
+        for tables in &partitions.tables {
+            Self::reset_table(
+                &mut instr_builder,
+                tables.refs,
+                RefType::Externref,
+                len,
+                None,
+            );
+            if let Some(occupancy) = tables.occupancy {
+                let tombstone = tables.tombstone;
+                Self::reset_table(
+                    &mut instr_builder,
+                    occupancy,
+                    RefType::Externref,
+                    len,
+                    tombstone,
+                );
+            }
+            if let Some(KindSource::Tables(kinds)) = tables.kinds {
+                Self::reset_table(&mut instr_builder, kinds.slots, RefType::Funcref, len, None);
+            }
         }
+        builder.finish(vec![len], &mut module.funcs)
     }
 
-    // We want to implement the following logic:
+    // Implements the following logic:
     //
     // ```
-    // if value == NULL {
-    //     return -1;
-    // }
-    // let table_len = externrefs_table.len();
-    // let mut free_idx;
-    // if table_len > 0 {
-    //     free_idx -= 1;
-    //     loop {
-    //         if externrefs_table[free_idx] == NULL {
-    //             break;
-    //         } else if free_idx == 0 {
-    //             free_idx = table_len;
-    //             break;
-    //         } else {
-    //             free_idx -= 1;
-    //         }
-    //     }
-    // } else {
-    //     free_idx = 0;
-    // };
-    // if free_idx == table_len {
-    //     externrefs_table.grow(1, value);
-    // } else {
-    //     externrefs_table[free_idx] = value;
+    // if table.len() < len {
+    //     table.grow(len - table.len(), NULL);
     // }
-    // free_idx
+    // table[..len].fill(occupied.unwrap_or(NULL));
+    // table[len..].fill(NULL);
     // ```
-    fn patch_insert_fn(module: &mut Module, table_id: TableId) -> FunctionId {
-        let mut builder = FunctionBuilder::new(&mut module.types, &[EXTERNREF], &[ValType::I32]);
-        let value = module.locals.add(EXTERNREF);
-        let free_idx = module.locals.add(ValType::I32);
+    fn reset_table(
+        builder: &mut InstrSeqBuilder<'_>,
+        table_id: TableId,
+        ref_ty: RefType,
+        len: LocalId,
+        occupied: Option<GlobalId>,
+    ) {
         builder
-            .func_body()
-            .local_get(value)
-            .ref_is_null()
-            .if_else(
-                None,
-                |value_is_null| {
-                    value_is_null.i32_const(-1).return_();
-                },
-                |_| {},
-            )
-            .table_size(table_id)
-            .if_else(
-                None,
-                |table_is_not_empty| {
-                    table_is_not_empty
-                        .table_size(table_id)
-                        .i32_const(1)
-                        .binop(BinaryOp::I32Sub)
-                        .local_set(free_idx)
-                        .block(None, |loop_wrapper| {
-                            Self::create_loop(loop_wrapper, table_id, free_idx);
-                        });
-                },
-                |_| {},
-            )
-            .local_get(free_idx)
             .table_size(table_id)
-            .binop(BinaryOp::I32Eq)
+            .local_get(len)
+            .binop(BinaryOp::I32LtU)
             .if_else(
                 None,
                 |growth_required| {
                     growth_required
-                        .local_get(value)
-                        .i32_const(1)
+                        .ref_null(ref_ty)
+                        .local_get(len)
+                        .table_size(table_id)
+                        .binop(BinaryOp::I32Sub)
                         .table_grow(table_id)
                         .i32_const(-1)
                         .binop(BinaryOp::I32Eq)
@@ -189,120 +2487,134 @@ impl PatchedFunctions {
                             |_| {},
                         );
                 },
-                |growth_not_required| {
-                    growth_not_required
-                        .local_get(free_idx)
-                        .local_get(value)
-                        .table_set(table_id);
-                },
-            )
-            .local_get(free_idx);
-        builder.finish(vec![value], &mut module.funcs)
+                |_| {},
+            );
+
+        if let Some(occupied) = occupied {
+            builder
+                .i32_const(0)
+                .global_get(occupied)
+                .local_get(len)
+                .table_fill(table_id)
+                .local_get(len)
+                .ref_null(ref_ty)
+                .table_size(table_id)
+                .local_get(len)
+                .binop(BinaryOp::I32Sub)
+                .table_fill(table_id);
+        } else {
+            builder
+                .i32_const(0)
+                .ref_null(ref_ty)
+                .table_size(table_id)
+                .table_fill(table_id);
+        }
     }
 
-    fn create_loop(builder: &mut InstrSeqBuilder<'_>, table_id: TableId, free_idx: LocalId) {
-        let break_id = builder.id();
-        builder.loop_(None, |idx_loop| {
-            let loop_id = idx_loop.id();
-            idx_loop
-                .local_get(free_idx)
-                .table_get(table_id)
-                .ref_is_null()
-                .if_else(
-                    None,
-                    |is_null| {
-                        is_null.br(break_id);
-                    },
-                    |is_not_null| {
-                        is_not_null.local_get(free_idx).if_else(
-                            None,
-                            |is_not_zero| {
-                                is_not_zero
-                                    .local_get(free_idx)
-                                    .i32_const(1)
-                                    .binop(BinaryOp::I32Sub)
-                                    .local_set(free_idx)
-                                    .br(loop_id);
-                            },
-                            |is_zero| {
-                                is_zero
-                                    .table_size(table_id)
-                                    .local_set(free_idx)
-                                    .br(break_id);
-                            },
-                        );
-                    },
-                );
-        });
+    fn patch_set_error_code_fn(module: &mut Module, global_id: GlobalId) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+        builder.name("externref_set_error_code".to_owned());
+        let code = module.locals.add(ValType::I32);
+        builder.func_body().local_get(code).global_set(global_id);
+        builder.finish(vec![code], &mut module.funcs)
     }
 
-    fn patch_get_fn(module: &mut Module, table_id: TableId) -> FunctionId {
-        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[EXTERNREF]);
+    // Checks that the slot is within the table bounds and is not null in all `table_ids`
+    // (the tables are assumed to have the same size).
+    fn patch_is_live_fn(module: &mut Module, table_ids: &[TableId], name: &str) -> FunctionId {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::I32]);
+        builder.name(name.to_owned());
         let idx = module.locals.add(ValType::I32);
         builder
             .func_body()
             .local_get(idx)
-            .i32_const(-1)
-            .binop(BinaryOp::I32Eq)
+            .table_size(table_ids[0])
+            .binop(BinaryOp::I32LtU)
             .if_else(
-                EXTERNREF,
-                |null_requested| {
-                    null_requested.ref_null(RefType::Externref);
+                ValType::I32,
+                |in_bounds| {
+                    for (i, &table_id) in table_ids.iter().enumerate() {
+                        in_bounds
+                            .local_get(idx)
+                            .table_get(table_id)
+                            .ref_is_null()
+                            .unop(UnaryOp::I32Eqz);
+                        if i > 0 {
+                            in_bounds.binop(BinaryOp::I32And);
+                        }
+                    }
                 },
-                |elem_requested| {
-                    elem_requested.local_get(idx).table_get(table_id);
+                |out_of_bounds| {
+                    out_of_bounds.i32_const(0);
                 },
             );
         builder.finish(vec![idx], &mut module.funcs)
     }
 
-    fn patch_drop_fn(
-        module: &mut Module,
-        table_id: TableId,
-        drop_fn_id: Option<FunctionId>,
-    ) -> FunctionId {
-        let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
-        let idx = module.locals.add(ValType::I32);
+    pub fn get_ref_id(&self) -> Option<FunctionId> {
+        self.get_ref_id
+    }
 
-        let mut instr_builder = builder.func_body();
-        if let Some(drop_fn_id) = drop_fn_id {
-            instr_builder
-                .local_get(idx)
-                .table_get(table_id)
-                .call(drop_fn_id);
-        }
-        instr_builder
-            .local_get(idx)
-            .ref_null(RefType::Externref)
-            .table_set(table_id);
-        builder.finish(vec![idx], &mut module.funcs)
+    /// Returns the ID of the global storing the error code for fallible exports.
+    pub fn error_code_id(&self) -> Option<GlobalId> {
+        self.error_code_id
     }
 
-    pub fn get_ref_id(&self) -> Option<FunctionId> {
-        self.get_ref_id
+    /// Returns the ID of the function checking the `externref`s table for leaked references.
+    pub fn check_leaks_id(&self) -> Option<FunctionId> {
+        self.check_leaks_id
+    }
+
+    /// Returns IDs of the injected functions and tables.
+    pub fn artifacts(&self) -> &ProcessingArtifacts {
+        &self.artifacts
+    }
+
+    /// Checks whether the surrogate import with the specified name was replaced.
+    pub fn has_surrogate(&self, name: &str) -> bool {
+        self.surrogates
+            .iter()
+            .any(|(surrogate_name, _)| surrogate_name == name)
+    }
+
+    /// Returns names of replaced surrogate imports together with IDs of the replacing functions.
+    pub fn surrogates(&self) -> &[(String, FunctionId)] {
+        &self.surrogates
     }
 
+    /// Replaces calls to surrogate imports and removes `externref` guards in all local functions.
+    /// Returns the number of replaced calls and the references of each local function.
     pub fn replace_calls(
         &self,
         module: &mut Module,
-    ) -> Result<(usize, HashSet<FunctionId>), Error> {
+        errors: &mut ErrorCollector,
+    ) -> Result<(usize, FunctionScan), Error> {
         let mut visitor = FunctionsReplacer::new(&self.fn_mapping);
-        let mut guarded_fns = HashSet::new();
+        let mut scan = FunctionScan::default();
         for function in module.funcs.iter_mut() {
             if let WasmFunctionKind::Local(local_fn) = &mut function.kind {
                 ir::dfs_pre_order_mut(&mut visitor, local_fn, local_fn.entry_block());
+                let mut refs = mem::take(&mut visitor.refs);
+                refs.finish();
 
-                if let Some(guard_id) = self.guard_id {
-                    if Self::remove_guards(guard_id, function)? {
-                        guarded_fns.insert(function.id());
-                    }
-                }
+                let is_guarded = match self.guard_id {
+                    // Skip the guard visitor pass if the function doesn't reference the guard.
+                    Some(guard_id) if refs.references(guard_id) => errors
+                        .check(Self::remove_guards(guard_id, function, &module.debug))?
+                        .unwrap_or(false),
+                    _ => false,
+                };
+                scan.insert(function.id(), refs, is_guarded);
             }
         }
-        Ok((visitor.replaced_count, guarded_fns))
+        Ok((visitor.replaced_count, scan))
     }
 
-    fn remove_guards(guard_id: FunctionId, function: &mut Function) -> Result<bool, Error> {
+    fn remove_guards(
+        guard_id: FunctionId,
+        function: &mut Function,
+        debug: &ModuleDebugData,
+    ) -> Result<bool, Error> {
         let local_fn = function.kind.unwrap_local_mut();
         let mut guard_visitor = GuardRemover::new(guard_id, local_fn);
         ir::dfs_pre_order_mut(&mut guard_visitor, local_fn, local_fn.entry_block());
@@ -312,16 +2624,20 @@ impl PatchedFunctions {
             Some(GuardPlacement::Incorrect(code_offset)) => Err(Error::IncorrectGuard {
                 function_name: function.name.clone(),
                 code_offset,
+                code_location: code_offset
+                    .and_then(|offset| resolve_location(debug, local_fn, offset)),
             }),
         }
     }
 }
 
-/// Visitor replacing invocations of patched functions.
+/// Visitor replacing invocations of patched functions. Also records references
+/// in the visited function.
 #[derive(Debug)]
 struct FunctionsReplacer<'a> {
     fn_mapping: &'a HashMap<FunctionId, FunctionId>,
     replaced_count: usize,
+    refs: FunctionRefs,
 }
 
 impl<'a> FunctionsReplacer<'a> {
@@ -329,6 +2645,7 @@ impl<'a> FunctionsReplacer<'a> {
         Self {
             fn_mapping,
             replaced_count: 0,
+            refs: FunctionRefs::default(),
         }
     }
 }
@@ -339,6 +2656,15 @@ impl ir::VisitorMut for FunctionsReplacer<'_> {
             *function = *mapped_id;
             self.replaced_count += 1;
         }
+        self.refs.push_function(*function);
+    }
+
+    fn visit_call_indirect_mut(&mut self, instr: &mut ir::CallIndirect) {
+        self.refs.push_indirect_type(instr.ty);
+    }
+
+    fn visit_return_call_indirect_mut(&mut self, instr: &mut ir::ReturnCallIndirect) {
+        self.refs.push_indirect_type(instr.ty);
     }
 }
 
@@ -411,6 +2737,8 @@ pub(crate) fn get_offset(location: InstrLocId) -> Option<u32> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use assert_matches::assert_matches;
 
     use super::*;
@@ -419,7 +2747,7 @@ mod tests {
     fn taking_externref_imports() {
         const MODULE_BYTES: &[u8] = br#"
             (module
-                (import "externref" "insert" (func (param i32) (result i32)))
+                (import "externref" "insert" (func (param i32 i32) (result i32)))
                 (import "externref" "get" (func (param i32) (result i32)))
                 (import "test" "function" (func (param f32)))
             )
@@ -428,7 +2756,12 @@ mod tests {
         let module = wat::parse_bytes(MODULE_BYTES).unwrap();
         let mut module = Module::from_buffer(&module).unwrap();
 
-        let imports = ExternrefImports::new(&mut module.imports).unwrap();
+        let imports = ExternrefImports::new(
+            &mut module.imports,
+            ExternrefImports::MODULE_NAME,
+            (ExternrefImports::MODULE_NAME, "guard"),
+        )
+        .unwrap();
         assert!(imports.insert.is_some());
         assert!(imports.get.is_some());
         assert!(imports.drop.is_none());
@@ -439,26 +2772,43 @@ mod tests {
     fn replacing_function_calls() {
         const MODULE_BYTES: &[u8] = br#"
             (module
-                (import "externref" "insert" (func $insert_ref (param i32) (result i32)))
+                (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
                 (import "externref" "get" (func $get_ref (param i32) (result i32)))
 
                 (func (export "test") (param $ref i32)
                     (drop (call $get_ref
-                        (call $insert_ref (local.get $ref))
+                        (call $insert_ref (local.get $ref) (i32.const -1))
                     ))
                 )
+                (func (export "other") (result i32)
+                    (i32.add (i32.const 1) (i32.const 2))
+                )
             )
         "#;
 
         let module = wat::parse_bytes(MODULE_BYTES).unwrap();
         let mut module = Module::from_buffer(&module).unwrap();
-        let imports = ExternrefImports::new(&mut module.imports).unwrap();
+        let imports = ExternrefImports::new(
+            &mut module.imports,
+            ExternrefImports::MODULE_NAME,
+            (ExternrefImports::MODULE_NAME, "guard"),
+        )
+        .unwrap();
 
         let fns = PatchedFunctions::new(&mut module, &imports, &Processor::default());
         assert_eq!(fns.fn_mapping.len(), 2);
-        let (replaced_calls, guarded_fns) = fns.replace_calls(&mut module).unwrap();
+        let (replaced_calls, scan) = fns
+            .replace_calls(&mut module, &mut ErrorCollector::new(false))
+            .unwrap();
         assert_eq!(replaced_calls, 2); // 1 insert + 1 get
-        assert!(guarded_fns.is_empty());
+        assert!(scan.guarded_fns().is_empty());
+
+        let get_ref_id = fns.get_ref_id().unwrap();
+        let ref_producers = HashSet::from([get_ref_id]);
+        let test_fn_id = module.exports.get_func("test").unwrap();
+        assert!(scan.may_reference(test_fn_id, &ref_producers, &HashSet::new()));
+        let other_fn_id = module.exports.get_func("other").unwrap();
+        assert!(!scan.may_reference(other_fn_id, &ref_producers, &HashSet::new()));
     }
 
     #[test]
@@ -476,11 +2826,18 @@ mod tests {
 
         let module = wat::parse_bytes(MODULE_BYTES).unwrap();
         let mut module = Module::from_buffer(&module).unwrap();
-        let imports = ExternrefImports::new(&mut module.imports).unwrap();
+        let imports = ExternrefImports::new(
+            &mut module.imports,
+            ExternrefImports::MODULE_NAME,
+            (ExternrefImports::MODULE_NAME, "guard"),
+        )
+        .unwrap();
 
         let fns = PatchedFunctions::new(&mut module, &imports, &Processor::default());
-        let (_, guarded_fns) = fns.replace_calls(&mut module).unwrap();
-        assert_eq!(guarded_fns.len(), 1);
+        let (_, scan) = fns
+            .replace_calls(&mut module, &mut ErrorCollector::new(false))
+            .unwrap();
+        assert_eq!(scan.guarded_fns().len(), 1);
     }
 
     #[test]
@@ -505,11 +2862,18 @@ mod tests {
 
         let module = wat::parse_bytes(MODULE_BYTES).unwrap();
         let mut module = Module::from_buffer(&module).unwrap();
-        let imports = ExternrefImports::new(&mut module.imports).unwrap();
+        let imports = ExternrefImports::new(
+            &mut module.imports,
+            ExternrefImports::MODULE_NAME,
+            (ExternrefImports::MODULE_NAME, "guard"),
+        )
+        .unwrap();
 
         let fns = PatchedFunctions::new(&mut module, &imports, &Processor::default());
-        let (_, guarded_fns) = fns.replace_calls(&mut module).unwrap();
-        assert_eq!(guarded_fns.len(), 1);
+        let (_, scan) = fns
+            .replace_calls(&mut module, &mut ErrorCollector::new(false))
+            .unwrap();
+        assert_eq!(scan.guarded_fns().len(), 1);
     }
 
     #[test]
@@ -527,13 +2891,30 @@ mod tests {
 
         let module = wat::parse_bytes(MODULE_BYTES).unwrap();
         let mut module = Module::from_buffer(&module).unwrap();
-        let imports = ExternrefImports::new(&mut module.imports).unwrap();
+        let imports = ExternrefImports::new(
+            &mut module.imports,
+            ExternrefImports::MODULE_NAME,
+            (ExternrefImports::MODULE_NAME, "guard"),
+        )
+        .unwrap();
 
         let fns = PatchedFunctions::new(&mut module, &imports, &Processor::default());
-        let err = fns.replace_calls(&mut module).unwrap_err();
+        let err = fns
+            .replace_calls(&mut module, &mut ErrorCollector::new(false))
+            .unwrap_err();
         assert_matches!(
-            err,
+            &err,
             Error::IncorrectGuard { function_name: Some(name), .. } if name == "test"
         );
+        let Error::IncorrectGuard {
+            code_location: Some(location),
+            ..
+        } = &err
+        else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(location.instr_index, 2);
+        assert!(location.source.is_none());
+        assert!(err.to_string().contains("(instruction #2)"), "{err}");
     }
 }
@@ -0,0 +1,72 @@
+//! Additional roots for the `walrus` GC pass.
+
+use walrus::{
+    passes::Roots, CustomSection, FunctionId, FunctionKind, IdsToIndices, Import, Memory, MemoryId,
+    Module,
+};
+
+use super::state::matches_glob;
+use crate::alloc::{Cow, HashSet, Vec};
+
+/// Temporary custom section rooting all memories in the module, and optionally local functions
+/// with matching names. The section must be removed before emitting the module.
+#[derive(Debug)]
+pub(crate) struct GcRoots {
+    memories: Vec<MemoryId>,
+    functions: Vec<FunctionId>,
+}
+
+impl GcRoots {
+    pub fn new(module: &Module, fn_patterns: &[&str]) -> Self {
+        let functions = module.funcs.iter_local().filter_map(|(fn_id, _)| {
+            let name = module.funcs.get(fn_id).name.as_deref()?;
+            let is_kept = fn_patterns
+                .iter()
+                .any(|pattern| matches_glob(pattern, name));
+            is_kept.then_some(fn_id)
+        });
+        Self {
+            memories: module.memories.iter().map(Memory::id).collect(),
+            functions: functions.collect(),
+        }
+    }
+}
+
+impl CustomSection for GcRoots {
+    fn name(&self) -> &'static str {
+        "__externrefs_gc_roots"
+    }
+
+    fn data(&self, _ids_to_indices: &IdsToIndices) -> Cow<'_, [u8]> {
+        unreachable!("GC roots must be removed before emitting the module")
+    }
+
+    fn add_gc_roots(&self, roots: &mut Roots) {
+        for &memory in &self.memories {
+            roots.push_memory(memory);
+        }
+        for &function in &self.functions {
+            roots.push_func(function);
+        }
+    }
+}
+
+/// Removes imported functions whose imports were removed during processing (e.g., surrogate
+/// imports from the `externref` module). Such functions are normally removed by the GC;
+/// if it is skipped, they must be removed explicitly since they cannot be emitted.
+pub(crate) fn remove_dangling_imports(module: &mut Module) {
+    let live_imports: HashSet<_> = module.imports.iter().map(Import::id).collect();
+    let dangling_fns: Vec<_> = module
+        .funcs
+        .iter()
+        .filter_map(|function| match &function.kind {
+            FunctionKind::Import(import) if !live_imports.contains(&import.import) => {
+                Some(function.id())
+            }
+            _ => None,
+        })
+        .collect();
+    for fn_id in dangling_fns {
+        module.funcs.delete(fn_id);
+    }
+}
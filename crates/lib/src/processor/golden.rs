@@ -0,0 +1,86 @@
+//! Golden-output testing for the processor.
+//!
+//! The helpers in this module compare the processed module against the expected module
+//! in the WebAssembly text format. Both modules are normalized before comparison:
+//! custom sections (e.g., the marker of processed modules) are removed, and the modules
+//! are re-encoded, so that the expected text can be written (or generated via
+//! [`processed_wat()`]) without regard to formatting and encoding details.
+//! Function and local names are retained, so they must match as well.
+//!
+//! # Examples
+//!
+//! ```
+//! use externref::processor::{fixtures, golden, Processor};
+//!
+//! let processor = Processor::default();
+//! let input = fixtures::SIMPLE.annotated_wat();
+//! let expected = golden::processed_wat(&input, &processor)?;
+//! // `expected` would usually be read from a file with the golden output.
+//! golden::assert_processed_eq(&input, &expected, &processor);
+//! # Ok::<_, externref::processor::Error>(())
+//! ```
+
+use walrus::ModuleConfig;
+
+use super::{Error, Processor};
+
+/// Processes the module in the WebAssembly text format and returns the normalized text
+/// of the processed module. This can be used to generate golden outputs.
+///
+/// # Errors
+///
+/// Returns [`Error::Wasm`] if `input_wat` cannot be parsed, and in all cases
+/// [`Processor::process_bytes()`] returns an error.
+pub fn processed_wat(input_wat: &str, processor: &Processor<'_>) -> Result<String, Error> {
+    let bytes = wat::parse_str(input_wat).map_err(|err| Error::Wasm(err.into()))?;
+    let processed = processor.process_bytes(&bytes)?;
+    normalize(&processed)
+}
+
+/// Asserts that processing the `input_wat` module with the `processor` produces
+/// the `expected_wat` module. The comparison is performed on normalized module texts.
+///
+/// # Panics
+///
+/// Panics if either of the modules cannot be parsed, if processing fails, or if
+/// the processed module differs from the expected one. In the latter case, the panic message
+/// contains the first differing line and both normalized texts.
+#[track_caller]
+pub fn assert_processed_eq(input_wat: &str, expected_wat: &str, processor: &Processor<'_>) {
+    let actual = processed_wat(input_wat, processor)
+        .unwrap_or_else(|err| panic!("failed processing input module: {err}"));
+    let expected = wat::parse_str(expected_wat)
+        .map_err(|err| Error::Wasm(err.into()))
+        .and_then(|bytes| normalize(&bytes))
+        .unwrap_or_else(|err| panic!("failed parsing expected module: {err}"));
+
+    if actual.lines().ne(expected.lines()) {
+        let (line_idx, (actual_line, expected_line)) = actual
+            .lines()
+            .chain(["<end of module>"])
+            .zip(expected.lines().chain(["<end of module>"]))
+            .enumerate()
+            .find(|(_, (actual, expected))| actual != expected)
+            .expect("modules differ");
+        panic!(
+            "processed module differs from the expected one at line {line}:\n\
+             actual:   {actual_line}\n\
+             expected: {expected_line}\n\n\
+             Actual module:\n{actual}\n\nExpected module:\n{expected}",
+            line = line_idx + 1
+        );
+    }
+}
+
+/// Normalizes a binary module and prints it in the WebAssembly text format.
+fn normalize(bytes: &[u8]) -> Result<String, Error> {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = config.parse(bytes).map_err(Error::Wasm)?;
+    module.producers.clear();
+    let custom_ids: Vec<_> = module.customs.iter().map(|(id, _)| id).collect();
+    for id in custom_ids {
+        module.customs.delete(id);
+    }
+    wasmprinter::print_bytes(module.emit_wasm()).map_err(Error::Wasm)
+}
@@ -0,0 +1,125 @@
+//! Hooks allowing to observe and adjust functions patched by the processor.
+
+use core::fmt;
+
+use walrus::{FunctionId, LocalFunction, LocalId, Module};
+
+use crate::{
+    alloc::{vec, Vec},
+    Function,
+};
+
+/// Hook invoked by the [`Processor`](super::Processor) for each function patched
+/// during processing. Hooks can be used to instrument patched functions, e.g. to count
+/// inserted or dropped references.
+///
+/// Hooks are registered via [`Processor::add_hook()`](super::Processor::add_hook()) and are run
+/// in the registration order after all functions are patched, but before renaming imports /
+/// exports. Since hooks are shared, they receive an immutable reference to themselves;
+/// use interior mutability (e.g., a [`Cell`](std::cell::Cell)) to accumulate state.
+///
+/// # Examples
+///
+/// ```
+/// # use std::cell::Cell;
+/// use externref::processor::{PatchedFunction, ProcessingHook, Processor};
+/// use walrus::Module;
+///
+/// #[derive(Debug, Default)]
+/// struct InstructionCounter {
+///     count: Cell<usize>,
+/// }
+///
+/// impl ProcessingHook for InstructionCounter {
+///     fn on_patched_function(
+///         &self,
+///         module: &mut Module,
+///         function: &PatchedFunction<'_>,
+///     ) -> anyhow::Result<()> {
+///         let local_fn = function.local_fn(module);
+///         let entry_len = local_fn.block(local_fn.entry_block()).len();
+///         self.count.set(self.count.get() + entry_len);
+///         Ok(())
+///     }
+/// }
+///
+/// let counter = InstructionCounter::default();
+/// let mut processor = Processor::default();
+/// processor.add_hook(&counter);
+/// # let module: Vec<u8> = b"\0asm\x01\0\0\0".to_vec();
+/// processor.process_bytes(&module)?;
+/// # Ok::<_, externref::processor::Error>(())
+/// ```
+pub trait ProcessingHook: fmt::Debug {
+    /// Called for each patched function.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error aborts processing with [`Error::Hook`](super::Error::Hook).
+    fn on_patched_function(
+        &self,
+        module: &mut Module,
+        function: &PatchedFunction<'_>,
+    ) -> anyhow::Result<()>;
+}
+
+/// Kind of a [`PatchedFunction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatchedFunctionKind<'a> {
+    /// Local function replacing an import from the surrogate module,
+    /// such as `insert` or `drop`. The enclosed value is the name of the import.
+    Surrogate(&'a str),
+    /// Exported function with the enclosed declaration from the custom section.
+    Export(Function<'a>),
+    /// Function table entry with the enclosed declaration from the custom section.
+    TableEntry(Function<'a>),
+    /// Non-exported local function using `externref` locals, e.g., because it calls
+    /// imported functions returning `externref`s.
+    Local,
+}
+
+/// Information about a function patched by the [`Processor`](super::Processor),
+/// passed to [`ProcessingHook`]s.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PatchedFunction<'a> {
+    /// ID of the patched function in the module.
+    pub id: FunctionId,
+    /// Kind of the function.
+    pub kind: PatchedFunctionKind<'a>,
+    /// `externref` locals added to the function by the processor: `externref` args
+    /// for exports and locals holding results of calls to functions returning `externref`s.
+    /// Empty for [surrogate](PatchedFunctionKind::Surrogate) functions.
+    pub new_locals: Vec<LocalId>,
+}
+
+impl<'a> PatchedFunction<'a> {
+    pub(crate) fn new(id: FunctionId, kind: PatchedFunctionKind<'a>) -> Self {
+        Self {
+            id,
+            kind,
+            new_locals: vec![],
+        }
+    }
+
+    /// Returns the function declaration from the custom section. Only exported functions
+    /// and function table entries have a declaration.
+    pub fn declaration(&self) -> Option<&Function<'a>> {
+        match &self.kind {
+            PatchedFunctionKind::Export(function) | PatchedFunctionKind::TableEntry(function) => {
+                Some(function)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the code of this function in the provided module.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `module` is not the one being processed.
+    pub fn local_fn<'m>(&self, module: &'m mut Module) -> &'m mut LocalFunction {
+        module.funcs.get_mut(self.id).kind.unwrap_local_mut()
+    }
+}
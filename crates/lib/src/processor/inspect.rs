@@ -0,0 +1,335 @@
+//! Read-only inspection of function declarations in WASM modules.
+
+use wasmparser::{CompositeType, ExternalKind, KnownCustom, Name, Parser, Payload, TypeRef};
+
+use super::{functions::ExternrefImports, Error, Processor};
+use crate::{
+    alloc::{vec, HashMap, String, ToOwned, ToString, Vec},
+    Function, FunctionKind,
+};
+
+/// Information about a function with [`Resource`](crate::Resource) args or return type
+/// declared in a WASM module. Returned by [`inspect()`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct FunctionInfo<'a> {
+    /// Function declaration from the custom section of the module.
+    pub declaration: Function<'a>,
+    /// 0-based indices of `Resource` args of the function.
+    pub resource_args: Vec<usize>,
+    /// 0-based indices of `Resource` return values of the function.
+    pub resource_results: Vec<usize>,
+    /// 0-based indices of [`HostHandle`](crate::HostHandle) args of the function.
+    pub host_handle_args: Vec<usize>,
+    /// 0-based indices of [`HostHandle`](crate::HostHandle) return values of the function.
+    pub host_handle_results: Vec<usize>,
+    /// WASM signature of the function before processing.
+    pub(super) signature: Signature,
+}
+
+/// WASM function signature with value types represented as strings (e.g., `i32`).
+#[derive(Debug, Clone, Default)]
+pub(super) struct Signature {
+    pub params: Vec<String>,
+    pub results: Vec<String>,
+}
+
+impl<'a> FunctionInfo<'a> {
+    /// Returns the name of the function.
+    pub fn name(&self) -> &'a str {
+        self.declaration.name
+    }
+
+    /// Returns the name of the module the function is imported from, or `None`
+    /// for exported functions (including [re-exported imports](FunctionKind::Reexport))
+    /// and function table entries.
+    pub fn module(&self) -> Option<&'a str> {
+        match self.declaration.kind {
+            FunctionKind::Import(module) => Some(module),
+            FunctionKind::Export
+            | FunctionKind::TableEntry
+            | FunctionKind::Start
+            | FunctionKind::Reexport { .. } => None,
+        }
+    }
+
+    fn new(declaration: Function<'a>, signature: Signature) -> Self {
+        let arity = signature.params.len();
+        let (resource_args, resource_results) = declaration
+            .externrefs
+            .set_indices()
+            .partition::<Vec<_>, _>(|&idx| idx < arity);
+        let resource_results = resource_results.into_iter().map(|idx| idx - arity);
+        let (host_handle_args, host_handle_results) = declaration
+            .host_handles
+            .set_indices()
+            .partition::<Vec<_>, _>(|&idx| idx < arity);
+        let host_handle_results = host_handle_results.into_iter().map(|idx| idx - arity);
+        Self {
+            declaration,
+            resource_args,
+            resource_results: resource_results.collect(),
+            host_handle_args,
+            host_handle_results: host_handle_results.collect(),
+            signature,
+        }
+    }
+
+    /// Returns the WASM signature of the function after processing.
+    pub(super) fn processed_signature(&self) -> Signature {
+        let Signature {
+            mut params,
+            mut results,
+        } = self.signature.clone();
+        // Fallible exports return an additional `i32` error code after processing.
+        let is_fallible = self.declaration.kind == FunctionKind::Export
+            && results == ["i32"]
+            && self.declaration.externrefs.bit_len() == params.len() + 2;
+        if is_fallible {
+            results.push("i32".to_owned());
+        }
+        for &idx in &self.resource_args {
+            "externref".clone_into(&mut params[idx]);
+        }
+        for &idx in &self.resource_results {
+            if let Some(result) = results.get_mut(idx) {
+                "externref".clone_into(result);
+            }
+        }
+        Signature { params, results }
+    }
+}
+
+/// Function signatures in a WASM module, indexed by the function index.
+#[derive(Debug, Default)]
+struct ModuleSignatures<'a> {
+    /// Signature for each type; `None` for non-function types.
+    types: Vec<Option<Signature>>,
+    /// Type index for each function.
+    fn_types: Vec<u32>,
+    imported_fns: HashMap<(&'a str, &'a str), u32>,
+    exports: HashMap<&'a str, (ExternalKind, u32)>,
+    /// Function indices by names from the `name` custom section.
+    fn_names: HashMap<&'a str, u32>,
+}
+
+impl ModuleSignatures<'_> {
+    fn fn_signature(&self, fn_idx: u32) -> Option<Signature> {
+        let type_idx = *self.fn_types.get(fn_idx as usize)?;
+        self.types.get(type_idx as usize)?.clone()
+    }
+}
+
+/// Inspects declarations of functions with [`Resource`](crate::Resource) args or return type
+/// in the provided WASM module. Unlike [`Processor`], this does not modify the module, and
+/// is thus usable by host-side tools such as code generators or linters.
+///
+/// The module should not be [processed](Processor::process()) beforehand since processing
+/// removes the custom section with declarations. Similar to processing, declarations
+/// of imports missing from the module (e.g., removed by the compiler as unused) are skipped.
+/// Declarations are read from all custom sections with names starting with
+/// [`Function::CUSTOM_SECTION_NAME`], including ones [configured](Processor::add_section())
+/// with custom names.
+///
+/// # Errors
+///
+/// Returns an error if the module or the custom section is malformed, or if a declared
+/// export is missing or is not a function.
+///
+/// # Examples
+///
+/// ```
+/// let module: Vec<u8> = // WASM module, e.g., loaded from the file system
+/// #    b"\0asm\x01\0\0\0".to_vec();
+/// for function in externref::processor::inspect(&module)? {
+///     println!(
+///         "{} (module: {:?}): resource args {:?}, resource results {:?}",
+///         function.name(),
+///         function.module(),
+///         function.resource_args,
+///         function.resource_results
+///     );
+/// }
+/// # Ok::<_, externref::processor::Error>(())
+/// ```
+pub fn inspect(bytes: &[u8]) -> Result<Vec<FunctionInfo<'_>>, Error> {
+    inspect_module(bytes, ExternrefImports::MODULE_NAME).map(|interface| interface.functions)
+}
+
+/// Reference interface of a WASM module before processing.
+#[derive(Debug)]
+pub(super) struct ModuleInterface<'a> {
+    pub functions: Vec<FunctionInfo<'a>>,
+    /// Names of resource kinds, sorted alphabetically.
+    pub resource_kinds: Vec<&'a str>,
+    /// Whether the module uses the [resource table](crate::ResourceTable).
+    pub uses_resource_table: bool,
+    /// Whether the module uses the [resource global](crate::GlobalResource).
+    pub uses_resource_global: bool,
+    /// Custom sections with function declarations as `(name, data)` tuples.
+    pub sections: Vec<(&'a str, &'a [u8])>,
+}
+
+/// Inspects function declarations and other parts of the reference interface
+/// in the provided WASM module. `surrogate_module` is the module of surrogate imports.
+pub(super) fn inspect_module<'a>(
+    bytes: &'a [u8],
+    surrogate_module: &str,
+) -> Result<ModuleInterface<'a>, Error> {
+    let mut signatures = ModuleSignatures::default();
+    let mut declarations = vec![];
+    for payload in Parser::new(0).parse_all(bytes) {
+        let payload = payload.map_err(|err| Error::Wasm(err.into()))?;
+        parse_payload(payload, &mut signatures, &mut declarations)
+            .map_err(|err| Error::Wasm(err.into()))?;
+    }
+
+    let sections = declarations;
+    let declarations = Processor::parse_sections(sections.iter().map(|&(_, data)| data))?;
+    let mut functions = vec![];
+    for function in declarations {
+        let signature = match function.kind {
+            FunctionKind::Export | FunctionKind::Start => {
+                let export = signatures.exports.get(function.name);
+                let export = export.ok_or_else(|| Error::NoExport(function.name.to_owned()))?;
+                match export {
+                    (ExternalKind::Func, fn_idx) => signatures.fn_signature(*fn_idx),
+                    _ => return Err(Error::UnexpectedExportType(function.name.to_owned())),
+                }
+            }
+            FunctionKind::TableEntry => {
+                // Like in the processor, fall back to the export with the same name.
+                let fn_idx = signatures.fn_names.get(function.name).or_else(|| {
+                    match signatures.exports.get(function.name) {
+                        Some((ExternalKind::Func, fn_idx)) => Some(fn_idx),
+                        _ => None,
+                    }
+                });
+                let fn_idx = fn_idx.ok_or_else(|| Error::NoTableEntry(function.name.to_owned()))?;
+                signatures.fn_signature(*fn_idx)
+            }
+            FunctionKind::Import(module) => {
+                let Some(fn_idx) = signatures.imported_fns.get(&(module, function.name)) else {
+                    continue;
+                };
+                signatures.fn_signature(*fn_idx)
+            }
+            FunctionKind::Reexport {
+                module,
+                import_name,
+            } => {
+                let Some(fn_idx) = signatures.imported_fns.get(&(module, import_name)) else {
+                    continue;
+                };
+                signatures.fn_signature(*fn_idx)
+            }
+        };
+        let signature = signature.ok_or_else(|| {
+            Error::Wasm(anyhow::anyhow!(
+                "invalid type of function `{}`",
+                function.name
+            ))
+        })?;
+        functions.push(FunctionInfo::new(function, signature));
+    }
+
+    let kinds = signatures
+        .imported_fns
+        .keys()
+        .filter_map(|&(module, name)| {
+            if module == surrogate_module {
+                name.strip_prefix(ExternrefImports::KIND_PREFIX)
+            } else {
+                None
+            }
+        });
+    let mut resource_kinds: Vec<_> = kinds.collect();
+    resource_kinds.sort_unstable();
+    let uses_resource_table = signatures.imported_fns.keys().any(|&(module, name)| {
+        module == surrogate_module && ExternrefImports::RESOURCE_TABLE_FNS.contains(&name)
+    });
+    let uses_resource_global = signatures.imported_fns.keys().any(|&(module, name)| {
+        module == surrogate_module && ExternrefImports::RESOURCE_GLOBAL_FNS.contains(&name)
+    });
+
+    Ok(ModuleInterface {
+        functions,
+        resource_kinds,
+        uses_resource_table,
+        uses_resource_global,
+        sections,
+    })
+}
+
+fn parse_payload<'a>(
+    payload: Payload<'a>,
+    signatures: &mut ModuleSignatures<'a>,
+    declarations: &mut Vec<(&'a str, &'a [u8])>,
+) -> wasmparser::Result<()> {
+    match payload {
+        Payload::TypeSection(reader) => {
+            for rec_group in reader {
+                for ty in rec_group?.into_types() {
+                    let signature = match &ty.composite_type {
+                        CompositeType::Func(fn_type) => Some(Signature {
+                            params: fn_type.params().iter().map(ToString::to_string).collect(),
+                            results: fn_type.results().iter().map(ToString::to_string).collect(),
+                        }),
+                        _ => None,
+                    };
+                    signatures.types.push(signature);
+                }
+            }
+        }
+        Payload::ImportSection(reader) => {
+            for import in reader {
+                let import = import?;
+                if let TypeRef::Func(type_idx) = import.ty {
+                    let fn_idx = u32::try_from(signatures.fn_types.len()).unwrap();
+                    signatures.fn_types.push(type_idx);
+                    signatures
+                        .imported_fns
+                        .insert((import.module, import.name), fn_idx);
+                }
+            }
+        }
+        Payload::FunctionSection(reader) => {
+            for type_idx in reader {
+                signatures.fn_types.push(type_idx?);
+            }
+        }
+        Payload::ExportSection(reader) => {
+            for export in reader {
+                let export = export?;
+                signatures
+                    .exports
+                    .insert(export.name, (export.kind, export.index));
+            }
+        }
+        Payload::CustomSection(section)
+            if section.name().starts_with(Function::CUSTOM_SECTION_NAME)
+                && section.name() != Processor::MARKER_SECTION_NAME =>
+        {
+            declarations.push((section.name(), section.data()));
+        }
+        Payload::CustomSection(section) => {
+            if let KnownCustom::Name(reader) = section.as_known() {
+                for name in reader {
+                    if let Name::Function(names) = name? {
+                        for naming in names {
+                            let naming = naming?;
+                            // Names are not guaranteed to be unique; use the first function
+                            // similar to the processor.
+                            signatures
+                                .fn_names
+                                .entry(naming.name)
+                                .or_insert(naming.index);
+                        }
+                    }
+                }
+            }
+        }
+        _ => { /* skip other payloads */ }
+    }
+    Ok(())
+}
@@ -0,0 +1,445 @@
+//! Minimal interpreter for functions generated by the processor. Used to check invariants
+//! of the generated table functions (see [`test_util`](super::test_util)).
+//!
+//! Only instructions emitted by the processor (locals, integer arithmetic, control flow,
+//! table and global access, and calls) are supported; other instructions trap.
+
+use std::collections::HashMap;
+
+use walrus::{
+    ir::{BinaryOp, Instr, InstrSeqId, InstrSeqType, UnaryOp, Value},
+    ConstExpr, ElementItems, ElementKind, FunctionId, FunctionKind, GlobalId, GlobalKind,
+    LocalFunction, LocalId, Module, RefType, TableId, ValType,
+};
+
+/// Maximum number of instructions executed in a single call, to prevent infinite loops.
+const FUEL: usize = 1_000_000;
+
+/// Value on the operand stack, in a local, global or table slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Val {
+    I32(i32),
+    I64(i64),
+    /// `externref` represented by an opaque ID.
+    ExternRef(Option<u32>),
+    FuncRef(Option<FunctionId>),
+}
+
+impl Val {
+    /// Value of imported `externref` globals (e.g., tombstones).
+    pub const IMPORTED_REF: Self = Self::ExternRef(Some(u32::MAX));
+
+    fn default_for(ty: ValType) -> Self {
+        match ty {
+            ValType::I64 => Self::I64(0),
+            ValType::Ref(RefType::Externref) => Self::ExternRef(None),
+            ValType::Ref(RefType::Funcref) => Self::FuncRef(None),
+            _ => Self::I32(0),
+        }
+    }
+
+    fn null(ty: RefType) -> Self {
+        match ty {
+            RefType::Funcref => Self::FuncRef(None),
+            _ => Self::ExternRef(None),
+        }
+    }
+}
+
+/// Call to an imported function.
+#[derive(Debug, Clone)]
+pub(super) struct ImportCall {
+    pub args: Vec<Val>,
+}
+
+/// Trap or an unsupported instruction encountered during execution.
+#[derive(Debug)]
+pub(super) struct Trap(pub String);
+
+type ExecResult<T> = Result<T, Trap>;
+
+#[derive(Debug)]
+enum Flow {
+    Next,
+    Br(InstrSeqId),
+    Return,
+}
+
+#[derive(Debug)]
+struct Frame<'a> {
+    function: &'a LocalFunction,
+    locals: HashMap<LocalId, Val>,
+    stack: Vec<Val>,
+}
+
+impl Frame<'_> {
+    fn pop(&mut self) -> ExecResult<Val> {
+        self.stack
+            .pop()
+            .ok_or_else(|| Trap("operand stack underflow".to_owned()))
+    }
+
+    fn pop_i32(&mut self) -> ExecResult<i32> {
+        match self.pop()? {
+            Val::I32(value) => Ok(value),
+            other => Err(Trap(format!("expected i32, got {other:?}"))),
+        }
+    }
+
+    fn pop_i64(&mut self) -> ExecResult<i64> {
+        match self.pop()? {
+            Val::I64(value) => Ok(value),
+            other => Err(Trap(format!("expected i64, got {other:?}"))),
+        }
+    }
+}
+
+/// Interpreter state: tables and globals of the module, and calls to imported functions.
+#[derive(Debug)]
+pub(super) struct Interpreter<'a> {
+    module: &'a Module,
+    tables: HashMap<TableId, Vec<Val>>,
+    globals: HashMap<GlobalId, Val>,
+    import_calls: Vec<ImportCall>,
+    fuel: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        let globals = module.globals.iter().map(|global| {
+            let value = match &global.kind {
+                GlobalKind::Local(ConstExpr::Value(Value::I32(value))) => Val::I32(*value),
+                GlobalKind::Local(ConstExpr::Value(Value::I64(value))) => Val::I64(*value),
+                GlobalKind::Local(ConstExpr::RefNull(ty)) => Val::null(*ty),
+                GlobalKind::Import(_) if global.ty == ValType::Ref(RefType::Externref) => {
+                    Val::IMPORTED_REF
+                }
+                _ => Val::default_for(global.ty),
+            };
+            (global.id(), value)
+        });
+
+        let mut tables: HashMap<_, _> = module
+            .tables
+            .iter()
+            .map(|table| {
+                let len = usize::try_from(table.initial).expect("table is too large");
+                (table.id(), vec![Val::null(table.element_ty); len])
+            })
+            .collect();
+        for element in module.elements.iter() {
+            let ElementKind::Active {
+                table,
+                offset: ConstExpr::Value(Value::I32(offset)),
+            } = element.kind
+            else {
+                continue;
+            };
+            let ElementItems::Functions(fns) = &element.items else {
+                continue;
+            };
+            let offset = usize::try_from(offset).expect("negative element offset");
+            let slots = tables.get_mut(&table).unwrap();
+            for (slot, &fn_id) in slots[offset..].iter_mut().zip(fns) {
+                *slot = Val::FuncRef(Some(fn_id));
+            }
+        }
+
+        Self {
+            module,
+            tables,
+            globals: globals.collect(),
+            import_calls: vec![],
+            fuel: 0,
+        }
+    }
+
+    /// Takes calls to imported functions recorded since the last call to this method.
+    pub fn take_import_calls(&mut self) -> Vec<ImportCall> {
+        std::mem::take(&mut self.import_calls)
+    }
+
+    pub fn call(&mut self, fn_id: FunctionId, args: &[Val]) -> ExecResult<Vec<Val>> {
+        self.fuel = FUEL;
+        self.call_inner(fn_id, args)
+    }
+
+    fn call_inner(&mut self, fn_id: FunctionId, args: &[Val]) -> ExecResult<Vec<Val>> {
+        let module = self.module;
+        let function = module.funcs.get(fn_id);
+        let results = module.types.results(function.ty());
+        let FunctionKind::Local(function) = &function.kind else {
+            // Imported functions return default values.
+            self.import_calls.push(ImportCall {
+                args: args.to_vec(),
+            });
+            return Ok(results.iter().copied().map(Val::default_for).collect());
+        };
+
+        let mut frame = Frame {
+            function,
+            locals: function
+                .args
+                .iter()
+                .copied()
+                .zip(args.iter().copied())
+                .collect(),
+            stack: vec![],
+        };
+        self.exec_seq(&mut frame, function.entry_block())?;
+        let split_at = frame
+            .stack
+            .len()
+            .checked_sub(results.len())
+            .ok_or_else(|| Trap("not enough values returned".to_owned()))?;
+        Ok(frame.stack.split_off(split_at))
+    }
+
+    fn result_count(&self, ty: InstrSeqType) -> usize {
+        match ty {
+            InstrSeqType::Simple(ty) => usize::from(ty.is_some()),
+            InstrSeqType::MultiValue(ty) => self.module.types.results(ty).len(),
+        }
+    }
+
+    /// Executes a block with the specified ID, handling branches targeting it.
+    fn exec_block(&mut self, frame: &mut Frame<'_>, seq_id: InstrSeqId) -> ExecResult<Flow> {
+        let height = frame.stack.len();
+        match self.exec_seq(frame, seq_id)? {
+            Flow::Br(target) if target == seq_id => {
+                let result_count = self.result_count(frame.function.block(seq_id).ty);
+                let results_start = frame.stack.len() - result_count;
+                frame.stack.drain(height..results_start);
+                Ok(Flow::Next)
+            }
+            flow => Ok(flow),
+        }
+    }
+
+    #[allow(clippy::too_many_lines)] // straightforward instruction dispatch
+    fn exec_seq(&mut self, frame: &mut Frame<'_>, seq_id: InstrSeqId) -> ExecResult<Flow> {
+        let function = frame.function;
+        for (instr, _) in &function.block(seq_id).instrs {
+            self.fuel = self
+                .fuel
+                .checked_sub(1)
+                .ok_or_else(|| Trap("out of fuel".to_owned()))?;
+
+            match instr {
+                Instr::Block(block) => match self.exec_block(frame, block.seq)? {
+                    Flow::Next => {}
+                    flow => return Ok(flow),
+                },
+                Instr::Loop(block) => loop {
+                    let height = frame.stack.len();
+                    match self.exec_seq(frame, block.seq)? {
+                        Flow::Br(target) if target == block.seq => {
+                            frame.stack.truncate(height);
+                        }
+                        Flow::Next => break,
+                        flow => return Ok(flow),
+                    }
+                },
+                Instr::IfElse(if_else) => {
+                    let seq = if frame.pop_i32()? == 0 {
+                        if_else.alternative
+                    } else {
+                        if_else.consequent
+                    };
+                    match self.exec_block(frame, seq)? {
+                        Flow::Next => {}
+                        flow => return Ok(flow),
+                    }
+                }
+                Instr::Br(br) => return Ok(Flow::Br(br.block)),
+                Instr::BrIf(br) => {
+                    if frame.pop_i32()? != 0 {
+                        return Ok(Flow::Br(br.block));
+                    }
+                }
+                Instr::Return(_) => return Ok(Flow::Return),
+                Instr::Unreachable(_) => return Err(Trap("unreachable executed".to_owned())),
+                Instr::Drop(_) => {
+                    frame.pop()?;
+                }
+                Instr::Select(_) => {
+                    let condition = frame.pop_i32()?;
+                    let alternative = frame.pop()?;
+                    let consequent = frame.pop()?;
+                    frame.stack.push(if condition == 0 {
+                        alternative
+                    } else {
+                        consequent
+                    });
+                }
+
+                Instr::Call(call) => self.exec_call(frame, call.func)?,
+                Instr::CallIndirect(call) => {
+                    let idx = frame.pop_i32()?;
+                    let fn_id = match self.table_get(call.table, idx)? {
+                        Val::FuncRef(Some(fn_id)) => fn_id,
+                        other => return Err(Trap(format!("cannot call {other:?}"))),
+                    };
+                    if self.module.funcs.get(fn_id).ty() != call.ty {
+                        return Err(Trap("indirect call type mismatch".to_owned()));
+                    }
+                    self.exec_call(frame, fn_id)?;
+                }
+
+                Instr::LocalGet(local) => {
+                    let ty = self.module.locals.get(local.local).ty();
+                    let value = frame
+                        .locals
+                        .get(&local.local)
+                        .copied()
+                        .unwrap_or_else(|| Val::default_for(ty));
+                    frame.stack.push(value);
+                }
+                Instr::LocalSet(local) => {
+                    let value = frame.pop()?;
+                    frame.locals.insert(local.local, value);
+                }
+                Instr::LocalTee(local) => {
+                    let value = frame.pop()?;
+                    frame.locals.insert(local.local, value);
+                    frame.stack.push(value);
+                }
+                Instr::GlobalGet(global) => frame.stack.push(self.globals[&global.global]),
+                Instr::GlobalSet(global) => {
+                    let value = frame.pop()?;
+                    self.globals.insert(global.global, value);
+                }
+
+                Instr::Const(constant) => frame.stack.push(match constant.value {
+                    Value::I32(value) => Val::I32(value),
+                    Value::I64(value) => Val::I64(value),
+                    other => return Err(Trap(format!("unsupported constant: {other:?}"))),
+                }),
+                Instr::Binop(binop) => {
+                    let value = Self::exec_binop(frame, binop.op)?;
+                    frame.stack.push(value);
+                }
+                Instr::Unop(unop) => {
+                    let value = match unop.op {
+                        UnaryOp::I32Eqz => Val::I32((frame.pop_i32()? == 0).into()),
+                        #[allow(clippy::cast_possible_truncation)] // intended
+                        UnaryOp::I32WrapI64 => Val::I32(frame.pop_i64()? as i32),
+                        UnaryOp::I64ExtendSI32 => Val::I64(frame.pop_i32()?.into()),
+                        #[allow(clippy::cast_sign_loss)] // intended
+                        UnaryOp::I64ExtendUI32 => Val::I64((frame.pop_i32()? as u32).into()),
+                        op => return Err(Trap(format!("unsupported unary op: {op:?}"))),
+                    };
+                    frame.stack.push(value);
+                }
+
+                Instr::RefNull(ref_null) => frame.stack.push(Val::null(ref_null.ty)),
+                Instr::RefIsNull(_) => {
+                    let is_null = matches!(frame.pop()?, Val::ExternRef(None) | Val::FuncRef(None));
+                    frame.stack.push(Val::I32(is_null.into()));
+                }
+                Instr::RefFunc(ref_func) => frame.stack.push(Val::FuncRef(Some(ref_func.func))),
+
+                Instr::TableGet(table_get) => {
+                    let idx = frame.pop_i32()?;
+                    let value = self.table_get(table_get.table, idx)?;
+                    frame.stack.push(value);
+                }
+                Instr::TableSet(table_set) => {
+                    let value = frame.pop()?;
+                    let idx = frame.pop_i32()?;
+                    let slot = self.table_slot(table_set.table, idx)?;
+                    *slot = value;
+                }
+                Instr::TableSize(table_size) => {
+                    let len = self.tables[&table_size.table].len();
+                    frame.stack.push(Val::I32(len_to_i32(len)?));
+                }
+                Instr::TableGrow(table_grow) => {
+                    let delta = frame.pop_i32()?;
+                    let value = frame.pop()?;
+                    let table = self.tables.get_mut(&table_grow.table).unwrap();
+                    let prev_len = table.len();
+                    if let Ok(delta) = usize::try_from(delta) {
+                        table.resize(prev_len + delta, value);
+                    }
+                    frame.stack.push(Val::I32(len_to_i32(prev_len)?));
+                }
+                Instr::TableFill(table_fill) => {
+                    let len = frame.pop_i32()?;
+                    let value = frame.pop()?;
+                    let start = frame.pop_i32()?;
+                    for idx in start..start.saturating_add(len) {
+                        *self.table_slot(table_fill.table, idx)? = value;
+                    }
+                }
+
+                other => return Err(Trap(format!("unsupported instruction: {other:?}"))),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec_call(&mut self, frame: &mut Frame<'_>, fn_id: FunctionId) -> ExecResult<()> {
+        let ty = self.module.funcs.get(fn_id).ty();
+        let param_count = self.module.types.params(ty).len();
+        let args_start = frame
+            .stack
+            .len()
+            .checked_sub(param_count)
+            .ok_or_else(|| Trap("operand stack underflow".to_owned()))?;
+        let args = frame.stack.split_off(args_start);
+        let results = self.call_inner(fn_id, &args)?;
+        frame.stack.extend(results);
+        Ok(())
+    }
+
+    fn exec_binop(frame: &mut Frame<'_>, op: BinaryOp) -> ExecResult<Val> {
+        if let BinaryOp::I64Ne = op {
+            let rhs = frame.pop_i64()?;
+            let lhs = frame.pop_i64()?;
+            return Ok(Val::I32((lhs != rhs).into()));
+        }
+
+        let rhs = frame.pop_i32()?;
+        let lhs = frame.pop_i32()?;
+        #[allow(clippy::cast_sign_loss)] // intended
+        let (lhs_u, rhs_u) = (lhs as u32, rhs as u32);
+        let division_by_zero = || Trap("integer division by zero".to_owned());
+        #[allow(clippy::cast_possible_wrap)] // intended
+        Ok(Val::I32(match op {
+            BinaryOp::I32Add => lhs.wrapping_add(rhs),
+            BinaryOp::I32Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::I32Mul => lhs.wrapping_mul(rhs),
+            BinaryOp::I32DivU => lhs_u.checked_div(rhs_u).ok_or_else(division_by_zero)? as i32,
+            BinaryOp::I32RemU => lhs_u.checked_rem(rhs_u).ok_or_else(division_by_zero)? as i32,
+            BinaryOp::I32And => lhs & rhs,
+            BinaryOp::I32Or => lhs | rhs,
+            BinaryOp::I32Eq => (lhs == rhs).into(),
+            BinaryOp::I32Ne => (lhs != rhs).into(),
+            BinaryOp::I32LtS => (lhs < rhs).into(),
+            BinaryOp::I32LtU => (lhs_u < rhs_u).into(),
+            BinaryOp::I32GtS => (lhs > rhs).into(),
+            BinaryOp::I32GtU => (lhs_u > rhs_u).into(),
+            BinaryOp::I32LeU => (lhs_u <= rhs_u).into(),
+            BinaryOp::I32GeS => (lhs >= rhs).into(),
+            BinaryOp::I32GeU => (lhs_u >= rhs_u).into(),
+            op => return Err(Trap(format!("unsupported binary op: {op:?}"))),
+        }))
+    }
+
+    fn table_slot(&mut self, table: TableId, idx: i32) -> ExecResult<&mut Val> {
+        let table = self.tables.get_mut(&table).unwrap();
+        usize::try_from(idx)
+            .ok()
+            .and_then(|idx| table.get_mut(idx))
+            .ok_or_else(|| Trap(format!("table index {idx} is out of bounds")))
+    }
+
+    fn table_get(&mut self, table: TableId, idx: i32) -> ExecResult<Val> {
+        self.table_slot(table, idx).copied()
+    }
+}
+
+fn len_to_i32(len: usize) -> ExecResult<i32> {
+    i32::try_from(len).map_err(|_| Trap("table is too large".to_owned()))
+}
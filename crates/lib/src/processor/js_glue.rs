@@ -0,0 +1,434 @@
+//! Generation of JS glue for processed modules targeting browsers and Node.js.
+
+use core::fmt::Write as _;
+
+use super::manifest::{FunctionManifest, ImportManifest, Manifest};
+use crate::alloc::{format, BTreeSet, HashMap, String, ToOwned, Vec};
+
+/// Environment the JS glue is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum GlueTarget {
+    Browser,
+    /// Node.js, optionally with WASI.
+    Node,
+}
+
+/// Helpers not depending on the manifest.
+const PRELUDE: &str = r#"
+async function instantiateSource(source, imports) {
+  if (source instanceof WebAssembly.Module) {
+    return WebAssembly.instantiate(source, imports);
+  }
+  source = await source;
+  if (typeof Response === "function" && source instanceof Response) {
+    return (await WebAssembly.instantiateStreaming(source, imports)).instance;
+  }
+  return (await WebAssembly.instantiate(source, imports)).instance;
+}
+"#;
+
+/// Counterpart of [`PRELUDE`] for Node.js; allows specifying the module via a file path.
+const NODE_PRELUDE: &str = r#"
+async function instantiateSource(source, imports) {
+  if (typeof source === "string" || source instanceof URL) {
+    source = await readFile(source);
+  }
+  if (source instanceof WebAssembly.Module) {
+    return WebAssembly.instantiate(source, imports);
+  }
+  return (await WebAssembly.instantiate(await source, imports)).instance;
+}
+"#;
+
+/// Helpers shared by all targets.
+const HELPERS: &str = r"
+function checkArgs(name, args, expected) {
+  if (args.length !== expected) {
+    throw new TypeError(`\`${name}\` expects ${expected} arg(s), got ${args.length}`);
+  }
+}
+
+function withImport(imports, module, name, value) {
+  imports[module] = { ...imports[module], [name]: value };
+}
+";
+
+impl Manifest {
+    pub(super) fn to_js_glue(&self, target: GlueTarget) -> String {
+        let host = match target {
+            GlueTarget::Browser => "",
+            GlueTarget::Node => " for Node.js",
+        };
+        let mut js = format!(
+            "// Glue for a WASM module processed by `externref`{host} (manifest version {}).\n\
+             // This file is generated; do not edit.\n",
+            self.version
+        );
+        if target == GlueTarget::Node {
+            js.push_str("\nimport { readFile } from \"node:fs/promises\";\n");
+        }
+
+        let resource_types: BTreeSet<_> = self
+            .imports
+            .iter()
+            .chain(&self.exports)
+            .flat_map(|function| &function.resource_types)
+            .map(|name| js_ident(name))
+            .collect();
+        for name in &resource_types {
+            writeln!(
+                js,
+                "\n/** @typedef {{unknown}} {name} Reference to a host resource */"
+            )
+            .unwrap();
+        }
+
+        let kinds: Vec<_> = self
+            .resource_kinds
+            .iter()
+            .map(|kind| js_str(kind))
+            .collect();
+        js.push_str(
+            "\n/** Names of resource kinds; the index of a name is the kind ID. */\n\
+             export const RESOURCE_KINDS = Object.freeze([",
+        );
+        js.push_str(&kinds.join(", "));
+        js.push_str("]);\n");
+        if self.tombstone.is_some() {
+            js.push_str(
+                "\n/** Default tombstone written into dropped slots of the `externref`s table. */\n\
+                 export const TOMBSTONE = Object.freeze({ tombstone: true });\n",
+            );
+        }
+
+        js.push_str(
+            "\n/** Imports with `externref` args or return values that must be provided \
+             by the host. */\nconst REQUIRED_IMPORTS = [\n",
+        );
+        for function in &self.imports {
+            let module = function.module.as_deref().unwrap_or_default();
+            writeln!(js, "  [{}, {}],", js_str(module), js_str(&function.name)).unwrap();
+        }
+        js.push_str("];\n");
+        js.push_str(match target {
+            GlueTarget::Browser => PRELUDE,
+            GlueTarget::Node => NODE_PRELUDE,
+        });
+        js.push_str(HELPERS);
+
+        self.write_instantiate(&mut js, target);
+        js
+    }
+
+    fn write_instantiate(&self, js: &mut String, target: GlueTarget) {
+        let source_type = match target {
+            GlueTarget::Browser => {
+                "WebAssembly.Module | BufferSource | Response | Promise<Response>"
+            }
+            GlueTarget::Node => "WebAssembly.Module | BufferSource | string | URL",
+        };
+        write!(
+            js,
+            "\n/**\n \
+             * Instantiates the processed WASM module, providing hooks configured during processing.\n \
+             *\n \
+             * @param {{{source_type}}} source\n \
+             * @param {{Record<string, Record<string, unknown>>}} [imports] Imports provided \
+             by the host\n \
+             * @param {{object}} [options]\n \
+             * @param {{(ref: unknown, kind?: string) => void}} [options.onDrop] Called when \
+             the module drops a reference\n",
+        )
+        .unwrap();
+        if target == GlueTarget::Node {
+            js.push_str(
+                " * @param {import(\"node:wasi\").WASI} [options.wasi] WASI instance providing \
+                 WASI imports; the module is initialized as a WASI reactor after instantiation\n",
+            );
+        }
+        if self.tombstone.is_some() {
+            js.push_str(
+                " * @param {unknown} [options.tombstone] Tombstone written into dropped table \
+                 slots; `TOMBSTONE` by default\n",
+            );
+        }
+        if self.slot_allocator.is_some() {
+            js.push_str(
+                " * @param {() => number} [options.allocSlot] Selects a slot of the `externref`s \
+                 table for an inserted reference; a negative value falls back to the built-in \
+                 slot search\n",
+            );
+        }
+        if self.leak_fn.is_some() {
+            js.push_str(
+                " * @param {(count: number) => void} [options.onLeak] Called with the number \
+                 of leaked references\n",
+            );
+        }
+        if self.compaction.is_some() {
+            js.push_str(
+                " * @param {(oldId: number, newId: number) => void} [options.onMove] Called \
+                 when compaction moves a reference to another slot\n",
+            );
+        }
+        js.push_str(
+            " */\n\
+             export async function instantiate(source, imports = {}, options = {}) {\n  \
+               for (const [module, name] of REQUIRED_IMPORTS) {\n    \
+                 if (typeof imports[module]?.[name] !== \"function\") {\n      \
+                   throw new TypeError(`missing import \\`${module}::${name}\\``);\n    \
+                 }\n  \
+               }\n\n  \
+               // Cleanup callbacks registered via `onRelease()`, keyed by the reference.\n  \
+               const cleanups = new WeakMap();\n  \
+               const registry = new FinalizationRegistry((callbacks) => {\n    \
+                 for (const callback of callbacks) callback();\n  \
+               });\n  \
+               const dropRef = (ref, kind) => {\n    \
+                 options.onDrop?.(ref, kind);\n    \
+                 const callbacks = cleanups.get(ref);\n    \
+                 if (callbacks !== undefined) {\n      \
+                   cleanups.delete(ref);\n      \
+                   registry.unregister(callbacks);\n      \
+                   for (const callback of callbacks) callback();\n    \
+                 }\n  \
+               };\n\n  \
+               const wasmImports = { ...imports };\n",
+        );
+        if target == GlueTarget::Node {
+            js.push_str(
+                "  if (options.wasi !== undefined) {\n    \
+                   Object.assign(wasmImports, options.wasi.getImportObject(), imports);\n  \
+                 }\n",
+            );
+        }
+        self.write_hooks(js);
+
+        js.push_str("\n  const instance = await instantiateSource(source, wasmImports);\n");
+        if target == GlueTarget::Node {
+            js.push_str("  options.wasi?.initialize(instance);\n");
+        }
+        js.push_str(
+            "  const raw = instance.exports;\n  \
+             return {\n    \
+               instance,\n    \
+               /** Wrappers for exported functions with `externref` args or return values. */\n    \
+               exports: Object.freeze({\n",
+        );
+        for function in &self.exports {
+            function.write_js_wrapper(js);
+        }
+        js.push_str("    }),\n");
+        if let Some(export_name) = &self.table.export_name {
+            writeln!(
+                js,
+                "    /** Exported `externref`s table. */\n    table: raw[{}],",
+                js_str(export_name)
+            )
+            .unwrap();
+            if target == GlueTarget::Node {
+                self.write_inspect_table(js, export_name);
+            }
+        }
+        js.push_str(
+            "    /**\n     \
+               * Registers a callback invoked once the module drops the reference, or once \
+               the reference\n     \
+               * is garbage-collected if the module never obtained it. The callback must not \
+               capture\n     \
+               * the reference.\n     \
+               *\n     \
+               * @param {object} ref\n     \
+               * @param {() => void} callback\n     \
+               */\n    \
+             onRelease(ref, callback) {\n      \
+               let callbacks = cleanups.get(ref);\n      \
+               if (callbacks === undefined) {\n        \
+                 callbacks = [];\n        \
+                 cleanups.set(ref, callbacks);\n        \
+                 registry.register(ref, callbacks, callbacks);\n      \
+               }\n      \
+               callbacks.push(callback);\n    \
+             },\n  \
+             };\n\
+             }\n",
+        );
+    }
+
+    /// Writes a helper listing live references in the exported `externref`s table.
+    fn write_inspect_table(&self, js: &mut String, export_name: &str) {
+        let is_free = if self.tombstone.is_some() {
+            "ref === null || ref === tombstone"
+        } else {
+            "ref === null"
+        };
+        writeln!(
+            js,
+            "    /**\n     \
+               * Lists live references in the `externref`s table, e.g. to check for leaks.\n     \
+               *\n     \
+               * @returns {{{{ index: number, ref: unknown }}[]}}\n     \
+               */\n    \
+             inspectTable() {{\n      \
+               const table = raw[{}];\n      \
+               const entries = [];\n      \
+               for (let index = 0; index < table.length; index++) {{\n        \
+                 const ref = table.get(index);\n        \
+                 if (!({is_free})) entries.push({{ index, ref }});\n      \
+               }}\n      \
+               return entries;\n    \
+             }},",
+            js_str(export_name)
+        )
+        .unwrap();
+    }
+
+    /// Writes implementations of the imports added by the processor (e.g., the drop hook).
+    fn write_hooks(&self, js: &mut String) {
+        if let Some(ImportManifest { module, name }) = &self.drop_fn {
+            let hook = if self.drop_fn_with_kind {
+                "(ref, kind) => dropRef(ref, RESOURCE_KINDS[kind])"
+            } else {
+                "(ref) => dropRef(ref)"
+            };
+            writeln!(
+                js,
+                "  withImport(wasmImports, {}, {}, {hook});",
+                js_str(module),
+                js_str(name)
+            )
+            .unwrap();
+        }
+        if let Some(ImportManifest { module, name }) = &self.tombstone {
+            writeln!(
+                js,
+                "  const tombstone = options.tombstone ?? TOMBSTONE;\n  \
+                 withImport(wasmImports, {}, {}, \
+                 new WebAssembly.Global({{ value: \"externref\" }}, tombstone));",
+                js_str(module),
+                js_str(name)
+            )
+            .unwrap();
+        }
+        if let Some(ImportManifest { module, name }) = &self.slot_allocator {
+            writeln!(
+                js,
+                "  const allocSlot = options.allocSlot ?? (() => -1);\n  \
+                 withImport(wasmImports, {}, {}, () => allocSlot());",
+                js_str(module),
+                js_str(name)
+            )
+            .unwrap();
+        }
+        if let Some(ImportManifest { module, name }) = &self.leak_fn {
+            writeln!(
+                js,
+                "  const onLeak = options.onLeak ?? \
+                 ((count) => console.warn(`${{count}} reference(s) leaked`));\n  \
+                 withImport(wasmImports, {}, {}, (count) => onLeak(count));",
+                js_str(module),
+                js_str(name)
+            )
+            .unwrap();
+        }
+        if let Some(compaction) = &self.compaction {
+            let ImportManifest { module, name } = &compaction.move_fn;
+            writeln!(
+                js,
+                "  withImport(wasmImports, {}, {}, (oldId, newId) => options.onMove?.(oldId, newId));",
+                js_str(module),
+                js_str(name)
+            )
+            .unwrap();
+        }
+    }
+}
+
+impl FunctionManifest {
+    fn write_js_wrapper(&self, js: &mut String) {
+        let (arg_types, result_types) = if self.resource_types.is_empty() {
+            (&[][..], &[][..])
+        } else {
+            self.resource_types.split_at(self.externref_args.len())
+        };
+        let arg_types: HashMap<_, _> = self.externref_args.iter().zip(arg_types).collect();
+        let result_types: HashMap<_, _> = self.externref_results.iter().zip(result_types).collect();
+
+        writeln!(js, "      /**\n       * WASM name: `{}`", self.name).unwrap();
+        for (i, ty) in self.params.iter().enumerate() {
+            let ty = arg_types
+                .get(&i)
+                .map_or_else(|| js_type(ty), |name| js_ident(name));
+            if self.host_handle_args.contains(&i) {
+                writeln!(
+                    js,
+                    "       * @param {{{ty}}} arg{i} Host handle (index in the `externref`s table)"
+                )
+                .unwrap();
+            } else {
+                writeln!(js, "       * @param {{{ty}}} arg{i}").unwrap();
+            }
+        }
+        let results: Vec<_> = self
+            .results
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| {
+                result_types
+                    .get(&i)
+                    .map_or_else(|| js_type(ty), |name| js_ident(name))
+            })
+            .collect();
+        let results = match results.as_slice() {
+            [] => "void".to_owned(),
+            [ty] => ty.clone(),
+            tys => format!("[{}]", tys.join(", ")),
+        };
+        let results_descr = if self.host_handle_results.is_empty() {
+            ""
+        } else {
+            " Host handle (index in the `externref`s table)"
+        };
+        let name = js_str(&self.name);
+        writeln!(
+            js,
+            "       * @returns {{{results}}}{results_descr}\n       \
+             */\n      \
+             {name}: (...args) => {{\n        \
+               checkArgs({name}, args, {});\n        \
+               return raw[{name}](...args);\n      \
+             }},",
+            self.params.len()
+        )
+        .unwrap();
+    }
+}
+
+/// Converts a Rust type name to a JS identifier.
+fn js_ident(name: &str) -> String {
+    let ident: String = name
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+        .collect();
+    if ident.starts_with(|ch: char| ch.is_ascii_digit()) {
+        format!("_{ident}")
+    } else {
+        ident
+    }
+}
+
+/// Converts a WASM value type to a type in JS doc comments.
+fn js_type(ty: &str) -> String {
+    match ty {
+        "i32" | "f32" | "f64" => "number",
+        "i64" => "bigint",
+        "funcref" => "Function | null",
+        _ => "unknown",
+    }
+    .to_owned()
+}
+
+/// Renders a string as a JS string literal.
+fn js_str(s: &str) -> String {
+    serde_json::to_string(s).expect("failed serializing string")
+}
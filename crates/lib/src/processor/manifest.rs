@@ -0,0 +1,637 @@
+//! Manifest describing the reference interface of a processed module.
+
+use core::fmt::Write as _;
+
+use serde::Serialize;
+
+use super::{
+    inspect::{inspect_module, FunctionInfo},
+    js_glue::GlueTarget,
+    DropSlotValue, Error, ImportName, ImportShimVisibility, Partitioning, Processor,
+};
+use crate::{
+    alloc::{format, vec, BTreeMap, BTreeSet, HashMap, String, ToOwned, Vec},
+    FunctionKind,
+};
+
+/// Manifest describing the reference interface of a WASM module after processing:
+/// imported / exported functions with `externref` positions, the `externref`s table
+/// and hooks configured in the [`Processor`].
+///
+/// The manifest is intended as a contract artifact exchanged between guest and host developers.
+/// It is serializable (e.g., to JSON), and its contents are ordered deterministically,
+/// so that manifests are easy to diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Manifest {
+    /// Version of the manifest format, currently [`Self::VERSION`].
+    pub version: u32,
+    /// Imported functions with `externref` args or return values, sorted by the module
+    /// and function name.
+    pub imports: Vec<FunctionManifest>,
+    /// Exported functions with `externref` args or return values, sorted by the name.
+    pub exports: Vec<FunctionManifest>,
+    /// [Function table entries](crate::FunctionKind::TableEntry) with `externref` args
+    /// or return values, sorted by the name.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub table_entries: Vec<FunctionManifest>,
+    /// Information about the `externref`s table. If the table is [partitioned](Partitioning::PerKind),
+    /// this is the partition with references of unknown kind.
+    pub table: TableManifest,
+    /// Information about per-kind partitions of the `externref`s table; the index
+    /// of a partition is the kind ID. Empty unless [`Partitioning::PerKind`] is used.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub kind_tables: Vec<TableManifest>,
+    /// Information about the table backing [`ResourceTable`](crate::ResourceTable)s;
+    /// `None` if the module does not use resource tables.
+    pub resource_table: Option<TableManifest>,
+    /// Information about the global backing [`GlobalResource`](crate::GlobalResource)s;
+    /// `None` if the module does not use resource globals.
+    pub resource_global: Option<GlobalManifest>,
+    /// Hook notifying the host about dropped references.
+    pub drop_fn: Option<ImportManifest>,
+    /// Whether the [drop hook](Self::drop_fn) receives the resource kind ID as the second arg.
+    pub drop_fn_with_kind: bool,
+    /// Names of resource kinds; the index of a name is the kind ID.
+    /// See [`Processor::set_drop_fn_with_kind()`] for details.
+    pub resource_kinds: Vec<String>,
+    /// Imported global with the tombstone reference written into dropped table slots.
+    pub tombstone: Option<ImportManifest>,
+    /// Imported function selecting slots for inserted references;
+    /// see [`Processor::set_slot_allocator()`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot_allocator: Option<ImportManifest>,
+    /// Hook notifying the host about leaked references; see [`Processor::set_leak_check()`].
+    pub leak_fn: Option<ImportManifest>,
+    /// Exported globals with resource metrics; `None` if metrics are disabled.
+    /// See [`Processor::enable_resource_metrics()`] for details.
+    pub resource_metrics: Option<MetricsManifest>,
+    /// Exported snapshot helpers; `None` if helpers are disabled.
+    /// See [`Processor::enable_snapshot_helpers()`] for details.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_helpers: Option<SnapshotManifest>,
+    /// Table compaction export and hook; `None` if compaction is disabled.
+    /// See [`Processor::enable_compaction()`] for details.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compaction: Option<CompactionManifest>,
+    /// Exported functions replacing the surrogate imports; `None` if the functions are internal.
+    /// See [`Processor::set_import_shim_visibility()`] for details.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shims: Option<ShimsManifest>,
+}
+
+impl Manifest {
+    /// Current version of the manifest format.
+    pub const VERSION: u32 = 1;
+}
+
+/// Format of the interface description produced by [`Processor::emit_interface()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InterfaceFormat {
+    /// Pretty-printed JSON serialization of the [`Manifest`].
+    Json,
+    /// [WIT]-like rendering of the [`Manifest`] as a world. Core WASM types are mapped
+    /// to the closest WIT types (e.g., `i32` to `s32`), and references are represented
+    /// by an opaque `externref` resource. Names are converted to kebab case as required by WIT;
+    /// original names are retained in doc comments.
+    ///
+    /// The output is meant for documentation and code generation tooling;
+    /// the processed module is still a core WASM module, not a component.
+    ///
+    /// [WIT]: https://component-model.bytecodealliance.org/design/wit.html
+    Wit,
+    /// ES module with glue code for browser hosts not using `wasm-bindgen`. The module exports
+    /// the `instantiate()` function, which instantiates the processed module with the imports
+    /// provided by the host, implements hooks configured during processing (e.g., forwards
+    /// the drop hook to an `onDrop` callback), and returns typed wrappers for exports
+    /// listed in the manifest. Cleanup callbacks can be attached to references via `onRelease()`;
+    /// they are called once the module drops the reference, similar to `FinalizationRegistry`.
+    JsGlue,
+    /// Counterpart of [`Self::JsGlue`] for Node.js hosts. In addition to the browser glue
+    /// functionality, the module can be loaded from a file path, a [WASI] instance can be passed
+    /// via the `wasi` option (its imports are provided to the module, which is then initialized
+    /// as a WASI reactor), and live references in the exported `externref`s table can be listed
+    /// via `inspectTable()`.
+    ///
+    /// [WASI]: https://nodejs.org/api/wasi.html
+    NodeGlue,
+}
+
+/// Information about a function in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct FunctionManifest {
+    /// Name of the module the function is imported from; `None` for exported functions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    /// Name of the function.
+    pub name: String,
+    /// WASM types of the function args after processing, e.g. `i32` or `externref`.
+    pub params: Vec<String>,
+    /// WASM types of the function return values after processing.
+    pub results: Vec<String>,
+    /// 0-based indices of `externref` args.
+    pub externref_args: Vec<usize>,
+    /// 0-based indices of `externref` return values.
+    pub externref_results: Vec<usize>,
+    /// Rust type names of resources in the order of [`Self::externref_args`] followed by
+    /// [`Self::externref_results`]. Empty unless recorded via `#[externref(type_names)]`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resource_types: Vec<String>,
+    /// 0-based indices of args holding [`HostHandle`](crate::HostHandle)s, i.e., indices
+    /// in the `externref`s table. The args have the [index type](super::IndexType)
+    /// of the module (`i32` by default).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub host_handle_args: Vec<usize>,
+    /// 0-based indices of return values holding [`HostHandle`](crate::HostHandle)s;
+    /// see [`Self::host_handle_args`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub host_handle_results: Vec<usize>,
+}
+
+/// Information about the `externref`s table in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct TableManifest {
+    /// Name of the exported table; `None` if the table is not exported.
+    pub export_name: Option<String>,
+    /// Initial size of the table.
+    pub initial_size: u32,
+    /// Maximum size of the table; `None` if the table is unbounded.
+    pub max_size: Option<u32>,
+}
+
+/// Information about the mutable `externref` global in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct GlobalManifest {
+    /// Name of the exported global; `None` if the global is not exported.
+    pub export_name: Option<String>,
+}
+
+/// Information about resource metrics in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct MetricsManifest {
+    /// Name of the exported global with the number of live references.
+    pub live_count: String,
+    /// Name of the exported global with the maximum number of simultaneously live references.
+    pub peak_count: String,
+}
+
+/// Information about snapshot helpers in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct SnapshotManifest {
+    /// Name of the exported function returning the number of slots to save.
+    pub snapshot_len: String,
+    /// Name of the exported function preparing the `externref`s table for restoring.
+    pub restore_prepare: String,
+}
+
+/// Information about table compaction in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct CompactionManifest {
+    /// Name of the exported function compacting the `externref`s table.
+    pub compact: String,
+    /// Hook notifying the host about moved references.
+    pub move_fn: ImportManifest,
+}
+
+/// Information about exported functions replacing the surrogate imports in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct ShimsManifest {
+    /// Name of the exported function inserting a reference into the `externref`s table.
+    pub insert: String,
+    /// Name of the exported function getting a reference by its ID.
+    pub get: String,
+    /// Name of the exported function dropping a reference by its ID.
+    pub drop: String,
+}
+
+/// Import specified in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct ImportManifest {
+    /// Name of the imported module.
+    pub module: String,
+    /// Name of the import.
+    pub name: String,
+}
+
+impl Manifest {
+    fn to_wit(&self) -> String {
+        let mut imports = BTreeMap::<_, Vec<_>>::new();
+        for function in &self.imports {
+            let module = function.module.as_deref().unwrap_or_default();
+            imports.entry(module).or_default().push(function.to_wit());
+        }
+        let hooks = [
+            self.drop_fn.as_ref().map(|hook| {
+                let params = if self.drop_fn_with_kind {
+                    &["externref", "i32"][..]
+                } else {
+                    &["externref"]
+                };
+                (hook, params, &[][..])
+            }),
+            self.leak_fn
+                .as_ref()
+                .map(|hook| (hook, &["i32"][..], &[][..])),
+            self.slot_allocator
+                .as_ref()
+                .map(|hook| (hook, &[][..], &["i32"][..])),
+            self.compaction
+                .as_ref()
+                .map(|compaction| (&compaction.move_fn, &["i32", "i32"][..], &[][..])),
+        ];
+        for (hook, params, results) in hooks.into_iter().flatten() {
+            let params: Vec<_> = params.iter().map(|&ty| ty.to_owned()).collect();
+            let results: Vec<_> = results.iter().map(|&ty| ty.to_owned()).collect();
+            let function = FunctionManifest {
+                module: Some(hook.module.clone()),
+                name: hook.name.clone(),
+                params,
+                results,
+                externref_args: vec![],
+                externref_results: vec![],
+                resource_types: vec![],
+                host_handle_args: vec![],
+                host_handle_results: vec![],
+            };
+            imports
+                .entry(&hook.module)
+                .or_default()
+                .push(function.to_wit());
+        }
+
+        let mut wit = format!(
+            "// Interface of a WASM module processed by `externref` (manifest version {}).\n",
+            self.version
+        );
+        if !self.resource_kinds.is_empty() {
+            let kinds = self.resource_kinds.join(", ");
+            writeln!(wit, "// Resource kinds: {kinds}").unwrap();
+        }
+        wit.push_str("world module {\n    resource externref;\n");
+        let resource_types: BTreeSet<_> = self
+            .imports
+            .iter()
+            .chain(&self.exports)
+            .flat_map(|function| &function.resource_types)
+            .map(|name| wit_ident(name))
+            .collect();
+        for name in resource_types {
+            writeln!(wit, "    resource {name};").unwrap();
+        }
+        for (module, functions) in imports {
+            writeln!(wit, "\n    /// WASM module: `{module}`").unwrap();
+            writeln!(wit, "    import {}: interface {{", wit_ident(module)).unwrap();
+            for function in functions {
+                writeln!(wit, "        {}", function.replace('\n', "\n        ")).unwrap();
+            }
+            wit.push_str("    }\n");
+        }
+        if !self.exports.is_empty() {
+            wit.push('\n');
+        }
+        for function in &self.exports {
+            let function = function.to_wit().replace('\n', "\n    export ");
+            writeln!(wit, "    {function}").unwrap();
+        }
+        wit.push_str("}\n");
+        wit
+    }
+}
+
+impl FunctionManifest {
+    /// Renders the function as a WIT doc comment and a declaration, separated by a newline.
+    fn to_wit(&self) -> String {
+        let (arg_types, result_types) = if self.resource_types.is_empty() {
+            (&[][..], &[][..])
+        } else {
+            self.resource_types.split_at(self.externref_args.len())
+        };
+        let arg_types: HashMap<_, _> = self.externref_args.iter().zip(arg_types).collect();
+        let result_types: HashMap<_, _> = self.externref_results.iter().zip(result_types).collect();
+
+        let params: Vec<_> = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| {
+                let ty = arg_types
+                    .get(&i)
+                    .map_or_else(|| wit_type(ty), |name| wit_ident(name));
+                format!("arg{i}: {ty}")
+            })
+            .collect();
+        let results: Vec<_> = self
+            .results
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| {
+                result_types
+                    .get(&i)
+                    .map_or_else(|| wit_type(ty), |name| wit_ident(name))
+            })
+            .collect();
+        let results = match results.as_slice() {
+            [] => String::new(),
+            [ty] => format!(" -> {ty}"),
+            tys => format!(" -> tuple<{}>", tys.join(", ")),
+        };
+        format!(
+            "/// WASM name: `{}`\n{}: func({}){results};",
+            self.name,
+            wit_ident(&self.name),
+            params.join(", ")
+        )
+    }
+}
+
+/// Converts a WASM or Rust type name to a WIT identifier (i.e., kebab case).
+fn wit_ident(name: &str) -> String {
+    let mut ident = String::with_capacity(name.len());
+    let mut prev_char = None::<char>;
+    for ch in name.chars() {
+        if !ch.is_ascii_alphanumeric() {
+            prev_char = None;
+            continue;
+        }
+        let is_boundary = prev_char.is_none()
+            || (ch.is_ascii_uppercase()
+                && prev_char
+                    .is_some_and(|prev| prev.is_ascii_lowercase() || prev.is_ascii_digit()));
+        if is_boundary && !ident.is_empty() {
+            ident.push('-');
+        }
+        ident.push(ch.to_ascii_lowercase());
+        prev_char = Some(ch);
+    }
+    if ident.is_empty() {
+        "unnamed".to_owned()
+    } else {
+        ident
+    }
+}
+
+fn wit_type(ty: &str) -> String {
+    match ty {
+        "i32" => "s32",
+        "i64" => "s64",
+        _ => ty,
+    }
+    .to_owned()
+}
+
+impl ImportManifest {
+    fn new(module: &str, name: &str) -> Self {
+        Self {
+            module: module.to_owned(),
+            name: name.to_owned(),
+        }
+    }
+}
+
+impl Processor<'_> {
+    /// Creates a [`Manifest`] describing the reference interface of the provided module
+    /// after it is processed with this processor. The module is not modified; it must not be
+    /// processed beforehand.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error in the same cases as [`inspect()`](super::inspect()). Additionally,
+    /// returns [`Error::UnknownSection`] if the module contains declarations in a custom section
+    /// not configured in this processor.
+    pub fn manifest(&self, module_bytes: &[u8]) -> Result<Manifest, Error> {
+        let interface = inspect_module(module_bytes, self.surrogate_module)?;
+        for &(name, _) in &interface.sections {
+            self.check_section(name)?;
+        }
+        let section_renames = self.section_renames(interface.sections.iter().copied())?;
+        let mut imports = vec![];
+        let mut exports = vec![];
+        let mut table_entries = vec![];
+        for function in interface.functions {
+            let kind = function.declaration.kind;
+            let manifest = self.function_manifest(function, &section_renames);
+            match kind {
+                FunctionKind::Import(_) => imports.push(manifest),
+                FunctionKind::Export => exports.push(manifest),
+                // Re-exported imports must be provided by the host and are exported
+                // after processing, so they are listed both as imports and exports.
+                FunctionKind::Reexport {
+                    module,
+                    import_name,
+                } => {
+                    let (module, name) = self
+                        .renamed_declared_import(module, import_name, &section_renames)
+                        .unwrap_or((module, import_name));
+                    imports.push(FunctionManifest {
+                        module: Some(module.to_owned()),
+                        name: name.to_owned(),
+                        ..manifest.clone()
+                    });
+                    exports.push(manifest);
+                }
+                FunctionKind::TableEntry => table_entries.push(manifest),
+                // The start function is not exported after processing, so it is not listed.
+                FunctionKind::Start => { /* skip */ }
+            }
+        }
+        imports.sort_unstable_by(|x, y| (&x.module, &x.name).cmp(&(&y.module, &y.name)));
+        exports.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+        table_entries.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+
+        let drop_fn = self.drop_fn_name.map(|(module, name)| {
+            let (module, name) = self.renamed_import(module, name).unwrap_or((module, name));
+            ImportManifest::new(module, name)
+        });
+        let tombstone = match self.drop_slot_value {
+            DropSlotValue::Null => None,
+            DropSlotValue::Tombstone { module, name } => {
+                let (module, name) = self.renamed_import(module, name).unwrap_or((module, name));
+                Some(ImportManifest::new(module, name))
+            }
+        };
+
+        let slot_allocator = self.slot_allocator.map(|(module, name)| {
+            let (module, name) = self.renamed_import(module, name).unwrap_or((module, name));
+            ImportManifest::new(module, name)
+        });
+        let leak_fn = self.leak_check.map(|leak_check| {
+            let (module, name) = leak_check.hook;
+            let (module, name) = self.renamed_import(module, name).unwrap_or((module, name));
+            ImportManifest::new(module, name)
+        });
+
+        let kind_tables = if self.partitioning == Partitioning::PerKind {
+            let kind_tables = interface.resource_kinds.iter().map(|kind| {
+                let name = self.table_name.map(|name| format!("{name}::{kind}"));
+                self.table_manifest(name.as_deref(), self.reserved_slots)
+            });
+            kind_tables.collect()
+        } else {
+            vec![]
+        };
+
+        Ok(Manifest {
+            version: Manifest::VERSION,
+            imports,
+            exports,
+            table_entries,
+            table: self.table_manifest(self.table_name, self.reserved_slots),
+            kind_tables,
+            resource_table: interface
+                .uses_resource_table
+                .then(|| self.table_manifest(self.resource_table_name, 0)),
+            resource_global: interface.uses_resource_global.then(|| GlobalManifest {
+                export_name: self
+                    .resource_global_name
+                    .map(|name| self.renamed_export(name).to_owned()),
+            }),
+            drop_fn,
+            drop_fn_with_kind: self.drop_fn_with_kind,
+            resource_kinds: interface
+                .resource_kinds
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            tombstone,
+            slot_allocator,
+            leak_fn,
+            resource_metrics: self.resource_metrics.then(|| MetricsManifest {
+                live_count: self.renamed_export(Self::LIVE_COUNT_EXPORT).to_owned(),
+                peak_count: self.renamed_export(Self::PEAK_COUNT_EXPORT).to_owned(),
+            }),
+            snapshot_helpers: self.snapshot_helpers.then(|| SnapshotManifest {
+                snapshot_len: self.renamed_export(Self::SNAPSHOT_LEN_EXPORT).to_owned(),
+                restore_prepare: self.renamed_export(Self::RESTORE_PREPARE_EXPORT).to_owned(),
+            }),
+            compaction: self.compaction_manifest(),
+            shims: self.shims_manifest(),
+        })
+    }
+
+    fn shims_manifest(&self) -> Option<ShimsManifest> {
+        let ImportShimVisibility::Exported { insert, get, drop } = self.shim_visibility else {
+            return None;
+        };
+        Some(ShimsManifest {
+            insert: self.renamed_export(insert).to_owned(),
+            get: self.renamed_export(get).to_owned(),
+            drop: self.renamed_export(drop).to_owned(),
+        })
+    }
+
+    fn compaction_manifest(&self) -> Option<CompactionManifest> {
+        let (module, name) = self.compaction_hook?;
+        let (module, name) = self.renamed_import(module, name).unwrap_or((module, name));
+        Some(CompactionManifest {
+            compact: self.renamed_export(Self::COMPACT_EXPORT).to_owned(),
+            move_fn: ImportManifest::new(module, name),
+        })
+    }
+
+    /// Creates a manifest for an unbounded table with the specified export name
+    /// (before renaming).
+    fn table_manifest(&self, export_name: Option<&str>, initial_size: u32) -> TableManifest {
+        TableManifest {
+            export_name: export_name.map(|name| self.renamed_export(name).to_owned()),
+            initial_size,
+            max_size: None,
+        }
+    }
+
+    /// Produces a machine-readable description of the reference interface of the provided module
+    /// after it is processed with this processor. This is a serialized [`Manifest`];
+    /// see [`InterfaceFormat`] for the supported formats.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error in the same cases as [`Self::manifest()`].
+    #[allow(clippy::missing_panics_doc)] // serializing a manifest cannot fail
+    pub fn emit_interface(
+        &self,
+        module_bytes: &[u8],
+        format: InterfaceFormat,
+    ) -> Result<String, Error> {
+        let manifest = self.manifest(module_bytes)?;
+        Ok(match format {
+            InterfaceFormat::Json => {
+                serde_json::to_string_pretty(&manifest).expect("failed serializing manifest")
+            }
+            InterfaceFormat::Wit => manifest.to_wit(),
+            InterfaceFormat::JsGlue => manifest.to_js_glue(GlueTarget::Browser),
+            InterfaceFormat::NodeGlue => manifest.to_js_glue(GlueTarget::Node),
+        })
+    }
+
+    fn function_manifest(
+        &self,
+        function: FunctionInfo<'_>,
+        section_renames: &[(ImportName<'_>, ImportName<'_>)],
+    ) -> FunctionManifest {
+        let is_table_entry = function.declaration.kind == FunctionKind::TableEntry;
+        let module = function.module();
+        let renamed = module.and_then(|module| {
+            self.renamed_declared_import(module, function.name(), section_renames)
+        });
+        let (module, name) = match (renamed, module) {
+            (Some((module, name)), _) => (Some(module), name),
+            (None, Some(module)) => (Some(module), function.name()),
+            (None, None) if is_table_entry => (None, function.name()),
+            (None, None) => (None, self.renamed_export(function.name())),
+        };
+
+        let signature = function.processed_signature();
+        FunctionManifest {
+            module: module.map(str::to_owned),
+            name: name.to_owned(),
+            params: signature.params,
+            results: signature.results,
+            externref_args: function.resource_args,
+            externref_results: function.resource_results,
+            resource_types: function
+                .declaration
+                .type_names
+                .iter()
+                .map(str::to_owned)
+                .collect(),
+            host_handle_args: function.host_handle_args,
+            host_handle_results: function.host_handle_results,
+        }
+    }
+
+    fn renamed_import(&self, module: &str, name: &str) -> Option<(&str, &str)> {
+        self.import_renames
+            .iter()
+            .find_map(|&(from, to)| (from == (module, name)).then_some(to))
+    }
+
+    /// Same as [`Self::renamed_import()`], but additionally takes into account renames
+    /// derived from [section options](super::SectionOptions) for declared imports.
+    fn renamed_declared_import<'s>(
+        &'s self,
+        module: &str,
+        name: &str,
+        section_renames: &[(ImportName<'s>, ImportName<'s>)],
+    ) -> Option<(&'s str, &'s str)> {
+        self.renamed_import(module, name).or_else(|| {
+            section_renames
+                .iter()
+                .find_map(|&(from, to)| (from == (module, name)).then_some(to))
+        })
+    }
+
+    fn renamed_export<'s>(&'s self, name: &'s str) -> &'s str {
+        self.export_renames
+            .iter()
+            .find_map(|&(from, to)| (from == name).then_some(to))
+            .unwrap_or(name)
+    }
+}
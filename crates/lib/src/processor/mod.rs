@@ -7,16 +7,42 @@
 //! More precisely, the processor performs the following steps:
 //!
 //! - Parse the custom section with [`Function`] declarations and remove this section
-//!   from the module.
+//!   from the module. Declarations may be split among [several sections](Processor::add_section())
+//!   with section-specific options, e.g. for independent libraries linked into the module.
 //! - Replace imported functions from a surrogate module for handling `externref`s with
 //!   local functions.
 //! - Patch signatures and implementations of imported / exported functions so that they
 //!   use `externref`s where appropriate.
+//!   If a declared export points to a trampoline merely forwarding its args to another
+//!   local function (e.g., one generated by adapters for the `wasm32-wasip2` target),
+//!   the export is redirected to the forwarded-to function, which is patched instead.
+//! - Patch signatures of [function table entries](crate::FunctionKind::TableEntry)
+//!   and types of `call_indirect` instructions accessing the tables the entries are placed in,
+//!   so that resources can be passed via function pointers.
+//! - Export imports declared as [re-exports](crate::FunctionKind::Reexport) after patching
+//!   their signatures, so that the host can expose them to other modules.
+//! - Make the declared [start function](crate::FunctionKind::Start) the start function
+//!   of the module. The `externref`s table is defined in the module, so it is initialized
+//!   before the start function runs, and the function can freely create resources.
 //! - Add an initially empty, unconstrained table with `externref` elements and optionally
 //!   export it from the module. The host can use the table to inspect currently used references
 //!   (e.g., to save / restore WASM instance state).
+//! - Optionally, run [hooks](ProcessingHook) on patched functions, e.g. to instrument them.
+//! - Optionally, rename imports / exports (e.g., to adapt the module to host namespaces).
+//! - Add a custom section marking the module as processed, so that it is not accidentally
+//!   processed again.
 //!
+//! For the common case, [`process_bytes()`] processes a module with the default options.
 //! See [crate-level docs](..) for more insights on WASM module setup and processing.
+//! Function declarations can be inspected without processing the module
+//! via [`inspect()`]; a [`Manifest`] describing the reference interface of the processed module
+//! can be created via [`Processor::manifest()`] and serialized to JSON or WIT
+//! via [`Processor::emit_interface()`]. [`Processor::verify()`] checks that a module can be
+//! processed without producing the processed module.
+//!
+//! The processor implements the [`Transform`] trait, so it can be registered in pipelines
+//! accepting generic transforms of module bytes. For non-Rust build systems, the `capi`
+//! module (behind the `capi` crate feature) provides a stable C ABI for processing.
 //!
 //! # On processing order
 //!
@@ -25,14 +51,70 @@
 //! producing invalid WASM bytecode (roughly speaking, excessively replacing `i32`s
 //! with `externref`s). Such inlining can usually be detected by the processor, in which case
 //! it will return [`Error::IncorrectGuard`] or [`Error::UnexpectedCall`]
-//! from [`process()`](Processor::process()).
+//! from [`process()`](Processor::process()). These errors point to the offending instruction
+//! by its [index](CodeLocation) in the function body; if the `dwarf` crate feature is enabled
+//! and the module contains DWARF debug info, the instruction is also resolved to
+//! a [source location](SourceLocation).
 //!
 //! Optimizing WASM after the processor has an additional advantage in that it can
 //! optimize the changes produced by it (optimization is hard, and is best left
-//! to the dedicated tools).
+//! to the dedicated tools). The processor can run `wasm-opt` itself in the correct order;
+//! see [`Processor::set_post_optimizer()`].
+//!
+//! The same applies to asyncify (`wasm-opt --asyncify`), which is used to suspend
+//! and resume WASM execution on async imports. After processing, an `externref` returned
+//! by an import is inserted into the `externref`s table by the local function replacing
+//! the import, immediately after the import returns; since asyncify replays the import call
+//! when rewinding the stack, this remains true for suspended imports. Conversely, asyncify
+//! transformations before processing break the processor assumptions on placement of calls.
+//! Thus, the processor refuses to process modules already transformed by asyncify
+//! with [`Error::Asyncified`]; see [`Processor::set_asyncify_prefixes()`].
+//!
+//! # Slot index stability
+//!
+//! A reference obtained from the host is placed into a slot of the `externref`s table
+//! and is identified by the slot index (for example, [`HostHandle`](crate::HostHandle)s
+//! are exactly slot indices) until it is dropped. The processed module guarantees that:
+//!
+//! - A live reference is never moved to another slot, unless the module is processed
+//!   with [compaction](Processor::enable_compaction()) and the host calls the compaction export.
+//! - A slot is reused only after its reference is dropped; a new reference is placed
+//!   into the free slot with the highest index, or appended to the table if there are
+//!   no free slots.
+//! - If the table is [partitioned](Partitioning::PerKind), a reference ID
+//!   encodes the slot index together with the partition index, so IDs are stable as well.
+//! - The table is never shrunk.
+//!
+//! Thus, an instance can be migrated by saving the linear memory, globals and
+//! the `externref`s table, and restoring them into a fresh instance with references placed
+//! into the same slots. [Snapshot helpers](Processor::enable_snapshot_helpers()) allow
+//! to do this without knowing the internal table layout. Note that the processor-managed
+//! state not stored in the `externref`s table is not restored by the helpers. Namely:
+//!
+//! - [Resource metrics](Processor::enable_resource_metrics()) are exported mutable globals;
+//!   the host should restore them along with other globals.
+//! - Resource kinds of restored references are unknown, so the [drop hook](Processor::set_drop_fn_with_kind())
+//!   will receive -1 as the kind for them (unless the table is partitioned).
+//! - If dropped slots are [filled with tombstones](DropSlotValue::Tombstone), all restored slots
+//!   are considered occupied until the corresponding references are dropped.
+//!   Hence, slots freed before the snapshot are not reused, and are reported
+//!   by [leak checks](Processor::set_leak_check()).
+//! - The table backing [`ResourceTable`](crate::ResourceTable)s is not covered.
 //!
 //! # Examples
 //!
+//! Processing a module with the default options:
+//!
+//! ```
+//! let module: Vec<u8> = // WASM module, e.g., loaded from the file system
+//! #    b"\0asm\x01\0\0\0".to_vec();
+//! let processed: Vec<u8> = externref::processor::process_bytes(&module)?;
+//! // Store or use the processed module...
+//! # Ok::<_, externref::processor::Error>(())
+//! ```
+//!
+//! Customizing processing via [`Processor`]:
+//!
 //! ```
 //! use externref::processor::Processor;
 //!
@@ -46,36 +128,379 @@
 //! # Ok::<_, externref::processor::Error>(())
 //! ```
 
-use walrus::{passes::gc, Module, RefType, ValType};
+use core::iter;
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    io::{self, Write as _},
+    path::Path,
+    process,
+};
+
+use walrus::{
+    passes::gc, ExportItem, IdsToIndices, Local, Module, ModuleConfig, RawCustomSection, RefType,
+    ValType,
+};
+use wasmparser::Validator;
+pub use wasmparser::WasmFeatures;
 
-pub use self::error::{Error, Location};
-use self::state::ProcessingState;
-use crate::Function;
+#[cfg(feature = "miette")]
+pub use self::diagnostic::ErrorReport;
+pub use self::{
+    artifacts::ProcessingArtifacts,
+    error::{CodeLocation, Error, Location, SourceLocation},
+    hooks::{PatchedFunction, PatchedFunctionKind, ProcessingHook},
+    inspect::{inspect, FunctionInfo},
+    manifest::{
+        CompactionManifest, FunctionManifest, GlobalManifest, ImportManifest, InterfaceFormat,
+        Manifest, MetricsManifest, ShimsManifest, SnapshotManifest, TableManifest,
+    },
+    observer::{EventLog, ProcessingEvent, ProcessorObserver},
+};
+use self::{
+    error::ErrorCollector,
+    functions::ExternrefImports,
+    gc_roots::{remove_dangling_imports, GcRoots},
+    names::LocalNames,
+    state::{matches_glob, ProcessingState},
+};
+#[cfg(feature = "std")]
+pub use self::{
+    optimizer::PostOptimizer,
+    transform::{Transform, TransformError},
+};
+use crate::{
+    alloc::{format, vec, String, ToOwned, Vec},
+    Function, FunctionKind,
+};
 
+mod artifacts;
+#[cfg(feature = "capi")]
+#[cfg_attr(docsrs, doc(cfg(feature = "capi")))]
+pub mod capi;
+mod debug_info;
+#[cfg(feature = "miette")]
+mod diagnostic;
 mod error;
+#[cfg(feature = "test-fixtures")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-fixtures")))]
+pub mod fixtures;
 mod functions;
+mod gc_roots;
+#[cfg(feature = "test-fixtures")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-fixtures")))]
+pub mod golden;
+mod hooks;
+mod inspect;
+#[cfg(feature = "test-util")]
+mod interpreter;
+mod js_glue;
+mod manifest;
+mod names;
+mod observer;
+#[cfg(feature = "std")]
+mod optimizer;
+mod scan;
 mod state;
+#[cfg(feature = "test-util")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-util")))]
+pub mod test_util;
+#[cfg(feature = "std")]
+mod transform;
+
+/// Splits a raw custom section into its name and data.
+fn raw_section_parts(section: &RawCustomSection) -> (&str, &[u8]) {
+    (&section.name, &section.data)
+}
 
 /// Externref type as a constant.
 const EXTERNREF: ValType = ValType::Ref(RefType::Externref);
 
+/// Import name specified as a `(module, name)` tuple.
+type ImportName<'a> = (&'a str, &'a str);
+
+/// Value written into a slot of the `externref`s table when the reference in the slot is dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DropSlotValue<'a> {
+    /// Null reference. This is the default.
+    #[default]
+    Null,
+    /// Tombstone reference provided by the host as an immutable imported global
+    /// with the `externref` type.
+    ///
+    /// Tombstones allow the host to distinguish "never used" table slots (which contain
+    /// nulls) from "used and freed" slots. Since WASM cannot compare `externref`s,
+    /// the processor adds a non-exported shadow table tracking slot occupancy;
+    /// slots with tombstones are considered free and are reused for new references.
+    Tombstone {
+        /// Name of the module the global is imported from.
+        module: &'a str,
+        /// Name of the imported global.
+        name: &'a str,
+    },
+}
+
+/// Behavior of the processed module if a reference is dropped while the [drop hook](Processor::set_drop_fn())
+/// is running, e.g. if the hook calls back into the module, which drops another resource.
+///
+/// Unless the policy is [`Allow`](Self::Allow), the processor adds a module-level flag
+/// set while the hook is running. The flag is not reset if the hook traps, so after a trap
+/// in the hook, the instance should not be used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DropReentrancy {
+    /// Reentrant drops are not checked; the drop hook is called for each of them.
+    /// This is the default.
+    #[default]
+    Allow,
+    /// A reentrant drop traps (i.e., executes `unreachable`).
+    Trap,
+    /// A reentrant drop frees the table slot, but does not call the drop hook. Thus, the host
+    /// is not notified about such drops.
+    Skip,
+    /// A reentrant drop frees the table slot and defers the drop hook call for the reference.
+    /// Deferred calls are made by the outermost drop after the hook returns; drops made
+    /// by deferred calls are deferred as well. The order of deferred calls is unspecified.
+    Queue,
+}
+
+/// Visibility of the functions replacing the surrogate `insert`, `get` and `drop` imports;
+/// see [`Processor::set_import_shim_visibility()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImportShimVisibility<'a> {
+    /// Functions are internal to the module. This is the default.
+    #[default]
+    Internal,
+    /// Functions are exported under the specified names.
+    Exported {
+        /// Name of the exported `(externref, i32) -> i32` function inserting a reference
+        /// of the specified kind into the `externref`s table and returning its ID.
+        insert: &'a str,
+        /// Name of the exported `(i32) -> externref` function getting a reference by its ID.
+        get: &'a str,
+        /// Name of the exported `(i32) -> ()` function dropping a reference by its ID.
+        drop: &'a str,
+    },
+}
+
+impl ImportShimVisibility<'static> {
+    /// Exports functions under the default names: `externref_insert`, `externref_get`
+    /// and `externref_drop`.
+    pub const EXPORTED: Self = Self::Exported {
+        insert: "externref_insert",
+        get: "externref_get",
+        drop: "externref_drop",
+    };
+}
+
+/// Partitioning of the `externref`s table by resource kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Partitioning {
+    /// All references are placed into a single table. This is the default.
+    #[default]
+    Shared,
+    /// Each resource kind gets a dedicated table, so that the host can track usage
+    /// of each kind independently (e.g., to enforce per-kind limits).
+    ///
+    /// References of unknown kind (e.g., ones obtained from a [`ResourceTable`](crate::ResourceTable))
+    /// are placed into the table with the [configured name](Processor::set_ref_table()).
+    /// A table for the kind `Kind` is exported as `{name}::Kind`. Tables are listed
+    /// in [`Manifest::kind_tables`]. Besides kinds derived from resource types, the module
+    /// may route resources to named partitions via `#[externref(table = "name")]`.
+    ///
+    /// Reference IDs used by the module (including [`HostHandle`](crate::HostHandle) indices)
+    /// encode both the table and the slot index in the table as `slot * (kind_count + 1) + table`,
+    /// where `table` is 0 for references of unknown kind and `kind_id + 1` otherwise.
+    PerKind,
+}
+
+/// Type of reference IDs in the interface of the surrogate functions and declared functions.
+///
+/// The index type applies to the surrogate `insert`, `get`, `drop`, `drop_range` and `is_live`
+/// imports, to surrogate references in the signatures of declared functions, and to
+/// [`HostHandle`](crate::HostHandle) args. If the signature of a surrogate import does not match
+/// the index type, processing fails with [`Error::UnexpectedIndexType`]. Imports used by
+/// [`ResourceTable`](crate::ResourceTable)s, [`GlobalResource`](crate::GlobalResource)s
+/// and [`SharedResource`](crate::SharedResource)s always use 32-bit IDs.
+///
+/// The `externref`s table is always indexed by `i32`s; IDs of the configured type are converted
+/// to table indices at the surrogate function boundary. Converting an ID that does not fit
+/// into an `i32` (other than the `-1` sentinel for null references, i.e., `usize::MAX`)
+/// results in a trap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IndexType {
+    /// 32-bit IDs, corresponding to `usize` on `wasm32` targets. This is the default.
+    #[default]
+    I32,
+    /// 64-bit IDs, corresponding to `usize` on `wasm64` targets (i.e., with the `memory64`
+    /// proposal).
+    I64,
+}
+
+/// Garbage collection of unused items (functions, globals, types etc.) in the processed module;
+/// see [`Processor::set_gc()`]. Memories are never removed by the GC, so that memory imports
+/// and indices are left untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GcMode<'a> {
+    /// Removes all items not reachable from exports, the start function, element segments etc.
+    /// This is the default.
+    #[default]
+    Full,
+    /// Skips garbage collection, so that all items from the original module are retained.
+    /// Surrogate functions replaced during processing are still removed so that the module
+    /// remains valid.
+    Skip,
+    /// Same as [`Self::Full`], but additionally retains local functions with names matching
+    /// any of the provided glob patterns (`*` matches any sequence of chars, `?` matches
+    /// a single char), e.g. `"helper_*"`. Names are taken from the `name` section
+    /// of the module; functions without names are not retained.
+    KeepNamed(Vec<&'a str>),
+}
+
+/// Options for processing function declarations from an additional custom section;
+/// see [`Processor::add_section()`].
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct SectionOptions<'a> {
+    /// Module of imports declared in the section after processing. If set, imported functions
+    /// declared in the section are renamed to be imported from this module.
+    pub import_module: Option<&'a str>,
+}
+
+impl<'a> SectionOptions<'a> {
+    /// Sets the module of imports declared in the section after processing, e.g. to move
+    /// imports of a plugin SDK to a dedicated host namespace. Renaming is applied after
+    /// [explicit renames](Processor::rename_import()); if an import was renamed explicitly,
+    /// it is not affected.
+    #[must_use]
+    pub fn with_import_module(mut self, module: &'a str) -> Self {
+        self.import_module = Some(module);
+        self
+    }
+}
+
+/// Configuration of leak checks; see [`Processor::set_leak_check()`].
+#[derive(Debug, Clone, Copy)]
+struct LeakCheck<'a> {
+    hook: ImportName<'a>,
+    exports_pattern: &'a str,
+}
+
 /// WASM module processor encapsulating processing options.
 #[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)] // flags are independent processing options
 pub struct Processor<'a> {
     table_name: Option<&'a str>,
+    resource_table_name: Option<&'a str>,
+    resource_global_name: Option<&'a str>,
     drop_fn_name: Option<(&'a str, &'a str)>,
+    drop_fn_with_kind: bool,
+    drop_slot_value: DropSlotValue<'a>,
+    drop_reentrancy: DropReentrancy,
+    slot_allocator: Option<ImportName<'a>>,
+    reserved_slots: u32,
+    partitioning: Partitioning,
+    index_type: IndexType,
+    shim_visibility: ImportShimVisibility<'a>,
+    leak_check: Option<LeakCheck<'a>>,
+    section_name: &'a str,
+    extra_sections: Vec<(&'a str, SectionOptions<'a>)>,
+    import_renames: Vec<(ImportName<'a>, ImportName<'a>)>,
+    export_renames: Vec<(&'a str, &'a str)>,
+    strip_names: bool,
+    stripped_sections: Vec<&'a str>,
+    force: bool,
+    resource_metrics: bool,
+    snapshot_helpers: bool,
+    compaction_hook: Option<ImportName<'a>>,
+    asyncify_prefixes: &'a [&'a str],
+    #[cfg(feature = "std")]
+    post_optimizer: PostOptimizer<'a>,
+    gc_mode: GcMode<'a>,
+    wasm_features: Option<WasmFeatures>,
+    continue_on_error: bool,
+    surrogate_module: &'a str,
+    guard_import: Option<ImportName<'a>>,
+    hooks: Vec<&'a dyn ProcessingHook>,
+    observers: Vec<&'a dyn ProcessorObserver>,
 }
 
 impl Default for Processor<'_> {
     fn default() -> Self {
         Self {
             table_name: Some("externrefs"),
+            resource_table_name: Some("resources"),
+            resource_global_name: Some("global_resource"),
             drop_fn_name: None,
+            drop_fn_with_kind: false,
+            drop_slot_value: DropSlotValue::Null,
+            drop_reentrancy: DropReentrancy::Allow,
+            slot_allocator: None,
+            reserved_slots: 0,
+            partitioning: Partitioning::Shared,
+            index_type: IndexType::I32,
+            shim_visibility: ImportShimVisibility::Internal,
+            leak_check: None,
+            section_name: Function::CUSTOM_SECTION_NAME,
+            extra_sections: vec![],
+            import_renames: vec![],
+            export_renames: vec![],
+            strip_names: false,
+            stripped_sections: vec![],
+            force: false,
+            resource_metrics: false,
+            snapshot_helpers: false,
+            compaction_hook: None,
+            asyncify_prefixes: Self::ASYNCIFY_PREFIXES,
+            #[cfg(feature = "std")]
+            post_optimizer: PostOptimizer::None,
+            gc_mode: GcMode::Full,
+            wasm_features: None,
+            continue_on_error: false,
+            surrogate_module: ExternrefImports::MODULE_NAME,
+            guard_import: None,
+            hooks: vec![],
+            observers: vec![],
         }
     }
 }
 
 impl<'a> Processor<'a> {
+    /// Name of the custom section marking processed modules. The section contains the version
+    /// of the `externref` crate used for processing as a UTF-8 string.
+    ///
+    /// Besides the marker section, the processor records itself in the `processed-by` field
+    /// of the standard `producers` section, so that the processing can be audited by tools
+    /// like `wasm-tools metadata show`. The recorded version lists enabled options
+    /// affecting the module interface, e.g. `0.3.0 (drop-fn, tombstones)`.
+    /// The entry is not added if the `producers` section is [stripped](Self::strip_custom_sections()).
+    pub const MARKER_SECTION_NAME: &'static str = "__externrefs_processed";
+    /// Name of the exported global with the number of live references if
+    /// [resource metrics](Self::enable_resource_metrics()) are enabled.
+    pub const LIVE_COUNT_EXPORT: &'static str = "externref_live_count";
+    /// Name of the exported global with the maximum number of simultaneously live references if
+    /// [resource metrics](Self::enable_resource_metrics()) are enabled.
+    pub const PEAK_COUNT_EXPORT: &'static str = "externref_peak_count";
+    /// Name of the exported `() -> i32` function returning the number of slots to save
+    /// when snapshotting an instance if [snapshot helpers](Self::enable_snapshot_helpers())
+    /// are enabled.
+    pub const SNAPSHOT_LEN_EXPORT: &'static str = "externref_snapshot_len";
+    /// Name of the exported `(i32) -> ()` function preparing the `externref`s table
+    /// for restoring a snapshot if [snapshot helpers](Self::enable_snapshot_helpers())
+    /// are enabled.
+    pub const RESTORE_PREPARE_EXPORT: &'static str = "externref_restore_prepare";
+    /// Name of the exported `() -> i32` function compacting the `externref`s table
+    /// if [compaction](Self::enable_compaction()) is enabled.
+    pub const COMPACT_EXPORT: &'static str = "externref_compact";
+    /// Default prefixes of function names [signalling](Self::set_asyncify_prefixes())
+    /// that the module is transformed by asyncify.
+    pub const ASYNCIFY_PREFIXES: &'static [&'static str] = &["asyncify_"];
+
     /// Sets the name of the exported `externref`s table where refs obtained from the host
     /// are placed. If set to `None`, the table will not be exported from the module.
     ///
@@ -85,6 +510,26 @@ impl<'a> Processor<'a> {
         self
     }
 
+    /// Sets the name of the exported table backing [`ResourceTable`](crate::ResourceTable)s.
+    /// If set to `None`, the table will not be exported from the module. The table is only
+    /// added if the module uses `ResourceTable`s.
+    ///
+    /// By default, the table is exported as `"resources"`.
+    pub fn set_resource_table(&mut self, name: impl Into<Option<&'a str>>) -> &mut Self {
+        self.resource_table_name = name.into();
+        self
+    }
+
+    /// Sets the name of the exported mutable `externref` global backing
+    /// [`GlobalResource`](crate::GlobalResource)s. If set to `None`, the global will not
+    /// be exported from the module. The global is only added if the module uses `GlobalResource`s.
+    ///
+    /// By default, the global is exported as `"global_resource"`.
+    pub fn set_resource_global(&mut self, name: impl Into<Option<&'a str>>) -> &mut Self {
+        self.resource_global_name = name.into();
+        self
+    }
+
     /// Sets a function to notify the host about dropped `externref`s. This function
     /// will be added as an import with a signature `(externref) -> ()` and will be called
     /// immediately before dropping each reference.
@@ -92,54 +537,1120 @@ impl<'a> Processor<'a> {
     /// By default, there is no notifier hook installed.
     pub fn set_drop_fn(&mut self, module: &'a str, name: &'a str) -> &mut Self {
         self.drop_fn_name = Some((module, name));
+        self.drop_fn_with_kind = false;
+        self
+    }
+
+    /// Sets a function to notify the host about dropped `externref`s together with
+    /// the resource kind. Similar to [`Self::set_drop_fn()`], but the function will be added
+    /// as an import with a signature `(externref, i32) -> ()`, where the second arg is
+    /// the kind ID of the dropped resource.
+    ///
+    /// Kind IDs are assigned per `Resource<T>` type (identified by the last path segment of `T`
+    /// as written in the signatures of functions processed by the `#[externref]` macro),
+    /// in the alphabetical order of type names. The mapping can be obtained via
+    /// [`Manifest::resource_kinds`]. A reference that was upcast or downcast retains the kind
+    /// it was created with. If the kind is unknown, the kind ID is -1.
+    pub fn set_drop_fn_with_kind(&mut self, module: &'a str, name: &'a str) -> &mut Self {
+        self.drop_fn_name = Some((module, name));
+        self.drop_fn_with_kind = true;
+        self
+    }
+
+    /// Sets the value written into a slot of the `externref`s table when the reference
+    /// in the slot is dropped. See [`DropSlotValue`] for the available options.
+    ///
+    /// By default, slots are nulled.
+    pub fn set_drop_slot_value(&mut self, value: DropSlotValue<'a>) -> &mut Self {
+        self.drop_slot_value = value;
+        self
+    }
+
+    /// Sets the behavior on reference drops while the [drop hook](Self::set_drop_fn()) is running.
+    /// See [`DropReentrancy`] for the available options. Has no effect if the drop hook
+    /// is not set.
+    ///
+    /// By default, reentrant drops are allowed.
+    pub fn set_drop_reentrancy(&mut self, policy: DropReentrancy) -> &mut Self {
+        self.drop_reentrancy = policy;
+        self
+    }
+
+    /// Sets the function selecting slots of the `externref`s table for inserted references,
+    /// e.g. to reserve low indices for long-lived resources. This function will be added
+    /// as an import with a signature `() -> i32` and will be called each time a reference
+    /// is inserted into the table.
+    ///
+    /// The function should return the index of a free slot (a slot may be beyond the current
+    /// table size, in which case the table is grown), or a negative value to use the built-in
+    /// free slot search. Occupied slots returned by the function are ignored as well.
+    /// If the table is [partitioned](Partitioning::PerKind), the index is a slot in the table
+    /// of the partition the reference is inserted into.
+    ///
+    /// By default, slots are selected by the built-in search, which reuses the free slot
+    /// with the highest index.
+    pub fn set_slot_allocator(&mut self, module: &'a str, name: &'a str) -> &mut Self {
+        self.slot_allocator = Some((module, name));
+        self
+    }
+
+    /// Reserves the specified number of low slots in the `externref`s table for the host.
+    /// The table will initially have `slots` null slots, and the guest never inserts references
+    /// into them (slots below `slots` returned by the [slot allocator](Self::set_slot_allocator())
+    /// are ignored as well). The host can write well-known references (e.g., handles to standard
+    /// streams) into the reserved slots after instantiating the module, and the guest can refer
+    /// to them via [`Resource::well_known()`](crate::Resource::well_known()) without inserting
+    /// them into the table.
+    ///
+    /// Reserved slots are owned by the host; they are not dropped by [`drop_all()`](crate::drop_all()),
+    /// not counted by [leak checks](Self::set_leak_check()) and not moved
+    /// by [compaction](Self::enable_compaction()). If the table is
+    /// [partitioned](Self::set_table_partitioning()), slots are reserved in each partition.
+    ///
+    /// By default, no slots are reserved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` exceeds `i32::MAX`.
+    pub fn set_reserved_slots(&mut self, slots: u32) -> &mut Self {
+        assert!(
+            i32::try_from(slots).is_ok(),
+            "number of reserved slots must fit into `i32`"
+        );
+        self.reserved_slots = slots;
         self
     }
 
-    /// Processes the provided `module`.
+    /// Sets partitioning of the `externref`s table by resource kind. See [`Partitioning`]
+    /// for the available options.
+    ///
+    /// By default, all references are placed into a single table.
+    pub fn set_table_partitioning(&mut self, partitioning: Partitioning) -> &mut Self {
+        self.partitioning = partitioning;
+        self
+    }
+
+    /// Sets the type of reference IDs in the interface of the surrogate functions and declared
+    /// functions. See [`IndexType`] for the available options and the affected functions.
+    ///
+    /// By default, IDs are 32-bit.
+    pub fn set_index_type(&mut self, index_type: IndexType) -> &mut Self {
+        self.index_type = index_type;
+        self
+    }
+
+    /// Sets the visibility of the functions replacing the surrogate `insert`, `get` and `drop`
+    /// imports. See [`ImportShimVisibility`] for the available options.
+    ///
+    /// Exporting the functions allows the host to call them directly, e.g. to pre-populate
+    /// the `externref`s table before invoking exports on runtimes without table access.
+    /// If the functions are exported, they are added even if the module does not use them.
+    /// The exported functions use `i32` IDs regardless of the [index type](Self::set_index_type()),
+    /// and the kind ID passed to `insert` is -1 for references of unknown kind.
+    /// Like other exports, the functions can be [renamed](Self::rename_export()).
+    ///
+    /// By default, the functions are internal.
+    pub fn set_import_shim_visibility(
+        &mut self,
+        visibility: ImportShimVisibility<'a>,
+    ) -> &mut Self {
+        self.shim_visibility = visibility;
+        self
+    }
+
+    /// Adds checks for leaked references to exported functions with names matching
+    /// the `exports` glob pattern (`*` matches any sequence of chars, `?` matches a single char).
+    /// Patterns are matched against export names before [renaming](Self::rename_export()).
+    ///
+    /// After a matching function returns, the wrapper added by the processor counts
+    /// live references in the `externref`s table. If there are any, the hook function is called
+    /// with the count; this function will be added as an import with a signature `(i32) -> ()`.
+    /// Checks are intended to be used in tests for "exit" exports after which no references
+    /// are expected to be live. They can catch resources leaked via [`core::mem::forget()`]
+    /// or converted into [`HostHandle`](crate::HostHandle)s and never converted back.
+    ///
+    /// By default, there are no leak checks.
+    pub fn set_leak_check(
+        &mut self,
+        module: &'a str,
+        name: &'a str,
+        exports: &'a str,
+    ) -> &mut Self {
+        self.leak_check = Some(LeakCheck {
+            hook: (module, name),
+            exports_pattern: exports,
+        });
+        self
+    }
+
+    /// Sets the name of the custom section with function declarations processed
+    /// with the options of this processor. The name should start with
+    /// [`Function::CUSTOM_SECTION_NAME`] and match the section specified
+    /// in `#[externref(section = "..")]`, e.g. `"__externrefs_sdk"`.
+    ///
+    /// By default, declarations are read from [`Function::CUSTOM_SECTION_NAME`].
+    pub fn set_section_name(&mut self, name: &'a str) -> &mut Self {
+        self.section_name = name;
+        self
+    }
+
+    /// Adds a custom section with function declarations to process in the same pass
+    /// as the [main section](Self::set_section_name()), with the specified section-specific
+    /// `options`. This allows processing modules linking several libraries that declare
+    /// their functions in different sections (e.g., independent plugin SDKs)
+    /// without separate processing passes. Can be called multiple times.
+    ///
+    /// If the module contains a section with declarations that is neither the main section
+    /// nor added via this method, processing fails with [`Error::UnknownSection`],
+    /// since the declarations in it would not be processed otherwise. Configured sections
+    /// missing from the module are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use externref::processor::{Processor, SectionOptions};
+    /// # let module: Vec<u8> = b"\0asm\x01\0\0\0".to_vec();
+    /// let processed = Processor::default()
+    ///     .add_section(
+    ///         "__externrefs_sdk",
+    ///         SectionOptions::default().with_import_module("sdk"),
+    ///     )
+    ///     .process_bytes(&module)?;
+    /// # Ok::<_, externref::processor::Error>(())
+    /// ```
+    pub fn add_section(&mut self, name: &'a str, options: SectionOptions<'a>) -> &mut Self {
+        self.extra_sections.push((name, options));
+        self
+    }
+
+    /// Renames an import specified as a `(module, name)` tuple. Renaming is applied after
+    /// all functions are patched, so it does not influence matching imports with
+    /// the declarations produced by the `externref` macro. Renaming affects all import kinds
+    /// (not only functions), including the imports added by the processor itself,
+    /// such as the [drop hook](Self::set_drop_fn()).
+    ///
+    /// If the import is missing from the module (e.g., because it was removed by the compiler
+    /// as unused), renaming is a no-op.
+    pub fn rename_import(&mut self, from: (&'a str, &'a str), to: (&'a str, &'a str)) -> &mut Self {
+        self.import_renames.push((from, to));
+        self
+    }
+
+    /// Renames an export. Similar to [imports](Self::rename_import()), renaming is applied
+    /// after all functions are patched. Unlike imports, a missing export results in
+    /// an [`Error::NoExport`] during processing, and renaming onto the name of another export
+    /// results in an [`Error::DuplicateExport`].
+    pub fn rename_export(&mut self, from: &'a str, to: &'a str) -> &mut Self {
+        self.export_renames.push((from, to));
+        self
+    }
+
+    /// Sets whether to strip the name section (i.e., function and local names) from
+    /// the processed module, which reduces the module size at the cost of debuggability.
+    ///
+    /// By default, names are retained, and the processor names the functions and locals
+    /// it injects.
+    pub fn set_strip_names(&mut self, strip: bool) -> &mut Self {
+        self.strip_names = strip;
+        self
+    }
+
+    /// Adds glob patterns for names of custom sections to strip from the processed module
+    /// (`*` matches any sequence of chars, `?` matches a single char), e.g., `"producers"`.
+    /// Can be called multiple times; a section is stripped if it matches any of the patterns.
+    /// Stripping is performed in the same pass as processing, so there is no need to run
+    /// a separate tool (such as `wasm-strip`) afterwards.
+    ///
+    /// The patterns apply to all custom sections including `producers`; a pattern matching
+    /// `name` has the same effect as [name stripping](Self::set_strip_names()).
+    /// The [marker section](Self::MARKER_SECTION_NAME) is never stripped. DWARF debug info
+    /// (`.debug_*` sections) is not retained in the processed module regardless of patterns.
+    ///
+    /// By default, no custom sections are stripped.
+    pub fn strip_custom_sections(
+        &mut self,
+        patterns: impl IntoIterator<Item = &'a str>,
+    ) -> &mut Self {
+        self.stripped_sections.extend(patterns);
+        self
+    }
+
+    /// Sets whether to process modules that were already processed, i.e., contain
+    /// the [marker section](Self::MARKER_SECTION_NAME). Such modules usually do not contain
+    /// function declarations, so processing only applies renames and name stripping.
+    ///
+    /// By default, processing such a module results in [`Error::AlreadyProcessed`].
+    pub fn set_force(&mut self, force: bool) -> &mut Self {
+        self.force = force;
+        self
+    }
+
+    /// Sets prefixes of exported or named functions signalling that the module was transformed
+    /// by asyncify (e.g., `asyncify_start_unwind`). Processing such a module results
+    /// in [`Error::Asyncified`] since the processor must run *before* asyncify; see
+    /// [module docs](self#on-processing-order) for details. Setting prefixes can be useful
+    /// if the asyncify runtime functions are renamed by the toolchain. An empty slice
+    /// disables the check.
+    ///
+    /// By default, [`Self::ASYNCIFY_PREFIXES`] are used. Modules without function declarations
+    /// are not checked.
+    pub fn set_asyncify_prefixes(&mut self, prefixes: &'a [&'a str]) -> &mut Self {
+        self.asyncify_prefixes = prefixes;
+        self
+    }
+
+    /// Enables resource metrics. The processor adds two mutable `i32` globals exported
+    /// as [`Self::LIVE_COUNT_EXPORT`] and [`Self::PEAK_COUNT_EXPORT`], which are updated
+    /// when references are inserted into or dropped from the `externref`s table. The host
+    /// can read these globals to monitor resource leaks without walking the table.
+    /// Like other exports, the globals can be [renamed](Self::rename_export()).
+    ///
+    /// Null references are not counted since they are not placed into the table.
+    ///
+    /// By default, metrics are disabled.
+    pub fn enable_resource_metrics(&mut self) -> &mut Self {
+        self.resource_metrics = true;
+        self
+    }
+
+    /// Enables helpers for snapshotting and restoring instance state. The processor adds
+    /// two exported functions:
+    ///
+    /// - [`Self::SNAPSHOT_LEN_EXPORT`] with the `() -> i32` signature returns the number
+    ///   of slots the host should save from the `externref`s table. If the table
+    ///   is [partitioned](Self::set_table_partitioning()), this is the maximum length
+    ///   among all partitions.
+    /// - [`Self::RESTORE_PREPARE_EXPORT`] with the `(i32) -> ()` signature receives
+    ///   the saved length and resizes all `externref` tables to contain at least this number
+    ///   of slots, filling them with nulls. Afterwards, the host should write saved references
+    ///   into the table(s) at their original indices.
+    ///
+    /// See [module docs](self#slot-index-stability) for the guarantees regarding slot indices.
+    /// Like other exports, the functions can be [renamed](Self::rename_export()).
+    ///
+    /// By default, snapshot helpers are disabled.
+    pub fn enable_snapshot_helpers(&mut self) -> &mut Self {
+        self.snapshot_helpers = true;
+        self
+    }
+
+    /// Enables compaction of the `externref`s table. A new reference is placed into the free slot
+    /// found by scanning the table from its end, so long-running instances accumulating
+    /// sparse tables may spend considerable time on inserting references. With compaction, the processor adds
+    /// the [`Self::COMPACT_EXPORT`] function with the `() -> i32` signature, which moves
+    /// live references toward the start of the table (or of each partition if the table
+    /// is [partitioned](Self::set_table_partitioning())), so that free slots form a contiguous
+    /// range at the end. The function returns the number of moved references.
+    ///
+    /// Moving a reference changes its ID. For each moved reference, the hook function is called
+    /// with the old and the new ID; this function will be added as an import with a signature
+    /// `(i32, i32) -> ()`. IDs held by the module (e.g., in [`Resource`](crate::Resource)s
+    /// or [`HostHandle`](crate::HostHandle)s) are **not** updated, so the host must only run
+    /// compaction when the module opts in, e.g. between requests once the module has
+    /// remapped or released the affected IDs based on the hook notifications. The hook should
+    /// also be used to update host-side bookkeeping, such as the state
+    /// of the [slot allocator](Self::set_slot_allocator()).
+    ///
+    /// Like other exports, the function can be [renamed](Self::rename_export()).
+    ///
+    /// By default, compaction is disabled.
+    pub fn enable_compaction(&mut self, module: &'a str, name: &'a str) -> &mut Self {
+        self.compaction_hook = Some((module, name));
+        self
+    }
+
+    /// Sets the optimizer applied to the module after processing in [`Self::process_bytes()`].
+    /// See [`PostOptimizer`] for the available options.
+    ///
+    /// By default, the module is not optimized.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_post_optimizer(&mut self, optimizer: PostOptimizer<'a>) -> &mut Self {
+        self.post_optimizer = optimizer;
+        self
+    }
+
+    /// Sets garbage collection of unused items in the processed module. See [`GcMode`]
+    /// for the available options. Skipping GC or retaining functions can be useful if the module
+    /// is further transformed by tools relying on functions not reachable from exports.
+    ///
+    /// By default, [full GC](GcMode::Full) is performed.
+    pub fn set_gc(&mut self, mode: GcMode<'a>) -> &mut Self {
+        self.gc_mode = mode;
+        self
+    }
+
+    /// Sets the module of surrogate imports replaced by the processor (e.g., ones for creating
+    /// and dropping resources). Should match the [`SURROGATE_MODULE`](crate::SURROGATE_MODULE)
+    /// of the `externref` crate the module was compiled with, which can be changed
+    /// via the `EXTERNREF_SURROGATE_MODULE` env variable, e.g. if the host provides
+    /// a real module named `externref`. Imports from other modules (including `externref`
+    /// if it is not the surrogate module) are left intact.
+    ///
+    /// Unless [overridden](Self::set_guard_import()), the guard import is expected
+    /// in this module as well.
+    ///
+    /// By default, the surrogate module is `externref`.
+    pub fn set_surrogate_module(&mut self, module: &'a str) -> &mut Self {
+        self.surrogate_module = module;
+        self
+    }
+
+    /// Sets the surrogate import placed by the `externref` macro at the start of wrappers
+    /// for imported functions. The processor uses the guard to check that wrappers were not
+    /// transformed by external tools.
+    ///
+    /// The module of the guard import can be changed in the macro via
+    /// `#[externref(surrogate_module = "..")]`; the module specified here must match.
+    /// Functions declared with another surrogate module result
+    /// in [`Error::SurrogateModuleMismatch`]. Other surrogate imports are expected
+    /// in the [surrogate module](Self::set_surrogate_module()).
+    ///
+    /// By default, the guard is imported as `guard` from the surrogate module.
+    pub fn set_guard_import(&mut self, module: &'a str, name: &'a str) -> &mut Self {
+        self.guard_import = Some((module, name));
+        self
+    }
+
+    fn guard_import(&self) -> ImportName<'a> {
+        self.guard_import
+            .unwrap_or((self.surrogate_module, "guard"))
+    }
+
+    /// Sets whether processing should continue after encountering an error related
+    /// to a certain function (e.g., an [incorrectly placed guard](Error::IncorrectGuard)
+    /// or a [mismatched signature](Error::UnexpectedArity)). If enabled, the processor attempts
+    /// to patch all functions and returns all encountered errors at once as [`Error::Multiple`]
+    /// (or as a single error if there is only one). This is useful to triage broken modules,
+    /// e.g. in CI. Applies to [`Self::process()`] and [`Self::verify()`].
+    ///
+    /// Errors not related to specific functions (e.g., [a malformed custom section](Error::Read))
+    /// are still returned immediately. [Hooks](Self::add_hook()) are only run if there are
+    /// no errors.
+    ///
+    /// By default, processing stops on the first error.
+    pub fn set_continue_on_error(&mut self, continue_on_error: bool) -> &mut Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Sets WASM features (proposals) that modules are validated against when parsed
+    /// by [`Self::process_bytes()`] and [`Self::verify()`]. Modules using features not
+    /// in this set are rejected with [`Error::Wasm`]. For example, modules using the multi-memory
+    /// proposal require [`WasmFeatures::MULTI_MEMORY`].
+    ///
+    /// Note that the set can only restrict features supported by `walrus`, which is used
+    /// to transform modules; enabling features unknown to `walrus` (e.g., exception handling)
+    /// has no effect. Modules passed to [`Self::process()`] are already parsed and are not validated.
+    ///
+    /// By default, modules are validated against the `walrus` defaults, which include
+    /// all finished proposals (reference types, bulk memory, SIMD, etc.), as well as
+    /// multi-memory, 64-bit memories and threads.
+    pub fn set_wasm_features(&mut self, features: WasmFeatures) -> &mut Self {
+        self.wasm_features = Some(features);
+        self
+    }
+
+    /// Adds a hook invoked for each function patched during processing. Hooks are run
+    /// in the order they are added; see [`ProcessingHook`] for details.
+    ///
+    /// By default, there are no hooks.
+    pub fn add_hook(&mut self, hook: &'a dyn ProcessingHook) -> &mut Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Adds an observer receiving structured events during [processing](Self::process());
+    /// see [`ProcessorObserver`] for details. Observers are notified in the order they are added.
+    ///
+    /// By default, there are no observers.
+    pub fn add_observer(&mut self, observer: &'a dyn ProcessorObserver) -> &mut Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Processes the provided `module`. After successful processing, the module is marked
+    /// with a custom section named [`Self::MARKER_SECTION_NAME`].
+    ///
+    /// Returns IDs of the functions and tables injected into the module, which can be used
+    /// to further instrument it.
     ///
     /// # Errors
     ///
     /// Returns an error if a module is malformed. This shouldn't normally happen and
     /// could be caused by another post-processor or a bug in the `externref` crate / proc macro.
-    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err))]
-    pub fn process(&self, module: &mut Module) -> Result<(), Error> {
-        let raw_section = module.customs.remove_raw(Function::CUSTOM_SECTION_NAME);
-        let Some(raw_section) = raw_section else {
+    ///
+    /// Returns [`Error::AlreadyProcessed`] if the module was already processed, unless
+    /// [forced processing](Self::set_force()) is enabled. Returns [`Error::Asyncified`]
+    /// if the module was [transformed by asyncify](Self::set_asyncify_prefixes()).
+    /// Returns [`Error::OrphanedDeclarations`] if declared functions require surrogate imports
+    /// missing from the module (e.g., removed by LTO); declarations that do not require them,
+    /// like exports with unused resource args, are patched as usual.
+    pub fn process(&self, module: &mut Module) -> Result<ProcessingArtifacts, Error> {
+        self.process_with(module, |_, _| Ok(()))
+    }
+
+    /// Processes the provided `module`, running the `customize` closure after patching,
+    /// but before removing unused items from the module. This allows adding items referencing
+    /// the injected functions and tables (e.g., exports) without re-parsing the processed module.
+    ///
+    /// The closure runs after [hooks](Self::add_hook()) and export / import renames. It receives
+    /// the [artifacts](ProcessingArtifacts) of processing; all IDs in the artifacts are valid
+    /// at this point, as are IDs of all items in the module. Surrogate imports from
+    /// the surrogate module have already been removed at this point. After the closure
+    /// returns, items that are not reachable from exports, the start function, element segments,
+    /// etc. are removed, so IDs of such items become invalid; this includes items injected
+    /// by the processor and not used by the module. If the module contains no declarations
+    /// (i.e., processing only applies renames and stripping), the closure is still run,
+    /// with empty artifacts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error in the same cases as [`Self::process()`]. Errors returned by
+    /// the closure are wrapped into [`Error::Hook`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use externref::processor::Processor;
+    /// # use walrus::Module;
+    /// # let module_bytes: Vec<u8> = b"\0asm\x01\0\0\0".to_vec();
+    /// let mut module = Module::from_buffer(&module_bytes)?;
+    /// Processor::default().process_with(&mut module, |module, artifacts| {
+    ///     if let Some(drop_fn) = artifacts.drop_fn {
+    ///         // Expose the injected drop function to the host.
+    ///         module.exports.add("drop_ref", drop_fn);
+    ///     }
+    ///     Ok(())
+    /// })?;
+    /// # Ok::<_, anyhow::Error>(())
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "process", skip_all, err)
+    )]
+    pub fn process_with(
+        &self,
+        module: &mut Module,
+        customize: impl FnOnce(&mut Module, &ProcessingArtifacts) -> anyhow::Result<()>,
+    ) -> Result<ProcessingArtifacts, Error> {
+        self.remove_marker(module)?;
+        let raw_sections = self.take_raw_sections(module)?;
+        if raw_sections.is_empty() {
             #[cfg(feature = "tracing")]
             tracing::info!("module contains no custom section; skipping");
-            return Ok(());
-        };
-        let functions = Self::parse_section(&raw_section.data)?;
+            self.apply_renames(module, &[])?;
+            let artifacts = ProcessingArtifacts::default();
+            customize(module, &artifacts).map_err(Error::Hook)?;
+            self.apply_name_stripping(module);
+            self.apply_section_stripping(module);
+            self.add_marker(module);
+            return Ok(artifacts);
+        }
+        self.check_asyncify(module)?;
+        let section_renames = self.section_renames(raw_sections.iter().map(raw_section_parts))?;
+        let functions = Self::parse_sections(raw_sections.iter().map(|section| &section.data[..]))?;
         #[cfg(feature = "tracing")]
         tracing::info!(functions.len = functions.len(), "parsed custom section");
+        self.check_surrogate_modules(&functions)?;
 
+        let mut errors = ErrorCollector::new(self.continue_on_error);
         let state = ProcessingState::new(module, self)?;
-        let guarded_fns = state.replace_functions(module)?;
-        state.process_functions(&functions, &guarded_fns, module)?;
+        let scan = state.replace_functions(module, &mut errors)?;
+        let patched_fns = state.process_functions(&functions, scan, &mut errors, module)?;
+        errors.finish()?;
+        if let Some(leak_check) = self.leak_check {
+            state.add_leak_checks(module, leak_check.exports_pattern);
+        }
+        let patched_fns: Vec<_> = state.patched_surrogates().chain(patched_fns).collect();
+        observer::notify(&self.observers, module, &functions, &patched_fns);
+        self.run_hooks(module, &patched_fns)?;
+        self.apply_renames(module, &section_renames)?;
+        let mut artifacts = state.artifacts();
+        customize(module, &artifacts).map_err(Error::Hook)?;
+
+        self.run_gc(module);
+        self.apply_name_stripping(module);
+        self.apply_section_stripping(module);
+        self.add_marker(module);
+        artifacts.retain_live(module);
+        Ok(artifacts)
+    }
+
+    /// Checks that the provided WASM module `bytes` can be processed without producing
+    /// the processed module. This is faster than [processing](Self::process_bytes()) since
+    /// the module is not emitted, yet it performs the same checks: discovering surrogate imports,
+    /// checking placement of `externref` guards, and validating function declarations
+    /// against signatures of imported / exported functions.
+    ///
+    /// [Hooks](Self::add_hook()) and the [post-optimizer](Self::set_post_optimizer())
+    /// are not run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error in the same cases as [`Self::process()`], except for errors
+    /// returned by hooks.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err))]
+    pub fn verify(&self, bytes: &[u8]) -> Result<(), Error> {
+        let mut module = self.parse(bytes, &ModuleConfig::new())?;
+        self.remove_marker(&mut module)?;
+        let raw_sections = self.take_raw_sections(&mut module)?;
+        let section_renames = self.section_renames(raw_sections.iter().map(raw_section_parts))?;
+        if !raw_sections.is_empty() {
+            self.check_asyncify(&module)?;
+            let functions =
+                Self::parse_sections(raw_sections.iter().map(|section| &section.data[..]))?;
+            self.check_surrogate_modules(&functions)?;
+            let mut errors = ErrorCollector::new(self.continue_on_error);
+            let state = ProcessingState::new(&mut module, self)?;
+            let scan = state.replace_functions(&mut module, &mut errors)?;
+            state.process_functions(&functions, scan, &mut errors, &mut module)?;
+            errors.finish()?;
+        }
+        self.apply_renames(&mut module, &section_renames)
+    }
+
+    /// Parses the module, validating it against the [configured](Self::set_wasm_features())
+    /// WASM features.
+    fn parse(&self, bytes: &[u8], config: &ModuleConfig) -> Result<Module, Error> {
+        if let Some(features) = self.wasm_features {
+            Validator::new_with_features(features)
+                .validate_all(bytes)
+                .map_err(|err| Error::Wasm(err.into()))?;
+        }
+        config.parse(bytes).map_err(Error::Wasm)
+    }
+
+    /// Removes unused items from the module according to the [GC mode](GcMode).
+    /// Unlike with plain `gc::run()`, memories are retained even if they are not referenced
+    /// by the remaining code, so that memory imports and indices are left untouched.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "gc", skip_all))]
+    fn run_gc(&self, module: &mut Module) {
+        // Elapsed time is not recorded since it's available from the span.
+        #[cfg(feature = "tracing")]
+        let (fn_count, import_count) = (module.funcs.iter().count(), module.imports.iter().count());
+
+        if self.gc_mode == GcMode::Skip {
+            remove_dangling_imports(module);
+        } else {
+            let fn_patterns = match &self.gc_mode {
+                GcMode::KeepNamed(patterns) => patterns.as_slice(),
+                _ => &[],
+            };
+            let roots = GcRoots::new(module, fn_patterns);
+            let roots_id = module.customs.add(roots);
+            gc::run(module);
+            module.customs.delete(roots_id);
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            removed_fns = fn_count - module.funcs.iter().count(),
+            removed_imports = import_count - module.imports.iter().count(),
+            "removed unused items"
+        );
+    }
+
+    /// Checks whether the module can be processed, and removes the marker section from it
+    /// if it can. The module is not modified if processing is refused.
+    fn remove_marker(&self, module: &mut Module) -> Result<(), Error> {
+        let marker = module
+            .customs
+            .iter()
+            .find(|(_, section)| section.name() == Self::MARKER_SECTION_NAME);
+        let Some((_, marker)) = marker else {
+            return Ok(());
+        };
+        if !self.force {
+            let version = marker.data(&IdsToIndices::default());
+            let version = String::from_utf8_lossy(&version).into_owned();
+            return Err(Error::AlreadyProcessed { version });
+        }
 
-        gc::run(module);
+        #[cfg(feature = "tracing")]
+        tracing::warn!("module is already processed; processing anyway");
+        module.customs.remove_raw(Self::MARKER_SECTION_NAME);
         Ok(())
     }
 
-    fn parse_section(mut raw_section: &[u8]) -> Result<Vec<Function<'_>>, Error> {
-        let mut functions = vec![];
-        while !raw_section.is_empty() {
-            let next_function = Function::read_from_section(&mut raw_section)?;
-            functions.push(next_function);
+    /// Checks that the module is not transformed by asyncify.
+    fn check_asyncify(&self, module: &Module) -> Result<(), Error> {
+        let is_asyncify_fn = |name: &str| {
+            self.asyncify_prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        };
+        let exported_fns = module.exports.iter().filter_map(|export| {
+            matches!(export.item, ExportItem::Function(_)).then_some(export.name.as_str())
+        });
+        let named_fns = module
+            .funcs
+            .iter()
+            .filter_map(|function| function.name.as_deref());
+        let asyncify_fn = exported_fns
+            .chain(named_fns)
+            .find(|name| is_asyncify_fn(name));
+        if let Some(name) = asyncify_fn {
+            return Err(Error::Asyncified {
+                function_name: name.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes custom sections with function declarations from the module. The main section
+    /// goes first, followed by [additional sections](Self::add_section()) in the order
+    /// they were added.
+    fn take_raw_sections(&self, module: &mut Module) -> Result<Vec<RawCustomSection>, Error> {
+        let extra_names = self.extra_sections.iter().map(|&(name, _)| name);
+        let mut raw_sections = vec![];
+        for name in iter::once(self.section_name).chain(extra_names) {
+            // The module may contain several sections with the same name, e.g. if it was linked
+            // from objects produced by different toolchains.
+            while let Some(raw_section) = module.customs.remove_raw(name) {
+                raw_sections.push(raw_section);
+            }
+        }
+        for (_, section) in module.customs.iter() {
+            self.check_section(section.name())?;
+        }
+        Ok(raw_sections)
+    }
+
+    /// Checks that a custom section with the specified name, if it contains function
+    /// declarations, is configured in the processor.
+    fn check_section(&self, name: &str) -> Result<(), Error> {
+        let has_declarations =
+            name.starts_with(Function::CUSTOM_SECTION_NAME) && name != Self::MARKER_SECTION_NAME;
+        let is_configured = name == self.section_name
+            || self
+                .extra_sections
+                .iter()
+                .any(|&(section, _)| section == name);
+        if has_declarations && !is_configured {
+            return Err(Error::UnknownSection {
+                name: name.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns import renames derived from [options](SectionOptions) of additional sections
+    /// for the provided `(name, data)` custom sections.
+    fn section_renames<'s>(
+        &'s self,
+        raw_sections: impl IntoIterator<Item = (&'s str, &'s [u8])>,
+    ) -> Result<Vec<(ImportName<'s>, ImportName<'s>)>, Error> {
+        let mut renames = vec![];
+        for (section_name, mut raw_section) in raw_sections {
+            let options = self
+                .extra_sections
+                .iter()
+                .find_map(|(name, options)| (*name == section_name).then_some(options));
+            let Some(new_module) = options.and_then(|options| options.import_module) else {
+                continue;
+            };
+
+            let mut functions = vec![];
+            Self::parse_section(&mut raw_section, &mut functions)?;
+            for function in functions {
+                let import = match function.kind {
+                    FunctionKind::Import(module) => (module, function.name),
+                    FunctionKind::Reexport {
+                        module,
+                        import_name,
+                    } => (module, import_name),
+                    FunctionKind::Export | FunctionKind::TableEntry | FunctionKind::Start => {
+                        continue;
+                    }
+                };
+                renames.push((import, (new_module, import.1)));
+            }
+        }
+        Ok(renames)
+    }
+
+    fn add_marker(&self, module: &mut Module) {
+        module.customs.add(RawCustomSection {
+            name: Self::MARKER_SECTION_NAME.to_owned(),
+            data: env!("CARGO_PKG_VERSION").as_bytes().to_vec(),
+        });
+        if !self.is_stripped_section("producers") {
+            let version = self.producer_version();
+            module.producers.add_processed_by("externref", &version);
+        }
+    }
+
+    /// Returns the version recorded in the `producers` section, e.g. `0.3.0 (drop-fn, per-kind)`.
+    /// Options that affect the interface between the module and the host are listed
+    /// in parentheses similar to the `rustc` version format.
+    fn producer_version(&self) -> String {
+        let options = [
+            (
+                "drop-fn",
+                self.drop_fn_name.is_some() && !self.drop_fn_with_kind,
+            ),
+            ("drop-fn-with-kind", self.drop_fn_with_kind),
+            (
+                "tombstones",
+                matches!(self.drop_slot_value, DropSlotValue::Tombstone { .. }),
+            ),
+            ("slot-allocator", self.slot_allocator.is_some()),
+            ("reserved-slots", self.reserved_slots > 0),
+            ("per-kind", self.partitioning == Partitioning::PerKind),
+            ("i64-index", self.index_type == IndexType::I64),
+            ("leak-check", self.leak_check.is_some()),
+            ("metrics", self.resource_metrics),
+            ("snapshots", self.snapshot_helpers),
+            ("compaction", self.compaction_hook.is_some()),
+            (
+                "exported-shims",
+                self.shim_visibility != ImportShimVisibility::Internal,
+            ),
+        ];
+        let options: Vec<_> = options
+            .into_iter()
+            .filter_map(|(name, is_enabled)| is_enabled.then_some(name))
+            .collect();
+
+        let version = env!("CARGO_PKG_VERSION");
+        if options.is_empty() {
+            version.to_owned()
+        } else {
+            format!("{version} ({})", options.join(", "))
+        }
+    }
+
+    fn run_hooks(
+        &self,
+        module: &mut Module,
+        patched_fns: &[PatchedFunction<'_>],
+    ) -> Result<(), Error> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        for function in patched_fns {
+            for hook in &self.hooks {
+                hook.on_patched_function(module, function)
+                    .map_err(Error::Hook)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_stripped_section(&self, name: &str) -> bool {
+        self.stripped_sections
+            .iter()
+            .any(|pattern| matches_glob(pattern, name))
+    }
+
+    fn apply_name_stripping(&self, module: &mut Module) {
+        if !self.strip_names && !self.is_stripped_section("name") {
+            return;
+        }
+        module.name = None;
+        for function in module.funcs.iter_mut() {
+            function.name = None;
+        }
+        let local_ids: Vec<_> = module.locals.iter().map(Local::id).collect();
+        for local_id in local_ids {
+            module.locals.get_mut(local_id).name = None;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!("stripped function and local names");
+    }
+
+    fn apply_section_stripping(&self, module: &mut Module) {
+        if self.stripped_sections.is_empty() {
+            return;
+        }
+        let stripped_ids: Vec<_> = module
+            .customs
+            .iter()
+            .filter_map(|(id, section)| self.is_stripped_section(section.name()).then_some(id))
+            .collect();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(count = stripped_ids.len(), "stripping custom sections");
+        for id in stripped_ids {
+            module.customs.delete(id);
+        }
+        if self.is_stripped_section("producers") {
+            module.producers.clear();
+            #[cfg(feature = "tracing")]
+            tracing::debug!("stripped producers section");
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err))]
+    fn apply_renames(
+        &self,
+        module: &mut Module,
+        section_renames: &[(ImportName<'_>, ImportName<'_>)],
+    ) -> Result<(), Error> {
+        // Section renames go last, so that imports renamed explicitly are not found by them.
+        let import_renames = self.import_renames.iter().chain(section_renames);
+        for &((module_name, name), (new_module_name, new_name)) in import_renames {
+            let Some(import_id) = module.imports.find(module_name, name) else {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(module_name, name, "renamed import is missing; skipping");
+                continue;
+            };
+            let import = module.imports.get_mut(import_id);
+            new_module_name.clone_into(&mut import.module);
+            new_name.clone_into(&mut import.name);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                module_name,
+                name,
+                new_module_name,
+                new_name,
+                "renamed import"
+            );
+        }
+
+        for &(name, new_name) in &self.export_renames {
+            let export = module
+                .exports
+                .iter_mut()
+                .find(|export| export.name == name)
+                .ok_or_else(|| Error::NoExport(name.to_owned()))?;
+            let export_id = export.id();
+            let has_conflict = module
+                .exports
+                .iter()
+                .any(|other| other.id() != export_id && other.name == new_name);
+            if has_conflict {
+                return Err(Error::DuplicateExport(new_name.to_owned()));
+            }
+            let export = module.exports.get_mut(export_id);
+            new_name.clone_into(&mut export.name);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(name, new_name, "renamed export");
+        }
+        Ok(())
+    }
+
+    /// Checks that surrogate modules recorded in declarations of imported functions match
+    /// the [configured](Self::set_guard_import()) guard import. (Only wrappers of imported
+    /// functions contain the guard.) Functions without a recorded module use the guard
+    /// from the `externref` crate, which is assumed to be imported from
+    /// the [surrogate module](Self::set_surrogate_module()).
+    fn check_surrogate_modules(&self, functions: &[Function<'_>]) -> Result<(), Error> {
+        let (guard_module, _) = self.guard_import();
+        for function in functions {
+            let FunctionKind::Import(module) = function.kind else {
+                continue;
+            };
+            let declared_module = function.surrogate_module.unwrap_or(self.surrogate_module);
+            if declared_module != guard_module {
+                return Err(Error::SurrogateModuleMismatch {
+                    module: module.to_owned(),
+                    name: function.name.to_owned(),
+                    declared: declared_module.to_owned(),
+                    expected: guard_module.to_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses function declarations from custom sections. Duplicate declarations
+    /// (e.g., produced by linking several objects declaring the same import) are merged
+    /// provided that they agree with each other.
+    fn parse_sections<'s>(
+        raw_sections: impl IntoIterator<Item = &'s [u8]>,
+    ) -> Result<Vec<Function<'s>>, Error> {
+        let mut functions: Vec<Function<'_>> = vec![];
+        for mut raw_section in raw_sections {
+            Self::parse_section(&mut raw_section, &mut functions)?;
         }
         Ok(functions)
     }
 
+    fn parse_section<'s>(
+        raw_section: &mut &'s [u8],
+        functions: &mut Vec<Function<'s>>,
+    ) -> Result<(), Error> {
+        while !raw_section.is_empty() {
+            let next_function = Function::read_from_section(raw_section)?;
+            let duplicate = functions.iter().find(|function| {
+                function.kind == next_function.kind && function.name == next_function.name
+            });
+            let Some(duplicate) = duplicate else {
+                functions.push(next_function);
+                continue;
+            };
+
+            if *duplicate != next_function {
+                let module = match next_function.kind {
+                    FunctionKind::Import(module) => Some(module.to_owned()),
+                    FunctionKind::Export
+                    | FunctionKind::TableEntry
+                    | FunctionKind::Start
+                    | FunctionKind::Reexport { .. } => None,
+                };
+                return Err(Error::ConflictingDeclarations {
+                    module,
+                    name: next_function.name.to_owned(),
+                });
+            }
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                name = next_function.name,
+                kind = ?next_function.kind,
+                "merged duplicate function declaration"
+            );
+        }
+        Ok(())
+    }
+
     /// Processes the provided WASM module `bytes`. This is a higher-level alternative to
     /// [`Self::process()`].
     ///
+    /// Unlike parsing the module with [`Module::from_buffer()`], this method preserves
+    /// local names from the name section of the module, which is useful for debugging.
+    /// If a [post-optimizer](Self::set_post_optimizer()) is set, it is applied
+    /// to the processed module.
+    ///
     /// # Errors
     ///
     /// Returns an error if `bytes` does not represent a valid WASM module, and in all cases
-    /// [`Self::process()`] returns an error.
+    /// [`Self::process()`] returns an error. Also returns an error if the post-optimizer fails.
     pub fn process_bytes(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
-        let mut module = Module::from_buffer(bytes).map_err(Error::Wasm)?;
+        let local_names = LocalNames::parse(bytes);
+        let mut config = ModuleConfig::new();
+        config.on_parse(move |module, indices| {
+            local_names.restore(module, indices);
+            Ok(())
+        });
+        let mut module = self.parse(bytes, &config)?;
         self.process(&mut module)?;
-        Ok(module.emit_wasm())
+        let processed = module.emit_wasm();
+        #[cfg(feature = "std")]
+        if !matches!(self.post_optimizer, PostOptimizer::None) {
+            return self.post_optimizer.optimize(&processed);
+        }
+        Ok(processed)
+    }
+
+    /// Processes the provided WASM module in the [text format][WAT], returning the processed module
+    /// in the text format as well. This is useful to inspect the patched module.
+    ///
+    /// The module is [processed as bytes](Self::process_bytes()), so name sections (e.g., ones
+    /// produced from identifiers in the input text) are retained unless
+    /// [name stripping](Self::set_strip_names()) is enabled. Function declarations can be specified
+    /// in the text as the custom section annotation, like `(@custom "__externrefs" "...")`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Wasm`] if `wat` cannot be parsed, and in all cases
+    /// [`Self::process_bytes()`] returns an error.
+    ///
+    /// [WAT]: https://webassembly.github.io/spec/core/text/index.html
+    #[cfg(feature = "wat")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "wat")))]
+    pub fn process_wat(&self, wat: &str) -> Result<String, Error> {
+        let bytes = wat::parse_str(wat).map_err(|err| Error::Wasm(err.into()))?;
+        let processed = self.process_bytes(&bytes)?;
+        wasmprinter::print_bytes(processed).map_err(Error::Wasm)
+    }
+
+    /// Reads a WASM module from the `input` file, [processes](Self::process_bytes()) it
+    /// and writes the processed module to the `output` file.
+    ///
+    /// The output is written atomically: the module is first written to a temporary file
+    /// in the same directory as `output`, which is then renamed to `output`. Thus, `output`
+    /// is never left partially written, and is not modified if processing fails.
+    /// It is valid for `input` and `output` to be the same file. If `output` exists and is not
+    /// a regular file (e.g., `/dev/null`), it is written to directly.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if reading `input` or writing `output` fails,
+    /// and [`Error::File`] wrapping a processing error in all cases
+    /// [`Self::process_bytes()`] returns an error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self), fields(input = %input.display(), output = %output.display()), err)
+    )]
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn process_file(&self, input: &Path, output: &Path) -> Result<(), Error> {
+        let module = fs::read(input).map_err(|error| Error::Io {
+            path: input.to_owned(),
+            error,
+        })?;
+        let processed = self.process_bytes(&module).map_err(|error| Error::File {
+            path: input.to_owned(),
+            error: Box::new(error),
+        })?;
+        write_atomically(output, &processed).map_err(|error| Error::Io {
+            path: output.to_owned(),
+            error,
+        })?;
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            original_len = module.len(),
+            processed_len = processed.len(),
+            "wrote processed module"
+        );
+        Ok(())
+    }
+}
+
+/// Processes the provided WASM module `bytes` with the default options. This is a shortcut
+/// for `Processor::default().process_bytes(bytes)`; use [`Processor`] to customize processing.
+///
+/// # Errors
+///
+/// Returns an error in the same cases as [`Processor::process_bytes()`].
+///
+/// # Examples
+///
+/// ```
+/// let module: Vec<u8> = // WASM module, e.g., loaded from the file system
+/// #    b"\0asm\x01\0\0\0".to_vec();
+/// let processed: Vec<u8> = externref::processor::process_bytes(&module)?;
+/// // Store or use the processed module...
+/// # Ok::<_, externref::processor::Error>(())
+/// ```
+pub fn process_bytes(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    Processor::default().process_bytes(bytes)
+}
+
+/// Writes `bytes` to a temporary file next to `path`, and then renames the file to `path`,
+/// so that the file at `path` is never left partially written. This is used to write outputs
+/// in [`Processor::process_file()`]. If `path` exists and is not a regular file
+/// (e.g., `/dev/null`), it is written to directly.
+///
+/// # Errors
+///
+/// Returns an I/O error if writing or renaming the temporary file fails. In this case,
+/// the temporary file is removed.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    // Special files (e.g., `/dev/null`) cannot be replaced by renaming.
+    if fs::metadata(path).is_ok_and(|metadata| !metadata.is_file()) {
+        return fs::write(path, bytes);
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "output path is not a file"))?;
+    let mut temp_name = file_name.to_owned();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = fs::File::create(&temp_path).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp_path, path)
+    });
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
     }
+    result
 }
@@ -0,0 +1,58 @@
+//! Preserving local names from the WASM name section.
+
+use walrus::{IndicesToIds, Module};
+use wasmparser::{KnownCustom, Name, Parser, Payload};
+
+use crate::alloc::{vec, String, ToOwned, Vec};
+
+/// Local names parsed from the name section of a module, as `(function index, local index, name)`
+/// tuples.
+///
+/// `walrus` parses the name section before function bodies, and thus silently drops local names.
+/// To preserve them, we parse local names separately and restore them once the module is parsed.
+#[derive(Debug, Default)]
+pub(crate) struct LocalNames(Vec<(u32, u32, String)>);
+
+impl LocalNames {
+    /// Parses local names from the module `bytes`. Since the name section is purely informational,
+    /// parsing errors are ignored.
+    pub fn parse(bytes: &[u8]) -> Self {
+        Self::try_parse(bytes).unwrap_or_default()
+    }
+
+    fn try_parse(bytes: &[u8]) -> wasmparser::Result<Self> {
+        let mut names = vec![];
+        for payload in Parser::new(0).parse_all(bytes) {
+            let Payload::CustomSection(section) = payload? else {
+                continue;
+            };
+            let KnownCustom::Name(reader) = section.as_known() else {
+                continue;
+            };
+            for subsection in reader {
+                let Name::Local(locals) = subsection? else {
+                    continue;
+                };
+                for function in locals {
+                    let function = function?;
+                    for naming in function.names {
+                        let naming = naming?;
+                        names.push((function.index, naming.index, naming.name.to_owned()));
+                    }
+                }
+            }
+        }
+        Ok(Self(names))
+    }
+
+    pub fn restore(&self, module: &mut Module, indices: &IndicesToIds) {
+        for (fn_idx, local_idx, name) in &self.0 {
+            let Ok(fn_id) = indices.get_func(*fn_idx) else {
+                continue;
+            };
+            if let Ok(local_id) = indices.get_local(fn_id, *local_idx) {
+                module.locals.get_mut(local_id).name = Some(name.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Observers receiving structured events during processing.
+
+use core::{cell::RefCell, fmt, mem};
+
+use walrus::Module;
+
+use super::hooks::{PatchedFunction, PatchedFunctionKind};
+use crate::{
+    alloc::{String, ToOwned, Vec},
+    Function, FunctionKind,
+};
+
+/// Observer of structured events emitted by the [`Processor`](super::Processor).
+///
+/// Unlike [`ProcessingHook`](super::ProcessingHook)s, observers cannot modify the module;
+/// they are meant for logging and diagnostics. All methods have no-op default implementations,
+/// so an observer only needs to implement the methods it is interested in. [`EventLog`]
+/// is a ready-made observer recording events in memory.
+///
+/// Observers are registered via [`Processor::add_observer()`](super::Processor::add_observer())
+/// and are notified after all functions are patched, before running hooks. If the `tracing`
+/// crate feature is enabled, the same events are additionally emitted as `DEBUG` tracing events;
+/// observers do not require the feature.
+#[allow(unused_variables)]
+pub trait ProcessorObserver: fmt::Debug {
+    /// Called for each import from the surrogate module (e.g., `insert` or `drop`)
+    /// replaced with a local function. `name` is the name of the import.
+    fn on_import_replaced(&self, name: &str) {}
+
+    /// Called for each declared imported function (including [re-exported](FunctionKind::Reexport)
+    /// ones) whose signature was patched to use `externref`s.
+    fn on_import_patched(&self, function: &Function<'_>) {}
+
+    /// Called for each declared exported function that was transformed.
+    fn on_export_transformed(&self, function: &Function<'_>) {}
+
+    /// Called for each declared function table entry that was transformed.
+    fn on_table_entry_transformed(&self, function: &Function<'_>) {}
+
+    /// Called for each non-exported local function that received `externref` locals.
+    /// `name` is the function name from the module name section, if any.
+    fn on_local_fn_patched(&self, name: Option<&str>, new_locals: usize) {}
+}
+
+/// Structured event recorded by an [`EventLog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProcessingEvent {
+    /// Surrogate import with the specified name was replaced with a local function.
+    ImportReplaced {
+        /// Name of the import in the surrogate module.
+        name: String,
+    },
+    /// Imported function was patched.
+    ImportPatched {
+        /// Name of the imported module.
+        module: String,
+        /// Name of the function.
+        name: String,
+    },
+    /// Exported function was transformed.
+    ExportTransformed {
+        /// Name of the export.
+        name: String,
+    },
+    /// Function table entry was transformed.
+    TableEntryTransformed {
+        /// Name of the function.
+        name: String,
+    },
+    /// Non-exported local function was patched.
+    LocalFnPatched {
+        /// Function name from the module name section, if any.
+        name: Option<String>,
+        /// Number of `externref` locals added to the function.
+        new_locals: usize,
+    },
+}
+
+/// [`ProcessorObserver`] recording events in memory.
+///
+/// # Examples
+///
+/// ```
+/// use externref::processor::{EventLog, ProcessingEvent, Processor};
+///
+/// let log = EventLog::default();
+/// let mut processor = Processor::default();
+/// processor.add_observer(&log);
+/// # let module: Vec<u8> = b"\0asm\x01\0\0\0".to_vec();
+/// processor.process_bytes(&module)?;
+///
+/// for event in log.take() {
+///     if let ProcessingEvent::ExportTransformed { name } = event {
+///         println!("transformed export `{name}`");
+///     }
+/// }
+/// # Ok::<_, externref::processor::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct EventLog {
+    events: RefCell<Vec<ProcessingEvent>>,
+}
+
+impl EventLog {
+    /// Returns a copy of the recorded events in the order they were emitted.
+    pub fn events(&self) -> Vec<ProcessingEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// Takes the recorded events, leaving the log empty.
+    pub fn take(&self) -> Vec<ProcessingEvent> {
+        mem::take(&mut *self.events.borrow_mut())
+    }
+
+    fn push(&self, event: ProcessingEvent) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+impl ProcessorObserver for EventLog {
+    fn on_import_replaced(&self, name: &str) {
+        self.push(ProcessingEvent::ImportReplaced {
+            name: name.to_owned(),
+        });
+    }
+
+    fn on_import_patched(&self, function: &Function<'_>) {
+        let Some((module, name)) = imported_name(function) else {
+            return;
+        };
+        self.push(ProcessingEvent::ImportPatched {
+            module: module.to_owned(),
+            name: name.to_owned(),
+        });
+    }
+
+    fn on_export_transformed(&self, function: &Function<'_>) {
+        self.push(ProcessingEvent::ExportTransformed {
+            name: function.name.to_owned(),
+        });
+    }
+
+    fn on_table_entry_transformed(&self, function: &Function<'_>) {
+        self.push(ProcessingEvent::TableEntryTransformed {
+            name: function.name.to_owned(),
+        });
+    }
+
+    fn on_local_fn_patched(&self, name: Option<&str>, new_locals: usize) {
+        self.push(ProcessingEvent::LocalFnPatched {
+            name: name.map(str::to_owned),
+            new_locals,
+        });
+    }
+}
+
+/// Observer emitting events via `tracing`.
+#[cfg(feature = "tracing")]
+#[derive(Debug)]
+struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl ProcessorObserver for TracingObserver {
+    fn on_import_replaced(&self, name: &str) {
+        tracing::debug!(name = format!("externref::{name}"), "replaced import");
+    }
+
+    fn on_import_patched(&self, function: &Function<'_>) {
+        if let Some((module, name)) = imported_name(function) {
+            tracing::debug!(module, name, "patched import");
+        }
+    }
+
+    fn on_export_transformed(&self, function: &Function<'_>) {
+        tracing::debug!(name = function.name, "transformed export");
+    }
+
+    fn on_table_entry_transformed(&self, function: &Function<'_>) {
+        tracing::debug!(name = function.name, "transformed table entry");
+    }
+
+    fn on_local_fn_patched(&self, name: Option<&str>, new_locals: usize) {
+        tracing::debug!(name, new_locals, "patched local function");
+    }
+}
+
+/// Returns the module and the name of an imported function, including
+/// [re-exported imports](FunctionKind::Reexport).
+fn imported_name<'a>(function: &Function<'a>) -> Option<(&'a str, &'a str)> {
+    match function.kind {
+        FunctionKind::Import(module) => Some((module, function.name)),
+        FunctionKind::Reexport {
+            module,
+            import_name,
+        } => Some((module, import_name)),
+        _ => None,
+    }
+}
+
+/// Notifies observers (including the `tracing` observer if the corresponding feature is enabled)
+/// about the outcome of processing.
+pub(crate) fn notify(
+    observers: &[&dyn ProcessorObserver],
+    module: &Module,
+    functions: &[Function<'_>],
+    patched_fns: &[PatchedFunction<'_>],
+) {
+    #[cfg(feature = "tracing")]
+    let tracing_observer: [&dyn ProcessorObserver; 1] = [&TracingObserver];
+    #[cfg(feature = "tracing")]
+    let observers = tracing_observer.iter().chain(observers);
+    #[cfg(not(feature = "tracing"))]
+    let observers = observers.iter();
+
+    for observer in observers {
+        notify_observer(*observer, module, functions, patched_fns);
+    }
+}
+
+fn notify_observer(
+    observer: &dyn ProcessorObserver,
+    module: &Module,
+    functions: &[Function<'_>],
+    patched_fns: &[PatchedFunction<'_>],
+) {
+    for function in patched_fns {
+        if let PatchedFunctionKind::Surrogate(name) = function.kind {
+            observer.on_import_replaced(name);
+        }
+    }
+    for function in functions {
+        if let Some((module_name, name)) = imported_name(function) {
+            // Only imports present in the module are patched.
+            if module.imports.find(module_name, name).is_some() {
+                observer.on_import_patched(function);
+            }
+        }
+    }
+    for function in patched_fns {
+        match &function.kind {
+            PatchedFunctionKind::Export(declaration) => {
+                observer.on_export_transformed(declaration);
+            }
+            PatchedFunctionKind::TableEntry(declaration) => {
+                observer.on_table_entry_transformed(declaration);
+            }
+            PatchedFunctionKind::Local => {
+                let name = module.funcs.get(function.id).name.as_deref();
+                observer.on_local_fn_patched(name, function.new_locals.len());
+            }
+            PatchedFunctionKind::Surrogate(_) => { /* already reported */ }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Optimization of processed modules with external tools.
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{self, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Context as _;
+use wasmparser::{Parser, Payload};
+
+use super::{Error, Processor};
+
+/// Optimizer applied to the module after processing in [`Processor::process_bytes()`].
+///
+/// Running the optimizer via the processor ensures the correct
+/// [processing order](super#on-processing-order).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PostOptimizer<'a> {
+    /// No optimization. This is the default.
+    #[default]
+    None,
+    /// Optimization with the `wasm-opt` tool from [Binaryen]. The tool is invoked as an external
+    /// process and must be available in `PATH`.
+    ///
+    /// [Binaryen]: https://github.com/WebAssembly/binaryen
+    WasmOpt {
+        /// Args supplied to `wasm-opt` in addition to the input and output paths,
+        /// e.g. `["-Os", "--enable-reference-types"]`. Since processed modules use
+        /// `externref`s, the reference types proposal must be enabled.
+        args: Vec<&'a str>,
+    },
+}
+
+impl PostOptimizer<'_> {
+    /// Optimizes the provided module.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotProcessed`] if the module was not processed, i.e., does not contain
+    /// the [marker section](Processor::MARKER_SECTION_NAME); optimizing such a module
+    /// is likely to break subsequent processing. Returns [`Error::PostOptimization`] if running
+    /// the optimizer fails.
+    pub fn optimize(&self, module_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        if !Self::is_processed(module_bytes)? {
+            return Err(Error::NotProcessed);
+        }
+        match self {
+            Self::None => Ok(module_bytes.to_vec()),
+            Self::WasmOpt { args } => {
+                let optimized =
+                    run_wasm_opt(args, module_bytes).map_err(Error::PostOptimization)?;
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    original_len = module_bytes.len(),
+                    optimized_len = optimized.len(),
+                    "optimized module with wasm-opt"
+                );
+                Ok(optimized)
+            }
+        }
+    }
+
+    fn is_processed(module_bytes: &[u8]) -> Result<bool, Error> {
+        for payload in Parser::new(0).parse_all(module_bytes) {
+            let payload = payload.map_err(|err| Error::Wasm(err.into()))?;
+            if let Payload::CustomSection(reader) = payload {
+                if reader.name() == Processor::MARKER_SECTION_NAME {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Temporary files removed on drop.
+#[derive(Debug)]
+struct TempFiles {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+impl TempFiles {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let idx = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir();
+        let stem = format!("externref-{}-{idx}", process::id());
+        Self {
+            input: dir.join(format!("{stem}.wasm")),
+            output: dir.join(format!("{stem}.opt.wasm")),
+        }
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        fs::remove_file(&self.input).ok();
+        fs::remove_file(&self.output).ok();
+    }
+}
+
+fn run_wasm_opt(args: &[&str], module_bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let files = TempFiles::new();
+    fs::write(&files.input, module_bytes).context("failed writing module to a temporary file")?;
+
+    let output = Command::new("wasm-opt")
+        .args(args)
+        .arg(&files.input)
+        .arg("-o")
+        .arg(&files.output)
+        .stdin(Stdio::null())
+        .output()
+        .context("failed running wasm-opt; is it installed and present in PATH?")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "wasm-opt finished abnormally ({}): {}",
+            output.status,
+            stderr.trim()
+        );
+    }
+    fs::read(&files.output).context("failed reading optimized module")
+}
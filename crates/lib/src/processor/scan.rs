@@ -0,0 +1,115 @@
+//! Summary of references in local functions, used to skip functions that cannot require patching.
+
+use walrus::{FunctionId, TypeId};
+
+use crate::alloc::{HashMap, HashSet, Vec};
+
+/// Functions and `call_indirect` types referenced by a single local function.
+///
+/// References are stored in vectors rather than hash sets since they are pushed for each
+/// instruction, while lookups are comparatively rare.
+#[derive(Debug, Default)]
+pub(crate) struct FunctionRefs {
+    functions: Vec<FunctionId>,
+    indirect_types: Vec<TypeId>,
+}
+
+impl FunctionRefs {
+    pub fn push_function(&mut self, fn_id: FunctionId) {
+        self.functions.push(fn_id);
+    }
+
+    pub fn push_indirect_type(&mut self, ty: TypeId) {
+        self.indirect_types.push(ty);
+    }
+
+    /// Deduplicates references.
+    pub fn finish(&mut self) {
+        self.functions.sort_unstable();
+        self.functions.dedup();
+        self.indirect_types.sort_unstable();
+        self.indirect_types.dedup();
+    }
+
+    pub fn references(&self, fn_id: FunctionId) -> bool {
+        self.functions.binary_search(&fn_id).is_ok()
+    }
+}
+
+/// References of all local functions collected while replacing calls to surrogate imports.
+///
+/// Most functions in a typical module do not deal with `externref`s at all; the scan allows
+/// to skip such functions in the subsequent visitor passes. Functions missing from the scan
+/// (e.g., ones added to the module after it was collected) are conservatively assumed
+/// to reference anything.
+#[derive(Debug, Default)]
+pub(crate) struct FunctionScan {
+    refs: HashMap<FunctionId, FunctionRefs>,
+    guarded_fns: HashSet<FunctionId>,
+}
+
+impl FunctionScan {
+    /// Inserts references of a function. `refs` must be [finished](FunctionRefs::finish()).
+    pub fn insert(&mut self, fn_id: FunctionId, refs: FunctionRefs, is_guarded: bool) {
+        self.refs.insert(fn_id, refs);
+        if is_guarded {
+            self.guarded_fns.insert(fn_id);
+        }
+    }
+
+    /// Checks whether the function contains a correctly placed `externref` guard.
+    pub fn is_guarded(&self, fn_id: FunctionId) -> bool {
+        self.guarded_fns.contains(&fn_id)
+    }
+
+    #[cfg(test)]
+    pub fn guarded_fns(&self) -> &HashSet<FunctionId> {
+        &self.guarded_fns
+    }
+
+    /// Checks whether the function may reference any of the specified `functions`,
+    /// or contain a `call_indirect` instruction with any of the specified `types`.
+    pub fn may_reference(
+        &self,
+        fn_id: FunctionId,
+        functions: &HashSet<FunctionId>,
+        types: &HashSet<TypeId>,
+    ) -> bool {
+        let Some(refs) = self.refs.get(&fn_id) else {
+            return true;
+        };
+        refs.functions.iter().any(|fn_id| functions.contains(fn_id))
+            || refs.indirect_types.iter().any(|ty| types.contains(ty))
+    }
+
+    /// Checks whether the function may contain a `call_indirect` instruction
+    /// with any of the types in `type_mapping`.
+    pub fn may_call_indirect<V>(
+        &self,
+        fn_id: FunctionId,
+        type_mapping: &HashMap<TypeId, V>,
+    ) -> bool {
+        self.refs.get(&fn_id).map_or(true, |refs| {
+            refs.indirect_types
+                .iter()
+                .any(|ty| type_mapping.contains_key(ty))
+        })
+    }
+
+    /// Replaces `call_indirect` types according to the mapping.
+    pub fn patch_indirect_types(&mut self, type_mapping: &HashMap<TypeId, TypeId>) {
+        for refs in self.refs.values_mut() {
+            for ty in &mut refs.indirect_types {
+                if let Some(&patched_ty) = type_mapping.get(ty) {
+                    *ty = patched_ty;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of scanned functions.
+    #[cfg(feature = "tracing")]
+    pub fn len(&self) -> usize {
+        self.refs.len()
+    }
+}
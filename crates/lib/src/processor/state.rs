@@ -1,95 +1,579 @@
 //! Stateful WASM module processing.
 
-use std::{
-    collections::{HashMap, HashSet},
-    iter, mem,
-};
+use core::{iter, mem};
+#[cfg(feature = "tracing")]
+use std::time::Instant;
 
 use walrus::{
-    ir, ExportItem, FunctionBuilder, FunctionId, ImportKind, LocalFunction, LocalId, Module,
-    ModuleLocals, ModuleTypes, TypeId, ValType,
+    ir, ConstExpr, Element, ElementItems, ElementKind, Export, ExportItem, FunctionBuilder,
+    FunctionId, GlobalId, ImportKind, InstrLocId, LocalFunction, LocalId, Module, ModuleLocals,
+    ModuleTypes, TableId, TypeId, ValType,
 };
 
 use super::{
+    debug_info::resolve_location,
+    error::ErrorCollector,
     functions::{get_offset, ExternrefImports, PatchedFunctions},
-    Error, Location, Processor, EXTERNREF,
+    scan::FunctionScan,
+    Error, IndexType, Location, PatchedFunction, PatchedFunctionKind, ProcessingArtifacts,
+    Processor, EXTERNREF,
+};
+use crate::{
+    alloc::{format, hash_map, vec, BTreeMap, HashMap, HashSet, String, ToOwned, Vec},
+    Function, FunctionKind,
 };
-use crate::{Function, FunctionKind};
 
 #[derive(Debug)]
 pub(crate) struct ProcessingState {
     patched_fns: PatchedFunctions,
+    /// Type of surrogate references in declared functions.
+    index_type: IndexType,
+    /// Module of surrogate imports.
+    surrogate_module: String,
 }
 
 impl ProcessingState {
     pub fn new(module: &mut Module, processor: &Processor<'_>) -> Result<Self, Error> {
-        let imports = ExternrefImports::new(&mut module.imports)?;
+        let imports = ExternrefImports::new(
+            &mut module.imports,
+            processor.surrogate_module,
+            processor.guard_import(),
+        )?;
+        imports.check_signatures(module, processor.index_type, processor.surrogate_module)?;
         let patched_fns = PatchedFunctions::new(module, &imports, processor);
-        Ok(Self { patched_fns })
+        Ok(Self {
+            patched_fns,
+            index_type: processor.index_type,
+            surrogate_module: processor.surrogate_module.to_owned(),
+        })
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
-    pub fn replace_functions(&self, module: &mut Module) -> Result<HashSet<FunctionId>, Error> {
-        let (replaced_count, guarded_fns) = self.patched_fns.replace_calls(module)?;
+    pub fn replace_functions(
+        &self,
+        module: &mut Module,
+        errors: &mut ErrorCollector,
+    ) -> Result<FunctionScan, Error> {
+        let (replaced_count, scan) = self.patched_fns.replace_calls(module, errors)?;
         #[cfg(feature = "tracing")]
-        tracing::info!(replaced_count, "replaced calls to externref imports");
-        Ok(guarded_fns)
+        tracing::info!(
+            replaced_count,
+            scanned_fns = scan.len(),
+            "replaced calls to externref imports"
+        );
+        Ok(scan)
     }
 
+    /// Returns IDs of the functions and tables injected by the processor.
+    pub fn artifacts(&self) -> ProcessingArtifacts {
+        self.patched_fns.artifacts().clone()
+    }
+
+    /// Returns surrogate functions patched by the processor.
+    pub fn patched_surrogates(&self) -> impl Iterator<Item = PatchedFunction<'_>> + '_ {
+        self.patched_fns
+            .surrogates()
+            .iter()
+            .map(|(name, fn_id)| PatchedFunction::new(*fn_id, PatchedFunctionKind::Surrogate(name)))
+    }
+
+    /// Patches exports and local functions. Returns the functions that were patched.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    pub fn process_functions(
+    pub fn process_functions<'f>(
         &self,
+        functions: &[Function<'f>],
+        mut scan: FunctionScan,
+        errors: &mut ErrorCollector,
+        module: &mut Module,
+    ) -> Result<Vec<PatchedFunction<'f>>, Error> {
+        // First, resolve function IDs for exports / imports. Declarations that cannot be resolved
+        // are skipped if errors are collected.
+        let mut function_ids = Vec::with_capacity(functions.len());
+        for function in functions {
+            let fn_id = errors.check(Self::function_id(function, module))?;
+            function_ids.push(fn_id.flatten());
+        }
+        Self::unwrap_export_trampolines(functions, &mut function_ids, module);
+        errors.check(self.check_orphaned_declarations(functions, &function_ids, module))?;
+
+        let mut ref_producers = self.patch_imports(functions, &function_ids, errors, module)?;
+        errors.check(Self::export_reexports(functions, &function_ids, module))?;
+        let functions_by_id =
+            errors.check(Self::functions_by_id(functions, &function_ids, module))?;
+        let functions_by_id = functions_by_id.unwrap_or_default();
+        ref_producers.types = errors
+            .check(Self::patch_indirect_calls(
+                &functions_by_id,
+                &mut scan,
+                module,
+                self.index_type,
+            ))?
+            .unwrap_or_default();
+
+        let local_fn_ids: Vec<_> = module.funcs.iter_local().map(|(id, _)| id).collect();
+        let mut patched_fns = vec![];
+        #[cfg(feature = "tracing")]
+        let mut skipped_count = 0_usize;
+        for fn_id in local_fn_ids {
+            let (kind, mut new_locals) = if let Some(&&function) = functions_by_id.get(&fn_id) {
+                let error_code = self.patched_fns.error_code_id();
+                let new_locals = Self::transform_export(
+                    module,
+                    &ref_producers,
+                    error_code,
+                    fn_id,
+                    &function,
+                    self.index_type,
+                );
+                let Some(new_locals) = errors.check(new_locals)? else {
+                    continue;
+                };
+                let kind = if function.kind == FunctionKind::TableEntry {
+                    PatchedFunctionKind::TableEntry(function)
+                } else {
+                    PatchedFunctionKind::Export(function)
+                };
+                (kind, new_locals)
+            } else {
+                // Fast path: a function that doesn't call functions returning refs
+                // cannot have ref locals.
+                if !scan.may_reference(fn_id, &ref_producers.functions, &ref_producers.types) {
+                    #[cfg(feature = "tracing")]
+                    {
+                        skipped_count += 1;
+                    }
+                    continue;
+                }
+                let can_have_locals = scan.is_guarded(fn_id);
+                let new_locals =
+                    Self::transform_local_fn(module, &ref_producers, can_have_locals, fn_id);
+                let Some(new_locals) = errors.check(new_locals)? else {
+                    continue;
+                };
+                if new_locals.is_empty() {
+                    continue;
+                }
+                (PatchedFunctionKind::Local, new_locals)
+            };
+
+            new_locals.sort_unstable();
+            let mut patched_fn = PatchedFunction::new(fn_id, kind);
+            patched_fn.new_locals = new_locals;
+            patched_fns.push(patched_fn);
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            patched_count = patched_fns.len(),
+            skipped_count,
+            "patched local functions"
+        );
+        errors.check(Self::set_start_fn(&functions_by_id, module))?;
+        Ok(patched_fns)
+    }
+
+    /// Redirects declared exports pointing to trampolines that merely forward their args
+    /// to another local function with the same signature (e.g., ones generated by adapters
+    /// for the `wasm32-wasip2` target) to the forwarded-to function, so that it is patched
+    /// instead. Otherwise, the trampoline would pass `externref`s to a function expecting `i32`s.
+    /// Trampolines referenced elsewhere in the module are left as is.
+    fn unwrap_export_trampolines(
+        functions: &[Function<'_>],
+        function_ids: &mut [Option<FunctionId>],
+        module: &mut Module,
+    ) {
+        for (function, fn_id) in functions.iter().zip(function_ids) {
+            if function.kind != FunctionKind::Export {
+                continue;
+            }
+            while let Some(trampoline_id) = *fn_id {
+                let Some(target_id) = forwarded_function(module, trampoline_id) else {
+                    break;
+                };
+                if is_referenced(module, trampoline_id) {
+                    break;
+                }
+                for export in module.exports.iter_mut() {
+                    if matches!(export.item, ExportItem::Function(id) if id == trampoline_id) {
+                        export.item = ExportItem::Function(target_id);
+                    }
+                }
+                *fn_id = Some(target_id);
+                #[cfg(feature = "tracing")]
+                tracing::info!(name = function.name, "unwrapped trampoline around export");
+            }
+        }
+    }
+
+    /// Exports imports declared as [re-exports](FunctionKind::Reexport). Must be called
+    /// after the imports are patched.
+    fn export_reexports(
         functions: &[Function<'_>],
-        guarded_fns: &HashSet<FunctionId>,
+        function_ids: &[Option<FunctionId>],
         module: &mut Module,
     ) -> Result<(), Error> {
-        // First, resolve function IDs for exports / imports.
-        let function_ids: Result<Vec<_>, _> = functions
+        for (function, &fn_id) in functions.iter().zip(function_ids) {
+            let (FunctionKind::Reexport { .. }, Some(fn_id)) = (function.kind, fn_id) else {
+                continue;
+            };
+            let export = module
+                .exports
+                .iter()
+                .find(|export| export.name == function.name);
+            let Some(export) = export else {
+                module.exports.add(function.name, fn_id);
+                #[cfg(feature = "tracing")]
+                tracing::info!(name = function.name, "exported re-exported import");
+                continue;
+            };
+
+            match export.item {
+                ExportItem::Function(id) if id == fn_id => { /* already exported */ }
+                ExportItem::Function(_) => {
+                    // The export refers to a local function, e.g. a shim calling the import.
+                    // The shim may be called elsewhere with the original signature,
+                    // so it is left as is.
+                    let export_id = export.id();
+                    let trampoline_id = add_reexport_trampoline(module, fn_id, function.name);
+                    module.exports.get_mut(export_id).item = ExportItem::Function(trampoline_id);
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(
+                        name = function.name,
+                        "redirected export to trampoline calling re-exported import"
+                    );
+                }
+                _ => return Err(Error::UnexpectedExportType(function.name.to_owned())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps resolved function IDs to declarations. A local function may be exported
+    /// under several names, all of which are patched together; if more than one alias
+    /// is declared, the declarations must agree.
+    fn functions_by_id<'a, 'f>(
+        functions: &'a [Function<'f>],
+        function_ids: &[Option<FunctionId>],
+        module: &Module,
+    ) -> Result<HashMap<FunctionId, &'a Function<'f>>, Error> {
+        let mut functions_by_id = HashMap::<_, &Function<'_>>::with_capacity(functions.len());
+        for (function, &fn_id) in functions.iter().zip(function_ids) {
+            let Some(fn_id) = fn_id else {
+                continue;
+            };
+            match functions_by_id.entry(fn_id) {
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert(function);
+                }
+                hash_map::Entry::Occupied(entry) => {
+                    // Conflicts for imported functions are checked when patching imports.
+                    let is_local =
+                        matches!(module.funcs.get(fn_id).kind, walrus::FunctionKind::Local(_));
+                    if is_local && entry.get().externrefs != function.externrefs {
+                        return Err(Error::ConflictingDeclarations {
+                            module: None,
+                            name: function.name.to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(functions_by_id)
+    }
+
+    /// Makes the declared [start function](FunctionKind::Start) (if any) the start function
+    /// of the module and removes its export.
+    fn set_start_fn(
+        functions_by_id: &HashMap<FunctionId, &Function<'_>>,
+        module: &mut Module,
+    ) -> Result<(), Error> {
+        // Ordered, so that processing is deterministic.
+        let mut start_fns: Vec<_> = functions_by_id
             .iter()
-            .map(|function| Self::function_id(function, module))
+            .filter(|(_, function)| function.kind == FunctionKind::Start)
+            .map(|(&fn_id, &function)| (fn_id, function))
             .collect();
-        let function_ids = function_ids?;
+        start_fns.sort_unstable_by_key(|(fn_id, _)| *fn_id);
+
+        for (fn_id, function) in start_fns {
+            if let Some(other_id) = module.start.filter(|&other_id| other_id != fn_id) {
+                let other = module.funcs.get(other_id).name.clone();
+                return Err(Error::ConflictingStart {
+                    name: function.name.to_owned(),
+                    other: other.unwrap_or_else(|| format!("function #{}", other_id.index())),
+                });
+            }
+            module.start = Some(fn_id);
+            let export = module
+                .exports
+                .iter()
+                .find(|export| export.name == function.name);
+            if let Some(export_id) = export.map(Export::id) {
+                module.exports.delete(export_id);
+            }
+            #[cfg(feature = "tracing")]
+            tracing::info!(name = function.name, "set start function");
+        }
+        Ok(())
+    }
 
-        // Determine which functions return externrefs (only patched imports or exports can
-        // do that).
-        let mut functions_returning_ref = HashSet::new();
+    /// Patches imported functions and determines which functions return externrefs
+    /// (only patched imports or exports can do that).
+    fn patch_imports(
+        &self,
+        functions: &[Function<'_>],
+        function_ids: &[Option<FunctionId>],
+        errors: &mut ErrorCollector,
+        module: &mut Module,
+    ) -> Result<RefProducers, Error> {
+        let mut ref_producers = RefProducers::default();
         if let Some(fn_id) = self.patched_fns.get_ref_id() {
-            functions_returning_ref.insert(fn_id);
+            ref_producers.functions.insert(fn_id);
         }
+        // Imported functions may be re-exported, in which case both the import and the export
+        // declarations resolve to the same function, which must be patched only once.
+        let mut patched_imports = HashMap::<_, &Function<'_>>::new();
+
+        for (function, &fn_id) in functions.iter().zip(function_ids) {
+            let Some(fn_id) = fn_id else {
+                continue;
+            };
+            let type_id = module.funcs.get(fn_id).ty();
+            let (params, results) = module.types.params_results(type_id);
+            // For fallible exports, the declared signature has an additional `i32` result
+            // after the returned ref; hence, we check the first result, not the last one.
+            if results.len() == 1 && function.externrefs.is_set(params.len()) {
+                ref_producers.functions.insert(fn_id);
+            }
 
-        for (function, &fn_id) in functions.iter().zip(&function_ids) {
-            if let Some(fn_id) = fn_id {
-                let type_id = module.funcs.get(fn_id).ty();
-                let results_len = module.types.get(type_id).results().len();
-                let refs = &function.externrefs;
-                if results_len == 1 && refs.is_set(refs.bit_len() - 1) {
-                    functions_returning_ref.insert(fn_id);
+            if !matches!(
+                module.funcs.get(fn_id).kind,
+                walrus::FunctionKind::Import(_)
+            ) {
+                continue;
+            }
+            match patched_imports.entry(fn_id) {
+                hash_map::Entry::Vacant(entry) => {
+                    let transformed =
+                        errors.check(transform_import(module, function, fn_id, self.index_type))?;
+                    if transformed.is_some() {
+                        entry.insert(function);
+                    }
                 }
+                hash_map::Entry::Occupied(entry) => {
+                    if entry.get().externrefs != function.externrefs {
+                        errors.check::<()>(Err(Error::ConflictingDeclarations {
+                            module: fn_module(&function.kind).map(str::to_owned),
+                            name: function.name.to_owned(),
+                        }))?;
+                    }
+                }
+            }
+        }
+        Ok(ref_producers)
+    }
+
+    /// Checks that the surrogate imports needed to patch the declared functions are present
+    /// in the module. Declarations may be left without surrogate imports if LTO removes all
+    /// `Resource` method calls, e.g. if resource args of an export are unused; in this case,
+    /// only the declarations that actually require surrogates are reported.
+    fn check_orphaned_declarations(
+        &self,
+        functions: &[Function<'_>],
+        function_ids: &[Option<FunctionId>],
+        module: &Module,
+    ) -> Result<(), Error> {
+        let has_insert = self.patched_fns.has_surrogate("insert");
+        let has_get = self.patched_fns.has_surrogate("get");
+        if has_insert && has_get {
+            return Ok(());
+        }
+
+        let mut usage = UsageDetector::default();
+        for (_, local_fn) in module.funcs.iter_local() {
+            ir::dfs_in_order(&mut usage, local_fn, local_fn.entry_block());
+        }
 
-                if let FunctionKind::Import(_) = function.kind {
-                    transform_import(module, function, fn_id)?;
+        let mut orphaned = vec![];
+        let (mut needs_insert, mut needs_get) = (false, false);
+        for (function, &fn_id) in functions.iter().zip(function_ids) {
+            let Some(fn_id) = fn_id else {
+                continue;
+            };
+            let (params, _) = module.types.params_results(module.funcs.get(fn_id).ty());
+            let has_ref_args = function
+                .externrefs
+                .set_indices()
+                .any(|idx| idx < params.len());
+            let has_ref_results = function
+                .externrefs
+                .set_indices()
+                .any(|idx| idx >= params.len());
+
+            let (fn_needs_insert, fn_needs_get) = match &module.funcs.get(fn_id).kind {
+                walrus::FunctionKind::Import(_) if usage.functions.contains(&fn_id) => {
+                    (has_ref_results, has_ref_args)
                 }
+                walrus::FunctionKind::Local(local_fn)
+                    if matches!(
+                        function.kind,
+                        FunctionKind::Export | FunctionKind::TableEntry | FunctionKind::Start
+                    ) =>
+                {
+                    let uses_ref_args = function
+                        .externrefs
+                        .set_indices()
+                        .filter_map(|idx| local_fn.args.get(idx))
+                        .any(|arg| usage.locals.contains(arg));
+                    (uses_ref_args, has_ref_results)
+                }
+                _ => (false, false),
+            };
+            let fn_needs_insert = fn_needs_insert && !has_insert;
+            let fn_needs_get = fn_needs_get && !has_get;
+            if fn_needs_insert || fn_needs_get {
+                let module_name = fn_module(&function.kind).map(str::to_owned);
+                orphaned.push((module_name, function.name.to_owned()));
             }
+            needs_insert |= fn_needs_insert;
+            needs_get |= fn_needs_get;
         }
 
-        let functions_by_id = function_ids
-            .into_iter()
-            .zip(functions)
-            .filter_map(|(fn_id, function)| fn_id.map(|fn_id| (fn_id, function)));
-        let functions_by_id: HashMap<_, _> = functions_by_id.collect();
+        if orphaned.is_empty() {
+            Ok(())
+        } else {
+            let missing_imports = [("insert", needs_insert), ("get", needs_get)];
+            let missing_imports = missing_imports
+                .into_iter()
+                .filter(|&(_, is_missing)| is_missing)
+                .map(|(name, _)| name.to_owned());
+            Err(Error::OrphanedDeclarations {
+                functions: orphaned,
+                module: self.surrogate_module.clone(),
+                missing_imports: missing_imports.collect(),
+            })
+        }
+    }
 
-        let local_fn_ids: Vec<_> = module.funcs.iter_local().map(|(id, _)| id).collect();
-        for fn_id in local_fn_ids {
-            if let Some(function) = functions_by_id.get(&fn_id) {
-                Self::transform_export(module, &functions_returning_ref, fn_id, function)?;
-            } else {
-                let can_have_locals = guarded_fns.contains(&fn_id);
-                Self::transform_local_fn(module, &functions_returning_ref, can_have_locals, fn_id)?;
+    /// Patches types of `call_indirect` instructions calling declared function table entries,
+    /// so that they correspond to the patched signatures of the entries. Returns the patched types
+    /// returning `externref`s.
+    ///
+    /// Since the callee of a `call_indirect` instruction is only known at runtime, all
+    /// instructions with the original type of a table entry are patched. To make this sound,
+    /// all functions with this type placed in the tables owning the entries must have the same
+    /// patched type. Other tables (e.g., ones created by the processor itself) and `call_indirect`
+    /// instructions accessing them are left as is.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err))]
+    fn patch_indirect_calls(
+        functions_by_id: &HashMap<FunctionId, &Function<'_>>,
+        scan: &mut FunctionScan,
+        module: &mut Module,
+        index_type: IndexType,
+    ) -> Result<HashSet<TypeId>, Error> {
+        // Ordered, so that processing is deterministic.
+        let mut table_entries: Vec<_> = functions_by_id
+            .iter()
+            .filter(|(_, function)| function.kind == FunctionKind::TableEntry)
+            .map(|(&fn_id, &function)| (fn_id, function))
+            .collect();
+        table_entries.sort_unstable_by_key(|(fn_id, _)| *fn_id);
+
+        let mut type_mapping = HashMap::<TypeId, (TypeId, &Function<'_>)>::new();
+        for &(fn_id, function) in &table_entries {
+            let ty = module.funcs.get(fn_id).ty();
+            let patched_ty = patch_type(&mut module.types, function, ty, index_type)?;
+            match type_mapping.entry(ty) {
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert((patched_ty, function));
+                }
+                hash_map::Entry::Occupied(entry) => {
+                    let &(other_ty, other) = entry.get();
+                    if other_ty != patched_ty {
+                        return Err(Error::AmbiguousTableEntry {
+                            name: function.name.to_owned(),
+                            other: other.name.to_owned(),
+                        });
+                    }
+                }
             }
         }
+        if type_mapping.is_empty() {
+            return Ok(HashSet::new());
+        }
 
-        Ok(())
+        let declared_ids = table_entries.iter().map(|(fn_id, _)| *fn_id).collect();
+        let owning_tables = owning_tables(module, &declared_ids);
+        for fn_id in table_entries_of(module, owning_tables.as_ref()) {
+            if functions_by_id.contains_key(&fn_id) {
+                continue;
+            }
+            let function = module.funcs.get(fn_id);
+            if let Some((_, declared)) = type_mapping.get(&function.ty()) {
+                return Err(Error::AmbiguousTableEntry {
+                    name: declared.name.to_owned(),
+                    other: function
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("function #{}", fn_id.index())),
+                });
+            }
+        }
+
+        let mut patcher = IndirectCallPatcher {
+            type_mapping: type_mapping
+                .iter()
+                .map(|(&ty, &(patched_ty, _))| (ty, patched_ty))
+                .collect(),
+            tables: owning_tables,
+            patched_count: 0,
+        };
+        for (fn_id, local_fn) in module.funcs.iter_local_mut() {
+            if scan.may_call_indirect(fn_id, &patcher.type_mapping) {
+                ir::dfs_pre_order_mut(&mut patcher, local_fn, local_fn.entry_block());
+            }
+        }
+        scan.patch_indirect_types(&patcher.type_mapping);
+        #[cfg(feature = "tracing")]
+        tracing::info!(patcher.patched_count, "patched indirect calls");
+
+        let types_returning_ref = type_mapping.into_values().filter_map(|(ty, _)| {
+            let (_, results) = module.types.params_results(ty);
+            (results == [EXTERNREF]).then_some(ty)
+        });
+        Ok(types_returning_ref.collect())
+    }
+
+    /// Wraps exported functions with names matching `exports_pattern` so that the wrappers
+    /// check for leaked references after calling the original functions.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, module)))]
+    pub fn add_leak_checks(&self, module: &mut Module, exports_pattern: &str) {
+        let Some(check_leaks_id) = self.patched_fns.check_leaks_id() else {
+            return;
+        };
+        let exports: Vec<_> = module
+            .exports
+            .iter()
+            .filter_map(|export| match export.item {
+                ExportItem::Function(fn_id) if matches_glob(exports_pattern, &export.name) => {
+                    Some((export.id(), export.name.clone(), fn_id))
+                }
+                _ => None,
+            })
+            .collect();
+
+        #[cfg(feature = "tracing")]
+        if exports.is_empty() {
+            tracing::warn!("no exported functions match the pattern; leak checks are not added");
+        }
+        for (export_id, name, fn_id) in exports {
+            let wrapper_id = add_leak_check_wrapper(module, &name, fn_id, check_leaks_id);
+            let export = module.exports.get_mut(export_id);
+            export.item = ExportItem::Function(wrapper_id);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(name = export.name, "added leak check to export");
+        }
     }
 
     #[cfg_attr(
@@ -103,7 +587,7 @@ impl ProcessingState {
     )]
     fn function_id(function: &Function<'_>, module: &Module) -> Result<Option<FunctionId>, Error> {
         Ok(Some(match function.kind {
-            FunctionKind::Export => {
+            FunctionKind::Export | FunctionKind::Start => {
                 let export = module
                     .exports
                     .iter()
@@ -115,57 +599,99 @@ impl ProcessingState {
                 }
             }
 
+            FunctionKind::TableEntry => {
+                let table_entries = table_entries_of(module, None);
+                let fn_id = module.funcs.by_name(function.name);
+                let fn_id = fn_id.filter(|fn_id| table_entries.contains(fn_id));
+                // Entries declared via `#[externref(indirect_export)]` are also exported
+                // under the same name, so they can be resolved if the `name` section is stripped.
+                let fn_id = fn_id.or_else(|| {
+                    let exported_id = module.exports.get_func(function.name).ok();
+                    exported_id.filter(|fn_id| table_entries.contains(fn_id))
+                });
+                fn_id.ok_or_else(|| Error::NoTableEntry(function.name.to_owned()))?
+            }
+
             FunctionKind::Import(module_name) => {
-                let Some(import_id) = module.imports.find(module_name, function.name) else {
-                    // The function is declared, but not actually used from the module.
-                    // This is fine for us.
-                    return Ok(None);
-                };
-                match module.imports.get(import_id).kind {
-                    ImportKind::Function(fn_id) => fn_id,
-                    _ => {
-                        return Err(Error::UnexpectedImportType {
-                            module: module_name.to_owned(),
-                            name: function.name.to_owned(),
-                        })
-                    }
-                }
+                return Self::import_id(module, module_name, function.name);
             }
+            // Re-exports are resolved to the imported function, so that it is patched
+            // as a regular import.
+            FunctionKind::Reexport {
+                module: module_name,
+                import_name,
+            } => return Self::import_id(module, module_name, import_name),
         }))
     }
 
+    fn import_id(
+        module: &Module,
+        module_name: &str,
+        name: &str,
+    ) -> Result<Option<FunctionId>, Error> {
+        let Some(import_id) = module.imports.find(module_name, name) else {
+            // The function is declared, but not actually used from the module.
+            // This is fine for us.
+            return Ok(None);
+        };
+        match module.imports.get(import_id).kind {
+            ImportKind::Function(fn_id) => Ok(Some(fn_id)),
+            _ => Err(Error::UnexpectedImportType {
+                module: module_name.to_owned(),
+                name: name.to_owned(),
+            }),
+        }
+    }
+
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(skip_all, err, fields(name = function.name))
+        tracing::instrument(
+            skip_all,
+            err,
+            fields(
+                name = function.name,
+                instr_count = tracing::field::Empty,
+                elapsed_us = tracing::field::Empty
+            )
+        )
     )]
     #[allow(clippy::needless_collect)] // false positive
     fn transform_export(
         module: &mut Module,
-        functions_returning_ref: &HashSet<FunctionId>,
+        ref_producers: &RefProducers,
+        error_code: Option<GlobalId>,
         fn_id: FunctionId,
         function: &Function<'_>,
-    ) -> Result<(), Error> {
+        index_type: IndexType,
+    ) -> Result<Vec<LocalId>, Error> {
+        #[cfg(feature = "tracing")]
+        let started_at = Instant::now();
         let local_fn = module.funcs.get_mut(fn_id).kind.unwrap_local_mut();
-        let (params, results) = patch_type_inner(&module.types, function, local_fn.ty())?;
+        let is_fallible = is_fallible_export(&module.types, function, local_fn.ty());
+        let (params, mut results) =
+            patch_type_inner(&module.types, function, local_fn.ty(), index_type)?;
+        if is_fallible {
+            // Remove the error code; it will be added by the trampoline.
+            results.pop();
+        }
 
-        let mut locals_mapping = HashMap::new();
+        let mut locals_mapping = BTreeMap::new();
         for idx in function.externrefs.set_indices() {
             if let Some(arg) = local_fn.args.get_mut(idx) {
-                let new_local = module.locals.add(EXTERNREF);
+                let new_local = add_ref_local(&mut module.locals, locals_mapping.len());
                 locals_mapping.insert(new_local, *arg);
                 *arg = new_local;
             }
         }
         let ref_args: Vec<_> = locals_mapping.keys().copied().collect();
 
-        let mut calls_visitor = RefCallDetector {
-            locals: &mut module.locals,
-            functions_returning_ref,
-            new_locals: HashMap::default(),
-        };
+        let mut calls_visitor = RefCallDetector::new(&mut module.locals, ref_producers);
+        calls_visitor.new_locals = locals_mapping;
         ir::dfs_pre_order_mut(&mut calls_visitor, local_fn, local_fn.entry_block());
-        let mut new_locals = calls_visitor.new_locals;
-        new_locals.extend(locals_mapping);
+        #[cfg(feature = "tracing")]
+        let instr_count = calls_visitor.instr_count;
+        let new_locals = calls_visitor.new_locals;
+        let new_local_ids = new_locals.keys().copied().collect();
 
         // Determine which `local.get $arg` instructions must be replaced with new arg locals.
         let mut locals_visitor = LocalReplacementCounter::new(ref_args.into_iter(), new_locals);
@@ -177,7 +703,36 @@ impl ProcessingState {
         ir::dfs_in_order(&mut cloner, local_fn, local_fn.entry_block());
         cloner.clone_function(local_fn, &mut replacer);
 
-        Ok(())
+        // The function may be exported under several names (e.g., `run` and `_start`);
+        // all aliases must expose the patched signature.
+        #[cfg(feature = "tracing")]
+        {
+            let aliases: Vec<_> = module
+                .exports
+                .iter()
+                .filter(|export| {
+                    matches!(export.item, ExportItem::Function(id) if id == fn_id)
+                        && export.name != function.name
+                })
+                .map(|export| export.name.as_str())
+                .collect();
+            if !aliases.is_empty() {
+                tracing::info!(?aliases, "patched export has aliases");
+            }
+        }
+
+        if is_fallible {
+            // Re-point all exports of the function (i.e., the declared export and its aliases).
+            let trampoline_id = add_fallible_trampoline(module, fn_id, &params, error_code);
+            for export in module.exports.iter_mut() {
+                if matches!(export.item, ExportItem::Function(id) if id == fn_id) {
+                    export.item = ExportItem::Function(trampoline_id);
+                }
+            }
+        }
+        #[cfg(feature = "tracing")]
+        record_fn_stats(started_at, instr_count);
+        Ok(new_local_ids)
     }
 
     /// What we want to do here and in [`Self::transform_export()`] is to patch some
@@ -189,15 +744,15 @@ impl ProcessingState {
     /// - Locals assigned from calling a function that returns `externref`. We know such functions
     ///   in advance; they are among imported functions (in which case whether a function
     ///   returns an `externref` is determined based on the function sig from the custom section),
-    ///   plus the `exernref::get` function.
+    ///   plus the `exernref::get` function. Besides direct calls, `externref`s can be returned
+    ///   by `call_indirect` instructions calling declared function table entries; such calls
+    ///   are recognized by their patched function type.
     ///
     /// Locals of the second type can occur in any local function; thus, we need to scan all
-    /// of them. We scan for these locals by searching tuples of `call $fn` + `local.set $r` /
-    /// `local.tee $r` instructions, where `$fn` is a function returning `externref`.
-    /// Thus, we assume that:
+    /// of them. We scan for these locals by searching for `call $fn` or `call_indirect $ty`
+    /// instructions followed by `local.set $r` / `local.tee $r`, where `$fn` / `$ty`
+    /// is a function / function type returning `externref`. Thus, we assume that:
     ///
-    /// - `call.indirect` is not used to produce `externref`s. This seems to be correct
-    ///   for properly produced modules.
     /// - A local is assigned immediately after the call. This *looks* reasonable; besides
     ///   being assigned to a local, an `externref` can only be consumed by a function
     ///   accepting an `externref` argument. Still, this assumption is somewhat shaky.
@@ -217,32 +772,48 @@ impl ProcessingState {
     /// this process is combined with cloning function code.
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(level = "trace", skip_all, err, fields(fn_id))
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            err,
+            fields(
+                ?fn_id,
+                name = module.funcs.get(fn_id).name.as_deref(),
+                instr_count = tracing::field::Empty,
+                elapsed_us = tracing::field::Empty
+            )
+        )
     )]
     fn transform_local_fn(
         module: &mut Module,
-        functions_returning_ref: &HashSet<FunctionId>,
+        ref_producers: &RefProducers,
         can_have_locals: bool,
         fn_id: FunctionId,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<LocalId>, Error> {
+        #[cfg(feature = "tracing")]
+        let started_at = Instant::now();
         let function = module.funcs.get_mut(fn_id);
         let local_fn = function.kind.unwrap_local_mut();
 
-        let mut calls_visitor = RefCallDetector {
-            locals: &mut module.locals,
-            functions_returning_ref,
-            new_locals: HashMap::default(),
-        };
+        let mut calls_visitor = RefCallDetector::new(&mut module.locals, ref_producers);
         ir::dfs_pre_order_mut(&mut calls_visitor, local_fn, local_fn.entry_block());
-        let new_locals = calls_visitor.new_locals;
+        #[cfg(feature = "tracing")]
+        let instr_count = calls_visitor.instr_count;
+        let (new_locals, call_offset) = (calls_visitor.new_locals, calls_visitor.first_call_offset);
         if new_locals.is_empty() {
             #[cfg(feature = "tracing")]
-            tracing::trace!("no new locals; skipping function transform");
-            return Ok(());
+            {
+                record_fn_stats(started_at, instr_count);
+                tracing::trace!("no new locals; skipping function transform");
+            }
+            return Ok(vec![]);
         } else if !can_have_locals {
+            let code_offset = call_offset.or_else(|| function_offset(local_fn));
             return Err(Error::UnexpectedCall {
                 function_name: function.name.clone(),
-                code_offset: function_offset(local_fn),
+                code_offset,
+                code_location: code_offset
+                    .and_then(|offset| resolve_location(&module.debug, local_fn, offset)),
             });
         }
 
@@ -253,15 +824,28 @@ impl ProcessingState {
             "replacing function locals"
         );
 
+        let new_local_ids = new_locals.keys().copied().collect();
         // Determine which `local.get $arg` instructions must be replaced with new arg locals.
         let mut locals_visitor = LocalReplacementCounter::new(iter::empty(), new_locals);
         ir::dfs_in_order(&mut locals_visitor, local_fn, local_fn.entry_block());
         let mut replacer = LocalReplacer::from(locals_visitor);
         ir::dfs_pre_order_mut(&mut replacer, local_fn, local_fn.entry_block());
-        Ok(())
+        #[cfg(feature = "tracing")]
+        record_fn_stats(started_at, instr_count);
+        Ok(new_local_ids)
     }
 }
 
+/// Records the number of visited instructions and the elapsed time for a transformed function
+/// in the current span, so that pathological functions can be found in processing traces.
+#[cfg(feature = "tracing")]
+fn record_fn_stats(started_at: Instant, instr_count: usize) {
+    let elapsed_us = u64::try_from(started_at.elapsed().as_micros()).unwrap_or(u64::MAX);
+    let span = tracing::Span::current();
+    span.record("instr_count", instr_count);
+    span.record("elapsed_us", elapsed_us);
+}
+
 fn function_offset(local_fn: &LocalFunction) -> Option<u32> {
     local_fn
         .block(local_fn.entry_block())
@@ -270,27 +854,61 @@ fn function_offset(local_fn: &LocalFunction) -> Option<u32> {
         .and_then(|(_, location)| get_offset(*location))
 }
 
+/// Adds a new `externref` local named `externref_local_{idx}` for debugging.
+fn add_ref_local(locals: &mut ModuleLocals, idx: usize) -> LocalId {
+    let local = locals.add(EXTERNREF);
+    locals.get_mut(local).name = Some(format!("externref_local_{idx}"));
+    local
+}
+
+/// Functions and function types (for `call_indirect` instructions) returning `externref`s.
+#[derive(Debug, Default)]
+struct RefProducers {
+    functions: HashSet<FunctionId>,
+    types: HashSet<TypeId>,
+}
+
 /// Visitor to detect calls to functions returning `externref`s and create a new ref local
 /// for each call.
 #[derive(Debug)]
 struct RefCallDetector<'a> {
     locals: &'a mut ModuleLocals,
-    functions_returning_ref: &'a HashSet<FunctionId>,
-    /// Mapping from a new local to the old local.
-    new_locals: HashMap<LocalId, LocalId>,
+    ref_producers: &'a RefProducers,
+    /// Mapping from a new local to the old local. Ordered, so that processing is deterministic.
+    new_locals: BTreeMap<LocalId, LocalId>,
+    /// WASM bytecode offset of the first detected call.
+    first_call_offset: Option<u32>,
+    /// Number of visited instructions.
+    instr_count: usize,
 }
 
-impl RefCallDetector<'_> {
+impl<'a> RefCallDetector<'a> {
+    fn new(locals: &'a mut ModuleLocals, ref_producers: &'a RefProducers) -> Self {
+        Self {
+            locals,
+            ref_producers,
+            new_locals: BTreeMap::default(),
+            first_call_offset: None,
+            instr_count: 0,
+        }
+    }
+
     fn returns_ref(&self, instr: &ir::Instr) -> bool {
-        if let ir::Instr::Call(call) = instr {
-            self.functions_returning_ref.contains(&call.func)
-        } else {
-            false
+        match instr {
+            ir::Instr::Call(call) => self.ref_producers.functions.contains(&call.func),
+            ir::Instr::CallIndirect(call) => self.ref_producers.types.contains(&call.ty),
+            _ => false,
+        }
+    }
+
+    fn record_call(&mut self, location: Option<InstrLocId>) {
+        if self.first_call_offset.is_none() {
+            self.first_call_offset = location.and_then(get_offset);
         }
     }
 
     fn replace_local(&mut self, local: &mut LocalId) {
-        let new_local = self.locals.add(EXTERNREF);
+        let new_local = add_ref_local(self.locals, self.new_locals.len());
         self.new_locals.insert(new_local, *local);
         *local = new_local;
     }
@@ -298,45 +916,205 @@ impl RefCallDetector<'_> {
 
 impl ir::VisitorMut for RefCallDetector<'_> {
     fn start_instr_seq_mut(&mut self, instr_seq: &mut ir::InstrSeq) {
-        let mut ref_on_top_of_stack = false;
-        for (instr, _) in &mut instr_seq.instrs {
+        self.instr_count += instr_seq.instrs.len();
+        let mut ref_call_location = None;
+        for (instr, location) in &mut instr_seq.instrs {
             match instr {
-                ir::Instr::LocalSet(local_set) if ref_on_top_of_stack => {
+                ir::Instr::LocalSet(local_set) if ref_call_location.is_some() => {
                     self.replace_local(&mut local_set.local);
-                    ref_on_top_of_stack = false;
+                    self.record_call(ref_call_location.take());
                 }
-                ir::Instr::LocalTee(local_tee) if ref_on_top_of_stack => {
+                ir::Instr::LocalTee(local_tee) if ref_call_location.is_some() => {
                     self.replace_local(&mut local_tee.local);
+                    self.record_call(ref_call_location);
                 }
                 _ => {
-                    ref_on_top_of_stack = self.returns_ref(instr);
+                    ref_call_location = self.returns_ref(instr).then_some(*location);
                 }
             }
         }
     }
 }
 
+/// Visitor replacing types of `call_indirect` / `return_call_indirect` instructions.
+#[derive(Debug)]
+struct IndirectCallPatcher {
+    type_mapping: HashMap<TypeId, TypeId>,
+    /// Tables accessed by the patched instructions; `None` if all tables are patched.
+    tables: Option<HashSet<TableId>>,
+    patched_count: usize,
+}
+
+impl IndirectCallPatcher {
+    fn patch(&mut self, ty: &mut TypeId, table: TableId) {
+        if let Some(tables) = &self.tables {
+            if !tables.contains(&table) {
+                return;
+            }
+        }
+        if let Some(&patched_ty) = self.type_mapping.get(ty) {
+            *ty = patched_ty;
+            self.patched_count += 1;
+        }
+    }
+}
+
+impl ir::VisitorMut for IndirectCallPatcher {
+    fn visit_call_indirect_mut(&mut self, instr: &mut ir::CallIndirect) {
+        self.patch(&mut instr.ty, instr.table);
+    }
+
+    fn visit_return_call_indirect_mut(&mut self, instr: &mut ir::ReturnCallIndirect) {
+        self.patch(&mut instr.ty, instr.table);
+    }
+}
+
+/// Iterates over functions referenced by an element segment.
+fn element_fns(element: &Element) -> impl Iterator<Item = FunctionId> + '_ {
+    let (fn_ids, exprs) = match &element.items {
+        ElementItems::Functions(fn_ids) => (fn_ids.as_slice(), [].as_slice()),
+        ElementItems::Expressions(_, exprs) => ([].as_slice(), exprs.as_slice()),
+    };
+    let expr_fns = exprs.iter().filter_map(|expr| match expr {
+        ConstExpr::RefFunc(fn_id) => Some(*fn_id),
+        _ => None,
+    });
+    fn_ids.iter().copied().chain(expr_fns)
+}
+
+/// Returns tables that the specified functions are placed in by active element segments.
+/// Returns `None` if any of the functions is referenced by a passive or declared segment,
+/// since such a function may end up in any table.
+fn owning_tables(module: &Module, fn_ids: &HashSet<FunctionId>) -> Option<HashSet<TableId>> {
+    let mut tables = HashSet::new();
+    for element in module.elements.iter() {
+        if !element_fns(element).any(|fn_id| fn_ids.contains(&fn_id)) {
+            continue;
+        }
+        match element.kind {
+            ElementKind::Active { table, .. } => {
+                tables.insert(table);
+            }
+            ElementKind::Passive | ElementKind::Declared => return None,
+        }
+    }
+    Some(tables)
+}
+
+/// Returns local functions placed in function tables by element segments. If `tables`
+/// are specified, only active segments for these tables are considered (passive segments are
+/// always considered since they may be copied into any table).
+fn table_entries_of(module: &Module, tables: Option<&HashSet<TableId>>) -> HashSet<FunctionId> {
+    let mut entries = HashSet::new();
+    for element in module.elements.iter() {
+        if let (ElementKind::Active { table, .. }, Some(tables)) = (&element.kind, tables) {
+            if !tables.contains(table) {
+                continue;
+            }
+        }
+        entries.extend(element_fns(element));
+    }
+    entries.retain(|&fn_id| matches!(module.funcs.get(fn_id).kind, walrus::FunctionKind::Local(_)));
+    entries
+}
+
+/// Returns the function that the specified local function forwards its args to, provided
+/// that the function consists of this call only, and the callee is a distinct local function
+/// with the same signature.
+fn forwarded_function(module: &Module, fn_id: FunctionId) -> Option<FunctionId> {
+    let walrus::FunctionKind::Local(local_fn) = &module.funcs.get(fn_id).kind else {
+        return None;
+    };
+    let (last, arg_gets) = local_fn.block(local_fn.entry_block()).instrs.split_last()?;
+    let ir::Instr::Call(ir::Call { func: target_id }) = &last.0 else {
+        return None;
+    };
+    let forwards_args = arg_gets.len() == local_fn.args.len()
+        && arg_gets.iter().zip(&local_fn.args).all(
+            |((instr, _), arg)| matches!(instr, ir::Instr::LocalGet(get) if get.local == *arg),
+        );
+    let target = module.funcs.get(*target_id);
+    let is_compatible = matches!(target.kind, walrus::FunctionKind::Local(_))
+        && module.types.params_results(target.ty()) == module.types.params_results(local_fn.ty());
+    (forwards_args && is_compatible && *target_id != fn_id).then_some(*target_id)
+}
+
+/// Checks whether the specified function is referenced from the module code
+/// (e.g., called by another function or placed into a table), not counting exports.
+fn is_referenced(module: &Module, fn_id: FunctionId) -> bool {
+    if module.start == Some(fn_id) || table_entries_of(module, None).contains(&fn_id) {
+        return true;
+    }
+    let mut usage = UsageDetector::default();
+    for (id, local_fn) in module.funcs.iter_local() {
+        if id != fn_id {
+            ir::dfs_in_order(&mut usage, local_fn, local_fn.entry_block());
+        }
+    }
+    usage.functions.contains(&fn_id)
+}
+
+/// Visitor collecting functions referenced from local functions (e.g., called)
+/// and mentioned locals.
+#[derive(Debug, Default)]
+struct UsageDetector {
+    functions: HashSet<FunctionId>,
+    locals: HashSet<LocalId>,
+}
+
+impl ir::Visitor<'_> for UsageDetector {
+    fn visit_function_id(&mut self, function: &FunctionId) {
+        self.functions.insert(*function);
+    }
+
+    fn visit_local_id(&mut self, local: &LocalId) {
+        self.locals.insert(*local);
+    }
+}
+
 #[derive(Debug, Default)]
 struct LocalState {
     replacements: HashMap<ir::InstrSeqId, Vec<Option<LocalId>>>,
     current_replacement: Option<LocalId>,
 }
 
+/// Current replacements of the original locals, used to propagate state along control flow.
+type ReplacementsSnapshot = Vec<(LocalId, Option<LocalId>)>;
+
 /// Visitor counting mentions of `externref` locals in patched functions.
 ///
 /// It is valid to reassign param locals via `local.set` or `local.tee`
 /// (and Rust frequently does this in practice).
 /// Since we change the local type from `i32` to `externref`, we need to track reassignments,
 /// and not change the local ID after reassignment (since it should retain the old `i32` type).
+///
+/// Reassignments are tracked along the structured control flow: the state after a block
+/// is merged from the fall-through state and the states at branches to the block, and
+/// both arms of an `if` start from the state before the `if`. Otherwise, an assignment
+/// in a block ending with an unconditional branch would leak into the following code,
+/// which may reuse the same `i32` local for an unrelated value (e.g., a resource index
+/// passed to a drop hook).
 #[derive(Debug)]
 struct LocalReplacementCounter {
     locals: HashMap<LocalId, LocalState>,
-    new_locals: HashMap<LocalId, LocalId>,
+    new_locals: BTreeMap<LocalId, LocalId>,
     current_seqs: Vec<ir::InstrSeqId>,
+    /// Loop sequences; branches to them do not affect the state after the loop.
+    loops: HashSet<ir::InstrSeqId>,
+    /// States at the start of `else` arms of `if` instructions.
+    alternative_states: HashMap<ir::InstrSeqId, ReplacementsSnapshot>,
+    /// Mapping from the `then` arm of an `if` to its `else` arm.
+    alternatives: HashMap<ir::InstrSeqId, ir::InstrSeqId>,
+    /// States flowing to the end of a block via branches.
+    branch_states: HashMap<ir::InstrSeqId, Vec<ReplacementsSnapshot>>,
+    is_unreachable: bool,
 }
 
 impl LocalReplacementCounter {
-    fn new(ref_args: impl Iterator<Item = LocalId>, new_locals: HashMap<LocalId, LocalId>) -> Self {
+    fn new(
+        ref_args: impl Iterator<Item = LocalId>,
+        new_locals: BTreeMap<LocalId, LocalId>,
+    ) -> Self {
         let mut locals: HashMap<_, _> = new_locals
             .values()
             .map(|local_id| (*local_id, LocalState::default()))
@@ -350,6 +1128,11 @@ impl LocalReplacementCounter {
             locals,
             new_locals,
             current_seqs: vec![],
+            loops: HashSet::new(),
+            alternative_states: HashMap::new(),
+            alternatives: HashMap::new(),
+            branch_states: HashMap::new(),
+            is_unreachable: false,
         }
     }
 
@@ -361,15 +1144,77 @@ impl LocalReplacementCounter {
             state.current_replacement = Some(local);
         }
     }
+
+    fn snapshot(&self) -> ReplacementsSnapshot {
+        self.locals
+            .iter()
+            .map(|(&local, state)| (local, state.current_replacement))
+            .collect()
+    }
+
+    fn restore(&mut self, snapshot: ReplacementsSnapshot) {
+        for (local, replacement) in snapshot {
+            self.locals.get_mut(&local).unwrap().current_replacement = replacement;
+        }
+    }
+
+    fn visit_branch(&mut self, target: ir::InstrSeqId) {
+        if !self.is_unreachable && !self.loops.contains(&target) {
+            let snapshot = self.snapshot();
+            self.branch_states.entry(target).or_default().push(snapshot);
+        }
+    }
+
+    /// Collects states flowing out of the sequence: the fall-through state (if reachable),
+    /// and states at branches to the sequence.
+    fn outgoing_states(&mut self, seq: ir::InstrSeqId) -> Vec<ReplacementsSnapshot> {
+        let mut states = vec![];
+        if !self.is_unreachable {
+            states.push(self.snapshot());
+        }
+        states.extend(self.branch_states.remove(&seq).unwrap_or_default());
+        states
+    }
 }
 
 impl ir::Visitor<'_> for LocalReplacementCounter {
     fn start_instr_seq(&mut self, instr_seq: &ir::InstrSeq) {
-        self.current_seqs.push(instr_seq.id());
+        let seq = instr_seq.id();
+        if let Some(snapshot) = self.alternative_states.remove(&seq) {
+            // The `else` arm starts from the state before the `if`.
+            self.restore(snapshot);
+            self.is_unreachable = false;
+        }
+        self.current_seqs.push(seq);
     }
 
-    fn end_instr_seq(&mut self, _: &ir::InstrSeq) {
+    fn end_instr_seq(&mut self, instr_seq: &ir::InstrSeq) {
         self.current_seqs.pop();
+        let seq = instr_seq.id();
+        if self.loops.contains(&seq) || self.current_seqs.is_empty() {
+            // The state after a loop is its fall-through state; the state after the function body
+            // is irrelevant.
+            return;
+        }
+
+        let mut states = self.outgoing_states(seq);
+        if let Some(alternative) = self.alternatives.remove(&seq) {
+            // The state after the `if` will be determined after visiting the `else` arm.
+            self.branch_states
+                .entry(alternative)
+                .or_default()
+                .append(&mut states);
+            return;
+        }
+
+        if states.is_empty() {
+            self.is_unreachable = true;
+        } else {
+            // If the states differ, the first one wins; the processed module would be invalid
+            // in this case anyway.
+            self.restore(states.swap_remove(0));
+            self.is_unreachable = false;
+        }
     }
 
     fn visit_local_get(&mut self, instr: &ir::LocalGet) {
@@ -391,6 +1236,49 @@ impl ir::Visitor<'_> for LocalReplacementCounter {
     fn visit_local_tee(&mut self, instr: &ir::LocalTee) {
         self.visit_assignment(instr.local);
     }
+
+    fn visit_loop(&mut self, instr: &ir::Loop) {
+        self.loops.insert(instr.seq);
+    }
+
+    fn visit_if_else(&mut self, instr: &ir::IfElse) {
+        self.alternative_states
+            .insert(instr.alternative, self.snapshot());
+        self.alternatives
+            .insert(instr.consequent, instr.alternative);
+    }
+
+    fn visit_br(&mut self, instr: &ir::Br) {
+        self.visit_branch(instr.block);
+        self.is_unreachable = true;
+    }
+
+    fn visit_br_if(&mut self, instr: &ir::BrIf) {
+        self.visit_branch(instr.block);
+    }
+
+    fn visit_br_table(&mut self, instr: &ir::BrTable) {
+        for &block in instr.blocks.iter().chain([&instr.default]) {
+            self.visit_branch(block);
+        }
+        self.is_unreachable = true;
+    }
+
+    fn visit_return(&mut self, _: &ir::Return) {
+        self.is_unreachable = true;
+    }
+
+    fn visit_return_call(&mut self, _: &ir::ReturnCall) {
+        self.is_unreachable = true;
+    }
+
+    fn visit_return_call_indirect(&mut self, _: &ir::ReturnCallIndirect) {
+        self.is_unreachable = true;
+    }
+
+    fn visit_unreachable(&mut self, _: &ir::Unreachable) {
+        self.is_unreachable = true;
+    }
 }
 
 #[derive(Debug)]
@@ -448,14 +1336,16 @@ impl ir::VisitorMut for LocalReplacer {
 #[derive(Debug)]
 struct FunctionCloner {
     builder: FunctionBuilder,
-    sequence_mapping: HashMap<ir::InstrSeqId, ir::InstrSeqId>,
+    /// Mapping from the original sequences to the cloned ones. Ordered, so that processing
+    /// is deterministic.
+    sequence_mapping: BTreeMap<ir::InstrSeqId, ir::InstrSeqId>,
 }
 
 impl FunctionCloner {
     fn new(builder: FunctionBuilder) -> Self {
         Self {
             builder,
-            sequence_mapping: HashMap::new(),
+            sequence_mapping: BTreeMap::new(),
         }
     }
 
@@ -529,19 +1419,131 @@ fn transform_import(
     module: &mut Module,
     function: &Function<'_>,
     fn_id: FunctionId,
+    index_type: IndexType,
 ) -> Result<(), Error> {
     let imported_fn = module.funcs.get_mut(fn_id).kind.unwrap_import_mut();
-    let patched_ty = patch_type(&mut module.types, function, imported_fn.ty)?;
+    let patched_ty = patch_type(&mut module.types, function, imported_fn.ty, index_type)?;
     imported_fn.ty = patched_ty;
     Ok(())
 }
 
+/// Checks whether the function is a fallible export, i.e., its declared signature
+/// has `(externref, i32)` return type (with the second value being the error code
+/// or the presence flag for `#[resource(nullable = "flag")]` exports), while the actual
+/// signature returns a single `i32`.
+fn is_fallible_export(types: &ModuleTypes, function: &Function<'_>, ty: TypeId) -> bool {
+    let (params, results) = types.params_results(ty);
+    let refs = &function.externrefs;
+    matches!(function.kind, FunctionKind::Export)
+        && results == [ValType::I32]
+        && refs.bit_len() == params.len() + 2
+        && refs.is_set(params.len())
+        && !refs.is_set(params.len() + 1)
+}
+
+/// Adds a function wrapping a fallible export: it calls the export and returns
+/// the error code as the second result.
+fn add_fallible_trampoline(
+    module: &mut Module,
+    fn_id: FunctionId,
+    params: &[ValType],
+    error_code: Option<GlobalId>,
+) -> FunctionId {
+    let mut builder = FunctionBuilder::new(&mut module.types, params, &[EXTERNREF, ValType::I32]);
+    let name = module.funcs.get(fn_id).name.as_deref().unwrap_or("export");
+    builder.name(format!("externref_fallible_{name}"));
+    let args: Vec<_> = params.iter().map(|&ty| module.locals.add(ty)).collect();
+    let mut instr_builder = builder.func_body();
+    for &arg in &args {
+        instr_builder.local_get(arg);
+    }
+    instr_builder.call(fn_id);
+    if let Some(error_code) = error_code {
+        instr_builder.global_get(error_code);
+    } else {
+        // The error code is never set, so it's always 0.
+        instr_builder.i32_const(0);
+    }
+    builder.finish(args, &mut module.funcs)
+}
+
+/// Adds a function forwarding its args to a re-exported import.
+fn add_reexport_trampoline(module: &mut Module, import_id: FunctionId, name: &str) -> FunctionId {
+    let (params, results) = module
+        .types
+        .params_results(module.funcs.get(import_id).ty());
+    let (params, results) = (params.to_vec(), results.to_vec());
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+    builder.name(format!("externref_reexport_{name}"));
+    let args: Vec<_> = params.iter().map(|&ty| module.locals.add(ty)).collect();
+    let mut instr_builder = builder.func_body();
+    for &arg in &args {
+        instr_builder.local_get(arg);
+    }
+    instr_builder.call(import_id);
+    builder.finish(args, &mut module.funcs)
+}
+
+/// Adds a function calling the specified function and then checking for leaked references.
+fn add_leak_check_wrapper(
+    module: &mut Module,
+    export_name: &str,
+    fn_id: FunctionId,
+    check_leaks_id: FunctionId,
+) -> FunctionId {
+    let (params, results) = module.types.params_results(module.funcs.get(fn_id).ty());
+    let (params, results) = (params.to_vec(), results.to_vec());
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+    builder.name(format!("externref_leak_check_{export_name}"));
+    let args: Vec<_> = params.iter().map(|&ty| module.locals.add(ty)).collect();
+    let mut instr_builder = builder.func_body();
+    for &arg in &args {
+        instr_builder.local_get(arg);
+    }
+    // The results of the wrapped function remain on the stack.
+    instr_builder.call(fn_id).call(check_leaks_id);
+    builder.finish(args, &mut module.funcs)
+}
+
+/// Checks whether `name` matches a glob `pattern`, in which `*` matches any sequence
+/// of chars (including an empty one), and `?` matches a single char.
+pub(super) fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut pattern_pos, mut name_pos) = (0, 0);
+    // Position in the pattern after the last `*`, and the position in the name it is matched to.
+    let mut backtrack = None;
+    while name_pos < name.len() {
+        match pattern.get(pattern_pos) {
+            Some('*') => {
+                pattern_pos += 1;
+                backtrack = Some((pattern_pos, name_pos));
+            }
+            Some(&ch) if ch == '?' || ch == name[name_pos] => {
+                pattern_pos += 1;
+                name_pos += 1;
+            }
+            _ => {
+                let Some((star_pattern_pos, star_name_pos)) = backtrack else {
+                    return false;
+                };
+                // Extend the sequence matched by the last `*` by one char.
+                pattern_pos = star_pattern_pos;
+                name_pos = star_name_pos + 1;
+                backtrack = Some((star_pattern_pos, name_pos));
+            }
+        }
+    }
+    pattern[pattern_pos..].iter().all(|&ch| ch == '*')
+}
+
 fn patch_type(
     types: &mut ModuleTypes,
     function: &Function<'_>,
     ty: TypeId,
+    index_type: IndexType,
 ) -> Result<TypeId, Error> {
-    let (params, results) = patch_type_inner(types, function, ty)?;
+    let (params, results) = patch_type_inner(types, function, ty, index_type)?;
     Ok(types.add(&params, &results))
 }
 
@@ -549,8 +1551,13 @@ fn patch_type_inner(
     types: &ModuleTypes,
     function: &Function<'_>,
     ty: TypeId,
+    index_type: IndexType,
 ) -> Result<(Vec<ValType>, Vec<ValType>), Error> {
     let (params, results) = types.params_results(ty);
+    let mut results = results.to_vec();
+    if is_fallible_export(types, function, ty) {
+        results.push(ValType::I32);
+    }
     if params.len() + results.len() != function.externrefs.bit_len() {
         return Err(Error::UnexpectedArity {
             module: fn_module(&function.kind).map(str::to_owned),
@@ -560,25 +1567,49 @@ fn patch_type_inner(
         });
     }
 
+    // Host handles are passed as table indices, so they must have the index type as well.
+    let expected_type = index_type.val_type();
+    for idx in function.host_handles.set_indices() {
+        let (location, real_type) = if idx < params.len() {
+            (Location::Arg(idx), params[idx])
+        } else if let Some(&ty) = results.get(idx - params.len()) {
+            (Location::ReturnType(idx - params.len()), ty)
+        } else {
+            continue;
+        };
+        if real_type != expected_type {
+            return Err(Error::UnexpectedType {
+                module: fn_module(&function.kind).map(str::to_owned),
+                name: function.name.to_owned(),
+                location,
+                resource_type: None,
+                expected_type,
+                real_type,
+            });
+        }
+    }
+
     let mut new_params = params.to_vec();
-    let mut new_results = results.to_vec();
-    for idx in function.externrefs.set_indices() {
+    let mut new_results = results.clone();
+    for (pos, idx) in function.externrefs.set_indices().enumerate() {
         let placement = if idx < new_params.len() {
             &mut new_params[idx]
         } else {
             &mut new_results[idx - new_params.len()]
         };
 
-        if *placement != ValType::I32 {
+        if *placement != expected_type {
             return Err(Error::UnexpectedType {
                 module: fn_module(&function.kind).map(str::to_owned),
                 name: function.name.to_owned(),
-                location: if idx < new_params.len() {
+                location: if idx < params.len() {
                     Location::Arg(idx)
                 } else {
-                    Location::ReturnType(idx - new_params.len())
+                    Location::ReturnType(idx - params.len())
                 },
-                real_type: new_params[idx],
+                resource_type: function.type_names.get(pos).map(str::to_owned),
+                expected_type,
+                real_type: *placement,
             });
         }
         *placement = EXTERNREF;
@@ -597,7 +1628,10 @@ fn patch_type_inner(
 
 fn fn_module<'a>(fn_kind: &FunctionKind<'a>) -> Option<&'a str> {
     match fn_kind {
-        FunctionKind::Export => None,
+        FunctionKind::Export
+        | FunctionKind::TableEntry
+        | FunctionKind::Start
+        | FunctionKind::Reexport { .. } => None,
         FunctionKind::Import(module) => Some(*module),
     }
 }
@@ -606,6 +1640,25 @@ fn fn_module<'a>(fn_kind: &FunctionKind<'a>) -> Option<&'a str> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn matching_globs() {
+        assert!(matches_glob("test", "test"));
+        assert!(!matches_glob("test", "tests"));
+        assert!(matches_glob("test*", "test"));
+        assert!(matches_glob("test*", "test_export"));
+        assert!(!matches_glob("test*", "other_test"));
+        assert!(matches_glob("*_exit", "on_exit"));
+        assert!(matches_glob("*_exit", "_exit"));
+        assert!(!matches_glob("*_exit", "exit"));
+        assert!(matches_glob("t?st", "test"));
+        assert!(!matches_glob("t?st", "tst"));
+        assert!(matches_glob("*a*b*", "xxaybzz"));
+        assert!(matches_glob("*a*b", "abab"));
+        assert!(!matches_glob("*a*b", "abba"));
+        assert!(matches_glob("*", ""));
+        assert!(!matches_glob("?", ""));
+    }
+
     #[test]
     fn detecting_calls_to_functions_returning_ref() {
         const MODULE_BYTES: &[u8] = br#"
@@ -626,7 +1679,7 @@ mod tests {
 
         let module = wat::parse_bytes(MODULE_BYTES).unwrap();
         let mut module = Module::from_buffer(&module).unwrap();
-        let functions_returning_ref: HashSet<_> = module
+        let functions: HashSet<_> = module
             .funcs
             .iter()
             .filter_map(|function| {
@@ -646,8 +1699,58 @@ mod tests {
             unreachable!()
         };
 
-        ProcessingState::transform_local_fn(&mut module, &functions_returning_ref, true, fn_id)
-            .unwrap();
+        let ref_producers = RefProducers {
+            functions,
+            types: HashSet::new(),
+        };
+        ProcessingState::transform_local_fn(&mut module, &ref_producers, true, fn_id).unwrap();
+
+        let ref_locals: Vec<_> = module
+            .locals
+            .iter()
+            .filter(|local| local.ty() == EXTERNREF)
+            .collect();
+        assert_eq!(ref_locals.len(), 1, "{ref_locals:?}");
+        let ref_local_id = ref_locals[0].id();
+
+        let local_fn = module.funcs.get(fn_id).kind.unwrap_local();
+        let mut mentions = LocalMentions::default();
+        ir::dfs_in_order(&mut mentions, local_fn, local_fn.entry_block());
+        assert_eq!(mentions.local_counts[&ref_local_id], 2);
+    }
+
+    #[test]
+    fn ref_local_assignment_does_not_leak_from_diverging_block() {
+        const MODULE_BYTES: &[u8] = br#"
+            (module
+                (import "test" "get_ref" (func $get_ref (result i32)))
+                (import "test" "use_index" (func $use_index (param i32)))
+
+                (func (export "test") (param $x i32) (param $flag i32)
+                    (block $outer
+                        (block $inner
+                            (br_if $inner (local.get $flag))
+                            (local.set $x (call $get_ref)) ;; new local required
+                            (drop (local.get $x)) ;; new local used
+                            (br $outer)
+                        )
+                        ;; Only reachable via `br_if`, so the existing local should be used
+                        (call $use_index (local.get $x))
+                    )
+                )
+            )
+        "#;
+
+        let module = wat::parse_bytes(MODULE_BYTES).unwrap();
+        let mut module = Module::from_buffer(&module).unwrap();
+        let get_ref_id = module.imports.get_func("test", "get_ref").unwrap();
+        let fn_id = module.exports.get_func("test").unwrap();
+
+        let ref_producers = RefProducers {
+            functions: HashSet::from([get_ref_id]),
+            types: HashSet::new(),
+        };
+        ProcessingState::transform_local_fn(&mut module, &ref_producers, true, fn_id).unwrap();
 
         let ref_locals: Vec<_> = module
             .locals
@@ -658,9 +1761,11 @@ mod tests {
         let ref_local_id = ref_locals[0].id();
 
         let local_fn = module.funcs.get(fn_id).kind.unwrap_local();
+        let index_local_id = local_fn.args[0];
         let mut mentions = LocalMentions::default();
         ir::dfs_in_order(&mut mentions, local_fn, local_fn.entry_block());
         assert_eq!(mentions.local_counts[&ref_local_id], 2);
+        assert_eq!(mentions.local_counts[&index_local_id], 1);
     }
 
     #[derive(Debug, Default)]
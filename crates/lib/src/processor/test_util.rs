@@ -0,0 +1,725 @@
+//! Random module generation for fuzzing the [`Processor`](super::Processor), and invariant checks
+//! for functions generated by the processor.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use walrus::{
+    FunctionBuilder, FunctionId, InstrSeqBuilder, LocalId, Module, RawCustomSection, ValType,
+};
+
+use super::{
+    functions::ExternrefImports,
+    interpreter::{Interpreter, Val},
+};
+use crate::Function;
+
+const MAX_PARAMS: usize = 4;
+const MAX_HOST_IMPORTS: usize = 4;
+const MAX_EXPORTS: usize = 4;
+const MAX_CALLS: usize = 4;
+const MAX_KINDS: usize = 3;
+
+/// Signature of a generated function with `i32` surrogates in place of `externref`s.
+#[derive(Debug)]
+struct Signature {
+    param_count: usize,
+    has_result: bool,
+    /// Resource args followed by the resource return type, same as in [`Function::externrefs`].
+    externrefs: Vec<bool>,
+}
+
+impl Signature {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Result<Self> {
+        let param_count = u.int_in_range(0..=MAX_PARAMS)?;
+        let has_result = u.arbitrary()?;
+        let externrefs = (0..param_count + usize::from(has_result))
+            .map(|_| u.arbitrary())
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            param_count,
+            has_result,
+            externrefs,
+        })
+    }
+
+    fn has_refs(&self) -> bool {
+        self.externrefs.contains(&true)
+    }
+
+    fn has_ref_params(&self) -> bool {
+        self.externrefs[..self.param_count].contains(&true)
+    }
+
+    fn has_ref_result(&self) -> bool {
+        self.has_result && self.externrefs[self.param_count]
+    }
+
+    fn params(&self) -> Vec<ValType> {
+        vec![ValType::I32; self.param_count]
+    }
+
+    fn results(&self) -> Vec<ValType> {
+        if self.has_result {
+            vec![ValType::I32]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Writes a [`Function`] declaration for this signature, either in the current format
+    /// or in the legacy one without an envelope.
+    #[allow(clippy::cast_possible_truncation)] // generated lengths are small
+    fn write_declaration(
+        &self,
+        buffer: &mut Vec<u8>,
+        module: Option<&str>,
+        name: &str,
+        versioned: bool,
+    ) {
+        let mut payload = vec![];
+        if let Some(module) = module {
+            payload.extend_from_slice(&(module.len() as u32).to_le_bytes());
+            payload.extend_from_slice(module.as_bytes());
+        } else {
+            payload.extend_from_slice(&u32::MAX.to_le_bytes());
+        }
+        payload.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        payload.extend_from_slice(name.as_bytes());
+
+        payload.extend_from_slice(&(self.externrefs.len() as u32).to_le_bytes());
+        let mut bytes = vec![0_u8; self.externrefs.len().div_ceil(8)];
+        for (i, _) in self
+            .externrefs
+            .iter()
+            .enumerate()
+            .filter(|(_, &is_set)| is_set)
+        {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+        payload.extend_from_slice(&bytes);
+
+        if versioned {
+            buffer.extend_from_slice(&(u32::MAX - 1).to_le_bytes());
+            buffer.extend_from_slice(&Function::CUSTOM_SECTION_VERSION.to_le_bytes());
+            buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        }
+        buffer.extend_from_slice(&payload);
+    }
+}
+
+/// Surrogate imports from the `externref` module included into the generated module.
+#[derive(Debug, Default)]
+struct Surrogates {
+    insert: Option<FunctionId>,
+    get: Option<FunctionId>,
+    drop: Option<FunctionId>,
+    guard: Option<FunctionId>,
+    kinds: Vec<FunctionId>,
+}
+
+impl Surrogates {
+    /// Adds surrogate imports to the `module`. Imports required by the generated functions
+    /// are always added; other imports are added randomly.
+    fn arbitrary(
+        u: &mut Unstructured<'_>,
+        module: &mut Module,
+        needs_insert: bool,
+        needs_get: bool,
+    ) -> Result<Self> {
+        let mut add_import = |name: &str, params: &[ValType], results: &[ValType]| {
+            let ty = module.types.add(params, results);
+            module
+                .add_import_func(ExternrefImports::MODULE_NAME, name, ty)
+                .0
+        };
+
+        let i32 = ValType::I32;
+        let mut this = Self::default();
+        if needs_insert || u.arbitrary()? {
+            this.insert = Some(add_import("insert", &[i32, i32], &[i32]));
+        }
+        if needs_get || u.arbitrary()? {
+            this.get = Some(add_import("get", &[i32], &[i32]));
+        }
+        if u.arbitrary()? {
+            this.drop = Some(add_import("drop", &[i32], &[]));
+        }
+        if u.arbitrary()? {
+            this.guard = Some(add_import("guard", &[], &[]));
+        }
+        for i in 0..u.int_in_range(0..=MAX_KINDS)? {
+            let name = format!("{}Kind{i}", ExternrefImports::KIND_PREFIX);
+            this.kinds.push(add_import(&name, &[], &[i32]));
+        }
+        Ok(this)
+    }
+
+    /// Pushes a resource kind ID onto the stack.
+    fn push_kind(&self, u: &mut Unstructured<'_>, body: &mut InstrSeqBuilder<'_>) -> Result<()> {
+        if !self.kinds.is_empty() && u.arbitrary()? {
+            body.call(*u.choose(&self.kinds)?);
+        } else {
+            body.i32_const(-1);
+        }
+        Ok(())
+    }
+
+    /// Converts a reference obtained from the host into a surrogate.
+    fn insert(&self, u: &mut Unstructured<'_>, body: &mut InstrSeqBuilder<'_>) -> Result<()> {
+        if let Some(insert) = self.insert {
+            self.push_kind(u, body)?;
+            body.call(insert);
+        }
+        Ok(())
+    }
+
+    /// Pushes a reference onto the stack: either one of the `ref_locals`, or a null reference.
+    fn push_ref(
+        &self,
+        u: &mut Unstructured<'_>,
+        body: &mut InstrSeqBuilder<'_>,
+        ref_locals: &[LocalId],
+    ) -> Result<()> {
+        if !ref_locals.is_empty() && u.arbitrary()? {
+            body.local_get(*u.choose(ref_locals)?);
+        } else {
+            body.i32_const(-1);
+        }
+        if let Some(get) = self.get {
+            body.call(get);
+        }
+        Ok(())
+    }
+}
+
+/// Pushes either one of `locals` or a constant onto the stack.
+fn push_value(
+    u: &mut Unstructured<'_>,
+    body: &mut InstrSeqBuilder<'_>,
+    locals: &[LocalId],
+) -> Result<()> {
+    if !locals.is_empty() && u.arbitrary()? {
+        body.local_get(*u.choose(locals)?);
+    } else {
+        body.i32_const(u.arbitrary()?);
+    }
+    Ok(())
+}
+
+/// Random WASM module mimicking the output of the `#[externref]` macro, intended for fuzzing
+/// the [`Processor`](super::Processor).
+///
+/// The module contains a random subset of surrogate imports (including the guard and resource
+/// kind imports), host imports and exports with resource args and return types,
+/// and the custom section with declarations of these functions. Exports call host imports
+/// converting surrogates to and from references in the same way as the code generated
+/// by the macro does. The module is always valid WASM, but its processing may fail
+/// (e.g., if some surrogate imports are missing); processing must never panic, though.
+///
+/// # Examples
+///
+/// ```
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use externref::processor::{test_util::ArbitraryModule, Processor};
+///
+/// // Fuzzer-provided data
+/// let data = [42_u8; 256];
+/// let module = ArbitraryModule::arbitrary(&mut Unstructured::new(&data))?;
+/// // Processing may fail, but it must not panic.
+/// Processor::default().process_bytes(module.bytes()).ok();
+/// # Ok::<_, arbitrary::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ArbitraryModule {
+    bytes: Vec<u8>,
+}
+
+impl ArbitraryModule {
+    /// Returns the bytes of the generated module.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Converts this module into its bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn add_export(
+        u: &mut Unstructured<'_>,
+        module: &mut Module,
+        surrogates: &Surrogates,
+        host_imports: &[(FunctionId, Signature)],
+        signature: &Signature,
+    ) -> Result<FunctionId> {
+        let params: Vec<_> = (0..signature.param_count)
+            .map(|_| module.locals.add(ValType::I32))
+            .collect();
+        let mut builder =
+            FunctionBuilder::new(&mut module.types, &signature.params(), &signature.results());
+        let mut body = builder.func_body();
+
+        if let Some(guard) = surrogates.guard {
+            if u.arbitrary()? {
+                body.call(guard);
+            }
+        }
+        let (ref_params, value_params): (Vec<_>, Vec<_>) = params
+            .iter()
+            .enumerate()
+            .partition(|&(i, _)| signature.externrefs[i]);
+        let ref_params: Vec<_> = ref_params.into_iter().map(|(_, &param)| param).collect();
+        let value_params: Vec<_> = value_params.into_iter().map(|(_, &param)| param).collect();
+        for &param in &ref_params {
+            body.local_get(param);
+            surrogates.insert(u, &mut body)?;
+            body.local_set(param);
+        }
+
+        for _ in 0..u.int_in_range(0..=MAX_CALLS)? {
+            if host_imports.is_empty() {
+                break;
+            }
+            let (import_id, import_signature) = u.choose(host_imports)?;
+            for i in 0..import_signature.param_count {
+                if import_signature.externrefs[i] {
+                    surrogates.push_ref(u, &mut body, &ref_params)?;
+                } else {
+                    push_value(u, &mut body, &value_params)?;
+                }
+            }
+            body.call(*import_id);
+
+            if import_signature.has_ref_result() {
+                surrogates.insert(u, &mut body)?;
+                if let Some(drop) = surrogates.drop {
+                    body.call(drop);
+                    continue;
+                }
+            }
+            if import_signature.has_result {
+                body.drop();
+            }
+        }
+
+        if let Some(drop) = surrogates.drop {
+            for &param in &ref_params {
+                if u.arbitrary()? {
+                    body.local_get(param).call(drop);
+                }
+            }
+        }
+        if signature.has_ref_result() {
+            surrogates.push_ref(u, &mut body, &ref_params)?;
+        } else if signature.has_result {
+            push_value(u, &mut body, &value_params)?;
+        }
+        Ok(builder.finish(params, &mut module.funcs))
+    }
+}
+
+impl<'a> Arbitrary<'a> for ArbitraryModule {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let import_signatures = (0..u.int_in_range(0..=MAX_HOST_IMPORTS)?)
+            .map(|_| Signature::arbitrary(u))
+            .collect::<Result<Vec<_>>>()?;
+        let export_signatures = (0..u.int_in_range(0..=MAX_EXPORTS)?)
+            .map(|_| Signature::arbitrary(u))
+            .collect::<Result<Vec<_>>>()?;
+        let needs_insert = import_signatures.iter().any(Signature::has_ref_result)
+            || export_signatures.iter().any(Signature::has_ref_params);
+        let needs_get = import_signatures.iter().any(Signature::has_ref_params)
+            || export_signatures.iter().any(Signature::has_ref_result);
+
+        let mut module = Module::default();
+        let mut section_data = vec![];
+        let surrogates = Surrogates::arbitrary(u, &mut module, needs_insert, needs_get)?;
+
+        let mut host_imports = vec![];
+        for (i, signature) in import_signatures.into_iter().enumerate() {
+            let ty = module.types.add(&signature.params(), &signature.results());
+            let module_name = format!("host{}", i % 2);
+            let name = format!("import{i}");
+            let (fn_id, _) = module.add_import_func(&module_name, &name, ty);
+            // Functions with resources are always declared, like in the macro output.
+            if signature.has_refs() || u.arbitrary()? {
+                let versioned = u.arbitrary()?;
+                signature.write_declaration(
+                    &mut section_data,
+                    Some(&module_name),
+                    &name,
+                    versioned,
+                );
+            }
+            host_imports.push((fn_id, signature));
+        }
+
+        for (i, signature) in export_signatures.iter().enumerate() {
+            let fn_id = Self::add_export(u, &mut module, &surrogates, &host_imports, signature)?;
+            let name = format!("export{i}");
+            module.exports.add(&name, fn_id);
+            if signature.has_refs() || u.arbitrary()? {
+                let versioned = u.arbitrary()?;
+                signature.write_declaration(&mut section_data, None, &name, versioned);
+            }
+        }
+
+        if !section_data.is_empty() {
+            module.customs.add(RawCustomSection {
+                name: Function::CUSTOM_SECTION_NAME.to_owned(),
+                data: section_data,
+            });
+        }
+        Ok(Self {
+            bytes: module.emit_wasm(),
+        })
+    }
+}
+
+/// Reference implementation of the `externref`s table managed by the processed module.
+///
+/// References are placed into partitions: a single partition for [shared] tables,
+/// or a partition per resource kind plus a partition for references of unknown kind
+/// for [per-kind] tables. Reference IDs encode the slot index and the partition
+/// as `slot * partition_count + partition`. Inserting a reference reuses the free slot
+/// with the greatest index in the partition, or appends a new slot if there are no free slots.
+///
+/// [shared]: super::Partitioning::Shared
+/// [per-kind]: super::Partitioning::PerKind
+///
+/// # Examples
+///
+/// ```
+/// # use externref::processor::test_util::RefTableModel;
+/// let mut table = RefTableModel::new(1);
+/// assert_eq!(table.insert(Some("a"), -1), 0);
+/// assert_eq!(table.insert(Some("b"), -1), 1);
+/// assert_eq!(table.insert(None, -1), -1);
+/// assert_eq!(table.remove(0), Some("a"));
+/// // The freed slot is reused.
+/// assert_eq!(table.insert(Some("c"), -1), 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RefTableModel<T> {
+    partitions: Vec<Vec<Option<T>>>,
+}
+
+impl<T> RefTableModel<T> {
+    /// Creates an empty table with the specified number of partitions: 1 for shared tables,
+    /// and the number of resource kinds + 1 for per-kind tables.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `partition_count` is zero.
+    pub fn new(partition_count: usize) -> Self {
+        assert!(
+            partition_count > 0,
+            "table must have at least one partition"
+        );
+        Self {
+            partitions: (0..partition_count).map(|_| vec![]).collect(),
+        }
+    }
+
+    /// Inserts a reference with the specified resource kind ID (-1 if the kind is unknown)
+    /// and returns the reference ID, or -1 if `value` is `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reference ID overflows `i32`.
+    pub fn insert(&mut self, value: Option<T>, kind: i32) -> i32 {
+        let Some(value) = value else {
+            return -1;
+        };
+        let partition_count = self.partitions.len();
+        let partition = match usize::try_from(kind) {
+            Ok(kind) if kind + 1 < partition_count => kind + 1,
+            _ => 0,
+        };
+        let slots = &mut self.partitions[partition];
+        let slot = slots.iter().rposition(Option::is_none).unwrap_or_else(|| {
+            slots.push(None);
+            slots.len() - 1
+        });
+        slots[slot] = Some(value);
+        i32::try_from(slot * partition_count + partition).expect("reference ID overflow")
+    }
+
+    fn slot(&self, id: i32) -> Option<(usize, usize)> {
+        let id = usize::try_from(id).ok()?;
+        let partition_count = self.partitions.len();
+        Some((id % partition_count, id / partition_count))
+    }
+
+    /// Returns the reference with the specified ID, or `None` if the ID is not live.
+    pub fn get(&self, id: i32) -> Option<&T> {
+        let (partition, slot) = self.slot(id)?;
+        self.partitions[partition].get(slot)?.as_ref()
+    }
+
+    /// Removes the reference with the specified ID, returning it if the ID was live.
+    pub fn remove(&mut self, id: i32) -> Option<T> {
+        let (partition, slot) = self.slot(id)?;
+        self.partitions[partition].get_mut(slot)?.take()
+    }
+
+    /// Returns IDs of all live references in the increasing order.
+    #[allow(clippy::missing_panics_doc)] // IDs are checked on insertion
+    pub fn live_ids(&self) -> Vec<i32> {
+        let partition_count = self.partitions.len();
+        let mut ids: Vec<_> = self
+            .partitions
+            .iter()
+            .enumerate()
+            .flat_map(|(partition, slots)| {
+                let live_slots = slots.iter().enumerate().filter(|(_, slot)| slot.is_some());
+                live_slots.map(move |(slot, _)| slot * partition_count + partition)
+            })
+            .map(|id| i32::try_from(id).unwrap())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// Simple xorshift PRNG to avoid depending on `rand`.
+#[derive(Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // `bound` is small
+    fn next_below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+/// Functions managing the `externref`s table.
+#[derive(Debug, Clone, Copy)]
+struct TableFns {
+    insert: FunctionId,
+    get: Option<FunctionId>,
+    drop: Option<FunctionId>,
+}
+
+const CHECKED_SEQUENCES: u64 = 32;
+const OPS_PER_SEQUENCE: usize = 64;
+
+/// Checks that the functions managing the `externref`s table generated by the processor
+/// (`externref_insert`, `externref_get` and `externref_drop`) behave like [`RefTableModel`].
+///
+/// The functions are executed by a minimal interpreter on pseudo-random sequences of inserting
+/// (including null references and references of all resource kinds), reading and dropping
+/// references, starting from an empty table. In particular, this checks the null check,
+/// the free slot search and the table growth in the insert function, and that dropped slots
+/// are reused. The drop hook, if any, must receive the dropped reference.
+///
+/// Functions are looked up by their names, so the module must be processed without
+/// [stripping names](super::Processor::set_strip_names()). If the module does not contain
+/// the insert function (e.g., because it does not use references), the check is a no-op.
+///
+/// # Panics
+///
+/// Panics if the functions diverge from the model, trap, or contain instructions
+/// not supported by the interpreter.
+///
+/// # Examples
+///
+/// ```
+/// # use externref::processor::{test_util::debug_assert_table_fn_correct, Processor};
+/// # fn test_module(module: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut module = walrus::Module::from_buffer(module)?;
+/// Processor::default().process(&mut module)?;
+/// debug_assert_table_fn_correct(&module);
+/// # Ok(())
+/// # }
+/// ```
+pub fn debug_assert_table_fn_correct(module: &Module) {
+    let Some(insert) = module.funcs.by_name("externref_insert") else {
+        return;
+    };
+    let fns = TableFns {
+        insert,
+        get: module.funcs.by_name("externref_get"),
+        drop: module.funcs.by_name("externref_drop"),
+    };
+    // With per-kind tables, each partition has a dedicated insert function.
+    let is_partition_insert = |name: &str| {
+        name.strip_prefix("externref_insert_")
+            .is_some_and(|partition| partition.parse::<usize>().is_ok())
+    };
+    let partition_count = module
+        .funcs
+        .iter()
+        .filter(|function| function.name.as_deref().is_some_and(is_partition_insert))
+        .count()
+        .max(1);
+
+    for seed in 0..CHECKED_SEQUENCES {
+        check_table_fns(module, fns, partition_count, seed);
+    }
+}
+
+fn check_table_fns(module: &Module, fns: TableFns, partition_count: usize, seed: u64) {
+    let mut interpreter = Interpreter::new(module);
+    let mut model = RefTableModel::new(partition_count);
+    let mut rng = XorShift::new(seed);
+    let mut next_ref = 0_u32;
+
+    for op_idx in 0..OPS_PER_SEQUENCE {
+        let context = || format!("seed {seed}, operation #{op_idx}");
+        let live_ids = model.live_ids();
+
+        match rng.next_below(8) {
+            0..=3 => {
+                let value = (rng.next_below(8) != 0).then(|| {
+                    next_ref += 1;
+                    next_ref
+                });
+                // Includes -1 (unknown kind) and an out-of-range kind.
+                #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+                let kind = rng.next_below(partition_count + 1) as i32 - 1;
+                let expected_id = model.insert(value, kind);
+                let args = [Val::ExternRef(value), Val::I32(kind)];
+                let id = call_fn(&mut interpreter, fns.insert, &args, context);
+                assert_eq!(
+                    id,
+                    [Val::I32(expected_id)],
+                    "{}: inserting {value:?} with kind {kind}",
+                    context()
+                );
+            }
+            4..=6 if !live_ids.is_empty() => {
+                let Some(drop) = fns.drop else { continue };
+                let id = live_ids[rng.next_below(live_ids.len())];
+                let value = model.remove(id);
+                interpreter.take_import_calls();
+                call_fn(&mut interpreter, drop, &[Val::I32(id)], context);
+                for hook_call in interpreter.take_import_calls() {
+                    assert_eq!(
+                        hook_call.args.first(),
+                        Some(&Val::ExternRef(value)),
+                        "{}: drop hook called with unexpected args when dropping {id}",
+                        context()
+                    );
+                }
+            }
+            _ => {
+                let Some(get) = fns.get else { continue };
+                let null_ref = call_fn(&mut interpreter, get, &[Val::I32(-1)], context);
+                assert_eq!(null_ref, [Val::ExternRef(None)], "{}: get(-1)", context());
+                for id in live_ids {
+                    let expected = model.get(id).copied();
+                    let value = call_fn(&mut interpreter, get, &[Val::I32(id)], context);
+                    assert_eq!(
+                        value,
+                        [Val::ExternRef(expected)],
+                        "{}: get({id})",
+                        context()
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn call_fn(
+    interpreter: &mut Interpreter<'_>,
+    fn_id: FunctionId,
+    args: &[Val],
+    context: impl Fn() -> String,
+) -> Vec<Val> {
+    interpreter
+        .call(fn_id, args)
+        .unwrap_or_else(|trap| panic!("{}: trap: {}", context(), trap.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{DropSlotValue, Partitioning, Processor};
+
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut rng = XorShift::new(seed);
+        (0..len).map(|_| rng.next().to_le_bytes()[0]).collect()
+    }
+
+    #[test]
+    fn processing_arbitrary_modules() {
+        let mut processed_count = 0;
+        for seed in 0..500 {
+            let data = random_bytes(seed, 512);
+            let module = ArbitraryModule::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            wasmparser::validate(module.bytes()).unwrap();
+
+            for partitioning in [Partitioning::Shared, Partitioning::PerKind] {
+                let result = Processor::default()
+                    .set_table_partitioning(partitioning)
+                    .process_bytes(module.bytes());
+                if let Ok(processed) = result {
+                    wasmparser::validate(&processed).unwrap();
+                    processed_count += 1;
+                }
+            }
+        }
+        // Sanity check that the generated modules are not all rejected.
+        assert!(processed_count > 0);
+    }
+
+    #[test]
+    fn table_fns_match_reference_model() {
+        type Configure = fn(&mut Processor<'_>);
+        let configurations: [Configure; 5] = [
+            |_| {},
+            |processor| {
+                processor.set_drop_fn("hook", "drop_ref");
+            },
+            |processor| {
+                processor
+                    .set_drop_fn_with_kind("hook", "drop_ref")
+                    .enable_resource_metrics();
+            },
+            |processor| {
+                processor
+                    .set_drop_fn("hook", "drop_ref")
+                    .set_drop_slot_value(DropSlotValue::Tombstone {
+                        module: "hook",
+                        name: "tombstone",
+                    });
+            },
+            |processor| {
+                processor
+                    .set_table_partitioning(Partitioning::PerKind)
+                    .set_drop_fn_with_kind("hook", "drop_ref")
+                    .enable_resource_metrics();
+            },
+        ];
+
+        let mut checked_count = 0;
+        for seed in 0..100 {
+            let data = random_bytes(seed, 512);
+            let module = ArbitraryModule::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            for configure in configurations {
+                let mut processor = Processor::default();
+                configure(&mut processor);
+                let mut module = Module::from_buffer(module.bytes()).unwrap();
+                if processor.process(&mut module).is_ok() {
+                    if module.funcs.by_name("externref_insert").is_some() {
+                        checked_count += 1;
+                    }
+                    debug_assert_table_fn_correct(&module);
+                }
+            }
+        }
+        assert!(checked_count > 0);
+    }
+}
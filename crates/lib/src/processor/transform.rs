@@ -0,0 +1,119 @@
+//! Adapter allowing to use the processor as a generic transform of module bytes.
+
+use std::error;
+
+use super::Processor;
+
+/// Boxed error returned by a [`Transform`].
+pub type TransformError = Box<dyn error::Error + Send + Sync>;
+
+/// Transform of WASM module bytes, e.g. a step in a post-link pipeline of a build tool.
+///
+/// This trait allows to slot the [`Processor`] into pipelines that accept boxed transforms
+/// alongside other tools (e.g., instrumentation or stripping of custom sections). The trait
+/// is implemented for the [`Processor`] (delegating to [`Processor::process_bytes()`])
+/// and for closures with a compatible signature.
+///
+/// Transforms are object-safe, so a pipeline can be registered as a `Vec<Box<dyn Transform>>`.
+/// Since the processor must run before optimization tools such as `wasm-opt`
+/// (see [processing order](super#on-processing-order)), it should be registered
+/// before such tools in the pipeline.
+///
+/// # Examples
+///
+/// ```
+/// use externref::processor::{Processor, Transform, TransformError};
+///
+/// fn run_pipeline(
+///     transforms: &[Box<dyn Transform + '_>],
+///     module: &[u8],
+/// ) -> Result<Vec<u8>, TransformError> {
+///     transforms
+///         .iter()
+///         .try_fold(module.to_vec(), |module, transform| transform.transform(&module))
+/// }
+///
+/// let pipeline: Vec<Box<dyn Transform>> = vec![
+///     Box::new(Processor::default()),
+///     // Closures can be registered as transforms as well.
+///     Box::new(|module: &[u8]| {
+///         let mut module = walrus::Module::from_buffer(module)?;
+///         module.producers.add_processed_by("my-pipeline", "0.1.0");
+///         Ok::<_, anyhow::Error>(module.emit_wasm())
+///     }),
+/// ];
+///
+/// let module: Vec<u8> = // WASM module, e.g., loaded from the file system
+/// #    b"\0asm\x01\0\0\0".to_vec();
+/// let processed = run_pipeline(&pipeline, &module)?;
+/// # Ok::<_, TransformError>(())
+/// ```
+pub trait Transform {
+    /// Transforms the provided WASM module.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module cannot be transformed.
+    fn transform(&self, module: &[u8]) -> Result<Vec<u8>, TransformError>;
+}
+
+impl Transform for Processor<'_> {
+    fn transform(&self, module: &[u8]) -> Result<Vec<u8>, TransformError> {
+        self.process_bytes(module).map_err(Into::into)
+    }
+}
+
+impl<F, E> Transform for F
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, E>,
+    E: Into<TransformError>,
+{
+    fn transform(&self, module: &[u8]) -> Result<Vec<u8>, TransformError> {
+        self(module).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::Error;
+
+    const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn processor_as_transform() {
+        let transform: Box<dyn Transform> = Box::new(Processor::default());
+        let processed = transform.transform(EMPTY_MODULE).unwrap();
+        let module = walrus::Module::from_buffer(&processed).unwrap();
+        assert!(module
+            .customs
+            .iter()
+            .any(|(_, section)| section.name() == Processor::MARKER_SECTION_NAME));
+
+        let err = transform.transform(&processed).unwrap_err();
+        let err = err.downcast::<Error>().unwrap();
+        assert!(matches!(*err, Error::AlreadyProcessed { .. }), "{err}");
+    }
+
+    #[test]
+    fn closure_as_transform() {
+        let transform = |module: &[u8]| {
+            if module.is_empty() {
+                Err("empty module")
+            } else {
+                Ok(module.to_vec())
+            }
+        };
+        let pipeline: [&dyn Transform; 2] = [&Processor::default(), &transform];
+        let processed = pipeline
+            .iter()
+            .try_fold(EMPTY_MODULE.to_vec(), |module, transform| {
+                transform.transform(&module)
+            })
+            .unwrap();
+        assert!(processed.starts_with(b"\0asm"));
+
+        let err = transform.transform(&[]).unwrap_err();
+        assert_eq!(err.to_string(), "empty module");
+    }
+}
@@ -0,0 +1,109 @@
+//! `serde` integration for [`HostHandle`]s and [`ResourceId`]s.
+
+use core::{fmt, marker::PhantomData};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{HostHandle, ResourceId};
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "externref_serde")]
+extern "C" {
+    #[link_name = "validate_handle"]
+    fn validate_handle(index: usize) -> i32;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn validate_handle(_index: usize) -> i32 {
+    1
+}
+
+/// Serializes the handle as its index in the `externref`s table.
+impl<T> Serialize for HostHandle<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.index.serialize(serializer)
+    }
+}
+
+/// Deserializes the handle from its index in the `externref`s table. The index is validated
+/// by the host via the `validate_handle` import from the [`HostHandle::SERDE_MODULE_NAME`] module.
+/// Indices that can never be valid (`usize::MAX - 1` and `usize::MAX`) are rejected
+/// without calling the host.
+impl<'de, T> Deserialize<'de> for HostHandle<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IndexVisitor;
+
+        impl de::Visitor<'_> for IndexVisitor {
+            type Value = usize;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("index in the `externref`s table")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                usize::try_from(value)
+                    .ok()
+                    // `usize::MAX` and `usize::MAX - 1` are never valid table indices,
+                    // and cannot be converted to resources.
+                    .filter(|&index| index < usize::MAX - 1)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+            }
+        }
+
+        let index = deserializer.deserialize_u64(IndexVisitor)?;
+        if unsafe { validate_handle(index) } == 0 {
+            let msg = "handle is not valid (e.g., refers to a null slot in the `externref`s table)";
+            return Err(de::Error::custom(msg));
+        }
+        Ok(Self {
+            index,
+            _ty: PhantomData,
+        })
+    }
+}
+
+/// Serializes the identity as its index in the `externref`s table.
+impl Serialize for ResourceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.index().serialize(serializer)
+    }
+}
+
+/// Deserializes the identity from its index in the `externref`s table. Unlike with handles,
+/// the index is not validated.
+impl<'de> Deserialize<'de> for ResourceId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        usize::deserialize(deserializer).map(Self::from_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::format;
+
+    #[test]
+    fn handle_serialization() {
+        let handle = unsafe { HostHandle::<()>::from_index(5) };
+        let json = serde_json::to_string(&handle).unwrap();
+        assert_eq!(json, "5");
+
+        let restored: HostHandle<()> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.index(), 5);
+
+        let err = serde_json::from_str::<HostHandle<()>>("-1").unwrap_err();
+        assert!(format!("{err}").contains("expected index"), "{err}");
+        let max_index = format!("{}", usize::MAX - 1);
+        let err = serde_json::from_str::<HostHandle<()>>(&max_index).unwrap_err();
+        assert!(format!("{err}").contains("expected index"), "{err}");
+    }
+
+    #[test]
+    fn resource_id_serialization() {
+        let id = ResourceId::from_index(3);
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, "3");
+        let restored: ResourceId = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, id);
+    }
+}
@@ -0,0 +1,111 @@
+//! Resources shared among module threads via the host.
+
+use core::{fmt, marker::PhantomData};
+
+use crate::Resource;
+
+#[cfg(target_arch = "wasm32")]
+__surrogate_imports! {
+    #[link_name = "share"]
+    fn share_ref(id: usize) -> usize;
+    #[link_name = "get_shared"]
+    fn get_shared_ref(handle: usize) -> usize;
+    #[link_name = "drop_shared"]
+    fn drop_shared_ref(handle: usize);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn share_ref(id: usize) -> usize {
+    id
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn get_shared_ref(handle: usize) -> usize {
+    handle
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn drop_shared_ref(_handle: usize) {
+    // Do nothing
+}
+
+/// [`Resource`] that can be sent to and shared among threads of the module
+/// (e.g., ones created under the WASM threads proposal).
+///
+/// Tables are not shared among threads, so a `Resource` is only valid in the thread
+/// that created it. In contrast, a `SharedResource` is a handle to the reference stored
+/// by the host, which is obtained via an import from the `"externref_shared"` module
+/// with the `(externref) -> i32` signature named `"share"`. A handle can be converted back
+/// to a thread-local `Resource` via the `(i32) -> externref` import named `"get"`,
+/// and is released via the `(i32) -> ()` import named `"drop"` when the `SharedResource`
+/// is dropped. Since all shared state is managed by the host, the host must synchronize
+/// access to it (see `host::SharedRegistry` for a possible implementation).
+///
+/// Similar to [`ResourceTable`](crate::ResourceTable), host imports are added by the processor
+/// only if shared resources are used by the module. Like other imports, they can be renamed
+/// during processing via `Processor::rename_import()`.
+///
+/// Resources obtained from a `SharedResource` have an unknown resource kind. If the host
+/// returns a null reference, the obtained resource is [null](Resource::null()).
+///
+/// # Examples
+///
+/// ```
+/// # use externref::{Resource, SharedResource};
+/// pub struct Sender(());
+///
+/// fn share_sender(sender: &Resource<Sender>) -> SharedResource<Sender> {
+///     SharedResource::new(sender)
+/// }
+///
+/// // Can be called from another thread.
+/// fn use_sender(shared: &SharedResource<Sender>) {
+///     let sender: Resource<Sender> = shared.to_resource();
+///     // Use `sender`...
+/// }
+/// ```
+pub struct SharedResource<T> {
+    handle: usize,
+    _ty: PhantomData<fn(T)>,
+}
+
+impl<T> fmt::Debug for SharedResource<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_tuple("SharedResource")
+            .field(&self.handle)
+            .finish()
+    }
+}
+
+impl<T> SharedResource<T> {
+    /// Name of the module with host imports used by shared resources.
+    pub const MODULE_NAME: &'static str = "externref_shared";
+
+    /// Shares the provided `resource` with the host. The resource itself remains usable
+    /// and should be dropped as usual.
+    pub fn new(resource: &Resource<T>) -> Self {
+        Self {
+            handle: unsafe { share_ref(resource.checked_id()) },
+            _ty: PhantomData,
+        }
+    }
+
+    /// Returns the host handle of this resource.
+    pub fn handle(&self) -> usize {
+        self.handle
+    }
+
+    /// Obtains a resource usable in the current thread.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn to_resource(&self) -> Resource<T> {
+        Resource::from_id(unsafe { get_shared_ref(self.handle) })
+    }
+}
+
+/// Releases the reference stored by the host.
+impl<T> Drop for SharedResource<T> {
+    fn drop(&mut self) {
+        unsafe { drop_shared_ref(self.handle) };
+    }
+}
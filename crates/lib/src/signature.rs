@@ -3,20 +3,35 @@
 use core::str;
 
 use crate::{
-    alloc::{format, String},
+    alloc::{format, vec, String, Vec},
     error::{ReadError, ReadErrorKind},
 };
 
-/// Builder for [`BitSlice`]s that can be used in const contexts.
-#[doc(hidden)] // used by macro; not public (yet?)
+macro_rules! write_u32 {
+    ($buffer:ident, $value:expr, $pos:expr) => {{
+        let value: u32 = $value;
+        let pos: usize = $pos;
+        $buffer[pos] = (value & 0xff) as u8;
+        $buffer[pos + 1] = ((value >> 8) & 0xff) as u8;
+        $buffer[pos + 2] = ((value >> 16) & 0xff) as u8;
+        $buffer[pos + 3] = ((value >> 24) & 0xff) as u8;
+    }};
+}
+
+/// Builder for [`BitSlice`]s that can be used in const contexts. Created via
+/// [`BitSlice::builder()`].
 #[derive(Debug)]
 pub struct BitSliceBuilder<const BYTES: usize> {
     bytes: [u8; BYTES],
     bit_len: usize,
 }
 
-#[doc(hidden)] // not public yet
 impl<const BYTES: usize> BitSliceBuilder<BYTES> {
+    /// Sets the bit with the specified index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
     #[must_use]
     pub const fn with_set_bit(mut self, bit_idx: usize) -> Self {
         assert!(bit_idx < self.bit_len);
@@ -24,6 +39,7 @@ impl<const BYTES: usize> BitSliceBuilder<BYTES> {
         self
     }
 
+    /// Builds a slice borrowing from this builder.
     pub const fn build(&self) -> BitSlice<'_> {
         BitSlice {
             bytes: &self.bytes,
@@ -32,19 +48,105 @@ impl<const BYTES: usize> BitSliceBuilder<BYTES> {
     }
 }
 
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_len(buffer: &mut Vec<u8>, len: usize) {
+    let len = u32::try_from(len).expect("length does not fit into `u32`");
+    push_u32(buffer, len);
+}
+
+/// Owned [`BitSlice`] that can be constructed at runtime, e.g. by code generation tools
+/// emitting [`Function`] declarations.
+///
+/// # Examples
+///
+/// ```
+/// # use externref::{BitSlice, BitSliceBuf, Function, FunctionKind, TypeNames};
+/// // Mark the first and the last arg of a function with 3 args.
+/// let mut externrefs = BitSliceBuf::new(3);
+/// externrefs.set(0).set(2);
+/// let function = Function {
+///     kind: FunctionKind::Import("arena"),
+///     name: "alloc",
+///     externrefs: externrefs.as_slice(),
+///     host_handles: BitSlice::EMPTY,
+///     type_names: TypeNames::EMPTY,
+///     surrogate_module: None,
+/// };
+///
+/// let mut section = vec![];
+/// function.write_to_vec(&mut section);
+/// let restored = Function::read_from_section(&mut section.as_slice())?;
+/// assert_eq!(restored, function);
+/// # Ok::<_, externref::ReadError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitSliceBuf {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitSliceBuf {
+    /// Creates a slice with the specified number of bits, all of which are unset.
+    pub fn new(bit_len: usize) -> Self {
+        Self {
+            bytes: vec![0; bit_len.div_ceil(8)],
+            bit_len,
+        }
+    }
+
+    /// Sets a bit with the specified 0-based index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is out of bounds.
+    pub fn set(&mut self, idx: usize) -> &mut Self {
+        assert!(
+            idx < self.bit_len,
+            "bit index {idx} is out of bounds (bit length: {})",
+            self.bit_len
+        );
+        self.bytes[idx / 8] |= 1 << (idx % 8);
+        self
+    }
+
+    /// Borrows this buffer as a [`BitSlice`].
+    pub fn as_slice(&self) -> BitSlice<'_> {
+        BitSlice {
+            bytes: &self.bytes,
+            bit_len: self.bit_len,
+        }
+    }
+}
+
+impl From<BitSlice<'_>> for BitSliceBuf {
+    fn from(slice: BitSlice<'_>) -> Self {
+        Self {
+            bytes: slice.bytes.to_vec(),
+            bit_len: slice.bit_len,
+        }
+    }
+}
+
 /// Slice of bits. This type is used to mark [`Resource`](crate::Resource) args
 /// in imported / exported functions.
 // Why invent a new type? Turns out that existing implementations (e.g., `bv` and `bitvec`)
 // cannot be used in const contexts.
-#[derive(Debug, Clone, Copy)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BitSlice<'a> {
     bytes: &'a [u8],
     bit_len: usize,
 }
 
 impl BitSlice<'static> {
-    #[doc(hidden)]
+    /// Creates a builder for a slice with `bit_len` bits, all of which are initially unset.
+    /// `BYTES` must be the minimum number of bytes fitting `bit_len` bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `BYTES` does not correspond to `bit_len`.
     pub const fn builder<const BYTES: usize>(bit_len: usize) -> BitSliceBuilder<BYTES> {
         assert!(BYTES > 0);
         assert!(bit_len > (BYTES - 1) * 8 && bit_len <= BYTES * 8);
@@ -56,18 +158,25 @@ impl BitSlice<'static> {
 }
 
 impl<'a> BitSlice<'a> {
+    /// Empty bit slice.
+    pub const EMPTY: Self = Self {
+        bytes: &[],
+        bit_len: 0,
+    };
+
     /// Returns the number of bits in this slice.
     pub fn bit_len(&self) -> usize {
         self.bit_len
     }
 
-    /// Checks if a bit with the specified 0-based index is set.
+    /// Checks if a bit with the specified 0-based index is set. Returns `false` if the index
+    /// is out of bounds.
     pub fn is_set(&self, idx: usize) -> bool {
-        if idx > self.bit_len {
+        if idx >= self.bit_len {
             return false;
         }
         let mask = 1 << (idx % 8);
-        self.bytes[idx / 8] & mask > 0
+        self.bytes.get(idx / 8).is_some_and(|byte| byte & mask > 0)
     }
 
     /// Iterates over the indexes of set bits in this slice.
@@ -77,71 +186,227 @@ impl<'a> BitSlice<'a> {
 
     /// Returns the number of set bits in this slice.
     pub fn count_ones(&self) -> usize {
-        let ones: u32 = self.bytes.iter().copied().map(u8::count_ones).sum();
-        ones as usize
+        let Some((last, full_bytes)) = self.bytes.split_last() else {
+            return 0;
+        };
+        let ones: u32 = full_bytes.iter().copied().map(u8::count_ones).sum();
+        // Padding bits in the last byte must not be counted; they may be set
+        // in a malformed custom section.
+        let padding_bits = self.bytes.len() * 8 - self.bit_len;
+        let last_ones = (last << padding_bits).count_ones();
+        (ones + last_ones) as usize
     }
 
-    fn read_from_section(buffer: &mut &'a [u8], context: &str) -> Result<Self, ReadError> {
-        let bit_len = read_u32(buffer, || format!("length for {context}"))? as usize;
-        let byte_len = (bit_len + 7) / 8;
-        if buffer.len() < byte_len {
-            Err(ReadErrorKind::UnexpectedEof.with_context(context))
-        } else {
-            let bytes = &buffer[..byte_len];
-            *buffer = &buffer[byte_len..];
-            Ok(Self { bytes, bit_len })
+    #[allow(clippy::cast_possible_truncation)] // `TryFrom` cannot be used in const fns
+    const fn write_to_custom_section<const N: usize>(
+        &self,
+        mut buffer: [u8; N],
+        mut pos: usize,
+    ) -> ([u8; N], usize) {
+        write_u32!(buffer, self.bit_len as u32, pos);
+        pos += 4;
+        let mut i = 0;
+        while i < self.bytes.len() {
+            buffer[pos] = self.bytes[i];
+            i += 1;
+            pos += 1;
         }
+        (buffer, pos)
+    }
+
+    fn write_to_vec(&self, buffer: &mut Vec<u8>) {
+        push_len(buffer, self.bit_len);
+        buffer.extend_from_slice(self.bytes);
+    }
+
+    fn read_from_section(buffer: &mut &'a [u8], context: &str) -> Result<Self, ReadError> {
+        let bit_len = read_len(buffer, MAX_BIT_LEN, context)?;
+        let bytes = take(buffer, bit_len.div_ceil(8), context)?;
+        Ok(Self { bytes, bit_len })
     }
 }
 
-macro_rules! write_u32 {
-    ($buffer:ident, $value:expr, $pos:expr) => {{
-        let value: u32 = $value;
-        let pos: usize = $pos;
-        $buffer[pos] = (value & 0xff) as u8;
-        $buffer[pos + 1] = ((value >> 8) & 0xff) as u8;
-        $buffer[pos + 2] = ((value >> 16) & 0xff) as u8;
-        $buffer[pos + 3] = ((value >> 24) & 0xff) as u8;
-    }};
+#[allow(clippy::cast_possible_truncation)] // `TryFrom` cannot be used in const fns
+const fn write_str_to_custom_section<const N: usize>(
+    mut buffer: [u8; N],
+    s: &str,
+    mut pos: usize,
+) -> ([u8; N], usize) {
+    write_u32!(buffer, s.len() as u32, pos);
+    pos += 4;
+    let mut i = 0;
+    while i < s.len() {
+        buffer[pos] = s.as_bytes()[i];
+        i += 1;
+        pos += 1;
+    }
+    (buffer, pos)
+}
+
+/// Maximum supported byte length of strings in custom section entries. Matches the limit
+/// on name lengths enforced by `wasmparser`.
+const MAX_STR_LEN: usize = 100_000;
+/// Maximum supported bit length of [`BitSlice`]s in custom section entries. WASM functions
+/// can have at most 1,000 params and 1,000 results.
+const MAX_BIT_LEN: usize = 2_000;
+
+/// Splits off the first `len` bytes from the `buffer`.
+fn take<'a>(buffer: &mut &'a [u8], len: usize, context: &str) -> Result<&'a [u8], ReadError> {
+    if buffer.len() < len {
+        return Err(ReadErrorKind::UnexpectedEof.with_context(context));
+    }
+    let (head, tail) = buffer.split_at(len);
+    *buffer = tail;
+    Ok(head)
 }
 
 fn read_u32(buffer: &mut &[u8], context: impl FnOnce() -> String) -> Result<u32, ReadError> {
     if buffer.len() < 4 {
-        Err(ReadErrorKind::UnexpectedEof.with_context(context()))
-    } else {
-        let value = u32::from_le_bytes(buffer[..4].try_into().unwrap());
-        *buffer = &buffer[4..];
-        Ok(value)
+        return Err(ReadErrorKind::UnexpectedEof.with_context(context()));
+    }
+    let (head, tail) = buffer.split_at(4);
+    *buffer = tail;
+    Ok(u32::from_le_bytes([head[0], head[1], head[2], head[3]]))
+}
+
+/// Reads a length prefix for the `context`, checking that it does not exceed `max`.
+fn read_len(buffer: &mut &[u8], max: usize, context: &str) -> Result<usize, ReadError> {
+    let len = read_u32(buffer, || format!("length for {context}"))?;
+    match usize::try_from(len) {
+        Ok(len) if len <= max => Ok(len),
+        _ => Err(ReadErrorKind::OversizedLength { len, max }
+            .with_context(format!("length for {context}"))),
     }
 }
 
 fn read_str<'a>(buffer: &mut &'a [u8], context: &str) -> Result<&'a str, ReadError> {
-    let len = read_u32(buffer, || format!("length for {context}"))? as usize;
-    if buffer.len() < len {
-        Err(ReadErrorKind::UnexpectedEof.with_context(context))
-    } else {
-        let string = str::from_utf8(&buffer[..len])
-            .map_err(|err| ReadErrorKind::Utf8(err).with_context(context))?;
-        *buffer = &buffer[len..];
-        Ok(string)
+    let len = read_len(buffer, MAX_STR_LEN, context)?;
+    let bytes = take(buffer, len, context)?;
+    str::from_utf8(bytes).map_err(|err| ReadErrorKind::Utf8(err).with_context(context))
+}
+
+/// Rust type names of [`Resource`](crate::Resource)s in a function signature, e.g. `Sender`
+/// for `Resource<Sender>`. Names are specified for each set bit in [`Function::externrefs`]
+/// in the increasing bit order.
+///
+/// Type names are only recorded if requested via `#[externref(type_names)]`; otherwise,
+/// the names are [empty](Self::EMPTY).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeNames<'a> {
+    /// Names separated by `\n`.
+    raw: &'a str,
+}
+
+impl<'a> TypeNames<'a> {
+    /// Empty type names.
+    pub const EMPTY: Self = Self { raw: "" };
+
+    #[doc(hidden)] // used by macro; names are separated by `\n`
+    pub const fn from_raw(raw: &'a str) -> Self {
+        Self { raw }
+    }
+
+    /// Checks whether type names are empty (i.e., were not recorded).
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Iterates over the type names.
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> + '_ {
+        let raw = if self.raw.is_empty() {
+            None
+        } else {
+            Some(self.raw)
+        };
+        raw.into_iter().flat_map(|raw| raw.split('\n'))
+    }
+
+    /// Returns the type name with the specified 0-based index, i.e., the name for
+    /// the `idx`th set bit in [`Function::externrefs`].
+    pub fn get(&self, idx: usize) -> Option<&'a str> {
+        self.iter().nth(idx)
+    }
+
+    const fn len_in_custom_section(&self) -> usize {
+        4 + self.raw.len()
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // `TryFrom` cannot be used in const fns
+    const fn write_to_custom_section<const N: usize>(
+        &self,
+        mut buffer: [u8; N],
+        mut pos: usize,
+    ) -> ([u8; N], usize) {
+        write_u32!(buffer, self.raw.len() as u32, pos);
+        pos += 4;
+        let mut i = 0;
+        while i < self.raw.len() {
+            buffer[pos] = self.raw.as_bytes()[i];
+            i += 1;
+            pos += 1;
+        }
+        (buffer, pos)
+    }
+
+    fn write_to_vec(&self, buffer: &mut Vec<u8>) {
+        push_len(buffer, self.raw.len());
+        buffer.extend_from_slice(self.raw.as_bytes());
     }
 }
 
 /// Kind of a function with [`Resource`](crate::Resource) args or return type.
-#[derive(Debug)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FunctionKind<'a> {
     /// Function exported from a WASM module.
     Export,
     /// Function imported to a WASM module from the module with the enclosed name.
     Import(&'a str),
+    /// Local function placed in a function table of a WASM module, e.g. a callback invoked
+    /// via a function pointer (i.e., using `call_indirect`). The function is resolved by its name
+    /// from the `name` custom section of the module or, if it is not found there, by its export
+    /// name. The latter is the case for functions declared
+    /// via `#[externref(indirect_export)]`, which are exported under the declared name.
+    ///
+    /// Declarations of this kind cannot be read by older versions of the processor.
+    TableEntry,
+    /// Exported function without args and return values that should run as the start function
+    /// of a WASM module, e.g. to initialize resources obtained from the host. The processor
+    /// sets the function as the module start function and removes its export.
+    ///
+    /// Declarations of this kind cannot be read by older versions of the processor.
+    Start,
+    /// Host import re-exported by a WASM module under [`Function::name`], e.g. to let
+    /// other modules linked by the host call it directly. The processor patches the signature
+    /// of the import and exports it; if the export name is already taken by a local function
+    /// (e.g., a shim calling the import), the export is redirected to a trampoline calling
+    /// the patched import.
+    ///
+    /// Declarations of this kind cannot be read by older versions of the processor.
+    Reexport {
+        /// Name of the module the function is imported from.
+        module: &'a str,
+        /// Name of the imported function.
+        import_name: &'a str,
+    },
 }
 
 impl<'a> FunctionKind<'a> {
+    /// Marker for table entries. Unlike the export marker, it is only written in versioned
+    /// entries, so it cannot be confused with the envelope marker of the entry.
+    const TABLE_ENTRY_MARKER: u32 = u32::MAX - 2;
+    /// Marker for start functions; similar to [`Self::TABLE_ENTRY_MARKER`].
+    const START_MARKER: u32 = u32::MAX - 3;
+    /// Marker for re-exported imports; similar to [`Self::TABLE_ENTRY_MARKER`].
+    const REEXPORT_MARKER: u32 = u32::MAX - 4;
+
     const fn len_in_custom_section(&self) -> usize {
         match self {
-            Self::Export => 4,
+            Self::Export | Self::TableEntry | Self::Start => 4,
             Self::Import(module_name) => 4 + module_name.len(),
+            Self::Reexport {
+                module,
+                import_name,
+            } => 12 + module.len() + import_name.len(),
         }
     }
 
@@ -149,21 +414,60 @@ impl<'a> FunctionKind<'a> {
     const fn write_to_custom_section<const N: usize>(
         &self,
         mut buffer: [u8; N],
+        start: usize,
     ) -> ([u8; N], usize) {
         match self {
             Self::Export => {
-                write_u32!(buffer, u32::MAX, 0);
-                (buffer, 4)
+                write_u32!(buffer, u32::MAX, start);
+                (buffer, start + 4)
+            }
+            Self::TableEntry => {
+                write_u32!(buffer, Self::TABLE_ENTRY_MARKER, start);
+                (buffer, start + 4)
+            }
+            Self::Start => {
+                write_u32!(buffer, Self::START_MARKER, start);
+                (buffer, start + 4)
+            }
+            Self::Reexport {
+                module,
+                import_name,
+            } => {
+                write_u32!(buffer, Self::REEXPORT_MARKER, start);
+                let (buffer, pos) = write_str_to_custom_section(buffer, module, start + 4);
+                write_str_to_custom_section(buffer, import_name, pos)
             }
 
             Self::Import(module_name) => {
-                write_u32!(buffer, module_name.len() as u32, 0);
-                let mut pos = 4;
-                while pos - 4 < module_name.len() {
-                    buffer[pos] = module_name.as_bytes()[pos - 4];
-                    pos += 1;
+                write_u32!(buffer, module_name.len() as u32, start);
+                let mut i = 0;
+                while i < module_name.len() {
+                    buffer[start + 4 + i] = module_name.as_bytes()[i];
+                    i += 1;
                 }
-                (buffer, pos)
+                (buffer, start + 4 + module_name.len())
+            }
+        }
+    }
+
+    fn write_to_vec(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Self::Export => push_u32(buffer, u32::MAX),
+            Self::TableEntry => push_u32(buffer, Self::TABLE_ENTRY_MARKER),
+            Self::Start => push_u32(buffer, Self::START_MARKER),
+            Self::Reexport {
+                module,
+                import_name,
+            } => {
+                push_u32(buffer, Self::REEXPORT_MARKER);
+                push_len(buffer, module.len());
+                buffer.extend_from_slice(module.as_bytes());
+                push_len(buffer, import_name.len());
+                buffer.extend_from_slice(import_name.as_bytes());
+            }
+            Self::Import(module_name) => {
+                push_len(buffer, module_name.len());
+                buffer.extend_from_slice(module_name.as_bytes());
             }
         }
     }
@@ -172,6 +476,18 @@ impl<'a> FunctionKind<'a> {
         if buffer.len() >= 4 && buffer[..4] == [0xff; 4] {
             *buffer = &buffer[4..];
             Ok(Self::Export)
+        } else if buffer.len() >= 4 && buffer[..4] == Self::TABLE_ENTRY_MARKER.to_le_bytes() {
+            *buffer = &buffer[4..];
+            Ok(Self::TableEntry)
+        } else if buffer.len() >= 4 && buffer[..4] == Self::START_MARKER.to_le_bytes() {
+            *buffer = &buffer[4..];
+            Ok(Self::Start)
+        } else if buffer.len() >= 4 && buffer[..4] == Self::REEXPORT_MARKER.to_le_bytes() {
+            *buffer = &buffer[4..];
+            Ok(Self::Reexport {
+                module: read_str(buffer, "re-exported module name")?,
+                import_name: read_str(buffer, "re-exported import name")?,
+            })
         } else {
             let module_name = read_str(buffer, "module name")?;
             Ok(Self::Import(module_name))
@@ -185,35 +501,94 @@ impl<'a> FunctionKind<'a> {
 /// during module [post-processing].
 ///
 /// [post-processing]: crate::processor
-#[derive(Debug)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Function<'a> {
     /// Kind of this function.
     pub kind: FunctionKind<'a>,
     /// Name of this function.
     pub name: &'a str,
     /// Bit slice marking [`Resource`](crate::Resource) args / return type.
+    ///
+    /// For fallible exports (ones returning `Result<Resource<_>, _>`) and exports with
+    /// a flagged `Option<Resource<_>>` return type, the slice describes the signature
+    /// after processing, i.e., with the `(externref, i32)` return type.
     pub externrefs: BitSlice<'a>,
+    /// Bit slice marking [`HostHandle`](crate::HostHandle) args / return type, with the same
+    /// layout as [`Self::externrefs`]. Empty if the function has no host handles.
+    pub host_handles: BitSlice<'a>,
+    /// Rust type names of [`Resource`](crate::Resource)s corresponding to [`Self::externrefs`].
+    /// Empty unless recorded via `#[externref(type_names)]`.
+    pub type_names: TypeNames<'a>,
+    /// Module of the surrogate `guard` import called by the wrapper of an imported function,
+    /// if it differs from the [surrogate module](crate::SURROGATE_MODULE) of the crate. Set via
+    /// `#[externref(surrogate_module = "..")]`; the processor must be configured to look up
+    /// the guard in the same module (see `Processor::set_guard_import()` in the processor).
+    /// In the custom section, the default module is encoded as an empty string.
+    pub surrogate_module: Option<&'a str>,
 }
 
 impl<'a> Function<'a> {
     /// Name of a custom section in WASM modules where `Function` declarations are stored.
     /// `Function`s can be read from this section using [`Self::read_from_section()`].
+    ///
+    /// Declarations may be placed into another section with a name starting with this prefix
+    /// (e.g., `__externrefs_sdk`) via `#[externref(section = "..")]`, so that they can be
+    /// processed with different options; see `Processor::add_section()` in the processor.
     // **NB.** Keep synced with the `declare_function!()` macro below.
     pub const CUSTOM_SECTION_NAME: &'static str = "__externrefs";
 
+    /// Version of the custom section entry format written by this crate.
+    ///
+    /// Each entry is wrapped in an envelope consisting of a marker, the format version
+    /// and the length of the entry payload. Fields added to the payload within a version
+    /// are appended to its end, so that they are skipped by older readers.
+    /// [`Self::read_from_section()`] also supports legacy entries without an envelope.
+    pub const CUSTOM_SECTION_VERSION: u32 = 1;
+
+    /// Marker of a versioned entry. Legacy entries start with the module name length
+    /// or with `u32::MAX` for exports, so the marker cannot be confused with them.
+    const ENVELOPE_MARKER: u32 = u32::MAX - 1;
+    /// Envelope length: marker, version and payload length.
+    const ENVELOPE_LEN: usize = 12;
+
+    const fn payload_len(&self) -> usize {
+        self.kind.len_in_custom_section()
+            + 4
+            + self.name.len()
+            + 4
+            + self.externrefs.bytes.len()
+            + 4
+            + self.host_handles.bytes.len()
+            + self.type_names.len_in_custom_section()
+            + 4
+            + self.surrogate_module_str().len()
+    }
+
+    const fn surrogate_module_str(&self) -> &'a str {
+        match self.surrogate_module {
+            Some(module) => module,
+            None => "",
+        }
+    }
+
     /// Computes length of a custom section for this function signature.
     #[doc(hidden)]
     pub const fn custom_section_len(&self) -> usize {
-        self.kind.len_in_custom_section() + 4 + self.name.len() + 4 + self.externrefs.bytes.len()
+        Self::ENVELOPE_LEN + self.payload_len()
     }
 
     #[doc(hidden)]
     #[allow(clippy::cast_possible_truncation)] // `TryFrom` cannot be used in const fns
     pub const fn custom_section<const N: usize>(&self) -> [u8; N] {
         debug_assert!(N == self.custom_section_len());
-        let (mut buffer, mut pos) = self.kind.write_to_custom_section([0_u8; N]);
+        let mut buffer = [0_u8; N];
+        write_u32!(buffer, Self::ENVELOPE_MARKER, 0);
+        write_u32!(buffer, Self::CUSTOM_SECTION_VERSION, 4);
+        write_u32!(buffer, self.payload_len() as u32, 8);
 
+        let (mut buffer, mut pos) = self
+            .kind
+            .write_to_custom_section(buffer, Self::ENVELOPE_LEN);
         write_u32!(buffer, self.name.len() as u32, pos);
         pos += 4;
         let mut i = 0;
@@ -223,18 +598,46 @@ impl<'a> Function<'a> {
             i += 1;
         }
 
-        write_u32!(buffer, self.externrefs.bit_len as u32, pos);
+        let (buffer, pos) = self.externrefs.write_to_custom_section(buffer, pos);
+        let (buffer, pos) = self.host_handles.write_to_custom_section(buffer, pos);
+        let (mut buffer, mut pos) = self.type_names.write_to_custom_section(buffer, pos);
+        let surrogate_module = self.surrogate_module_str();
+        write_u32!(buffer, surrogate_module.len() as u32, pos);
         pos += 4;
         let mut i = 0;
-        while i < self.externrefs.bytes.len() {
-            buffer[pos] = self.externrefs.bytes[i];
-            i += 1;
+        while i < surrogate_module.len() {
+            buffer[pos] = surrogate_module.as_bytes()[i];
             pos += 1;
+            i += 1;
         }
-
         buffer
     }
 
+    /// Appends this declaration to the `buffer` in the same format as the declarations emitted
+    /// by the [`externref`](macro@crate::externref) macro. This allows emitting declarations
+    /// at runtime, e.g. by code generation tools. To be recognized by the [processor](crate::processor),
+    /// the declarations must be placed in the custom section named [`Self::CUSTOM_SECTION_NAME`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of a string or a bit slice in the declaration does not fit into `u32`.
+    pub fn write_to_vec(&self, buffer: &mut Vec<u8>) {
+        buffer.reserve(self.custom_section_len());
+        push_u32(buffer, Self::ENVELOPE_MARKER);
+        push_u32(buffer, Self::CUSTOM_SECTION_VERSION);
+        push_len(buffer, self.payload_len());
+
+        self.kind.write_to_vec(buffer);
+        push_len(buffer, self.name.len());
+        buffer.extend_from_slice(self.name.as_bytes());
+        self.externrefs.write_to_vec(buffer);
+        self.host_handles.write_to_vec(buffer);
+        self.type_names.write_to_vec(buffer);
+        let surrogate_module = self.surrogate_module_str();
+        push_len(buffer, surrogate_module.len());
+        buffer.extend_from_slice(surrogate_module.as_bytes());
+    }
+
     /// Reads function information from a WASM custom section. After reading, the `buffer`
     /// is advanced to trim the bytes consumed by the parser.
     ///
@@ -243,28 +646,73 @@ impl<'a> Function<'a> {
     ///
     /// # Errors
     ///
-    /// Returns an error if the custom section is malformed.
+    /// Returns an error if the custom section is malformed, or if the entry has a version
+    /// newer than [`Self::CUSTOM_SECTION_VERSION`].
     ///
     /// [`walrus`]: https://docs.rs/walrus/
     /// [`wasmparser`]: https://docs.rs/wasmparser/
     pub fn read_from_section(buffer: &mut &'a [u8]) -> Result<Self, ReadError> {
+        if buffer.len() < 4 || buffer[..4] != Self::ENVELOPE_MARKER.to_le_bytes() {
+            // Legacy entry without an envelope.
+            return Self::read_payload(buffer);
+        }
+        *buffer = &buffer[4..];
+
+        let version = read_u32(buffer, || "entry version".into())?;
+        if version != Self::CUSTOM_SECTION_VERSION {
+            return Err(ReadErrorKind::UnsupportedVersion(version).with_context("entry"));
+        }
+        let payload_len = read_u32(buffer, || "entry length".into())? as usize;
+        let mut payload = take(buffer, payload_len, "entry")?;
+        let mut function = Self::read_payload(&mut payload)?;
+        if !payload.is_empty() {
+            function.host_handles =
+                BitSlice::read_from_section(&mut payload, "host handle bit slice")?;
+        }
+        if !payload.is_empty() {
+            let raw = read_str(&mut payload, "resource type names")?;
+            function.type_names = TypeNames::from_raw(raw);
+        }
+        if !payload.is_empty() {
+            let module = read_str(&mut payload, "surrogate module")?;
+            function.surrogate_module = (!module.is_empty()).then_some(module);
+        }
+        // Any remaining payload bytes correspond to fields unknown to this reader.
+        Ok(function)
+    }
+
+    /// Reads the payload fields shared with the legacy format.
+    fn read_payload(buffer: &mut &'a [u8]) -> Result<Self, ReadError> {
         let kind = FunctionKind::read_from_section(buffer)?;
         Ok(Self {
             kind,
             name: read_str(buffer, "function name")?,
             externrefs: BitSlice::read_from_section(buffer, "externref bit slice")?,
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
         })
     }
 }
 
+/// Declares a [`Function`] by placing its serialized form into the custom section
+/// of the WASM module. Must be invoked for each imported / exported function with
+/// `externref`s handled without the [`externref`](macro@crate::externref) macro;
+/// see the [`wrappers`](crate::wrappers) module for details.
+///
+/// By default, the declaration is placed into the [`Function::CUSTOM_SECTION_NAME`] section.
+/// Another section can be specified as `declare_function!(function, section = "__externrefs_sdk")`;
+/// its name must start with [`Function::CUSTOM_SECTION_NAME`].
 #[macro_export]
-#[doc(hidden)]
 macro_rules! declare_function {
     ($signature:expr) => {
+        $crate::declare_function!($signature, section = "__externrefs");
+    };
+    ($signature:expr, section = $section:literal) => {
         const _: () = {
             const FUNCTION: $crate::Function = $signature;
 
-            #[cfg_attr(target_arch = "wasm32", link_section = "__externrefs")]
+            #[cfg_attr(target_arch = "wasm32", link_section = $section)]
             static DATA_SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
         };
     };
@@ -280,20 +728,167 @@ mod tests {
             kind: FunctionKind::Import("module"),
             name: "test",
             externrefs: BitSlice::builder::<1>(3).with_set_bit(1).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
         };
 
         const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
 
-        assert_eq!(SECTION[..4], [6, 0, 0, 0]); // little-endian module name length
-        assert_eq!(SECTION[4..10], *b"module");
-        assert_eq!(SECTION[10..14], [4, 0, 0, 0]); // little-endian fn name length
-        assert_eq!(SECTION[14..18], *b"test");
-        assert_eq!(SECTION[18..22], [3, 0, 0, 0]); // little-endian bit slice length
-        assert_eq!(SECTION[22], 2); // bit slice
+        assert_eq!(SECTION[..4], [0xfe, 0xff, 0xff, 0xff]); // envelope marker
+        assert_eq!(SECTION[4..8], [1, 0, 0, 0]); // little-endian version
+        assert_eq!(SECTION[8..12], [35, 0, 0, 0]); // little-endian payload length
+        let payload = &SECTION[12..];
+        assert_eq!(payload[..4], [6, 0, 0, 0]); // little-endian module name length
+        assert_eq!(payload[4..10], *b"module");
+        assert_eq!(payload[10..14], [4, 0, 0, 0]); // little-endian fn name length
+        assert_eq!(payload[14..18], *b"test");
+        assert_eq!(payload[18..22], [3, 0, 0, 0]); // little-endian bit slice length
+        assert_eq!(payload[22], 2); // bit slice
+        assert_eq!(payload[23..27], [0, 0, 0, 0]); // empty host handles bit slice
+        assert_eq!(payload[27..31], [0, 0, 0, 0]); // empty type names
+        assert_eq!(payload[31..], [0, 0, 0, 0]); // default surrogate module
+
+        let mut section_reader = &SECTION as &[u8];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(section_reader.is_empty());
+
+        // Check reading the legacy format without an envelope.
+        let mut section_reader = &payload[..23];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn function_with_host_handles_serialization() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Export,
+            name: "test",
+            externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+            host_handles: BitSlice::builder::<1>(2).with_set_bit(1).build(),
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let payload = &SECTION[12..];
+        assert_eq!(payload[12..16], [2, 0, 0, 0]); // little-endian bit slice length
+        assert_eq!(payload[16], 1); // externrefs bit slice
+        assert_eq!(payload[17..21], [2, 0, 0, 0]); // little-endian bit slice length
+        assert_eq!(payload[21], 2); // host handles bit slice
+
+        let mut section_reader = &SECTION as &[u8];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(!restored_function.host_handles.is_set(0));
+        assert!(restored_function.host_handles.is_set(1));
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn function_with_type_names_serialization() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Export,
+            name: "test",
+            externrefs: BitSlice::builder::<1>(3)
+                .with_set_bit(0)
+                .with_set_bit(2)
+                .build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::from_raw("Sender\nBytes"),
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let payload = &SECTION[12..];
+        assert_eq!(payload[17..21], [0, 0, 0, 0]); // empty host handles bit slice
+        assert_eq!(payload[21..25], [12, 0, 0, 0]); // little-endian type names length
+        assert_eq!(payload[25..37], *b"Sender\nBytes");
+        assert_eq!(payload[37..], [0, 0, 0, 0]); // default surrogate module
 
         let mut section_reader = &SECTION as &[u8];
         let restored_function = Function::read_from_section(&mut section_reader).unwrap();
         assert_eq!(restored_function, FUNCTION);
+        let type_names: Vec<_> = restored_function.type_names.iter().collect();
+        assert_eq!(type_names, ["Sender", "Bytes"]);
+        assert_eq!(restored_function.type_names.get(1), Some("Bytes"));
+        assert_eq!(restored_function.type_names.get(2), None);
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn function_with_surrogate_module_serialization() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Import("module"),
+            name: "test",
+            externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: Some("shim"),
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let payload_len = SECTION.len();
+        assert_eq!(SECTION[payload_len - 8..], *b"\x04\0\0\0shim");
+
+        let mut section_reader = &SECTION as &[u8];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(section_reader.is_empty());
+
+        let mut buffer = vec![];
+        FUNCTION.write_to_vec(&mut buffer);
+        assert_eq!(buffer, SECTION);
+    }
+
+    #[test]
+    fn reading_entry_with_unknown_fields() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Import("module"),
+            name: "test",
+            externrefs: BitSlice::builder::<1>(3).with_set_bit(1).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let mut section = SECTION.to_vec();
+        section[8] += 3; // increase the payload length
+        section.extend_from_slice(b"new");
+        section.extend_from_slice(&SECTION); // add another entry
+
+        let mut section_reader = section.as_slice();
+        for _ in 0..2 {
+            let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+            assert_eq!(restored_function, FUNCTION);
+        }
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn reading_entry_with_unsupported_version() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Export,
+            name: "test",
+            externrefs: BitSlice::builder::<1>(3).with_set_bit(1).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let mut section = SECTION;
+        section[4] = 2;
+        let err = Function::read_from_section(&mut section.as_slice()).unwrap_err();
+        assert!(
+            matches!(err.kind(), ReadErrorKind::UnsupportedVersion(2)),
+            "{err:?}"
+        );
+        let err = format!("{err}");
+        assert!(err.contains("unsupported version 2"), "{err}");
     }
 
     #[test]
@@ -302,16 +897,280 @@ mod tests {
             kind: FunctionKind::Export,
             name: "test",
             externrefs: BitSlice::builder::<1>(3).with_set_bit(1).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
         };
 
         const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
 
-        assert_eq!(SECTION[..4], [0xff, 0xff, 0xff, 0xff]);
-        assert_eq!(SECTION[4..8], [4, 0, 0, 0]); // little-endian fn name length
-        assert_eq!(SECTION[8..12], *b"test");
+        let payload = &SECTION[12..];
+        assert_eq!(payload[..4], [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(payload[4..8], [4, 0, 0, 0]); // little-endian fn name length
+        assert_eq!(payload[8..12], *b"test");
 
         let mut section_reader = &SECTION as &[u8];
         let restored_function = Function::read_from_section(&mut section_reader).unwrap();
         assert_eq!(restored_function, FUNCTION);
     }
+
+    #[test]
+    fn table_entry_fn_serialization() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::TableEntry,
+            name: "callback",
+            externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let payload = &SECTION[12..];
+        assert_eq!(payload[..4], [0xfd, 0xff, 0xff, 0xff]);
+        assert_eq!(payload[4..8], [8, 0, 0, 0]); // little-endian fn name length
+        assert_eq!(payload[8..16], *b"callback");
+
+        let mut section_reader = &SECTION as &[u8];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn start_fn_serialization() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Start,
+            name: "init",
+            externrefs: BitSlice::EMPTY,
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let payload = &SECTION[12..];
+        assert_eq!(payload[..4], [0xfc, 0xff, 0xff, 0xff]);
+        assert_eq!(payload[8..12], *b"init");
+
+        let mut section_reader = &SECTION as &[u8];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn reexport_fn_serialization() {
+        const FUNCTION: Function = Function {
+            kind: FunctionKind::Reexport {
+                module: "arena",
+                import_name: "alloc",
+            },
+            name: "alloc_bytes",
+            externrefs: BitSlice::builder::<1>(3).with_set_bit(0).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::EMPTY,
+            surrogate_module: None,
+        };
+        const SECTION: [u8; FUNCTION.custom_section_len()] = FUNCTION.custom_section();
+
+        let payload = &SECTION[12..];
+        assert_eq!(payload[..4], [0xfb, 0xff, 0xff, 0xff]);
+        assert_eq!(payload[4..8], [5, 0, 0, 0]); // little-endian module name length
+        assert_eq!(payload[8..13], *b"arena");
+        assert_eq!(payload[13..17], [5, 0, 0, 0]); // little-endian import name length
+        assert_eq!(payload[17..22], *b"alloc");
+        assert_eq!(payload[22..26], [11, 0, 0, 0]); // little-endian fn name length
+        assert_eq!(payload[26..37], *b"alloc_bytes");
+
+        let mut buffer = vec![];
+        FUNCTION.write_to_vec(&mut buffer);
+        assert_eq!(buffer, SECTION);
+
+        let mut section_reader = &SECTION as &[u8];
+        let restored_function = Function::read_from_section(&mut section_reader).unwrap();
+        assert_eq!(restored_function, FUNCTION);
+        assert!(section_reader.is_empty());
+    }
+
+    #[test]
+    fn building_bit_slices_at_runtime() {
+        let mut buf = BitSliceBuf::new(10);
+        buf.set(1).set(9);
+        let slice = buf.as_slice();
+        assert_eq!(slice.bit_len(), 10);
+        assert_eq!(slice.set_indices().collect::<Vec<_>>(), [1, 9]);
+        assert_eq!(
+            slice,
+            BitSlice::builder::<2>(10)
+                .with_set_bit(1)
+                .with_set_bit(9)
+                .build()
+        );
+        assert_eq!(BitSliceBuf::from(slice), buf);
+
+        let empty = BitSliceBuf::default();
+        assert_eq!(empty.as_slice(), BitSlice::EMPTY);
+    }
+
+    #[test]
+    #[should_panic(expected = "bit index 3 is out of bounds")]
+    fn setting_out_of_bounds_bit() {
+        BitSliceBuf::new(3).set(3);
+    }
+
+    #[test]
+    fn writing_functions_at_runtime() {
+        const FUNCTIONS: [Function; 3] = [
+            Function {
+                kind: FunctionKind::Import("module"),
+                name: "test",
+                externrefs: BitSlice::builder::<1>(3).with_set_bit(1).build(),
+                host_handles: BitSlice::EMPTY,
+                type_names: TypeNames::EMPTY,
+                surrogate_module: None,
+            },
+            Function {
+                kind: FunctionKind::Export,
+                name: "test",
+                externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+                host_handles: BitSlice::builder::<1>(2).with_set_bit(1).build(),
+                type_names: TypeNames::from_raw("Sender"),
+                surrogate_module: None,
+            },
+            Function {
+                kind: FunctionKind::TableEntry,
+                name: "callback",
+                externrefs: BitSlice::builder::<2>(9).with_set_bit(8).build(),
+                host_handles: BitSlice::EMPTY,
+                type_names: TypeNames::EMPTY,
+                surrogate_module: None,
+            },
+        ];
+        const SECTIONS: [&[u8]; 3] = [
+            &FUNCTIONS[0].custom_section::<{ FUNCTIONS[0].custom_section_len() }>(),
+            &FUNCTIONS[1].custom_section::<{ FUNCTIONS[1].custom_section_len() }>(),
+            &FUNCTIONS[2].custom_section::<{ FUNCTIONS[2].custom_section_len() }>(),
+        ];
+
+        let mut section = vec![];
+        for (function, expected) in FUNCTIONS.iter().zip(SECTIONS) {
+            let mut buffer = vec![];
+            function.write_to_vec(&mut buffer);
+            assert_eq!(buffer, expected);
+            section.extend_from_slice(&buffer);
+        }
+
+        let mut section_reader = section.as_slice();
+        for function in &FUNCTIONS {
+            let restored = Function::read_from_section(&mut section_reader).unwrap();
+            assert_eq!(restored, *function);
+        }
+        assert!(section_reader.is_empty());
+    }
+
+    fn envelope(payload: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![];
+        push_u32(&mut buffer, Function::ENVELOPE_MARKER);
+        push_u32(&mut buffer, Function::CUSTOM_SECTION_VERSION);
+        push_len(&mut buffer, payload.len());
+        buffer.extend_from_slice(payload);
+        buffer
+    }
+
+    #[test]
+    fn bit_slice_with_set_padding_bits() {
+        let slice = BitSlice {
+            bytes: &[0b1111_1101],
+            bit_len: 3,
+        };
+        assert_eq!(slice.count_ones(), 2);
+        assert_eq!(slice.set_indices().collect::<Vec<_>>(), [0, 2]);
+        assert!(!slice.is_set(3)); // the first padding bit
+        assert!(!slice.is_set(4));
+        assert!(!slice.is_set(usize::MAX));
+    }
+
+    // Regression cases for malformed inputs found via fuzzing (see the `fuzz` directory).
+    #[test]
+    fn reading_truncated_entries() {
+        let mut section = vec![];
+        Function {
+            kind: FunctionKind::Import("module"),
+            name: "test",
+            externrefs: BitSlice::builder::<2>(9).with_set_bit(8).build(),
+            host_handles: BitSlice::EMPTY,
+            type_names: TypeNames::from_raw("Sender"),
+            surrogate_module: None,
+        }
+        .write_to_vec(&mut section);
+
+        for len in 0..section.len() {
+            let mut reader = &section[..len];
+            let err = Function::read_from_section(&mut reader).unwrap_err();
+            assert!(
+                matches!(err.kind(), ReadErrorKind::UnexpectedEof),
+                "{len}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn reading_entry_with_huge_payload_length() {
+        let mut section = envelope(&[]);
+        section[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = Function::read_from_section(&mut section.as_slice()).unwrap_err();
+        assert!(matches!(err.kind(), ReadErrorKind::UnexpectedEof), "{err}");
+    }
+
+    #[test]
+    fn reading_oversized_string() {
+        for len in [100_001, u32::MAX - 5] {
+            let mut payload = vec![];
+            push_u32(&mut payload, len);
+            payload.extend_from_slice(b"module");
+            let err = Function::read_from_section(&mut envelope(&payload).as_slice()).unwrap_err();
+            assert!(
+                matches!(
+                    err.kind(),
+                    ReadErrorKind::OversizedLength { len: actual, max: MAX_STR_LEN }
+                        if *actual == len
+                ),
+                "{err}"
+            );
+            assert!(format!("{err}").contains("length for module name"), "{err}");
+        }
+    }
+
+    #[test]
+    fn reading_oversized_bit_slice() {
+        let mut payload = vec![];
+        FunctionKind::Export.write_to_vec(&mut payload);
+        push_len(&mut payload, 4);
+        payload.extend_from_slice(b"test");
+        push_u32(&mut payload, u32::MAX);
+        payload.extend_from_slice(&[0xff; 16]);
+
+        let err = Function::read_from_section(&mut envelope(&payload).as_slice()).unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                ReadErrorKind::OversizedLength {
+                    len: u32::MAX,
+                    max: MAX_BIT_LEN
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn reading_non_utf8_name() {
+        let mut payload = vec![];
+        FunctionKind::Export.write_to_vec(&mut payload);
+        push_len(&mut payload, 2);
+        payload.extend_from_slice(&[0xc3, 0x28]);
+        let err = Function::read_from_section(&mut envelope(&payload).as_slice()).unwrap_err();
+        assert!(matches!(err.kind(), ReadErrorKind::Utf8(_)), "{err}");
+    }
 }
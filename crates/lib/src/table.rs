@@ -0,0 +1,163 @@
+//! Resource tables exposing collections of resources to the host.
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::Resource;
+
+#[cfg(target_arch = "wasm32")]
+__surrogate_imports! {
+    #[link_name = "table_push"]
+    fn table_push(id: usize) -> usize;
+    #[link_name = "table_get"]
+    fn table_get(idx: usize) -> usize;
+    #[link_name = "table_len"]
+    fn table_len() -> usize;
+    #[link_name = "table_clear"]
+    fn table_clear();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn table_push(_id: usize) -> usize {
+    0
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn table_get(_idx: usize) -> usize {
+    usize::MAX
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn table_len() -> usize {
+    0
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe fn table_clear() {
+    // Do nothing
+}
+
+/// Is the resource table currently acquired?
+static IS_ACQUIRED: AtomicBool = AtomicBool::new(false);
+
+/// Collection of [`Resource`]s exposed to the host as a dedicated table with `externref` elements.
+///
+/// The [processor](crate::processor) adds the table to the module and exports it
+/// (by default, as `"resources"`). The table contains [`Self::len()`] pushed references
+/// followed by nulls; the host can read it directly, e.g., to get the entire collection
+/// without calling into the module for each element.
+///
+/// The table is unique per module and is shared by `ResourceTable`s for all resource types `T`,
+/// hence the only way to obtain a `ResourceTable` is via [`Self::acquire()`]; while a table
+/// for one type is acquired, tables for other types cannot be acquired either. Pushing a resource stores a copy of the reference
+/// in the table; the resource itself remains usable and should be dropped as usual.
+///
+/// # Examples
+///
+/// ```
+/// # use externref::{externref, Resource, ResourceTable};
+/// pub struct Bytes(());
+///
+/// #[externref]
+/// #[link(wasm_import_module = "test")]
+/// extern "C" {
+///     fn next_message() -> Option<Resource<Bytes>>;
+/// }
+///
+/// fn collect_messages() -> usize {
+///     let mut table = ResourceTable::<Bytes>::acquire().expect("table is already used");
+///     table.clear();
+///     while let Some(message) = unsafe { next_message() } {
+///         table.push(&message);
+///     }
+///     let len = table.len();
+///     // Retain the collected messages for the host.
+///     table.release();
+///     len
+/// }
+/// ```
+pub struct ResourceTable<T> {
+    _ty: PhantomData<fn(T)>,
+}
+
+impl<T> fmt::Debug for ResourceTable<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ResourceTable")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<T> ResourceTable<T> {
+    /// Acquires the resource table. Returns `None` if the table is already acquired
+    /// (i.e., another `ResourceTable` instance is alive).
+    ///
+    /// The table is not cleared on acquisition, so it may contain resources pushed
+    /// by a previous owner if it was [released](Self::release()) rather than dropped.
+    pub fn acquire() -> Option<Self> {
+        // `then_some()` is not used since it would construct and drop a table even if
+        // it is already acquired.
+        if IS_ACQUIRED.swap(true, Ordering::Acquire) {
+            None
+        } else {
+            Some(Self { _ty: PhantomData })
+        }
+    }
+
+    /// Pushes a copy of the `resource` reference to the end of this table
+    /// and returns its 0-based index.
+    pub fn push(&mut self, resource: &Resource<T>) -> usize {
+        unsafe { table_push(resource.checked_id()) }
+    }
+
+    /// Gets the resource with the specified 0-based index. Returns `None` if the index
+    /// is out of bounds or if the host has nulled the table element.
+    ///
+    /// The returned resource is a new copy of the reference in the table; dropping it
+    /// does not affect the table.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn get(&self, idx: usize) -> Option<Resource<T>> {
+        let id = unsafe { table_get(idx) };
+        if id == usize::MAX {
+            None
+        } else {
+            Some(Resource::from_id(id))
+        }
+    }
+
+    /// Returns the number of resources pushed to this table.
+    pub fn len(&self) -> usize {
+        unsafe { table_len() }
+    }
+
+    /// Checks whether this table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all resources from this table by nulling the table elements.
+    pub fn clear(&mut self) {
+        unsafe { table_clear() };
+    }
+
+    /// Releases this table without clearing it, so that the host can read pushed resources
+    /// and the table can be [acquired](Self::acquire()) again. Unlike dropping the table,
+    /// this retains its contents.
+    pub fn release(self) {
+        core::mem::forget(self);
+        IS_ACQUIRED.store(false, Ordering::Release);
+    }
+}
+
+/// Clears the table and releases it, so that it can be [acquired](ResourceTable::acquire())
+/// again.
+impl<T> Drop for ResourceTable<T> {
+    fn drop(&mut self) {
+        self.clear();
+        IS_ACQUIRED.store(false, Ordering::Release);
+    }
+}
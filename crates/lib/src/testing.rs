@@ -0,0 +1,601 @@
+//! Mock host for executing processed WASM modules in tests without implementing real imports.
+//!
+//! [`MockHost`] is runtime-agnostic: it describes all function imports of a processed module
+//! and handles calls to them, while the glue between the mock and a specific WASM runtime
+//! (e.g., defining imports in a `wasmtime::Linker` and mapping [`MockRef`]s to `externref`s)
+//! is left to the test code. The mock:
+//!
+//! - Provides default stubs for all imports (returning zeros and fresh references)
+//!   that can be overridden via [`MockHost::stub()`].
+//! - Records every reference passed across the module boundary.
+//! - Tracks references held by the module using the [drop hook](crate::processor::Processor::set_drop_fn())
+//!   and allows asserting on them via [`MockHost::assert_live_refs()`].
+//!
+//! # Examples
+//!
+//! ```
+//! use externref::{
+//!     processor::Processor,
+//!     testing::{MockHost, MockValue},
+//! };
+//!
+//! # fn test_wasm(module: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+//! let mut processor = Processor::default();
+//! processor.set_drop_fn("test", "drop_ref");
+//! let manifest = processor.manifest(module)?;
+//! let processed = processor.process_bytes(module)?;
+//!
+//! let mut host = MockHost::new(&processed, &manifest)?;
+//! host.stub("arena", "alloc", |refs, _args| {
+//!     vec![MockValue::Ref(Some(refs.create("Arena")))]
+//! });
+//! for import in host.imports() {
+//!     // Define the import in the WASM runtime, forwarding calls to `host.call_import()`.
+//!     println!("{}::{}: {:?} -> {:?}", import.module, import.name, import.params, import.results);
+//! }
+//! // Run the module, then check that it has released all references:
+//! host.assert_live_refs(0);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, error, fmt};
+
+use walrus::{ImportKind, Module, RefType, ValType};
+
+use crate::processor::{Error, Manifest};
+
+/// Reference created by a [`MockHost`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MockRef(usize);
+
+impl MockRef {
+    /// Returns the 0-based ID of this reference, which is unique within the [`MockHost`]
+    /// that created it.
+    pub fn id(self) -> usize {
+        self.0
+    }
+}
+
+/// Type of a value passed across the module boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MockValueType {
+    /// 32-bit integer.
+    I32,
+    /// 64-bit integer.
+    I64,
+    /// 32-bit float.
+    F32,
+    /// 64-bit float.
+    F64,
+    /// `externref`.
+    ExternRef,
+}
+
+impl fmt::Display for MockValueType {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(match self {
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::ExternRef => "externref",
+        })
+    }
+}
+
+impl MockValueType {
+    fn from_wasm(ty: ValType) -> Option<Self> {
+        Some(match ty {
+            ValType::I32 => Self::I32,
+            ValType::I64 => Self::I64,
+            ValType::F32 => Self::F32,
+            ValType::F64 => Self::F64,
+            ValType::Ref(RefType::Externref) => Self::ExternRef,
+            _ => return None,
+        })
+    }
+}
+
+/// Value passed across the module boundary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum MockValue {
+    /// 32-bit integer.
+    I32(i32),
+    /// 64-bit integer.
+    I64(i64),
+    /// 32-bit float.
+    F32(f32),
+    /// 64-bit float.
+    F64(f64),
+    /// Possibly null `externref`.
+    Ref(Option<MockRef>),
+}
+
+impl MockValue {
+    /// Returns the type of this value.
+    pub fn ty(&self) -> MockValueType {
+        match self {
+            Self::I32(_) => MockValueType::I32,
+            Self::I64(_) => MockValueType::I64,
+            Self::F32(_) => MockValueType::F32,
+            Self::F64(_) => MockValueType::F64,
+            Self::Ref(_) => MockValueType::ExternRef,
+        }
+    }
+
+    fn as_ref(&self) -> Option<MockRef> {
+        match self {
+            Self::Ref(reference) => *reference,
+            _ => None,
+        }
+    }
+}
+
+/// Function imported by the module, as described by [`MockHost::imports()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MockImport {
+    /// Name of the imported module.
+    pub module: String,
+    /// Name of the function.
+    pub name: String,
+    /// Types of the function args.
+    pub params: Vec<MockValueType>,
+    /// Types of the function return values.
+    pub results: Vec<MockValueType>,
+    /// Kinds of references returned by the default stub, in the order of `externref` results.
+    result_kinds: Vec<String>,
+}
+
+/// Direction of a reference crossing the module boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    /// Reference was passed from the host into the module (as an arg of an exported function
+    /// or a return value of an imported one).
+    IntoModule,
+    /// Reference was passed from the module to the host (as an arg of an imported function
+    /// or a return value of an exported one).
+    FromModule,
+}
+
+/// Record of a reference crossing the module boundary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RefCrossing {
+    /// Name of the function, in the `module::name` format for imports.
+    pub function: String,
+    /// Direction of the crossing.
+    pub direction: Crossing,
+    /// Crossing reference; `None` for null references.
+    pub reference: Option<MockRef>,
+}
+
+/// Errors that can occur when calling an import of a [`MockHost`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MockError {
+    /// The called function is not imported by the module.
+    UnknownImport {
+        /// Name of the imported module.
+        module: String,
+        /// Name of the function.
+        name: String,
+    },
+    /// Args provided by the runtime or values returned by a stub do not match the function signature.
+    SignatureMismatch {
+        /// Name of the function, in the `module::name` format.
+        function: String,
+        /// Expected types.
+        expected: Vec<MockValueType>,
+        /// Actual types.
+        actual: Vec<MockValueType>,
+    },
+    /// Drop hook was called for a reference not held by the module, or with a null reference.
+    UnexpectedDrop(Option<MockRef>),
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownImport { module, name } => {
+                write!(formatter, "function `{module}::{name}` is not imported")
+            }
+            Self::SignatureMismatch {
+                function,
+                expected,
+                actual,
+            } => {
+                write!(
+                    formatter,
+                    "unexpected types for `{function}`: expected {}, got {}",
+                    TypeList(expected),
+                    TypeList(actual)
+                )
+            }
+            Self::UnexpectedDrop(None) => {
+                formatter.write_str("drop hook called for a null reference")
+            }
+            Self::UnexpectedDrop(Some(reference)) => write!(
+                formatter,
+                "drop hook called for reference #{} not held by the module",
+                reference.0
+            ),
+        }
+    }
+}
+
+impl error::Error for MockError {}
+
+struct TypeList<'a>(&'a [MockValueType]);
+
+impl fmt::Display for TypeList<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("(")?;
+        for (i, ty) in self.0.iter().enumerate() {
+            if i > 0 {
+                formatter.write_str(", ")?;
+            }
+            write!(formatter, "{ty}")?;
+        }
+        formatter.write_str(")")
+    }
+}
+
+/// References created by a [`MockHost`], together with their kinds.
+#[derive(Debug, Default)]
+pub struct MockRefs {
+    kinds: Vec<String>,
+}
+
+impl MockRefs {
+    /// Creates a new reference with the specified kind.
+    pub fn create(&mut self, kind: impl Into<String>) -> MockRef {
+        self.kinds.push(kind.into());
+        MockRef(self.kinds.len() - 1)
+    }
+
+    /// Returns the kind of the specified reference.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reference was not created by this host.
+    pub fn kind(&self, reference: MockRef) -> &str {
+        &self.kinds[reference.0]
+    }
+}
+
+type Stub = Box<dyn FnMut(&mut MockRefs, &[MockValue]) -> Vec<MockValue>>;
+
+#[derive(Debug, Clone, Copy)]
+enum Hook {
+    Drop { with_kind: bool },
+    Leak,
+}
+
+/// In-memory mock host for a processed WASM module. See the [module docs](self) for an overview.
+pub struct MockHost {
+    imports: Vec<MockImport>,
+    hooks: HashMap<(String, String), Hook>,
+    stubs: HashMap<(String, String), Stub>,
+    refs: MockRefs,
+    /// Number of times each reference was passed into the module minus the number of drops.
+    held_counts: HashMap<MockRef, usize>,
+    crossings: Vec<RefCrossing>,
+    dropped_kinds: Vec<i32>,
+    leaked_counts: Vec<i32>,
+}
+
+impl fmt::Debug for MockHost {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MockHost")
+            .field("imports", &self.imports)
+            .field("hooks", &self.hooks)
+            .field("refs", &self.refs)
+            .field("held_counts", &self.held_counts)
+            .field("crossings", &self.crossings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MockHost {
+    /// Default kind of references returned by default stubs if the manifest does not specify
+    /// the resource type.
+    pub const DEFAULT_KIND: &'static str = "externref";
+
+    /// Creates a mock host for the provided processed module. The manifest must be obtained
+    /// via [`Processor::manifest()`](crate::processor::Processor::manifest()) from the same processor
+    /// used to process the module; it is used to recognize the drop and leak hooks,
+    /// and to determine kinds of references returned by default stubs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module cannot be parsed, or if an imported function
+    /// has args or return values of unsupported types (e.g., `v128`).
+    pub fn new(processed_module: &[u8], manifest: &Manifest) -> Result<Self, Error> {
+        let module = Module::from_buffer(processed_module).map_err(Error::Wasm)?;
+        let mut imports = vec![];
+        for import in module.imports.iter() {
+            let ImportKind::Function(fn_id) = import.kind else {
+                continue;
+            };
+            let ty = module.types.get(module.funcs.get(fn_id).ty());
+            let convert = |types: &[ValType]| -> Result<Vec<_>, Error> {
+                types
+                    .iter()
+                    .map(|&ty| {
+                        MockValueType::from_wasm(ty).ok_or_else(|| {
+                            Error::Wasm(anyhow::anyhow!(
+                                "import `{}::{}` has unsupported type `{ty}`",
+                                import.module,
+                                import.name
+                            ))
+                        })
+                    })
+                    .collect()
+            };
+
+            let declared = manifest.imports.iter().find(|function| {
+                function.module.as_deref() == Some(&import.module) && function.name == import.name
+            });
+            let result_kinds = declared.map_or_else(Vec::new, |function| {
+                function.resource_types[function
+                    .externref_args
+                    .len()
+                    .min(function.resource_types.len())..]
+                    .to_vec()
+            });
+            imports.push(MockImport {
+                module: import.module.clone(),
+                name: import.name.clone(),
+                params: convert(ty.params())?,
+                results: convert(ty.results())?,
+                result_kinds,
+            });
+        }
+
+        let mut hooks = HashMap::new();
+        if let Some(drop_fn) = &manifest.drop_fn {
+            let hook = Hook::Drop {
+                with_kind: manifest.drop_fn_with_kind,
+            };
+            hooks.insert((drop_fn.module.clone(), drop_fn.name.clone()), hook);
+        }
+        if let Some(leak_fn) = &manifest.leak_fn {
+            hooks.insert((leak_fn.module.clone(), leak_fn.name.clone()), Hook::Leak);
+        }
+
+        Ok(Self {
+            imports,
+            hooks,
+            stubs: HashMap::new(),
+            refs: MockRefs::default(),
+            held_counts: HashMap::new(),
+            crossings: vec![],
+            dropped_kinds: vec![],
+            leaked_counts: vec![],
+        })
+    }
+
+    /// Returns all functions imported by the module. All of them should be defined
+    /// in the WASM runtime and forwarded to [`Self::call_import()`].
+    pub fn imports(&self) -> &[MockImport] {
+        &self.imports
+    }
+
+    /// Overrides the stub for the specified import. The stub receives the call args
+    /// and must return values matching the function signature.
+    ///
+    /// Stubs for the drop and leak hooks are called in addition to the built-in handling
+    /// of these hooks.
+    pub fn stub<F>(&mut self, module: &str, name: &str, stub: F) -> &mut Self
+    where
+        F: FnMut(&mut MockRefs, &[MockValue]) -> Vec<MockValue> + 'static,
+    {
+        self.stubs
+            .insert((module.to_owned(), name.to_owned()), Box::new(stub));
+        self
+    }
+
+    /// Returns references created by this host.
+    pub fn refs(&self) -> &MockRefs {
+        &self.refs
+    }
+
+    /// Creates a new reference with the specified kind, e.g. to pass it to an exported function.
+    pub fn create_ref(&mut self, kind: impl Into<String>) -> MockRef {
+        self.refs.create(kind)
+    }
+
+    /// Records args passed to an exported function. Must be called by the runtime glue
+    /// for all exports receiving `externref`s, so that references held by the module
+    /// are tracked correctly.
+    pub fn pass_to_export(&mut self, name: &str, args: &[MockValue]) {
+        for arg in args {
+            if let MockValue::Ref(reference) = arg {
+                self.record_crossing(name.to_owned(), Crossing::IntoModule, *reference);
+            }
+        }
+    }
+
+    /// Records values returned from an exported function.
+    pub fn return_from_export(&mut self, name: &str, results: &[MockValue]) {
+        for result in results {
+            if let MockValue::Ref(reference) = result {
+                self.record_crossing(name.to_owned(), Crossing::FromModule, *reference);
+            }
+        }
+    }
+
+    fn record_crossing(
+        &mut self,
+        function: String,
+        direction: Crossing,
+        reference: Option<MockRef>,
+    ) {
+        if let (Crossing::IntoModule, Some(reference)) = (direction, reference) {
+            *self.held_counts.entry(reference).or_default() += 1;
+        }
+        self.crossings.push(RefCrossing {
+            function,
+            direction,
+            reference,
+        });
+    }
+
+    /// Handles a call to an imported function.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the function is not imported by the module, if the args or values
+    /// returned by a stub do not match the function signature, or if the drop hook
+    /// is called for a reference not held by the module.
+    pub fn call_import(
+        &mut self,
+        module: &str,
+        name: &str,
+        args: &[MockValue],
+    ) -> Result<Vec<MockValue>, MockError> {
+        let import = self
+            .imports
+            .iter()
+            .find(|import| import.module == module && import.name == name)
+            .ok_or_else(|| MockError::UnknownImport {
+                module: module.to_owned(),
+                name: name.to_owned(),
+            })?;
+        let function = format!("{module}::{name}");
+        check_types(&function, &import.params, args)?;
+        let expected_results = import.results.clone();
+        let mut result_kinds = import.result_kinds.clone().into_iter();
+
+        for arg in args {
+            if let MockValue::Ref(reference) = arg {
+                self.record_crossing(function.clone(), Crossing::FromModule, *reference);
+            }
+        }
+
+        let key = (module.to_owned(), name.to_owned());
+        match self.hooks.get(&key) {
+            Some(Hook::Drop { with_kind }) => {
+                let dropped = args[0].as_ref();
+                let held_count = dropped.and_then(|reference| self.held_counts.get_mut(&reference));
+                match held_count {
+                    Some(count) if *count > 0 => *count -= 1,
+                    _ => return Err(MockError::UnexpectedDrop(dropped)),
+                }
+                if *with_kind {
+                    if let MockValue::I32(kind) = args[1] {
+                        self.dropped_kinds.push(kind);
+                    }
+                }
+            }
+            Some(Hook::Leak) => {
+                if let MockValue::I32(count) = args[0] {
+                    self.leaked_counts.push(count);
+                }
+            }
+            None => { /* not a hook */ }
+        }
+
+        let results = if let Some(stub) = self.stubs.get_mut(&key) {
+            stub(&mut self.refs, args)
+        } else {
+            expected_results
+                .iter()
+                .map(|ty| match ty {
+                    MockValueType::I32 => MockValue::I32(0),
+                    MockValueType::I64 => MockValue::I64(0),
+                    MockValueType::F32 => MockValue::F32(0.0),
+                    MockValueType::F64 => MockValue::F64(0.0),
+                    MockValueType::ExternRef => {
+                        let kind = result_kinds.next();
+                        let kind = kind.as_deref().unwrap_or(Self::DEFAULT_KIND);
+                        MockValue::Ref(Some(self.refs.create(kind)))
+                    }
+                })
+                .collect()
+        };
+        check_types(&function, &expected_results, &results)?;
+
+        for result in &results {
+            if let MockValue::Ref(reference) = result {
+                self.record_crossing(function.clone(), Crossing::IntoModule, *reference);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns all references that crossed the module boundary, in the order of crossings.
+    pub fn crossings(&self) -> &[RefCrossing] {
+        &self.crossings
+    }
+
+    /// Returns references currently held by the module, i.e., passed into the module
+    /// and not reported by the drop hook, sorted by ID. A reference passed into the module
+    /// several times is listed once.
+    ///
+    /// Tracking requires the [drop hook](crate::processor::Processor::set_drop_fn())
+    /// to be set during processing; otherwise, references are never considered dropped.
+    pub fn live_refs(&self) -> Vec<MockRef> {
+        let mut refs: Vec<_> = self
+            .held_counts
+            .iter()
+            .filter_map(|(&reference, &count)| (count > 0).then_some(reference))
+            .collect();
+        refs.sort_unstable();
+        refs
+    }
+
+    /// Asserts that the module holds the specified number of references.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of [live references](Self::live_refs()) differs from `expected`.
+    /// The panic message lists live references together with their kinds.
+    #[track_caller]
+    pub fn assert_live_refs(&self, expected: usize) {
+        let live_refs = self.live_refs();
+        if live_refs.len() != expected {
+            let refs: Vec<_> = live_refs
+                .iter()
+                .map(|&reference| format!("#{} ({})", reference.0, self.refs.kind(reference)))
+                .collect();
+            panic!(
+                "expected {expected} live reference(s), got {}: {}",
+                live_refs.len(),
+                refs.join(", ")
+            );
+        }
+    }
+
+    /// Returns kind IDs passed to the drop hook if it [receives kinds](crate::processor::Processor::set_drop_fn_with_kind()).
+    pub fn dropped_kinds(&self) -> &[i32] {
+        &self.dropped_kinds
+    }
+
+    /// Returns reference counts reported by [leak checks](crate::processor::Processor::set_leak_check()).
+    pub fn leaked_counts(&self) -> &[i32] {
+        &self.leaked_counts
+    }
+}
+
+fn check_types(
+    function: &str,
+    expected: &[MockValueType],
+    values: &[MockValue],
+) -> Result<(), MockError> {
+    let actual: Vec<_> = values.iter().map(MockValue::ty).collect();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(MockError::SignatureMismatch {
+            function: function.to_owned(),
+            expected: expected.to_vec(),
+            actual,
+        })
+    }
+}
@@ -0,0 +1,145 @@
+//! Building blocks for wrappers of imported / exported functions generated without
+//! the [`externref`](macro@crate::externref) macro, e.g. by `bindgen`-style code generators.
+//!
+//! # Contract
+//!
+//! The [processor](crate::processor) relies on wrappers having the same shape as ones generated
+//! by the macro. Generated code must adhere to the following rules:
+//!
+//! - Each wrapped function must be declared with [`declare_function!`](crate::declare_function)
+//!   so that its [`Function`](crate::Function) declaration is placed into the custom section
+//!   of the module.
+//! - An imported function with `externref` args / return values must be declared in an
+//!   `extern` block with [`ExternRef`] in place of each such arg / return value.
+//! - The wrapper of an imported function must be marked with `#[inline(never)]` and must call
+//!   [`guard()`] as its first statement. Afterwards, the wrapper must call the raw import
+//!   exactly once, with each `ExternRef` arg obtained via [`lower()`] / [`lower_owned()`]
+//!   *directly* in the argument position. A returned `ExternRef` must be immediately converted
+//!   to a resource via [`lift()`], [`lift_non_null()`] or [`lift_nullable()`].
+//!   Note that WASM optimizers (e.g., `wasm-opt`) may still inline a wrapper with a single
+//!   call site, which breaks the guard placement.
+//! - An exported function must take [`ExternRef`]s in place of `Resource` args and convert
+//!   each of them to a resource via one of the `lift*()` functions before doing anything else.
+//!   A returned resource must be converted via [`lower_owned()`] as the last expression
+//!   of the function. Fallible exports must additionally call [`set_error_code()`] before
+//!   the conversion.
+//! - `ExternRef`s must not be stored anywhere (e.g., in variables outliving the call,
+//!   or in the linear memory).
+//!
+//! Violations of the contract are detected by the processor where possible (e.g., a misplaced
+//! guard leads to [`Error::IncorrectGuard`](crate::processor::Error::IncorrectGuard)).
+//! Since `ExternRef`s cannot be created or cloned outside of these functions, violations
+//! cannot lead to undefined behavior; at worst, the processed module will be rejected
+//! by WASM validation.
+//!
+//! # Examples
+//!
+//! Wrappers equivalent to ones generated by the macro for the declarations in
+//! [crate-level docs](crate#examples):
+//!
+//! ```no_run
+//! use externref::{
+//!     declare_function, resource_kind, wrappers, BitSlice, ExternRef, Function, FunctionKind,
+//!     Resource, TypeNames,
+//! };
+//!
+//! pub struct Sender(());
+//! pub struct Bytes(());
+//!
+//! #[link(wasm_import_module = "test")]
+//! extern "C" {
+//!     #[link_name = "send_message"]
+//!     fn raw_send_message(sender: ExternRef, ptr: *const u8, len: usize) -> ExternRef;
+//! }
+//!
+//! declare_function!(Function {
+//!     kind: FunctionKind::Import("test"),
+//!     name: "send_message",
+//!     // 3 args + return value; the first arg and the return value are `externref`s
+//!     externrefs: BitSlice::builder::<1>(4).with_set_bit(0).with_set_bit(3).build(),
+//!     host_handles: BitSlice::EMPTY,
+//!     type_names: TypeNames::EMPTY,
+//!     surrogate_module: None,
+//! });
+//!
+//! #[inline(never)]
+//! pub fn send_message(sender: &Resource<Sender>, message: &[u8]) -> Resource<Bytes> {
+//!     wrappers::guard();
+//!     let raw = unsafe {
+//!         raw_send_message(wrappers::lower(Some(sender)), message.as_ptr(), message.len())
+//!     };
+//!     wrappers::lift_non_null(raw, resource_kind!("Bytes"))
+//! }
+//!
+//! declare_function!(Function {
+//!     kind: FunctionKind::Export,
+//!     name: "test_export",
+//!     externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+//!     host_handles: BitSlice::EMPTY,
+//!     type_names: TypeNames::EMPTY,
+//!     surrogate_module: None,
+//! });
+//!
+//! #[export_name = "test_export"]
+//! pub extern "C" fn test_export(sender: ExternRef) {
+//!     let sender: Resource<Sender> = wrappers::lift_non_null(sender, resource_kind!("Sender"));
+//!     let _bytes = send_message(&sender, b"test");
+//! }
+//! ```
+
+use crate::{ExternRef, Resource};
+
+/// Marks the start of a wrapper of an imported function. Must be the first statement
+/// of the wrapper.
+#[inline(always)]
+pub fn guard() {
+    unsafe { ExternRef::guard() }
+}
+
+/// Converts a borrowed resource to an `externref` passed to an imported function.
+/// `None` is converted to a null `externref`.
+#[inline(always)]
+pub fn lower<T, S>(resource: Option<&Resource<T, S>>) -> ExternRef {
+    unsafe { Resource::raw(resource) }
+}
+
+/// Converts an owned resource to an `externref` passed to an imported function
+/// or returned from an exported function. The resource is dropped after obtaining the reference.
+#[inline(always)]
+pub fn lower_owned<T, S>(resource: Option<Resource<T, S>>) -> ExternRef {
+    unsafe { Resource::take_raw(resource) }
+}
+
+/// Converts an `externref` obtained from the host to a resource; a null reference
+/// is converted to `None`. `kind` should be produced by the [`resource_kind!`](crate::resource_kind)
+/// macro.
+#[inline(always)]
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn lift<T, S>(raw: ExternRef, kind: impl FnOnce() -> i32) -> Option<Resource<T, S>> {
+    unsafe { Resource::new(raw, kind) }
+}
+
+/// Same as [`lift()`], but for non-nullable references.
+///
+/// # Panics
+///
+/// Panics if the reference is null.
+#[inline(always)]
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn lift_non_null<T, S>(raw: ExternRef, kind: impl FnOnce() -> i32) -> Resource<T, S> {
+    unsafe { Resource::new_non_null(raw, kind) }
+}
+
+/// Same as [`lift()`], but represents a null reference as a [null](Resource::null()) resource.
+#[inline(always)]
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn lift_nullable<T, S>(raw: ExternRef, kind: impl FnOnce() -> i32) -> Resource<T, S> {
+    unsafe { Resource::new_nullable(raw, kind) }
+}
+
+/// Sets the error code returned by a fallible exported function (i.e., one declared
+/// with the `(externref, i32)` return type) as the second return value. 0 means success.
+#[inline(always)]
+pub fn set_error_code(code: i32) {
+    unsafe { ExternRef::set_error_code(code) }
+}
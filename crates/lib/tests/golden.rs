@@ -0,0 +1,44 @@
+//! Golden-output tests for the processor on the corpus of fixture modules.
+//!
+//! Golden outputs can be regenerated by running the tests with the `EXTERNREF_BLESS`
+//! env variable set.
+
+use std::{env, fs};
+
+use externref::processor::{fixtures, golden, Processor};
+
+fn golden_path(name: &str) -> String {
+    format!("tests/modules/golden/{name}.wat")
+}
+
+#[test]
+fn processing_fixtures_with_default_options() {
+    let processor = Processor::default();
+    let bless = env::var_os("EXTERNREF_BLESS").is_some();
+    for fixture in fixtures::ALL {
+        let input = fixture.annotated_wat();
+        let path = golden_path(fixture.name);
+        if bless {
+            let output = golden::processed_wat(&input, &processor).unwrap();
+            fs::write(&path, output).unwrap();
+        }
+        let expected = fs::read_to_string(&path).unwrap();
+        golden::assert_processed_eq(&input, &expected, &processor);
+    }
+}
+
+#[test]
+fn fixture_names_are_unique() {
+    let mut names: Vec<_> = fixtures::ALL.iter().map(|fixture| fixture.name).collect();
+    names.sort_unstable();
+    names.dedup();
+    assert_eq!(names.len(), fixtures::ALL.len());
+}
+
+#[test]
+#[should_panic(expected = "processed module differs from the expected one")]
+fn golden_mismatch() {
+    let input = fixtures::SIMPLE.annotated_wat();
+    let expected = fs::read_to_string(golden_path(fixtures::SIMPLE_KINDS.name)).unwrap();
+    golden::assert_processed_eq(&input, &expected, &Processor::default());
+}
@@ -0,0 +1,131 @@
+(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32) (result externref)))
+  (type (;2;) (func (param i32 i32) (result i32)))
+  (type (;3;) (func (param externref)))
+  (type (;4;) (func (param externref i32) (result i32)))
+  (type (;5;) (func (param externref i32) (result externref)))
+  (import "arena" "alloc" (func $alloc (;0;) (type 5)))
+  (func $externref_insert (;1;) (type 4) (param externref i32) (result i32)
+    (local i32)
+    local.get 0
+    ref.is_null
+    if ;; label = @1
+      i32.const -1
+      return
+    else
+    end
+    table.size 0
+    if ;; label = @1
+      table.size 0
+      i32.const 1
+      i32.sub
+      local.set 2
+      block ;; label = @2
+        loop ;; label = @3
+          local.get 2
+          table.get 0
+          ref.is_null
+          if ;; label = @4
+            br 2 (;@2;)
+          else
+            local.get 2
+            if ;; label = @5
+              local.get 2
+              i32.const 1
+              i32.sub
+              local.set 2
+              br 2 (;@3;)
+            else
+              table.size 0
+              local.set 2
+              br 3 (;@2;)
+            end
+          end
+        end
+      end
+    else
+    end
+    local.get 2
+    table.size 0
+    i32.eq
+    if ;; label = @1
+      local.get 0
+      i32.const 1
+      table.grow 0
+      i32.const -1
+      i32.eq
+      if ;; label = @2
+        unreachable
+      else
+      end
+    else
+      local.get 2
+      local.get 0
+      table.set 0
+    end
+    local.get 2
+  )
+  (func (;2;) (type 3) (param externref)
+    (local i32 i32)
+    local.get 0
+    i32.const -1
+    call $externref_insert
+    local.set 1
+    local.get 1
+    i32.const 42
+    call $alloc_wrapper
+    local.set 2
+    local.get 2
+    i32.const -1
+    i32.eq
+    if ;; label = @1
+      unreachable
+    else
+      local.get 2
+      call $externref_drop
+    end
+    local.get 1
+    call $externref_drop
+  )
+  (func $alloc_wrapper (;3;) (type 2) (param i32 i32) (result i32)
+    (local i32 externref)
+    global.get 0
+    i32.const 16
+    i32.sub
+    local.tee 2
+    global.set 0
+    local.get 0
+    call $externref_get
+    local.get 1
+    call $alloc
+    local.set 3
+    local.get 2
+    i32.const 16
+    i32.add
+    global.set 0
+    local.get 3
+    i32.const -1
+    call $externref_insert
+  )
+  (func $externref_get (;4;) (type 1) (param i32) (result externref)
+    local.get 0
+    i32.const -1
+    i32.eq
+    if (result externref) ;; label = @1
+      ref.null extern
+    else
+      local.get 0
+      table.get 0
+    end
+  )
+  (func $externref_drop (;5;) (type 0) (param i32)
+    local.get 0
+    ref.null extern
+    table.set 0
+  )
+  (table (;0;) 0 externref)
+  (global (;0;) (mut i32) i32.const 65536)
+  (export "test" (func 2))
+  (export "externrefs" (table 0))
+)
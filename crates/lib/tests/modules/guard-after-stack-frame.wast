@@ -0,0 +1,45 @@
+(module
+  ;; Same logic as in `guard-leading.wast`, but the wrapper for the imported function
+  ;; allocates a shadow stack frame before calling the guard. This is the pattern
+  ;; produced by rustc versions / optimization settings that set up the frame
+  ;; in the function prologue.
+
+  ;; surrogate imports
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "externref" "get" (func $get_ref (param i32) (result i32)))
+  (import "externref" "drop" (func $drop_ref (param i32)))
+  (import "externref" "guard" (func $ref_guard))
+  ;; real imported fn
+  (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+  (global $__stack_pointer (mut i32) (i32.const 65536))
+
+  ;; exported fn
+  (func (export "test") (param $arena i32)
+    (local $bytes i32)
+    (local.set $arena (call $insert_ref (local.get $arena) (i32.const -1)))
+    (local.set $bytes (call $alloc_wrapper (local.get $arena) (i32.const 42)))
+    (if (i32.eq (local.get $bytes) (i32.const -1))
+      (then (unreachable))
+      (else (call $drop_ref (local.get $bytes)))
+    )
+    (call $drop_ref (local.get $arena))
+  )
+
+  ;; wrapper for the imported fn
+  (func $alloc_wrapper (param $arena i32) (param $cap i32) (result i32)
+    (local $frame i32)
+    (local $bytes i32)
+    (global.set $__stack_pointer
+      (local.tee $frame
+        (i32.sub (global.get $__stack_pointer) (i32.const 16))
+      )
+    )
+    (call $ref_guard)
+    (local.set $bytes
+      (call $alloc (call $get_ref (local.get $arena)) (local.get $cap))
+    )
+    (global.set $__stack_pointer (i32.add (local.get $frame) (i32.const 16)))
+    (call $insert_ref (local.get $bytes) (i32.const -1))
+  )
+)
@@ -0,0 +1,30 @@
+(module
+  ;; Same logic as in `guard-leading.wast`, but with the wrapper for the imported function
+  ;; inlined into the exported function, so that the guard is the first instruction
+  ;; of the exported function. This is the pattern produced by rustc with LTO.
+
+  ;; surrogate imports
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "externref" "get" (func $get_ref (param i32) (result i32)))
+  (import "externref" "drop" (func $drop_ref (param i32)))
+  (import "externref" "guard" (func $ref_guard))
+  ;; real imported fn
+  (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+  ;; exported fn
+  (func (export "test") (param $arena i32)
+    (local $raw i32)
+    (local $bytes i32)
+    (call $ref_guard)
+    (local.set $arena (call $insert_ref (local.get $arena) (i32.const -1)))
+    (local.set $raw
+      (call $alloc (call $get_ref (local.get $arena)) (i32.const 42))
+    )
+    (local.set $bytes (call $insert_ref (local.get $raw) (i32.const -1)))
+    (if (i32.eq (local.get $bytes) (i32.const -1))
+      (then (unreachable))
+      (else (call $drop_ref (local.get $bytes)))
+    )
+    (call $drop_ref (local.get $arena))
+  )
+)
@@ -0,0 +1,36 @@
+(module
+  ;; Same logic as in `simple.wast`, but with the imported function called
+  ;; via a wrapper generated by the `externref` macro, which was not inlined.
+  ;; The guard is the first instruction of the wrapper, which is the pattern
+  ;; produced by rustc for wrappers without a shadow stack frame.
+
+  ;; surrogate imports
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "externref" "get" (func $get_ref (param i32) (result i32)))
+  (import "externref" "drop" (func $drop_ref (param i32)))
+  (import "externref" "guard" (func $ref_guard))
+  ;; real imported fn
+  (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+  ;; exported fn
+  (func (export "test") (param $arena i32)
+    (local $bytes i32)
+    (local.set $arena (call $insert_ref (local.get $arena) (i32.const -1)))
+    (local.set $bytes (call $alloc_wrapper (local.get $arena) (i32.const 42)))
+    (if (i32.eq (local.get $bytes) (i32.const -1))
+      (then (unreachable))
+      (else (call $drop_ref (local.get $bytes)))
+    )
+    (call $drop_ref (local.get $arena))
+  )
+
+  ;; wrapper for the imported fn
+  (func $alloc_wrapper (param $arena i32) (param $cap i32) (result i32)
+    (local $bytes i32)
+    (call $ref_guard)
+    (local.set $bytes
+      (call $alloc (call $get_ref (local.get $arena)) (local.get $cap))
+    )
+    (call $insert_ref (local.get $bytes) (i32.const -1))
+  )
+)
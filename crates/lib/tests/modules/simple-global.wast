@@ -0,0 +1,37 @@
+(module
+  ;; Same logic as in `simple.wast`, but with allocated bytes stored
+  ;; in the resource global.
+
+  ;; surrogate imports
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "externref" "get" (func $get_ref (param i32) (result i32)))
+  (import "externref" "drop" (func $drop_ref (param i32)))
+  (import "externref" "global_get" (func $global_get (result i32)))
+  (import "externref" "global_set" (func $global_set (param i32)))
+  ;; real imported fn
+  (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+  ;; exported fn
+  (func (export "test") (param $arena i32)
+    (local $bytes i32)
+    (local.set $arena (call $insert_ref (local.get $arena) (i32.const -1)))
+    (local.set $bytes
+      (call $insert_ref
+        (call $alloc
+          (call $get_ref (local.get $arena))
+          (i32.const 42)
+        )
+        (i32.const -1)
+      )
+    )
+    (call $global_set (local.get $bytes))
+    (call $drop_ref (local.get $bytes))
+    (local.set $bytes (call $global_get))
+    (if (i32.eq (local.get $bytes) (i32.const -1))
+      (then (unreachable))
+    )
+    (call $drop_ref (local.get $bytes))
+    (call $global_set (i32.const -1))
+    (call $drop_ref (local.get $arena))
+  )
+)
@@ -0,0 +1,35 @@
+(module
+  ;; Same logic as in `simple.wast`, but with resource kinds tracked
+  ;; by the `externref` macro.
+
+  ;; surrogate imports
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "externref" "get" (func $get_ref (param i32) (result i32)))
+  (import "externref" "drop" (func $drop_ref (param i32)))
+  (import "externref" "kind::Bytes" (func $bytes_kind (result i32)))
+  (import "externref" "kind::Arena" (func $arena_kind (result i32)))
+  ;; real imported fn
+  (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+  ;; exported fn
+  (func (export "test") (param $arena i32)
+    (local $bytes i32)
+    (local.set $arena (call $insert_ref (local.get $arena) (call $arena_kind)))
+    (local.set $bytes
+      (call $insert_ref
+        (call $alloc
+          (call $get_ref (local.get $arena))
+          (i32.const 42)
+        )
+        (call $bytes_kind)
+      )
+    )
+    (if (i32.eq (local.get $bytes) (i32.const -1))
+      (then (unreachable))
+      (else
+        (call $drop_ref (local.get $bytes))
+      )
+    )
+    (call $drop_ref (local.get $arena))
+  )
+)
@@ -17,7 +17,7 @@
   ;; locals are transformed.
 
   ;; surrogate imports
-  (import "externref" "insert" (func $insert_ref (param i32) (result i32)))
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
   (import "externref" "get" (func $get_ref (param i32) (result i32)))
   (import "externref" "drop" (func $drop_ref (param i32)))
   (import "externref" "guard" (func $ref_guard))
@@ -31,7 +31,7 @@
       (call $alloc
         (call $get_ref
           (local.tee $arena
-            (call $insert_ref (local.get $arena))
+            (call $insert_ref (local.get $arena) (i32.const -1))
           )
         )
         (i32.const 42)
@@ -39,7 +39,7 @@
     )
     (if (i32.eq
       (local.tee $bytes
-        (call $insert_ref (local.get $bytes))
+        (call $insert_ref (local.get $bytes) (i32.const -1))
       )
       (i32.const -1))
       (then (unreachable))
@@ -0,0 +1,43 @@
+(module
+  ;; Same logic as in `simple.wast`, but with allocated bytes pushed
+  ;; to the resource table.
+
+  ;; surrogate imports
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "externref" "get" (func $get_ref (param i32) (result i32)))
+  (import "externref" "drop" (func $drop_ref (param i32)))
+  (import "externref" "table_push" (func $table_push (param i32) (result i32)))
+  (import "externref" "table_get" (func $table_get (param i32) (result i32)))
+  (import "externref" "table_len" (func $table_len (result i32)))
+  (import "externref" "table_clear" (func $table_clear))
+  ;; real imported fn
+  (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+  ;; exported fn
+  (func (export "test") (param $arena i32)
+    (local $bytes i32)
+    (local.set $arena (call $insert_ref (local.get $arena) (i32.const -1)))
+    (local.set $bytes
+      (call $insert_ref
+        (call $alloc
+          (call $get_ref (local.get $arena))
+          (i32.const 42)
+        )
+        (i32.const -1)
+      )
+    )
+    (if (i32.eq (local.get $bytes) (i32.const -1))
+      (then (unreachable))
+      (else
+        (drop (call $table_push (local.get $bytes)))
+        (call $drop_ref (local.get $bytes))
+      )
+    )
+    (if (i32.ne (call $table_len) (i32.const 1))
+      (then (unreachable))
+    )
+    (call $drop_ref (call $table_get (i32.const 0)))
+    (call $table_clear)
+    (call $drop_ref (local.get $arena))
+  )
+)
@@ -13,7 +13,7 @@
   ;; ```
 
   ;; surrogate imports
-  (import "externref" "insert" (func $insert_ref (param i32) (result i32)))
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
   (import "externref" "get" (func $get_ref (param i32) (result i32)))
   (import "externref" "drop" (func $drop_ref (param i32)))
   ;; real imported fn
@@ -30,11 +30,12 @@
               ;; Reassigning the param local is completely valid,
               ;; and the Rust compliler frequently does this.
               (local.tee $arena
-                (call $insert_ref (local.get $arena))
+                (call $insert_ref (local.get $arena) (i32.const -1))
               )
             )
             (i32.const 42)
           )
+          (i32.const -1)
         )
       )
       (i32.const -1))
@@ -1,9 +1,17 @@
 //! Tests for processor logic.
 
-use std::path::Path;
+use std::{cell::RefCell, collections::HashSet, env, fs, path::Path, process};
 
-use externref::{processor::Processor, BitSlice, Function, FunctionKind};
-use walrus::{ExportItem, ImportKind, Module, RawCustomSection, RefType, ValType};
+use assert_matches::assert_matches;
+use externref::{
+    processor::{
+        self, DropSlotValue, Error, EventLog, GcMode, ImportShimVisibility, IndexType,
+        InterfaceFormat, Partitioning, PatchedFunction, PatchedFunctionKind, PostOptimizer,
+        ProcessingEvent, ProcessingHook, Processor, SectionOptions, WasmFeatures,
+    },
+    BitSlice, Function, FunctionKind, TypeNames,
+};
+use walrus::{ir, ConstExpr, ExportItem, ImportKind, Module, RawCustomSection, RefType, ValType};
 
 const EXTERNREF: ValType = ValType::Ref(RefType::Externref);
 
@@ -14,6 +22,9 @@ const ARENA_ALLOC: Function<'static> = Function {
         .with_set_bit(0)
         .with_set_bit(2)
         .build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
 };
 const ARENA_ALLOC_BYTES: [u8; ARENA_ALLOC.custom_section_len()] = ARENA_ALLOC.custom_section();
 
@@ -21,6 +32,9 @@ const TEST: Function<'static> = Function {
     kind: FunctionKind::Export,
     name: "test",
     externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
 };
 const TEST_BYTES: [u8; TEST.custom_section_len()] = TEST.custom_section();
 
@@ -32,6 +46,18 @@ fn no_inline_module_path() -> &'static Path {
     Path::new("tests/modules/simple-no-inline.wast")
 }
 
+fn kinds_module_path() -> &'static Path {
+    Path::new("tests/modules/simple-kinds.wast")
+}
+
+fn table_module_path() -> &'static Path {
+    Path::new("tests/modules/simple-table.wast")
+}
+
+fn global_module_path() -> &'static Path {
+    Path::new("tests/modules/simple-global.wast")
+}
+
 fn add_basic_custom_section(module: &mut Module) {
     let mut section_data = Vec::with_capacity(ARENA_ALLOC_BYTES.len() + TEST_BYTES.len());
     section_data.extend_from_slice(&ARENA_ALLOC_BYTES);
@@ -89,6 +115,394 @@ fn basic_module() {
     Module::from_buffer(&module_bytes).unwrap();
 }
 
+#[test]
+fn processing_with_default_options() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let processed = processor::process_bytes(&module_bytes).unwrap();
+    let expected = Processor::default().process_bytes(&module_bytes).unwrap();
+    assert_eq!(processed, expected);
+
+    let err = processor::process_bytes(&processed).unwrap_err();
+    assert_matches!(err, Error::AlreadyProcessed { .. });
+}
+
+#[test]
+fn processing_artifacts() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    let artifacts = Processor::default().process(&mut module).unwrap();
+
+    let fn_type = |fn_id| {
+        let function_type = module.types.get(module.funcs.get(fn_id).ty());
+        (
+            function_type.params().to_vec(),
+            function_type.results().to_vec(),
+        )
+    };
+    assert_eq!(
+        fn_type(artifacts.insert_fn.unwrap()),
+        (vec![EXTERNREF, ValType::I32], vec![ValType::I32])
+    );
+    assert_eq!(
+        fn_type(artifacts.get_fn.unwrap()),
+        (vec![ValType::I32], vec![EXTERNREF])
+    );
+    assert_eq!(
+        fn_type(artifacts.drop_fn.unwrap()),
+        (vec![ValType::I32], vec![])
+    );
+
+    let exported_table = module.exports.iter().find_map(|export| match export.item {
+        ExportItem::Table(table_id) if export.name == "externrefs" => Some(table_id),
+        _ => None,
+    });
+    assert_eq!(artifacts.ref_table, exported_table);
+    assert!(artifacts.kind_tables.is_empty());
+
+    // Check artifacts for the partitioned table.
+    let module = wat::parse_file(kinds_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let artifacts = Processor::default()
+        .set_table_partitioning(Partitioning::PerKind)
+        .process(&mut module)
+        .unwrap();
+
+    let exported_tables: Vec<_> = module
+        .exports
+        .iter()
+        .filter_map(|export| match export.item {
+            ExportItem::Table(table_id) => Some(table_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(exported_tables[0], artifacts.ref_table.unwrap());
+    assert_eq!(exported_tables[1..], artifacts.kind_tables);
+}
+
+#[test]
+fn customizing_module_before_gc() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    let mut unused_fn_id = None;
+    let artifacts = Processor::default()
+        .rename_export("test", "exit")
+        .process_with(&mut module, |module, artifacts| {
+            // Renames should be applied at this point.
+            assert!(module.exports.iter().any(|export| export.name == "exit"));
+            assert!(module.imports.find("externref", "insert").is_none());
+
+            module.exports.add("drop_ref", artifacts.drop_fn.unwrap());
+            let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+            builder.func_body().unreachable();
+            unused_fn_id = Some(builder.finish(vec![], &mut module.funcs));
+            Ok(())
+        })
+        .unwrap();
+
+    let exported_fn = module.exports.iter().find_map(|export| match export.item {
+        ExportItem::Function(fn_id) if export.name == "drop_ref" => Some(fn_id),
+        _ => None,
+    });
+    assert_eq!(exported_fn, artifacts.drop_fn);
+    // The function added by the closure is not referenced and is thus removed.
+    let unused_fn_id = unused_fn_id.unwrap();
+    assert!(module
+        .funcs
+        .iter()
+        .all(|function| function.id() != unused_fn_id));
+    Module::from_buffer(&module.emit_wasm()).unwrap();
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let err = Processor::default()
+        .process_with(&mut module, |_, _| Err(anyhow::anyhow!("oops")))
+        .unwrap_err();
+    assert_matches!(&err, Error::Hook(err) if err.to_string() == "oops");
+}
+
+/// Collects function and local names from the name section of the module.
+fn collect_names(module_bytes: &[u8]) -> (HashSet<String>, HashSet<String>) {
+    let mut fn_names = HashSet::new();
+    let mut local_names = HashSet::new();
+    for payload in wasmparser::Parser::new(0).parse_all(module_bytes) {
+        let wasmparser::Payload::CustomSection(section) = payload.unwrap() else {
+            continue;
+        };
+        let wasmparser::KnownCustom::Name(reader) = section.as_known() else {
+            continue;
+        };
+        for subsection in reader {
+            match subsection.unwrap() {
+                wasmparser::Name::Function(names) => {
+                    for naming in names {
+                        fn_names.insert(naming.unwrap().name.to_owned());
+                    }
+                }
+                wasmparser::Name::Local(locals) => {
+                    for function in locals {
+                        for naming in function.unwrap().names {
+                            local_names.insert(naming.unwrap().name.to_owned());
+                        }
+                    }
+                }
+                _ => { /* skip other subsections */ }
+            }
+        }
+    }
+    (fn_names, local_names)
+}
+
+#[test]
+fn names_of_patched_functions_and_locals() {
+    // Append the custom section to the module directly; parsing the module with `walrus`
+    // would lose local names.
+    let mut module_bytes = wat::parse_file(simple_module_path()).unwrap();
+    let mut section = vec![Function::CUSTOM_SECTION_NAME.len() as u8];
+    section.extend_from_slice(Function::CUSTOM_SECTION_NAME.as_bytes());
+    section.extend_from_slice(&ARENA_ALLOC_BYTES);
+    section.extend_from_slice(&TEST_BYTES);
+    let section_len = u8::try_from(section.len()).unwrap();
+    assert!(section_len < 128); // the length is LEB128-encoded
+    module_bytes.extend_from_slice(&[0, section_len]);
+    module_bytes.extend_from_slice(&section);
+
+    let processed = Processor::default().process_bytes(&module_bytes).unwrap();
+    let (fn_names, local_names) = collect_names(&processed);
+    for expected_name in [
+        "externref_insert",
+        "externref_get",
+        "externref_drop",
+        "alloc",
+    ] {
+        assert!(fn_names.contains(expected_name), "{fn_names:?}");
+    }
+    // `arena` and `bytes` are original local names from the module.
+    for expected_name in ["externref_local_0", "arena", "bytes"] {
+        assert!(local_names.contains(expected_name), "{local_names:?}");
+    }
+}
+
+#[test]
+fn stripping_names() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let processed = Processor::default()
+        .set_strip_names(true)
+        .process_bytes(&module_bytes)
+        .unwrap();
+    let (fn_names, local_names) = collect_names(&processed);
+    assert!(fn_names.is_empty(), "{fn_names:?}");
+    assert!(local_names.is_empty(), "{local_names:?}");
+}
+
+/// Returns bytes of the simple module with unused named local functions.
+fn module_with_unused_functions() -> Vec<u8> {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    for name in ["helper_len", "unused"] {
+        let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+        builder.func_body().i32_const(0);
+        let fn_id = builder.finish(vec![], &mut module.funcs);
+        module.funcs.get_mut(fn_id).name = Some(name.to_owned());
+    }
+    module.emit_wasm()
+}
+
+fn function_names(module: &Module) -> HashSet<&str> {
+    module
+        .funcs
+        .iter()
+        .filter_map(|function| function.name.as_deref())
+        .collect()
+}
+
+#[test]
+fn configuring_gc() {
+    let module_bytes = module_with_unused_functions();
+
+    let processed = Processor::default().process_bytes(&module_bytes).unwrap();
+    let processed = Module::from_buffer(&processed).unwrap();
+    let names = function_names(&processed);
+    assert!(
+        !names.contains("helper_len") && !names.contains("unused"),
+        "{names:?}"
+    );
+
+    let processed = Processor::default()
+        .set_gc(GcMode::KeepNamed(vec!["helper_*"]))
+        .process_bytes(&module_bytes)
+        .unwrap();
+    let processed = Module::from_buffer(&processed).unwrap();
+    let names = function_names(&processed);
+    assert!(
+        names.contains("helper_len") && !names.contains("unused"),
+        "{names:?}"
+    );
+
+    let processed = Processor::default()
+        .set_gc(GcMode::Skip)
+        .process_bytes(&module_bytes)
+        .unwrap();
+    let processed = Module::from_buffer(&processed).unwrap();
+    let names = function_names(&processed);
+    assert!(
+        names.contains("helper_len") && names.contains("unused"),
+        "{names:?}"
+    );
+    // Replaced surrogate imports must be removed regardless of the GC mode.
+    let import_modules: Vec<_> = processed
+        .imports
+        .iter()
+        .map(|import| import.module.as_str())
+        .collect();
+    assert_eq!(import_modules, ["arena"]);
+}
+
+#[test]
+fn stripping_custom_sections() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    for name in ["build_id", "target_features", "sourceMappingURL"] {
+        module.customs.add(RawCustomSection {
+            name: name.to_owned(),
+            data: vec![1, 2, 3],
+        });
+    }
+    module.producers.add_processed_by("test", "0.1.0");
+    let module_bytes = module.emit_wasm();
+
+    let processed = Processor::default()
+        .strip_custom_sections(["producers", "*_*"])
+        .process_bytes(&module_bytes)
+        .unwrap();
+    let has_producers = processed
+        .windows(b"producers".len())
+        .any(|window| window == b"producers");
+    assert!(!has_producers);
+
+    let processed = Module::from_buffer(&processed).unwrap();
+    let section_names: HashSet<_> = processed
+        .customs
+        .iter()
+        .map(|(_, section)| section.name())
+        .collect();
+    assert_eq!(
+        section_names,
+        HashSet::from(["sourceMappingURL", Processor::MARKER_SECTION_NAME])
+    );
+}
+
+fn read_processed_by(module_bytes: &[u8]) -> Vec<(String, String)> {
+    let mut processed_by = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(module_bytes) {
+        let wasmparser::Payload::CustomSection(section) = payload.unwrap() else {
+            continue;
+        };
+        let wasmparser::KnownCustom::Producers(reader) = section.as_known() else {
+            continue;
+        };
+        for field in reader {
+            let field = field.unwrap();
+            if field.name != "processed-by" {
+                continue;
+            }
+            for value in field.values {
+                let value = value.unwrap();
+                processed_by.push((value.name.to_owned(), value.version.to_owned()));
+            }
+        }
+    }
+    processed_by
+}
+
+#[test]
+fn recording_processor_in_producers() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    module.producers.add_processed_by("test", "0.1.0");
+    let module_bytes = module.emit_wasm();
+
+    let version = env!("CARGO_PKG_VERSION");
+    let processed = Processor::default().process_bytes(&module_bytes).unwrap();
+    let processed_by = read_processed_by(&processed);
+    // Existing entries are preserved; `walrus` also records itself.
+    assert!(
+        processed_by.contains(&("test".to_owned(), "0.1.0".to_owned())),
+        "{processed_by:?}"
+    );
+    assert_eq!(
+        processed_by.last().unwrap(),
+        &("externref".to_owned(), version.to_owned())
+    );
+
+    let processed = Processor::default()
+        .set_drop_fn("hook", "drop_ref")
+        .set_drop_slot_value(DropSlotValue::Tombstone {
+            module: "hook",
+            name: "tombstone",
+        })
+        .set_slot_allocator("hook", "alloc_slot")
+        .enable_resource_metrics()
+        .process_bytes(&module_bytes)
+        .unwrap();
+    let expected_version = format!("{version} (drop-fn, tombstones, slot-allocator, metrics)");
+    assert_eq!(
+        read_processed_by(&processed).last().unwrap(),
+        &("externref".to_owned(), expected_version)
+    );
+}
+
+#[cfg(feature = "wat")]
+#[test]
+fn processing_wat() {
+    use std::fmt::Write as _;
+
+    let section_data = [ARENA_ALLOC_BYTES.as_slice(), &TEST_BYTES].concat();
+    let escaped_data = section_data.iter().fold(String::new(), |mut acc, byte| {
+        write!(acc, "\\{byte:02x}").unwrap();
+        acc
+    });
+    let module = fs::read_to_string(simple_module_path()).unwrap();
+    let module = module.trim_end().strip_suffix(')').unwrap();
+    let module = format!(
+        "{module}  (@custom \"{}\" \"{escaped_data}\")\n)",
+        Function::CUSTOM_SECTION_NAME
+    );
+
+    let processed = Processor::default().process_wat(&module).unwrap();
+    assert!(
+        processed.contains("(param externref i32) (result externref)"),
+        "{processed}"
+    );
+    assert!(
+        processed.contains("(export \"externrefs\" (table"),
+        "{processed}"
+    );
+    assert!(
+        !processed.contains("\"externref\" \"insert\""),
+        "{processed}"
+    );
+
+    let err = Processor::default().process_wat("(module").unwrap_err();
+    assert_matches!(err, Error::Wasm(_));
+}
+
 #[test]
 fn basic_module_with_no_table_export_and_drop_hook() {
     let module = wat::parse_file(simple_module_path()).unwrap();
@@ -123,16 +537,2605 @@ fn basic_module_with_no_table_export_and_drop_hook() {
     Module::from_buffer(&module_bytes).unwrap();
 }
 
+#[cfg(feature = "testing")]
 #[test]
-fn module_without_inlines() {
-    let module = wat::parse_file(no_inline_module_path()).unwrap();
+fn mock_host_for_basic_module() {
+    use externref::testing::{Crossing, MockError, MockHost, MockValue};
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
     let mut module = Module::from_buffer(&module).unwrap();
-    // We need to add a custom section to the module before processing.
     add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
 
-    Processor::default().process(&mut module).unwrap();
+    let mut processor = Processor::default();
+    processor.set_drop_fn("hook", "drop_ref");
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let processed = processor.process_bytes(&module_bytes).unwrap();
+    let mut host = MockHost::new(&processed, &manifest).unwrap();
+    let import_names: Vec<_> = host
+        .imports()
+        .iter()
+        .map(|import| (import.module.as_str(), import.name.as_str()))
+        .collect();
+    assert_eq!(import_names, [("arena", "alloc"), ("hook", "drop_ref")]);
+
+    // Emulate a call to the `test` export.
+    let arena = host.create_ref("Arena");
+    host.pass_to_export("test", &[MockValue::Ref(Some(arena))]);
+    let results = host
+        .call_import(
+            "arena",
+            "alloc",
+            &[MockValue::Ref(Some(arena)), MockValue::I32(42)],
+        )
+        .unwrap();
+    let [MockValue::Ref(Some(bytes))] = results[..] else {
+        panic!("unexpected results: {results:?}");
+    };
+    assert_eq!(host.refs().kind(bytes), MockHost::DEFAULT_KIND);
+    assert_eq!(host.live_refs(), [arena, bytes]);
+
+    host.call_import("hook", "drop_ref", &[MockValue::Ref(Some(bytes))])
+        .unwrap();
+    host.assert_live_refs(1);
+    host.call_import("hook", "drop_ref", &[MockValue::Ref(Some(arena))])
+        .unwrap();
+    host.assert_live_refs(0);
+
+    let crossings: Vec<_> = host
+        .crossings()
+        .iter()
+        .map(|crossing| (crossing.function.as_str(), crossing.direction))
+        .collect();
+    assert_eq!(
+        crossings,
+        [
+            ("test", Crossing::IntoModule),
+            ("arena::alloc", Crossing::FromModule),
+            ("arena::alloc", Crossing::IntoModule),
+            ("hook::drop_ref", Crossing::FromModule),
+            ("hook::drop_ref", Crossing::FromModule),
+        ]
+    );
+
+    let err = host
+        .call_import("hook", "drop_ref", &[MockValue::Ref(Some(arena))])
+        .unwrap_err();
+    assert_matches!(err, MockError::UnexpectedDrop(Some(r)) if r == arena);
+    let err = host
+        .call_import("arena", "alloc", &[MockValue::I32(0)])
+        .unwrap_err();
+    assert_matches!(err, MockError::SignatureMismatch { .. });
+    assert!(
+        err.to_string()
+            .contains("expected (externref, i32), got (i32)"),
+        "{err}"
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "expected 0 live reference(s), got 1: #0 (Arena)")]
+fn asserting_live_refs_in_mock_host() {
+    use externref::testing::{MockHost, MockValue};
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let processor = Processor::default();
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let processed = processor.process_bytes(&module_bytes).unwrap();
+    let mut host = MockHost::new(&processed, &manifest).unwrap();
+    let arena = host.create_ref("Arena");
+    host.pass_to_export("test", &[MockValue::Ref(Some(arena))]);
+    host.assert_live_refs(0);
+}
+
+const START_MODULE: &str = r#"
+(module
+  (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+  (import "arena" "create" (func $create (result i32)))
+  (global $arena (mut i32) (i32.const -1))
+  (func $init (export "init")
+    (global.set $arena (call $insert_ref (call $create) (i32.const -1)))
+  )
+  (func (export "arena") (result i32) (global.get $arena))
+  (func (export "other"))
+)
+"#;
+
+const ARENA_CREATE: Function<'static> = Function {
+    kind: FunctionKind::Import("arena"),
+    name: "create",
+    externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+
+const INIT: Function<'static> = Function {
+    kind: FunctionKind::Start,
+    name: "init",
+    externrefs: BitSlice::EMPTY,
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+
+fn start_module(existing_start: bool) -> Vec<u8> {
+    let module = wat::parse_str(START_MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    if existing_start {
+        let other_id = module.exports.get_func("other").unwrap();
+        module.start = Some(other_id);
+    }
+
+    let mut section_data = vec![];
+    let declarations = [ARENA_CREATE, INIT];
+    for declaration in &declarations {
+        declaration.write_to_vec(&mut section_data);
+    }
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+    module.emit_wasm()
+}
+
+#[test]
+fn module_with_start_function() {
+    let module_bytes = start_module(false);
+    let processor = Processor::default();
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    assert!(manifest.exports.is_empty(), "{manifest:?}");
+
+    let processed = processor.process_bytes(&module_bytes).unwrap();
+    let module = Module::from_buffer(&processed).unwrap();
+    let start_id = module.start.expect("no start function");
+    let start_fn = module.funcs.get(start_id);
+    assert_eq!(start_fn.name.as_deref(), Some("init"));
+    let start_ty = module.types.get(start_fn.ty());
+    assert!(start_ty.params().is_empty() && start_ty.results().is_empty());
+    assert!(module.exports.get_func("init").is_err());
+    assert!(module.exports.get_func("arena").is_ok());
+
+    let import_id = module.imports.get_func("arena", "create").unwrap();
+    let import_ty = module.types.get(module.funcs.get(import_id).ty());
+    assert_eq!(import_ty.results(), [EXTERNREF]);
+}
+
+#[test]
+fn module_with_conflicting_start_function() {
+    let module_bytes = start_module(true);
+    let err = Processor::default()
+        .process_bytes(&module_bytes)
+        .unwrap_err();
+    assert_matches!(
+        &err,
+        Error::ConflictingStart { name, .. } if name == "init"
+    );
+}
+
+#[test]
+fn basic_module_with_tombstones() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    Processor::default()
+        .set_drop_slot_value(DropSlotValue::Tombstone {
+            module: "hook",
+            name: "tombstone",
+        })
+        .process(&mut module)
+        .unwrap();
+
+    // Check that the tombstone global is imported.
+    let import_id = module.imports.find("hook", "tombstone").unwrap();
+    let global_id = match &module.imports.get(import_id).kind {
+        ImportKind::Global(global_id) => *global_id,
+        other => panic!("unexpected import type: {other:?}"),
+    };
+    let global = module.globals.get(global_id);
+    assert_eq!(global.ty, EXTERNREF);
+    assert!(!global.mutable);
+
+    // Check that the shadow occupancy table is added, but not exported.
+    assert_eq!(module.tables.iter().count(), 2);
+    let exported_tables = module
+        .exports
+        .iter()
+        .filter(|export| matches!(export.item, ExportItem::Table(_)));
+    assert_eq!(exported_tables.count(), 1);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+const I64_INDEX_MODULE: &str = r#"
+    (module
+        (import "externref" "insert" (func $insert_ref (param i64 i32) (result i64)))
+        (import "externref" "get" (func $get_ref (param i64) (result i64)))
+        (import "externref" "drop" (func $drop_ref (param i64)))
+        (import "externref" "drop_range" (func $drop_range (param i64 i64)))
+        (import "externref" "is_live" (func $is_live (param i64) (result i32)))
+
+        (func (export "test") (param $sender i64)
+            (local $id i64)
+            (local.set $id (call $insert_ref (local.get $sender) (i32.const -1)))
+            (drop (call $get_ref (local.get $id)))
+            (drop (call $is_live (local.get $id)))
+            (call $drop_range (local.get $id) (i64.const 1))
+            (call $drop_ref (local.get $id))
+        )
+    )
+"#;
+
+#[test]
+fn module_with_i64_index_type() {
+    let module = wat::parse_str(I64_INDEX_MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: TEST_BYTES.to_vec(),
+    });
+    let module_bytes = module.emit_wasm();
+
+    let err = Processor::default()
+        .process_bytes(&module_bytes)
+        .unwrap_err();
+    assert_matches!(
+        &err,
+        Error::UnexpectedIndexType { module, name, index_type: IndexType::I32 }
+            if module == "externref" && name == "insert"
+    );
+    assert_eq!(err.code(), "externref::unexpected_index_type");
+    assert!(err.help().unwrap().contains("set_index_type"), "{err}");
+
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    let artifacts = Processor::default()
+        .set_index_type(IndexType::I64)
+        .process(&mut module)
+        .unwrap();
+    let fn_signature = |name: &str| {
+        let function = module.funcs.by_name(name).unwrap();
+        let fn_type = module.types.get(module.funcs.get(function).ty());
+        (fn_type.params().to_vec(), fn_type.results().to_vec())
+    };
+    assert_eq!(
+        fn_signature("externref_insert_i64"),
+        (vec![EXTERNREF, ValType::I32], vec![ValType::I64])
+    );
+    assert_eq!(
+        fn_signature("externref_get_i64"),
+        (vec![ValType::I64], vec![EXTERNREF])
+    );
+    assert_eq!(
+        fn_signature("externref_drop_i64"),
+        (vec![ValType::I64], vec![])
+    );
+    assert_eq!(
+        fn_signature("externref_is_live_i64"),
+        (vec![ValType::I64], vec![ValType::I32])
+    );
+    assert_eq!(
+        fn_signature("externref_drop_range_i64"),
+        (vec![ValType::I64, ValType::I64], vec![])
+    );
+    // The surrogate reference in the declared export is replaced with `externref`.
+    let test_fn = module.exports.get_func("test").unwrap();
+    let test_type = module.types.get(module.funcs.get(test_fn).ty());
+    assert_eq!(test_type.params(), [EXTERNREF]);
+    // Artifacts refer to functions with `i32` IDs.
+    let insert_type = module
+        .types
+        .get(module.funcs.get(artifacts.insert_fn.unwrap()).ty());
+    assert_eq!(insert_type.results(), [ValType::I32]);
+
+    Module::from_buffer(&module.emit_wasm()).unwrap();
+}
+
+#[test]
+fn host_handles_with_mismatched_index_type() {
+    const CALLBACK: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "on_message",
+        externrefs: BitSlice::builder::<1>(1).build(),
+        host_handles: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const CALLBACK_BYTES: [u8; CALLBACK.custom_section_len()] = CALLBACK.custom_section();
+
+    let module = wat::parse_str(r#"(module (func (export "on_message") (param i32)))"#).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: CALLBACK_BYTES.to_vec(),
+    });
+    let module_bytes = module.emit_wasm();
+
+    Processor::default().process_bytes(&module_bytes).unwrap();
+    let err = Processor::default()
+        .set_index_type(IndexType::I64)
+        .process_bytes(&module_bytes)
+        .unwrap_err();
+    assert_matches!(
+        err,
+        Error::UnexpectedType {
+            location: processor::Location::Arg(0),
+            expected_type: ValType::I64,
+            real_type: ValType::I32,
+            ..
+        }
+    );
+}
+
+const BULK_DROP_MODULE: &str = r#"
+    (module
+        (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+        (import "externref" "drop_all" (func $drop_all))
+        (import "externref" "drop_range" (func $drop_range (param i32 i32)))
+
+        (func (export "test") (param $sender i32)
+            (drop (call $insert_ref (local.get $sender) (i32.const -1)))
+            (call $drop_range (i32.const 0) (i32.const 1))
+            (call $drop_all)
+        )
+    )
+"#;
+
+#[test]
+fn module_with_bulk_drops() {
+    let module = wat::parse_str(BULK_DROP_MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: TEST_BYTES.to_vec(),
+    });
+    let module_bytes = module.emit_wasm();
+
+    type Configure = fn(&mut Processor<'_>);
+    let configure_plain = |_: &mut Processor<'_>| {};
+    let configure_tombstones = |processor: &mut Processor<'_>| {
+        processor.set_drop_slot_value(DropSlotValue::Tombstone {
+            module: "hook",
+            name: "tombstone",
+        });
+    };
+    let configure_partitions = |processor: &mut Processor<'_>| {
+        processor.set_table_partitioning(Partitioning::PerKind);
+    };
+    for configure in [
+        configure_plain as Configure,
+        configure_tombstones,
+        configure_partitions,
+    ] {
+        let mut processor = Processor::default();
+        processor.set_drop_fn("hook", "drop_ref");
+        configure(&mut processor);
+        let mut module = Module::from_buffer(&module_bytes).unwrap();
+        processor.process(&mut module).unwrap();
+
+        assert!(module.imports.find("externref", "drop_all").is_none());
+        assert!(module.imports.find("externref", "drop_range").is_none());
+        // The drop hook is imported even though the module does not import `drop`.
+        assert!(module.imports.find("hook", "drop_ref").is_some());
+        let fn_names: HashSet<_> = module
+            .funcs
+            .iter()
+            .filter_map(|function| function.name.as_deref())
+            .collect();
+        assert!(fn_names.contains("externref_drop_all"), "{fn_names:?}");
+        assert!(fn_names.contains("externref_drop_range"), "{fn_names:?}");
+        // Check that the processed module is valid.
+        Module::from_buffer(&module.emit_wasm()).unwrap();
+    }
+}
+
+#[test]
+fn basic_module_with_renamed_imports_and_exports() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    Processor::default()
+        .set_drop_fn("hook", "drop_ref")
+        .rename_import(("arena", "alloc"), ("env", "arena_alloc"))
+        .rename_import(("hook", "drop_ref"), ("env", "drop_ref"))
+        .rename_import(("missing", "import"), ("env", "other"))
+        .rename_export("test", "run")
+        .process(&mut module)
+        .unwrap();
+
+    assert!(module.imports.find("arena", "alloc").is_none());
+    let import_id = module.imports.find("env", "arena_alloc").unwrap();
+    let import_id = match &module.imports.get(import_id).kind {
+        ImportKind::Function(fn_id) => *fn_id,
+        other => panic!("unexpected import type: {other:?}"),
+    };
+    // Check that the import is patched despite renaming.
+    let function_type = module.types.get(module.funcs.get(import_id).ty());
+    assert_eq!(function_type.params(), [EXTERNREF, ValType::I32]);
+    assert_eq!(function_type.results(), [EXTERNREF]);
+    assert!(module.imports.find("env", "drop_ref").is_some());
+
+    let export_names: Vec<_> = module.exports.iter().map(|export| &export.name).collect();
+    assert!(
+        export_names.contains(&&"run".to_owned()),
+        "{export_names:?}"
+    );
+    assert!(
+        !export_names.contains(&&"test".to_owned()),
+        "{export_names:?}"
+    );
 
-    // Check that the module is well-formed by converting it to bytes and back.
     let module_bytes = module.emit_wasm();
     Module::from_buffer(&module_bytes).unwrap();
 }
+
+#[test]
+fn renaming_missing_export() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    let err = Processor::default()
+        .rename_export("missing", "other")
+        .process(&mut module)
+        .unwrap_err();
+    assert_matches!(err, Error::NoExport(name) if name == "missing");
+}
+
+#[test]
+fn renaming_export_onto_existing_export() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let test_fn = module.exports.get_func("test").unwrap();
+    module.exports.add("run", test_fn);
+
+    let err = Processor::default()
+        .rename_export("test", "run")
+        .process(&mut module)
+        .unwrap_err();
+    assert_matches!(err, Error::DuplicateExport(name) if name == "run");
+}
+
+#[test]
+fn processing_module_twice() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    Processor::default().process(&mut module).unwrap();
+
+    let marker = module
+        .customs
+        .iter()
+        .find(|(_, section)| section.name() == Processor::MARKER_SECTION_NAME);
+    assert!(marker.is_some());
+
+    let module_bytes = module.emit_wasm();
+    let err = Processor::default()
+        .process_bytes(&module_bytes)
+        .unwrap_err();
+    assert_matches!(
+        err,
+        Error::AlreadyProcessed { version } if version == env!("CARGO_PKG_VERSION")
+    );
+    // The module must not be modified if processing is refused.
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(err, Error::AlreadyProcessed { .. });
+    let marker = module
+        .customs
+        .iter()
+        .find(|(_, section)| section.name() == Processor::MARKER_SECTION_NAME);
+    assert!(marker.is_some());
+
+    let reprocessed = Processor::default()
+        .set_force(true)
+        .process_bytes(&module_bytes)
+        .unwrap();
+    let reprocessed = Module::from_buffer(&reprocessed).unwrap();
+    let marker_count = reprocessed
+        .customs
+        .iter()
+        .filter(|(_, section)| section.name() == Processor::MARKER_SECTION_NAME)
+        .count();
+    assert_eq!(marker_count, 1);
+}
+
+#[test]
+fn processing_asyncified_module() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    // Emulate an export added by asyncify.
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+    builder.func_body().unreachable();
+    let fn_id = builder.finish(vec![module.locals.add(ValType::I32)], &mut module.funcs);
+    module.exports.add("async_start_unwind", fn_id);
+    let module_bytes = module.emit_wasm();
+
+    // Default prefixes do not match the export.
+    Processor::default().process_bytes(&module_bytes).unwrap();
+
+    let mut processor = Processor::default();
+    processor.set_asyncify_prefixes(&["async_"]);
+    let err = processor.process_bytes(&module_bytes).unwrap_err();
+    assert_matches!(
+        err,
+        Error::Asyncified { function_name } if function_name == "async_start_unwind"
+    );
+    let err = processor.verify(&module_bytes).unwrap_err();
+    assert_matches!(err, Error::Asyncified { .. });
+
+    processor.set_asyncify_prefixes(&[]);
+    processor.process_bytes(&module_bytes).unwrap();
+}
+
+#[test]
+fn processing_files() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let dir = env::temp_dir().join(format!("externref-process-file-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.wasm");
+    fs::write(&input, module.emit_wasm()).unwrap();
+
+    let output = dir.join("output.wasm");
+    Processor::default().process_file(&input, &output).unwrap();
+    let processed = fs::read(&output).unwrap();
+    Module::from_buffer(&processed).unwrap();
+
+    // The output must not be modified on failure.
+    let err = Processor::default()
+        .process_file(&output, &output)
+        .unwrap_err();
+    assert_matches!(
+        &err,
+        Error::File { path, error } if *path == output
+            && matches!(**error, Error::AlreadyProcessed { .. })
+    );
+    assert!(err.to_string().contains("output.wasm"), "{err}");
+    assert_eq!(fs::read(&output).unwrap(), processed);
+
+    let missing_input = dir.join("missing.wasm");
+    let err = Processor::default()
+        .process_file(&missing_input, &output)
+        .unwrap_err();
+    assert_matches!(err, Error::Io { path, .. } if path == missing_input);
+
+    // Check that no temporary files are left.
+    let mut file_names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    file_names.sort_unstable();
+    assert_eq!(file_names, ["input.wasm", "output.wasm"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn verifying_modules() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+    Processor::default().verify(&module_bytes).unwrap();
+
+    let err = Processor::default()
+        .rename_export("missing", "other")
+        .verify(&module_bytes)
+        .unwrap_err();
+    assert_matches!(err, Error::NoExport(name) if name == "missing");
+
+    let processed = Processor::default().process_bytes(&module_bytes).unwrap();
+    let err = Processor::default().verify(&processed).unwrap_err();
+    assert_matches!(err, Error::AlreadyProcessed { .. });
+
+    // Declare the exported function with an extra arg.
+    const TEST_WITH_EXTRA_ARG: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "test",
+        externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const TEST_WITH_EXTRA_ARG_BYTES: [u8; TEST_WITH_EXTRA_ARG.custom_section_len()] =
+        TEST_WITH_EXTRA_ARG.custom_section();
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    let mut section_data = ARENA_ALLOC_BYTES.to_vec();
+    section_data.extend_from_slice(&TEST_WITH_EXTRA_ARG_BYTES);
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+    let err = Processor::default()
+        .verify(&module.emit_wasm())
+        .unwrap_err();
+    assert_matches!(
+        err,
+        Error::UnexpectedArity { name, expected_arity: 2, real_arity: 1, .. } if name == "test"
+    );
+}
+
+#[test]
+fn module_with_multiple_memories() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    // Neither memory is referenced by the module code.
+    let (imported_memory, _) =
+        module.add_import_memory("env", "memory", false, false, 1, None, None);
+    let exported_memory = module.memories.add_local(false, false, 2, None, None);
+    module.exports.add("secondary", exported_memory);
+    let module_bytes = module.emit_wasm();
+
+    let processed = Processor::default().process_bytes(&module_bytes).unwrap();
+    let processed = Module::from_buffer(&processed).unwrap();
+    let memories: Vec<_> = processed.memories.iter().collect();
+    assert_eq!(memories.len(), 2);
+    assert!(memories[0].import.is_some());
+    assert_eq!(memories[0].initial, 1);
+    assert_eq!(memories[1].initial, 2);
+    let export = processed.exports.get_exported_memory(memories[1].id());
+    assert_eq!(export.unwrap().name, "secondary");
+    assert!(module.memories.get(imported_memory).import.is_some());
+
+    let mut features = WasmFeatures::default();
+    features.remove(WasmFeatures::MULTI_MEMORY);
+    let err = Processor::default()
+        .set_wasm_features(features)
+        .process_bytes(&module_bytes)
+        .unwrap_err();
+    assert_matches!(err, Error::Wasm(_));
+    let err = Processor::default()
+        .set_wasm_features(features)
+        .verify(&module_bytes)
+        .unwrap_err();
+    assert_matches!(err, Error::Wasm(_));
+
+    features.insert(WasmFeatures::MULTI_MEMORY);
+    Processor::default()
+        .set_wasm_features(features)
+        .process_bytes(&module_bytes)
+        .unwrap();
+}
+
+#[test]
+fn collecting_multiple_errors() {
+    const TEST_WITH_EXTRA_ARG: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "test",
+        externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const MISSING: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "missing",
+        externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    let section_data = [
+        MISSING
+            .custom_section::<{ MISSING.custom_section_len() }>()
+            .as_slice(),
+        &ARENA_ALLOC_BYTES,
+        &TEST_WITH_EXTRA_ARG.custom_section::<{ TEST_WITH_EXTRA_ARG.custom_section_len() }>(),
+    ]
+    .concat();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+    let module_bytes = module.emit_wasm();
+
+    let err = Processor::default().verify(&module_bytes).unwrap_err();
+    assert_matches!(err, Error::NoExport(name) if name == "missing");
+
+    let mut processor = Processor::default();
+    processor.set_continue_on_error(true);
+    let err = processor.verify(&module_bytes).unwrap_err();
+    let Error::Multiple(errors) = &err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert_matches!(&errors[0], Error::NoExport(name) if name == "missing");
+    assert_matches!(
+        &errors[1],
+        Error::UnexpectedArity { name, expected_arity: 2, real_arity: 1, .. } if name == "test"
+    );
+    let err = err.to_string();
+    assert!(
+        err.starts_with("2 errors occurred during processing:"),
+        "{err}"
+    );
+    assert!(
+        err.contains("\n1. missing exported function `missing`"),
+        "{err}"
+    );
+
+    let err = processor.process_bytes(&module_bytes).unwrap_err();
+    assert_matches!(err, Error::Multiple(errors) if errors.len() == 2);
+
+    // A single collected error should be returned as is.
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let err = processor
+        .rename_export("other_missing", "other")
+        .verify(&module.emit_wasm())
+        .unwrap_err();
+    assert_matches!(err, Error::NoExport(name) if name == "other_missing");
+}
+
+#[test]
+fn post_optimizing_unprocessed_module() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let optimizer = PostOptimizer::WasmOpt { args: vec!["-Os"] };
+    let err = optimizer.optimize(&module).unwrap_err();
+    assert_matches!(err, Error::NotProcessed);
+
+    let processed = Processor::default().process_bytes(&module).unwrap();
+    let same_module = PostOptimizer::None.optimize(&processed).unwrap();
+    assert_eq!(same_module, processed);
+}
+
+#[test]
+fn module_with_duplicate_declarations() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    // Emulate linking several objects with the same declarations; the linker concatenates
+    // custom sections with the same name.
+    let section_data = [
+        ARENA_ALLOC_BYTES.as_slice(),
+        &TEST_BYTES,
+        &ARENA_ALLOC_BYTES,
+    ]
+    .concat();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+    // Sections with the same name (e.g., produced by non-merging linkers) are merged as well.
+    add_basic_custom_section(&mut module);
+    Processor::default().process(&mut module).unwrap();
+    assert!(module
+        .customs
+        .iter()
+        .all(|(_, section)| section.name() != Function::CUSTOM_SECTION_NAME));
+
+    let import_id = module.imports.find("arena", "alloc").unwrap();
+    let ImportKind::Function(import_id) = module.imports.get(import_id).kind else {
+        unreachable!();
+    };
+    let function_type = module.types.get(module.funcs.get(import_id).ty());
+    assert_eq!(function_type.params(), [EXTERNREF, ValType::I32]);
+    assert_eq!(function_type.results(), [EXTERNREF]);
+}
+
+#[test]
+fn module_with_unused_resources_and_no_surrogate_imports() {
+    // Emulates a module where LTO has removed all uses of `Resource` methods,
+    // since the resource arg of the export is unused.
+    const MODULE_BYTES: &[u8] = br#"
+        (module
+            (func (export "test") (param $arena i32))
+        )
+    "#;
+
+    let module = wat::parse_bytes(MODULE_BYTES).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    Processor::default().process(&mut module).unwrap();
+
+    let export = module.exports.iter().find(|export| export.name == "test");
+    let ExportItem::Function(fn_id) = export.unwrap().item else {
+        unreachable!("not a function export");
+    };
+    let function_type = module.types.get(module.funcs.get(fn_id).ty());
+    assert_eq!(function_type.params(), [EXTERNREF]);
+}
+
+#[test]
+fn module_with_orphaned_declarations() {
+    const MODULE_BYTES: &[u8] = br#"
+        (module
+            (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+            (func (export "test") (param $arena i32)
+                (drop (call $alloc (local.get $arena) (i32.const 42)))
+            )
+        )
+    "#;
+
+    let module = wat::parse_bytes(MODULE_BYTES).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let err = Processor::default().process(&mut module).unwrap_err();
+
+    let Error::OrphanedDeclarations {
+        functions,
+        module,
+        missing_imports,
+    } = &err
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(
+        *functions,
+        [
+            (Some("arena".to_owned()), "alloc".to_owned()),
+            (None, "test".to_owned())
+        ]
+    );
+    assert_eq!(module, "externref");
+    assert_eq!(*missing_imports, ["insert", "get"]);
+    let err = err.to_string();
+    assert!(
+        err.contains("`alloc` imported from `arena`, `test`"),
+        "{err}"
+    );
+    assert!(
+        err.contains("`externref::insert`, `externref::get`"),
+        "{err}"
+    );
+}
+
+/// Adds an export re-exporting the `arena.alloc` import.
+fn reexport_arena_alloc(module: &mut Module) {
+    let import_id = module.imports.find("arena", "alloc").unwrap();
+    let ImportKind::Function(fn_id) = module.imports.get(import_id).kind else {
+        unreachable!("not a function import");
+    };
+    module.exports.add("alloc", fn_id);
+}
+
+#[test]
+fn module_with_reexported_import() {
+    const ALLOC_EXPORT: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "alloc",
+        ..ARENA_ALLOC
+    };
+    const ALLOC_EXPORT_BYTES: [u8; ALLOC_EXPORT.custom_section_len()] =
+        ALLOC_EXPORT.custom_section();
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let sections = [
+        // Only the import is declared.
+        [ARENA_ALLOC_BYTES.as_slice(), &TEST_BYTES].concat(),
+        // Both the import and the export are declared.
+        [
+            ARENA_ALLOC_BYTES.as_slice(),
+            &TEST_BYTES,
+            &ALLOC_EXPORT_BYTES,
+        ]
+        .concat(),
+        [
+            ALLOC_EXPORT_BYTES.as_slice(),
+            &TEST_BYTES,
+            &ARENA_ALLOC_BYTES,
+        ]
+        .concat(),
+        // Only the export is declared.
+        [TEST_BYTES.as_slice(), &ALLOC_EXPORT_BYTES].concat(),
+    ];
+
+    for section_data in sections {
+        let mut module = Module::from_buffer(&module).unwrap();
+        reexport_arena_alloc(&mut module);
+        module.customs.add(RawCustomSection {
+            name: Function::CUSTOM_SECTION_NAME.to_owned(),
+            data: section_data,
+        });
+        Processor::default().process(&mut module).unwrap();
+
+        let export = module.exports.get_func("alloc").unwrap();
+        let import_id = module.imports.find("arena", "alloc").unwrap();
+        assert_eq!(
+            module.imports.get(import_id).kind,
+            ImportKind::Function(export)
+        );
+        let function_type = module.types.get(module.funcs.get(export).ty());
+        assert_eq!(function_type.params(), [EXTERNREF, ValType::I32]);
+        assert_eq!(function_type.results(), [EXTERNREF]);
+
+        let module_bytes = module.emit_wasm();
+        Module::from_buffer(&module_bytes).unwrap();
+    }
+}
+
+#[test]
+fn module_with_conflicting_reexport_declaration() {
+    const ALLOC_EXPORT: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "alloc",
+        externrefs: BitSlice::builder::<1>(3).with_set_bit(0).build(),
+        ..ARENA_ALLOC
+    };
+    const ALLOC_EXPORT_BYTES: [u8; ALLOC_EXPORT.custom_section_len()] =
+        ALLOC_EXPORT.custom_section();
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    reexport_arena_alloc(&mut module);
+    let section_data = [
+        ARENA_ALLOC_BYTES.as_slice(),
+        &TEST_BYTES,
+        &ALLOC_EXPORT_BYTES,
+    ]
+    .concat();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(
+        err,
+        Error::ConflictingDeclarations { module: None, name } if name == "alloc"
+    );
+}
+
+#[test]
+fn module_with_conflicting_declarations() {
+    const CONFLICTING_ALLOC: Function<'static> = Function {
+        kind: FunctionKind::Import("arena"),
+        name: "alloc",
+        externrefs: BitSlice::builder::<1>(3).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    let conflicting_bytes =
+        CONFLICTING_ALLOC.custom_section::<{ CONFLICTING_ALLOC.custom_section_len() }>();
+    let section_data = [
+        ARENA_ALLOC_BYTES.as_slice(),
+        &TEST_BYTES,
+        &conflicting_bytes,
+    ]
+    .concat();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(
+        err,
+        Error::ConflictingDeclarations { module: Some(module), name }
+            if module == "arena" && name == "alloc"
+    );
+}
+
+const REEXPORTED_ALLOC: Function<'static> = Function {
+    kind: FunctionKind::Reexport {
+        module: "arena",
+        import_name: "alloc",
+    },
+    name: "alloc_bytes",
+    ..ARENA_ALLOC
+};
+const REEXPORTED_ALLOC_BYTES: [u8; REEXPORTED_ALLOC.custom_section_len()] =
+    REEXPORTED_ALLOC.custom_section();
+
+#[test]
+fn module_with_reexport_declaration() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let sections = [
+        [
+            ARENA_ALLOC_BYTES.as_slice(),
+            &TEST_BYTES,
+            &REEXPORTED_ALLOC_BYTES,
+        ]
+        .concat(),
+        // The import is not declared on its own.
+        [TEST_BYTES.as_slice(), &REEXPORTED_ALLOC_BYTES].concat(),
+    ];
+
+    for section_data in sections {
+        let mut module = Module::from_buffer(&module).unwrap();
+        module.customs.add(RawCustomSection {
+            name: Function::CUSTOM_SECTION_NAME.to_owned(),
+            data: section_data,
+        });
+        let module_bytes = module.emit_wasm();
+        let manifest = Processor::default().manifest(&module_bytes).unwrap();
+        assert!(manifest
+            .imports
+            .iter()
+            .any(
+                |function| function.module.as_deref() == Some("arena") && function.name == "alloc"
+            ));
+        let export = manifest
+            .exports
+            .iter()
+            .find(|function| function.name == "alloc_bytes");
+        let export = export.unwrap();
+        assert_eq!(export.params, ["externref", "i32"]);
+        assert_eq!(export.results, ["externref"]);
+
+        let events = EventLog::default();
+        let processed = Processor::default()
+            .add_observer(&events)
+            .process_bytes(&module_bytes)
+            .unwrap();
+        assert!(events.events().contains(&ProcessingEvent::ImportPatched {
+            module: "arena".to_owned(),
+            name: "alloc".to_owned(),
+        }));
+
+        let module = Module::from_buffer(&processed).unwrap();
+        let export_id = module.exports.get_func("alloc_bytes").unwrap();
+        let import_id = module.imports.get_func("arena", "alloc").unwrap();
+        assert_eq!(export_id, import_id);
+        let function_type = module.types.get(module.funcs.get(export_id).ty());
+        assert_eq!(function_type.params(), [EXTERNREF, ValType::I32]);
+        assert_eq!(function_type.results(), [EXTERNREF]);
+    }
+}
+
+#[test]
+fn module_with_reexport_shadowed_by_local_function() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    // Add a local function occupying the export name.
+    let mut builder = walrus::FunctionBuilder::new(
+        &mut module.types,
+        &[ValType::I32, ValType::I32],
+        &[ValType::I32],
+    );
+    builder.func_body().i32_const(-1);
+    let args = vec![
+        module.locals.add(ValType::I32),
+        module.locals.add(ValType::I32),
+    ];
+    let shim_id = builder.finish(args, &mut module.funcs);
+    module.exports.add("alloc_bytes", shim_id);
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: [TEST_BYTES.as_slice(), &REEXPORTED_ALLOC_BYTES].concat(),
+    });
+
+    Processor::default().process(&mut module).unwrap();
+
+    let export_id = module.exports.get_func("alloc_bytes").unwrap();
+    let trampoline = module.funcs.get(export_id);
+    assert_eq!(
+        trampoline.name.as_deref(),
+        Some("externref_reexport_alloc_bytes")
+    );
+    let import_id = module.imports.get_func("arena", "alloc").unwrap();
+    assert_eq!(trampoline.ty(), module.funcs.get(import_id).ty());
+    let function_type = module.types.get(trampoline.ty());
+    assert_eq!(function_type.params(), [EXTERNREF, ValType::I32]);
+    assert_eq!(function_type.results(), [EXTERNREF]);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+const ALIASED_MODULE: &str = r#"
+    (module
+      (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+      (import "externref" "get" (func $get_ref (param i32) (result i32)))
+      (import "test" "create" (func $create (result i32)))
+
+      (func $run (result i32)
+        (call $get_ref (call $insert_ref (call $create) (i32.const -1)))
+      )
+      (export "run" (func $run))
+      (export "_start" (func $run))
+    )
+"#;
+
+fn aliased_module(declarations: &[&[u8]]) -> Module {
+    const CREATE: Function<'static> = Function {
+        kind: FunctionKind::Import("test"),
+        name: "create",
+        externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const CREATE_BYTES: [u8; CREATE.custom_section_len()] = CREATE.custom_section();
+
+    let module = wat::parse_str(ALIASED_MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    let mut section_data = CREATE_BYTES.to_vec();
+    for declaration in declarations {
+        section_data.extend_from_slice(declaration);
+    }
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+    module
+}
+
+#[test]
+fn module_with_aliased_fallible_export() {
+    // Corresponds to `fn run() -> Result<Resource<_>, i32>`.
+    const RUN: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "run",
+        externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const RUN_BYTES: [u8; RUN.custom_section_len()] = RUN.custom_section();
+
+    let mut module = aliased_module(&[&RUN_BYTES]);
+    Processor::default().process(&mut module).unwrap();
+
+    let run_id = module.exports.get_func("run").unwrap();
+    let alias_id = module.exports.get_func("_start").unwrap();
+    assert_eq!(run_id, alias_id);
+    let function_type = module.types.get(module.funcs.get(run_id).ty());
+    assert_eq!(function_type.params(), []);
+    assert_eq!(function_type.results(), [EXTERNREF, ValType::I32]);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn module_with_conflicting_alias_declarations() {
+    const RUN: Function<'static> = Function {
+        kind: FunctionKind::Export,
+        name: "run",
+        externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    };
+    const RUN_BYTES: [u8; RUN.custom_section_len()] = RUN.custom_section();
+    const START: Function<'static> = Function {
+        name: "_start",
+        ..RUN
+    };
+    const START_BYTES: [u8; START.custom_section_len()] = START.custom_section();
+    const CONFLICTING_START: Function<'static> = Function {
+        externrefs: BitSlice::builder::<1>(1).build(),
+        ..START
+    };
+    const CONFLICTING_START_BYTES: [u8; CONFLICTING_START.custom_section_len()] =
+        CONFLICTING_START.custom_section();
+
+    // Agreeing declarations of aliases are fine.
+    let mut module = aliased_module(&[&RUN_BYTES, &START_BYTES]);
+    Processor::default().process(&mut module).unwrap();
+    let alias_id = module.exports.get_func("_start").unwrap();
+    let function_type = module.types.get(module.funcs.get(alias_id).ty());
+    assert_eq!(function_type.results(), [EXTERNREF]);
+
+    let mut module = aliased_module(&[&RUN_BYTES, &CONFLICTING_START_BYTES]);
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(
+        err,
+        Error::ConflictingDeclarations { module: None, name } if name == "_start"
+    );
+}
+
+/// Module with an export wrapped in a trampoline, similar to ones generated by adapters
+/// for the `wasm32-wasip2` target.
+const TRAMPOLINE_MODULE: &str = r#"
+    (module
+      (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+
+      (func $test_impl (param $ref i32)
+        (drop (call $insert_ref (local.get $ref) (i32.const -1)))
+      )
+      (func $test (param i32)
+        (call $test_impl (local.get 0))
+      )
+      (export "test" (func $test))
+    )
+"#;
+
+#[test]
+fn module_with_export_trampoline() {
+    let module = wat::parse_str(TRAMPOLINE_MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: TEST_BYTES.to_vec(),
+    });
+    Processor::default().process(&mut module).unwrap();
+
+    let test_id = module.exports.get_func("test").unwrap();
+    let test_fn = module.funcs.get(test_id);
+    assert_eq!(test_fn.name.as_deref(), Some("test_impl"));
+    let function_type = module.types.get(test_fn.ty());
+    assert_eq!(function_type.params(), [EXTERNREF]);
+    assert!(module.funcs.by_name("test").is_none());
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+/// Hook recording patched functions and counting calls to the `insert` surrogate.
+#[derive(Debug, Default)]
+struct InstrumentingHook {
+    patched_fns: RefCell<Vec<(String, usize)>>,
+}
+
+impl ProcessingHook for InstrumentingHook {
+    fn on_patched_function(
+        &self,
+        module: &mut Module,
+        function: &PatchedFunction<'_>,
+    ) -> anyhow::Result<()> {
+        let name = match function.kind {
+            PatchedFunctionKind::Surrogate(name) => format!("externref::{name}"),
+            PatchedFunctionKind::Export(declaration)
+            | PatchedFunctionKind::TableEntry(declaration) => {
+                assert_eq!(function.declaration(), Some(&declaration));
+                declaration.name.to_owned()
+            }
+            PatchedFunctionKind::Local => "(local)".to_owned(),
+            _ => anyhow::bail!("unexpected function kind"),
+        };
+        for &local in &function.new_locals {
+            anyhow::ensure!(module.locals.get(local).ty() == EXTERNREF);
+        }
+        self.patched_fns
+            .borrow_mut()
+            .push((name, function.new_locals.len()));
+
+        if function.kind == PatchedFunctionKind::Surrogate("insert") {
+            let counter = module.globals.add_local(
+                ValType::I32,
+                true,
+                false,
+                ConstExpr::Value(ir::Value::I32(0)),
+            );
+            module.exports.add("insert_count", counter);
+
+            let local_fn = function.local_fn(module);
+            let mut builder = local_fn.builder_mut().func_body();
+            builder
+                .global_get_at(0, counter)
+                .const_at(1, ir::Value::I32(1))
+                .binop_at(2, ir::BinaryOp::I32Add)
+                .global_set_at(3, counter);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FailingHook;
+
+impl ProcessingHook for FailingHook {
+    fn on_patched_function(
+        &self,
+        _module: &mut Module,
+        _function: &PatchedFunction<'_>,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("oops")
+    }
+}
+
+const CALLBACKS_MODULE: &str = r#"
+    (module
+        (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+        (import "externref" "get" (func $get_ref (param i32) (result i32)))
+        (import "externref" "drop" (func $drop_ref (param i32)))
+        (import "externref" "guard" (func $guard))
+
+        (type $callback (func (param i32)))
+        (type $producer (func (result i32)))
+        (table 2 funcref)
+        (elem (i32.const 0) $on_event $produce)
+
+        (func $on_event (param $event i32)
+            (call $drop_ref (call $insert_ref (local.get $event) (i32.const -1)))
+        )
+        (func $produce (result i32)
+            (call $get_ref (i32.const 0))
+        )
+
+        (func (export "dispatch") (param $event i32) (param $idx i32)
+            (local $ref i32)
+            (call $guard)
+            (call_indirect (type $callback) (call $get_ref (local.get $event)) (local.get $idx))
+            (local.set $ref (call_indirect (type $producer) (i32.const 1)))
+            (call $drop_ref (call $insert_ref (local.get $ref) (i32.const -1)))
+        )
+    )
+"#;
+
+const ON_EVENT: Function<'static> = Function {
+    kind: FunctionKind::TableEntry,
+    name: "on_event",
+    externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+const ON_EVENT_BYTES: [u8; ON_EVENT.custom_section_len()] = ON_EVENT.custom_section();
+
+const PRODUCE: Function<'static> = Function {
+    kind: FunctionKind::TableEntry,
+    name: "produce",
+    externrefs: BitSlice::builder::<1>(1).with_set_bit(0).build(),
+    host_handles: BitSlice::EMPTY,
+    type_names: TypeNames::EMPTY,
+    surrogate_module: None,
+};
+const PRODUCE_BYTES: [u8; PRODUCE.custom_section_len()] = PRODUCE.custom_section();
+
+fn callbacks_module(module_str: &str) -> Module {
+    let module = wat::parse_str(module_str).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: [ON_EVENT_BYTES.as_slice(), &PRODUCE_BYTES].concat(),
+    });
+    module
+}
+
+#[test]
+fn module_with_table_entries() {
+    let mut module = callbacks_module(CALLBACKS_MODULE);
+    let hook = InstrumentingHook::default();
+    Processor::default()
+        .add_hook(&hook)
+        .process(&mut module)
+        .unwrap();
+    let mut patched_fns = hook.patched_fns.into_inner();
+    patched_fns.retain(|(name, _)| !name.starts_with("externref::"));
+    patched_fns.sort_unstable();
+    assert_eq!(
+        patched_fns,
+        [
+            ("(local)".to_owned(), 1), // `dispatch` receiving a ref from `call_indirect`
+            ("on_event".to_owned(), 1),
+            ("produce".to_owned(), 0),
+        ]
+    );
+
+    let on_event = module.funcs.by_name("on_event").unwrap();
+    let on_event_ty = module.types.get(module.funcs.get(on_event).ty());
+    assert_eq!(on_event_ty.params(), [EXTERNREF]);
+    assert_eq!(on_event_ty.results(), []);
+    let produce = module.funcs.by_name("produce").unwrap();
+    let produce_ty = module.types.get(module.funcs.get(produce).ty());
+    assert_eq!(produce_ty.params(), []);
+    assert_eq!(produce_ty.results(), [EXTERNREF]);
+
+    // Indirect calls must be patched; otherwise, the module would fail validation.
+    let module_bytes = module.emit_wasm();
+    wasmparser::Validator::new()
+        .validate_all(&module_bytes)
+        .unwrap();
+}
+
+/// Table entries declared via `#[externref(indirect_export)]` are also exported
+/// under their name.
+#[test]
+fn module_with_exported_table_entry() {
+    let module_str = CALLBACKS_MODULE.replace(
+        "(func $on_event (param $event i32)",
+        "(func $on_event (export \"on_event\") (param $event i32)",
+    );
+    let manifest = Processor::default()
+        .manifest(&callbacks_module(&module_str).emit_wasm())
+        .unwrap();
+    assert!(manifest.exports.is_empty());
+    assert_eq!(manifest.table_entries.len(), 2);
+
+    let mut module = callbacks_module(&module_str);
+    Processor::default().process(&mut module).unwrap();
+    let export = module.exports.get_func("on_event").unwrap();
+    assert_eq!(module.funcs.by_name("on_event"), Some(export));
+    let on_event_ty = module.types.get(module.funcs.get(export).ty());
+    assert_eq!(on_event_ty.params(), [EXTERNREF]);
+
+    let module_bytes = module.emit_wasm();
+    wasmparser::Validator::new()
+        .validate_all(&module_bytes)
+        .unwrap();
+}
+
+#[test]
+fn exported_table_entry_without_name_section() {
+    let module_str = CALLBACKS_MODULE.replace(
+        "(func $on_event (param $event i32)",
+        "(func $on_event (export \"on_event\") (param $event i32)",
+    );
+    let mut module = callbacks_module(&module_str);
+    // Emulate stripping the `name` section, e.g. by `wasm-opt --strip-debug`.
+    for function in module.funcs.iter_mut() {
+        function.name = None;
+    }
+    // `produce` is not exported and thus cannot be resolved.
+    let err = Processor::default()
+        .process_bytes(&module.emit_wasm())
+        .unwrap_err();
+    assert_matches!(&err, Error::NoTableEntry(name) if name == "produce");
+
+    let module_str = module_str.replace(
+        "(func $produce (result i32)",
+        "(func $produce (export \"produce\") (result i32)",
+    );
+    let mut module = callbacks_module(&module_str);
+    for function in module.funcs.iter_mut() {
+        function.name = None;
+    }
+    let module_bytes = module.emit_wasm();
+    let functions = processor::inspect(&module_bytes).unwrap();
+    assert_eq!(functions[0].resource_args, [0]);
+    assert_eq!(functions[1].resource_results, [0]);
+
+    let module_bytes = Processor::default().process_bytes(&module_bytes).unwrap();
+    let module = Module::from_buffer(&module_bytes).unwrap();
+    let on_event = module.exports.get_func("on_event").unwrap();
+    let on_event_ty = module.types.get(module.funcs.get(on_event).ty());
+    assert_eq!(on_event_ty.params(), [EXTERNREF]);
+    let produce = module.exports.get_func("produce").unwrap();
+    let produce_ty = module.types.get(module.funcs.get(produce).ty());
+    assert_eq!(produce_ty.results(), [EXTERNREF]);
+}
+
+#[test]
+fn inspecting_table_entries() {
+    let module_bytes = callbacks_module(CALLBACKS_MODULE).emit_wasm();
+    let functions = processor::inspect(&module_bytes).unwrap();
+    assert_eq!(functions.len(), 2);
+    assert_eq!(functions[0].name(), "on_event");
+    assert_eq!(functions[0].module(), None);
+    assert_eq!(functions[0].resource_args, [0]);
+    assert_eq!(functions[1].name(), "produce");
+    assert_eq!(functions[1].resource_results, [0]);
+
+    let manifest = Processor::default().manifest(&module_bytes).unwrap();
+    assert!(manifest.exports.is_empty());
+    let table_entries: Vec<_> = manifest
+        .table_entries
+        .iter()
+        .map(|function| (function.name.as_str(), &function.params, &function.results))
+        .collect();
+    assert_eq!(
+        table_entries,
+        [
+            ("on_event", &vec!["externref".to_owned()], &vec![]),
+            ("produce", &vec![], &vec!["externref".to_owned()]),
+        ]
+    );
+}
+
+#[test]
+fn module_with_ambiguous_table_entries() {
+    let module_str = CALLBACKS_MODULE.replace(
+        "(elem (i32.const 0) $on_event $produce)",
+        "(elem (i32.const 0) $on_event $produce $other)
+        (func $other (param i32))",
+    );
+    let mut module = callbacks_module(&module_str);
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(
+        &err,
+        Error::AmbiguousTableEntry { name, other } if name == "on_event" && other == "other"
+    );
+}
+
+#[test]
+fn module_with_unrelated_fn_table() {
+    // `$other` has the same type as `$on_event`, but is placed in another table,
+    // so it does not make `$on_event` ambiguous.
+    let module_str = CALLBACKS_MODULE.replace(
+        "(elem (i32.const 0) $on_event $produce)",
+        "(elem (i32.const 0) $on_event $produce)
+        (table $other_table 1 funcref)
+        (elem (table $other_table) (i32.const 0) func $other)
+        (func $other (param i32))
+        (func (export \"call_other\") (param $arg i32)
+            (call_indirect $other_table (type $callback) (local.get $arg) (i32.const 0))
+        )",
+    );
+    let mut module = callbacks_module(&module_str);
+    Processor::default().process(&mut module).unwrap();
+
+    let other = module.funcs.by_name("other").unwrap();
+    let other_ty = module.types.get(module.funcs.get(other).ty());
+    assert_eq!(other_ty.params(), [ValType::I32]);
+    // The `call_indirect` instruction in `call_other` must not be patched; otherwise,
+    // the module would fail validation.
+    let module_bytes = module.emit_wasm();
+    wasmparser::Validator::new()
+        .validate_all(&module_bytes)
+        .unwrap();
+}
+
+#[test]
+fn module_with_missing_table_entry() {
+    // `$on_event` is not placed in the table.
+    let module_str = CALLBACKS_MODULE.replace(
+        "(elem (i32.const 0) $on_event $produce)",
+        "(elem (i32.const 0) $produce)",
+    );
+    let mut module = callbacks_module(&module_str);
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(&err, Error::NoTableEntry(name) if name == "on_event");
+}
+
+#[test]
+fn processing_hooks() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    let hook = InstrumentingHook::default();
+    Processor::default()
+        .add_hook(&hook)
+        .process(&mut module)
+        .unwrap();
+
+    let patched_fns = hook.patched_fns.into_inner();
+    assert_eq!(
+        patched_fns,
+        [
+            ("externref::insert".to_owned(), 0),
+            ("externref::get".to_owned(), 0),
+            ("externref::drop".to_owned(), 0),
+            ("test".to_owned(), 1),
+        ]
+    );
+    assert!(module
+        .exports
+        .iter()
+        .any(|export| export.name == "insert_count"));
+    // Check that the instrumented module is valid.
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+
+    let mut module = Module::from_buffer(&wat::parse_file(simple_module_path()).unwrap()).unwrap();
+    add_basic_custom_section(&mut module);
+    let err = Processor::default()
+        .add_hook(&FailingHook)
+        .process(&mut module)
+        .unwrap_err();
+    assert_matches!(err, Error::Hook(err) if err.to_string() == "oops");
+}
+
+#[test]
+fn inspecting_declarations() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let functions = processor::inspect(&module_bytes).unwrap();
+    assert_eq!(functions.len(), 2);
+    let alloc = &functions[0];
+    assert_eq!(alloc.name(), "alloc");
+    assert_eq!(alloc.module(), Some("arena"));
+    assert_eq!(alloc.resource_args, [0]);
+    assert_eq!(alloc.resource_results, [0]);
+    let test = &functions[1];
+    assert_eq!(test.name(), "test");
+    assert_eq!(test.module(), None);
+    assert_eq!(test.resource_args, [0]);
+    assert!(test.resource_results.is_empty());
+
+    // The processed module has no declarations.
+    let processed = Processor::default().process_bytes(&module_bytes).unwrap();
+    assert!(processor::inspect(&processed).unwrap().is_empty());
+}
+
+#[test]
+fn creating_manifest() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let manifest = Processor::default()
+        .set_drop_fn("hook", "drop_ref")
+        .rename_import(("arena", "alloc"), ("env", "arena_alloc"))
+        .enable_resource_metrics()
+        .rename_export(Processor::PEAK_COUNT_EXPORT, "peak_refs")
+        .manifest(&module_bytes)
+        .unwrap();
+    let manifest = serde_json::to_value(manifest).unwrap();
+    assert_eq!(
+        manifest,
+        serde_json::json!({
+            "version": 1,
+            "imports": [{
+                "module": "env",
+                "name": "arena_alloc",
+                "params": ["externref", "i32"],
+                "results": ["externref"],
+                "externref_args": [0],
+                "externref_results": [0],
+            }],
+            "exports": [{
+                "name": "test",
+                "params": ["externref"],
+                "results": [],
+                "externref_args": [0],
+                "externref_results": [],
+            }],
+            "table": {
+                "export_name": "externrefs",
+                "initial_size": 0,
+                "max_size": null,
+            },
+            "resource_table": null,
+            "resource_global": null,
+            "drop_fn": { "module": "hook", "name": "drop_ref" },
+            "drop_fn_with_kind": false,
+            "resource_kinds": [],
+            "tombstone": null,
+            "leak_fn": null,
+            "resource_metrics": {
+                "live_count": "externref_live_count",
+                "peak_count": "peak_refs",
+            },
+        })
+    );
+}
+
+#[test]
+fn emitting_interface() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor.set_drop_fn("hook", "drop_ref");
+    let json = processor
+        .emit_interface(&module_bytes, InterfaceFormat::Json)
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    assert_eq!(json, serde_json::to_value(manifest).unwrap());
+
+    let wit = processor
+        .emit_interface(&module_bytes, InterfaceFormat::Wit)
+        .unwrap();
+    let expected = "\
+        // Interface of a WASM module processed by `externref` (manifest version 1).\n\
+        world module {\n    \
+            resource externref;\n\n    \
+            /// WASM module: `arena`\n    \
+            import arena: interface {\n        \
+                /// WASM name: `alloc`\n        \
+                alloc: func(arg0: externref, arg1: s32) -> externref;\n    \
+            }\n\n    \
+            /// WASM module: `hook`\n    \
+            import hook: interface {\n        \
+                /// WASM name: `drop_ref`\n        \
+                drop-ref: func(arg0: externref);\n    \
+            }\n\n    \
+            /// WASM name: `test`\n    \
+            export test: func(arg0: externref);\n\
+        }\n";
+    assert_eq!(wit, expected, "{wit}");
+}
+
+#[test]
+fn module_with_slot_allocator() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor
+        .set_slot_allocator("hook", "alloc_slot")
+        .rename_import(("hook", "alloc_slot"), ("host", "alloc"));
+    let processed = processor.process_bytes(&module_bytes).unwrap();
+    let module = Module::from_buffer(&processed).unwrap();
+    let import = module.imports.find("host", "alloc").unwrap();
+    let ImportKind::Function(fn_id) = module.imports.get(import).kind else {
+        panic!("unexpected import kind");
+    };
+    let ty = module.types.get(module.funcs.get(fn_id).ty());
+    assert!(ty.params().is_empty());
+    assert_eq!(ty.results(), [ValType::I32]);
+
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let allocator = manifest.slot_allocator.as_ref().unwrap();
+    assert_eq!(
+        (allocator.module.as_str(), allocator.name.as_str()),
+        ("host", "alloc")
+    );
+    let wit = processor
+        .emit_interface(&module_bytes, InterfaceFormat::Wit)
+        .unwrap();
+    assert!(wit.contains("alloc: func() -> s32;"), "{wit}");
+}
+
+#[test]
+fn emitting_js_glue() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor.set_drop_fn("hook", "drop_ref");
+    let js = processor
+        .emit_interface(&module_bytes, InterfaceFormat::JsGlue)
+        .unwrap();
+    assert!(
+        js.starts_with("// Glue for a WASM module processed by `externref`"),
+        "{js}"
+    );
+    assert!(
+        js.contains("const REQUIRED_IMPORTS = [\n  [\"arena\", \"alloc\"],\n];"),
+        "{js}"
+    );
+    assert!(
+        js.contains("withImport(wasmImports, \"hook\", \"drop_ref\", (ref) => dropRef(ref));"),
+        "{js}"
+    );
+    assert!(js.contains("@param {unknown} arg0\n"), "{js}");
+    assert!(
+        js.contains("\"test\": (...args) => {\n        checkArgs(\"test\", args, 1);"),
+        "{js}"
+    );
+    assert!(js.contains("table: raw[\"externrefs\"],"), "{js}");
+    assert!(!js.contains("TOMBSTONE"), "{js}");
+    assert!(!js.contains("onLeak"), "{js}");
+}
+
+#[test]
+fn emitting_node_glue() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor.set_drop_fn("hook", "drop_ref");
+    let js = processor
+        .emit_interface(&module_bytes, InterfaceFormat::NodeGlue)
+        .unwrap();
+    assert!(
+        js.starts_with("// Glue for a WASM module processed by `externref` for Node.js"),
+        "{js}"
+    );
+    assert!(
+        js.contains("import { readFile } from \"node:fs/promises\";"),
+        "{js}"
+    );
+    assert!(!js.contains("Response"), "{js}");
+    assert!(
+        js.contains("Object.assign(wasmImports, options.wasi.getImportObject(), imports);"),
+        "{js}"
+    );
+    assert!(js.contains("options.wasi?.initialize(instance);"), "{js}");
+    assert!(
+        js.contains("withImport(wasmImports, \"hook\", \"drop_ref\", (ref) => dropRef(ref));"),
+        "{js}"
+    );
+    assert!(js.contains("const table = raw[\"externrefs\"];"), "{js}");
+    assert!(js.contains("if (!(ref === null)) entries.push"), "{js}");
+
+    processor.set_drop_slot_value(DropSlotValue::Tombstone {
+        module: "hook",
+        name: "tombstone",
+    });
+    let js = processor
+        .emit_interface(&module_bytes, InterfaceFormat::NodeGlue)
+        .unwrap();
+    assert!(
+        js.contains("if (!(ref === null || ref === tombstone)) entries.push"),
+        "{js}"
+    );
+}
+
+#[test]
+fn manifest_with_type_names() {
+    const ARENA_ALLOC_WITH_NAMES: Function<'static> = Function {
+        type_names: TypeNames::from_raw("Arena\nDataBytes"),
+        surrogate_module: None,
+        ..ARENA_ALLOC
+    };
+    const TEST_WITH_NAMES: Function<'static> = Function {
+        type_names: TypeNames::from_raw("Arena"),
+        surrogate_module: None,
+        ..TEST
+    };
+
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    let mut section_data = ARENA_ALLOC_WITH_NAMES
+        .custom_section::<{ ARENA_ALLOC_WITH_NAMES.custom_section_len() }>()
+        .to_vec();
+    section_data.extend_from_slice(
+        &TEST_WITH_NAMES.custom_section::<{ TEST_WITH_NAMES.custom_section_len() }>(),
+    );
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: section_data,
+    });
+    let module_bytes = module.emit_wasm();
+
+    let processor = Processor::default();
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    assert_eq!(manifest.imports[0].resource_types, ["Arena", "DataBytes"]);
+    assert_eq!(manifest.exports[0].resource_types, ["Arena"]);
+
+    let wit = processor
+        .emit_interface(&module_bytes, InterfaceFormat::Wit)
+        .unwrap();
+    assert!(
+        wit.contains("resource arena;\n    resource data-bytes;\n"),
+        "{wit}"
+    );
+    assert!(
+        wit.contains("alloc: func(arg0: arena, arg1: s32) -> data-bytes;"),
+        "{wit}"
+    );
+    assert!(wit.contains("export test: func(arg0: arena);"), "{wit}");
+
+    // Check that type names do not influence processing.
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    processor.process(&mut module).unwrap();
+    assert!(module
+        .customs
+        .iter()
+        .all(|(_, section)| { section.name() != Function::CUSTOM_SECTION_NAME }));
+}
+
+#[test]
+fn module_with_resource_metrics() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    Processor::default()
+        .enable_resource_metrics()
+        .rename_export(Processor::PEAK_COUNT_EXPORT, "peak_refs")
+        .process(&mut module)
+        .unwrap();
+
+    for name in [Processor::LIVE_COUNT_EXPORT, "peak_refs"] {
+        let export = module.exports.iter().find(|export| export.name == name);
+        let export = export.unwrap_or_else(|| panic!("missing export `{name}`"));
+        let ExportItem::Global(global_id) = export.item else {
+            panic!("unexpected export: {export:?}");
+        };
+        let global = module.globals.get(global_id);
+        assert_eq!(global.ty, ValType::I32);
+        assert!(global.mutable);
+    }
+    // Check that the processed module is valid.
+    Module::from_buffer(&module.emit_wasm()).unwrap();
+}
+
+#[test]
+fn module_with_snapshot_helpers() {
+    let module = wat::parse_file(kinds_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    type Configure = fn(&mut Processor<'_>);
+    let configure_tombstones = |processor: &mut Processor<'_>| {
+        processor
+            .set_drop_fn_with_kind("hook", "drop_ref")
+            .set_drop_slot_value(DropSlotValue::Tombstone {
+                module: "hook",
+                name: "tombstone",
+            });
+    };
+    let configure_partitions = |processor: &mut Processor<'_>| {
+        processor.set_table_partitioning(Partitioning::PerKind);
+    };
+    for configure in [configure_tombstones as Configure, configure_partitions] {
+        let mut processor = Processor::default();
+        processor
+            .enable_snapshot_helpers()
+            .rename_export(Processor::RESTORE_PREPARE_EXPORT, "restore");
+        configure(&mut processor);
+        let manifest = processor.manifest(&module_bytes).unwrap();
+        let helpers = manifest.snapshot_helpers.unwrap();
+        assert_eq!(helpers.snapshot_len, Processor::SNAPSHOT_LEN_EXPORT);
+        assert_eq!(helpers.restore_prepare, "restore");
+
+        let mut module = Module::from_buffer(&module_bytes).unwrap();
+        processor.process(&mut module).unwrap();
+        let expected_signatures = [
+            (
+                Processor::SNAPSHOT_LEN_EXPORT,
+                [].as_slice(),
+                [ValType::I32].as_slice(),
+            ),
+            ("restore", &[ValType::I32], &[]),
+        ];
+        for (name, params, results) in expected_signatures {
+            let export = module.exports.iter().find(|export| export.name == name);
+            let export = export.unwrap_or_else(|| panic!("missing export `{name}`"));
+            let ExportItem::Function(fn_id) = export.item else {
+                panic!("unexpected export: {export:?}");
+            };
+            let fn_type = module.types.get(module.funcs.get(fn_id).ty());
+            assert_eq!(fn_type.params(), params);
+            assert_eq!(fn_type.results(), results);
+        }
+        // Check that the processed module is valid.
+        Module::from_buffer(&module.emit_wasm()).unwrap();
+    }
+}
+
+#[test]
+fn module_with_compaction() {
+    let module = wat::parse_file(kinds_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    for partitioning in [Partitioning::Shared, Partitioning::PerKind] {
+        let mut processor = Processor::default();
+        processor
+            .set_table_partitioning(partitioning)
+            .set_drop_fn_with_kind("hook", "drop_ref")
+            .set_drop_slot_value(DropSlotValue::Tombstone {
+                module: "hook",
+                name: "tombstone",
+            })
+            .enable_compaction("hook", "move_ref")
+            .rename_export(Processor::COMPACT_EXPORT, "compact");
+        let manifest = processor.manifest(&module_bytes).unwrap();
+        let compaction = manifest.compaction.unwrap();
+        assert_eq!(compaction.compact, "compact");
+        assert_eq!(compaction.move_fn.module, "hook");
+        assert_eq!(compaction.move_fn.name, "move_ref");
+
+        let mut module = Module::from_buffer(&module_bytes).unwrap();
+        processor.process(&mut module).unwrap();
+        let import_id = module.imports.find("hook", "move_ref").unwrap();
+        let ImportKind::Function(hook_id) = module.imports.get(import_id).kind else {
+            panic!("unexpected import type");
+        };
+        let hook_type = module.types.get(module.funcs.get(hook_id).ty());
+        assert_eq!(hook_type.params(), [ValType::I32, ValType::I32]);
+        assert_eq!(hook_type.results(), []);
+
+        let export = module
+            .exports
+            .iter()
+            .find(|export| export.name == "compact");
+        let ExportItem::Function(export_id) = export.unwrap().item else {
+            panic!("unexpected export type");
+        };
+        let export_type = module.types.get(module.funcs.get(export_id).ty());
+        assert_eq!(export_type.params(), []);
+        assert_eq!(export_type.results(), [ValType::I32]);
+        // Check that the processed module is valid.
+        Module::from_buffer(&module.emit_wasm()).unwrap();
+    }
+}
+
+#[test]
+fn module_with_exported_shims() {
+    let module = wat::parse_file(global_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    for partitioning in [Partitioning::Shared, Partitioning::PerKind] {
+        let mut processor = Processor::default();
+        processor
+            .set_table_partitioning(partitioning)
+            .set_drop_fn("hook", "drop_ref")
+            .set_import_shim_visibility(ImportShimVisibility::EXPORTED)
+            .rename_export("externref_get", "get_ref");
+        let manifest = processor.manifest(&module_bytes).unwrap();
+        let shims = manifest.shims.unwrap();
+        assert_eq!(shims.insert, "externref_insert");
+        assert_eq!(shims.get, "get_ref");
+        assert_eq!(shims.drop, "externref_drop");
+
+        let mut module = Module::from_buffer(&module_bytes).unwrap();
+        processor.process(&mut module).unwrap();
+        let expected_signatures = [
+            (
+                "externref_insert",
+                [EXTERNREF, ValType::I32].as_slice(),
+                [ValType::I32].as_slice(),
+            ),
+            ("get_ref", &[ValType::I32], &[EXTERNREF]),
+            ("externref_drop", &[ValType::I32], &[]),
+        ];
+        for (name, params, results) in expected_signatures {
+            let fn_id = module.exports.get_func(name);
+            let fn_id = fn_id.unwrap_or_else(|_| panic!("missing export `{name}`"));
+            let fn_type = module.types.get(module.funcs.get(fn_id).ty());
+            assert_eq!(fn_type.params(), params);
+            assert_eq!(fn_type.results(), results);
+        }
+        // Check that the processed module is valid.
+        Module::from_buffer(&module.emit_wasm()).unwrap();
+    }
+}
+
+#[test]
+fn exported_shims_for_module_without_surrogates() {
+    // The module has an empty custom section; otherwise, it would not be processed.
+    let module = r#"(module (func (export "test")) (@custom "__externrefs" ""))"#;
+    let module = wat::parse_str(module).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    Processor::default()
+        .set_import_shim_visibility(ImportShimVisibility::Exported {
+            insert: "insert",
+            get: "get",
+            drop: "drop",
+        })
+        .process(&mut module)
+        .unwrap();
+
+    for name in ["insert", "get", "drop"] {
+        module.exports.get_func(name).unwrap();
+    }
+    Module::from_buffer(&module.emit_wasm()).unwrap();
+}
+
+#[test]
+fn module_with_reserved_slots() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    for partitioning in [Partitioning::Shared, Partitioning::PerKind] {
+        let mut processor = Processor::default();
+        processor
+            .set_table_partitioning(partitioning)
+            .set_drop_slot_value(DropSlotValue::Tombstone {
+                module: "test",
+                name: "tombstone",
+            })
+            .set_reserved_slots(3);
+        let manifest = processor.manifest(&module_bytes).unwrap();
+        assert_eq!(manifest.table.initial_size, 3);
+
+        let processed = processor.process_bytes(&module_bytes).unwrap();
+        let (_, version) = read_processed_by(&processed).pop().unwrap();
+        assert!(version.contains("reserved-slots"), "{version}");
+        let module = Module::from_buffer(&processed).unwrap();
+        // Occupancy tables must have the same size as the `externref`s tables.
+        let ref_tables: Vec<_> = module
+            .tables
+            .iter()
+            .filter(|table| table.import.is_none() && table.element_ty == RefType::Externref)
+            .collect();
+        assert!(ref_tables.len() >= 2);
+        for table in ref_tables {
+            assert_eq!(table.initial, 3);
+        }
+    }
+}
+
+#[test]
+fn module_with_leak_checks() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    Processor::default()
+        .set_leak_check("hook", "on_leak", "te?t*")
+        .rename_export("test", "exit")
+        .process(&mut module)
+        .unwrap();
+
+    let import_id = module.imports.find("hook", "on_leak").unwrap();
+    let ImportKind::Function(hook_id) = module.imports.get(import_id).kind else {
+        panic!("unexpected import type");
+    };
+    let hook_type = module.types.get(module.funcs.get(hook_id).ty());
+    assert_eq!(hook_type.params(), [ValType::I32]);
+    assert_eq!(hook_type.results(), []);
+
+    let export = module.exports.iter().find(|export| export.name == "exit");
+    let ExportItem::Function(export_id) = export.unwrap().item else {
+        panic!("unexpected export type");
+    };
+    let export_fn = module.funcs.get(export_id);
+    assert_eq!(export_fn.name.as_deref(), Some("externref_leak_check_test"));
+    let export_type = module.types.get(export_fn.ty());
+    assert_eq!(export_type.params(), [EXTERNREF]);
+    // Check that the processed module is valid.
+    Module::from_buffer(&module.emit_wasm()).unwrap();
+}
+
+#[test]
+fn module_with_resource_table() {
+    let module = wat::parse_file(table_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor.set_resource_table("collection");
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let resource_table = manifest.resource_table.unwrap();
+    assert_eq!(resource_table.export_name.as_deref(), Some("collection"));
+
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    processor.process(&mut module).unwrap();
+    assert_eq!(module.imports.iter().count(), 1, "{:?}", module.imports);
+
+    let exported_tables: Vec<_> = module
+        .exports
+        .iter()
+        .filter(|export| matches!(export.item, ExportItem::Table(_)))
+        .map(|export| export.name.as_str())
+        .collect();
+    assert_eq!(exported_tables, ["externrefs", "collection"]);
+    let externref_tables = module
+        .tables
+        .iter()
+        .filter(|table| table.element_ty == RefType::Externref);
+    assert_eq!(externref_tables.count(), 2);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn module_with_resource_global() {
+    let module = wat::parse_file(global_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor.set_resource_global("context");
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let resource_global = manifest.resource_global.unwrap();
+    assert_eq!(resource_global.export_name.as_deref(), Some("context"));
+    assert!(manifest.resource_table.is_none());
+
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    processor.process(&mut module).unwrap();
+    assert_eq!(module.imports.iter().count(), 1, "{:?}", module.imports);
+
+    let exported_globals: Vec<_> = module
+        .exports
+        .iter()
+        .filter_map(|export| match export.item {
+            ExportItem::Global(global_id) => Some((export.name.as_str(), global_id)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(exported_globals.len(), 1);
+    let (name, global_id) = exported_globals[0];
+    assert_eq!(name, "context");
+    let global = module.globals.get(global_id);
+    assert_eq!(global.ty, EXTERNREF);
+    assert!(global.mutable);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn module_with_resource_kinds() {
+    let module = wat::parse_file(kinds_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor.set_drop_fn_with_kind("hook", "drop_ref");
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    assert!(manifest.drop_fn_with_kind);
+    assert_eq!(manifest.resource_kinds, ["Arena", "Bytes"]);
+
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    processor.process(&mut module).unwrap();
+
+    // Check that surrogate imports are replaced, and the drop hook is imported.
+    assert_eq!(module.imports.iter().count(), 2, "{:?}", module.imports);
+    let import_id = module.imports.find("hook", "drop_ref").unwrap();
+    let import_id = match &module.imports.get(import_id).kind {
+        ImportKind::Function(fn_id) => *fn_id,
+        other => panic!("unexpected import type: {other:?}"),
+    };
+    let function_type = module.types.get(module.funcs.get(import_id).ty());
+    assert_eq!(function_type.params(), [EXTERNREF, ValType::I32]);
+    assert_eq!(function_type.results(), []);
+
+    // Check that the kind tables are added.
+    let funcref_tables = module
+        .tables
+        .iter()
+        .filter(|table| table.element_ty == RefType::Funcref);
+    assert_eq!(funcref_tables.count(), 2);
+    // ...and that kind markers are retained during GC.
+    assert_eq!(module.elements.iter().count(), 1);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn module_with_resource_kinds_and_plain_drop_hook() {
+    let module = wat::parse_file(kinds_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    Processor::default()
+        .set_drop_fn("hook", "drop_ref")
+        .process(&mut module)
+        .unwrap();
+
+    // Kinds are not tracked.
+    assert_eq!(module.imports.iter().count(), 2, "{:?}", module.imports);
+    assert_eq!(module.tables.iter().count(), 1);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn module_with_per_kind_tables() {
+    let module = wat::parse_file(kinds_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+    let module_bytes = module.emit_wasm();
+
+    let mut processor = Processor::default();
+    processor
+        .set_table_partitioning(Partitioning::PerKind)
+        .set_drop_fn_with_kind("hook", "drop_ref")
+        .enable_resource_metrics()
+        .rename_export("externrefs::Bytes", "bytes");
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let kind_tables: Vec<_> = manifest
+        .kind_tables
+        .iter()
+        .map(|table| table.export_name.as_deref().unwrap())
+        .collect();
+    assert_eq!(kind_tables, ["externrefs::Arena", "bytes"]);
+
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    processor.process(&mut module).unwrap();
+
+    let exported_tables: Vec<_> = module
+        .exports
+        .iter()
+        .filter(|export| matches!(export.item, ExportItem::Table(_)))
+        .map(|export| export.name.as_str())
+        .collect();
+    assert_eq!(
+        exported_tables,
+        ["externrefs", "externrefs::Arena", "bytes"]
+    );
+    // Kinds are fixed for each partition, so kind tables are not added. Instead, there are
+    // dispatch tables for the insertion, getter and drop functions.
+    let funcref_tables = module
+        .tables
+        .iter()
+        .filter(|table| table.element_ty == RefType::Funcref);
+    assert_eq!(funcref_tables.count(), 3);
+
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn module_without_inlines() {
+    let module = wat::parse_file(no_inline_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    // We need to add a custom section to the module before processing.
+    add_basic_custom_section(&mut module);
+
+    Processor::default().process(&mut module).unwrap();
+
+    // Check that the module is well-formed by converting it to bytes and back.
+    let module_bytes = module.emit_wasm();
+    Module::from_buffer(&module_bytes).unwrap();
+}
+
+#[test]
+fn processing_is_deterministic() {
+    type Configure = fn(&mut Processor<'_>);
+
+    let configure_default = |_: &mut Processor<'_>| {};
+    let configure_kinds = |processor: &mut Processor<'_>| {
+        processor
+            .set_drop_fn_with_kind("hook", "drop_ref")
+            .enable_resource_metrics();
+    };
+    let configure_leak_checks = |processor: &mut Processor<'_>| {
+        processor.set_leak_check("hook", "on_leak", "te?t*");
+    };
+    let configure_partitions = |processor: &mut Processor<'_>| {
+        processor
+            .set_table_partitioning(Partitioning::PerKind)
+            .set_drop_fn_with_kind("hook", "drop_ref");
+    };
+    let cases: [(&Path, Configure); 5] = [
+        (simple_module_path(), configure_default),
+        (simple_module_path(), configure_leak_checks),
+        (no_inline_module_path(), configure_default),
+        (kinds_module_path(), configure_kinds),
+        (kinds_module_path(), configure_partitions),
+    ];
+
+    for (path, configure) in cases {
+        let module = wat::parse_file(path).unwrap();
+        let mut module = Module::from_buffer(&module).unwrap();
+        add_basic_custom_section(&mut module);
+        let module_bytes = module.emit_wasm();
+
+        let process = || {
+            let mut processor = Processor::default();
+            configure(&mut processor);
+            let mut module = Module::from_buffer(&module_bytes).unwrap();
+            processor.process(&mut module).unwrap();
+            module.emit_wasm()
+        };
+        let expected = process();
+        for _ in 0..5 {
+            assert_eq!(process(), expected, "non-deterministic output for {path:?}");
+        }
+    }
+}
+
+const CUSTOM_GUARD_MODULE: &str = r#"
+    (module
+        (import "externref" "insert" (func $insert_ref (param i32 i32) (result i32)))
+        (import "externref" "get" (func $get_ref (param i32) (result i32)))
+        (import "externref_shim" "guard" (func $guard))
+        (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+        (func $alloc_wrapper (param $arena i32) (result i32)
+            (call $guard)
+            (call $insert_ref
+                (call $alloc (call $get_ref (local.get $arena)) (i32.const 42))
+                (i32.const -1)
+            )
+        )
+        (func (export "test") (param $arena i32)
+            (drop (call $alloc_wrapper (call $insert_ref (local.get $arena) (i32.const -1))))
+        )
+    )
+"#;
+
+fn custom_guard_module() -> Module {
+    const SHIM_ALLOC: Function<'static> = Function {
+        surrogate_module: Some("externref_shim"),
+        ..ARENA_ALLOC
+    };
+    const SHIM_ALLOC_BYTES: [u8; SHIM_ALLOC.custom_section_len()] = SHIM_ALLOC.custom_section();
+
+    let module = wat::parse_str(CUSTOM_GUARD_MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: [SHIM_ALLOC_BYTES.as_slice(), &TEST_BYTES].concat(),
+    });
+    module
+}
+
+#[test]
+fn module_with_custom_guard_import() {
+    let mut module = custom_guard_module();
+    Processor::default()
+        .set_guard_import("externref_shim", "guard")
+        .process(&mut module)
+        .unwrap();
+
+    let imports: Vec<_> = module
+        .imports
+        .iter()
+        .map(|import| (import.module.as_str(), import.name.as_str()))
+        .collect();
+    assert_eq!(imports, [("arena", "alloc")]);
+    let alloc_id = module.imports.find("arena", "alloc").unwrap();
+    let ImportKind::Function(alloc_id) = module.imports.get(alloc_id).kind else {
+        unreachable!();
+    };
+    let alloc_ty = module.types.get(module.funcs.get(alloc_id).ty());
+    assert_eq!(alloc_ty.params(), [EXTERNREF, ValType::I32]);
+    assert_eq!(alloc_ty.results(), [EXTERNREF]);
+}
+
+#[test]
+fn mismatched_surrogate_module() {
+    let mut module = custom_guard_module();
+    let err = Processor::default().process(&mut module).unwrap_err();
+    assert_matches!(
+        &err,
+        Error::SurrogateModuleMismatch { module, name, declared, expected }
+            if module == "arena" && name == "alloc"
+                && declared == "externref_shim" && expected == "externref"
+    );
+    let err = err.to_string();
+    assert!(err.contains("set_guard_import()"), "{err}");
+}
+
+#[test]
+fn module_with_custom_surrogate_module() {
+    // The host provides a real `externref` module, so surrogate imports are moved elsewhere.
+    const MODULE: &str = r#"
+        (module
+            (import "externref_shim" "insert" (func $insert_ref (param i32 i32) (result i32)))
+            (import "externref_shim" "get" (func $get_ref (param i32) (result i32)))
+            (import "externref_shim" "guard" (func $guard))
+            (import "externref" "insert" (func $host_insert (param i32)))
+            (import "arena" "alloc" (func $alloc (param i32 i32) (result i32)))
+
+            (func $alloc_wrapper (param $arena i32) (result i32)
+                (call $guard)
+                (call $insert_ref
+                    (call $alloc (call $get_ref (local.get $arena)) (i32.const 42))
+                    (i32.const -1)
+                )
+            )
+            (func (export "test") (param $arena i32)
+                (call $host_insert (i32.const 1))
+                (drop (call $alloc_wrapper (call $insert_ref (local.get $arena) (i32.const -1))))
+            )
+        )
+    "#;
+
+    let module = wat::parse_str(MODULE).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: [ARENA_ALLOC_BYTES.as_slice(), &TEST_BYTES].concat(),
+    });
+    Processor::default()
+        .set_surrogate_module("externref_shim")
+        .process(&mut module)
+        .unwrap();
+
+    let imports: Vec<_> = module
+        .imports
+        .iter()
+        .map(|import| (import.module.as_str(), import.name.as_str()))
+        .collect();
+    assert_eq!(imports, [("externref", "insert"), ("arena", "alloc")]);
+    let host_insert_id = module.imports.find("externref", "insert").unwrap();
+    let ImportKind::Function(host_insert_id) = module.imports.get(host_insert_id).kind else {
+        unreachable!();
+    };
+    let host_insert_ty = module.types.get(module.funcs.get(host_insert_id).ty());
+    assert_eq!(host_insert_ty.params(), [ValType::I32]);
+    assert_eq!(host_insert_ty.results(), []);
+}
+
+fn module_with_sdk_section() -> Vec<u8> {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    module.customs.add(RawCustomSection {
+        name: "__externrefs_sdk".to_owned(),
+        data: ARENA_ALLOC_BYTES.to_vec(),
+    });
+    module.customs.add(RawCustomSection {
+        name: Function::CUSTOM_SECTION_NAME.to_owned(),
+        data: TEST_BYTES.to_vec(),
+    });
+    module.emit_wasm()
+}
+
+#[test]
+fn processing_multiple_declaration_sections() {
+    let module_bytes = module_with_sdk_section();
+    let mut processor = Processor::default();
+    processor.add_section(
+        "__externrefs_sdk",
+        SectionOptions::default().with_import_module("sdk"),
+    );
+    let manifest = processor.manifest(&module_bytes).unwrap();
+    let imports: Vec<_> = manifest
+        .imports
+        .iter()
+        .map(|import| (import.module.as_deref(), import.name.as_str()))
+        .collect();
+    assert_eq!(imports, [(Some("sdk"), "alloc")]);
+
+    let mut module = Module::from_buffer(&module_bytes).unwrap();
+    processor.process(&mut module).unwrap();
+    assert!(module.imports.find("arena", "alloc").is_none());
+    let alloc_id = module.imports.find("sdk", "alloc").unwrap();
+    let ImportKind::Function(alloc_id) = module.imports.get(alloc_id).kind else {
+        unreachable!();
+    };
+    let alloc_ty = module.types.get(module.funcs.get(alloc_id).ty());
+    assert_eq!(alloc_ty.params(), [EXTERNREF, ValType::I32]);
+    assert_eq!(alloc_ty.results(), [EXTERNREF]);
+    let has_sdk_section = module
+        .customs
+        .iter()
+        .any(|(_, section)| section.name() == "__externrefs_sdk");
+    assert!(!has_sdk_section);
+}
+
+#[test]
+fn processing_renamed_declaration_section() {
+    let module_bytes = module_with_sdk_section();
+    let mut processor = Processor::default();
+    processor
+        .set_section_name("__externrefs_sdk")
+        .add_section(Function::CUSTOM_SECTION_NAME, SectionOptions::default());
+    let processed = processor.process_bytes(&module_bytes).unwrap();
+    let module = Module::from_buffer(&processed).unwrap();
+    // The import module is not changed since no options are specified.
+    assert!(module.imports.find("arena", "alloc").is_some());
+}
+
+#[test]
+fn unknown_declaration_section() {
+    let module_bytes = module_with_sdk_section();
+    let processor = Processor::default();
+    let err = processor.process_bytes(&module_bytes).unwrap_err();
+    assert_matches!(&err, Error::UnknownSection { name } if name == "__externrefs_sdk");
+    let err = err.to_string();
+    assert!(err.contains("add_section()"), "{err}");
+
+    let err = processor.manifest(&module_bytes).unwrap_err();
+    assert_matches!(&err, Error::UnknownSection { name } if name == "__externrefs_sdk");
+
+    // `inspect()` reads all declaration sections.
+    let functions = processor::inspect(&module_bytes).unwrap();
+    let names: Vec<_> = functions.iter().map(|function| function.name()).collect();
+    assert_eq!(names, ["alloc", "test"]);
+}
+
+#[test]
+fn observing_processing_events() {
+    let module = wat::parse_file(simple_module_path()).unwrap();
+    let mut module = Module::from_buffer(&module).unwrap();
+    add_basic_custom_section(&mut module);
+
+    let log = EventLog::default();
+    Processor::default()
+        .add_observer(&log)
+        .process(&mut module)
+        .unwrap();
+
+    let events = log.take();
+    assert_eq!(
+        events,
+        [
+            ProcessingEvent::ImportReplaced {
+                name: "insert".to_owned()
+            },
+            ProcessingEvent::ImportReplaced {
+                name: "get".to_owned()
+            },
+            ProcessingEvent::ImportReplaced {
+                name: "drop".to_owned()
+            },
+            ProcessingEvent::ImportPatched {
+                module: "arena".to_owned(),
+                name: "alloc".to_owned(),
+            },
+            ProcessingEvent::ExportTransformed {
+                name: "test".to_owned()
+            },
+        ]
+    );
+    assert!(log.events().is_empty());
+}
@@ -0,0 +1,88 @@
+//! Error codes for diagnostics raised by the `externref` macro.
+
+use std::fmt;
+
+use proc_macro2::Span;
+use quote::ToTokens;
+use syn::parse::Error as SynError;
+
+/// Code of an error raised by the `externref` macro. Codes are listed in the "Error codes"
+/// section of the macro docs, which is referenced by each error message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorCode {
+    /// Missing or unsupported ABI of the wrapped item, or an unsupported `abi` attribute value.
+    UnsupportedAbi,
+    /// Malformed `#[link(..)]`, `#[link_name = ..]` or `#[export_name = ..]` attribute.
+    BadLinkAttr,
+    /// WASM module name that cannot be resolved at macro expansion.
+    BadModuleName,
+    /// Item that cannot be wrapped, e.g. a struct or a variadic function.
+    UnsupportedItem,
+    /// Unsupported or malformed `#[externref(..)]` / `#[resource(..)]` attribute.
+    BadAttr,
+    /// `#[resource(..)]` attribute placed on an arg or function that does not support it.
+    MisplacedArgAttr,
+    /// Type mentioning `Resource<_>` in a position not recognized as a resource.
+    UnsupportedResourceType,
+    /// `Result` return type of an imported function.
+    ImportWithResult,
+    /// `std` path in a signature with the `no_std` attribute.
+    StdPath,
+    /// Resource misuse flagged in the strict mode.
+    ResourceMisuse,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            Self::UnsupportedAbi => "E001",
+            Self::BadLinkAttr => "E002",
+            Self::BadModuleName => "E003",
+            Self::UnsupportedItem => "E004",
+            Self::BadAttr => "E005",
+            Self::MisplacedArgAttr => "E006",
+            Self::UnsupportedResourceType => "E007",
+            Self::ImportWithResult => "E008",
+            Self::StdPath => "E009",
+            Self::ResourceMisuse => "E010",
+        };
+        formatter.write_str(code)
+    }
+}
+
+impl ErrorCode {
+    pub const DOCS_URL: &'static str =
+        "https://docs.rs/externref/latest/externref/attr.externref.html#error-codes";
+
+    fn message(self, msg: impl fmt::Display) -> String {
+        format!("[{self}] {msg}\n= note: see {}", Self::DOCS_URL)
+    }
+
+    /// Creates an error with the specified span.
+    pub fn error(self, span: Span, msg: impl fmt::Display) -> SynError {
+        SynError::new(span, self.message(msg))
+    }
+
+    /// Creates an error spanning the specified tokens.
+    pub fn spanned_error(self, tokens: impl ToTokens, msg: impl fmt::Display) -> SynError {
+        SynError::new_spanned(tokens, self.message(msg))
+    }
+
+    /// Attaches this code to all messages in an error produced by `syn` (e.g., a syntax error
+    /// in an attribute). Messages that already have a code are left as is.
+    pub fn wrap(self, err: SynError) -> SynError {
+        err.into_iter()
+            .map(|err| {
+                if err.to_string().starts_with("[E") {
+                    err
+                } else {
+                    self.error(err.span(), err)
+                }
+            })
+            .reduce(|mut acc, err| {
+                acc.combine(err);
+                acc
+            })
+            .expect("`syn` errors are non-empty")
+    }
+}
@@ -1,44 +1,409 @@
 use std::{collections::HashMap, mem};
 
-use proc_macro2::TokenStream;
+use proc_macro2::{Group, Span, TokenStream, TokenTree};
 use quote::{quote, ToTokens};
 use syn::{
-    parse::Error as SynError, punctuated::Punctuated, spanned::Spanned, Attribute, Expr, ExprLit,
-    FnArg, ForeignItem, GenericArgument, Ident, ItemFn, ItemForeignMod, Lit, LitStr, Meta, PatType,
-    Path, PathArguments, Signature, Token, Type, TypePath, Visibility,
+    parse::{Error as SynError, Parse, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Abi, Attribute, Expr, ExprLit, FnArg, ForeignItem, ForeignItemFn, GenericArgument, Ident,
+    ItemFn, ItemForeignMod, Lit, LitStr, Meta, Pat, PatType, Path, PathArguments, Signature, Token,
+    Type, TypePath, Visibility,
 };
 
-use crate::ExternrefAttrs;
+use crate::{parse_table_name, ErrorCode, ExternrefAttrs, TargetAbi};
 
 fn check_abi(
     target_name: &str,
     abi_name: Option<&LitStr>,
     root_span: &impl ToTokens,
+    attrs: &ExternrefAttrs,
 ) -> Result<(), SynError> {
     let abi_name = abi_name.ok_or_else(|| {
         let msg = format!("{target_name} must be marked with `extern \"C\"`");
-        SynError::new_spanned(root_span, msg)
+        ErrorCode::UnsupportedAbi.spanned_error(root_span, msg)
     })?;
-    if abi_name.value() != "C" {
+    let accepted_abis = attrs.accepted_abis();
+    if !accepted_abis.contains(&abi_name.value().as_str()) {
         let msg = format!(
-            "Unexpected ABI {} for {target_name}; expected `C`",
-            abi_name.value()
+            "Unexpected ABI {} for {target_name}; expected {}",
+            abi_name.value(),
+            ExternrefAttrs::expected_abis(&accepted_abis)
         );
-        return Err(SynError::new(abi_name.span(), msg));
+        return Err(ErrorCode::UnsupportedAbi.error(abi_name.span(), msg));
+    }
+    Ok(())
+}
+
+/// Checks whether the provided tokens mention the specified identifier (e.g., as a variable).
+/// Besides identifier tokens, this checks string literals for inline format args
+/// (e.g., `format!("{res:?}")`).
+fn mentions_ident(tokens: TokenStream, ident: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(token) => token == *ident,
+        TokenTree::Group(group) => mentions_ident(group.stream(), ident),
+        TokenTree::Literal(lit) => {
+            let lit = syn::parse2::<syn::LitStr>(TokenTree::Literal(lit).into());
+            lit.is_ok_and(|lit| mentions_format_arg(&lit.value(), &ident.to_string()))
+        }
+        TokenTree::Punct(_) => false,
+    })
+}
+
+/// Checks whether a format string mentions `name` as an inline arg, e.g. `{name}` or `{name:?}`.
+fn mentions_format_arg(format: &str, name: &str) -> bool {
+    let mut rest = format;
+    while let Some(pos) = rest.find('{') {
+        rest = &rest[pos + 1..];
+        if let Some(escaped) = rest.strip_prefix('{') {
+            rest = escaped;
+            continue;
+        }
+        let arg_len = rest.find(['}', ':']).unwrap_or(rest.len());
+        if rest[..arg_len].trim() == name {
+            return true;
+        }
+    }
+    false
+}
+
+/// Checks that the provided tokens do not contain `std::` paths.
+fn check_no_std(tokens: TokenStream) -> Result<(), SynError> {
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident == "std" => {
+                let is_path = matches!(
+                    tokens.peek(),
+                    Some(TokenTree::Punct(punct)) if punct.as_char() == ':'
+                );
+                if is_path {
+                    let msg = "`std` paths are not allowed in `no_std` mode; use `core` \
+                        or `alloc` paths instead";
+                    return Err(ErrorCode::StdPath.error(ident.span(), msg));
+                }
+            }
+            TokenTree::Group(group) => check_no_std(group.stream())?,
+            _ => { /* Do nothing */ }
+        }
     }
     Ok(())
 }
 
+/// Sets the span of all `tokens` to `span`. This is used for resource initialization,
+/// so that the creation site recorded with the `debug-tracking` feature of the `externref` crate
+/// (via `Location::caller()`) points to the wrapped function rather than to the macro invocation.
+fn respan(tokens: TokenStream, span: Span) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|mut token| {
+            if let TokenTree::Group(group) = &token {
+                let mut new_group = Group::new(group.delimiter(), respan(group.stream(), span));
+                new_group.set_span(span);
+                token = TokenTree::Group(new_group);
+            } else {
+                token.set_span(span);
+            }
+            token
+        })
+        .collect()
+}
+
 fn attr_expr(attrs: &[Attribute], name: &str) -> Result<Option<Expr>, SynError> {
-    let attr = attrs.iter().find(|attr| attr.path().is_ident(name));
-    let Some(attr) = attr else {
+    let meta = attrs
+        .iter()
+        .map(attr_meta)
+        .find(|meta| meta.path().is_ident(name));
+    let Some(meta) = meta else {
         return Ok(None);
     };
 
-    let name_value = attr.meta.require_name_value()?;
+    let name_value = meta
+        .require_name_value()
+        .map_err(|err| ErrorCode::BadLinkAttr.wrap(err))?;
     Ok(Some(name_value.value.clone()))
 }
 
+/// Returns the meta of an attribute, unwrapping unsafe attributes (e.g.,
+/// `#[unsafe(export_name = "..")]`, which is required in Rust 2024).
+fn attr_meta(attr: &Attribute) -> Meta {
+    if let Meta::List(list) = &attr.meta {
+        if list.path.is_ident("unsafe") {
+            if let Ok(inner) = list.parse_args::<Meta>() {
+                return inner;
+            }
+        }
+    }
+    attr.meta.clone()
+}
+
+/// Names of attributes only applicable to foreign functions.
+const FOREIGN_FN_ATTRS: &[&str] = &["link_name", "link_ordinal"];
+
+/// Rebuilds a `#[cfg_attr(..)]` attribute retaining only nested attributes matching `filter`.
+/// Returns `None` if the attribute is not `cfg_attr`, cannot be parsed, or no nested attributes
+/// are retained.
+fn filter_cfg_attr(attr: &Attribute, filter: impl Fn(&Meta) -> bool) -> Option<Attribute> {
+    let Meta::List(list) = &attr.meta else {
+        return None;
+    };
+    if !list.path.is_ident("cfg_attr") {
+        return None;
+    }
+    let mut nested = list
+        .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        .ok()?
+        .into_iter();
+    let predicate = nested.next()?;
+    let nested: Vec<_> = nested.filter(|meta| filter(meta)).collect();
+    if nested.is_empty() {
+        return None;
+    }
+    Some(syn::parse_quote!(#[cfg_attr(#predicate, #(#nested),*)]))
+}
+
+/// Returns attributes conditionally compiling an item: `#[cfg(..)]`, and `#[cfg_attr(..)]`
+/// reduced to the nested `cfg(..)`s. The code generated for the item (wrappers
+/// and declarations) must be gated with the same attributes; otherwise, it would refer
+/// to a non-existing item.
+fn cfg_gates(attrs: &[Attribute]) -> Vec<Attribute> {
+    let gates = attrs.iter().filter_map(|attr| {
+        if attr.path().is_ident("cfg") {
+            Some(attr.clone())
+        } else {
+            filter_cfg_attr(attr, |meta| meta.path().is_ident("cfg"))
+        }
+    });
+    gates.collect()
+}
+
+/// Takes attributes of an imported function that should be placed on its wrapper. Doc comments
+/// are moved to the wrapper since the imported function becomes private. `#[cfg(..)]`
+/// and `#[cfg_attr(..)]` are copied (except for nested attributes only applicable
+/// to foreign functions, such as `link_name`).
+fn take_wrapper_attrs(attrs: &mut Vec<Attribute>) -> Vec<Attribute> {
+    let mut wrapper_attrs = vec![];
+    attrs.retain(|attr| {
+        if attr.path().is_ident("doc") {
+            wrapper_attrs.push(attr.clone());
+            return false;
+        }
+        if attr.path().is_ident("cfg") {
+            wrapper_attrs.push(attr.clone());
+        } else if let Some(attr) = filter_cfg_attr(attr, |meta| {
+            !FOREIGN_FN_ATTRS
+                .iter()
+                .any(|&name| meta.path().is_ident(name))
+        }) {
+            wrapper_attrs.push(attr);
+        }
+        true
+    });
+    wrapper_attrs
+}
+
+/// Resolves a string expression that can be evaluated at macro expansion: a string literal,
+/// or a `concat!(..)` / `env!(..)` macro call (possibly nested). `env!` is resolved using
+/// the environment of the compiler process, which contains the `CARGO_*` variables set by Cargo.
+fn resolve_str_expr(expr: &Expr) -> Result<String, SynError> {
+    const MSG: &str = "Unexpected WASM module name format (expected a string literal, \
+        or a `concat!(..)` / `env!(..)` call)";
+
+    match expr {
+        Expr::Lit(ExprLit { lit, .. }) => match lit {
+            Lit::Str(str) => Ok(str.value()),
+            _ => Err(ErrorCode::BadModuleName.spanned_error(lit, MSG)),
+        },
+        Expr::Macro(expr) if expr.mac.path.is_ident("concat") => {
+            let args = expr
+                .mac
+                .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+                .map_err(|err| ErrorCode::BadModuleName.wrap(err))?;
+            let mut concatenated = String::new();
+            for arg in &args {
+                match arg {
+                    Expr::Lit(ExprLit { lit, .. }) => match lit {
+                        Lit::Str(str) => concatenated.push_str(&str.value()),
+                        Lit::Char(ch) => concatenated.push(ch.value()),
+                        Lit::Int(int) => concatenated.push_str(int.base10_digits()),
+                        Lit::Float(float) => concatenated.push_str(float.base10_digits()),
+                        Lit::Bool(bool) => concatenated.push_str(&bool.value.to_string()),
+                        _ => return Err(ErrorCode::BadModuleName.spanned_error(lit, MSG)),
+                    },
+                    _ => concatenated.push_str(&resolve_str_expr(arg)?),
+                }
+            }
+            Ok(concatenated)
+        }
+        Expr::Macro(expr) if expr.mac.path.is_ident("env") => {
+            let args = expr
+                .mac
+                .parse_body_with(Punctuated::<LitStr, Token![,]>::parse_terminated)
+                .map_err(|err| ErrorCode::BadModuleName.wrap(err))?;
+            let Some(var_name) = args.first() else {
+                let msg = "expected a variable name";
+                return Err(ErrorCode::BadModuleName.spanned_error(&expr.mac, msg));
+            };
+            std::env::var(var_name.value()).map_err(|_| {
+                let msg = format!(
+                    "environment variable `{}` is not defined at compile time",
+                    var_name.value()
+                );
+                ErrorCode::BadModuleName.spanned_error(var_name, msg)
+            })
+        }
+        _ => Err(ErrorCode::BadModuleName.spanned_error(expr, MSG)),
+    }
+}
+
+/// Function-level attributes placed on items inside a foreign module,
+/// e.g. `#[externref(skip)]`.
+#[derive(Debug, Default)]
+struct ItemAttrs {
+    skip: bool,
+    table: Option<String>,
+}
+
+impl ItemAttrs {
+    /// Parses and removes `#[externref(..)]` attributes from the provided list.
+    fn take(attrs: &mut Vec<Attribute>) -> Result<Self, SynError> {
+        let mut item_attrs = Self::default();
+        let mut parse_result = Ok(());
+        attrs.retain(|attr| {
+            if !attr.path().is_ident("externref") {
+                return true;
+            }
+            if parse_result.is_ok() {
+                parse_result = attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("skip") {
+                        item_attrs.skip = true;
+                        Ok(())
+                    } else if meta.path.is_ident("table") {
+                        item_attrs.table = Some(parse_table_name(&meta)?);
+                        Ok(())
+                    } else {
+                        Err(ErrorCode::BadAttr.error(meta.path.span(), "unsupported attribute"))
+                    }
+                });
+            }
+            false
+        });
+        parse_result
+            .map(|()| item_attrs)
+            .map_err(|err| ErrorCode::BadAttr.wrap(err))
+    }
+}
+
+/// Attributes placed on function args, e.g. `#[resource(nullable)]`.
+#[derive(Debug, Default)]
+struct ArgAttrs {
+    /// Set by the bare `#[resource]` attribute marking an arg with a type alias for a resource.
+    alias: bool,
+    nullable: bool,
+    /// Set by `#[resource(consumed)]` acknowledging that a resource passed by value
+    /// to an imported function is dropped after the call.
+    consumed: bool,
+    table: Option<String>,
+}
+
+impl ArgAttrs {
+    const NAME: &'static str = "resource";
+
+    fn parse(attrs: &[Attribute]) -> Result<Self, SynError> {
+        let mut arg_attrs = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident(Self::NAME)) {
+            if matches!(attr.meta, Meta::Path(_)) {
+                arg_attrs.alias = true;
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("nullable") {
+                    arg_attrs.nullable = true;
+                    Ok(())
+                } else if meta.path.is_ident("consumed") {
+                    arg_attrs.consumed = true;
+                    Ok(())
+                } else if meta.path.is_ident("table") {
+                    arg_attrs.table = Some(parse_table_name(&meta)?);
+                    Ok(())
+                } else {
+                    Err(ErrorCode::BadAttr.error(meta.path.span(), "unsupported attribute"))
+                }
+            })
+            .map_err(|err| ErrorCode::BadAttr.wrap(err))?;
+        }
+        Ok(arg_attrs)
+    }
+
+    /// Removes `#[resource(..)]` attributes from args in the provided signature.
+    fn strip(sig: &mut Signature) {
+        for arg in &mut sig.inputs {
+            if let FnArg::Typed(typed_arg) = arg {
+                typed_arg
+                    .attrs
+                    .retain(|attr| !attr.path().is_ident(Self::NAME));
+            }
+        }
+    }
+}
+
+/// `#[resource(..)]` attributes placed on an exported function and applying
+/// to its return type, e.g. `#[resource(nullable = "flag")]`.
+#[derive(Debug, Default)]
+struct ReturnAttrs {
+    /// Whether an `Option<Resource<_>>` return type is lowered to `(externref, i32)`
+    /// with the `i32` flag signalling whether the resource is present.
+    flag: bool,
+}
+
+impl ReturnAttrs {
+    fn parse(attrs: &[Attribute]) -> Result<Self, SynError> {
+        let mut return_attrs = Self::default();
+        for attr in attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident(ArgAttrs::NAME))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("nullable") {
+                    let lowering: LitStr = meta.value()?.parse()?;
+                    return_attrs.flag = match lowering.value().as_str() {
+                        "null" => false,
+                        "flag" => true,
+                        _ => {
+                            let msg = "unsupported lowering; expected \"null\" or \"flag\"";
+                            return Err(ErrorCode::BadAttr.spanned_error(&lowering, msg));
+                        }
+                    };
+                    Ok(())
+                } else {
+                    Err(ErrorCode::BadAttr.error(meta.path.span(), "unsupported attribute"))
+                }
+            })
+            .map_err(|err| ErrorCode::BadAttr.wrap(err))?;
+        }
+        Ok(return_attrs)
+    }
+
+    /// Removes `#[resource(..)]` attributes from the provided list.
+    fn strip(attrs: &mut Vec<Attribute>) {
+        attrs.retain(|attr| !attr.path().is_ident(ArgAttrs::NAME));
+    }
+}
+
+/// Identifiers of types recognized as `Resource`: `Resource` itself and, optionally,
+/// an alias set via `#[externref(resource_type = "..")]` (e.g., for a re-export
+/// like `pub use externref::Resource as Handle`).
+#[derive(Debug, Clone, Copy, Default)]
+struct ResourceIdents<'a> {
+    alias: Option<&'a str>,
+}
+
+impl ResourceIdents<'_> {
+    fn matches(self, ident: &Ident) -> bool {
+        ident == "Resource" || self.alias.is_some_and(|alias| ident == alias)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SimpleResourceKind {
     Owned,
@@ -47,22 +412,58 @@ enum SimpleResourceKind {
 }
 
 impl SimpleResourceKind {
-    fn is_resource(ty: &TypePath) -> bool {
-        ty.path.segments.last().map_or(false, |segment| {
-            segment.ident == "Resource"
-                && matches!(
-                    &segment.arguments,
-                    PathArguments::AngleBracketed(args) if args.args.len() == 1
-                )
-        })
+    /// Returns the marker type `T` if the path is `Resource<T>` or `Resource<T, S>` (where `S`
+    /// is the typestate), possibly qualified (e.g., `externref::Resource<T>`). The marker type
+    /// may be arbitrary, e.g. `Wrapper<T>` or `Wrapper<Vec<u8>, T>`.
+    fn marker_type<'ty>(ty: &'ty TypePath, idents: ResourceIdents<'_>) -> Option<&'ty Type> {
+        if ty.qself.is_some() {
+            return None;
+        }
+        let segment = ty.path.segments.last()?;
+        if !idents.matches(&segment.ident) {
+            return None;
+        }
+        let PathArguments::AngleBracketed(args) = &segment.arguments else {
+            return None;
+        };
+        let mut args = args.args.iter();
+        let Some(GenericArgument::Type(marker)) = args.next() else {
+            return None;
+        };
+        match (args.next(), args.next()) {
+            (None | Some(GenericArgument::Type(_)), None) => Some(marker),
+            _ => None,
+        }
+    }
+
+    fn is_resource(ty: &TypePath, idents: ResourceIdents<'_>) -> bool {
+        Self::marker_type(ty, idents).is_some()
+    }
+
+    /// Returns the resource kind name and the typestate `S` (`()` if not specified)
+    /// of a resource nested in the type, such as `Option<Resource<T, S>>`.
+    fn kind_and_state(ty: &Type, idents: ResourceIdents<'_>) -> Option<(String, String)> {
+        let path = find_nested_resource(ty, idents)?;
+        let kind_name = ResourceKind::kind_name(&Type::Path(path.clone()), idents);
+        let PathArguments::AngleBracketed(args) = &path.path.segments.last()?.arguments else {
+            return None;
+        };
+        let state = args
+            .args
+            .iter()
+            .nth(1)
+            .map_or_else(|| quote!(()), ToTokens::to_token_stream);
+        Some((kind_name, state.to_string().replace(' ', "")))
     }
 
-    fn from_type(ty: &Type) -> Option<Self> {
+    /// Recognizes a resource type, using `is_resource` to check whether a path denotes
+    /// a resource.
+    fn from_type(ty: &Type, is_resource: &dyn Fn(&TypePath) -> bool) -> Option<Self> {
         match ty {
-            Type::Path(path) if Self::is_resource(path) => Some(Self::Owned),
+            Type::Path(path) if is_resource(path) => Some(Self::Owned),
             Type::Reference(reference) => {
                 if let Type::Path(path) = reference.elem.as_ref() {
-                    if Self::is_resource(path) {
+                    if is_resource(path) {
                         return Some(if reference.mutability.is_some() {
                             Self::MutRef
                         } else {
@@ -81,6 +482,9 @@ impl SimpleResourceKind {
 enum ResourceKind {
     Simple(SimpleResourceKind),
     Option(SimpleResourceKind),
+    /// Arg marked with `#[resource(nullable)]`. Null `externref`s are converted
+    /// to null resources.
+    Nullable(SimpleResourceKind),
 }
 
 impl From<SimpleResourceKind> for ResourceKind {
@@ -104,26 +508,62 @@ impl ResourceKind {
         None
     }
 
-    fn from_type(ty: &Type) -> Option<Self> {
-        if let Some(kind) = SimpleResourceKind::from_type(ty) {
-            return Some(kind.into());
-        }
+    fn from_type(ty: &Type, idents: ResourceIdents<'_>) -> Option<Self> {
+        Self::from_type_with(ty, &|path| SimpleResourceKind::is_resource(path, idents))
+    }
 
+    /// Recognizes a type alias for `Resource<_>` (e.g., `Sender` for
+    /// `type Sender = Resource<SenderMarker>`), a reference to it, or `Option<_>` of these.
+    /// Used for args marked with `#[resource]` since the macro cannot resolve aliases.
+    fn from_alias(ty: &Type) -> Option<Self> {
+        Self::from_type_with(ty, &|_| true)
+    }
+
+    fn from_type_with(ty: &Type, is_resource: &dyn Fn(&TypePath) -> bool) -> Option<Self> {
         if let Type::Path(path) = ty {
-            Self::parse_option(path)
-                .and_then(|inner_ty| SimpleResourceKind::from_type(inner_ty).map(Self::Option))
-        } else {
-            None
+            if let Some(inner_ty) = Self::parse_option(path) {
+                return SimpleResourceKind::from_type(inner_ty, is_resource).map(Self::Option);
+            }
         }
+        SimpleResourceKind::from_type(ty, is_resource).map(Self::Simple)
     }
 
     fn simple_kind(self) -> SimpleResourceKind {
         match self {
-            Self::Simple(simple) | Self::Option(simple) => simple,
+            Self::Simple(simple) | Self::Option(simple) | Self::Nullable(simple) => simple,
         }
     }
 
-    fn initialize_for_export(self, arg: &Ident, cr: &Path) -> TokenStream {
+    /// Returns the resource kind name for a type recognized by [`Self::from_type()`], i.e.,
+    /// the last path segment of `T` in `Resource<T>` (or the entire `T` if it's not a path).
+    /// For types recognized by [`Self::from_alias()`], this is the last path segment
+    /// of the alias.
+    fn kind_name(ty: &Type, idents: ResourceIdents<'_>) -> String {
+        let ty = match ty {
+            Type::Reference(reference) => reference.elem.as_ref(),
+            _ => ty,
+        };
+        let Type::Path(path) = ty else {
+            unreachable!("not a resource type");
+        };
+
+        let name = if let Some(marker) = SimpleResourceKind::marker_type(path, idents) {
+            let name = match marker {
+                Type::Path(inner) => inner.path.segments.last().map(ToTokens::to_token_stream),
+                _ => None,
+            };
+            name.unwrap_or_else(|| marker.to_token_stream())
+        } else if let Some(inner_ty) = Self::parse_option(path) {
+            return Self::kind_name(inner_ty, idents);
+        } else {
+            let alias = path.path.segments.last().expect("empty path");
+            alias.to_token_stream()
+        };
+        name.to_string().replace(' ', "")
+    }
+
+    fn initialize_for_export(self, arg: &Ident, kind_name: &str, cr: &Path) -> TokenStream {
+        let kind = quote!(#cr::resource_kind!(#kind_name));
         match self {
             Self::Option(_) => {
                 let method_call = match self.simple_kind() {
@@ -131,22 +571,28 @@ impl ResourceKind {
                     SimpleResourceKind::Ref => Some(quote!(.as_ref())),
                     SimpleResourceKind::MutRef => Some(quote!(.as_mut())),
                 };
-                quote!(#cr::Resource::new(#arg) #method_call)
+                quote!(#cr::Resource::new(#arg, #kind) #method_call)
             }
-            Self::Simple(_) => {
+            Self::Simple(_) | Self::Nullable(_) => {
                 let ref_token = match self.simple_kind() {
                     SimpleResourceKind::Owned => None,
                     SimpleResourceKind::Ref => Some(quote!(&)),
                     SimpleResourceKind::MutRef => Some(quote!(&mut)),
                 };
-                quote!(#ref_token #cr::Resource::new_non_null(#arg))
+                let constructor = if matches!(self, Self::Nullable(_)) {
+                    quote!(new_nullable)
+                } else {
+                    quote!(new_non_null)
+                };
+                quote!(#ref_token #cr::Resource::#constructor(#arg, #kind))
             }
         }
     }
 
     fn prepare_for_import(self, arg: &Ident, cr: &Path) -> TokenStream {
         let arg = match self {
-            Self::Simple(_) => quote!(core::option::Option::Some(#arg)),
+            // Null resources are converted to null `externref`s by `Resource::raw()`.
+            Self::Simple(_) | Self::Nullable(_) => quote!(::core::option::Option::Some(#arg)),
             Self::Option(_) => quote!(#arg),
         };
 
@@ -157,6 +603,68 @@ impl ResourceKind {
             SimpleResourceKind::Owned => quote!(#cr::Resource::take_raw(#arg)),
         }
     }
+
+    /// Same as [`Self::prepare_for_import()`], but for a resource passed by value
+    /// and borrowed by the wrapper (see [`Function::wrap_import()`]).
+    fn prepare_borrowed_for_import(self, arg: &Ident, cr: &Path) -> TokenStream {
+        let arg = match self {
+            Self::Simple(_) | Self::Nullable(_) => quote!(::core::option::Option::Some(#arg)),
+            Self::Option(_) => quote!(::core::option::Option::as_ref(#arg)),
+        };
+        quote!(#cr::Resource::raw(#arg))
+    }
+}
+
+/// Checks whether the type is a `HostHandle<_>` passed by value.
+fn is_host_handle(ty: &Type) -> bool {
+    let Type::Path(path) = ty else {
+        return false;
+    };
+    path.path.segments.last().is_some_and(|segment| {
+        segment.ident == "HostHandle"
+            && matches!(
+                &segment.arguments,
+                PathArguments::AngleBracketed(args) if args.args.len() == 1
+            )
+    })
+}
+
+/// Finds a `Resource<_>` nested in the type, e.g. in `Vec<Resource<_>>` or `&Option<Resource<_>>`.
+/// Raw pointers are not inspected since they can be used to pass resources via linear memory.
+fn find_nested_resource<'ty>(ty: &'ty Type, idents: ResourceIdents<'_>) -> Option<&'ty TypePath> {
+    match ty {
+        Type::Path(path) if SimpleResourceKind::is_resource(path, idents) => Some(path),
+        Type::Path(path) => path.path.segments.iter().find_map(|segment| {
+            let PathArguments::AngleBracketed(args) = &segment.arguments else {
+                return None;
+            };
+            args.args.iter().find_map(|arg| match arg {
+                GenericArgument::Type(ty) => find_nested_resource(ty, idents),
+                _ => None,
+            })
+        }),
+        Type::Reference(reference) => find_nested_resource(&reference.elem, idents),
+        Type::Array(array) => find_nested_resource(&array.elem, idents),
+        Type::Slice(slice) => find_nested_resource(&slice.elem, idents),
+        Type::Paren(paren) => find_nested_resource(&paren.elem, idents),
+        Type::Group(group) => find_nested_resource(&group.elem, idents),
+        Type::Tuple(tuple) => tuple
+            .elems
+            .iter()
+            .find_map(|ty| find_nested_resource(ty, idents)),
+        _ => None,
+    }
+}
+
+/// Checks that a type not recognized as a resource does not contain resources, which
+/// would otherwise be silently passed without `externref` conversion.
+fn check_not_resource(ty: &Type, idents: ResourceIdents<'_>) -> Result<(), SynError> {
+    if find_nested_resource(ty, idents).is_some() {
+        let msg = "Unsupported resource type; resources can only be passed as `Resource<_>`, \
+            `&Resource<_>`, `&mut Resource<_>` or `Option<_>` of these";
+        return Err(ErrorCode::UnsupportedResourceType.spanned_error(ty, msg));
+    }
+    Ok(())
 }
 
 #[derive(Debug, PartialEq)]
@@ -164,39 +672,207 @@ enum ReturnType {
     Default,
     NotResource,
     Resource(ResourceKind),
+    /// `Result<_, E>` with a resource as the `Ok` variant and `E: Into<i32>`.
+    Fallible(ResourceKind),
+    /// `Option<_>` of a resource lowered to `(externref, i32)` with the `i32` flag signalling
+    /// whether the resource is present; set via `#[resource(nullable = "flag")]`.
+    Flagged(SimpleResourceKind),
+}
+
+impl ReturnType {
+    fn parse_result(ty: &Type) -> Option<&Type> {
+        let Type::Path(path) = ty else {
+            return None;
+        };
+        let segment = path.path.segments.last()?;
+        if segment.ident == "Result" {
+            if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                if args.args.len() == 2 {
+                    if let GenericArgument::Type(ty) = args.args.first().unwrap() {
+                        return Some(ty);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn from_type(ty: &Type, idents: ResourceIdents<'_>) -> Self {
+        if let Some(kind) = ResourceKind::from_type(ty, idents) {
+            Self::Resource(kind)
+        } else if let Some(ok_ty) = Self::parse_result(ty) {
+            ResourceKind::from_type(ok_ty, idents).map_or(Self::NotResource, Self::Fallible)
+        } else {
+            Self::NotResource
+        }
+    }
+
+    /// Number of values returned by the function on the WASM level after processing.
+    fn wasm_len(&self) -> usize {
+        match self {
+            Self::Default => 0,
+            Self::NotResource | Self::Resource(_) => 1,
+            Self::Fallible(_) | Self::Flagged(_) => 2,
+        }
+    }
 }
 
 struct Function {
     name: Expr,
     arg_count: usize,
     resource_args: HashMap<usize, ResourceKind>,
+    /// Indices of `HostHandle` args. Unlike `Resource`s, these args do not need conversion;
+    /// they are only recorded in the function declaration.
+    host_handle_args: Vec<usize>,
+    /// Whether the function returns a `HostHandle`. Like args, such returns are only recorded.
+    returns_host_handle: bool,
+    /// Indices of args marked with `#[resource(consumed)]`.
+    consumed_args: Vec<usize>,
     return_type: ReturnType,
+    abi: Option<Abi>,
     crate_path: Path,
+    /// Resource type names to record in the declaration; `None` if names are not recorded.
+    type_names: Option<Vec<String>>,
+    /// Table partition for resources obtained from the host, if overridden for the function.
+    table: Option<String>,
+    /// Table partitions overridden for specific args.
+    arg_tables: HashMap<usize, String>,
+    /// Module of the surrogate `guard` import, if overridden.
+    surrogate_module: Option<String>,
+    /// Custom section to place the declaration into, if overridden.
+    section: Option<String>,
+    /// Whether the function is declared as the module start function.
+    start: bool,
+    /// Whether the function is declared as a function table entry.
+    indirect_export: bool,
+    /// Alias of the `Resource` type, if specified.
+    resource_type: Option<String>,
+    /// Target ABI determining how the export wrapper is exported.
+    target_abi: TargetAbi,
+    /// `#[cfg(..)]` attributes gating the function (see [`cfg_gates()`]).
+    cfg_gates: Vec<Attribute>,
 }
 
 impl Function {
     fn new(function: &ItemFn, attrs: &ExternrefAttrs) -> Result<Self, SynError> {
-        let abi_name = function.sig.abi.as_ref().and_then(|abi| abi.name.as_ref());
-        check_abi("exported function", abi_name, &function.sig)?;
+        // With the `wasip2` target ABI, the ABI may be omitted since the wrapper is `extern "C"`
+        // anyway. The start function has no wrapper, so it must specify the ABI.
+        let may_omit_abi = attrs.target_abi == TargetAbi::Wasip2 && !attrs.start;
+        if !(may_omit_abi && function.sig.abi.is_none()) {
+            let abi_name = function.sig.abi.as_ref().and_then(|abi| abi.name.as_ref());
+            check_abi("exported function", abi_name, &function.sig, attrs)?;
+        }
 
         if let Some(variadic) = &function.sig.variadic {
             let msg = "Variadic functions are not supported";
-            return Err(SynError::new_spanned(variadic, msg));
+            return Err(ErrorCode::UnsupportedItem.spanned_error(variadic, msg));
+        }
+        if attrs.no_std {
+            check_no_std(function.sig.to_token_stream())?;
+        }
+        if attrs.surrogate_module.is_some() {
+            let msg = "`surrogate_module` attribute is only supported for foreign modules";
+            return Err(ErrorCode::BadAttr.spanned_error(&function.sig.ident, msg));
+        }
+        if attrs.start && attrs.indirect_export {
+            let msg = "`start` and `indirect_export` attributes are mutually exclusive";
+            return Err(ErrorCode::BadAttr.spanned_error(&function.sig.ident, msg));
+        }
+        let has_output = !matches!(function.sig.output, syn::ReturnType::Default);
+        if attrs.start && (!function.sig.inputs.is_empty() || has_output) {
+            let msg = "`start` attribute is only supported for functions without args \
+                and return type";
+            return Err(ErrorCode::BadAttr.spanned_error(&function.sig, msg));
         }
         let export_name = attr_expr(&function.attrs, "export_name")?;
-        Ok(Self::from_sig(&function.sig, export_name, attrs))
+        let mut parsed = Self::from_sig(&function.sig, export_name, attrs)?;
+        parsed.cfg_gates = cfg_gates(&function.attrs);
+        if ReturnAttrs::parse(&function.attrs)?.flag {
+            let ReturnType::Resource(ResourceKind::Option(kind)) = parsed.return_type else {
+                let msg = "`#[resource(nullable = \"flag\")]` is only supported for functions \
+                    returning `Option<_>` of a resource";
+                return Err(ErrorCode::MisplacedArgAttr.spanned_error(&function.sig.output, msg));
+            };
+            parsed.return_type = ReturnType::Flagged(kind);
+        }
+        if let Some(&idx) = parsed.consumed_args.first() {
+            let msg = "`#[resource(consumed)]` is only supported for args of imported functions";
+            return Err(ErrorCode::MisplacedArgAttr.spanned_error(&function.sig.inputs[idx], msg));
+        }
+        if attrs.strict {
+            parsed.check_unused_resources(function)?;
+        }
+        Ok(parsed)
     }
 
-    fn from_sig(sig: &Signature, name_override: Option<Expr>, attrs: &ExternrefAttrs) -> Self {
-        let resource_args = sig.inputs.iter().enumerate().filter_map(|(i, arg)| {
+    fn from_sig(
+        sig: &Signature,
+        name_override: Option<Expr>,
+        attrs: &ExternrefAttrs,
+    ) -> Result<Self, SynError> {
+        let idents = ResourceIdents {
+            alias: attrs.resource_type.as_deref(),
+        };
+        let mut resource_args = HashMap::new();
+        let mut arg_tables = HashMap::new();
+        let mut consumed_args = vec![];
+        for (i, arg) in sig.inputs.iter().enumerate() {
+            let FnArg::Typed(PatType { attrs, ty, .. }) = arg else {
+                continue;
+            };
+            let arg_attrs = ArgAttrs::parse(attrs)?;
+            let kind = if arg_attrs.alias {
+                let kind = ResourceKind::from_alias(ty);
+                if kind.is_none() {
+                    let msg = "`#[resource]` is only supported for args with a type alias \
+                        for `Resource<_>`, a reference to it, or `Option<_>` of these";
+                    return Err(ErrorCode::MisplacedArgAttr.spanned_error(ty, msg));
+                }
+                kind
+            } else {
+                ResourceKind::from_type(ty, idents)
+            };
+            if let Some(table) = arg_attrs.table {
+                if kind.is_none() {
+                    let msg = "`#[resource(table = ..)]` is only supported for resource args";
+                    return Err(ErrorCode::MisplacedArgAttr.spanned_error(ty, msg));
+                }
+                arg_tables.insert(i, table);
+            }
+            if arg_attrs.consumed {
+                if kind.map(ResourceKind::simple_kind) != Some(SimpleResourceKind::Owned) {
+                    let msg = "`#[resource(consumed)]` is only supported for resources \
+                        passed by value";
+                    return Err(ErrorCode::MisplacedArgAttr.spanned_error(ty, msg));
+                }
+                consumed_args.push(i);
+            }
+            if arg_attrs.nullable {
+                let Some(ResourceKind::Simple(simple)) = kind else {
+                    let msg = "`#[resource(nullable)]` is only supported for `Resource<_>`, \
+                        `&Resource<_>` and `&mut Resource<_>` args";
+                    return Err(ErrorCode::MisplacedArgAttr.spanned_error(ty, msg));
+                };
+                resource_args.insert(i, ResourceKind::Nullable(simple));
+            } else if let Some(kind) = kind {
+                resource_args.insert(i, kind);
+            } else if !is_host_handle(ty) {
+                check_not_resource(ty, idents)?;
+            }
+        }
+        let host_handle_args = sig.inputs.iter().enumerate().filter_map(|(i, arg)| {
             if let FnArg::Typed(PatType { ty, .. }) = arg {
-                return ResourceKind::from_type(ty).map(|kind| (i, kind));
+                return is_host_handle(ty).then_some(i);
             }
             None
         });
         let return_type = match &sig.output {
             syn::ReturnType::Type(_, ty) => {
-                ResourceKind::from_type(ty).map_or(ReturnType::NotResource, ReturnType::Resource)
+                let return_type = ReturnType::from_type(ty, idents);
+                if return_type == ReturnType::NotResource {
+                    check_not_resource(ty, idents)?;
+                }
+                return_type
             }
             syn::ReturnType::Default => ReturnType::Default,
         };
@@ -204,18 +880,158 @@ impl Function {
             let str = sig.ident.to_string();
             syn::parse_quote!(#str)
         });
+        let type_names = attrs
+            .type_names
+            .then(|| Self::type_names(sig, &resource_args, idents));
 
-        Self {
+        Ok(Self {
             name,
             arg_count: sig.inputs.len(),
-            resource_args: resource_args.collect(),
+            resource_args,
+            host_handle_args: host_handle_args.collect(),
+            returns_host_handle: matches!(&sig.output, syn::ReturnType::Type(_, ty) if is_host_handle(ty)),
+            consumed_args,
             return_type,
+            abi: sig.abi.clone(),
             crate_path: attrs.crate_path(),
+            type_names,
+            table: attrs.table.clone(),
+            arg_tables,
+            surrogate_module: attrs.surrogate_module.clone(),
+            section: attrs.section.clone(),
+            start: attrs.start,
+            indirect_export: attrs.indirect_export,
+            resource_type: attrs.resource_type.clone(),
+            target_abi: attrs.target_abi,
+            cfg_gates: vec![],
+        })
+    }
+
+    /// Checks that resources passed by value to an imported function are marked
+    /// with `#[resource(consumed)]`. Such resources are dropped after the call, which is easy
+    /// to miss when passing a resource by value rather than by reference.
+    ///
+    /// Typestate transitions, i.e. args of the same resource kind as the returned resource,
+    /// but in a different state, are considered consumed without the attribute.
+    fn check_consumed_args(&self, sig: &Signature) -> Result<(), SynError> {
+        let idents = self.resource_idents();
+        let returned = match &sig.output {
+            syn::ReturnType::Type(_, ty) => SimpleResourceKind::kind_and_state(ty, idents),
+            syn::ReturnType::Default => None,
+        };
+
+        for (i, arg) in sig.inputs.iter().enumerate() {
+            let Some(kind) = self.resource_args.get(&i) else {
+                continue;
+            };
+            let is_transition = returned.as_ref().is_some_and(|(ret_kind, ret_state)| {
+                let FnArg::Typed(PatType { ty, .. }) = arg else {
+                    return false;
+                };
+                SimpleResourceKind::kind_and_state(ty, idents)
+                    .is_some_and(|(kind, state)| kind == *ret_kind && state != *ret_state)
+            });
+            if kind.simple_kind() == SimpleResourceKind::Owned
+                && !self.consumed_args.contains(&i)
+                && !is_transition
+            {
+                let msg = "resource passed by value to an imported function is dropped \
+                    after the call; pass it by reference, or mark the arg \
+                    with `#[resource(consumed)]` if this is intended";
+                return Err(ErrorCode::ResourceMisuse.spanned_error(arg, msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that resources passed by value to an exported function are used
+    /// in the function body; otherwise, they are silently dropped once the function returns.
+    /// Like with unused variables, args with names starting with `_` are not checked.
+    fn check_unused_resources(&self, function: &ItemFn) -> Result<(), SynError> {
+        let body = function.block.to_token_stream();
+        for (i, arg) in function.sig.inputs.iter().enumerate() {
+            let Some(kind) = self.resource_args.get(&i) else {
+                continue;
+            };
+            let FnArg::Typed(PatType { pat, .. }) = arg else {
+                continue;
+            };
+            let Pat::Ident(pat) = pat.as_ref() else {
+                continue;
+            };
+            let is_unused = kind.simple_kind() == SimpleResourceKind::Owned
+                && !pat.ident.to_string().starts_with('_')
+                && !mentions_ident(body.clone(), &pat.ident);
+            if is_unused {
+                let msg = format!(
+                    "resource `{}` is never used and is dropped once the function returns; \
+                     prefix its name with `_` if this is intended",
+                    pat.ident
+                );
+                return Err(ErrorCode::ResourceMisuse.spanned_error(arg, msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the table partition for a resource obtained from the host,
+    /// which is located at the specified arg (`None` for the return type).
+    fn kind_name(&self, arg_idx: Option<usize>, ty: &Type) -> String {
+        let table = arg_idx.and_then(|idx| self.arg_tables.get(&idx));
+        let table = table.or(self.table.as_ref());
+        table.map_or_else(
+            || ResourceKind::kind_name(ty, self.resource_idents()),
+            String::clone,
+        )
+    }
+
+    fn resource_idents(&self) -> ResourceIdents<'_> {
+        ResourceIdents {
+            alias: self.resource_type.as_deref(),
         }
     }
 
+    /// Collects resource type names in the order of `externref` positions, i.e., args
+    /// in the increasing index order followed by the return type.
+    fn type_names(
+        sig: &Signature,
+        resource_args: &HashMap<usize, ResourceKind>,
+        idents: ResourceIdents<'_>,
+    ) -> Vec<String> {
+        let args = sig
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, arg)| match arg {
+                FnArg::Typed(PatType { ty, .. }) if resource_args.contains_key(&i) => {
+                    Some(ResourceKind::kind_name(ty, idents))
+                }
+                _ => None,
+            });
+        let return_ty = match &sig.output {
+            syn::ReturnType::Type(_, ty) => match ReturnType::from_type(ty, idents) {
+                ReturnType::Resource(_) | ReturnType::Flagged(_) => {
+                    Some(ResourceKind::kind_name(ty, idents))
+                }
+                ReturnType::Fallible(_) => {
+                    let ok_ty = ReturnType::parse_result(ty).expect("not a `Result`");
+                    Some(ResourceKind::kind_name(ok_ty, idents))
+                }
+                ReturnType::Default | ReturnType::NotResource => None,
+            },
+            syn::ReturnType::Default => None,
+        };
+        args.chain(return_ty).collect()
+    }
+
     fn needs_declaring(&self) -> bool {
-        !self.resource_args.is_empty() || matches!(self.return_type, ReturnType::Resource(_))
+        !self.resource_args.is_empty()
+            || !self.host_handle_args.is_empty()
+            || self.returns_host_handle
+            || matches!(
+                self.return_type,
+                ReturnType::Resource(_) | ReturnType::Fallible(_) | ReturnType::Flagged(_)
+            )
     }
 
     fn declare(&self, module_name: Option<&str>) -> impl ToTokens {
@@ -223,28 +1039,62 @@ impl Function {
         let cr = &self.crate_path;
         let kind = if let Some(module_name) = module_name {
             quote!(#cr::FunctionKind::Import(#module_name))
+        } else if self.start {
+            quote!(#cr::FunctionKind::Start)
+        } else if self.indirect_export {
+            quote!(#cr::FunctionKind::TableEntry)
         } else {
             quote!(#cr::FunctionKind::Export)
         };
         let externrefs = self.create_externrefs();
+        let host_handles = self.create_host_handles();
+        let type_names = if let Some(names) = &self.type_names {
+            let raw = names.join("\n");
+            quote!(#cr::TypeNames::from_raw(#raw))
+        } else {
+            quote!(#cr::TypeNames::EMPTY)
+        };
+        let surrogate_module = if let Some(module) = &self.surrogate_module {
+            quote!(::core::option::Option::Some(#module))
+        } else {
+            quote!(::core::option::Option::None)
+        };
 
+        let section = self
+            .section
+            .as_ref()
+            .map(|section| quote!(, section = #section));
+
+        let cfg_gates = &self.cfg_gates;
         quote! {
+            #(#cfg_gates)*
             #cr::declare_function!(#cr::Function {
                 kind: #kind,
                 name: #name,
                 externrefs: #externrefs,
-            });
+                host_handles: #host_handles,
+                type_names: #type_names,
+                surrogate_module: #surrogate_module,
+            } #section);
         }
     }
 
-    fn wrap_export(&self, raw: &ItemFn, export_name: Option<Attribute>) -> impl ToTokens {
+    fn wrap_export(&self, raw: &ItemFn) -> impl ToTokens {
         let cr = &self.crate_path;
-        let export_name = export_name.unwrap_or_else(|| {
-            let name = raw.sig.ident.to_string();
-            syn::parse_quote!(#[export_name = #name])
-        });
+        let name = &self.name;
+        let export_name: Attribute = match self.target_abi {
+            TargetAbi::Core => syn::parse_quote!(#[export_name = #name]),
+            TargetAbi::Wasip2 => syn::parse_quote!(#[unsafe(export_name = #name)]),
+        };
         let mut export_sig = raw.sig.clone();
-        export_sig.abi = Some(syn::parse_quote!(extern "C"));
+        // Retain the original ABI (e.g., `C-unwind`); it was checked when parsing the function.
+        // The ABI may be omitted only for the `wasip2` target ABI, in which case
+        // the wrapper uses the `C` ABI.
+        export_sig.abi = Some(
+            self.abi
+                .clone()
+                .unwrap_or_else(|| syn::parse_quote!(extern "C")),
+        );
         export_sig.unsafety = Some(syn::parse_quote!(unsafe));
         export_sig.ident = Ident::new("__externref_export", export_sig.ident.span());
 
@@ -252,11 +1102,13 @@ impl Function {
         for (i, arg) in export_sig.inputs.iter_mut().enumerate() {
             if let FnArg::Typed(typed_arg) = arg {
                 let arg = Ident::new(&format!("__arg{i}"), typed_arg.pat.span());
-                typed_arg.pat = Box::new(syn::parse_quote!(#arg));
+                *typed_arg.pat = syn::parse_quote!(#arg);
 
                 if let Some(kind) = self.resource_args.get(&i) {
-                    typed_arg.ty = Box::new(syn::parse_quote!(#cr::ExternRef));
-                    args.push(kind.initialize_for_export(&arg, cr));
+                    let kind_name = self.kind_name(Some(i), &typed_arg.ty);
+                    *typed_arg.ty = syn::parse_quote!(#cr::ExternRef);
+                    let init = kind.initialize_for_export(&arg, &kind_name, cr);
+                    args.push(respan(init, arg.span()));
                 } else {
                     args.push(quote!(#arg));
                 }
@@ -275,11 +1127,61 @@ impl Function {
                     #conversion
                 }
             }
+            ReturnType::Fallible(kind) => {
+                export_sig.output = syn::parse_quote!(-> #cr::ExternRef);
+                let output = Ident::new("__output", raw.sig.span());
+                let ok_value = match kind {
+                    ResourceKind::Simple(_) | ResourceKind::Nullable(_) => {
+                        quote!(::core::option::Option::Some(#output))
+                    }
+                    ResourceKind::Option(_) => quote!(#output),
+                };
+                // Convert the output to an `Option` first, so that a single `externref`
+                // is obtained on both paths.
+                let conversion =
+                    ResourceKind::Option(kind.simple_kind()).prepare_for_import(&output, cr);
+                quote! {
+                    let #output = match #delegation {
+                        ::core::result::Result::Ok(#output) => {
+                            #cr::ExternRef::set_error_code(0);
+                            #ok_value
+                        }
+                        ::core::result::Result::Err(__err) => {
+                            let __code: i32 = ::core::convert::Into::into(__err);
+                            #cr::ExternRef::set_error_code(__code);
+                            ::core::option::Option::None
+                        }
+                    };
+                    #conversion
+                }
+            }
+            ReturnType::Flagged(kind) => {
+                export_sig.output = syn::parse_quote!(-> #cr::ExternRef);
+                let output = Ident::new("__output", raw.sig.span());
+                let conversion = ResourceKind::Option(kind).prepare_for_import(&output, cr);
+                quote! {
+                    let #output = #delegation;
+                    let __flag = ::core::option::Option::is_some(&#output);
+                    #cr::ExternRef::set_error_code(::core::primitive::i32::from(__flag));
+                    #conversion
+                }
+            }
             ReturnType::NotResource => delegation,
             ReturnType::Default => quote!(#delegation;),
         };
 
+        let cfg_gates = &self.cfg_gates;
+        if self.indirect_export {
+            return Self::wrap_indirect_export(
+                raw,
+                cfg_gates,
+                &export_name,
+                export_sig,
+                &delegation,
+            );
+        }
         quote! {
+            #(#cfg_gates)*
             const _: () = {
                 #export_name
                 #export_sig {
@@ -289,60 +1191,187 @@ impl Function {
         }
     }
 
-    fn wrap_import(&self, vis: &Visibility, mut sig: Signature) -> (TokenStream, Ident) {
+    /// Unlike with ordinary exports, the wrapper of a function table entry needs to be accessible,
+    /// so that it can be placed in the table. Thus, it is emitted as a sibling of the original
+    /// function.
+    fn wrap_indirect_export(
+        raw: &ItemFn,
+        cfg_gates: &[Attribute],
+        export_name: &Attribute,
+        mut export_sig: Signature,
+        delegation: &TokenStream,
+    ) -> TokenStream {
+        let vis = &raw.vis;
+        let original_name = &raw.sig.ident;
+        export_sig.ident = Ident::new(&format!("{original_name}_indirect"), original_name.span());
+        let doc = format!("Wrapper of [`{original_name}()`] to be placed in a function table.");
+        quote! {
+            #(#cfg_gates)*
+            #[doc = #doc]
+            #[doc = ""]
+            #[doc = "# Safety"]
+            #[doc = ""]
+            #[doc = "The wrapper must only be called by the host, e.g. via the function table."]
+            #export_name
+            #vis #export_sig {
+                #delegation
+            }
+        }
+    }
+
+    /// Wraps an imported function. If the function is declared as `safe` (which is possible
+    /// in `unsafe extern` blocks), the wrapper is safe as well, and only conversions
+    /// between resources and `externref`s are wrapped in `unsafe` blocks.
+    ///
+    /// If the function takes resources by value, the guarded wrapper is nested into an outer
+    /// function and borrows these resources; the outer function drops them after the call.
+    /// Otherwise, the compiler may spill owned resources to the stack before the guard,
+    /// which would break guard placement.
+    fn wrap_import(
+        &self,
+        attrs: &[Attribute],
+        vis: &Visibility,
+        mut sig: Signature,
+        is_safe: bool,
+    ) -> (TokenStream, Ident) {
         let cr = &self.crate_path;
-        sig.unsafety = Some(syn::parse_quote!(unsafe));
+        if !is_safe {
+            sig.unsafety = Some(syn::parse_quote!(unsafe));
+        }
+        let unsafe_block = |tokens: TokenStream| {
+            if is_safe {
+                quote!(unsafe { #tokens })
+            } else {
+                tokens
+            }
+        };
         let new_ident = format!("__externref_{}", sig.ident);
         let new_ident = Ident::new(&new_ident, sig.ident.span());
 
         let mut args = Vec::with_capacity(sig.inputs.len());
-        for (i, arg) in sig.inputs.iter_mut().enumerate() {
+        let mut outer_args = Vec::with_capacity(sig.inputs.len());
+        let mut inner_sig = sig.clone();
+        for (i, arg) in inner_sig.inputs.iter_mut().enumerate() {
             if let FnArg::Typed(typed_arg) = arg {
                 let arg = Ident::new(&format!("__arg{i}"), typed_arg.pat.span());
-                typed_arg.pat = Box::new(syn::parse_quote!(#arg));
-
-                if let Some(kind) = self.resource_args.get(&i) {
-                    args.push(kind.prepare_for_import(&arg, cr));
-                } else {
-                    args.push(quote!(#arg));
-                }
+                *typed_arg.pat = syn::parse_quote!(#arg);
+
+                match self.resource_args.get(&i) {
+                    Some(kind) if kind.simple_kind() == SimpleResourceKind::Owned => {
+                        let ty = &typed_arg.ty;
+                        *typed_arg.ty = syn::parse_quote!(&#ty);
+                        outer_args.push(quote!(&#arg));
+                        args.push(unsafe_block(kind.prepare_borrowed_for_import(&arg, cr)));
+                    }
+                    Some(kind) => {
+                        outer_args.push(quote!(#arg));
+                        args.push(unsafe_block(kind.prepare_for_import(&arg, cr)));
+                    }
+                    None => {
+                        outer_args.push(quote!(#arg));
+                        args.push(quote!(#arg));
+                    }
+                }
             }
         }
+        let has_owned_args = self
+            .resource_args
+            .values()
+            .any(|kind| kind.simple_kind() == SimpleResourceKind::Owned);
 
         let delegation = quote!(#new_ident(#(#args,)*));
         let delegation = match self.return_type {
             ReturnType::Resource(kind) => {
                 let output = Ident::new("__output", sig.span());
-                let init = kind.initialize_for_export(&output, cr);
+                let syn::ReturnType::Type(_, return_ty) = &sig.output else {
+                    unreachable!("resource return type");
+                };
+                let kind_name = self.kind_name(None, return_ty);
+                let init = unsafe_block(kind.initialize_for_export(&output, &kind_name, cr));
+                let init = respan(init, sig.ident.span());
                 quote! {
                     let #output = #delegation;
                     #init
                 }
             }
+            ReturnType::Fallible(_) => unreachable!("fallible imports are rejected on parsing"),
+            ReturnType::Flagged(_) => unreachable!("flagged imports are rejected on parsing"),
             ReturnType::NotResource => delegation,
             ReturnType::Default => quote!(#delegation;),
         };
 
-        let wrapper = quote! {
-            #[inline(never)]
-            #vis #sig {
-                unsafe { #cr::ExternRef::guard(); }
-                #delegation
+        let guard = self.guard(cr);
+        let wrapper = if has_owned_args {
+            for (i, arg) in sig.inputs.iter_mut().enumerate() {
+                if let FnArg::Typed(typed_arg) = arg {
+                    let arg = Ident::new(&format!("__arg{i}"), typed_arg.pat.span());
+                    *typed_arg.pat = syn::parse_quote!(#arg);
+                }
+            }
+            let inner_ident = Ident::new("__externref_wrapper", sig.ident.span());
+            inner_sig.ident = inner_ident.clone();
+            let call = quote!(#inner_ident(#(#outer_args,)*));
+            let call = if is_safe {
+                call
+            } else {
+                quote!(unsafe { #call })
+            };
+            quote! {
+                #(#attrs)*
+                #vis #sig {
+                    #[inline(never)]
+                    #inner_sig {
+                        #guard
+                        #delegation
+                    }
+                    #call
+                }
+            }
+        } else {
+            quote! {
+                #(#attrs)*
+                #[inline(never)]
+                #vis #inner_sig {
+                    #guard
+                    #delegation
+                }
             }
         };
         (wrapper, new_ident)
     }
 
-    fn create_externrefs(&self) -> impl ToTokens {
-        let cr = &self.crate_path;
-        let args_and_return_type_count = if matches!(self.return_type, ReturnType::Default) {
-            self.arg_count
-        } else {
-            self.arg_count + 1
+    /// Generates a call to the surrogate `guard` import, which must be the first instruction
+    /// in the import wrapper.
+    fn guard(&self, cr: &Path) -> TokenStream {
+        let Some(module) = &self.surrogate_module else {
+            return quote!(unsafe { #cr::ExternRef::guard(); });
         };
-        let bytes = (args_and_return_type_count + 7) / 8;
+        quote! {
+            unsafe {
+                #[cfg(target_arch = "wasm32")]
+                #[link(wasm_import_module = #module)]
+                extern "C" {
+                    #[link_name = "guard"]
+                    fn __externref_guard();
+                }
+
+                #[cfg(target_arch = "wasm32")]
+                __externref_guard();
+            }
+        }
+    }
 
-        let maybe_ret_idx = if matches!(self.return_type, ReturnType::Resource(_)) {
+    fn create_externrefs(&self) -> impl ToTokens {
+        let cr = &self.crate_path;
+        // Fallible and flagged exports are recorded with the `(externref, i32)` return type,
+        // which they will have after processing.
+        let args_and_return_type_count = self.arg_count + self.return_type.wasm_len();
+        let bytes = args_and_return_type_count.div_ceil(8);
+
+        let maybe_ret_idx = if matches!(
+            self.return_type,
+            ReturnType::Resource(_) | ReturnType::Fallible(_) | ReturnType::Flagged(_)
+        ) {
             Some(self.arg_count)
         } else {
             None
@@ -364,6 +1393,29 @@ impl Function {
                 .build()
         }
     }
+
+    fn create_host_handles(&self) -> TokenStream {
+        let cr = &self.crate_path;
+        if self.host_handle_args.is_empty() && !self.returns_host_handle {
+            return quote!(#cr::BitSlice::EMPTY);
+        }
+
+        // Like for `externrefs`, return values are placed after args.
+        let arg_count = self.arg_count;
+        let bit_len = arg_count + usize::from(self.returns_host_handle);
+        let bytes = bit_len.div_ceil(8);
+        let return_idx = self.returns_host_handle.then_some(arg_count);
+        let set_bits = self
+            .host_handle_args
+            .iter()
+            .chain(&return_idx)
+            .map(|idx| quote!(.with_set_bit(#idx)));
+        quote! {
+            #cr::BitSlice::builder::<#bytes>(#bit_len)
+                #(#set_bits)*
+                .build()
+        }
+    }
 }
 
 pub(crate) fn for_export(function: &mut ItemFn, attrs: &ExternrefAttrs) -> TokenStream {
@@ -371,26 +1423,36 @@ pub(crate) fn for_export(function: &mut ItemFn, attrs: &ExternrefAttrs) -> Token
         Ok(function) => function,
         Err(err) => return err.into_compile_error(),
     };
-    let (declaration, export) = if parsed_function.needs_declaring() {
-        // "Un-export" the function by removing the relevant attributes.
+    ArgAttrs::strip(&mut function.sig);
+    ReturnAttrs::strip(&mut function.attrs);
+    let (declaration, export) = if parsed_function.start {
+        // The start function has no resource args / return type, so it needs no wrapper.
+        (Some(parsed_function.declare(None)), None)
+    } else if parsed_function.needs_declaring() {
+        // "Un-export" the function by removing the relevant attributes (possibly wrapped
+        // in `unsafe(..)`); if they are retained, they will still generate an export.
+        // The export name is already recorded in the parsed function.
         function.sig.abi = None;
-        let attr_idx = function.attrs.iter().enumerate().find_map(|(idx, attr)| {
-            if attr.path().is_ident("export_name") {
-                Some(idx)
-            } else {
-                None
-            }
+        function.attrs.retain(|attr| {
+            let meta = attr_meta(attr);
+            !meta.path().is_ident("export_name") && !meta.path().is_ident("no_mangle")
         });
-        let export_name_attr = attr_idx.map(|idx| function.attrs.remove(idx));
-
-        // Remove `#[no_mangle]` attr if present as well; if it is retained, it will still
-        // generate an export.
-        function
-            .attrs
-            .retain(|attr| !attr.path().is_ident("no_mangle"));
 
-        let export = parsed_function.wrap_export(function, export_name_attr);
+        let export = parsed_function.wrap_export(function);
         (Some(parsed_function.declare(None)), Some(export))
+    } else if parsed_function.indirect_export {
+        let msg = "`indirect_export` attribute is only supported for functions \
+            with resource args or return type";
+        return ErrorCode::BadAttr
+            .spanned_error(&function.sig, msg)
+            .into_compile_error();
+    } else if function.sig.abi.is_none() {
+        // Only possible with the `wasip2` target ABI; without a wrapper, the function
+        // would be exported with the Rust ABI.
+        let msg = "exported function without resources must be marked with `extern \"C\"`";
+        return ErrorCode::UnsupportedAbi
+            .spanned_error(&function.sig, msg)
+            .into_compile_error();
     } else {
         (None, None)
     };
@@ -402,86 +1464,84 @@ pub(crate) fn for_export(function: &mut ItemFn, attrs: &ExternrefAttrs) -> Token
     }
 }
 
+/// `safe fn` item in an `unsafe extern` block (Rust 2024), which `syn` parses as verbatim tokens.
+struct SafeForeignFn(ForeignItemFn);
+
+impl Parse for SafeForeignFn {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        syn::custom_keyword!(safe);
+
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<safe>()?;
+        let mut fn_item: ForeignItemFn = input.parse()?;
+        fn_item.attrs = attrs;
+        fn_item.vis = vis;
+        Ok(Self(fn_item))
+    }
+}
+
+impl ToTokens for SafeForeignFn {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let ForeignItemFn {
+            attrs,
+            vis,
+            sig,
+            semi_token,
+        } = &self.0;
+        tokens.extend(quote!(#(#attrs)* #vis safe #sig #semi_token));
+    }
+}
+
 struct Imports {
     module_name: String,
     functions: Vec<(Function, TokenStream)>,
 }
 
 impl Imports {
-    fn new(module: &mut ItemForeignMod, attrs: &ExternrefAttrs) -> Result<Self, SynError> {
-        const NO_ATTR_MSG: &str = "#[link(wasm_import_module = \"..\")] must be specified \
-            on the foreign module";
+    const NO_ATTR_MSG: &'static str = "#[link(wasm_import_module = \"..\")] must be specified \
+        on the foreign module";
 
-        check_abi("foreign module", module.abi.name.as_ref(), &module.abi)?;
+    fn new(module: &mut ItemForeignMod, attrs: &ExternrefAttrs) -> Result<Self, SynError> {
+        check_abi(
+            "foreign module",
+            module.abi.name.as_ref(),
+            &module.abi,
+            attrs,
+        )?;
+        if attrs.start || attrs.indirect_export {
+            let msg = "`start` and `indirect_export` attributes are only supported \
+                for exported functions";
+            return Err(ErrorCode::BadAttr.spanned_error(&module.abi, msg));
+        }
+        if attrs.target_abi != TargetAbi::Core {
+            let msg = "`target_abi` attribute is only supported for exported functions";
+            return Err(ErrorCode::BadAttr.spanned_error(&module.abi, msg));
+        }
 
         let link_attr = module
             .attrs
-            .iter()
+            .iter_mut()
             .find(|attr| attr.path().is_ident("link"));
         let Some(link_attr) = link_attr else {
-            return Err(SynError::new_spanned(module, NO_ATTR_MSG));
-        };
-
-        let module_name = if matches!(link_attr.meta, Meta::List(_)) {
-            let nested =
-                link_attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
-            nested
-                .into_iter()
-                .find_map(|nested_meta| match nested_meta {
-                    Meta::NameValue(nv) if nv.path.is_ident("wasm_import_module") => Some(nv.value),
-                    _ => None,
-                })
-        } else {
-            let msg =
-                "Unexpected contents of `#[link(..)]` attr (expected a list of name-value pairs)";
-            return Err(SynError::new_spanned(link_attr, msg));
-        };
-
-        let module_name =
-            module_name.ok_or_else(|| SynError::new_spanned(link_attr, NO_ATTR_MSG))?;
-        let module_name = if let Expr::Lit(ExprLit {
-            lit: Lit::Str(str), ..
-        }) = module_name
-        {
-            str.value()
-        } else {
-            let msg = "Unexpected WASM module name format (expected a string)";
-            return Err(SynError::new(module_name.span(), msg));
+            return Err(ErrorCode::BadLinkAttr.spanned_error(module, Self::NO_ATTR_MSG));
         };
+        let module_name = Self::resolve_module_name(link_attr)?;
 
-        let cr = attrs.crate_path();
         let mut functions = Vec::with_capacity(module.items.len());
         for item in &mut module.items {
-            if let ForeignItem::Fn(fn_item) = item {
-                let link_name = attr_expr(&fn_item.attrs, "link_name")?;
-                let has_link_name = link_name.is_some();
-                let function = Function::from_sig(&fn_item.sig, link_name, attrs);
-                if !function.needs_declaring() {
-                    continue;
-                }
-
-                let vis = mem::replace(&mut fn_item.vis, Visibility::Inherited);
-                let (wrapper, new_ident) = function.wrap_import(&vis, fn_item.sig.clone());
-                if !has_link_name {
-                    // Add `#[link_name = ".."]` since the function is renamed.
-                    let name = fn_item.sig.ident.to_string();
-                    fn_item.attrs.push(syn::parse_quote!(#[link_name = #name]));
-                }
-                fn_item.sig.ident = new_ident;
-
-                // Change function signature to use `usize`s in place of `Resource`s.
-                for (i, arg) in fn_item.sig.inputs.iter_mut().enumerate() {
-                    if function.resource_args.contains_key(&i) {
-                        if let FnArg::Typed(typed_arg) = arg {
-                            typed_arg.ty = Box::new(syn::parse_quote!(#cr::ExternRef));
-                        }
-                    }
+            match item {
+                ForeignItem::Fn(fn_item) => {
+                    functions.extend(Self::process_fn(fn_item, false, attrs)?);
                 }
-                if matches!(function.return_type, ReturnType::Resource(_)) {
-                    fn_item.sig.output = syn::parse_quote!(-> #cr::ExternRef);
+                ForeignItem::Verbatim(tokens) => {
+                    let Ok(SafeForeignFn(mut fn_item)) = syn::parse2(tokens.clone()) else {
+                        continue; // not a function; leave the item as is
+                    };
+                    functions.extend(Self::process_fn(&mut fn_item, true, attrs)?);
+                    *tokens = SafeForeignFn(fn_item).into_token_stream();
                 }
-
-                functions.push((function, wrapper));
+                _ => { /* other items are left as is */ }
             }
         }
 
@@ -491,6 +1551,100 @@ impl Imports {
         })
     }
 
+    /// Processes a function in the foreign module, transforming it into a surrogate import
+    /// and returning its wrapper if the function needs declaring.
+    fn process_fn(
+        fn_item: &mut ForeignItemFn,
+        is_safe: bool,
+        attrs: &ExternrefAttrs,
+    ) -> Result<Option<(Function, TokenStream)>, SynError> {
+        let item_attrs = ItemAttrs::take(&mut fn_item.attrs)?;
+        if item_attrs.skip {
+            return Ok(None);
+        }
+        if ReturnAttrs::parse(&fn_item.attrs)?.flag {
+            let msg = "`#[resource(nullable = \"flag\")]` is only supported \
+                for exported functions";
+            return Err(ErrorCode::MisplacedArgAttr.spanned_error(&fn_item.sig.output, msg));
+        }
+        ReturnAttrs::strip(&mut fn_item.attrs);
+        let link_name = attr_expr(&fn_item.attrs, "link_name")?;
+        let has_link_name = link_name.is_some();
+        let mut function = Function::from_sig(&fn_item.sig, link_name, attrs)?;
+        if attrs.strict {
+            function.check_consumed_args(&fn_item.sig)?;
+        }
+        if item_attrs.table.is_some() {
+            function.table = item_attrs.table;
+        }
+        ArgAttrs::strip(&mut fn_item.sig);
+        if !function.needs_declaring() {
+            return Ok(None);
+        }
+        if matches!(function.return_type, ReturnType::Fallible(_)) {
+            let msg = "`Result` return types are only supported for exported functions";
+            return Err(ErrorCode::ImportWithResult.spanned_error(&fn_item.sig.output, msg));
+        }
+        if attrs.no_std {
+            check_no_std(fn_item.sig.to_token_stream())?;
+        }
+
+        function.cfg_gates = cfg_gates(&fn_item.attrs);
+        let wrapper_attrs = take_wrapper_attrs(&mut fn_item.attrs);
+        let vis = mem::replace(&mut fn_item.vis, Visibility::Inherited);
+        let (wrapper, new_ident) =
+            function.wrap_import(&wrapper_attrs, &vis, fn_item.sig.clone(), is_safe);
+        if !has_link_name {
+            // Add `#[link_name = ".."]` since the function is renamed.
+            let name = fn_item.sig.ident.to_string();
+            fn_item.attrs.push(syn::parse_quote!(#[link_name = #name]));
+        }
+        fn_item.sig.ident = new_ident;
+
+        // Change function signature to use `usize`s in place of `Resource`s.
+        let cr = attrs.crate_path();
+        for (i, arg) in fn_item.sig.inputs.iter_mut().enumerate() {
+            if function.resource_args.contains_key(&i) {
+                if let FnArg::Typed(typed_arg) = arg {
+                    *typed_arg.ty = syn::parse_quote!(#cr::ExternRef);
+                }
+            }
+        }
+        if matches!(function.return_type, ReturnType::Resource(_)) {
+            fn_item.sig.output = syn::parse_quote!(-> #cr::ExternRef);
+        }
+        Ok(Some((function, wrapper)))
+    }
+
+    /// Resolves the module name from the `#[link(..)]` attribute. If the name is specified
+    /// via a macro call, the attribute is rewritten to use the resolved string literal
+    /// since `rustc` only accepts literals in `#[link(..)]`.
+    fn resolve_module_name(link_attr: &mut Attribute) -> Result<String, SynError> {
+        let mut nested = if matches!(link_attr.meta, Meta::List(_)) {
+            link_attr
+                .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                .map_err(|err| ErrorCode::BadLinkAttr.wrap(err))?
+        } else {
+            let msg =
+                "Unexpected contents of `#[link(..)]` attr (expected a list of name-value pairs)";
+            return Err(ErrorCode::BadLinkAttr.spanned_error(link_attr, msg));
+        };
+
+        let name_value = nested.iter_mut().find_map(|nested_meta| match nested_meta {
+            Meta::NameValue(nv) if nv.path.is_ident("wasm_import_module") => Some(nv),
+            _ => None,
+        });
+        let name_value = name_value
+            .ok_or_else(|| ErrorCode::BadLinkAttr.spanned_error(&*link_attr, Self::NO_ATTR_MSG))?;
+        let module_name = resolve_str_expr(&name_value.value)?;
+        if !matches!(name_value.value, Expr::Lit(_)) {
+            let lit = LitStr::new(&module_name, name_value.value.span());
+            name_value.value = syn::parse_quote!(#lit);
+            *link_attr = syn::parse_quote!(#[link(#nested)]);
+        }
+        Ok(module_name)
+    }
+
     fn declarations(&self) -> impl ToTokens {
         let function_declarations = self
             .functions
@@ -550,45 +1704,252 @@ mod tests {
                     .with_set_bit(0usize)
                     .with_set_bit(1usize)
                     .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
             });
         };
         assert_eq!(declaration, expected, "{}", quote!(#declaration));
     }
 
     #[test]
-    fn transforming_export() {
+    fn declaring_start_function() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            #[no_mangle]
+            pub extern "C" fn init() {
+                // does nothing
+            }
+        };
+        let attrs = ExternrefAttrs {
+            start: true,
+            ..ExternrefAttrs::default()
+        };
+        let output = for_export(&mut export_fn, &attrs);
+        let output: syn::File = syn::parse_quote!(#output);
+        let expected: syn::File = syn::parse_quote! {
+            #[no_mangle]
+            pub extern "C" fn init() {
+                // does nothing
+            }
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Start,
+                name: "init",
+                externrefs: externref::BitSlice::builder::<0usize>(0usize).build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(output, expected, "{}", quote!(#output));
+    }
+
+    #[test]
+    fn start_function_with_args() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn init(sender: Resource<Sender>) {}
+        };
+        let attrs = ExternrefAttrs {
+            start: true,
+            ..ExternrefAttrs::default()
+        };
+        let Err(err) = Function::new(&export_fn, &attrs) else {
+            panic!("start function with args accepted");
+        };
+        let err = err.to_string();
+        assert!(err.starts_with("[E005]"), "{err}");
+        assert!(err.contains("without args and return type"), "{err}");
+    }
+
+    #[test]
+    fn declaring_indirect_export() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn on_event(event: Resource<Event>, code: u32) {
+                // does nothing
+            }
+        };
+        let attrs = ExternrefAttrs {
+            indirect_export: true,
+            ..ExternrefAttrs::default()
+        };
+        let output = for_export(&mut export_fn, &attrs);
+        let output: syn::File = syn::parse_quote!(#output);
+        let expected: syn::File = syn::parse_quote! {
+            pub fn on_event(event: Resource<Event>, code: u32) {
+                // does nothing
+            }
+
+            #[doc = "Wrapper of [`on_event()`] to be placed in a function table."]
+            #[doc = ""]
+            #[doc = "# Safety"]
+            #[doc = ""]
+            #[doc = "The wrapper must only be called by the host, e.g. via the function table."]
+            #[export_name = "on_event"]
+            pub unsafe extern "C" fn on_event_indirect(__arg0: externref::ExternRef, __arg1: u32) {
+                on_event(
+                    externref::Resource::new_non_null(__arg0, externref::resource_kind!("Event")),
+                    __arg1,
+                );
+            }
+
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::TableEntry,
+                name: "on_event",
+                externrefs: externref::BitSlice::builder::<1usize>(2usize)
+                    .with_set_bit(0usize)
+                    .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(output, expected, "{}", quote!(#output));
+    }
+
+    #[test]
+    fn indirect_export_without_resources() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn on_event(code: u32) {}
+        };
+        let attrs = ExternrefAttrs {
+            indirect_export: true,
+            ..ExternrefAttrs::default()
+        };
+        let err = for_export(&mut export_fn, &attrs).to_string();
+        assert!(err.contains("[E005] `indirect_export` attribute"), "{err}");
+
+        let attrs = ExternrefAttrs {
+            start: true,
+            indirect_export: true,
+            ..ExternrefAttrs::default()
+        };
+        let Err(err) = Function::new(&export_fn, &attrs) else {
+            panic!("start function declared as a table entry accepted");
+        };
+        let err = err.to_string();
+        assert!(err.starts_with("[E005]"), "{err}");
+        assert!(err.contains("mutually exclusive"), "{err}");
+    }
+
+    #[test]
+    fn declaring_signature_with_type_names() {
         let export_fn: ItemFn = syn::parse_quote! {
             pub extern "C" fn test_export(
                 sender: &mut Resource<Sender>,
-                buffer: Option<Resource<Buffer>>,
-                some_ptr: *const u8,
-            ) {
+                len: usize,
+                buffer: Option<Resource<crate::Buffer>>,
+            ) -> Result<Resource<Bytes>, i32> {
                 // does nothing
             }
         };
-        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
-        assert_eq!(parsed.resource_args.len(), 2);
-        assert_eq!(parsed.resource_args[&0], SimpleResourceKind::MutRef.into());
+        let attrs = ExternrefAttrs {
+            type_names: true,
+            ..ExternrefAttrs::default()
+        };
+        let parsed = Function::new(&export_fn, &attrs).unwrap();
         assert_eq!(
-            parsed.resource_args[&1],
-            ResourceKind::Option(SimpleResourceKind::Owned)
+            parsed.type_names.as_deref().unwrap(),
+            ["Sender", "Buffer", "Bytes"]
         );
-        assert_eq!(parsed.return_type, ReturnType::Default);
 
-        let wrapper = parsed.wrap_export(&export_fn, None);
+        let declaration = parsed.declare(None);
+        let declaration: syn::Item = syn::parse_quote!(#declaration);
+        let expected: syn::Item = syn::parse_quote! {
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Export,
+                name: "test_export",
+                externrefs: externref::BitSlice::builder::<1usize>(5usize)
+                    .with_set_bit(0usize)
+                    .with_set_bit(2usize)
+                    .with_set_bit(3usize)
+                    .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::from_raw("Sender\nBuffer\nBytes"),
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(declaration, expected, "{}", quote!(#declaration));
+    }
+
+    #[test]
+    fn recognizing_resource_type_alias() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(
+                sender: &crate::reexports::Handle<Sender>,
+                bytes: Option<Handle<Bytes>>,
+                buffer: Resource<Buffer>,
+            ) -> Handle<Bytes> {
+                // does nothing
+            }
+        };
+        let attrs = ExternrefAttrs {
+            type_names: true,
+            resource_type: Some("Handle".to_owned()),
+            ..ExternrefAttrs::default()
+        };
+        let parsed = Function::new(&export_fn, &attrs).unwrap();
+        assert_eq!(parsed.resource_args.len(), 3);
+        assert_eq!(
+            parsed.return_type,
+            ReturnType::Resource(ResourceKind::Simple(SimpleResourceKind::Owned))
+        );
+        assert_eq!(
+            parsed.type_names.as_deref().unwrap(),
+            ["Sender", "Bytes", "Buffer", "Bytes"]
+        );
+
+        // Without the attribute, the alias is not recognized.
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(parsed.resource_args.len(), 1);
+        assert_eq!(parsed.return_type, ReturnType::NotResource);
+    }
+
+    #[test]
+    fn declaring_signature_with_host_handles() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(
+                context: HostHandle<Sender>,
+                buffer: &Resource<Buffer>,
+            ) {
+                // does nothing
+            }
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(parsed.host_handle_args, [0]);
+
+        let declaration = parsed.declare(None);
+        let declaration: syn::Item = syn::parse_quote!(#declaration);
+        let expected: syn::Item = syn::parse_quote! {
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Export,
+                name: "test_export",
+                externrefs: externref::BitSlice::builder::<1usize>(2usize)
+                    .with_set_bit(1usize)
+                    .build(),
+                host_handles: externref::BitSlice::builder::<1usize>(2usize)
+                    .with_set_bit(0usize)
+                    .build(),
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(declaration, expected, "{}", quote!(#declaration));
+
+        // Host handles are passed as is.
+        let wrapper = parsed.wrap_export(&export_fn);
         let wrapper: syn::Item = syn::parse_quote!(#wrapper);
         let expected: syn::Item = syn::parse_quote! {
             const _: () = {
                 #[export_name = "test_export"]
                 unsafe extern "C" fn __externref_export(
-                    __arg0: externref::ExternRef,
+                    __arg0: HostHandle<Sender>,
                     __arg1: externref::ExternRef,
-                    __arg2: *const u8,
                 ) {
                     test_export(
-                        &mut externref::Resource::new_non_null(__arg0),
-                        externref::Resource::new(__arg1),
-                        __arg2,
+                        __arg0,
+                        &externref::Resource::new_non_null(
+                            __arg1,
+                            externref::resource_kind!("Buffer")
+                        ),
                     );
                 }
             };
@@ -597,41 +1958,630 @@ mod tests {
     }
 
     #[test]
-    fn wrapper_for_import() {
-        let sig: Signature = syn::parse_quote! {
-            fn send_message(
-                sender: &Resource<Sender>,
-                message_ptr: *const u8,
-                message_len: usize,
-            ) -> Resource<Bytes>
+    fn declaring_signature_with_returned_host_handle() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(sender: Resource<Sender>) -> HostHandle<Sender> {
+                sender.into_host_handle()
+            }
         };
-        let parsed = Function::from_sig(&sig, None, &ExternrefAttrs::default());
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert!(parsed.host_handle_args.is_empty());
+        assert!(parsed.returns_host_handle);
 
-        let (wrapper, ident) = parsed.wrap_import(&Visibility::Inherited, sig);
-        assert_eq!(ident, "__externref_send_message");
+        let declaration = parsed.declare(None);
+        let declaration: syn::Item = syn::parse_quote!(#declaration);
+        let expected: syn::Item = syn::parse_quote! {
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Export,
+                name: "test_export",
+                externrefs: externref::BitSlice::builder::<1usize>(2usize)
+                    .with_set_bit(0usize)
+                    .build(),
+                host_handles: externref::BitSlice::builder::<1usize>(2usize)
+                    .with_set_bit(1usize)
+                    .build(),
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(declaration, expected, "{}", quote!(#declaration));
+    }
 
-        let wrapper: ItemFn = syn::parse_quote!(#wrapper);
-        let expected: ItemFn = syn::parse_quote! {
-            #[inline(never)]
-            unsafe fn send_message(
-                __arg0: &Resource<Sender>,
-                __arg1: *const u8,
-                __arg2: usize,
-            ) -> Resource<Bytes> {
-                unsafe { externref::ExternRef::guard(); }
-                let __output = __externref_send_message(
-                    externref::Resource::raw(core::option::Option::Some(__arg0)),
-                    __arg1,
-                    __arg2,
-                );
-                externref::Resource::new_non_null(__output)
+    #[test]
+    fn transforming_export_with_unwind_abi() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C-unwind" fn test_export(sender: Resource<Sender>) {
+                // does nothing
             }
         };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C-unwind" fn __externref_export(__arg0: externref::ExternRef) {
+                    test_export(externref::Resource::new_non_null(
+                        __arg0,
+                        externref::resource_kind!("Sender")
+                    ),);
+                }
+            };
+        };
         assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
     }
 
     #[test]
-    fn foreign_mod_transformation() {
+    fn transforming_export_for_wasip2() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            #[unsafe(export_name = "test_export")]
+            pub fn test(sender: Resource<Sender>) {
+                // does nothing
+            }
+        };
+        let attrs = ExternrefAttrs {
+            target_abi: TargetAbi::Wasip2,
+            ..ExternrefAttrs::default()
+        };
+        let output = for_export(&mut export_fn, &attrs);
+        let output: syn::File = syn::parse_quote!(#output);
+        let expected: syn::File = syn::parse_quote! {
+            pub fn test(sender: Resource<Sender>) {
+                // does nothing
+            }
+            const _: () = {
+                #[unsafe(export_name = "test_export")]
+                unsafe extern "C" fn __externref_export(__arg0: externref::ExternRef) {
+                    test(externref::Resource::new_non_null(
+                        __arg0,
+                        externref::resource_kind!("Sender")
+                    ),);
+                }
+            };
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Export,
+                name: "test_export",
+                externrefs: externref::BitSlice::builder::<1usize>(1usize)
+                    .with_set_bit(0usize)
+                    .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(output, expected, "{}", quote!(#output));
+    }
+
+    #[test]
+    fn stripping_unsafe_export_attrs() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            #[unsafe(no_mangle)]
+            pub extern "C" fn test_export(sender: Resource<Sender>) {}
+        };
+        let output = for_export(&mut export_fn, &ExternrefAttrs::default());
+        assert!(export_fn.attrs.is_empty(), "{}", quote!(#export_fn));
+        assert!(export_fn.sig.abi.is_none());
+        let output = output.to_string();
+        assert!(
+            output.contains("# [export_name = \"test_export\"]"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn omitting_abi_without_wasip2_target() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub fn test_export(sender: Resource<Sender>) {}
+        };
+        let err = Function::new(&export_fn, &ExternrefAttrs::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            err.starts_with("[E001] exported function must be marked"),
+            "{err}"
+        );
+
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            #[no_mangle]
+            pub fn test_export(len: usize) {}
+        };
+        let attrs = ExternrefAttrs {
+            target_abi: TargetAbi::Wasip2,
+            ..ExternrefAttrs::default()
+        };
+        let output = for_export(&mut export_fn, &attrs).to_string();
+        assert!(output.contains("compile_error"), "{output}");
+        assert!(output.contains("without resources"), "{output}");
+    }
+
+    #[test]
+    fn rejecting_nested_resources() {
+        let valid_types: [Type; 4] = [
+            syn::parse_quote!(*const Resource<Sender>),
+            syn::parse_quote!(HostHandle<Sender>),
+            syn::parse_quote!(Vec<u8>),
+            syn::parse_quote!(Resource<A, B, C>),
+        ];
+        for ty in &valid_types {
+            check_not_resource(ty, ResourceIdents::default()).unwrap();
+        }
+
+        let invalid_types: [Type; 4] = [
+            syn::parse_quote!(&Option<Resource<Sender>>),
+            syn::parse_quote!(Box<Resource<Sender>>),
+            syn::parse_quote!([&Resource<Sender>; 2]),
+            syn::parse_quote!((u32, Resource<Sender>)),
+        ];
+        for ty in &invalid_types {
+            let err = check_not_resource(ty, ResourceIdents::default())
+                .unwrap_err()
+                .to_string();
+            assert!(err.starts_with("[E007] Unsupported resource type"), "{err}");
+        }
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(senders: &[Resource<Sender>]) {}
+        };
+        let err = Function::new(&export_fn, &ExternrefAttrs::default()).err();
+        assert!(err.is_some());
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export() -> Result<u32, Resource<Error>> {}
+        };
+        let err = Function::new(&export_fn, &ExternrefAttrs::default()).err();
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn restricting_abi() {
+        let attrs = ExternrefAttrs {
+            abi: Some("C-unwind".to_owned()),
+            ..ExternrefAttrs::default()
+        };
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(sender: Resource<Sender>) {}
+        };
+        let err = Function::new(&export_fn, &attrs).err().unwrap().to_string();
+        assert_eq!(
+            err.lines().next().unwrap(),
+            "[E001] Unexpected ABI C for exported function; expected `C-unwind`"
+        );
+        assert!(err.contains(ErrorCode::DOCS_URL), "{err}");
+
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C-unwind" {
+                fn send_message(sender: &Resource<Sender>);
+            }
+        };
+        Imports::new(&mut foreign_mod, &attrs).unwrap();
+    }
+
+    #[test]
+    fn transforming_export() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(
+                sender: &mut Resource<Sender>,
+                buffer: Option<Resource<Buffer>>,
+                some_ptr: *const u8,
+            ) {
+                // does nothing
+            }
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(parsed.resource_args.len(), 2);
+        assert_eq!(parsed.resource_args[&0], SimpleResourceKind::MutRef.into());
+        assert_eq!(
+            parsed.resource_args[&1],
+            ResourceKind::Option(SimpleResourceKind::Owned)
+        );
+        assert_eq!(parsed.return_type, ReturnType::Default);
+
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C" fn __externref_export(
+                    __arg0: externref::ExternRef,
+                    __arg1: externref::ExternRef,
+                    __arg2: *const u8,
+                ) {
+                    test_export(
+                        &mut externref::Resource::new_non_null(
+                            __arg0,
+                            externref::resource_kind!("Sender")
+                        ),
+                        externref::Resource::new(__arg1, externref::resource_kind!("Buffer")),
+                        __arg2,
+                    );
+                }
+            };
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn transforming_export_with_nullable_args() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(
+                #[resource(nullable)] sender: &Resource<Sender>,
+                #[resource(nullable)] buffer: Resource<Buffer>,
+            ) -> Resource<Bytes> {
+                // does nothing
+            }
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(
+            parsed.resource_args[&0],
+            ResourceKind::Nullable(SimpleResourceKind::Ref)
+        );
+        assert_eq!(
+            parsed.resource_args[&1],
+            ResourceKind::Nullable(SimpleResourceKind::Owned)
+        );
+
+        ArgAttrs::strip(&mut export_fn.sig);
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C" fn __externref_export(
+                    __arg0: externref::ExternRef,
+                    __arg1: externref::ExternRef,
+                ) -> externref::ExternRef {
+                    let __output = test_export(
+                        &externref::Resource::new_nullable(
+                            __arg0,
+                            externref::resource_kind!("Sender")
+                        ),
+                        externref::Resource::new_nullable(
+                            __arg1,
+                            externref::resource_kind!("Buffer")
+                        ),
+                    );
+                    externref::Resource::take_raw(::core::option::Option::Some(__output))
+                }
+            };
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(#[resource(nullable)] buffer: Option<Resource<Buffer>>) {}
+        };
+        let err = Function::new(&export_fn, &ExternrefAttrs::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("only supported for `Resource<_>`"), "{err}");
+    }
+
+    #[test]
+    fn transforming_export_with_aliased_args() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(
+                #[resource] sender: &Sender,
+                #[resource] buffer: Option<crate::Buffer>,
+                #[resource] #[resource(nullable)] bytes: Bytes<u8>,
+                len: usize,
+            ) {
+                // does nothing
+            }
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(parsed.resource_args[&0], SimpleResourceKind::Ref.into());
+        assert_eq!(
+            parsed.resource_args[&1],
+            ResourceKind::Option(SimpleResourceKind::Owned)
+        );
+        assert_eq!(
+            parsed.resource_args[&2],
+            ResourceKind::Nullable(SimpleResourceKind::Owned)
+        );
+        assert_eq!(parsed.resource_args.len(), 3);
+
+        ArgAttrs::strip(&mut export_fn.sig);
+        assert!(export_fn.sig.inputs.iter().all(|arg| match arg {
+            FnArg::Typed(arg) => arg.attrs.is_empty(),
+            FnArg::Receiver(_) => true,
+        }));
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C" fn __externref_export(
+                    __arg0: externref::ExternRef,
+                    __arg1: externref::ExternRef,
+                    __arg2: externref::ExternRef,
+                    __arg3: usize,
+                ) {
+                    test_export(
+                        &externref::Resource::new_non_null(
+                            __arg0,
+                            externref::resource_kind!("Sender")
+                        ),
+                        externref::Resource::new(__arg1, externref::resource_kind!("Buffer")),
+                        externref::Resource::new_nullable(
+                            __arg2,
+                            externref::resource_kind!("Bytes<u8>")
+                        ),
+                        __arg3,
+                    );
+                }
+            };
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(#[resource] buffers: &[Buffer]) {}
+        };
+        let err = Function::new(&export_fn, &ExternrefAttrs::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            err.starts_with("[E006] `#[resource]` is only supported"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn transforming_import_with_generic_markers() {
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                fn send_message(
+                    sender: &::externref::Resource<Wrapper<Sender, u8>>,
+                    #[resource] buffer: Buffer,
+                ) -> Option<externref::Resource<Wrapper<Vec<Bytes>>>>;
+            }
+        };
+        let attrs = ExternrefAttrs {
+            type_names: true,
+            ..ExternrefAttrs::default()
+        };
+        let imports = Imports::new(&mut foreign_mod, &attrs).unwrap();
+        let (function, _) = &imports.functions[0];
+        assert_eq!(function.resource_args[&0], SimpleResourceKind::Ref.into());
+        assert_eq!(function.resource_args[&1], SimpleResourceKind::Owned.into());
+        assert_eq!(
+            function.return_type,
+            ReturnType::Resource(ResourceKind::Option(SimpleResourceKind::Owned))
+        );
+        assert_eq!(
+            function.type_names.as_deref().unwrap(),
+            ["Wrapper<Sender,u8>", "Buffer", "Wrapper<Vec<Bytes>>"]
+        );
+
+        let expected: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                #[link_name = "send_message"]
+                fn __externref_send_message(
+                    sender: externref::ExternRef,
+                    buffer: externref::ExternRef,
+                ) -> externref::ExternRef;
+            }
+        };
+        assert_eq!(foreign_mod, expected, "{}", quote!(#foreign_mod));
+    }
+
+    #[test]
+    fn routing_export_args_to_tables() {
+        let attrs = ExternrefAttrs {
+            table: Some("audio".to_owned()),
+            ..ExternrefAttrs::default()
+        };
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(
+                sender: &Resource<Sender>,
+                #[resource(table = "video")] buffer: Option<Resource<Buffer>>,
+            ) {}
+        };
+        let parsed = Function::new(&export_fn, &attrs).unwrap();
+        assert_eq!(parsed.table.as_deref(), Some("audio"));
+        assert_eq!(parsed.arg_tables[&1], "video");
+
+        ArgAttrs::strip(&mut export_fn.sig);
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C" fn __externref_export(
+                    __arg0: externref::ExternRef,
+                    __arg1: externref::ExternRef,
+                ) {
+                    test_export(
+                        &externref::Resource::new_non_null(
+                            __arg0,
+                            externref::resource_kind!("audio")
+                        ),
+                        externref::Resource::new(__arg1, externref::resource_kind!("video")),
+                    );
+                }
+            };
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(#[resource(table = "audio")] len: usize) {}
+        };
+        let err = Function::new(&export_fn, &ExternrefAttrs::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("only supported for resource args"), "{err}");
+    }
+
+    #[test]
+    fn routing_import_results_to_tables() {
+        let attrs = ExternrefAttrs {
+            table: Some("audio".to_owned()),
+            ..ExternrefAttrs::default()
+        };
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                fn new_sender() -> Resource<Sender>;
+                #[externref(table = "video")]
+                fn new_buffer() -> Resource<Buffer>;
+            }
+        };
+        let imports = Imports::new(&mut foreign_mod, &attrs).unwrap();
+        let tables: Vec<_> = imports
+            .functions
+            .iter()
+            .map(|(function, _)| function.table.as_deref())
+            .collect();
+        assert_eq!(tables, [Some("audio"), Some("video")]);
+
+        let (_, wrapper) = &imports.functions[1];
+        let wrapper = wrapper.to_string();
+        let expected_kind = quote!(externref::resource_kind!("video")).to_string();
+        assert!(wrapper.contains(&expected_kind), "{wrapper}");
+    }
+
+    #[test]
+    fn wrapper_for_import() {
+        let sig: Signature = syn::parse_quote! {
+            fn send_message(
+                sender: &Resource<Sender>,
+                message_ptr: *const u8,
+                message_len: usize,
+            ) -> Resource<Bytes>
+        };
+        let parsed = Function::from_sig(&sig, None, &ExternrefAttrs::default()).unwrap();
+
+        let (wrapper, ident) = parsed.wrap_import(&[], &Visibility::Inherited, sig, false);
+        assert_eq!(ident, "__externref_send_message");
+
+        let wrapper: ItemFn = syn::parse_quote!(#wrapper);
+        let expected: ItemFn = syn::parse_quote! {
+            #[inline(never)]
+            unsafe fn send_message(
+                __arg0: &Resource<Sender>,
+                __arg1: *const u8,
+                __arg2: usize,
+            ) -> Resource<Bytes> {
+                unsafe { externref::ExternRef::guard(); }
+                let __output = __externref_send_message(
+                    externref::Resource::raw(::core::option::Option::Some(__arg0)),
+                    __arg1,
+                    __arg2,
+                );
+                externref::Resource::new_non_null(__output, externref::resource_kind!("Bytes"))
+            }
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn wrapper_for_import_with_owned_resource() {
+        let sig: Signature = syn::parse_quote! {
+            fn consume(bytes: Resource<Bytes>, len: usize)
+        };
+        let parsed = Function::from_sig(&sig, None, &ExternrefAttrs::default()).unwrap();
+
+        let (wrapper, _) = parsed.wrap_import(&[], &Visibility::Inherited, sig, false);
+        let wrapper: ItemFn = syn::parse_quote!(#wrapper);
+        let expected: ItemFn = syn::parse_quote! {
+            unsafe fn consume(__arg0: Resource<Bytes>, __arg1: usize) {
+                #[inline(never)]
+                unsafe fn __externref_wrapper(__arg0: &Resource<Bytes>, __arg1: usize) {
+                    unsafe { externref::ExternRef::guard(); }
+                    __externref_consume(
+                        externref::Resource::raw(::core::option::Option::Some(__arg0)),
+                        __arg1,
+                    );
+                }
+                unsafe { __externref_wrapper(&__arg0, __arg1,) }
+            }
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn wrapper_for_import_with_surrogate_module() {
+        let attrs = ExternrefAttrs {
+            surrogate_module: Some("externref_shim".to_owned()),
+            ..ExternrefAttrs::default()
+        };
+        let sig: Signature = syn::parse_quote! {
+            fn inspect(sender: &Resource<Sender>)
+        };
+        let parsed = Function::from_sig(&sig, None, &attrs).unwrap();
+
+        let (wrapper, _) = parsed.wrap_import(&[], &Visibility::Inherited, sig, false);
+        let wrapper: ItemFn = syn::parse_quote!(#wrapper);
+        let expected: ItemFn = syn::parse_quote! {
+            #[inline(never)]
+            unsafe fn inspect(__arg0: &Resource<Sender>) {
+                unsafe {
+                    #[cfg(target_arch = "wasm32")]
+                    #[link(wasm_import_module = "externref_shim")]
+                    extern "C" {
+                        #[link_name = "guard"]
+                        fn __externref_guard();
+                    }
+
+                    #[cfg(target_arch = "wasm32")]
+                    __externref_guard();
+                }
+                __externref_inspect(
+                    externref::Resource::raw(::core::option::Option::Some(__arg0)),
+                );
+            }
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+
+        let declaration = parsed.declare(Some("test"));
+        let declaration = quote!(#declaration).to_string();
+        let expected_module =
+            quote!(surrogate_module: ::core::option::Option::Some("externref_shim")).to_string();
+        assert!(declaration.contains(&expected_module), "{declaration}");
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(sender: Resource<Sender>) {}
+        };
+        let err = Function::new(&export_fn, &attrs).err().unwrap().to_string();
+        assert!(
+            err.starts_with("[E005] `surrogate_module` attribute"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn declaring_function_in_custom_section() {
+        let attrs = ExternrefAttrs {
+            section: Some("__externrefs_sdk".to_owned()),
+            ..ExternrefAttrs::default()
+        };
+        let sig: Signature = syn::parse_quote! {
+            fn inspect(sender: &Resource<Sender>)
+        };
+        let parsed = Function::from_sig(&sig, None, &attrs).unwrap();
+        let declaration = parsed.declare(Some("test"));
+        let declaration: syn::Stmt = syn::parse_quote!(#declaration);
+        let expected: syn::Stmt = syn::parse_quote! {
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Import("test"),
+                name: "inspect",
+                externrefs: externref::BitSlice::builder::<1usize>(1usize)
+                    .with_set_bit(0usize)
+                    .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            }, section = "__externrefs_sdk");
+        };
+        assert_eq!(declaration, expected, "{}", quote!(#declaration));
+    }
+
+    #[test]
+    fn foreign_mod_transformation() {
         let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
             #[link(wasm_import_module = "test")]
             extern "C" {
@@ -657,4 +2607,485 @@ mod tests {
         };
         assert_eq!(foreign_mod, expected, "{}", quote!(#foreign_mod));
     }
+
+    #[test]
+    fn transforming_safe_fns_in_unsafe_extern_block() {
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            unsafe extern "C" {
+                pub safe fn send_message(sender: &Resource<Sender>) -> Resource<Bytes>;
+                pub fn inspect(sender: &Resource<Sender>);
+                safe fn answer() -> u32;
+            }
+        };
+        let imports = Imports::new(&mut foreign_mod, &ExternrefAttrs::default()).unwrap();
+
+        let expected: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            unsafe extern "C" {
+                #[link_name = "send_message"]
+                safe fn __externref_send_message(sender: externref::ExternRef) -> externref::ExternRef;
+                #[link_name = "inspect"]
+                fn __externref_inspect(sender: externref::ExternRef);
+                safe fn answer() -> u32;
+            }
+        };
+        assert_eq!(foreign_mod, expected, "{}", quote!(#foreign_mod));
+
+        let wrappers: Vec<ItemFn> = imports
+            .functions
+            .iter()
+            .map(|(_, wrapper)| syn::parse_quote!(#wrapper))
+            .collect();
+        let expected: ItemFn = syn::parse_quote! {
+            #[inline(never)]
+            pub fn send_message(__arg0: &Resource<Sender>) -> Resource<Bytes> {
+                unsafe { externref::ExternRef::guard(); }
+                let __output = __externref_send_message(
+                    unsafe { externref::Resource::raw(::core::option::Option::Some(__arg0)) },
+                );
+                unsafe {
+                    externref::Resource::new_non_null(__output, externref::resource_kind!("Bytes"))
+                }
+            }
+        };
+        assert_eq!(wrappers[0], expected, "{}", quote!(#(#wrappers)*));
+        assert!(wrappers[1].sig.unsafety.is_some());
+    }
+
+    #[test]
+    fn forwarding_attrs_for_imports() {
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                /// Sends a message.
+                #[cfg(target_arch = "wasm32")]
+                #[cfg_attr(feature = "extra", link_name = "send", doc = "More docs.")]
+                #[cfg_attr(test, cfg(any()))]
+                pub fn send_message(sender: &Resource<Sender>);
+            }
+        };
+        let imports = Imports::new(&mut foreign_mod, &ExternrefAttrs::default()).unwrap();
+
+        let expected: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                #[cfg(target_arch = "wasm32")]
+                #[cfg_attr(feature = "extra", link_name = "send", doc = "More docs.")]
+                #[cfg_attr(test, cfg(any()))]
+                #[link_name = "send_message"]
+                fn __externref_send_message(sender: externref::ExternRef);
+            }
+        };
+        assert_eq!(foreign_mod, expected, "{}", quote!(#foreign_mod));
+
+        let (function, wrapper) = &imports.functions[0];
+        let wrapper: ItemFn = syn::parse_quote!(#wrapper);
+        let expected_attrs: Vec<Attribute> = syn::parse_quote! {
+            /// Sends a message.
+            #[cfg(target_arch = "wasm32")]
+            #[cfg_attr(feature = "extra", doc = "More docs.")]
+            #[cfg_attr(test, cfg(any()))]
+            #[inline(never)]
+        };
+        assert_eq!(wrapper.attrs, expected_attrs, "{}", quote!(#wrapper));
+
+        let expected_gates: Vec<Attribute> = syn::parse_quote! {
+            #[cfg(target_arch = "wasm32")]
+            #[cfg_attr(test, cfg(any()))]
+        };
+        assert_eq!(function.cfg_gates, expected_gates);
+        let declarations = imports.declarations().into_token_stream().to_string();
+        assert!(
+            declarations.starts_with(
+                "# [cfg (target_arch = \"wasm32\")] # [cfg_attr (test , cfg (any ()))] \
+                 externref :: declare_function !"
+            ),
+            "{declarations}"
+        );
+    }
+
+    #[test]
+    fn gating_export_wrapper() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            /// Test export.
+            #[cfg(feature = "exports")]
+            #[cfg_attr(test, allow(dead_code))]
+            pub extern "C" fn test_export(sender: Resource<Sender>) {}
+        };
+        let output = for_export(&mut export_fn, &ExternrefAttrs::default()).to_string();
+        let gate = "# [cfg (feature = \"exports\")]";
+        // The function itself, the wrapper and the declaration must be gated.
+        assert_eq!(output.matches(gate).count(), 3, "{output}");
+        assert!(output.contains(&format!("{gate} const _ : ()")), "{output}");
+        assert!(
+            output.contains(&format!("{gate} externref :: declare_function !")),
+            "{output}"
+        );
+        assert_eq!(output.matches("allow (dead_code)").count(), 1, "{output}");
+    }
+
+    #[test]
+    fn foreign_mod_with_computed_module_name() {
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = concat!("ns:", env!("CARGO_PKG_NAME"), '/', 1))]
+            extern "C" {
+                fn message_len(bytes: &Resource<Bytes>) -> usize;
+            }
+        };
+        let imports = Imports::new(&mut foreign_mod, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(imports.module_name, "ns:externref-macro/1");
+
+        let expected: Attribute = syn::parse_quote! {
+            #[link(wasm_import_module = "ns:externref-macro/1")]
+        };
+        assert_eq!(foreign_mod.attrs, [expected]);
+    }
+
+    #[test]
+    fn unresolvable_module_names() {
+        let names: [Expr; 3] = [
+            syn::parse_quote!(MODULE_NAME),
+            syn::parse_quote!(concat!("ns:", some_macro!())),
+            syn::parse_quote!(env!("__EXTERNREF_UNDEFINED_VAR")),
+        ];
+        for name in &names {
+            let err = resolve_str_expr(name).unwrap_err().to_string();
+            assert!(
+                err.contains("module name format") || err.contains("is not defined"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn skipping_functions_in_foreign_mod() {
+        let mut foreign_mod: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                #[externref(skip)]
+                fn send_raw_message(sender: Resource<Sender>) -> Resource<Bytes>;
+                fn message_len(bytes: &Resource<Bytes>) -> usize;
+            }
+        };
+        let imports = Imports::new(&mut foreign_mod, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(imports.functions.len(), 1);
+
+        let expected: ItemForeignMod = syn::parse_quote! {
+            #[link(wasm_import_module = "test")]
+            extern "C" {
+                fn send_raw_message(sender: Resource<Sender>) -> Resource<Bytes>;
+                #[link_name = "message_len"]
+                fn __externref_message_len(bytes: externref::ExternRef) -> usize;
+            }
+        };
+        assert_eq!(foreign_mod, expected, "{}", quote!(#foreign_mod));
+    }
+
+    #[test]
+    fn resource_kind_names() {
+        let idents = ResourceIdents::default();
+        let types: [(Type, &str); 11] = [
+            (syn::parse_quote!(Resource<Sender>), "Sender"),
+            (syn::parse_quote!(&mut Resource<crate::Sender>), "Sender"),
+            (syn::parse_quote!(Option<&Resource<()>>), "()"),
+            (
+                syn::parse_quote!(Option<externref::Resource<Vec<u8>>>),
+                "Vec<u8>",
+            ),
+            (syn::parse_quote!(Resource<[u8; 4]>), "[u8;4]"),
+            (
+                syn::parse_quote!(::externref::Resource<Wrapper<Option<T>, u8>>),
+                "Wrapper<Option<T>,u8>",
+            ),
+            (
+                syn::parse_quote!(&Resource<crate::Wrapper<Resource<T>>>),
+                "Wrapper<Resource<T>>",
+            ),
+            (syn::parse_quote!(Resource<<T as Trait>::Marker>), "Marker"),
+            (syn::parse_quote!(Resource<T, U>), "T"),
+            (syn::parse_quote!(&Resource<crate::File, Open>), "File"),
+            (
+                syn::parse_quote!(Option<Resource<Wrapper<u8>, state::Closed>>),
+                "Wrapper<u8>",
+            ),
+        ];
+        for (ty, expected) in types {
+            assert!(
+                ResourceKind::from_type(&ty, idents).is_some(),
+                "{}",
+                quote!(#ty)
+            );
+            assert_eq!(ResourceKind::kind_name(&ty, idents), expected);
+        }
+
+        let non_resources: [Type; 4] = [
+            syn::parse_quote!(Resource<T, U, V>),
+            syn::parse_quote!(Resource<T, 4>),
+            syn::parse_quote!(Resource<4>),
+            syn::parse_quote!(<T as Trait>::Resource<U>),
+        ];
+        for ty in &non_resources {
+            assert!(
+                ResourceKind::from_type(ty, idents).is_none(),
+                "{}",
+                quote!(#ty)
+            );
+        }
+
+        let aliases: [(Type, &str); 3] = [
+            (syn::parse_quote!(Sender), "Sender"),
+            (syn::parse_quote!(&mut crate::Sender), "Sender"),
+            (syn::parse_quote!(Option<Bytes<u8>>), "Bytes<u8>"),
+        ];
+        for (ty, expected) in aliases {
+            assert!(ResourceKind::from_alias(&ty).is_some(), "{}", quote!(#ty));
+            assert_eq!(ResourceKind::kind_name(&ty, idents), expected);
+        }
+    }
+
+    #[test]
+    fn checking_std_paths() {
+        let sig: Signature = syn::parse_quote! {
+            fn test(buffer: *const core::primitive::u8, len: usize, res: &Resource<Std>)
+        };
+        check_no_std(sig.to_token_stream()).unwrap();
+
+        let sig: Signature = syn::parse_quote! {
+            fn test(buffer: Option<&Resource<std::string::String>>)
+        };
+        let err = check_no_std(sig.to_token_stream()).unwrap_err();
+        assert!(err.to_string().contains("`std` paths"), "{err}");
+    }
+
+    #[test]
+    fn checking_consumed_import_args() {
+        let sig: Signature = syn::parse_quote! {
+            fn send(#[resource(consumed)] buffer: Resource<Buffer>, sender: &Resource<Sender>)
+        };
+        let function = Function::from_sig(&sig, None, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(function.consumed_args, [0]);
+        function.check_consumed_args(&sig).unwrap();
+
+        let sig: Signature = syn::parse_quote! {
+            fn send(buffer: Option<Resource<Buffer>>)
+        };
+        let function = Function::from_sig(&sig, None, &ExternrefAttrs::default()).unwrap();
+        let err = function.check_consumed_args(&sig).unwrap_err().to_string();
+        assert!(err.starts_with("[E010]"), "{err}");
+        assert!(err.contains("#[resource(consumed)]"), "{err}");
+
+        let sig: Signature = syn::parse_quote! {
+            fn close(file: Resource<File, Open>) -> Option<Resource<File, Closed>>
+        };
+        let function = Function::from_sig(&sig, None, &ExternrefAttrs::default()).unwrap();
+        function.check_consumed_args(&sig).unwrap();
+
+        let sig: Signature = syn::parse_quote! {
+            fn reopen(file: Resource<File, Open>) -> Resource<File, Open>
+        };
+        let function = Function::from_sig(&sig, None, &ExternrefAttrs::default()).unwrap();
+        let err = function.check_consumed_args(&sig).unwrap_err().to_string();
+        assert!(err.starts_with("[E010]"), "{err}");
+
+        let sig: Signature = syn::parse_quote! {
+            fn send(#[resource(consumed)] sender: &Resource<Sender>)
+        };
+        let Err(err) = Function::from_sig(&sig, None, &ExternrefAttrs::default()) else {
+            panic!("unexpected success");
+        };
+        assert!(err.to_string().starts_with("[E006]"), "{err}");
+    }
+
+    #[test]
+    fn checking_unused_export_resources() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test(sender: Resource<Sender>, _buffer: Resource<Buffer>) {
+                if let Some(id) = sender.id() {
+                    log(id);
+                }
+            }
+        };
+        let function = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        function.check_unused_resources(&export_fn).unwrap();
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test(sender: Resource<Sender>, bytes: &Resource<Bytes>) {
+                log(bytes);
+            }
+        };
+        let function = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        let err = function.check_unused_resources(&export_fn).unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("[E010]"), "{err}");
+        assert!(err.contains("resource `sender` is never used"), "{err}");
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test(sender: Resource<Sender>, bytes: Resource<Bytes>) {
+                log(format!("{sender:?}, {{bytes}}"));
+            }
+        };
+        let function = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        let err = function.check_unused_resources(&export_fn).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("resource `bytes` is never used"), "{err}");
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test(#[resource(consumed)] sender: Resource<Sender>) {}
+        };
+        let Err(err) = Function::new(&export_fn, &ExternrefAttrs::default()) else {
+            panic!("unexpected success");
+        };
+        assert!(err.to_string().starts_with("[E006]"), "{err}");
+    }
+
+    #[test]
+    fn transforming_fallible_export() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            pub extern "C" fn test_export(sender: &Resource<Sender>) -> Result<Resource<Bytes>, i32> {
+                // does nothing
+            }
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(
+            parsed.return_type,
+            ReturnType::Fallible(SimpleResourceKind::Owned.into())
+        );
+
+        let declaration = parsed.declare(None);
+        let declaration: syn::Item = syn::parse_quote!(#declaration);
+        let expected: syn::Item = syn::parse_quote! {
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Export,
+                name: "test_export",
+                externrefs: externref::BitSlice::builder::<1usize>(3usize)
+                    .with_set_bit(0usize)
+                    .with_set_bit(1usize)
+                    .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(declaration, expected, "{}", quote!(#declaration));
+
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C" fn __externref_export(__arg0: externref::ExternRef)
+                    -> externref::ExternRef
+                {
+                    let __output = match test_export(
+                        &externref::Resource::new_non_null(
+                            __arg0,
+                            externref::resource_kind!("Sender")
+                        ),
+                    ) {
+                        ::core::result::Result::Ok(__output) => {
+                            externref::ExternRef::set_error_code(0);
+                            ::core::option::Option::Some(__output)
+                        }
+                        ::core::result::Result::Err(__err) => {
+                            let __code: i32 = ::core::convert::Into::into(__err);
+                            externref::ExternRef::set_error_code(__code);
+                            ::core::option::Option::None
+                        }
+                    };
+                    externref::Resource::take_raw(__output)
+                }
+            };
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn transforming_flagged_export() {
+        let mut export_fn: ItemFn = syn::parse_quote! {
+            #[resource(nullable = "flag")]
+            pub extern "C" fn test_export(sender: &Resource<Sender>) -> Option<Resource<Bytes>> {
+                // does nothing
+            }
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(
+            parsed.return_type,
+            ReturnType::Flagged(SimpleResourceKind::Owned)
+        );
+
+        let declaration = parsed.declare(None);
+        let declaration: syn::Item = syn::parse_quote!(#declaration);
+        let expected: syn::Item = syn::parse_quote! {
+            externref::declare_function!(externref::Function {
+                kind: externref::FunctionKind::Export,
+                name: "test_export",
+                externrefs: externref::BitSlice::builder::<1usize>(3usize)
+                    .with_set_bit(0usize)
+                    .with_set_bit(1usize)
+                    .build(),
+                host_handles: externref::BitSlice::EMPTY,
+                type_names: externref::TypeNames::EMPTY,
+                surrogate_module: ::core::option::Option::None,
+            });
+        };
+        assert_eq!(declaration, expected, "{}", quote!(#declaration));
+
+        ReturnAttrs::strip(&mut export_fn.attrs);
+        assert!(export_fn.attrs.is_empty());
+        let wrapper = parsed.wrap_export(&export_fn);
+        let wrapper: syn::Item = syn::parse_quote!(#wrapper);
+        let expected: syn::Item = syn::parse_quote! {
+            const _: () = {
+                #[export_name = "test_export"]
+                unsafe extern "C" fn __externref_export(__arg0: externref::ExternRef)
+                    -> externref::ExternRef
+                {
+                    let __output = test_export(
+                        &externref::Resource::new_non_null(
+                            __arg0,
+                            externref::resource_kind!("Sender")
+                        ),
+                    );
+                    let __flag = ::core::option::Option::is_some(&__output);
+                    externref::ExternRef::set_error_code(::core::primitive::i32::from(__flag));
+                    externref::Resource::take_raw(__output)
+                }
+            };
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn flagged_return_errors() {
+        let export_fn: ItemFn = syn::parse_quote! {
+            #[resource(nullable = "flag")]
+            pub extern "C" fn test_export(sender: &Resource<Sender>) -> Resource<Bytes> {}
+        };
+        let Err(err) = Function::new(&export_fn, &ExternrefAttrs::default()) else {
+            panic!("unexpected success");
+        };
+        assert!(err.to_string().starts_with("[E006]"), "{err}");
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            #[resource(nullable = "bogus")]
+            pub extern "C" fn test_export(sender: &Resource<Sender>) -> Option<Resource<Bytes>> {}
+        };
+        let Err(err) = Function::new(&export_fn, &ExternrefAttrs::default()) else {
+            panic!("unexpected success");
+        };
+        let err = err.to_string();
+        assert!(err.starts_with("[E005]"), "{err}");
+        assert!(err.contains("unsupported lowering"), "{err}");
+
+        let export_fn: ItemFn = syn::parse_quote! {
+            #[resource(nullable = "null")]
+            pub extern "C" fn test_export(sender: &Resource<Sender>) -> Option<Resource<Bytes>> {}
+        };
+        let parsed = Function::new(&export_fn, &ExternrefAttrs::default()).unwrap();
+        assert_eq!(
+            parsed.return_type,
+            ReturnType::Resource(ResourceKind::Option(SimpleResourceKind::Owned))
+        );
+    }
 }
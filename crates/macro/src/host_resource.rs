@@ -0,0 +1,121 @@
+//! `HostResource` derive macro.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse::Error as SynError, DeriveInput, LitStr, Path};
+
+#[derive(Default)]
+struct HostResourceAttrs {
+    kind: Option<String>,
+    on_drop: Option<Path>,
+    crate_path: Option<Path>,
+}
+
+impl HostResourceAttrs {
+    const NAME: &'static str = "host_resource";
+
+    fn parse(input: &DeriveInput) -> Result<Self, SynError> {
+        let mut attrs = Self::default();
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident(Self::NAME))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("kind") {
+                    let kind: LitStr = meta.value()?.parse()?;
+                    attrs.kind = Some(kind.value());
+                    Ok(())
+                } else if meta.path.is_ident("on_drop") {
+                    let path: LitStr = meta.value()?.parse()?;
+                    attrs.on_drop = Some(path.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("crate") {
+                    let path: LitStr = meta.value()?.parse()?;
+                    attrs.crate_path = Some(path.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported attribute"))
+                }
+            })?;
+        }
+        Ok(attrs)
+    }
+}
+
+fn derive(input: &DeriveInput) -> Result<TokenStream, SynError> {
+    let attrs = HostResourceAttrs::parse(input)?;
+    let cr = attrs
+        .crate_path
+        .unwrap_or_else(|| syn::parse_quote!(externref));
+    let kind = attrs.kind.unwrap_or_else(|| input.ident.to_string());
+    let on_drop = attrs.on_drop.map(|path| {
+        quote! {
+            fn on_drop(&self) {
+                #path(self);
+            }
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #cr::host::HostResource for #name #ty_generics #where_clause {
+            const KIND: &'static str = #kind;
+            #on_drop
+        }
+    })
+}
+
+pub(crate) fn impl_host_resource(input: &DeriveInput) -> TokenStream {
+    derive(input).unwrap_or_else(SynError::into_compile_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deriving_host_resource() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[host_resource(kind = "Sender", on_drop = "close")]
+            struct HostSender<T: Clone> {
+                channel: T,
+            }
+        };
+        let derived = impl_host_resource(&input);
+        let derived: syn::ItemImpl = syn::parse_quote!(#derived);
+        let expected: syn::ItemImpl = syn::parse_quote! {
+            impl<T: Clone> externref::host::HostResource for HostSender<T> {
+                const KIND: &'static str = "Sender";
+                fn on_drop(&self) {
+                    close(self);
+                }
+            }
+        };
+        assert_eq!(derived, expected, "{}", quote!(#derived));
+    }
+
+    #[test]
+    fn deriving_host_resource_with_defaults() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[host_resource(crate = "ext")]
+            struct Bytes(Vec<u8>);
+        };
+        let derived = impl_host_resource(&input);
+        let derived: syn::ItemImpl = syn::parse_quote!(#derived);
+        let expected: syn::ItemImpl = syn::parse_quote! {
+            impl ext::host::HostResource for Bytes {
+                const KIND: &'static str = "Bytes";
+            }
+        };
+        assert_eq!(derived, expected, "{}", quote!(#derived));
+
+        let input: DeriveInput = syn::parse_quote! {
+            #[host_resource(bogus)]
+            struct Bytes(Vec<u8>);
+        };
+        let err = derive(&input).unwrap_err().to_string();
+        assert_eq!(err, "unsupported attribute");
+    }
+}
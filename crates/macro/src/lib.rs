@@ -1,9 +1,10 @@
-//! Procedural macro for [`externref`].
+//! Procedural macros for [`externref`].
 //!
-//! This macro wraps imported or exported functions with `Resource` args / return type
+//! The `externref` macro wraps imported or exported functions with `Resource` args / return type
 //! doing all heavy lifting to prepare these functions for usage with `externref`s.
 //! Note that it is necessary to post-process the module with the module processor provided
-//! by the `externref` crate.
+//! by the `externref` crate. The `HostResource` derive macro implements the host-side
+//! `externref::host::HostResource` trait.
 //!
 //! See `externref` docs for more details and examples of usage.
 //!
@@ -20,21 +21,74 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use syn::{
-    parse::{Error as SynError, Parser},
-    Item, Path,
-};
+use syn::{parse::Parser, spanned::Spanned, DeriveInput, Item, Path};
 
+mod error;
 mod externref;
+mod host_resource;
 
-use crate::externref::{for_export, for_foreign_module};
+use crate::{
+    error::ErrorCode,
+    externref::{for_export, for_foreign_module},
+};
 
 #[derive(Default)]
+#[allow(clippy::struct_excessive_bools)] // flags are independent options
 struct ExternrefAttrs {
     crate_path: Option<Path>,
+    no_std: bool,
+    type_names: bool,
+    abi: Option<String>,
+    table: Option<String>,
+    surrogate_module: Option<String>,
+    section: Option<String>,
+    start: bool,
+    indirect_export: bool,
+    /// Whether to raise errors for likely resource misuse; see the "Strict mode" docs section.
+    strict: bool,
+    resource_type: Option<String>,
+    target_abi: TargetAbi,
+}
+
+/// Target ABI of exported functions, which determines how the generated wrappers are exported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum TargetAbi {
+    /// Core WASM modules (e.g., `wasm32-unknown-unknown` or `wasm32-wasip1`). Wrappers
+    /// are exported via `#[export_name = ..]`.
+    #[default]
+    Core,
+    /// Components built for `wasm32-wasip2`. Wrappers are exported
+    /// via `#[unsafe(export_name = ..)]`, and exported functions may omit `extern "C"`.
+    Wasip2,
+}
+
+impl TargetAbi {
+    const NAMES: &'static [&'static str] = &["core", "wasip2"];
+
+    fn parse(name: &syn::LitStr) -> syn::Result<Self> {
+        match name.value().as_str() {
+            "core" => Ok(Self::Core),
+            "wasip2" => Ok(Self::Wasip2),
+            other => {
+                let msg = format!(
+                    "Unsupported target ABI {other}; expected {}",
+                    ExternrefAttrs::expected_abis(Self::NAMES)
+                );
+                Err(ErrorCode::UnsupportedAbi.error(name.span(), msg))
+            }
+        }
+    }
 }
 
 impl ExternrefAttrs {
+    /// ABIs compatible with the `C` ABI on WASM that are accepted by default.
+    const SUPPORTED_ABIS: &'static [&'static str] = &["C", "C-unwind"];
+    /// Default name of the custom section with function declarations; custom section names
+    /// must start with it. Must be synced with `Function::CUSTOM_SECTION_NAME`.
+    const SECTION_PREFIX: &'static str = "__externrefs";
+    /// Name of the section marking processed modules, which cannot be used for declarations.
+    const MARKER_SECTION_NAME: &'static str = "__externrefs_processed";
+
     fn parse(tokens: TokenStream) -> syn::Result<Self> {
         let mut attrs = Self::default();
         if tokens.is_empty() {
@@ -46,11 +100,80 @@ impl ExternrefAttrs {
                 let path_str: syn::LitStr = meta.value()?.parse()?;
                 attrs.crate_path = Some(path_str.parse()?);
                 Ok(())
+            } else if meta.path.is_ident("no_std") {
+                attrs.no_std = true;
+                Ok(())
+            } else if meta.path.is_ident("type_names") {
+                attrs.type_names = true;
+                Ok(())
+            } else if meta.path.is_ident("table") {
+                attrs.table = Some(parse_table_name(&meta)?);
+                Ok(())
+            } else if meta.path.is_ident("start") {
+                attrs.start = true;
+                Ok(())
+            } else if meta.path.is_ident("indirect_export") {
+                attrs.indirect_export = true;
+                Ok(())
+            } else if meta.path.is_ident("strict") {
+                attrs.strict = if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<syn::LitBool>()?.value
+                } else {
+                    true
+                };
+                Ok(())
+            } else if meta.path.is_ident("surrogate_module") {
+                let module: syn::LitStr = meta.value()?.parse()?;
+                if module.value().is_empty() {
+                    let msg = "surrogate module name cannot be empty";
+                    return Err(ErrorCode::BadAttr.error(module.span(), msg));
+                }
+                attrs.surrogate_module = Some(module.value());
+                Ok(())
+            } else if meta.path.is_ident("section") {
+                let section: syn::LitStr = meta.value()?.parse()?;
+                let name = section.value();
+                if !name.starts_with(Self::SECTION_PREFIX) || name == Self::MARKER_SECTION_NAME {
+                    let msg = format!(
+                        "custom section name must start with `{}`, e.g. `{}_sdk`",
+                        Self::SECTION_PREFIX,
+                        Self::SECTION_PREFIX
+                    );
+                    return Err(ErrorCode::BadAttr.error(section.span(), msg));
+                }
+                attrs.section = Some(name);
+                Ok(())
+            } else if meta.path.is_ident("resource_type") {
+                let name: syn::LitStr = meta.value()?.parse()?;
+                if name.parse::<syn::Ident>().is_err() {
+                    let msg = "resource type must be an identifier, e.g. `Handle`";
+                    return Err(ErrorCode::BadAttr.error(name.span(), msg));
+                }
+                attrs.resource_type = Some(name.value());
+                Ok(())
+            } else if meta.path.is_ident("abi") {
+                let abi: syn::LitStr = meta.value()?.parse()?;
+                if !Self::SUPPORTED_ABIS.contains(&abi.value().as_str()) {
+                    let msg = format!(
+                        "Unsupported ABI {}; expected {}",
+                        abi.value(),
+                        Self::expected_abis(Self::SUPPORTED_ABIS)
+                    );
+                    return Err(ErrorCode::UnsupportedAbi.error(abi.span(), msg));
+                }
+                attrs.abi = Some(abi.value());
+                Ok(())
+            } else if meta.path.is_ident("target_abi") {
+                let target_abi: syn::LitStr = meta.value()?.parse()?;
+                attrs.target_abi = TargetAbi::parse(&target_abi)?;
+                Ok(())
             } else {
-                Err(meta.error("unsupported attribute"))
+                Err(ErrorCode::BadAttr.error(meta.path.span(), "unsupported attribute"))
             }
         });
-        parser.parse(tokens)?;
+        parser
+            .parse(tokens)
+            .map_err(|err| ErrorCode::BadAttr.wrap(err))?;
         Ok(attrs)
     }
 
@@ -59,6 +182,28 @@ impl ExternrefAttrs {
             .clone()
             .unwrap_or_else(|| syn::parse_quote!(externref))
     }
+
+    fn accepted_abis(&self) -> Vec<&str> {
+        match &self.abi {
+            Some(abi) => vec![abi.as_str()],
+            None => Self::SUPPORTED_ABIS.to_vec(),
+        }
+    }
+
+    fn expected_abis(abis: &[&str]) -> String {
+        let abis: Vec<_> = abis.iter().map(|abi| format!("`{abi}`")).collect();
+        abis.join(" or ")
+    }
+}
+
+/// Parses a table name from a `table = "name"` attribute.
+fn parse_table_name(meta: &syn::meta::ParseNestedMeta<'_>) -> syn::Result<String> {
+    let name: syn::LitStr = meta.value()?.parse()?;
+    if name.value().is_empty() {
+        let msg = "table name must not be empty";
+        return Err(ErrorCode::BadAttr.error(name.span(), msg));
+    }
+    Ok(name.value())
 }
 
 /// Prepares imported functions or an exported function with `Resource` args and/or return type.
@@ -69,12 +214,198 @@ impl ExternrefAttrs {
 /// If placed on block, all enclosed functions with `Resource` args / return type will be
 /// wrapped.
 ///
+/// The block may be declared as `unsafe extern "C" { ... }` (which is required in Rust 2024).
+/// Functions in such a block marked as `safe fn` retain the `safe` qualifier on the declarations
+/// passed to the host, and their generated wrappers are safe to call as well.
+///
 /// # Processing
 ///
 /// The following arg / return types are recognized as resources:
 ///
 /// - `Resource<_>`, `&Resource<_>`, `&mut Resource<_>`
 /// - `Option<_>` of any of the above three variations
+///
+/// `Resource` may be specified with a path (e.g., `externref::Resource<_>`), and its type arg
+/// may be an arbitrary type, including a generic one (e.g., `Resource<Wrapper<T>>`).
+/// Resources with a typestate param (e.g., `Resource<File, Open>`) are recognized as well;
+/// the state does not influence the resource kind, so `Resource<File, Open>`
+/// and `Resource<File, Closed>` are both recorded as `File`.
+/// The macro cannot resolve type aliases, so args with aliased resource types must be marked
+/// with `#[resource]` (see below). If `Resource` itself is re-exported under another name,
+/// the name can be specified via the `resource_type` attribute.
+///
+/// Additionally, exported functions may return `Result<R, E>`, where `R` is one of the resource
+/// types listed above and `E: Into<i32>` is an error code. Such a function will have
+/// the `(externref, i32)` return type after processing; the reference is null on error,
+/// and the error code is 0 on success.
+///
+/// `HostHandle<_>` args and return values are passed as is (i.e., as `i32` table indices),
+/// but are recorded in the function declaration, so that the host can learn about them.
+///
+/// # Attributes
+///
+/// - `crate = "path::to::externref"` specifies the path to the `externref` crate, which is useful
+///   if the crate is re-exported.
+/// - `abi = "..."` restricts the accepted ABI of the wrapped items. By default, both `"C"`
+///   and `"C-unwind"` ABIs are accepted; e.g., `abi = "C-unwind"` will reject `extern "C"` items.
+///   The ABI of an exported function is propagated to the generated wrapper.
+/// - `target_abi = "..."` selects how wrappers of exported functions are exported. The default
+///   `"core"` target ABI uses `#[export_name = ..]`, which is appropriate for core WASM modules
+///   (e.g., `wasm32-unknown-unknown` or `wasm32-wasip1`). With `"wasip2"`, wrappers are exported
+///   via `#[unsafe(export_name = ..)]` as expected for `wasm32-wasip2` components, and exported
+///   functions with resources may be declared without `extern "C"`; the generated wrapper
+///   is `extern "C"` regardless. Only supported for exported functions.
+/// - `strict` (or `strict = true`) enables the [strict mode](#strict-mode) checks for the wrapped
+///   items; `strict = false` disables them. By default, the checks are disabled.
+/// - `no_std` guarantees that the generated code only refers to `core` items (the generated code
+///   never refers to `std` items, but it copies signatures of the wrapped functions). With this
+///   attribute, the macro will raise a compilation error if a wrapped function signature
+///   mentions a `std::` path.
+/// - `type_names` records Rust type names of resources (e.g., `Sender` for `Resource<Sender>`)
+///   in the function declarations. The processor surfaces these names in the module manifest,
+///   which can be used for host glue generation. Names are not recorded by default
+///   to keep the custom section compact.
+/// - `table = "name"` routes resources obtained from the host (i.e., args of the exported function
+///   or return values of imported functions) to the table partition `name` instead of
+///   the partition derived from the resource type (e.g., `Sender` for `Resource<Sender>`).
+///   If the processor partitions the `externref`s table by resource kind
+///   (`Processor::set_table_partitioning()`), resources of each partition end up in a separately
+///   exported table, so that references of different subsystems can be told apart by the host.
+///   Partition names share the namespace with type-derived kinds, so the host sees them
+///   as resource kinds (e.g., in the module manifest or the drop hook with kinds).
+/// - `surrogate_module = "name"` changes the module of the surrogate `guard` import called
+///   by the wrappers of imported functions. The module is recorded in the function
+///   declarations; the processor must be configured to look up the guard in the same module
+///   via `Processor::set_guard_import()`, otherwise processing fails. Only supported
+///   for foreign modules. Surrogate imports used by the `externref` crate itself
+///   (e.g., for creating and dropping `Resource`s) are not affected; to move all surrogate
+///   imports out of the `externref` module (e.g., if the host provides a real module
+///   named `externref`), compile the crate with the `EXTERNREF_SURROGATE_MODULE` env variable
+///   instead (see `externref::SURROGATE_MODULE`).
+/// - `section = "name"` places function declarations into the specified custom section
+///   instead of the default `__externrefs` one. The name must start with `__externrefs`
+///   (e.g., `__externrefs_sdk`). This allows independent libraries linked into the same module
+///   to be processed with different options; the processor must be configured to read
+///   the section via `Processor::set_section_name()` or `Processor::add_section()`.
+/// - `resource_type = "Name"` additionally recognizes `Name<_>` (possibly with a path, e.g.
+///   `crate::Name<_>`) as a resource type, e.g. if `Resource` is re-exported
+///   as `pub use externref::Resource as Name;`. Unlike with `#[resource]` args,
+///   the resource kind is derived from the type arg of `Name<_>`.
+/// - `start` declares an exported function without args and return type as the start function
+///   of the module (e.g., a function initializing resources obtained from the host).
+///   The function is left as is; the processor makes it the WASM start function, so that
+///   it runs on module instantiation, and removes its export. The function must be exported
+///   (e.g., via `#[no_mangle]`) so that it is retained by the linker. Only supported
+///   for exported functions.
+/// - `indirect_export` declares an exported function as a function table entry, e.g. a callback
+///   the host invokes via a function table rather than via a module export. Instead of hiding
+///   the wrapper converting `externref`s to resources, the macro emits it as a sibling function
+///   named `{name}_indirect` (e.g., `on_event_indirect` for `on_event`) with the same visibility;
+///   a pointer to this wrapper (rather than to the original function) should be placed
+///   into the table. The wrapper is exported under the function name, which makes its name
+///   in the `name` custom section stable; the processor resolves the wrapper by this name
+///   and patches its signature together with the `call_indirect` instructions calling it.
+///   Since all functions with the same raw signature in function tables must have the same
+///   patched signature, table entries without resources (e.g., Rust callbacks) must not share
+///   the raw signature with the wrapper. Only supported for exported functions with resource
+///   args or return type.
+///
+/// # Module name
+///
+/// The name of the imported module must be specified in the `#[link(wasm_import_module = ..)]`
+/// attribute on the foreign module. Besides string literals, the name can be specified
+/// via `concat!(..)` and `env!(..)` macro calls (e.g., `concat!("ns:", env!("CARGO_PKG_NAME"))`);
+/// such calls are resolved by the macro, and the attribute is replaced with the resolved literal.
+/// Since `env!` is resolved using the compiler environment, it is best suited for variables
+/// set by Cargo, such as `CARGO_PKG_NAME`.
+///
+/// # Item attributes
+///
+/// Functions inside an `extern "C" { ... }` block can be marked with `#[externref(skip)]`.
+/// Such functions are left as is: no wrapper is generated for them, and they are not declared
+/// in the custom section. This is useful for functions that intentionally use `Resource`-typed
+/// args / return type without `externref` semantics. Functions can also be marked
+/// with `#[externref(table = "name")]`, which overrides the table partition specified
+/// for the entire block.
+///
+/// Doc comments on wrapped functions are moved to the generated wrappers, which replace
+/// the functions in the public interface. `#[cfg(..)]` and `#[cfg_attr(..)]` attributes
+/// are copied to the wrappers, and the function declarations are gated by the same
+/// `#[cfg(..)]` predicates (including ones nested in `#[cfg_attr(..)]`). Attributes only
+/// applicable to foreign functions (e.g., `link_name`) are not copied. Likewise, wrappers
+/// and declarations of exported functions are gated by `#[cfg(..)]` attributes placed after
+/// the `externref` attribute.
+///
+/// # Arg attributes
+///
+/// `Resource<_>`, `&Resource<_>` and `&mut Resource<_>` args can be marked with
+/// `#[resource(nullable)]`. For exported functions, a null `externref` passed by the host
+/// is then converted to a null resource (see `Resource::null()`) instead of panicking.
+/// Null resources are always passed to the host as null `externref`s, so the attribute
+/// does not influence imported functions.
+///
+/// Args with a type alias for `Resource<_>` (e.g., `type Sender = Resource<SenderMarker>;`),
+/// a reference to it, or `Option<_>` of these must be marked with a bare `#[resource]` attribute
+/// so that the macro treats them as resources. The resource kind of such an arg is derived
+/// from the alias name (e.g., `Sender`) rather than from the marker type.
+///
+/// Resource args can also be marked with `#[resource(table = "name")]`, which overrides
+/// the table partition specified for the function (see the `table` attribute above).
+/// Like the function-level attribute, it only affects args of exported functions.
+///
+/// `Resource<_>` and `Option<Resource<_>>` args of imported functions can be marked with
+/// `#[resource(consumed)]`. Such resources are passed by value and are dropped after the call
+/// (i.e., the host must retain the reference if it needs it); the attribute acknowledges this.
+/// It has no effect on the generated code, but is required in the strict mode (see below).
+///
+/// # Return attributes
+///
+/// By default, an `Option<Resource<_>>` returned from an exported function is lowered
+/// to a nullable `externref`, with `None` mapped to a null reference. Some hosts cannot
+/// distinguish a null result from a missing one; for them, an exported function returning
+/// `Option<_>` of a resource can be marked with `#[resource(nullable = "flag")]`.
+/// After processing, such a function returns an `(externref, i32)` pair, with the second value
+/// being 1 if the resource is present and 0 otherwise (in which case the `externref` is null).
+/// `#[resource(nullable = "null")]` explicitly selects the default lowering.
+///
+/// # Strict mode
+///
+/// With the `strict` attribute, the macro raises errors for likely resource misuse:
+///
+/// - An imported function takes a resource by value without marking the arg with
+///   `#[resource(consumed)]`. Usually, resources should be passed to the host by reference.
+///   Typestate transitions (an arg of the same resource kind as the returned resource,
+///   but in a different state, like `Resource<File, Open>` → `Resource<File, Closed>`)
+///   are not flagged.
+/// - An exported function takes a resource by value, but never mentions it in the function body,
+///   so that the resource is silently dropped once the function returns. Like with unused
+///   variables, the check can be silenced by prefixing the arg name with `_`.
+///
+/// The checks are syntactic; e.g., an exported function arg mentioned only in a nested
+/// macro call (including inline format args like `format!("{res:?}")`) is considered used.
+///
+/// # Error codes
+///
+/// Errors raised by the macro are prefixed with a code pointing to the offending item,
+/// attribute or type:
+///
+/// - **E001:** the wrapped item is not marked with `extern "C"` (or `extern "C-unwind"`),
+///   its ABI is not accepted by the `abi` attribute, or the `abi` / `target_abi` attribute value
+///   is unsupported.
+/// - **E002:** malformed `#[link(..)]`, `#[link_name = ..]` or `#[export_name = ..]` attribute,
+///   e.g. the foreign module lacks `#[link(wasm_import_module = ..)]`.
+/// - **E003:** the WASM module name cannot be resolved at macro expansion; see
+///   [the module name section](#module-name) for the supported formats.
+/// - **E004:** the item cannot be wrapped, e.g. it is a struct or a variadic function.
+/// - **E005:** unsupported or malformed `#[externref(..)]` / `#[resource(..)]` attribute.
+/// - **E006:** a `#[resource]` / `#[resource(..)]` attribute is placed on an arg or function
+///   that does not support it, e.g. `#[resource(nullable)]` on an `Option<Resource<_>>` arg.
+/// - **E007:** an arg or return type contains `Resource<_>`, but is not recognized as a resource
+///   (e.g., `&[Resource<_>]` or `&Option<Resource<_>>`); see [the supported types](#processing).
+///   Raw pointers to resources are not checked.
+/// - **E008:** an imported function has a `Result` return type.
+/// - **E009:** a signature mentions a `std::` path with the `no_std` attribute.
+/// - **E010:** resource misuse flagged in [the strict mode](#strict-mode).
 #[proc_macro_attribute]
 pub fn externref(attr: TokenStream, input: TokenStream) -> TokenStream {
     const MSG: &str = "Unsupported item; only `extern \"C\" {}` modules and `extern \"C\" fn ...` \
@@ -89,7 +420,8 @@ pub fn externref(attr: TokenStream, input: TokenStream) -> TokenStream {
         Ok(Item::ForeignMod(mut module)) => for_foreign_module(&mut module, &attrs),
         Ok(Item::Fn(mut function)) => for_export(&mut function, &attrs),
         Ok(other) => {
-            return SynError::new_spanned(other, MSG)
+            return ErrorCode::UnsupportedItem
+                .spanned_error(other, MSG)
                 .into_compile_error()
                 .into()
         }
@@ -97,3 +429,14 @@ pub fn externref(attr: TokenStream, input: TokenStream) -> TokenStream {
     };
     output.into()
 }
+
+/// Derives the `HostResource` trait from the `host` module of the `externref` crate.
+///
+/// See the `externref::host::HostResource` docs for the supported attributes and examples.
+#[proc_macro_derive(HostResource, attributes(host_resource))]
+pub fn host_resource(input: TokenStream) -> TokenStream {
+    match syn::parse::<DeriveInput>(input) {
+        Ok(input) => host_resource::impl_host_resource(&input).into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
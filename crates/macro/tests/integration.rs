@@ -1,7 +1,8 @@
-//! UI tests for various compilation failures.
+//! UI tests for compilation failures, and for compiling the generated code in `no_std` crates.
 
 #[test]
 fn ui() {
     let t = trybuild::TestCases::new();
     t.compile_fail("tests/ui/*.rs");
+    t.pass("tests/ui/pass/*.rs");
 }
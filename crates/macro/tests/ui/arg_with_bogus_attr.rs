@@ -0,0 +1,10 @@
+use externref_macro::externref;
+
+pub struct Resource<T>(T);
+
+#[externref]
+pub extern "C" fn test_export(#[resource(optional)] sender: &Resource<()>) {
+    // Does nothing
+}
+
+fn main() {}
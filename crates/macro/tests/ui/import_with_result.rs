@@ -0,0 +1,11 @@
+use externref_macro::externref;
+
+pub struct Resource<T>(T);
+
+#[externref]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    pub fn fallible(resource: &Resource<()>) -> Result<Resource<()>, i32>;
+}
+
+fn main() {}
@@ -10,4 +10,25 @@ extern "win64" {
     pub fn unused(ptr: *const u8, len: usize);
 }
 
+#[externref(abi = "C-unwind")]
+pub extern "C" fn restricted() {
+    // Does nothing.
+}
+
+#[externref(abi = "system")]
+pub extern "system" fn unsupported() {
+    // Does nothing.
+}
+
+#[externref(target_abi = "wasip3")]
+pub extern "C" fn unsupported_target() {
+    // Does nothing.
+}
+
+#[externref(target_abi = "wasip2")]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    pub fn imported(ptr: *const u8, len: usize);
+}
+
 fn main() {}
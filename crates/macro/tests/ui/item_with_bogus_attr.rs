@@ -0,0 +1,25 @@
+use externref_macro::externref;
+
+pub struct Resource<T>(T);
+
+#[externref]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    #[externref(skip = true)]
+    pub fn skipped(resource: Resource<()>);
+}
+
+#[externref]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    #[externref(wrap)]
+    pub fn unknown(resource: Resource<()>);
+}
+
+#[externref(section = "custom_section")]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    pub fn bogus_section(resource: Resource<()>);
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+use externref_macro::externref;
+
+pub struct Resource<T>(T);
+
+#[externref(no_std)]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    pub fn send_message(resource: &Resource<()>, fallback: std::option::Option<u8>);
+}
+
+#[externref(no_std)]
+pub extern "C" fn test_export(resource: Resource<()>) -> std::primitive::u32 {
+    0
+}
+
+fn main() {}
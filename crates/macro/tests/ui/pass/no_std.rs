@@ -0,0 +1,25 @@
+//! Checks that the code generated by the macro compiles in `no_std` crates.
+
+#![no_std]
+
+// Links `std` to provide the runtime for the test binary without bringing `std` into scope,
+// so that the generated code referring to `std` paths would fail to compile.
+extern crate std as _;
+
+use externref::{externref, Resource};
+
+pub struct Sender(());
+
+#[externref(no_std)]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    fn send_message(sender: &Resource<Sender>, message: Option<&Resource<Sender>>) -> i32;
+}
+
+#[externref(no_std)]
+pub extern "C" fn test_export(sender: Resource<Sender>) -> Option<Resource<Sender>> {
+    unsafe { send_message(&sender, None) };
+    Some(sender)
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+use externref_macro::externref;
+
+pub struct Resource<T>(T);
+
+#[externref]
+#[link(wasm_import_module = "test")]
+extern "C" {
+    pub fn send_messages(resources: &[Resource<()>]);
+}
+
+#[externref]
+pub extern "C" fn test_export(resource: &Option<Resource<()>>) {
+    // Do nothing
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+//! Exports the function table from the WASM module, so that the host can invoke callbacks
+//! placed in it.
+
+use std::env;
+
+fn main() {
+    if env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32") {
+        println!("cargo:rustc-link-arg-cdylib=--export-table");
+    }
+}
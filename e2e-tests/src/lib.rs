@@ -5,8 +5,9 @@
 extern crate alloc;
 
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use externref::{externref, Resource};
+use externref::{externref, GlobalResource, HostHandle, Resource, ResourceTable, SharedResource};
 
 #[cfg(target_arch = "wasm32")]
 #[global_allocator]
@@ -22,6 +23,9 @@ pub struct Sender(());
 
 pub struct Bytes(());
 
+/// Marker for a cached message distinct from [`Bytes`].
+pub struct Greeting(());
+
 // Emulate reexporting the crate.
 mod reexports {
     pub use externref as anyref;
@@ -33,8 +37,9 @@ mod imports {
     use crate::{Bytes, Sender};
 
     #[cfg(target_arch = "wasm32")]
-    #[externref::externref]
-    #[link(wasm_import_module = "test")]
+    #[externref::externref(no_std)]
+    // Check that the module name can be computed at compile time.
+    #[link(wasm_import_module = concat!("te", "st"))]
     extern "C" {
         pub(crate) fn send_message(
             sender: &Resource<Sender>,
@@ -44,6 +49,8 @@ mod imports {
 
         pub(crate) fn message_len(bytes: Option<&Resource<Bytes>>) -> usize;
 
+        pub(crate) fn consume_message(#[resource(consumed)] bytes: Resource<Bytes>) -> usize;
+
         #[link_name = "inspect_refs"]
         pub(crate) fn inspect_refs_on_host();
     }
@@ -61,6 +68,11 @@ mod imports {
     pub(crate) unsafe fn message_len(_: Option<&Resource<Bytes>>) -> usize {
         panic!("only callable from WASM")
     }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) unsafe fn consume_message(_: Resource<Bytes>) -> usize {
+        panic!("only callable from WASM")
+    }
 }
 
 /// Calls to the host to check the `externrefs` table.
@@ -115,3 +127,290 @@ pub extern "C" fn test_nulls(sender: Option<&Resource<Sender>>) {
     }
     assert_eq!(unsafe { imports::message_len(None) }, 0);
 }
+
+#[externref(type_names)]
+pub extern "C" fn test_fallible(
+    sender: &Resource<Sender>,
+    error_code: i32,
+) -> Result<Resource<Bytes>, i32> {
+    if error_code == 0 {
+        let message = "test";
+        Ok(unsafe { imports::send_message(sender, message.as_ptr(), message.len()) })
+    } else {
+        Err(error_code)
+    }
+}
+
+#[externref]
+#[resource(nullable = "flag")]
+pub extern "C" fn test_flagged(sender: &Resource<Sender>, present: i32) -> Option<Resource<Bytes>> {
+    (present != 0).then(|| {
+        let message = "test";
+        unsafe { imports::send_message(sender, message.as_ptr(), message.len()) }
+    })
+}
+
+/// Passes a message to the host by value. The message must be passed before it is dropped;
+/// otherwise, the host would receive a null reference.
+#[externref]
+pub extern "C" fn test_consumed(sender: &Resource<Sender>) -> usize {
+    let message = "test";
+    let bytes = unsafe { imports::send_message(sender, message.as_ptr(), message.len()) };
+    unsafe { imports::consume_message(bytes) }
+}
+
+/// Returns the message length, or -1 if the host has revoked the message while it was lent.
+#[externref]
+pub extern "C" fn test_lending(sender: &Resource<Sender>) -> i32 {
+    let message = "test";
+    let bytes = unsafe { imports::send_message(sender, message.as_ptr(), message.len()) };
+    let len = bytes.lend(|token| {
+        // The host may revoke the message when inspecting refs.
+        inspect_refs();
+        unsafe { imports::message_len(Some(token.resource())) }
+    });
+
+    match len {
+        Ok(len) => i32::try_from(len).unwrap(),
+        Err(_) => {
+            // The slot may be reused, so the resource must not be dropped.
+            core::mem::forget(bytes);
+            -1
+        }
+    }
+}
+
+/// Converts the sender into a handle; the host checks that it points to the sender in the table.
+#[externref]
+pub extern "C" fn test_into_handle(sender: Resource<Sender>) -> HostHandle<Sender> {
+    sender.into_host_handle()
+}
+
+/// Converts the handle back into the sender.
+#[externref]
+pub extern "C" fn test_from_handle(handle: HostHandle<Sender>) -> Resource<Sender> {
+    Resource::from_host_handle(handle)
+}
+
+/// Pushes messages to the resource table, which is then inspected by the host.
+#[externref]
+pub extern "C" fn test_resource_table(sender: &Resource<Sender>) -> usize {
+    let mut table = ResourceTable::<Bytes>::acquire().unwrap();
+    table.clear();
+    for message in ["test", "42"] {
+        let bytes = unsafe { imports::send_message(sender, message.as_ptr(), message.len()) };
+        table.push(&bytes);
+    }
+    let bytes = table.get(1).unwrap();
+    assert_eq!(unsafe { imports::message_len(Some(&bytes)) }, 2);
+    assert!(table.get(2).is_none());
+
+    let len = table.len();
+    // Retain the pushed messages for the host.
+    table.release();
+    len
+}
+
+/// Sender set by the host, which is replaced with a message by [`test_global_resource()`].
+static CONTEXT: GlobalResource<Sender> = GlobalResource::new();
+
+/// Sends a message via the sender stored in the resource global and replaces the sender
+/// with the message.
+#[no_mangle]
+pub extern "C" fn test_global_resource() -> usize {
+    let sender = CONTEXT.get().expect("sender is not set");
+    let message = "context";
+    let bytes = unsafe { imports::send_message(&sender, message.as_ptr(), message.len()) };
+    // Types are only hints for resource globals.
+    GlobalResource::<Bytes>::new().set(Some(&bytes));
+    unsafe { imports::message_len(Some(&bytes)) }
+}
+
+/// Stores the nullable sender in an array and returns it back, checking that nulls pass through.
+#[externref]
+pub extern "C" fn test_nullable(
+    #[resource(nullable)] sender: Resource<Sender>,
+) -> Resource<Sender> {
+    let is_null = sender.is_null();
+    let mut senders = [Resource::null(), sender];
+    assert!(senders[0].is_null());
+    assert_eq!(senders[1].is_null(), is_null);
+    core::mem::replace(&mut senders[1], Resource::null())
+}
+
+/// Shares the sender with the host and sends a message via the resource restored from the share.
+#[externref]
+pub extern "C" fn test_shared(sender: &Resource<Sender>) -> usize {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let shared = SharedResource::new(sender);
+    assert_send_sync(&shared);
+    let sender = shared.to_resource();
+    let message = "shared";
+    let bytes = unsafe { imports::send_message(&sender, message.as_ptr(), message.len()) };
+    unsafe { imports::message_len(Some(&bytes)) }
+}
+
+/// Wrappers written by hand with the help of [`externref::wrappers`] instead of the macro.
+mod manual {
+    use externref::{
+        declare_function, resource_kind, wrappers, BitSlice, ExternRef, Function, FunctionKind,
+        Resource, TypeNames,
+    };
+
+    use crate::{Bytes, Sender};
+
+    #[cfg(target_arch = "wasm32")]
+    #[link(wasm_import_module = "test")]
+    extern "C" {
+        #[link_name = "send_message"]
+        fn raw_send_message(
+            sender: ExternRef,
+            message_ptr: *const u8,
+            message_len: usize,
+        ) -> ExternRef;
+        #[link_name = "message_len"]
+        fn raw_message_len(bytes: ExternRef) -> usize;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    unsafe fn raw_send_message(_: ExternRef, _: *const u8, _: usize) -> ExternRef {
+        panic!("only callable from WASM")
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    unsafe fn raw_message_len(_: ExternRef) -> usize {
+        panic!("only callable from WASM")
+    }
+
+    // Same declarations as ones produced by the macro.
+    declare_function!(Function {
+        kind: FunctionKind::Import("test"),
+        name: "send_message",
+        externrefs: BitSlice::builder::<1>(4)
+            .with_set_bit(0)
+            .with_set_bit(3)
+            .build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    });
+
+    declare_function!(Function {
+        kind: FunctionKind::Import("test"),
+        name: "message_len",
+        externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    });
+
+    #[inline(never)]
+    fn send_message(sender: &Resource<Sender>, message: &str) -> Resource<Bytes> {
+        wrappers::guard();
+        let raw = unsafe {
+            raw_send_message(
+                wrappers::lower(Some(sender)),
+                message.as_ptr(),
+                message.len(),
+            )
+        };
+        wrappers::lift_non_null(raw, resource_kind!("Bytes"))
+    }
+
+    #[inline(never)]
+    fn message_len(bytes: Option<&Resource<Bytes>>) -> usize {
+        wrappers::guard();
+        unsafe { raw_message_len(wrappers::lower(bytes)) }
+    }
+
+    declare_function!(Function {
+        kind: FunctionKind::Export,
+        name: "test_manual_wrappers",
+        externrefs: BitSlice::builder::<1>(2).with_set_bit(0).build(),
+        host_handles: BitSlice::EMPTY,
+        type_names: TypeNames::EMPTY,
+        surrogate_module: None,
+    });
+
+    /// Sends messages via hand-written wrappers and returns the total length of responses.
+    #[export_name = "test_manual_wrappers"]
+    pub extern "C" fn test_manual_wrappers(sender: ExternRef) -> usize {
+        let sender: Resource<Sender> = wrappers::lift_non_null(sender, resource_kind!("Sender"));
+        assert_eq!(message_len(None), 0);
+        ["manual", "wrappers"]
+            .into_iter()
+            .map(|message| {
+                let bytes = send_message(&sender, message);
+                message_len(Some(&bytes))
+            })
+            .sum()
+    }
+}
+
+/// Number of resources dropped since the drop hook was registered in [`test_drop_hook()`].
+static DROPPED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn record_drop(_index: usize) {
+    DROPPED_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Registers a drop hook and drops several messages.
+/// Returns the number of drops recorded by the hook.
+#[externref]
+pub extern "C" fn test_drop_hook(sender: &Resource<Sender>) -> usize {
+    Resource::on_drop(record_drop);
+    DROPPED_COUNT.store(0, Ordering::Relaxed);
+
+    for message in ["test", "42", "dropped"] {
+        let bytes = unsafe { imports::send_message(sender, message.as_ptr(), message.len()) };
+        drop(bytes);
+    }
+    DROPPED_COUNT.load(Ordering::Relaxed)
+}
+
+/// Sends several messages and then drops them together with the sender via bulk drop functions.
+#[externref]
+pub extern "C" fn test_bulk_drop(sender: Resource<Sender>) {
+    let handles: Vec<_> = ["test", "42", "bulk"]
+        .into_iter()
+        .map(|message| {
+            let bytes = unsafe { imports::send_message(&sender, message.as_ptr(), message.len()) };
+            bytes.into_host_handle()
+        })
+        .collect();
+    core::mem::forget(sender);
+
+    unsafe {
+        Resource::drop_range(handles[0].index(), 2);
+        externref::drop_all();
+    }
+}
+
+/// Caches messages sent via `sender` and returns the length of the cached [`Bytes`] message.
+/// The messages are sent only on the first call.
+#[externref]
+pub extern "C" fn test_cached(sender: &Resource<Sender>) -> usize {
+    let send =
+        |message: &str| unsafe { imports::send_message(sender, message.as_ptr(), message.len()) };
+    let bytes = Resource::<Bytes>::cached(|| send("cached"));
+    Resource::<Greeting>::cached(|| {
+        let greeting = send("hello");
+        unsafe { greeting.upcast().downcast_unchecked() }
+    });
+    unsafe { imports::message_len(Some(bytes)) }
+}
+
+/// Callback invoked by the host via the function table; sends a message via `sender`.
+#[externref(indirect_export)]
+pub extern "C" fn on_callback(sender: &Resource<Sender>) -> Resource<Bytes> {
+    let message = "callback";
+    unsafe { imports::send_message(sender, message.as_ptr(), message.len()) }
+}
+
+/// Returns the index of the [`on_callback()`] wrapper in the function table exported
+/// by the build script.
+#[no_mangle]
+pub extern "C" fn test_callback_index() -> usize {
+    on_callback_indirect as *const () as usize
+}
@@ -4,7 +4,10 @@ use std::{collections::HashSet, sync::Once};
 
 use anyhow::{anyhow, Context};
 use assert_matches::assert_matches;
-use externref::processor::Processor;
+use externref::{
+    host::{HostResource, SharedRegistry, TableSnapshot},
+    processor::{self, DropSlotValue, GcMode, Partitioning, PostOptimizer, Processor},
+};
 use once_cell::sync::Lazy;
 use test_casing::{test_casing, Product};
 use tracing::{subscriber::DefaultGuard, Level, Subscriber};
@@ -13,12 +16,15 @@ use tracing_subscriber::{
     fmt::format::FmtSpan, layer::SubscriberExt, registry::LookupSpan, FmtSubscriber,
 };
 use wasmtime::{
-    Caller, Engine, Extern, ExternRef, Linker, ManuallyRooted, Module, Ref, Rooted, Store, Table,
+    Caller, Engine, Extern, ExternRef, Global, GlobalType, Linker, ManuallyRooted, Module,
+    Mutability, Ref, Rooted, Store, Table, Val, ValType,
 };
 
 use crate::compile::CompilationProfile;
 
 mod compile;
+mod mock;
+mod table;
 
 type RefAssertion = fn(Caller<'_, Data>, &Table);
 
@@ -61,7 +67,8 @@ fn enable_tracing_assertions() -> (DefaultGuard, SharedStorage) {
     (guard, storage)
 }
 
-#[derive(Debug)]
+#[derive(Debug, HostResource)]
+#[host_resource(kind = "Sender")]
 struct HostSender {
     key: String,
 }
@@ -71,6 +78,12 @@ struct Data {
     ref_assertions: Vec<RefAssertion>,
     senders: HashSet<String>,
     dropped: Vec<ManuallyRooted<ExternRef>>,
+    dropped_kinds: Vec<i32>,
+    leaked_counts: Vec<i32>,
+    moved_refs: Vec<(i32, i32)>,
+    /// IDs of references dropped by the module from within the drop hook.
+    reentrant_drops: Vec<i32>,
+    shared: SharedRegistry<String>,
 }
 
 impl Data {
@@ -81,6 +94,11 @@ impl Data {
             ref_assertions,
             senders: HashSet::new(),
             dropped: vec![],
+            dropped_kinds: vec![],
+            leaked_counts: vec![],
+            moved_refs: vec![],
+            reentrant_drops: vec![],
+            shared: SharedRegistry::default(),
         }
     }
 
@@ -142,29 +160,54 @@ fn message_len(ctx: Caller<'_, Data>, resource: Option<Rooted<ExternRef>>) -> an
     Ok(u32::try_from(str.len()).unwrap())
 }
 
+fn consume_message(
+    ctx: Caller<'_, Data>,
+    resource: Option<Rooted<ExternRef>>,
+) -> anyhow::Result<u32> {
+    let resource = resource.context("null reference passed by value")?;
+    message_len(ctx, Some(resource))
+}
+
 fn inspect_refs(mut ctx: Caller<'_, Data>) {
     let refs = ctx.data().externrefs.unwrap();
     let assertions = ctx.data_mut().ref_assertions.pop().unwrap();
     assertions(ctx, &refs);
 }
 
-fn assert_refs(mut ctx: Caller<'_, Data>, table: &Table, buffers_liveness: &[bool]) {
+fn table_snapshot(mut ctx: Caller<'_, Data>, table: &Table) -> TableSnapshot<&'static str> {
     let size = table.size(&ctx);
-    assert_eq!(size, 1 + buffers_liveness.len() as u32);
     let refs: Vec<_> = (0..size)
         .map(|idx| table.get(&mut ctx, idx).unwrap())
         .collect();
-    let refs: Vec<_> = refs.iter().map(Ref::unwrap_extern).collect();
+    let slots = refs.iter().map(|r| {
+        let data = r.unwrap_extern().as_ref()?.data(&ctx).unwrap();
+        Some(if data.is::<HostSender>() {
+            "sender"
+        } else if data.is::<Box<str>>() {
+            "buffer"
+        } else {
+            "unknown"
+        })
+    });
+    TableSnapshot::new(slots.collect::<Vec<_>>())
+}
 
-    let sender_ref = refs[0].as_ref().unwrap();
-    assert!(sender_ref.data(&ctx).unwrap().is::<HostSender>());
+fn assert_refs(ctx: Caller<'_, Data>, table: &Table, buffers_liveness: &[bool]) {
+    let buffers = buffers_liveness
+        .iter()
+        .map(|&live| live.then_some("buffer"));
+    let expected = TableSnapshot::new(std::iter::once(Some("sender")).chain(buffers));
+    table_snapshot(ctx, table).assert_matches(&expected);
+}
 
-    for (buffer_ref, &live) in refs[1..].iter().zip(buffers_liveness) {
-        if live {
-            let buffer_ref = buffer_ref.as_ref().unwrap();
-            assert!(buffer_ref.data(&ctx).unwrap().is::<Box<str>>());
-        } else {
-            assert!(buffer_ref.is_none());
+fn revoke_buffers(mut ctx: Caller<'_, Data>, table: &Table) {
+    for idx in 0..table.size(&ctx) {
+        let slot = table.get(&mut ctx, idx).unwrap();
+        let Some(slot) = slot.unwrap_extern() else {
+            continue;
+        };
+        if slot.data(&ctx).unwrap().is::<Box<str>>() {
+            table.set(&mut ctx, idx, Ref::Extern(None)).unwrap();
         }
     }
 }
@@ -175,6 +218,42 @@ fn drop_ref(mut ctx: Caller<'_, Data>, dropped: Option<Rooted<ExternRef>>) {
     ctx.data_mut().dropped.push(dropped);
 }
 
+fn drop_ref_with_kind(mut ctx: Caller<'_, Data>, dropped: Option<Rooted<ExternRef>>, kind: i32) {
+    ctx.data_mut().dropped_kinds.push(kind);
+    drop_ref(ctx, dropped);
+}
+
+fn on_leak(mut ctx: Caller<'_, Data>, count: i32) {
+    ctx.data_mut().leaked_counts.push(count);
+}
+
+fn move_ref(mut ctx: Caller<'_, Data>, old_id: i32, new_id: i32) {
+    ctx.data_mut().moved_refs.push((old_id, new_id));
+}
+
+fn share_ref(ctx: Caller<'_, Data>, resource: Option<Rooted<ExternRef>>) -> anyhow::Result<u32> {
+    let sender = resource
+        .context("null reference passed to host")?
+        .data(&ctx)?
+        .downcast_ref::<HostSender>()
+        .ok_or_else(|| anyhow!("passed reference has incorrect type"))?;
+    let key = sender.key.clone();
+    Ok(ctx.data().shared.insert(key))
+}
+
+fn get_shared_ref(
+    mut ctx: Caller<'_, Data>,
+    handle: u32,
+) -> anyhow::Result<Option<Rooted<ExternRef>>> {
+    let key = ctx.data().shared.get(handle).context("invalid handle")?;
+    ExternRef::new(&mut ctx, HostSender { key }).map(Some)
+}
+
+fn drop_shared_ref(ctx: Caller<'_, Data>, handle: u32) -> anyhow::Result<()> {
+    ctx.data().shared.remove(handle).context("invalid handle")?;
+    Ok(())
+}
+
 fn create_linker(engine: &Engine) -> Linker<Data> {
     let mut linker = Linker::new(engine);
     linker
@@ -183,11 +262,28 @@ fn create_linker(engine: &Engine) -> Linker<Data> {
     linker
         .func_wrap("test", "message_len", message_len)
         .unwrap();
+    linker
+        .func_wrap("test", "consume_message", consume_message)
+        .unwrap();
     linker
         .func_wrap("test", "inspect_refs", inspect_refs)
         .unwrap();
     linker.func_wrap("test", "drop_ref", drop_ref).unwrap();
     linker
+        .func_wrap("test", "drop_ref_with_kind", drop_ref_with_kind)
+        .unwrap();
+    linker.func_wrap("test", "on_leak", on_leak).unwrap();
+    linker.func_wrap("test", "move_ref", move_ref).unwrap();
+    linker
+        .func_wrap("externref_shared", "share", share_ref)
+        .unwrap();
+    linker
+        .func_wrap("externref_shared", "get", get_shared_ref)
+        .unwrap();
+    linker
+        .func_wrap("externref_shared", "drop", drop_shared_ref)
+        .unwrap();
+    linker
 }
 
 #[test_casing(8, Product((CompilationProfile::ALL, ["test_export", "test_export_with_casts"])))]
@@ -245,12 +341,12 @@ fn assert_tracing_output(storage: &Storage) {
     let spans = storage.scan_spans();
     let process_span = spans.single(&name(eq("process")));
     let matches =
-        level(Level::INFO) & message(eq("parsed custom section")) & field("functions.len", 5_u64);
+        level(Level::INFO) & message(eq("parsed custom section")) & field("functions.len", 20_u64);
     process_span.scan_events().single(&matches);
 
-    let patch_imports_span = spans.single(&name(eq("patch_imports")));
+    // Replaced imports are reported by the tracing observer after processing.
     let matches = into_fn(message(contains("replaced import")) & level(Level::DEBUG));
-    let replaced_imports = patch_imports_span.events().filter_map(|event| {
+    let replaced_imports = process_span.events().filter_map(|event| {
         if matches(&event) {
             event.value("name")?.as_str()
         } else {
@@ -260,7 +356,27 @@ fn assert_tracing_output(storage: &Storage) {
     let replaced_imports: HashSet<_> = replaced_imports.collect();
     assert_eq!(
         replaced_imports,
-        HashSet::from_iter(["externref::insert", "externref::get", "externref::drop"])
+        HashSet::from_iter([
+            "externref::insert",
+            "externref::get",
+            "externref::drop",
+            "externref::drop_all",
+            "externref::drop_range",
+            "externref::set_error_code",
+            "externref::is_live",
+            "externref::kind::()",
+            "externref::kind::Bytes",
+            "externref::kind::Sender",
+            "externref::table_push",
+            "externref::table_get",
+            "externref::table_len",
+            "externref::table_clear",
+            "externref::global_get",
+            "externref::global_set",
+            "externref::share",
+            "externref::get_shared",
+            "externref::drop_shared",
+        ])
     );
 
     let replace_functions_span = spans.single(&name(eq("replace_functions")));
@@ -269,6 +385,12 @@ fn assert_tracing_output(storage: &Storage) {
         & field("replaced_count", value(gt(0_u64)));
     replace_functions_span.scan_events().single(&matches);
 
+    let gc_span = process_span.deep_scan_spans().single(&name(eq("gc")));
+    let matches = level(Level::INFO)
+        & message(eq("removed unused items"))
+        & field("removed_fns", value(gt(0_u64)));
+    gc_span.scan_events().single(&matches);
+
     let transformed_imports = storage.all_spans().filter_map(|span| {
         if span.metadata().name() == "transform_import" {
             assert_eq!(span["module"].as_str(), Some("test"));
@@ -280,7 +402,7 @@ fn assert_tracing_output(storage: &Storage) {
     let transformed_imports: HashSet<_> = transformed_imports.collect();
     assert_eq!(
         transformed_imports,
-        HashSet::from_iter(["send_message", "message_len"])
+        HashSet::from_iter(["send_message", "message_len", "consume_message"])
     );
 
     let transformed_exports = storage.all_spans().filter_map(|span| {
@@ -291,10 +413,43 @@ fn assert_tracing_output(storage: &Storage) {
         }
     });
     let transformed_exports: HashSet<_> = transformed_exports.collect();
+    // Each transformed function records processing stats.
+    for span in storage.all_spans() {
+        if span.metadata().name() == "transform_export" {
+            let instr_count = span.value("instr_count").and_then(|val| val.as_uint());
+            assert!(instr_count > Some(0), "{span:?}");
+            assert!(span.value("elapsed_us").is_some(), "{span:?}");
+        }
+    }
     assert!(
         transformed_exports.contains("test_nulls"),
         "{transformed_exports:?}"
     );
+    assert!(
+        transformed_exports.contains("test_fallible"),
+        "{transformed_exports:?}"
+    );
+    let names = [
+        "test_lending",
+        "test_consumed",
+        "test_into_handle",
+        "test_from_handle",
+        "test_resource_table",
+        "test_nullable",
+        "test_shared",
+        "test_drop_hook",
+        "test_bulk_drop",
+        "test_manual_wrappers",
+        "test_cached",
+        "test_flagged",
+        "on_callback",
+    ];
+    for name in names {
+        assert!(
+            transformed_exports.contains(name),
+            "{transformed_exports:?}"
+        );
+    }
 
     // Since `test_export` and `test_export_with_casts` have the same logic, they may be optimized
     // to a single implementation.
@@ -306,11 +461,50 @@ fn assert_tracing_output(storage: &Storage) {
     );
     assert_eq!(
         transformed_exports.len(),
-        1 + contains_export as usize + contains_export_with_casts as usize,
+        15 + contains_export as usize + contains_export_with_casts as usize,
         "{transformed_exports:?}"
     );
 }
 
+#[test_casing(4, CompilationProfile::ALL)]
+fn post_optimized_module(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_drop_fn("test", "drop_ref")
+        .set_post_optimizer(PostOptimizer::WasmOpt {
+            args: vec!["-Os", "--enable-reference-types"],
+        })
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+
+    let ref_assertions: Vec<RefAssertion> = vec![
+        |caller, table| assert_refs(caller, table, &[]),
+        |caller, table| assert_refs(caller, table, &[true]),
+        |caller, table| assert_refs(caller, table, &[true; 2]),
+        |caller, table| assert_refs(caller, table, &[true; 3]),
+        |caller, table| assert_refs(caller, table, &[false, true, true]),
+        |caller, table| assert_refs(caller, table, &[false; 3]),
+    ];
+    let mut store = Store::new(module.engine(), Data::new(ref_assertions));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+
+    let exported_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    exported_fn.call(&mut store, sender).unwrap();
+
+    store
+        .data()
+        .assert_drops(&store, &["test", "some other string", "42"]);
+}
+
 #[test_casing(4, CompilationProfile::ALL)]
 fn null_references(profile: CompilationProfile) {
     enable_tracing();
@@ -331,3 +525,950 @@ fn null_references(profile: CompilationProfile) {
     test_fn.call(&mut store, Some(sender)).unwrap();
     test_fn.call(&mut store, None).unwrap();
 }
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn nullable_args(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Option<Rooted<ExternRef>>, Option<Rooted<ExternRef>>>(
+            &mut store,
+            "test_nullable",
+        )
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let returned = test_fn.call(&mut store, Some(sender)).unwrap().unwrap();
+    assert!(Rooted::ref_eq(&store, &returned, &sender).unwrap());
+    assert!(test_fn.call(&mut store, None).unwrap().is_none());
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn shared_resources(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_shared")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, 6);
+    // The shared resource is dropped at the end of the call.
+    assert!(store.data().shared.is_empty());
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn module_without_gc(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_gc(GcMode::Skip)
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_shared")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, 6);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn manual_wrappers(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_manual_wrappers")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, 14);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn drop_hook(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_drop_fn("test", "drop_ref")
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_drop_hook")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let dropped_count = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(dropped_count, 3);
+    store
+        .data()
+        .assert_drops(&store, &["test", "42", "dropped"]);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn cached_resources(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_drop_fn("test", "drop_ref")
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_cached")
+        .unwrap();
+    for _ in 0..2 {
+        let sender = store.data_mut().push_sender("sender");
+        let sender = ExternRef::new(&mut store, sender).unwrap();
+        let len = test_fn.call(&mut store, sender).unwrap();
+        assert_eq!(len, 6);
+    }
+    store.data().assert_drops(&store, &[]);
+
+    let teardown_fn = instance
+        .get_typed_func::<(), ()>(&mut store, "__externref_teardown")
+        .unwrap();
+    teardown_fn.call(&mut store, ()).unwrap();
+    // Cached resources are dropped in the reverse order of caching.
+    store.data().assert_drops(&store, &["hello", "cached"]);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn bulk_drop(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_drop_fn("test", "drop_ref")
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_bulk_drop")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    test_fn.call(&mut store, sender).unwrap();
+    // The first 2 messages are dropped via `drop_range()`, and the remaining refs
+    // via `drop_all()` in the reverse table order.
+    store.data().assert_drops(&store, &["test", "42", "bulk"]);
+    assert_eq!(store.data().dropped.len(), 4);
+
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    for i in 0..externrefs.size(&store) {
+        assert!(externrefs
+            .get(&mut store, i)
+            .unwrap()
+            .unwrap_extern()
+            .is_none());
+    }
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn indirect_export(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let index_fn = instance
+        .get_typed_func::<(), u32>(&mut store, "test_callback_index")
+        .unwrap();
+    let index = index_fn.call(&mut store, ()).unwrap();
+    let fn_table = instance
+        .get_table(&mut store, "__indirect_function_table")
+        .unwrap();
+    let callback = fn_table.get(&mut store, index).unwrap();
+    let callback = callback.unwrap_func().expect("null callback");
+    let callback = callback
+        .typed::<Rooted<ExternRef>, Option<Rooted<ExternRef>>>(&store)
+        .unwrap();
+
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let bytes = callback.call(&mut store, sender).unwrap();
+    let bytes = bytes.expect("null ref returned from callback");
+    let bytes = bytes.data(&store).unwrap().downcast_ref::<Box<str>>();
+    assert_eq!(bytes.map(AsRef::as_ref), Some("callback"));
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn fallible_export(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<(Rooted<ExternRef>, i32), (Option<Rooted<ExternRef>>, i32)>(
+            &mut store,
+            "test_fallible",
+        )
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+
+    let (bytes, error_code) = test_fn.call(&mut store, (sender, 0)).unwrap();
+    assert_eq!(error_code, 0);
+    let bytes = bytes.expect("null ref returned on success");
+    let bytes = bytes.data(&store).unwrap().downcast_ref::<Box<str>>();
+    assert_eq!(bytes.map(AsRef::as_ref), Some("test"));
+
+    let (bytes, error_code) = test_fn.call(&mut store, (sender, 42)).unwrap();
+    assert_eq!(error_code, 42);
+    assert!(bytes.is_none());
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn flagged_optional_export(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<(Rooted<ExternRef>, i32), (Option<Rooted<ExternRef>>, i32)>(
+            &mut store,
+            "test_flagged",
+        )
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+
+    let (bytes, flag) = test_fn.call(&mut store, (sender, 1)).unwrap();
+    assert_eq!(flag, 1);
+    let bytes = bytes.expect("null ref returned for present resource");
+    let bytes = bytes.data(&store).unwrap().downcast_ref::<Box<str>>();
+    assert_eq!(bytes.map(AsRef::as_ref), Some("test"));
+
+    let (bytes, flag) = test_fn.call(&mut store, (sender, 0)).unwrap();
+    assert_eq!(flag, 0);
+    assert!(bytes.is_none());
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn lending_resources(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let ref_assertions = vec![no_op, revoke_buffers];
+    let mut store = Store::new(module.engine(), Data::new(ref_assertions));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, i32>(&mut store, "test_lending")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, 4);
+    // On the second call, the host revokes the lent buffer.
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, -1);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn consumed_resources(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_consumed")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, 4);
+    // The consumed message is dropped after being passed to the host.
+    let size = externrefs.size(&store);
+    for idx in 0..size {
+        assert_matches!(externrefs.get(&mut store, idx).unwrap(), Ref::Extern(None));
+    }
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn recorded_type_names(profile: CompilationProfile) {
+    let module_bytes = module_bytes(profile);
+    let declarations = processor::inspect(module_bytes).unwrap();
+    let fallible = declarations
+        .iter()
+        .find(|function| function.name() == "test_fallible")
+        .unwrap();
+    let type_names: Vec<_> = fallible.declaration.type_names.iter().collect();
+    assert_eq!(type_names, ["Sender", "Bytes"]);
+    // Names are not recorded by default.
+    let from_handle = declarations
+        .iter()
+        .find(|function| function.name() == "test_from_handle")
+        .unwrap();
+    assert!(from_handle.declaration.type_names.is_empty());
+
+    let manifest = Processor::default().manifest(module_bytes).unwrap();
+    let fallible = manifest
+        .exports
+        .iter()
+        .find(|function| function.name == "test_fallible")
+        .unwrap();
+    assert_eq!(fallible.resource_types, ["Sender", "Bytes"]);
+    assert_eq!(fallible.results, ["externref", "i32"]);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn host_handles(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module_bytes = module_bytes(profile);
+    let declarations = processor::inspect(module_bytes).unwrap();
+    let from_handle = declarations
+        .iter()
+        .find(|function| function.name() == "test_from_handle")
+        .unwrap();
+    assert_eq!(from_handle.host_handle_args, [0]);
+    assert_eq!(from_handle.resource_results, [0]);
+    let into_handle = declarations
+        .iter()
+        .find(|function| function.name() == "test_into_handle")
+        .unwrap();
+    assert_eq!(into_handle.resource_args, [0]);
+    assert_eq!(into_handle.host_handle_results, [0]);
+
+    let module = Processor::default().process_bytes(module_bytes).unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+
+    let into_handle = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_into_handle")
+        .unwrap();
+    let from_handle = instance
+        .get_typed_func::<u32, Option<Rooted<ExternRef>>>(&mut store, "test_from_handle")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+
+    let handle = into_handle.call(&mut store, sender).unwrap();
+    let slot = externrefs.get(&mut store, handle).unwrap();
+    let slot = slot.unwrap_extern().expect("handle points to a null slot");
+    assert!(slot.data(&store).unwrap().is::<HostSender>());
+
+    let restored = from_handle.call(&mut store, handle).unwrap();
+    let restored = restored.expect("null ref returned for handle");
+    let restored = restored.data(&store).unwrap().downcast_ref::<HostSender>();
+    assert_eq!(restored.unwrap().key, "sender");
+    // The resource is dropped after being returned.
+    let slot = externrefs.get(&mut store, handle).unwrap();
+    assert_matches!(slot, Ref::Extern(None));
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn restoring_snapshots(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .enable_snapshot_helpers()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    let into_handle = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_into_handle")
+        .unwrap();
+    let from_handle = instance
+        .get_typed_func::<u32, Option<Rooted<ExternRef>>>(&mut store, "test_from_handle")
+        .unwrap();
+    let snapshot_len = instance
+        .get_typed_func::<(), i32>(&mut store, Processor::SNAPSHOT_LEN_EXPORT)
+        .unwrap();
+
+    let handles = ["first", "second"].map(|name| {
+        let sender = store.data_mut().push_sender(name);
+        let sender = ExternRef::new(&mut store, sender).unwrap();
+        into_handle.call(&mut store, sender).unwrap()
+    });
+    from_handle.call(&mut store, handles[0]).unwrap();
+
+    let len = snapshot_len.call(&mut store, ()).unwrap();
+    assert_eq!(len, 2);
+    let saved_refs: Vec<_> = (0..len.unsigned_abs())
+        .map(|i| externrefs.get(&mut store, i).unwrap())
+        .collect();
+
+    // Restore the saved references into a fresh instance.
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    let restore_prepare = instance
+        .get_typed_func::<i32, ()>(&mut store, Processor::RESTORE_PREPARE_EXPORT)
+        .unwrap();
+    restore_prepare.call(&mut store, len).unwrap();
+    assert_eq!(externrefs.size(&store), 2);
+    for (i, saved_ref) in (0..).zip(saved_refs) {
+        externrefs.set(&mut store, i, saved_ref).unwrap();
+    }
+
+    let into_handle = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_into_handle")
+        .unwrap();
+    let from_handle = instance
+        .get_typed_func::<u32, Option<Rooted<ExternRef>>>(&mut store, "test_from_handle")
+        .unwrap();
+    // The free slot is reused, while the restored slot is left intact.
+    let sender = store.data_mut().push_sender("third");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    assert_eq!(into_handle.call(&mut store, sender).unwrap(), handles[0]);
+
+    let restored = from_handle.call(&mut store, handles[1]).unwrap();
+    let restored = restored.expect("null ref returned for handle");
+    let restored = restored.data(&store).unwrap().downcast_ref::<HostSender>();
+    assert_eq!(restored.unwrap().key, "second");
+}
+
+#[test_casing(8, Product((CompilationProfile::ALL, [Partitioning::Shared, Partitioning::PerKind])))]
+fn compacting_table(profile: CompilationProfile, partitioning: Partitioning) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_table_partitioning(partitioning)
+        .set_drop_fn_with_kind("test", "drop_ref_with_kind")
+        .enable_compaction("test", "move_ref")
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let into_handle = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_into_handle")
+        .unwrap();
+    let from_handle = instance
+        .get_typed_func::<u32, Option<Rooted<ExternRef>>>(&mut store, "test_from_handle")
+        .unwrap();
+    let compact = instance
+        .get_typed_func::<(), i32>(&mut store, Processor::COMPACT_EXPORT)
+        .unwrap();
+
+    let handles = ["first", "second", "third"].map(|name| {
+        let sender = store.data_mut().push_sender(name);
+        let sender = ExternRef::new(&mut store, sender).unwrap();
+        into_handle.call(&mut store, sender).unwrap()
+    });
+    // Free the first slot and compact the table; the last reference should be moved into it.
+    from_handle.call(&mut store, handles[0]).unwrap();
+    assert_eq!(compact.call(&mut store, ()).unwrap(), 1);
+    let expected_move = (
+        handles[2].try_into().unwrap(),
+        handles[0].try_into().unwrap(),
+    );
+    assert_eq!(store.data().moved_refs, [expected_move]);
+    // Compacting a dense table is a no-op.
+    assert_eq!(compact.call(&mut store, ()).unwrap(), 0);
+    assert_eq!(store.data().moved_refs.len(), 1);
+
+    let moved = from_handle.call(&mut store, handles[0]).unwrap();
+    let moved = moved.expect("null ref returned for moved handle");
+    let moved = moved.data(&store).unwrap().downcast_ref::<HostSender>();
+    assert_eq!(moved.unwrap().key, "third");
+    // The kind of the moved reference is retained.
+    let dropped_kinds = &store.data().dropped_kinds;
+    assert_eq!(dropped_kinds.len(), 2);
+    assert_eq!(dropped_kinds[0], dropped_kinds[1]);
+
+    // Free slots form a contiguous range at the end of the table, so the last one is reused.
+    let sender = store.data_mut().push_sender("fourth");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    assert_eq!(into_handle.call(&mut store, sender).unwrap(), handles[2]);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn resource_table(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let resources = instance.get_table(&mut store, "resources").unwrap();
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_resource_table")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, 2);
+
+    let messages: Vec<_> = (0..len)
+        .map(|idx| {
+            let message = resources.get(&mut store, idx).unwrap();
+            let message = message.unwrap_extern().expect("null message in table");
+            let message = message.data(&store).unwrap().downcast_ref::<Box<str>>();
+            message.unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(messages, ["test", "42"]);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn resource_global(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let global = instance.get_global(&mut store, "global_resource").unwrap();
+    assert_matches!(global.get(&mut store), Val::ExternRef(None));
+
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    global
+        .set(&mut store, Val::ExternRef(Some(sender)))
+        .unwrap();
+    let test_fn = instance
+        .get_typed_func::<(), u32>(&mut store, "test_global_resource")
+        .unwrap();
+    let len = test_fn.call(&mut store, ()).unwrap();
+    assert_eq!(len, 7);
+
+    let message = global.get(&mut store);
+    let message = message.unwrap_externref().expect("null message in global");
+    let message = message.data(&store).unwrap().downcast_ref::<Box<str>>();
+    assert_eq!(message.unwrap().as_ref(), "context");
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn drop_hook_with_resource_kinds(profile: CompilationProfile) {
+    enable_tracing();
+
+    let mut processor = Processor::default();
+    processor.set_drop_fn_with_kind("test", "drop_ref_with_kind");
+    let manifest = processor.manifest(module_bytes(profile)).unwrap();
+    assert!(manifest.drop_fn_with_kind);
+    let kind_id = |name: &str| {
+        let idx = manifest.resource_kinds.iter().position(|kind| kind == name);
+        i32::try_from(idx.unwrap()).unwrap()
+    };
+    assert_eq!(
+        HostSender::kind_id(&manifest.resource_kinds),
+        Some(kind_id("Sender"))
+    );
+
+    let module = processor.process_bytes(module_bytes(profile)).unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let mut store = Store::new(module.engine(), Data::new(vec![no_op; 6]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+
+    // The sender is created as a generic resource and is then downcast; buffers are upcast.
+    // Neither of these operations should change the resource kind.
+    let exported_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export_with_casts")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    exported_fn.call(&mut store, sender).unwrap();
+
+    store
+        .data()
+        .assert_drops(&store, &["test", "some other string", "42"]);
+    let bytes_kind = kind_id("Bytes");
+    let sender_kind = kind_id("()");
+    assert_eq!(
+        store.data().dropped_kinds,
+        [bytes_kind, bytes_kind, bytes_kind, sender_kind]
+    );
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn per_kind_tables(profile: CompilationProfile) {
+    enable_tracing();
+
+    let mut processor = Processor::default();
+    processor
+        .set_table_partitioning(Partitioning::PerKind)
+        .set_drop_fn_with_kind("test", "drop_ref_with_kind")
+        .enable_resource_metrics();
+    let manifest = processor.manifest(module_bytes(profile)).unwrap();
+    let kind_id = |name: &str| {
+        let idx = manifest.resource_kinds.iter().position(|kind| kind == name);
+        i32::try_from(idx.unwrap()).unwrap()
+    };
+    let kind_table = |name: &str| {
+        let idx = usize::try_from(kind_id(name)).unwrap();
+        manifest.kind_tables[idx].export_name.clone().unwrap()
+    };
+    assert_eq!(kind_table("Bytes"), "externrefs::Bytes");
+
+    let module = processor.process_bytes(module_bytes(profile)).unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let mut store = Store::new(module.engine(), Data::new(vec![no_op; 6]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+    let senders = instance.get_table(&mut store, &kind_table("()")).unwrap();
+    let buffers = instance
+        .get_table(&mut store, &kind_table("Bytes"))
+        .unwrap();
+    let live_count = instance
+        .get_global(&mut store, Processor::LIVE_COUNT_EXPORT)
+        .unwrap();
+    let peak_count = instance
+        .get_global(&mut store, Processor::PEAK_COUNT_EXPORT)
+        .unwrap();
+
+    let exported_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export_with_casts")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    exported_fn.call(&mut store, sender).unwrap();
+
+    // References are placed into the tables corresponding to their kinds.
+    assert_eq!(externrefs.size(&store), 0);
+    assert_eq!(senders.size(&store), 1);
+    assert_eq!(buffers.size(&store), 3);
+    assert_eq!(live_count.get(&mut store).unwrap_i32(), 0);
+    assert_eq!(peak_count.get(&mut store).unwrap_i32(), 4); // sender + 3 buffers
+
+    store
+        .data()
+        .assert_drops(&store, &["test", "some other string", "42"]);
+    let bytes_kind = kind_id("Bytes");
+    let sender_kind = kind_id("()");
+    assert_eq!(
+        store.data().dropped_kinds,
+        [bytes_kind, bytes_kind, bytes_kind, sender_kind]
+    );
+
+    // Host handles encode the partition.
+    let into_handle = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_into_handle")
+        .unwrap();
+    let from_handle = instance
+        .get_typed_func::<u32, Option<Rooted<ExternRef>>>(&mut store, "test_from_handle")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    let handle = into_handle.call(&mut store, sender).unwrap();
+    let partition_count = u32::try_from(manifest.resource_kinds.len()).unwrap() + 1;
+    let sender_partition = u32::try_from(kind_id("Sender")).unwrap() + 1;
+    assert_eq!(handle % partition_count, sender_partition);
+    let restored = from_handle.call(&mut store, handle).unwrap().unwrap();
+    assert!(restored.data(&store).unwrap().is::<HostSender>());
+}
+
+#[derive(Debug)]
+struct Tombstone;
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn tombstones_for_dropped_refs(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_drop_slot_value(DropSlotValue::Tombstone {
+            module: "test",
+            name: "tombstone",
+        })
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let mut linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let mut store = Store::new(module.engine(), Data::new(vec![no_op; 12]));
+    define_tombstone(&mut linker, &mut store);
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+
+    let exported_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export")
+        .unwrap();
+    // Tombstoned slots should be reused on the second call, so the table size shouldn't change.
+    for _ in 0..2 {
+        let sender = store.data_mut().push_sender("sender");
+        let sender = ExternRef::new(&mut store, sender).unwrap();
+        exported_fn.call(&mut store, sender).unwrap();
+
+        let size = externrefs.size(&store);
+        assert_eq!(size, 4); // sender + 3 buffers
+        for i in 0..size {
+            let slot = externrefs.get(&mut store, i).unwrap();
+            let slot = slot.unwrap_extern().expect("slot was nulled");
+            assert!(slot.data(&store).unwrap().is::<Tombstone>());
+        }
+    }
+}
+
+fn define_tombstone(linker: &mut Linker<Data>, store: &mut Store<Data>) {
+    let tombstone = ExternRef::new(&mut *store, Tombstone).unwrap();
+    let global_ty = GlobalType::new(ValType::EXTERNREF, Mutability::Const);
+    let tombstone = Global::new(&mut *store, global_ty, Val::ExternRef(Some(tombstone))).unwrap();
+    linker
+        .define(&*store, "test", "tombstone", tombstone)
+        .unwrap();
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn lending_resources_with_tombstones(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_drop_slot_value(DropSlotValue::Tombstone {
+            module: "test",
+            name: "tombstone",
+        })
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let mut linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let ref_assertions = vec![no_op, no_op, revoke_buffers];
+    let mut store = Store::new(module.engine(), Data::new(ref_assertions));
+    define_tombstone(&mut linker, &mut store);
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+
+    let test_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, i32>(&mut store, "test_lending")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+
+    // The lent buffer reuses the tombstoned slot of the buffer dropped on the previous call.
+    for _ in 0..2 {
+        let len = test_fn.call(&mut store, sender).unwrap();
+        assert_eq!(len, 4);
+    }
+    let len = test_fn.call(&mut store, sender).unwrap();
+    assert_eq!(len, -1);
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn slot_allocator(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_slot_allocator("test", "alloc_slot")
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+
+    // `(slot returned by the allocator, expected table size)`
+    let cases = [(-1, 4), (10, 11), (2, 4)];
+    for (slot, expected_size) in cases {
+        let mut linker = create_linker(module.engine());
+        linker
+            .func_wrap("test", "alloc_slot", move || slot)
+            .unwrap();
+        let no_op: RefAssertion = |_, _| {};
+        let mut store = Store::new(module.engine(), Data::new(vec![no_op; 12]));
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+        store.data_mut().externrefs = Some(externrefs);
+
+        let exported_fn = instance
+            .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export")
+            .unwrap();
+        // Dropped slots should be reused on the second call, so the table size shouldn't change.
+        for _ in 0..2 {
+            let sender = store.data_mut().push_sender("sender");
+            let sender = ExternRef::new(&mut store, sender).unwrap();
+            exported_fn.call(&mut store, sender).unwrap();
+            // If the allocator returns an occupied slot, the built-in search is used.
+            assert_eq!(externrefs.size(&store), expected_size, "slot = {slot}");
+        }
+    }
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn reserved_slots(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_reserved_slots(2)
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let mut store = Store::new(module.engine(), Data::new(vec![no_op; 12]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+    assert_eq!(externrefs.size(&store), 2);
+    let well_known = store.data_mut().push_sender("well-known");
+    let well_known = ExternRef::new(&mut store, well_known).unwrap();
+    externrefs.set(&mut store, 1, well_known.into()).unwrap();
+
+    let exported_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export")
+        .unwrap();
+    for _ in 0..2 {
+        let sender = store.data_mut().push_sender("sender");
+        let sender = ExternRef::new(&mut store, sender).unwrap();
+        exported_fn.call(&mut store, sender).unwrap();
+        // Inserted references are placed after the reserved slots, and the reserved slots
+        // are not touched.
+        assert_eq!(externrefs.size(&store), 6); // 2 reserved slots + sender + 3 buffers
+        let slot = externrefs.get(&mut store, 0).unwrap();
+        assert!(slot.unwrap_extern().is_none());
+        let slot = externrefs.get(&mut store, 1).unwrap();
+        assert!(slot.unwrap_extern().is_some());
+    }
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn resource_metrics(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .enable_resource_metrics()
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let no_op: RefAssertion = |_, _| {};
+    let mut store = Store::new(module.engine(), Data::new(vec![no_op; 12]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let externrefs = instance.get_table(&mut store, "externrefs").unwrap();
+    store.data_mut().externrefs = Some(externrefs);
+    let live_count = instance
+        .get_global(&mut store, Processor::LIVE_COUNT_EXPORT)
+        .unwrap();
+    let peak_count = instance
+        .get_global(&mut store, Processor::PEAK_COUNT_EXPORT)
+        .unwrap();
+
+    let exported_fn = instance
+        .get_typed_func::<Rooted<ExternRef>, ()>(&mut store, "test_export")
+        .unwrap();
+    for _ in 0..2 {
+        let sender = store.data_mut().push_sender("sender");
+        let sender = ExternRef::new(&mut store, sender).unwrap();
+        exported_fn.call(&mut store, sender).unwrap();
+
+        assert_eq!(live_count.get(&mut store).unwrap_i32(), 0);
+        assert_eq!(peak_count.get(&mut store).unwrap_i32(), 4); // sender + 3 buffers
+    }
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn leak_checks(profile: CompilationProfile) {
+    enable_tracing();
+
+    let module = Processor::default()
+        .set_leak_check("test", "on_leak", "test_*_handle")
+        .process_bytes(module_bytes(profile))
+        .unwrap();
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let into_handle = instance
+        .get_typed_func::<Rooted<ExternRef>, u32>(&mut store, "test_into_handle")
+        .unwrap();
+    let from_handle = instance
+        .get_typed_func::<u32, Option<Rooted<ExternRef>>>(&mut store, "test_from_handle")
+        .unwrap();
+    let sender = store.data_mut().push_sender("sender");
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+
+    // The sender remains in the table after being converted into a handle.
+    let handle = into_handle.call(&mut store, sender).unwrap();
+    assert_eq!(store.data().leaked_counts, [1]);
+    let restored = from_handle.call(&mut store, handle).unwrap();
+    assert!(restored.is_some());
+    assert_eq!(store.data().leaked_counts, [1]);
+}
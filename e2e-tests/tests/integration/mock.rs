@@ -0,0 +1,124 @@
+//! Tests for executing processed modules with a mock host.
+
+use anyhow::Context;
+use externref::{
+    processor::Processor,
+    testing::{Crossing, MockHost, MockRef, MockValue, MockValueType},
+};
+use test_casing::test_casing;
+use wasmtime::{Caller, Engine, ExternRef, FuncType, Linker, Module, Rooted, Store, Val, ValType};
+
+use crate::{compile::CompilationProfile, enable_tracing, module_bytes};
+
+fn val_type(ty: MockValueType) -> ValType {
+    match ty {
+        MockValueType::I32 => ValType::I32,
+        MockValueType::I64 => ValType::I64,
+        MockValueType::F32 => ValType::F32,
+        MockValueType::F64 => ValType::F64,
+        MockValueType::ExternRef => ValType::EXTERNREF,
+        _ => unreachable!(),
+    }
+}
+
+fn to_mock_value(caller: &Caller<'_, MockHost>, val: &Val) -> anyhow::Result<MockValue> {
+    Ok(match val {
+        Val::I32(val) => MockValue::I32(*val),
+        Val::I64(val) => MockValue::I64(*val),
+        Val::F32(bits) => MockValue::F32(f32::from_bits(*bits)),
+        Val::F64(bits) => MockValue::F64(f64::from_bits(*bits)),
+        Val::ExternRef(None) => MockValue::Ref(None),
+        Val::ExternRef(Some(reference)) => {
+            let reference = reference
+                .data(caller)?
+                .downcast_ref::<MockRef>()
+                .context("unexpected reference")?;
+            MockValue::Ref(Some(*reference))
+        }
+        _ => anyhow::bail!("unsupported value: {val:?}"),
+    })
+}
+
+fn from_mock_value(caller: &mut Caller<'_, MockHost>, value: MockValue) -> anyhow::Result<Val> {
+    Ok(match value {
+        MockValue::I32(val) => Val::I32(val),
+        MockValue::I64(val) => Val::I64(val),
+        MockValue::F32(val) => Val::F32(val.to_bits()),
+        MockValue::F64(val) => Val::F64(val.to_bits()),
+        MockValue::Ref(None) => Val::ExternRef(None),
+        MockValue::Ref(Some(reference)) => Val::ExternRef(Some(ExternRef::new(caller, reference)?)),
+        _ => anyhow::bail!("unsupported value: {value:?}"),
+    })
+}
+
+fn create_mock_linker(engine: &Engine, host: &MockHost) -> Linker<MockHost> {
+    let mut linker = Linker::new(engine);
+    for import in host.imports() {
+        let params = import.params.iter().copied().map(val_type);
+        let results = import.results.iter().copied().map(val_type);
+        let ty = FuncType::new(engine, params, results);
+        let (module, name) = (import.module.clone(), import.name.clone());
+        linker
+            .func_new(
+                &import.module,
+                &import.name,
+                ty,
+                move |mut caller, args, results| {
+                    let args = args
+                        .iter()
+                        .map(|arg| to_mock_value(&caller, arg))
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    let values = caller.data_mut().call_import(&module, &name, &args)?;
+                    for (dest, value) in results.iter_mut().zip(values) {
+                        *dest = from_mock_value(&mut caller, value)?;
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+    }
+    linker
+}
+
+#[test_casing(4, CompilationProfile::ALL)]
+fn executing_module_with_mock_host(profile: CompilationProfile) {
+    enable_tracing();
+
+    let mut processor = Processor::default();
+    processor.set_drop_fn("test", "drop_ref");
+    let manifest = processor.manifest(module_bytes(profile)).unwrap();
+    let module = processor.process_bytes(module_bytes(profile)).unwrap();
+    let host = MockHost::new(&module, &manifest).unwrap();
+
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_mock_linker(module.engine(), &host);
+    let mut store = Store::new(module.engine(), host);
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+
+    let sender = store.data_mut().create_ref("Sender");
+    let args = [MockValue::Ref(Some(sender))];
+    store.data_mut().pass_to_export("test_export", &args);
+    let sender = ExternRef::new(&mut store, sender).unwrap();
+    instance
+        .get_typed_func::<Option<Rooted<ExternRef>>, ()>(&mut store, "test_export")
+        .unwrap()
+        .call(&mut store, Some(sender))
+        .unwrap();
+
+    let host = store.data();
+    host.assert_live_refs(0);
+    let received_count = host
+        .crossings()
+        .iter()
+        .filter(|crossing| {
+            crossing.function == "test::send_message" && crossing.direction == Crossing::IntoModule
+        })
+        .count();
+    assert_eq!(received_count, 3);
+    let dropped_count = host
+        .crossings()
+        .iter()
+        .filter(|crossing| crossing.function == "test::drop_ref")
+        .count();
+    assert_eq!(dropped_count, 4); // sender + 3 messages
+}
@@ -0,0 +1,244 @@
+//! Tests for the functions managing the `externref`s table, which execute random sequences
+//! of operations and compare the outcome with the reference model.
+
+use std::mem;
+
+use externref::processor::{
+    test_util::{debug_assert_table_fn_correct, RefTableModel},
+    DropReentrancy, Partitioning, Processor,
+};
+use test_casing::{test_casing, Product};
+use wasmtime::{Caller, Engine, ExternRef, Module, Rooted, Store, TypedFunc};
+
+use crate::{compile::CompilationProfile, create_linker, enable_tracing, module_bytes, Data};
+
+const TABLE_FNS: [&str; 3] = ["externref_insert", "externref_get", "externref_drop"];
+const SEQUENCE_COUNT: u64 = 16;
+const OPS_PER_SEQUENCE: usize = 128;
+
+/// Simple xorshift PRNG to avoid depending on `rand`.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next_below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+struct TableFns {
+    insert: TypedFunc<(Option<Rooted<ExternRef>>, i32), i32>,
+    get: TypedFunc<i32, Option<Rooted<ExternRef>>>,
+    drop: TypedFunc<i32, ()>,
+}
+
+impl TableFns {
+    fn new(store: &mut Store<Data>, instance: &wasmtime::Instance) -> Self {
+        Self {
+            insert: instance.get_typed_func(&mut *store, TABLE_FNS[0]).unwrap(),
+            get: instance.get_typed_func(&mut *store, TABLE_FNS[1]).unwrap(),
+            drop: instance.get_typed_func(&mut *store, TABLE_FNS[2]).unwrap(),
+        }
+    }
+}
+
+/// Processes the test module exporting the table functions, so that they can be called directly.
+fn process_module(profile: CompilationProfile, partitioning: Partitioning) -> (Vec<u8>, usize) {
+    process_module_with(profile, partitioning, |processor| {
+        processor.set_drop_fn("test", "drop_ref");
+    })
+}
+
+fn process_module_with(
+    profile: CompilationProfile,
+    partitioning: Partitioning,
+    configure: impl FnOnce(&mut Processor<'_>),
+) -> (Vec<u8>, usize) {
+    let mut processor = Processor::default();
+    processor.set_table_partitioning(partitioning);
+    configure(&mut processor);
+    let bytes = module_bytes(profile);
+    let partition_count = match partitioning {
+        Partitioning::Shared => 1,
+        _ => processor.manifest(bytes).unwrap().resource_kinds.len() + 1,
+    };
+
+    let mut module = walrus::Module::from_buffer(bytes).unwrap();
+    processor
+        .process_with(&mut module, |module, _| {
+            debug_assert_table_fn_correct(module);
+            for name in TABLE_FNS {
+                let fn_id = module.funcs.by_name(name).unwrap();
+                module.exports.add(name, fn_id);
+            }
+            Ok(())
+        })
+        .unwrap();
+    (module.emit_wasm(), partition_count)
+}
+
+fn ref_tag(store: &Store<Data>, reference: Option<Rooted<ExternRef>>) -> Option<u32> {
+    let reference = reference?;
+    let tag = reference.data(store).unwrap().downcast_ref::<u32>();
+    Some(*tag.expect("unexpected reference"))
+}
+
+#[test_casing(8, Product((CompilationProfile::ALL, [Partitioning::Shared, Partitioning::PerKind])))]
+fn table_fns_match_reference_model(profile: CompilationProfile, partitioning: Partitioning) {
+    enable_tracing();
+
+    let (module, partition_count) = process_module(profile, partitioning);
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let linker = create_linker(module.engine());
+
+    for seed in 0..SEQUENCE_COUNT {
+        let mut store = Store::new(module.engine(), Data::new(vec![]));
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let fns = TableFns::new(&mut store, &instance);
+        let mut model = RefTableModel::new(partition_count);
+        let mut rng = XorShift::new(seed);
+        let mut next_tag = 0_u32;
+
+        for op_idx in 0..OPS_PER_SEQUENCE {
+            let context = format!("seed {seed}, operation #{op_idx}");
+            let live_ids = model.live_ids();
+            match rng.next_below(4) {
+                0 | 1 => {
+                    let tag = (rng.next_below(8) != 0).then(|| {
+                        next_tag += 1;
+                        next_tag
+                    });
+                    let kind = i32::try_from(rng.next_below(partition_count + 1)).unwrap() - 1;
+                    let reference = tag.map(|tag| ExternRef::new(&mut store, tag).unwrap());
+                    let id = fns.insert.call(&mut store, (reference, kind)).unwrap();
+                    assert_eq!(id, model.insert(tag, kind), "{context}");
+                }
+                2 if !live_ids.is_empty() => {
+                    let id = live_ids[rng.next_below(live_ids.len())];
+                    fns.drop.call(&mut store, id).unwrap();
+                    let expected_tag = model.remove(id);
+                    let dropped = store
+                        .data_mut()
+                        .dropped
+                        .pop()
+                        .expect("drop hook not called");
+                    let dropped = dropped.to_rooted(&mut store);
+                    assert_eq!(ref_tag(&store, Some(dropped)), expected_tag, "{context}");
+                }
+                _ => {
+                    for id in live_ids {
+                        let reference = fns.get.call(&mut store, id).unwrap();
+                        let tag = ref_tag(&store, reference);
+                        assert_eq!(tag.as_ref(), model.get(id), "{context}, id {id}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drop hook recording the dropped reference and its kind, which then drops references
+/// with [`Data::reentrant_drops`] IDs by calling back into the module.
+fn drop_ref_reentrant(
+    mut ctx: Caller<'_, Data>,
+    dropped: Option<Rooted<ExternRef>>,
+    kind: i32,
+) -> anyhow::Result<()> {
+    let dropped = dropped.expect("drop fn called with null ref");
+    let dropped = dropped.to_manually_rooted(&mut ctx)?;
+    ctx.data_mut().dropped.push(dropped);
+    ctx.data_mut().dropped_kinds.push(kind);
+
+    let drop_fn = ctx
+        .get_export(TABLE_FNS[2])
+        .and_then(|export| export.into_func())
+        .expect("drop function is not exported");
+    let drop_fn = drop_fn.typed::<i32, ()>(&ctx)?;
+    for id in mem::take(&mut ctx.data_mut().reentrant_drops) {
+        drop_fn.call(&mut ctx, id)?;
+    }
+    Ok(())
+}
+
+#[test_casing(8, Product((
+    [DropReentrancy::Allow, DropReentrancy::Trap, DropReentrancy::Skip, DropReentrancy::Queue],
+    [Partitioning::Shared, Partitioning::PerKind],
+)))]
+fn reentrant_drops(policy: DropReentrancy, partitioning: Partitioning) {
+    enable_tracing();
+
+    let (module, _) = process_module_with(CompilationProfile::Wasm, partitioning, |processor| {
+        processor
+            .set_drop_fn_with_kind("test", "drop_ref_reentrant")
+            .set_drop_reentrancy(policy);
+    });
+    let module = Module::new(&Engine::default(), module).unwrap();
+    let mut linker = create_linker(module.engine());
+    linker
+        .func_wrap("test", "drop_ref_reentrant", drop_ref_reentrant)
+        .unwrap();
+    let mut store = Store::new(module.engine(), Data::new(vec![]));
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    let fns = TableFns::new(&mut store, &instance);
+
+    let ids: Vec<_> = [(1_u32, 0), (2, -1), (3, 1)]
+        .into_iter()
+        .map(|(tag, kind)| {
+            let reference = ExternRef::new(&mut store, tag).unwrap();
+            fns.insert
+                .call(&mut store, (Some(reference), kind))
+                .unwrap()
+        })
+        .collect();
+    store.data_mut().reentrant_drops = ids[1..].to_vec();
+    let drop_result = fns.drop.call(&mut store, ids[0]);
+
+    let drops = dropped_tags_and_kinds(&mut store);
+    match policy {
+        DropReentrancy::Allow => {
+            drop_result.unwrap();
+            assert_eq!(drops, [(1, 0), (2, -1), (3, 1)]);
+        }
+        DropReentrancy::Trap => {
+            drop_result.unwrap_err();
+            assert_eq!(drops, [(1, 0)]);
+            return;
+        }
+        DropReentrancy::Skip => {
+            drop_result.unwrap();
+            assert_eq!(drops, [(1, 0)]);
+        }
+        DropReentrancy::Queue => {
+            drop_result.unwrap();
+            assert_eq!(drops, [(1, 0), (3, 1), (2, -1)]);
+        }
+        _ => unreachable!(),
+    }
+    for id in ids {
+        let reference = fns.get.call(&mut store, id).unwrap();
+        assert!(reference.is_none(), "reference {id} is not dropped");
+    }
+
+    // Check that the reentrancy guard is reset.
+    let reference = ExternRef::new(&mut store, 4_u32).unwrap();
+    let id = fns.insert.call(&mut store, (Some(reference), 0)).unwrap();
+    fns.drop.call(&mut store, id).unwrap();
+    assert_eq!(dropped_tags_and_kinds(&mut store), [(4, 0)]);
+}
+
+/// Takes tags and kinds of references dropped since the last call.
+fn dropped_tags_and_kinds(store: &mut Store<Data>) -> Vec<(u32, i32)> {
+    let dropped = mem::take(&mut store.data_mut().dropped);
+    let kinds = mem::take(&mut store.data_mut().dropped_kinds);
+    let tags = dropped.into_iter().map(|dropped| {
+        let dropped = dropped.to_rooted(&mut *store);
+        ref_tag(store, Some(dropped)).unwrap()
+    });
+    tags.collect::<Vec<_>>().into_iter().zip(kinds).collect()
+}
@@ -0,0 +1,48 @@
+// Runs the processed module under Node.js WASI. Build the module and the glue
+// with `npm run build` first.
+
+import { argv } from "node:process";
+import { WASI } from "node:wasi";
+
+import { instantiate } from "./glue.mjs";
+
+const wasi = new WASI({ version: "preview1" });
+let memory;
+
+const { instance, exports, inspectTable, onRelease } = await instantiate(
+  argv[2] ?? "processed.wasm",
+  {
+    host: {
+      create_buffer() {
+        const buffer = { text: "" };
+        onRelease(buffer, () => console.log("[host] buffer released"));
+        return buffer;
+      },
+      append(buffer, ptr, len) {
+        buffer.text += Buffer.from(memory.buffer, ptr, len).toString("utf8");
+      },
+      flush(logger, buffer) {
+        logger.log(buffer.text.trimEnd());
+      },
+    },
+  },
+  {
+    wasi,
+    onDrop: (ref) => console.log("[host] module dropped", ref),
+  },
+);
+memory = instance.exports.memory;
+
+const logger = {
+  name: "console",
+  log: (text) => console.log(text),
+};
+onRelease(logger, () => console.log("[host] logger released"));
+
+exports.register_logger(logger);
+instance.exports.greet(2);
+// The logger is retained by the module, while buffers are already dropped.
+console.log("[host] live references:", inspectTable());
+
+instance.exports.unregister_loggers();
+console.log("[host] live references after unregistering:", inspectTable());
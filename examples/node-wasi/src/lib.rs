@@ -0,0 +1,80 @@
+//! Example WASM module using `externref` that is run under Node.js with WASI.
+//! See the README for details.
+
+use std::sync::Mutex;
+
+use externref::{externref, Resource, ResourceArena};
+
+/// Host-side sink for log messages.
+pub struct Logger(());
+
+/// Host-side buffer of text.
+pub struct Buffer(());
+
+mod imports {
+    use externref::Resource;
+
+    use crate::{Buffer, Logger};
+
+    #[cfg(target_arch = "wasm32")]
+    #[externref::externref]
+    #[link(wasm_import_module = "host")]
+    extern "C" {
+        pub(crate) fn create_buffer() -> Resource<Buffer>;
+
+        pub(crate) fn append(buffer: &Resource<Buffer>, text_ptr: *const u8, text_len: usize);
+
+        pub(crate) fn flush(logger: &Resource<Logger>, buffer: &Resource<Buffer>);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) unsafe fn create_buffer() -> Resource<Buffer> {
+        panic!("only callable from WASM")
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) unsafe fn append(_: &Resource<Buffer>, _: *const u8, _: usize) {
+        panic!("only callable from WASM")
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) unsafe fn flush(_: &Resource<Logger>, _: &Resource<Buffer>) {
+        panic!("only callable from WASM")
+    }
+}
+
+/// Loggers registered by the host. References to them are retained in the `externref`s table
+/// until [`unregister_loggers()`] is called.
+static LOGGERS: Mutex<ResourceArena<Logger, 4>> = Mutex::new(ResourceArena::new());
+
+/// Registers a logger. Returns the index of the logger, or -1 if there are too many loggers.
+#[externref]
+pub extern "C" fn register_logger(logger: Resource<Logger>) -> i32 {
+    match LOGGERS.lock().unwrap().insert(logger) {
+        Ok(slot) => i32::try_from(slot.index()).unwrap(),
+        Err(_) => -1,
+    }
+}
+
+/// Sends greetings to all registered loggers. Buffers created during the call are dropped
+/// once it completes.
+#[no_mangle]
+pub extern "C" fn greet(count: u32) {
+    let loggers = LOGGERS.lock().unwrap();
+    for (slot, logger) in loggers.iter() {
+        let buffer = unsafe { imports::create_buffer() };
+        for i in 0..count {
+            let line = format!("Hello #{i} to logger #{}\n", slot.index());
+            unsafe { imports::append(&buffer, line.as_ptr(), line.len()) };
+        }
+        unsafe { imports::flush(logger, &buffer) };
+    }
+    // Printing to stdout is handled by WASI.
+    println!("Greeted {} logger(s)", loggers.len());
+}
+
+/// Drops all registered loggers.
+#[no_mangle]
+pub extern "C" fn unregister_loggers() {
+    LOGGERS.lock().unwrap().clear();
+}
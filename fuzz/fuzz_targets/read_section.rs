@@ -0,0 +1,26 @@
+//! Feeds arbitrary bytes to `Function::read_from_section()`. Reading must never panic
+//! or consume bytes past the end of the section.
+
+#![no_main]
+
+use externref::Function;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut section = data;
+    while !section.is_empty() {
+        let len_before = section.len();
+        let Ok(function) = Function::read_from_section(&mut section) else {
+            break;
+        };
+        assert!(section.len() < len_before, "reading did not advance the section");
+
+        // Exercise accessors on the parsed bit slices.
+        for slice in [function.externrefs, function.host_handles] {
+            let ones = slice.set_indices().count();
+            assert_eq!(ones, slice.count_ones());
+            assert!(!slice.is_set(slice.bit_len()));
+        }
+        function.type_names.iter().for_each(drop);
+    }
+});